@@ -44,7 +44,7 @@ pub(crate) fn process_ansi_escape_line<'a>(start_len: usize, line: &'a str) -> C
         let num: usize = if num.is_empty() {
             1
         } else {
-            usize::from_str_radix(num, 10).unwrap()
+            num.parse().unwrap()
         };
 
         let num = num + start_len;
@@ -61,7 +61,7 @@ pub(crate) fn process_ansi_escape_line<'a>(start_len: usize, line: &'a str) -> C
         let num: usize = if num.is_empty() {
             1
         } else {
-            usize::from_str_radix(num, 10).unwrap()
+            num.parse().unwrap()
         };
 
         let num = num + start_len;
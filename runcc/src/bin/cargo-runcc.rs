@@ -1,4 +1,4 @@
-use runcc::cli::run;
+use runcc::cli::{ctl, run};
 
 struct ExitMessage(String);
 
@@ -10,17 +10,16 @@ impl std::fmt::Debug for ExitMessage {
 
 #[tokio::main]
 async fn main() -> Result<(), ExitMessage> {
+    if let Some(sent) = ctl().await {
+        return sent.map_err(|err| ExitMessage(format!("{}", err)));
+    }
     let exit_code: i32 = match run().await {
         Err(err) => return Err(ExitMessage(format!("{}", err))),
-        Ok(report) => {
-            let failed = report.command_count_failed();
-            if failed == 0 {
-                return Ok(());
-            } else {
-                2
-            }
-        }
+        Ok(report) => report.exit_code(),
     };
+    if exit_code == 0 {
+        return Ok(());
+    }
 
     std::process::exit(exit_code);
 }
@@ -1,32 +1,559 @@
 use clap::Parser;
+use std::collections::HashMap;
 use std::io;
+#[cfg(all(unix, feature = "tui"))]
+use std::io::IsTerminal;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use super::{options::Opts, CommandSystemLogPlugin};
-use crate::run::CommandSystemSimpleReport;
+#[cfg(unix)]
+use super::ControlSocket;
+#[cfg(unix)]
+use super::ShutdownProgress;
+use super::{
+    can_pick_commands, control_from_stdin, dry_run, is_ctl, options::Opts, pick_commands,
+    sort_commands, summary_line, summary_table, tap_report, CommandSystemLogPlugin,
+    ControlledCommands, EventsLog, GithubAnnotations, LogFiles, Metrics, MetricsServer,
+    OutputFormat, PrefixRenderer, ReadinessNotifier, RunReport, StatusBoard, Stream,
+    SyscallTracing, Trace, Tracer,
+};
+use crate::run::watch::FileWatcher;
+use crate::run::{
+    run_steps, CommandSystemPlugin, CommandSystemSimpleReport, LabeledCommandData, Reloader,
+};
+#[cfg(unix)]
+use crate::run::{CommandHandle, CommandSystemKiller};
+use crate::RunConfig;
+use std::ffi::OsString;
+
+/// How long commands may take to exit after Ctrl-C or SIGTERM once they were killed
+/// forcefully, before runcc stops waiting for them
+const FORCE_STOP_AFTER: Duration = Duration::from_secs(5);
+
+/// How soon a second Ctrl-C must follow to shut down, see `RunConfig::ctrl_c_to_foreground`
+#[cfg(unix)]
+const DOUBLE_CTRL_C_WITHIN: Duration = Duration::from_secs(1);
+
+/// Sends Ctrl-C to `target` until it is pressed twice within [`DOUBLE_CTRL_C_WITHIN`],
+/// once the first one was received.
+///
+/// Commands which `share_terminal` with runcc already got Ctrl-C from the terminal.
+#[cfg(unix)]
+async fn forward_ctrl_c<T>(target: (String, CommandHandle<T>), share_terminal: bool) {
+    let (label, target) = target;
+    eprintln!(
+        "[runcc][info] sending Ctrl-C to command[{}], press it twice to shut down",
+        label
+    );
+    loop {
+        if !(share_terminal && crate::run::terminal::owns_terminal()) {
+            target.send_signal(crate::Signal::Interrupt);
+        }
+        let received_at = std::time::Instant::now();
+        if tokio::signal::ctrl_c().await.is_err() || received_at.elapsed() < DOUBLE_CTRL_C_WITHIN {
+            return;
+        }
+    }
+}
 
 pub async fn run() -> io::Result<CommandSystemSimpleReport> {
-    let args = std::env::args_os();
-    let mut args: Vec<_> = args.collect();
+    run_with_prefix_renderer(None).await
+}
+
+/// The arguments of runcc, also if it runs as `cargo runcc`
+fn args() -> Vec<OsString> {
+    let mut args: Vec<_> = std::env::args_os().collect();
+    if args.get(1).is_some_and(|arg| arg == "runcc") {
+        args.remove(1);
+    }
+    args
+}
+
+/// Sends a request to a running runcc if the arguments are `ctl` and a request,
+/// e.g. `cargo runcc ctl restart web`. `None` if they aren't, and [`run`] runs commands.
+///
+/// `ctl` followed by anything else is a command named `ctl`, like `cargo runcc -- ctl`.
+pub async fn ctl() -> Option<io::Result<()>> {
+    let mut args = args();
+    if !is_ctl(&args[1..]) {
+        return None;
+    }
+    args.remove(1);
+    #[cfg(unix)]
+    return Some(super::control::ctl(args).await);
+    #[cfg(not(unix))]
+    Some(Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "cargo runcc ctl is only supported on Unix",
+    )))
+}
+
+/// Like [`run`], with lines of commands prefixed by `prefix_renderer` instead of `[label]`
+pub async fn run_with_prefix_renderer(
+    prefix_renderer: Option<Arc<dyn PrefixRenderer>>,
+) -> io::Result<CommandSystemSimpleReport> {
+    let args = args();
+    let opts: Opts = Opts::parse_from(&args);
+    let print_config = opts.print_config();
+    let is_dry_run = opts.dry_run();
+    let trace_path = opts.trace().map(ToString::to_string);
+    let metrics_path = opts.metrics_out().map(ToString::to_string);
+    let metrics_addr = opts.metrics_addr().map(ToString::to_string);
+    let log_dir = opts.log_dir().map(ToString::to_string);
+    let github_annotations = opts.github_annotations();
+    let ci_format = opts.ci_format();
+    let format = opts.format();
+    let color = opts.color();
+    let show_cwd = opts.show_cwd();
+    let quiet = opts.quiet();
+    let no_summary = opts.no_summary();
+    let pausable = opts.pausable();
+    let control_stdin = opts.control_stdin();
+    let status_json = opts.status_json().map(ToString::to_string);
+    let control_socket = opts.control_socket().map(ToString::to_string);
+    let readiness = ReadinessNotifier::default().ready_file(opts.ready_file().map(Into::into));
+    #[cfg(all(unix, feature = "sd-notify"))]
+    let readiness = match opts.sd_notify() {
+        true => readiness.notify_socket(std::env::var_os("NOTIFY_SOCKET")),
+        false => readiness,
+    };
+    #[cfg(all(not(unix), feature = "sd-notify"))]
+    if opts.sd_notify() {
+        eprintln!("[runcc][warning] --sd-notify is only supported on Unix");
+    }
+    for (option, feature) in opts.without_feature() {
+        eprintln!(
+            "[runcc][warning] {} is ignored, runcc was built without the {} feature",
+            option, feature
+        );
+    }
+    let events_log = opts.events_log().map(ToString::to_string);
+    let report_path = opts.report().map(ToString::to_string);
+    let replay = opts.replay().map(ToString::to_string);
+    let picks_commands = opts.picks_commands();
+    let trace_syscalls = opts.trace_syscalls().map(ToString::to_string);
+    // SIGHUP and --watch-config reload the config file, see `reload_on_sighup_or_change`
+    let reload_args = (opts.reads_config_file() && replay.is_none()).then(|| args.clone());
+    let watch_config = opts.watch_config().then(|| opts.config_file());
+    #[cfg(feature = "notify-desktop")]
+    let notify = opts.notify();
+    #[cfg(feature = "tmux")]
+    let tmux = opts.tmux();
+    #[cfg(feature = "tui")]
+    let tui = opts.tui();
 
-    if let Some(arg) = args.get(1) {
-        if arg == "runcc" {
-            args.remove(1);
+    let mut config = match replay {
+        Some(path) => {
+            eprintln!("[runcc][info] replaying report {:?}", path);
+            let config = RunReport::read(&path)?.into_config()?;
+            config
+                .validate()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}", err)))?;
+            config
         }
+        None => opts
+            .try_into_config()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}", err)))?,
+    };
+
+    if print_config {
+        let toml = config
+            .to_toml_string()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}", err)))?;
+        print!("{}", toml);
+
+        return Ok(CommandSystemSimpleReport {
+            command_count_total: 0,
+            command_count_success: 0,
+            command_count_killed: 0,
+            first_failed_code: None,
+            last_exit_code: None,
+            last_failed: false,
+            exit_code_policy: Default::default(),
+        });
     }
 
-    let opts: Opts = Opts::parse_from(args);
+    if is_dry_run {
+        let missing = dry_run(&config, format == OutputFormat::Json);
+        if missing > 0 {
+            let programs = if missing == 1 { "program" } else { "programs" };
+            eprintln!("[runcc][error] {} {} not found", missing, programs);
+        }
 
-    let config = opts.try_into_config().or_else(|err| {
-        Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            format!("{}", err),
-        ))
-    })?;
+        return Ok(CommandSystemSimpleReport {
+            command_count_total: config.commands.len(),
+            command_count_success: config.commands.len() - missing,
+            command_count_killed: 0,
+            first_failed_code: None,
+            last_exit_code: None,
+            last_failed: false,
+            exit_code_policy: Default::default(),
+        });
+    }
+
+    // reloads keep running only the picked commands
+    let mut picked = None;
+    if picks_commands && config.commands.len() > 1 && can_pick_commands() {
+        picked = pick_commands(&mut config)?;
+    }
+
+    let run_report = match &report_path {
+        Some(_) => Some(RunReport::new(&config)?),
+        None => None,
+    };
+
+    config
+        .load_env_files()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}", err)))?;
 
-    let mut system =
-        crate::run::spawn_from_run_config_with_plugin(config, CommandSystemLogPlugin::new());
+    let secrets = config
+        .resolve_secrets()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}", err)))?;
+
+    config
+        .resolve_computed_env()
+        .await
+        .map_err(|err| io::Error::other(format!("{}", err)))?;
+    // reloads keep the ports
+    let mut ports = HashMap::new();
+    config.assign_ports(&mut ports)?;
+
+    #[cfg(not(unix))]
+    for cmd in config
+        .commands
+        .iter()
+        .filter(|cmd| cmd.kill_signal.is_some())
+    {
+        eprintln!(
+            "[runcc][warning] command[{}] has kill_signal, which is ignored on Windows",
+            cmd.resolved_label()
+        );
+    }
+
+    #[cfg(not(unix))]
+    if config.kill_signal.is_some() {
+        eprintln!("[runcc][warning] kill_signal is ignored on Windows");
+    }
+    #[cfg(not(unix))]
+    if config.kill_process_groups {
+        eprintln!("[runcc][warning] kill_process_groups is ignored on Windows");
+    }
+    #[cfg(not(unix))]
+    if config.pty || config.commands.iter().any(|cmd| cmd.pty) {
+        eprintln!("[runcc][warning] pty is ignored on Windows");
+    }
+    #[cfg(unix)]
+    for cmd in config.commands.iter().filter(|cmd| {
+        let limits = cmd.limits.as_ref();
+        limits.is_some_and(|limits| limits.cpu.is_some())
+    }) {
+        eprintln!(
+            "[runcc][warning] command[{}] has a cpu limit, which is only enforced on Windows, \
+             try nice instead",
+            cmd.resolved_label()
+        );
+    }
+
+    // the steps after the commands run once they stopped or a step before them failed
+    let after_steps = std::mem::take(&mut config.after);
+    let step_envs = config.envs.clone();
+
+    let ctrl_c_signal = config.ctrl_c_signal;
+    // commands are killed forcefully after the signal had this long, see `RunConfig::kill_grace`
+    // and `RunConfig::kill_signal`
+    let kill_grace = match (config.kill_grace, config.kill_signal) {
+        (Some(kill_grace), _) => kill_grace,
+        (None, Some(_)) => config
+            .shutdown_timeout
+            .unwrap_or(crate::DEFAULT_SHUTDOWN_TIMEOUT),
+        (None, None) => Duration::ZERO,
+    };
+    let ctrl_c_timeout = FORCE_STOP_AFTER
+        + match ctrl_c_signal {
+            Some(_) => config
+                .shutdown_timeout
+                .unwrap_or(crate::DEFAULT_SHUTDOWN_TIMEOUT),
+            None => kill_grace,
+        };
+    let trace = trace_path.as_ref().map(|_| Arc::new(Trace::new()));
+    let metrics =
+        (metrics_path.is_some() || metrics_addr.is_some()).then(|| Arc::new(Metrics::new()));
+    if let (Some(metrics), Some(addr)) = (&metrics, metrics_addr) {
+        let server = MetricsServer::bind(&addr).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("failed to serve metrics on {}: {}", addr, err),
+            )
+        })?;
+        // the port may have been picked by the system, with port 0
+        if let Ok(addr) = server.local_addr() {
+            eprintln!("[runcc][info] serving metrics on http://{}/metrics", addr);
+        }
+        server.serve(metrics.clone());
+    }
+    let has_log_files = config.commands.iter().any(|cmd| cmd.log_file.is_some());
+    if log_dir.is_none() {
+        for cmd in config.commands.iter().filter(|cmd| {
+            let routes = [cmd.stdout_route(), cmd.stderr_route()];
+            cmd.log_file.is_none() && routes.contains(&crate::StreamRoute::File)
+        }) {
+            eprintln!(
+                "[runcc][warning] command[{}] writes output only to its log file, \
+                 but has no log_file and --log-dir isn't given",
+                cmd.resolved_label()
+            );
+        }
+    }
+    let log_files = if log_dir.is_some() || has_log_files {
+        let log_files = LogFiles::new(log_dir.map(Into::into))?;
+        Some(Arc::new(log_files.max_size(config.log_file_max_size)))
+    } else {
+        None
+    };
+    let syscall_tracing = match trace_syscalls {
+        Some(dir) => match Tracer::detect() {
+            Some(tracer) => Some(SyscallTracing::new(tracer, dir.into())?),
+            None => {
+                eprintln!("[runcc][warning] --trace-syscalls needs strace on Linux or dtrace on macOS, running commands without tracing");
+                None
+            }
+        },
+        None => None,
+    };
+    let github = github_annotations.then(|| Arc::new(GithubAnnotations::new()));
+    #[cfg(not(feature = "tui"))]
+    let tui = false;
+    // the terminal UI shows the state of commands
+    let status = (status_json.is_some() || tui).then(|| Arc::new(StatusBoard::new()));
+    let events = match &events_log {
+        Some(path) => Some(Arc::new(EventsLog::create(path)?)),
+        None => None,
+    };
+    if config.group_output && format == OutputFormat::Json {
+        eprintln!("[runcc][warning] group_output is ignored with --format json");
+    }
+    let plugin = CommandSystemLogPlugin::new()
+        .collapse_repeats(config.collapse_repeats)
+        .interleave_guard(config.interleave_guard)
+        .group_output(config.group_output)
+        .partial_line_flush(config.partial_line_flush)
+        .timestamps(config.timestamps)
+        .show_silent_commands(config.show_silent_commands)
+        .restart_separator(config.restart_separator.clone())
+        .color(color)
+        .colored_labels(true)
+        .color_seed(config.color_seed)
+        .level_colors(
+            config
+                .colorize_log_levels
+                .then(|| config.log_level_colors.clone()),
+        )
+        .show_cwd(show_cwd)
+        .defer_output_until_started(config.defer_output_until_started)
+        .output_backpressure(config.output_backpressure)
+        .max_buffer_memory(config.max_buffer_memory)
+        .mask(secrets.clone())
+        .trace(trace.clone())
+        .metrics(metrics.clone())
+        .log_files(log_files)
+        .github(github.clone())
+        .ci_format(ci_format)
+        .format(format)
+        .status(status.clone())
+        .events(events.clone())
+        .syscall_tracing(syscall_tracing)
+        .prefix_renderer(prefix_renderer);
+    // the broadcast keeps the last lines the plugin publishes, see `RunConfig::tail_lines`
+    let tail_lines = config.tail_lines.unwrap_or(0);
+    let plugin = match tail_lines {
+        0 => plugin,
+        _ => plugin
+            .output_broadcast(Some(Arc::new(crate::run::OutputBroadcast::new(tail_lines))))
+            .tail_lines(tail_lines),
+    };
+
+    #[cfg(feature = "tmux")]
+    let tmux = if tmux {
+        Some(Arc::new(super::TmuxWindow::create()?))
+    } else {
+        None
+    };
+    #[cfg(feature = "tmux")]
+    let plugin = plugin.tmux(tmux.clone());
+
+    #[cfg(all(unix, feature = "tui"))]
+    let tui = match (tui, &status) {
+        (true, _)
+            if config.foreground.is_some() || config.commands.iter().any(|c| c.interactive) =>
+        {
+            eprintln!("[runcc][warning] --tui is ignored since a command reads stdin");
+            None
+        }
+        (true, _) if !(io::stdin().is_terminal() && io::stdout().is_terminal()) => {
+            eprintln!("[runcc][warning] --tui is ignored since stdin or stdout is no terminal");
+            None
+        }
+        (true, Some(status)) => {
+            let labels = config.commands.iter().map(|c| c.resolved_label()).collect();
+            let lines = std::cmp::max(tail_lines, crate::run::DEFAULT_TAIL_LINES);
+            let broadcast = Arc::new(crate::run::OutputBroadcast::new(lines));
+            Some((super::Tui::new(labels, status.clone()), broadcast))
+        }
+        _ => None,
+    };
+    #[cfg(all(unix, feature = "tui"))]
+    let plugin = match &tui {
+        Some((tui, broadcast)) => plugin
+            .output_sink(Some(tui.sink()))
+            .output_broadcast(Some(broadcast.clone())),
+        None => plugin,
+    };
+    #[cfg(all(unix, feature = "tui"))]
+    let pausable = match pausable && tui.is_some() {
+        true => {
+            eprintln!("[runcc][warning] --pausable is ignored with --tui");
+            false
+        }
+        false => pausable,
+    };
+    #[cfg(all(unix, feature = "tui"))]
+    let control_stdin = match control_stdin && tui.is_some() {
+        true => {
+            eprintln!("[runcc][warning] --control-stdin is ignored with --tui");
+            config.pipe_stdin = false;
+            false
+        }
+        false => control_stdin,
+    };
+    #[cfg(all(not(unix), feature = "tui"))]
+    if tui {
+        eprintln!("[runcc][warning] --tui is only supported on Unix");
+    }
+
+    let shutdown_progress = plugin.shutdown_progress();
+    let output = plugin.output();
+
+    #[cfg(unix)]
+    let control_socket = match control_socket {
+        Some(path) => Some(ControlSocket::bind(&path).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("failed to listen on control socket {}: {}", path, err),
+            )
+        })?),
+        None => None,
+    };
+    // nothing fails after the steps before the commands, so that the steps after them run
+    if let Err(failed) = run_steps(&config.before, step_envs.as_ref()).await {
+        #[cfg(unix)]
+        if let Some(socket) = control_socket {
+            let _ = std::fs::remove_file(socket.path());
+        }
+        run_after_steps(&after_steps, step_envs.as_ref()).await;
+        return Err(io::Error::other(format!(
+            "before {}, no command was run",
+            failed
+        )));
+    }
+
+    // commands reading stdin need its keys
+    let reads_stdin = config.foreground.is_some() || config.commands.iter().any(|c| c.interactive);
+    let control_stdin = match control_stdin && reads_stdin {
+        true => {
+            eprintln!("[runcc][warning] --control-stdin is ignored since a command reads stdin");
+            config.pipe_stdin = false;
+            false
+        }
+        false => control_stdin,
+    };
+    let pausable = match pausable && control_stdin {
+        true => {
+            eprintln!("[runcc][warning] --pausable is ignored with --control-stdin");
+            false
+        }
+        false => pausable,
+    };
+    if pausable && reads_stdin {
+        eprintln!("[runcc][warning] --pausable is ignored since a command reads stdin");
+    }
+    #[cfg(unix)]
+    let _terminal_mode = match pausable && !reads_stdin {
+        true => super::pause_on_key(output.clone()),
+        false => None,
+    };
+    #[cfg(not(unix))]
+    if pausable {
+        eprintln!("[runcc][warning] --pausable is only supported on Unix");
+    }
+
+    // the command which gets Ctrl-C instead of runcc, see `RunConfig::ctrl_c_to_foreground`
+    let ctrl_c_target = config.ctrl_c_to_foreground.then(|| {
+        config
+            .commands
+            .iter()
+            .position(|cmd| match &config.foreground {
+                Some(foreground) => cmd.resolved_label() == *foreground,
+                None => cmd.interactive,
+            })
+    });
+    match ctrl_c_target {
+        Some(None) => eprintln!(
+            "[runcc][warning] ctrl_c_to_foreground is ignored since no command is foreground or interactive"
+        ),
+        #[cfg(not(unix))]
+        Some(Some(_)) => eprintln!("[runcc][warning] ctrl_c_to_foreground is only supported on Unix"),
+        _ => {}
+    }
+    #[cfg(unix)]
+    let share_terminal = config.foreground.is_none();
+
+    let labels: Vec<String> = config.commands.iter().map(|c| c.resolved_label()).collect();
+    let summary_order = config.summary_order;
+    let mut exit_code_policy = config.exit_code;
+    let fail_on_skip = config.fail_on_skip;
+    let started = std::time::Instant::now();
+    let reloaded_config = config.clone();
+    let mut plugin = crate::run::PluginStack::new(plugin);
+    if let Some(run_report) = &run_report {
+        plugin = plugin.with(run_report.recorder());
+    }
+    let mut system = crate::run::spawn_from_run_config_with_plugin(config, plugin);
+    // the commands change when the config is reloaded
+    let reloader = Arc::new(Mutex::new(system.reloader(reloaded_config)));
+    let commands: Arc<dyn ControlledCommands> = reloader.clone();
+    let watcher = match (watch_config, &reload_args) {
+        (Some(Some(path)), Some(_)) => Some(FileWatcher::new(path.into())),
+        (Some(_), _) => {
+            eprintln!("[runcc][warning] --watch-config is ignored since there is no config file to reload");
+            None
+        }
+        (None, _) => None,
+    };
+    if let Some(args) = reload_args.filter(|_| cfg!(unix) || watcher.is_some()) {
+        reload_on_sighup_or_change(reloader, args, picked, secrets, ports, watcher);
+    }
+    #[cfg(unix)]
+    let ctrl_c_target = ctrl_c_target.flatten().and_then(|i| {
+        let handle = system.command_handles().into_iter().nth(i)?;
+        Some((labels[i].clone(), handle))
+    });
+
+    if !readiness.is_empty() {
+        let ready = system.wait_all_ready();
+        let labels = labels.clone();
+        tokio::spawn(readiness.notify_when(async move {
+            ready.await.map_err(|err| {
+                format!(
+                    "command[{}] stopped before it was ready",
+                    labels[err.command_index]
+                )
+            })
+        }));
+    }
 
     let killer = system.share_killer();
+    let progress = shutdown_progress.clone();
+    let shutdown_events = events.clone();
 
     tokio::spawn(async move {
         if let Err(err) = tokio::signal::ctrl_c().await {
@@ -35,9 +562,436 @@ pub async fn run() -> io::Result<CommandSystemSimpleReport> {
                 err
             );
         } else {
-            killer.kill_all().await;
+            #[cfg(unix)]
+            if let Some(target) = ctrl_c_target {
+                forward_ctrl_c(target, share_terminal).await;
+            }
+            if let Some(message) = progress.start() {
+                eprintln!("{}", message);
+            }
+            if let Some(events) = shutdown_events {
+                events.shutdown("Ctrl-C signal");
+            }
+            match ctrl_c_signal {
+                Some(signal) => {
+                    killer
+                        .kill_all_with_signal_and_timeout(signal, ctrl_c_timeout)
+                        .await
+                }
+                None => killer.kill_all_with_timeout(ctrl_c_timeout).await,
+            }
+
+            // a second Ctrl-C stops waiting for commands which don't exit
+            if tokio::signal::ctrl_c().await.is_ok() {
+                eprintln!("[runcc][warning] got Ctrl-C again, killing all commands forcefully");
+                killer.force_stop_all().await;
+            }
         }
     });
 
-    Ok(system.wait().await)
+    // SIGTERM (e.g. from systemd) always shuts down all commands,
+    // regardless of ctrl_c_signal
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let killer = system.share_killer();
+        let progress = shutdown_progress.clone();
+        let shutdown_events = events.clone();
+
+        tokio::spawn(async move {
+            match signal(SignalKind::terminate()) {
+                Err(err) => eprintln!(
+                    "[runcc][warning] failed to setup SIGTERM signal handler: {}",
+                    err
+                ),
+                Ok(mut sigterm) => {
+                    if sigterm.recv().await.is_some() {
+                        if let Some(message) = progress.start() {
+                            eprintln!("{}", message);
+                        }
+                        if let Some(events) = shutdown_events {
+                            events.shutdown("SIGTERM signal");
+                        }
+                        killer
+                            .kill_all_with_timeout(kill_grace + FORCE_STOP_AFTER)
+                            .await;
+                    }
+                }
+            }
+        });
+    }
+
+    // Ctrl-Break is the SIGTERM of Windows, commands get it when they are killed
+    // with a signal, see `CommandInitialized::new_process_group`
+    #[cfg(windows)]
+    {
+        let killer = system.share_killer();
+        let progress = shutdown_progress.clone();
+        let shutdown_events = events.clone();
+
+        tokio::spawn(async move {
+            match tokio::signal::windows::ctrl_break() {
+                Err(err) => eprintln!(
+                    "[runcc][warning] failed to setup Ctrl-Break handler: {}",
+                    err
+                ),
+                Ok(mut ctrl_break) => {
+                    if ctrl_break.recv().await.is_some() {
+                        if let Some(message) = progress.start() {
+                            eprintln!("{}", message);
+                        }
+                        if let Some(events) = shutdown_events {
+                            events.shutdown("Ctrl-Break signal");
+                        }
+                        killer
+                            .kill_all_with_timeout(kill_grace + FORCE_STOP_AFTER)
+                            .await;
+                    }
+                }
+            }
+        });
+    }
+
+    if let (Some(status), Some(path)) = (status, status_json) {
+        write_status_on_sigusr1(status, path);
+    }
+
+    #[cfg(unix)]
+    let control_socket = match control_socket {
+        Some(socket) => {
+            let path = socket.path().to_owned();
+            let stop = stop_all(
+                system.share_killer(),
+                shutdown_progress.clone(),
+                events.clone(),
+                "a control request",
+                kill_grace + FORCE_STOP_AFTER,
+            );
+            socket.serve(commands.clone(), stop);
+            Some(path)
+        }
+        None => None,
+    };
+    #[cfg(not(unix))]
+    if control_socket.is_some() {
+        eprintln!("[runcc][warning] --control-socket is only supported on Unix");
+    }
+
+    if control_stdin {
+        control_from_stdin(commands.clone());
+    }
+
+    #[cfg(all(unix, feature = "tui"))]
+    let tui = tui.map(|(tui, broadcast)| {
+        let stop = stop_all(
+            system.share_killer(),
+            shutdown_progress.clone(),
+            events.clone(),
+            "q pressed",
+            kill_grace + FORCE_STOP_AFTER,
+        );
+        tui.start(broadcast, commands.clone(), stop)
+    });
+
+    let mut report = match system.wait().await {
+        Ok(report) => report,
+        Err(panicked) => {
+            match panicked.command_index.and_then(|i| labels.get(i)) {
+                Some(label) => eprintln!(
+                    "[runcc][error] command[{}] panicked, killed all commands: {}",
+                    label, panicked.message
+                ),
+                None => eprintln!("[runcc][error] {}, killed all commands", panicked),
+            }
+            // the panicked command counts as failed
+            let stats = system.stats();
+            CommandSystemSimpleReport {
+                command_count_total: stats.command_count_total,
+                command_count_success: stats.command_count_success,
+                command_count_killed: stats.command_count_killed,
+                first_failed_code: None,
+                last_exit_code: None,
+                last_failed: false,
+                exit_code_policy: Default::default(),
+            }
+        }
+    };
+    if let Some(events) = &events {
+        events.stopped(&report);
+    }
+    // the terminal may still be behind on output of commands
+    output.resume();
+    let _ = tokio::task::spawn_blocking(move || output.flush()).await;
+    // the summary is printed on the terminal as it was before
+    #[cfg(all(unix, feature = "tui"))]
+    drop(tui);
+    run_after_steps(&after_steps, step_envs.as_ref()).await;
+
+    // empty if a task of the system panicked
+    let mut stopped = system
+        .wait_into_stopped_commands()
+        .await
+        .unwrap_or_default();
+    sort_commands(&mut stopped, summary_order, &system.history());
+
+    if let (Some(mut run_report), Some(path)) = (run_report, report_path) {
+        run_report.stopped(&stopped, &system.history());
+        if let Err(err) = run_report.write(&path) {
+            eprintln!("[runcc][warning] failed to write report {}: {}", path, err);
+        }
+    }
+
+    #[cfg(feature = "tmux")]
+    if let Some(tmux) = tmux {
+        tmux.kill();
+    }
+
+    if let (Some(trace), Some(path)) = (trace, trace_path) {
+        if let Err(err) = trace.write(&path) {
+            eprintln!(
+                "[runcc][warning] failed to write trace file {}: {}",
+                path, err
+            );
+        }
+    }
+
+    if let (Some(metrics), Some(path)) = (metrics, metrics_path) {
+        if let Err(err) = metrics.write(&path) {
+            eprintln!(
+                "[runcc][warning] failed to write metrics file {}: {}",
+                path, err
+            );
+        }
+    }
+
+    if let Some(github) = github {
+        for annotation in github.annotations() {
+            println!("{}", annotation);
+        }
+
+        if let Ok(path) = std::env::var("GITHUB_STEP_SUMMARY") {
+            if let Err(err) = github.write_summary(&path) {
+                eprintln!(
+                    "[runcc][warning] failed to write job summary {}: {}",
+                    path, err
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "notify-desktop")]
+    if notify {
+        super::notify::notify_desktop(&report).await;
+    }
+
+    if format == OutputFormat::Tap {
+        for line in tap_report(&stopped) {
+            println!("{}", line);
+        }
+    }
+
+    if !quiet {
+        let colored = color.colors(Stream::Stderr);
+        if !no_summary {
+            for line in summary_table(&stopped, colored) {
+                eprintln!("{}", line);
+            }
+            eprintln!("{}", summary_line(&report, started.elapsed(), colored));
+        }
+    }
+
+    let skipped: Vec<_> = stopped
+        .iter()
+        .filter_map(|cmd| Some((cmd.data.label.label(), cmd.skipped()?)))
+        .collect();
+    if fail_on_skip && !skipped.is_empty() {
+        eprintln!("[runcc][error] skips are not allowed, but these commands were skipped:");
+        for (label, skipped) in skipped {
+            eprintln!("  command[{}]: {}", label, skipped.0);
+        }
+        // also if the command which stopped last succeeded
+        exit_code_policy = crate::ExitCodePolicy::SuccessOnly;
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = control_socket {
+        let _ = std::fs::remove_file(path);
+    }
+
+    report.exit_code_policy = exit_code_policy;
+    Ok(report)
+}
+
+/// Runs the [`RunConfig::after`](crate::RunConfig::after) steps, warning about those which failed
+async fn run_after_steps(steps: &[crate::CommandConfig], envs: Option<&HashMap<String, String>>) {
+    for step in steps {
+        if let Err(failed) = run_steps(std::slice::from_ref(step), envs).await {
+            eprintln!("[runcc][warning] after {}", failed);
+        }
+    }
+}
+
+/// Kills all commands like on SIGTERM, e.g. on a request through the control socket or the tui
+#[cfg(unix)]
+fn stop_all<T: Clone + Send + Sync + 'static>(
+    killer: CommandSystemKiller<T>,
+    progress: Arc<ShutdownProgress>,
+    events: Option<Arc<EventsLog>>,
+    reason: &'static str,
+    timeout: Duration,
+) -> impl Fn() + Send + Sync + 'static {
+    // may be called from threads outside of the runtime, e.g. by the tui
+    let runtime = tokio::runtime::Handle::current();
+    move || {
+        if let Some(message) = progress.start() {
+            eprintln!("{}", message);
+        }
+        if let Some(events) = &events {
+            events.shutdown(reason);
+        }
+        let killer = killer.clone();
+        runtime.spawn(async move { killer.kill_all_with_timeout(timeout).await });
+    }
+}
+
+/// Reads the config file again each time runcc receives SIGHUP (Unix only) or `watcher`
+/// sees it change, and applies its changes, see [`Reloader::reload`].
+/// `args` are the arguments runcc was started with, `picked` the labels of the commands
+/// picked to run, if not all.
+fn reload_on_sighup_or_change<P>(
+    reloader: Arc<Mutex<Reloader<LabeledCommandData, P>>>,
+    args: Vec<OsString>,
+    picked: Option<Vec<String>>,
+    secrets: Vec<String>,
+    mut ports: HashMap<String, u16>,
+    mut watcher: Option<FileWatcher>,
+) where
+    P: CommandSystemPlugin<LabeledCommandData, CommandInitialData = LabeledCommandData>,
+{
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let mut sighup = {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            match signal(SignalKind::hangup()) {
+                Ok(sighup) => Some(sighup),
+                Err(err) => {
+                    eprintln!(
+                        "[runcc][warning] failed to setup SIGHUP signal handler: {}",
+                        err
+                    );
+                    None
+                }
+            }
+        };
+        loop {
+            #[cfg(unix)]
+            let sighup = async {
+                match &mut sighup {
+                    Some(sighup) => sighup.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let sighup = std::future::pending::<Option<()>>();
+            let changed = async {
+                match &mut watcher {
+                    Some(watcher) => watcher.changed().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                received = sighup => if received.is_none() {
+                    return;
+                },
+                _ = changed => {}
+            }
+
+            let config = match reload_config(&args, picked.as_deref(), &secrets, &mut ports).await {
+                Ok(config) => config,
+                Err(err) => {
+                    eprintln!("[runcc][warning] failed to reload config: {}", err);
+                    continue;
+                }
+            };
+            let reloaded = reloader.lock().unwrap().reload(config);
+            match reloaded {
+                Ok(changes) => eprintln!("[runcc][info] reloaded config: {}", changes),
+                // all commands stopped, runcc exits
+                Err(_) => return,
+            }
+        }
+    });
+}
+
+/// The config of `args` read again like on startup, which may only use `secrets`
+/// since the output masks only these. Commands keep their assigned `ports`.
+/// With `picked`, only the commands with these labels run.
+async fn reload_config(
+    args: &[OsString],
+    picked: Option<&[String]>,
+    secrets: &[String],
+    ports: &mut HashMap<String, u16>,
+) -> io::Result<RunConfig> {
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+
+    let mut config = Opts::parse_from(args)
+        .try_into_config()
+        .map_err(|err| invalid(format!("{}", err)))?;
+    if let Some(picked) = picked {
+        config
+            .commands
+            .retain(|cmd| picked.contains(&cmd.resolved_label()));
+    }
+    config
+        .load_env_files()
+        .map_err(|err| invalid(format!("{}", err)))?;
+    let new_secrets = config
+        .resolve_secrets()
+        .map_err(|err| invalid(format!("{}", err)))?;
+    if new_secrets.iter().any(|secret| !secrets.contains(secret)) {
+        return Err(invalid(
+            "secrets changed, they are only masked once runcc is restarted".to_string(),
+        ));
+    }
+    config
+        .resolve_computed_env()
+        .await
+        .map_err(|err| io::Error::other(format!("{}", err)))?;
+    config.assign_ports(ports)?;
+    Ok(config)
+}
+
+/// Writes the state of commands to `path` each time runcc receives SIGUSR1
+fn write_status_on_sigusr1(status: Arc<StatusBoard>, path: String) {
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigusr1 = match signal(SignalKind::user_defined1()) {
+            Ok(sigusr1) => sigusr1,
+            Err(err) => {
+                eprintln!(
+                    "[runcc][warning] failed to setup SIGUSR1 signal handler: {}",
+                    err
+                );
+                return;
+            }
+        };
+        while sigusr1.recv().await.is_some() {
+            if let Err(err) = status.write(&path) {
+                eprintln!(
+                    "[runcc][warning] failed to write status file {}: {}",
+                    path, err
+                );
+            }
+        }
+    });
+
+    #[cfg(not(unix))]
+    {
+        let _ = (status, path);
+        eprintln!("[runcc][warning] --status-json needs SIGUSR1, which is only supported on Unix");
+    }
 }
@@ -0,0 +1,280 @@
+use serde::Deserialize;
+
+use super::OptionsError;
+
+/// A positional command after expanding the `label:command` shorthand and
+/// `@bin <name>`/`@example <name>` entries, see [`expand_commands`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CliCommand {
+    pub script: String,
+    pub label: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// The parts of the output of `cargo metadata` needed to find the package of a target
+#[derive(Deserialize, Debug)]
+pub struct CargoMetadata {
+    packages: Vec<CargoPackage>,
+    workspace_root: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct CargoPackage {
+    name: String,
+    targets: Vec<CargoTarget>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CargoTarget {
+    name: String,
+    kind: Vec<String>,
+}
+
+impl CargoMetadata {
+    /// Runs `cargo metadata` for the workspace of the current directory
+    pub fn read() -> Result<Self, String> {
+        let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+        let output = std::process::Command::new(&cargo)
+            .args(["metadata", "--format-version", "1", "--no-deps"])
+            .stderr(std::process::Stdio::inherit())
+            .output()
+            .map_err(|err| format!("failed to run `{} metadata`: {}", cargo, err))?;
+        if !output.status.success() {
+            return Err(format!(
+                "`{} metadata` failed with {}",
+                cargo, output.status
+            ));
+        }
+        serde_json::from_slice(&output.stdout)
+            .map_err(|err| format!("failed to parse the output of `cargo metadata`: {}", err))
+    }
+
+    /// The packages with a target of `kind`, e.g. `bin`, named `name`
+    fn packages_with(&self, kind: &str, name: &str) -> Vec<&str> {
+        self.packages
+            .iter()
+            .filter(|package| {
+                package
+                    .targets
+                    .iter()
+                    .any(|target| target.name == name && target.kind.iter().any(|k| k == kind))
+            })
+            .map(|package| package.name.as_str())
+            .collect()
+    }
+}
+
+/// Splits `label:command` into its label and command. The label is a word of letters,
+/// digits, `_`, `-` and `.`, so that commands like `echo a:b`, `C:\bin\app.exe` or
+/// `http://localhost` are left alone.
+fn split_label(command: &str) -> (Option<&str>, &str) {
+    let (label, rest) = match command.split_once(':') {
+        Some(split) => split,
+        None => return (None, command),
+    };
+    let is_word = !label.is_empty()
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    let is_drive = label.len() == 1 && rest.starts_with(['\\', '/']);
+    let is_url = rest.starts_with("//");
+    if is_word && !is_drive && !is_url && !rest.trim().is_empty() {
+        (Some(label), rest.trim_start())
+    } else {
+        (None, command)
+    }
+}
+
+/// Expands the positional commands:
+///
+/// - `check:cargo check` runs `cargo check` labeled `check`
+/// - `@bin <name> [args]` runs `cargo run -p <package> --bin <name> -- [args]` in the
+///   workspace root, labeled `<name>`, with the package which has the binary
+/// - `@example <name> [args]` does the same for an example
+///
+/// `metadata` is only called, once, if any command refers to a target.
+pub fn expand_commands(
+    commands: Vec<String>,
+    mut metadata: impl FnMut() -> Result<CargoMetadata, String>,
+) -> Result<Vec<CliCommand>, OptionsError> {
+    let mut read = None;
+    commands
+        .into_iter()
+        .map(|command| {
+            let (label, script) = split_label(&command);
+            let label = label.map(str::to_string);
+            let script = script.trim();
+            let target = match script.strip_prefix('@') {
+                Some(target) => target,
+                None => {
+                    return Ok(CliCommand {
+                        script: script.to_string(),
+                        label,
+                        cwd: None,
+                    })
+                }
+            };
+
+            let failed = |reason: String| OptionsError::CargoTargetError {
+                entry: command.clone(),
+                reason,
+            };
+            let mut words = target.splitn(3, char::is_whitespace);
+            let kind = words.next().unwrap_or_default();
+            let name = words.next().unwrap_or_default();
+            let args = words.next().unwrap_or_default().trim();
+            let flag = match kind {
+                "bin" => "--bin",
+                "example" => "--example",
+                _ => {
+                    return Err(failed(format!(
+                        "unknown target kind `@{}`, expected `@bin` or `@example`",
+                        kind
+                    )))
+                }
+            };
+            if name.is_empty() {
+                return Err(failed(format!("expected `@{} <name>`", kind)));
+            }
+
+            if read.is_none() {
+                read = Some(metadata());
+            }
+            let metadata = match read.as_ref().unwrap() {
+                Ok(metadata) => metadata,
+                Err(err) => return Err(failed(err.clone())),
+            };
+            let package = match &metadata.packages_with(kind, name)[..] {
+                [package] => package.to_string(),
+                [] => {
+                    return Err(failed(format!(
+                        "no package of the workspace has a {} named `{}`",
+                        kind, name
+                    )))
+                }
+                packages => {
+                    return Err(failed(format!(
+                        "the packages {} all have a {} named `{}`",
+                        packages.join(", "),
+                        kind,
+                        name
+                    )))
+                }
+            };
+
+            let mut script = format!("cargo run -p {} {} {}", package, flag, name);
+            if !args.is_empty() {
+                script.push_str(" -- ");
+                script.push_str(args);
+            }
+            Ok(CliCommand {
+                script,
+                label: Some(label.unwrap_or_else(|| name.to_string())),
+                cwd: Some(metadata.workspace_root.clone()),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_commands, split_label, CargoMetadata, CliCommand};
+
+    #[test]
+    fn split_labels() {
+        assert_eq!(
+            split_label("check:cargo check --watch"),
+            (Some("check"), "cargo check --watch")
+        );
+        assert_eq!(
+            split_label("web-1: npm start"),
+            (Some("web-1"), "npm start")
+        );
+        for command in [
+            "echo a:b",
+            "C:\\bin\\app.exe",
+            "http://localhost",
+            "check:",
+            ":cargo check",
+            "cargo check",
+        ] {
+            assert_eq!(split_label(command), (None, command));
+        }
+    }
+
+    #[test]
+    fn expand_cargo_targets() {
+        let metadata = || -> Result<CargoMetadata, String> {
+            Ok(serde_json::from_str(
+                r#"{
+                    "packages": [
+                        { "name": "app", "targets": [
+                            { "name": "app", "kind": ["lib"] },
+                            { "name": "server", "kind": ["bin"] }
+                        ] },
+                        { "name": "tools", "targets": [
+                            { "name": "seed", "kind": ["example"] },
+                            { "name": "migrate", "kind": ["bin"] }
+                        ] },
+                        { "name": "legacy", "targets": [
+                            { "name": "migrate", "kind": ["bin"] }
+                        ] }
+                    ],
+                    "workspace_root": "/work"
+                }"#,
+            )
+            .unwrap())
+        };
+        let expand = |commands: &[&str]| {
+            expand_commands(commands.iter().map(|c| c.to_string()).collect(), metadata)
+        };
+
+        assert_eq!(
+            expand(&[
+                "check:cargo check --watch",
+                "@bin server --port 8080",
+                "data:@example seed"
+            ])
+            .unwrap(),
+            [
+                CliCommand {
+                    script: "cargo check --watch".to_string(),
+                    label: Some("check".to_string()),
+                    cwd: None,
+                },
+                CliCommand {
+                    script: "cargo run -p app --bin server -- --port 8080".to_string(),
+                    label: Some("server".to_string()),
+                    cwd: Some("/work".to_string()),
+                },
+                CliCommand {
+                    script: "cargo run -p tools --example seed".to_string(),
+                    label: Some("data".to_string()),
+                    cwd: Some("/work".to_string()),
+                },
+            ]
+        );
+
+        let reason = |command: &str| match expand(&[command]).unwrap_err() {
+            super::OptionsError::CargoTargetError { reason, .. } => reason,
+            err => panic!("{}", err),
+        };
+        assert_eq!(
+            reason("@bin seed"),
+            "no package of the workspace has a bin named `seed`"
+        );
+        assert_eq!(
+            reason("@bin migrate"),
+            "the packages tools, legacy all have a bin named `migrate`"
+        );
+        assert_eq!(
+            reason("@test it"),
+            "unknown target kind `@test`, expected `@bin` or `@example`"
+        );
+        assert_eq!(reason("@example"), "expected `@example <name>`");
+
+        // metadata is only read for targets
+        let plain = expand_commands(vec!["echo hi".to_string()], || Err("no cargo".into()));
+        assert_eq!(plain.unwrap()[0].script, "echo hi");
+    }
+}
@@ -0,0 +1,147 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Which CI system the output of each command is grouped for,
+/// so that it is shown as a collapsible section
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CiFormat {
+    /// `::group::` workflow commands of GitHub Actions
+    Github,
+    /// `section_start` and `section_end` markers of GitLab CI
+    Gitlab,
+}
+
+/// `auto` is resolved by [`CiFormat::detect`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CiFormatArg {
+    Auto,
+    Format(CiFormat),
+}
+
+impl std::str::FromStr for CiFormatArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "github" => Ok(Self::Format(CiFormat::Github)),
+            "gitlab" => Ok(Self::Format(CiFormat::Gitlab)),
+            _ => Err(format!(
+                "unknown ci format {:?}, expected github, gitlab or auto",
+                s
+            )),
+        }
+    }
+}
+
+impl CiFormatArg {
+    pub fn resolve(self) -> Option<CiFormat> {
+        match self {
+            Self::Auto => CiFormat::detect(),
+            Self::Format(format) => Some(format),
+        }
+    }
+}
+
+/// Name of a GitLab section, which may only contain letters, digits, `_`, `.` and `-`
+fn section_name(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' | '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn unix_secs(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+impl CiFormat {
+    /// The CI system runcc runs in, from the environment variables it sets
+    pub fn detect() -> Option<Self> {
+        if std::env::var_os("GITHUB_ACTIONS").is_some_and(|v| v == "true") {
+            Some(Self::Github)
+        } else if std::env::var_os("GITLAB_CI").is_some() {
+            Some(Self::Gitlab)
+        } else {
+            None
+        }
+    }
+
+    /// Wraps `output` of the command in markers of a section titled with `label`,
+    /// which started and ended at the given times
+    pub fn group(
+        self,
+        label: &str,
+        output: &[u8],
+        started_at: SystemTime,
+        ended_at: SystemTime,
+    ) -> Vec<u8> {
+        let (start, end) = match self {
+            Self::Github => (
+                format!("::group::{}\n", label),
+                "::endgroup::\n".to_string(),
+            ),
+            Self::Gitlab => {
+                let name = section_name(label);
+                (
+                    format!(
+                        "\x1b[0Ksection_start:{}:{}[collapsed=true]\r\x1b[0K{}\n",
+                        unix_secs(started_at),
+                        name,
+                        label
+                    ),
+                    format!(
+                        "\x1b[0Ksection_end:{}:{}\r\x1b[0K\n",
+                        unix_secs(ended_at),
+                        name
+                    ),
+                )
+            }
+        };
+
+        let mut group = start.into_bytes();
+        group.extend_from_slice(output);
+        if !output.is_empty() && !output.ends_with(b"\n") {
+            group.push(b'\n');
+        }
+        group.extend_from_slice(end.as_bytes());
+        group
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{CiFormat, CiFormatArg};
+
+    #[test]
+    fn group_output() {
+        let output = b"[web] compiling\n[web] listening\n";
+        let started_at = UNIX_EPOCH + Duration::from_secs(1700000000);
+        let ended_at = started_at + Duration::from_secs(3);
+
+        let group = CiFormat::Github.group("web", output, started_at, ended_at);
+        assert_eq!(
+            String::from_utf8(group).unwrap(),
+            "::group::web\n[web] compiling\n[web] listening\n::endgroup::\n"
+        );
+
+        let group = CiFormat::Gitlab.group("npm run dev", output, started_at, ended_at);
+        assert_eq!(
+            String::from_utf8(group).unwrap(),
+            "\x1b[0Ksection_start:1700000000:npm_run_dev[collapsed=true]\r\x1b[0Knpm run dev\n\
+             [web] compiling\n[web] listening\n\
+             \x1b[0Ksection_end:1700000003:npm_run_dev\r\x1b[0K\n"
+        );
+    }
+
+    #[test]
+    fn parse_ci_format() {
+        assert_eq!("auto".parse(), Ok(CiFormatArg::Auto));
+        assert_eq!("gitlab".parse(), Ok(CiFormatArg::Format(CiFormat::Gitlab)));
+        assert!("jenkins".parse::<CiFormatArg>().is_err());
+    }
+}
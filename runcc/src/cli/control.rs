@@ -0,0 +1,190 @@
+//! A Unix domain socket to control a running runcc, see `--control-socket` and `cargo runcc ctl`.
+//!
+//! Each request is one line of JSON, e.g. `{"command":"restart","label":"web"}`,
+//! and is answered by one line of JSON, e.g. `{"ok":true}` or
+//! `{"ok":false,"error":"no command[db]"}`.
+
+use std::{
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use clap::Parser;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use super::{respond, ControlRequest, ControlResponse, ControlledCommands};
+
+/// The socket `cargo runcc ctl` connects to without `--socket`
+const DEFAULT_CONTROL_SOCKET: &str = ".runcc.sock";
+
+/// The listening control socket of runcc
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// Listens at `path`, replacing a socket left over by a runcc which didn't exit cleanly
+    pub fn bind(path: &str) -> io::Result<Self> {
+        let listener = match UnixListener::bind(path) {
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+                if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                    return Err(io::Error::new(
+                        err.kind(),
+                        format!("control socket {} is used by another runcc", path),
+                    ));
+                }
+                std::fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            result => result?,
+        };
+        Ok(Self {
+            listener,
+            path: path.into(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Answers requests about `commands` until runcc exits,
+    /// `stop` is called on `stop` requests
+    pub fn serve(
+        self,
+        commands: Arc<dyn ControlledCommands>,
+        stop: impl Fn() + Send + Sync + 'static,
+    ) {
+        let stop: Arc<dyn Fn() + Send + Sync> = Arc::new(stop);
+        tokio::spawn(async move {
+            loop {
+                let stream = match self.listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        eprintln!(
+                            "[runcc][warning] control socket {} stopped accepting connections: {}",
+                            self.path.display(),
+                            err
+                        );
+                        return;
+                    }
+                };
+                let (commands, stop) = (commands.clone(), stop.clone());
+                tokio::spawn(async move {
+                    // the client went away
+                    let _ = answer(stream, &*commands, &*stop).await;
+                });
+            }
+        });
+    }
+}
+
+/// Answers each request line of `stream`
+async fn answer(
+    stream: UnixStream,
+    commands: &dyn ControlledCommands,
+    stop: &(dyn Fn() + Send + Sync),
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => respond(request, commands, stop),
+            Err(err) => ControlResponse::error(format!("invalid request: {}", err)),
+        };
+        let mut json = serde_json::to_string(&response)?;
+        json.push('\n');
+        writer.write_all(json.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Control a running runcc through its --control-socket
+#[derive(Parser)]
+#[clap(bin_name = "cargo runcc ctl")]
+struct CtlOpts {
+    /// Path of the control socket
+    #[clap(short, long, default_value = DEFAULT_CONTROL_SOCKET)]
+    socket: String,
+    #[clap(subcommand)]
+    request: ControlRequest,
+}
+
+/// Sends `request` to the control socket at `path` and returns the response
+pub async fn send_request(path: &str, request: &ControlRequest) -> io::Result<ControlResponse> {
+    let stream = UnixStream::connect(path).await.map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("failed to connect to control socket {}: {}", path, err),
+        )
+    })?;
+    let (reader, mut writer) = stream.into_split();
+    let mut json = serde_json::to_string(request)?;
+    json.push('\n');
+    writer.write_all(json.as_bytes()).await?;
+
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// `cargo runcc ctl`, `args` without `ctl`
+pub async fn ctl(args: Vec<OsString>) -> io::Result<()> {
+    let opts = CtlOpts::parse_from(args);
+    let response = send_request(&opts.socket, &opts.request).await?;
+    if !response.ok {
+        let error = response
+            .error
+            .unwrap_or_else(|| "request failed".to_string());
+        return Err(io::Error::other(error));
+    }
+
+    let commands = response.commands.unwrap_or_default();
+    let width = commands.iter().map(|cmd| cmd.label.len()).max();
+    for cmd in &commands {
+        let width = width.unwrap_or_default();
+        match cmd.pid {
+            Some(pid) => println!("{:width$}  running  pid {}", cmd.label, pid, width = width),
+            None => println!("{:width$}  not running", cmd.label, width = width),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_requests() {
+        let request: ControlRequest =
+            serde_json::from_str(r#"{"command":"restart","label":"web"}"#).unwrap();
+        assert_eq!(
+            request,
+            ControlRequest::Restart {
+                label: "web".to_string()
+            }
+        );
+        let request: ControlRequest = serde_json::from_str(r#"{"command":"stop"}"#).unwrap();
+        assert_eq!(request, ControlRequest::Stop);
+        assert!(serde_json::from_str::<ControlRequest>(r#"{"command":"kill"}"#).is_err());
+
+        let opts = CtlOpts::parse_from(["cargo runcc ctl", "-s", "/tmp/runcc.sock", "kill", "web"]);
+        assert_eq!(opts.socket, "/tmp/runcc.sock");
+        assert_eq!(
+            opts.request,
+            ControlRequest::Kill {
+                label: "web".to_string()
+            }
+        );
+    }
+}
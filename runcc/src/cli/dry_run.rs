@@ -0,0 +1,276 @@
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::quote, CommandConfig, KillBehavior, PlannedCommand, RunConfig};
+
+/// Shell builtins which have no binary on PATH
+const SHELL_BUILTINS: &[&str] = &[
+    ".", ":", "alias", "cd", "eval", "exit", "export", "return", "set", "shift", "source", "trap",
+    "unset", "wait",
+];
+
+/// The program a command runs.
+///
+/// For commands given as a script, like `"npm start"`, this is the first word
+/// of the script instead of the shell running it.
+/// For commands with a [`script`](CommandConfig::script), this is the program of its shebang
+/// or the first word of the script.
+/// For commands with a [`shell`](CommandConfig::shell), this is the shell.
+/// `None` if the script starts with a shell builtin.
+fn program_of(cmd: &CommandConfig) -> Option<&str> {
+    if let Some(shell) = &cmd.shell {
+        return shell.split_whitespace().next();
+    }
+    if let Some(shebang) = cmd.script.as_deref().and_then(|s| s.strip_prefix("#!")) {
+        return shebang.split_whitespace().next();
+    }
+
+    let script = match (
+        cmd.script.as_deref(),
+        cmd.program.as_str(),
+        cmd.args.as_deref(),
+    ) {
+        (Some(script), _, _) => script,
+        (None, "sh", Some([flag, script])) if flag == "-c" => script,
+        (None, "cmd", Some([flag, script])) if flag.eq_ignore_ascii_case("/C") => script,
+        (None, program, _) => return Some(program),
+    };
+
+    let mut words = script.split_whitespace();
+    let mut program = words.next()?;
+    if program == "exec" {
+        program = words.next()?;
+    }
+    (!SHELL_BUILTINS.contains(&program)).then_some(program)
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file()
+    }
+}
+
+/// Finds `program` like the OS would when spawning it with `path` as PATH.
+///
+/// Programs with a path separator are looked up relative to `cwd`.
+fn find_program(program: &str, path: Option<&OsString>, cwd: Option<&str>) -> Option<PathBuf> {
+    let candidates = |dir: &Path| {
+        let found = dir.join(program);
+        #[cfg(windows)]
+        {
+            let mut candidates = vec![found.clone()];
+            if found.extension().is_none() {
+                candidates.push(found.with_extension("exe"));
+                candidates.push(found.with_extension("cmd"));
+                candidates.push(found.with_extension("bat"));
+            }
+            candidates
+        }
+        #[cfg(not(windows))]
+        vec![found]
+    };
+
+    if program.contains(std::path::is_separator) {
+        let dir = Path::new(cwd.unwrap_or("."));
+        return candidates(dir).into_iter().find(|path| is_executable(path));
+    }
+
+    std::env::split_paths(path?)
+        .flat_map(|dir| candidates(&dir))
+        .find(|path| is_executable(path))
+}
+
+/// The PATH `cmd` is spawned with, the envs of the command take precedence
+fn path_of(cmd: &CommandConfig, config: &RunConfig) -> Option<OsString> {
+    let own = cmd
+        .envs
+        .iter()
+        .flatten()
+        .rev()
+        .find(|(name, _)| name == "PATH")
+        .map(|(_, value)| value);
+    let global = config.envs.as_ref().and_then(|envs| envs.get("PATH"));
+
+    own.or(global)
+        .map(OsString::from)
+        .or_else(|| std::env::var_os("PATH"))
+}
+
+/// What `--dry-run --format json` prints
+#[derive(Serialize)]
+struct DryRun<'a> {
+    kill: &'a KillBehavior,
+    max_concurrency: usize,
+    /// In the order of the config
+    commands: Vec<CheckedCommand<'a>>,
+}
+
+/// A [`PlannedCommand`] with where its program was found
+#[derive(Serialize)]
+struct CheckedCommand<'a> {
+    #[serde(flatten)]
+    command: &'a PlannedCommand,
+    /// See [`RunPlan::stages`](crate::RunPlan::stages)
+    stage: usize,
+    /// The program looked up on PATH, e.g. `npm` for `npm start`,
+    /// `None` if the command starts with a shell builtin
+    lookup: Option<&'a str>,
+    /// Where `lookup` was found, `None` if it wasn't
+    found: Option<PathBuf>,
+}
+
+/// Prints what each command would run as it is spawned, with its cwd, envs and
+/// prerequisites, and where its program was found on PATH. Prints JSON if `json`.
+///
+/// Returns how many programs were not found.
+pub fn dry_run(config: &RunConfig, json: bool) -> usize {
+    let plan = config.plan();
+    let mut planned: HashMap<_, _> = plan
+        .stages
+        .iter()
+        .enumerate()
+        .flat_map(|(stage, commands)| commands.iter().map(move |cmd| (&cmd.label, (stage, cmd))))
+        .collect();
+
+    let mut missing = 0;
+    let mut checked = Vec::with_capacity(config.commands.len());
+    for cmd in &config.commands {
+        let label = cmd.resolved_label();
+        let (stage, command) = match planned.remove(&label) {
+            Some(planned) => planned,
+            // a duplicate label, which the validation rejects
+            None => continue,
+        };
+        let lookup = program_of(cmd);
+        let path = path_of(cmd, config);
+        let found =
+            lookup.and_then(|program| find_program(program, path.as_ref(), cmd.cwd.as_deref()));
+        if lookup.is_some() && found.is_none() {
+            missing += 1;
+        }
+        checked.push(CheckedCommand {
+            command,
+            stage,
+            lookup,
+            found,
+        });
+    }
+
+    if json {
+        let dry_run = DryRun {
+            kill: &plan.kill,
+            max_concurrency: plan.max_concurrency,
+            commands: checked,
+        };
+        let json = serde_json::to_string_pretty(&dry_run).expect("the plan serializes to JSON");
+        println!("{}", json);
+        return missing;
+    }
+
+    println!("kill: {}", plan.kill);
+    for checked in &checked {
+        let cmd = checked.command;
+        match (checked.lookup, &checked.found) {
+            (Some(program), Some(found)) => {
+                println!("[{}] {} -> {}", cmd.label, program, found.display())
+            }
+            (Some(program), None) => println!("[{}] {} not found on PATH", cmd.label, program),
+            (None, _) => println!("[{}] starts with a shell builtin, not checked", cmd.label),
+        }
+
+        if !cmd.spawned.is_empty() {
+            let words: Vec<_> = cmd.spawned.iter().map(|word| quote(word)).collect();
+            println!("    run: {}", words.join(" "));
+        }
+        if let Some(script) = cmd.script.as_ref().filter(|_| cmd.spawned.is_empty()) {
+            println!("    script:");
+            for line in script.lines() {
+                println!("      {}", line);
+            }
+        }
+        if let Some(cwd) = &cmd.cwd {
+            println!("    cwd: {}", cwd);
+        }
+        for (name, value) in &cmd.envs {
+            println!("    env: {}={}", name, quote(value));
+        }
+        if !cmd.after.is_empty() {
+            println!("    after: {}", cmd.after.join(", "));
+        }
+        if !cmd.after_ready.is_empty() {
+            println!("    after_ready: {}", cmd.after_ready.join(", "));
+        }
+    }
+    missing
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{find_program, path_of, program_of};
+    use crate::{CommandConfig, CommandConfigFromScriptOptions, RunConfig, RunConfigInput};
+
+    #[test]
+    fn find_programs_on_path_override() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let tool = dir.path().join("my-tool");
+        std::fs::write(&tool, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(dir.path().join("not-executable"), "").unwrap();
+
+        let input: RunConfigInput = serde_yaml::from_str(&format!(
+            r#"
+commands:
+  - program: my-tool
+    envs: [[PATH, "{}"]]
+  - my-tool --verbose
+"#,
+            dir.path().display()
+        ))
+        .unwrap();
+        let config: RunConfig = input.into();
+        let found = |cmd: &CommandConfig| {
+            let program = program_of(cmd).unwrap();
+            find_program(program, path_of(cmd, &config).as_ref(), None)
+        };
+
+        assert_eq!(found(&config.commands[0]), Some(tool.clone()));
+        assert_eq!(found(&config.commands[1]), None);
+
+        let path = dir.path().as_os_str().to_owned();
+        assert_eq!(find_program("not-executable", Some(&path), None), None);
+        let cwd = dir.path().to_str();
+        assert_eq!(
+            find_program("./my-tool", None, cwd),
+            Some(dir.path().join("./my-tool"))
+        );
+
+        let options = CommandConfigFromScriptOptions::default();
+        let script = CommandConfig::from_script("cd web && npm start", &options);
+        assert_eq!(program_of(&script), None);
+        let script = CommandConfig::from_script("exec sleep 5", &options);
+        assert_eq!(program_of(&script), Some("sleep"));
+
+        let script = |script: &str| CommandConfig {
+            script: Some(script.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(program_of(&script("npm ci\nnpm test\n")), Some("npm"));
+        assert_eq!(
+            program_of(&script("#!/usr/bin/env python3\nprint(1)\n")),
+            Some("/usr/bin/env")
+        );
+    }
+}
@@ -1,19 +1,37 @@
 use std::fmt::Display;
 
-use crate::read::error::FindConfigError;
+use crate::{read::error::FindConfigError, ConfigError};
 
 #[derive(Debug)]
 pub enum OptionsError {
     ConfigFileError(FindConfigError),
+    InvalidConfig(ConfigError),
+    /// The commands of a config fragment of `--config-dir` can't be added
+    InvalidFragment {
+        file: String,
+        error: ConfigError,
+    },
     EnvSyntaxError(String),
+    /// A `--set` var is no KEY=VALUE
+    VarSyntaxError(String),
+    /// A `@bin <name>` or `@example <name>` command can't be expanded
+    CargoTargetError {
+        entry: String,
+        reason: String,
+    },
     DuplicateConfigs,
     NoConfigs,
+    NoCommandsMatchTags,
+    /// Commands left out by tags with why, while skips fail the run
+    CommandsSkipped(Vec<(String, String)>),
 }
 
 impl std::error::Error for OptionsError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             OptionsError::ConfigFileError(err) => Some(err),
+            OptionsError::InvalidConfig(err) => Some(err),
+            OptionsError::InvalidFragment { error, .. } => Some(error),
             _ => None,
         }
     }
@@ -23,9 +41,19 @@ impl Display for OptionsError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             OptionsError::ConfigFileError(err) => write!(f, "Config file error: {}", err),
+            OptionsError::InvalidConfig(err) => write!(f, "Invalid config: {}", err),
+            OptionsError::InvalidFragment { file, error } => {
+                write!(f, "Invalid config fragment {}: {}", file, error)
+            }
             OptionsError::EnvSyntaxError(env) => {
                 write!(f, "The following env var has invalid syntax: {}", env)
             }
+            OptionsError::VarSyntaxError(var) => {
+                write!(f, "The following --set var has invalid syntax: {}", var)
+            }
+            OptionsError::CargoTargetError { entry, reason } => {
+                write!(f, "Can't expand the command `{}`: {}", entry, reason)
+            }
             OptionsError::DuplicateConfigs => {
                 write!(
                     f,
@@ -38,6 +66,19 @@ impl Display for OptionsError {
                     "Please specify commands from config file or positional arguments"
                 )
             }
+            OptionsError::NoCommandsMatchTags => {
+                write!(f, "No commands match --tag and --exclude-tag")
+            }
+            OptionsError::CommandsSkipped(skipped) => {
+                write!(
+                    f,
+                    "Skips are not allowed, but these commands would be skipped:"
+                )?;
+                for (label, reason) in skipped {
+                    write!(f, "\n  command[{}]: {}", label, reason)?;
+                }
+                Ok(())
+            }
         }
     }
 }
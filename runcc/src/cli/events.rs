@@ -0,0 +1,294 @@
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
+};
+use tokio::sync::oneshot;
+
+use crate::run::{CommandOutcome, CommandSystemSimpleReport, ReadySignal};
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum EventKind<'a> {
+    Spawned {
+        label: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pane: Option<&'a str>,
+    },
+    /// the `ready_when` pattern matched or the startup probe succeeded
+    Ready { label: &'a str },
+    /// written before `spawned` when a command is spawned again
+    Restarted { label: &'a str, restarts: usize },
+    Exited {
+        label: &'a str,
+        /// `succeeded` or `failed`
+        outcome: &'a str,
+        exit_code: Option<i32>,
+    },
+    Killed {
+        label: &'a str,
+        reason: &'a str,
+        exit_code: Option<i32>,
+    },
+    /// runcc started to kill all commands
+    Shutdown { reason: &'a str },
+    /// all commands stopped
+    Stopped {
+        command_count_total: usize,
+        command_count_success: usize,
+        command_count_killed: usize,
+    },
+}
+
+#[derive(Serialize, Debug)]
+struct Event<'a> {
+    /// RFC 3339 timestamp
+    time: String,
+    /// milliseconds since the run started
+    elapsed_ms: u64,
+    #[serde(flatten)]
+    kind: EventKind<'a>,
+}
+
+#[derive(Default)]
+struct CommandRuns {
+    spawn_count: usize,
+    ready_logged: bool,
+    /// dropped when the current run exited, which stops waiting for it to become ready
+    exited: Option<oneshot::Sender<()>>,
+}
+
+struct EventsState {
+    file: File,
+    commands: HashMap<String, CommandRuns>,
+    shutdown_logged: bool,
+    /// whether a write failed, which is only reported once
+    failed: bool,
+}
+
+/// Writes lifecycle events of commands to a file, one JSON object per line
+pub struct EventsLog {
+    started_at: Instant,
+    started_at_system: SystemTime,
+    state: Mutex<EventsState>,
+}
+
+impl EventsLog {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            started_at: Instant::now(),
+            started_at_system: SystemTime::now(),
+            state: Mutex::new(EventsState {
+                file: File::create(path)?,
+                commands: Default::default(),
+                shutdown_logged: false,
+                failed: false,
+            }),
+        })
+    }
+
+    fn write(&self, state: &mut EventsState, at: Instant, kind: EventKind) {
+        let elapsed = at.saturating_duration_since(self.started_at);
+        let event = Event {
+            time: humantime::format_rfc3339_millis(self.started_at_system + elapsed).to_string(),
+            elapsed_ms: elapsed.as_millis() as u64,
+            kind,
+        };
+        let mut line = serde_json::to_string(&event).expect("events should serialize");
+        line.push('\n');
+
+        if let Err(err) = state.file.write_all(line.as_bytes()) {
+            if !state.failed {
+                state.failed = true;
+                eprintln!("[runcc][warning] failed to write events log: {}", err);
+            }
+        }
+    }
+
+    /// Logs that the command in `pane` was spawned, and when it becomes `ready`
+    pub fn command_spawned(
+        self: &Arc<Self>,
+        label: &str,
+        pane: Option<&str>,
+        ready: Option<ReadySignal>,
+    ) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let cmd = state.commands.entry(label.to_string()).or_default();
+        cmd.spawn_count += 1;
+        cmd.ready_logged = false;
+        let restarts = cmd.spawn_count - 1;
+        let (exited_tx, exited_rx) = oneshot::channel();
+        cmd.exited = Some(exited_tx);
+
+        if restarts > 0 {
+            self.write(&mut state, now, EventKind::Restarted { label, restarts });
+        }
+        self.write(&mut state, now, EventKind::Spawned { label, pane });
+
+        if let Some(ready) = ready {
+            let events = self.clone();
+            let label = label.to_string();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = ready.wait() => events.command_ready(&label, &ready),
+                    _ = exited_rx => {}
+                }
+            });
+        }
+    }
+
+    fn command_ready(&self, label: &str, ready: &ReadySignal) {
+        let mut state = self.state.lock().unwrap();
+        let cmd = match state.commands.get_mut(label) {
+            Some(cmd) if cmd.exited.is_some() && !cmd.ready_logged => cmd,
+            _ => return,
+        };
+        cmd.ready_logged = true;
+
+        let at = ready.ready_at().unwrap_or_else(Instant::now);
+        self.write(&mut state, at, EventKind::Ready { label });
+    }
+
+    /// Logs that the command exited, `killed` is the reason if runcc killed it
+    pub fn command_exited(
+        &self,
+        label: &str,
+        ready: Option<&ReadySignal>,
+        outcome: CommandOutcome,
+        killed: Option<&str>,
+        exit_code: Option<i32>,
+    ) {
+        // the ready event may not have been written before the command exited
+        if let Some(ready) = ready.filter(|ready| ready.is_ready()) {
+            self.command_ready(label, ready);
+        }
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if let Some(cmd) = state.commands.get_mut(label) {
+            cmd.exited = None;
+        }
+
+        let kind = match (outcome, killed) {
+            (CommandOutcome::Killed, Some(reason)) => EventKind::Killed {
+                label,
+                reason,
+                exit_code,
+            },
+            (CommandOutcome::Succeeded, _) => EventKind::Exited {
+                label,
+                outcome: "succeeded",
+                exit_code,
+            },
+            _ => EventKind::Exited {
+                label,
+                outcome: "failed",
+                exit_code,
+            },
+        };
+        self.write(&mut state, now, kind);
+    }
+
+    /// Logs that runcc started to kill all commands, only the first call is logged
+    pub fn shutdown(&self, reason: &str) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if !std::mem::replace(&mut state.shutdown_logged, true) {
+            self.write(&mut state, now, EventKind::Shutdown { reason });
+        }
+    }
+
+    pub fn stopped(&self, report: &CommandSystemSimpleReport) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let kind = EventKind::Stopped {
+            command_count_total: report.command_count_total,
+            command_count_success: report.command_count_success,
+            command_count_killed: report.command_count_killed,
+        };
+        self.write(&mut state, now, kind);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+    use std::sync::Arc;
+
+    use super::EventsLog;
+    use crate::run::{CommandOutcome, CommandSystemSimpleReport, ReadySignal};
+
+    #[tokio::test]
+    async fn lifecycle_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let events = Arc::new(EventsLog::create(&path).unwrap());
+
+        let ready = ReadySignal::new(Regex::new("listening").unwrap());
+        events.command_spawned("web", Some("servers"), Some(ready.clone()));
+        events.command_spawned("migrate", None, None);
+        ready.check_line("listening on :3000");
+        tokio::task::yield_now().await;
+
+        events.command_exited("migrate", None, CommandOutcome::Failed, None, Some(1));
+        events.command_spawned("migrate", None, None);
+        events.command_exited("migrate", None, CommandOutcome::Succeeded, None, Some(0));
+
+        events.shutdown("Ctrl-C signal");
+        events.shutdown("command[web] exited");
+        let reason = Some("Ctrl-C signal");
+        events.command_exited("web", Some(&ready), CommandOutcome::Killed, reason, None);
+        events.stopped(&CommandSystemSimpleReport {
+            command_count_total: 2,
+            command_count_success: 1,
+            command_count_killed: 1,
+            first_failed_code: None,
+            last_exit_code: None,
+            last_failed: false,
+            exit_code_policy: Default::default(),
+        });
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let events: Vec<_> = lines
+            .iter()
+            .map(|event| {
+                let label = event.get("label").or_else(|| event.get("reason"));
+                format!(
+                    "{} {}",
+                    event["event"],
+                    label.unwrap_or(&event["command_count_total"])
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                r#""spawned" "web""#,
+                r#""spawned" "migrate""#,
+                r#""ready" "web""#,
+                r#""exited" "migrate""#,
+                r#""restarted" "migrate""#,
+                r#""spawned" "migrate""#,
+                r#""exited" "migrate""#,
+                r#""shutdown" "Ctrl-C signal""#,
+                r#""killed" "web""#,
+                r#""stopped" 2"#,
+            ]
+        );
+        assert_eq!(lines[0]["pane"], "servers");
+        assert!(lines[1].get("pane").is_none());
+        assert_eq!(lines[3]["outcome"], "failed");
+        assert_eq!(lines[3]["exit_code"], 1);
+        assert_eq!(lines[4]["restarts"], 1);
+        assert!(lines[0]["time"].as_str().unwrap().ends_with('Z'));
+    }
+}
@@ -0,0 +1,184 @@
+use regex::Regex;
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use crate::run::CommandOutcome;
+
+/// The last output lines of a command run
+#[derive(Default)]
+pub struct LastLines {
+    pub stdout: Mutex<Option<String>>,
+    pub stderr: Mutex<Option<String>>,
+}
+
+struct CommandRun {
+    label: String,
+    last_lines: Arc<LastLines>,
+    /// `None` while the command is running
+    exit: Option<(CommandOutcome, String)>,
+}
+
+/// Collects command outcomes for GitHub Actions,
+/// which shows `::error` workflow commands as annotations.
+pub struct GithubAnnotations {
+    commands: Mutex<Vec<CommandRun>>,
+    ansi_escape: Regex,
+}
+
+/// Escapes the message of a workflow command
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escapes a property value, e.g. `title`, of a workflow command
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+fn annotation(label: &str, message: &str) -> String {
+    format!(
+        "::error title={}::{}",
+        escape_property(label),
+        escape_data(message)
+    )
+}
+
+impl GithubAnnotations {
+    pub fn new() -> Self {
+        Self {
+            commands: Default::default(),
+            ansi_escape: Regex::new(r"\x1B\[[0-9;?]*[ -/]*[@-~]").unwrap(),
+        }
+    }
+
+    fn command_run<'a>(commands: &'a mut Vec<CommandRun>, label: &str) -> &'a mut CommandRun {
+        let i = match commands.iter().position(|cmd| cmd.label == label) {
+            Some(i) => i,
+            None => {
+                commands.push(CommandRun {
+                    label: label.to_string(),
+                    last_lines: Default::default(),
+                    exit: None,
+                });
+                commands.len() - 1
+            }
+        };
+        &mut commands[i]
+    }
+
+    /// Returns where the output of this run of the command should be recorded
+    pub fn command_spawned(&self, label: &str) -> Arc<LastLines> {
+        let mut commands = self.commands.lock().unwrap();
+        let run = Self::command_run(&mut commands, label);
+        run.last_lines = Default::default();
+        run.exit = None;
+        run.last_lines.clone()
+    }
+
+    pub fn command_exited(&self, label: &str, outcome: CommandOutcome, status: String) {
+        let mut commands = self.commands.lock().unwrap();
+        Self::command_run(&mut commands, label).exit = Some((outcome, status));
+    }
+
+    /// An `::error` line for each command which failed on its own, with the last line it printed,
+    /// preferring stderr.
+    ///
+    /// Output must be read to the end before, so that the last lines are complete.
+    pub fn annotations(&self) -> Vec<String> {
+        let commands = self.commands.lock().unwrap();
+        commands
+            .iter()
+            .filter_map(|cmd| match &cmd.exit {
+                Some((CommandOutcome::Failed, status)) => {
+                    let last_line = cmd
+                        .last_lines
+                        .stderr
+                        .lock()
+                        .unwrap()
+                        .clone()
+                        .or_else(|| cmd.last_lines.stdout.lock().unwrap().clone());
+                    let message = match last_line {
+                        Some(line) => self.ansi_escape.replace_all(&line, "").into_owned(),
+                        None => format!("exited with status {}", status),
+                    };
+                    Some(annotation(&cmd.label, &message))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// A markdown table of all commands and how they exited
+    pub fn summary(&self) -> String {
+        let commands = self.commands.lock().unwrap();
+        let mut summary = String::from("## runcc\n\n| command | result |\n| --- | --- |\n");
+        for cmd in commands.iter() {
+            let result = match &cmd.exit {
+                Some((CommandOutcome::Succeeded, _)) => "succeeded".to_string(),
+                Some((CommandOutcome::Failed, status)) => format!("failed with status {}", status),
+                Some((CommandOutcome::Killed, status)) => format!("killed with status {}", status),
+                None => "still running".to_string(),
+            };
+            summary.push_str(&format!(
+                "| {} | {} |\n",
+                cmd.label.replace('|', "\\|"),
+                result
+            ));
+        }
+        summary
+    }
+
+    /// Appends the [`summary`](Self::summary) to the job summary file at `path`,
+    /// which GitHub Actions passes in `GITHUB_STEP_SUMMARY`
+    pub fn write_summary(&self, path: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(self.summary().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GithubAnnotations;
+    use crate::run::CommandOutcome;
+
+    #[test]
+    fn annotate_failed_commands() {
+        let github = GithubAnnotations::new();
+
+        let build = github.command_spawned("build");
+        *build.stdout.lock().unwrap() = Some("compiling".to_string());
+        *build.stderr.lock().unwrap() =
+            Some("\x1b[31merror\x1b[0m: 50% done, file: a.rs".to_string());
+        github.command_exited("build", CommandOutcome::Failed, "code 101".to_string());
+
+        github.command_spawned("lint");
+        github.command_exited("lint", CommandOutcome::Failed, "code 1".to_string());
+
+        github.command_spawned("server");
+        github.command_exited("server", CommandOutcome::Killed, "code None".to_string());
+
+        github.command_spawned("web, api");
+        github.command_exited("web, api", CommandOutcome::Succeeded, "code 0".to_string());
+
+        assert_eq!(
+            github.annotations(),
+            [
+                "::error title=build::error: 50%25 done, file: a.rs",
+                "::error title=lint::exited with status code 1",
+            ]
+        );
+        assert_eq!(
+            github.summary(),
+            "## runcc\n\n| command | result |\n| --- | --- |\n\
+             | build | failed with status code 101 |\n\
+             | lint | failed with status code 1 |\n\
+             | server | killed with status code None |\n\
+             | web, api | succeeded |\n"
+        );
+    }
+}
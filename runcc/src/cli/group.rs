@@ -0,0 +1,215 @@
+//! Prints the output of each command as one block once it exited, in the order of the
+//! commands, see `--group-output`.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use super::{Output, Stream};
+
+/// The output of one run of a command, held back until it is complete
+#[derive(Default)]
+struct Group {
+    items: Vec<(Stream, Vec<u8>)>,
+    /// Whether the output was read to the end
+    read: bool,
+    exit: Option<GroupExit>,
+}
+
+/// The lines written after the output of a run once it exited
+struct GroupExit {
+    line: Option<(Stream, String)>,
+    /// The exit line is only written if the run printed any output
+    hide_if_silent: bool,
+    message: Option<String>,
+}
+
+impl Group {
+    fn complete(&self) -> bool {
+        self.read && self.exit.is_some()
+    }
+}
+
+#[derive(Default)]
+struct Groups {
+    /// By index of the command, then in the order of the runs
+    pending: BTreeMap<(usize, u64), Group>,
+    /// How many runs were added, orders the runs of a command
+    runs: u64,
+}
+
+impl Groups {
+    /// The oldest run of the command at `index` which `is` matches
+    fn oldest(&mut self, index: usize, is: impl Fn(&Group) -> bool) -> Option<&mut Group> {
+        self.pending
+            .range_mut((index, 0)..=(index, u64::MAX))
+            .map(|(_, group)| group)
+            .find(|group| is(group))
+    }
+
+    fn add(&mut self, index: usize, group: Group) {
+        self.runs += 1;
+        self.pending.insert((index, self.runs), group);
+    }
+}
+
+/// Holds back the output of each run of a command until it exited and its output was read to
+/// the end, and then writes it to an [`Output`] as one block with its exit line.
+///
+/// A complete block is written once no run of a command before it in the config is pending,
+/// so that commands which run at the same time print in the order of the config.
+/// Commands which haven't been spawned yet, e.g. waiting for others, don't hold it back.
+#[derive(Default)]
+pub struct OutputGroups {
+    groups: Mutex<Groups>,
+}
+
+impl OutputGroups {
+    /// A run of the command at `index` was spawned
+    pub fn spawned(&self, index: usize) {
+        self.groups.lock().unwrap().add(index, Group::default());
+    }
+
+    /// The output of the oldest run of the command at `index` which was still read was read
+    /// to the end. It printed `items`, in order. Complete groups are written to `output`.
+    pub fn read(&self, output: &Output, index: usize, items: Vec<(Stream, Vec<u8>)>) {
+        let mut groups = self.groups.lock().unwrap();
+        match groups.oldest(index, |group| !group.read) {
+            Some(group) => {
+                group.items = items;
+                group.read = true;
+            }
+            None => return,
+        }
+        write_complete(output, &mut groups);
+    }
+
+    /// The oldest run of the command at `index` which hadn't exited yet exited, or the command
+    /// failed to spawn. Its `line` and `message` go after its output, `line` only if it printed
+    /// any output if `hide_if_silent`.
+    pub fn exited(
+        &self,
+        output: &Output,
+        index: usize,
+        line: Option<(Stream, String)>,
+        hide_if_silent: bool,
+        message: Option<String>,
+    ) {
+        let exit = GroupExit {
+            line,
+            hide_if_silent,
+            message,
+        };
+        let mut groups = self.groups.lock().unwrap();
+        match groups.oldest(index, |group| group.exit.is_none()) {
+            Some(group) => group.exit = Some(exit),
+            // never spawned, so it has no output
+            None => {
+                let group = Group {
+                    read: true,
+                    exit: Some(exit),
+                    ..Default::default()
+                };
+                groups.add(index, group);
+            }
+        }
+        write_complete(output, &mut groups);
+    }
+}
+
+/// Writes the complete groups in front of all pending ones to `output`
+fn write_complete(output: &Output, groups: &mut Groups) {
+    while let Some(entry) = groups.pending.first_entry() {
+        if !entry.get().complete() {
+            return;
+        }
+        let group = entry.remove();
+        let printed = !group.items.is_empty();
+        for (stream, bytes) in &group.items {
+            output.write(*stream, bytes);
+        }
+        let exit = group.exit.expect("a complete group exited");
+        let shown = printed || !exit.hide_if_silent;
+        if let Some((stream, line)) = exit.line.filter(|_| shown) {
+            output.write_line(stream, &line);
+        }
+        if let Some(message) = exit.message {
+            output.write_line(Stream::Stderr, &message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::OutputGroups;
+    use crate::cli::{Output, Stream};
+    use crate::OutputBackpressure;
+
+    #[test]
+    fn write_groups_in_order() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let sink = written.clone();
+        let output = Arc::new(Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| {
+                let text = String::from_utf8_lossy(bytes);
+                sink.lock()
+                    .unwrap()
+                    .extend(text.lines().map(str::to_string))
+            }),
+        ));
+        let groups = OutputGroups::default();
+        let exit = |label: &str| Some((Stream::Stderr, format!("[{}] exited", label)));
+        let lines = |lines: &[&str]| -> Vec<_> {
+            lines
+                .iter()
+                .map(|line| (Stream::Stdout, format!("{}\n", line).into_bytes()))
+                .collect()
+        };
+
+        groups.spawned(0);
+        groups.spawned(1);
+        groups.spawned(2);
+        // waits for fmt, which runs
+        groups.read(&output, 1, lines(&["[clippy] warning", "[clippy] done"]));
+        groups.exited(
+            &output,
+            1,
+            exit("clippy"),
+            false,
+            Some("[clippy] failed".to_string()),
+        );
+        groups.exited(&output, 2, exit("test"), true, None);
+        groups.read(&output, 2, vec![]);
+        output.flush();
+        assert!(written.lock().unwrap().is_empty());
+
+        groups.read(&output, 0, lines(&["[fmt] checked"]));
+        groups.exited(&output, 0, exit("fmt"), true, None);
+        // the next run of fmt holds back a command after it which failed to spawn
+        groups.spawned(0);
+        groups.exited(&output, 3, exit("skipped"), false, None);
+        output.flush();
+        assert_eq!(
+            *written.lock().unwrap(),
+            [
+                "[fmt] checked",
+                "[fmt] exited",
+                "[clippy] warning",
+                "[clippy] done",
+                "[clippy] exited",
+                "[clippy] failed",
+            ]
+        );
+
+        groups.exited(&output, 0, exit("fmt"), false, None);
+        groups.read(&output, 0, lines(&["[fmt] again"]));
+        output.flush();
+        assert_eq!(
+            written.lock().unwrap()[6..],
+            ["[fmt] again", "[fmt] exited", "[skipped] exited"]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! Reads control lines from stdin while commands run, see `--control-stdin`.
+//!
+//! `restart web` and `kill web` restart or kill a command like `cargo runcc ctl`,
+//! `send web y` and `web: y` write `y` to the stdin of `web`, and any other line is written
+//! to the stdin of all running commands.
+
+use std::io::{self, BufRead};
+use std::sync::Arc;
+
+use super::{respond, ControlRequest, ControlledCommands};
+
+/// The label `text` starts with, the longest of `labels` followed by whitespace,
+/// or else its first word, and the rest of `text`
+fn split_label<'a>(text: &'a str, labels: &[String]) -> (&'a str, &'a str) {
+    let known = labels
+        .iter()
+        .filter(|label| match text.strip_prefix(label.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with(char::is_whitespace),
+            None => false,
+        })
+        .max_by_key(|label| label.len());
+    let (label, rest) = match known {
+        Some(label) => text.split_at(label.len()),
+        None => text.split_once(char::is_whitespace).unwrap_or((text, "")),
+    };
+    (label, rest.trim_start())
+}
+
+/// Parses `line`, `labels` are those of the commands, which may contain spaces.
+/// Lines which aren't requests are written to all commands.
+fn parse_line(line: &str, labels: &[String]) -> ControlRequest {
+    let trimmed = line.trim();
+    let (word, rest) = match trimmed.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (trimmed, ""),
+    };
+    let label = rest.to_string();
+    match word {
+        "restart" if !rest.is_empty() => ControlRequest::Restart { label },
+        "kill" if !rest.is_empty() => ControlRequest::Kill { label },
+        "send" if !rest.is_empty() => {
+            let (label, text) = split_label(rest, labels);
+            ControlRequest::Send {
+                label: label.to_string(),
+                text: text.to_string(),
+            }
+        }
+        _ => match line.split_once(':') {
+            Some((label, text)) if labels.iter().any(|l| l == label.trim()) => {
+                ControlRequest::Send {
+                    label: label.trim().to_string(),
+                    text: text.trim_start().to_string(),
+                }
+            }
+            _ => ControlRequest::Broadcast {
+                text: line.to_string(),
+            },
+        },
+    }
+}
+
+/// Applies `line` to `commands`, fails with a message for the user
+fn apply(line: &str, commands: &dyn ControlledCommands) -> Result<(), String> {
+    let labels: Vec<_> = commands.commands().into_iter().map(|(l, _)| l).collect();
+    // lines never ask to stop runcc
+    let response = respond(parse_line(line, &labels), commands, &|| {});
+    match response.error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// Applies each line of stdin to `commands` until runcc exits or stdin is closed.
+/// Their stdin must be piped, see [`RunConfig::pipe_stdin`](crate::RunConfig::pipe_stdin).
+pub fn control_from_stdin(commands: Arc<dyn ControlledCommands>) {
+    // blocks on stdin until runcc exits
+    std::thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            if let Err(err) = apply(&line, &*commands) {
+                eprintln!("[runcc][warning] {}", err);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    use super::{apply, parse_line, ControlRequest};
+    use crate::{RunConfig, RunConfigInput};
+
+    #[test]
+    fn parse_input_lines() {
+        let labels = ["web".to_string(), "npm start".to_string()];
+        let parse = |line| parse_line(line, &labels);
+        let send = |label: &str, text: &str| ControlRequest::Send {
+            label: label.to_string(),
+            text: text.to_string(),
+        };
+
+        assert_eq!(
+            parse("restart web"),
+            ControlRequest::Restart {
+                label: "web".to_string()
+            }
+        );
+        assert_eq!(
+            parse("  kill npm start "),
+            ControlRequest::Kill {
+                label: "npm start".to_string()
+            }
+        );
+        assert_eq!(parse("send web y"), send("web", "y"));
+        assert_eq!(parse("send npm start rs"), send("npm start", "rs"));
+        assert_eq!(parse("send db"), send("db", ""));
+        assert_eq!(parse("web: y"), send("web", "y"));
+
+        // anything else goes to all commands, also lines starting with an unknown label
+        for line in ["y", "", "restart", "db: y", "note: web", "http://localhost"] {
+            assert_eq!(
+                parse(line),
+                ControlRequest::Broadcast {
+                    text: line.to_string()
+                }
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn apply_input_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        // each command writes the lines it reads to a file named like it
+        let input: RunConfigInput = serde_yaml::from_str(&format!(
+            r#"
+commands:
+  web:
+    program: sh
+    args: [-c, 'while read line; do echo "$line" >> web; done']
+    cwd: {dir}
+  api:
+    program: sh
+    args: [-c, 'while read line; do echo "$line" >> api; done']
+    cwd: {dir}
+pipe_stdin: true
+"#,
+            dir = dir.path().display()
+        ))
+        .unwrap();
+        let config: RunConfig = input.into();
+        let plugin = super::super::CommandSystemLogPlugin::new();
+        let mut system = crate::run::spawn_from_run_config_with_plugin(config.clone(), plugin);
+        let commands = Mutex::new(system.reloader(config));
+
+        assert_eq!(apply("web: hello", &commands), Ok(()));
+        assert_eq!(apply("send api hi", &commands), Ok(()));
+        assert_eq!(apply("everyone", &commands), Ok(()));
+        // an unknown label isn't a label, the line goes to all commands
+        assert_eq!(apply("db: hello", &commands), Ok(()));
+        assert_eq!(
+            apply("send db hello", &commands),
+            Err("no command[db]".to_string())
+        );
+        assert_eq!(
+            apply("kill db", &commands),
+            Err("no command[db]".to_string())
+        );
+
+        let read =
+            |label: &str| std::fs::read_to_string(dir.path().join(label)).unwrap_or_default();
+        let started = Instant::now();
+        while read("web").lines().count() < 3 || read("api").lines().count() < 3 {
+            assert!(started.elapsed() < Duration::from_secs(3), "lines not read");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(read("web"), "hello\neveryone\ndb: hello\n");
+        assert_eq!(read("api"), "hi\neveryone\ndb: hello\n");
+
+        assert_eq!(apply("kill web", &commands), Ok(()));
+        assert_eq!(apply("kill api", &commands), Ok(()));
+        system.wait().await.unwrap();
+        assert_eq!(
+            apply("anyone", &commands),
+            Err("no running command reads input".to_string())
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use serde::Serialize;
+use std::process::ExitStatus;
+use std::time::SystemTime;
+
+/// How runcc prints the output of commands
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OutputFormat {
+    /// Lines prefixed with the label of their command
+    #[default]
+    Text,
+    /// One JSON object per line for each event, see [`JsonEvent`]
+    Json,
+    /// Like [`Text`](Self::Text) but on stderr, and a report in the Test Anything Protocol
+    /// on stdout once all commands stopped, see [`tap_report`](super::tap_report)
+    Tap,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "tap" => Ok(Self::Tap),
+            _ => Err(format!(
+                "unknown format {:?}, expected text, json or tap",
+                s
+            )),
+        }
+    }
+}
+
+/// An event printed with [`OutputFormat::Json`].
+///
+/// Lines are decoded lossily, JSON escapes the control characters they contain.
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JsonEvent<'a> {
+    Started {
+        label: &'a str,
+        pid: Option<u32>,
+        /// See [`CommandConfig::pane`](crate::CommandConfig::pane)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pane: Option<&'a str>,
+    },
+    Line {
+        label: &'a str,
+        stream: &'static str,
+        line: &'a str,
+    },
+    Exited {
+        label: &'a str,
+        code: Option<i32>,
+        /// The signal which terminated the command, only on Unix
+        signal: Option<i32>,
+        /// Why the command has no exit status, e.g. it failed to spawn
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+#[derive(Serialize)]
+struct TimedEvent<'a> {
+    #[serde(flatten)]
+    event: JsonEvent<'a>,
+    ts: String,
+}
+
+impl JsonEvent<'_> {
+    pub fn exited<'a>(label: &'a str, status: &std::io::Result<ExitStatus>) -> JsonEvent<'a> {
+        match status {
+            Ok(status) => JsonEvent::Exited {
+                label,
+                code: status.code(),
+                signal: signal(status),
+                error: None,
+            },
+            Err(err) => JsonEvent::Exited {
+                label,
+                code: None,
+                signal: None,
+                error: Some(err.to_string()),
+            },
+        }
+    }
+
+    /// The event as a line of JSON without newline, stamped with the current time
+    pub fn into_line(self) -> String {
+        let event = TimedEvent {
+            event: self,
+            ts: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+        };
+        serde_json::to_string(&event).expect("events should serialize")
+    }
+}
+
+#[cfg(unix)]
+pub(super) fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+pub(super) fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JsonEvent, OutputFormat};
+
+    #[test]
+    fn escape_lines() {
+        let line = String::from_utf8_lossy(b"\x1b[31mred\x1b[0m\t\x00 \xff\xfe \"quoted\"");
+        let json = JsonEvent::Line {
+            label: "web",
+            stream: "stdout",
+            line: &line,
+        }
+        .into_line();
+
+        assert!(!json.contains(['\n', '\x1b', '\0']));
+        let event: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(event["event"], "line");
+        assert_eq!(event["label"], "web");
+        assert_eq!(event["stream"], "stdout");
+        assert_eq!(
+            event["line"],
+            "\x1b[31mred\x1b[0m\t\0 \u{fffd}\u{fffd} \"quoted\""
+        );
+        assert!(event["ts"].is_string());
+    }
+
+    #[test]
+    fn parse_output_format() {
+        assert_eq!("json".parse(), Ok(OutputFormat::Json));
+        assert_eq!("tap".parse(), Ok(OutputFormat::Tap));
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+}
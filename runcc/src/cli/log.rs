@@ -1,100 +1,1426 @@
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use tokio::io::AsyncBufReadExt;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncRead};
+use tokio::sync::oneshot;
 use tokio::{io::BufReader, task::JoinHandle};
 
-use crate::run::{kill, CommandStopped, CommandSystemPlugin, LabeledCommandData};
+use super::output::SharedSink;
+#[cfg(feature = "tmux")]
+use super::TmuxWindow;
+use super::{
+    tail_of, CiFormat, ColorChoice, EventsLog, GithubAnnotations, JsonEvent, LogFile, LogFiles,
+    Metrics, Output, OutputFormat, OutputGroups, ShutdownProgress, StatusBoard, Stream,
+    SyscallTracing, Trace,
+};
+use crate::run::{
+    kill, kill_reason, CommandOutcome, CommandStopped, CommandSystemPlugin, LabeledCommandData,
+    OutputBroadcast, OutputStream, Progress, ReadySignal,
+};
+use crate::{
+    label::Label, LineFilter, LineLabel, LogLevelColors, OutputBackpressure, OutputEncoding,
+    OutputTimestamps, RestartSeparator, StreamRoute,
+};
+use tokio::process::Command;
 
-pub struct CommandSystemLogPlugin(Mutex<Vec<JoinHandle<()>>>);
+pub struct CommandSystemLogPlugin {
+    joins: Mutex<Vec<JoinHandle<()>>>,
+    output: Arc<Output>,
+    /// Where [`output`](Self::output) writes instead of stdout and stderr
+    output_sink: Option<SharedSink>,
+    output_broadcast: Option<Arc<OutputBroadcast>>,
+    /// How many of the last lines in `output_broadcast` are printed when a command failed
+    tail_lines: usize,
+    defer_output_until_started: bool,
+    output_backpressure: OutputBackpressure,
+    max_buffer_memory: Option<usize>,
+    collapse_repeats: bool,
+    interleave_guard: bool,
+    /// see [`group_output`](Self::group_output)
+    groups: Option<Arc<OutputGroups>>,
+    partial_line_flush: Option<Duration>,
+    ci_format: Option<CiFormat>,
+    format: OutputFormat,
+    /// Sent once the started event of the running commands was printed, by label,
+    /// so that it comes before their output with [`OutputFormat::Json`]
+    started: Mutex<HashMap<String, oneshot::Sender<()>>>,
+    timestamps: Option<OutputTimestamps>,
+    /// origin of [`OutputTimestamps::Relative`]
+    started_at: Instant,
+    show_cwd: bool,
+    show_silent_commands: bool,
+    restart_separator: RestartSeparator,
+    color: ColorChoice,
+    colored_labels: bool,
+    color_seed: Option<u64>,
+    level_colors: Option<Arc<LogLevelColors>>,
+    prefix_renderer: Option<Arc<dyn PrefixRenderer>>,
+    /// Whether the running commands printed any output, by label,
+    /// sent once their output was read to the end
+    printed_output: Mutex<HashMap<String, oneshot::Receiver<bool>>>,
+    masked: Arc<[String]>,
+    trace: Option<Arc<Trace>>,
+    metrics: Option<Arc<Metrics>>,
+    log_files: Option<Arc<LogFiles>>,
+    github: Option<Arc<GithubAnnotations>>,
+    status: Option<Arc<StatusBoard>>,
+    events: Option<Arc<EventsLog>>,
+    syscall_tracing: Option<SyscallTracing>,
+    shutdown_progress: Arc<ShutdownProgress>,
+    #[cfg(feature = "tmux")]
+    tmux: Option<Arc<TmuxWindow>>,
+}
 
 impl CommandSystemLogPlugin {
     pub fn new() -> Self {
-        Self(Default::default())
+        Self {
+            joins: Default::default(),
+            output: Arc::new(Output::new(false, Default::default())),
+            output_sink: None,
+            output_broadcast: None,
+            tail_lines: 0,
+            defer_output_until_started: false,
+            output_backpressure: Default::default(),
+            max_buffer_memory: None,
+            collapse_repeats: false,
+            interleave_guard: false,
+            groups: None,
+            partial_line_flush: None,
+            ci_format: None,
+            format: OutputFormat::Text,
+            started: Default::default(),
+            timestamps: None,
+            started_at: Instant::now(),
+            show_cwd: false,
+            show_silent_commands: true,
+            restart_separator: Default::default(),
+            color: ColorChoice::Auto,
+            colored_labels: false,
+            color_seed: None,
+            level_colors: None,
+            prefix_renderer: None,
+            printed_output: Default::default(),
+            masked: Arc::new([]),
+            trace: None,
+            metrics: None,
+            log_files: None,
+            github: None,
+            status: None,
+            events: None,
+            syscall_tracing: None,
+            shutdown_progress: Default::default(),
+            #[cfg(feature = "tmux")]
+            tmux: None,
+        }
+    }
+
+    /// Run commands in panes of `tmux` instead of printing their output
+    #[cfg(feature = "tmux")]
+    pub fn tmux(mut self, tmux: Option<Arc<TmuxWindow>>) -> Self {
+        self.tmux = tmux;
+        self
+    }
+
+    pub fn shutdown_progress(&self) -> Arc<ShutdownProgress> {
+        self.shutdown_progress.clone()
+    }
+
+    /// Where output of commands is written, [`Output::flush`] it before exiting
+    pub fn output(&self) -> Arc<Output> {
+        self.output.clone()
+    }
+
+    /// Record spawn, ready and exit times of commands to `trace`
+    pub fn trace(mut self, trace: Option<Arc<Trace>>) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Record restarts, output lines and times to ready of commands to `metrics`
+    pub fn metrics(mut self, metrics: Option<Arc<Metrics>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Write output lines of commands to their files in `log_files` as well
+    pub fn log_files(mut self, log_files: Option<Arc<LogFiles>>) -> Self {
+        self.log_files = log_files;
+        self
+    }
+
+    /// Record last output lines and exit statuses of commands to `github`
+    pub fn github(mut self, github: Option<Arc<GithubAnnotations>>) -> Self {
+        self.github = github;
+        self
+    }
+
+    /// Record the state of commands to `status`
+    pub fn status(mut self, status: Option<Arc<StatusBoard>>) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Write lifecycle events of commands to `events`
+    pub fn events(mut self, events: Option<Arc<EventsLog>>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Run commands under a syscall tracer
+    pub fn syscall_tracing(mut self, syscall_tracing: Option<SyscallTracing>) -> Self {
+        self.syscall_tracing = syscall_tracing;
+        self
+    }
+
+    /// Print `***` instead of these values, e.g. secrets, in output of commands
+    pub fn mask(mut self, values: Vec<String>) -> Self {
+        self.masked = values.into_iter().filter(|v| !v.is_empty()).collect();
+        self
+    }
+
+    fn new_output(&self) -> Arc<Output> {
+        let output = match self.output_sink.clone() {
+            Some(sink) => Output::with_sink(
+                self.defer_output_until_started,
+                self.output_backpressure,
+                Box::new(move |stream, bytes| sink(stream, bytes)),
+            ),
+            None => Output::new(self.defer_output_until_started, self.output_backpressure),
+        };
+        Arc::new(output.max_buffer_memory(self.max_buffer_memory))
+    }
+
+    /// Write the output to `sink` instead of stdout and stderr, e.g. to show it in a terminal UI
+    #[cfg(all(unix, feature = "tui"))]
+    pub fn output_sink(mut self, sink: Option<SharedSink>) -> Self {
+        self.output_sink = sink;
+        self.output = self.new_output();
+        self
+    }
+
+    /// Publish output lines of commands to `broadcast` as well, without label,
+    /// for [`CommandSystem::subscribe`](crate::run::CommandSystem::subscribe) and
+    /// [`CommandSystem::tail`](crate::run::CommandSystem::tail)
+    pub fn output_broadcast(mut self, broadcast: Option<Arc<OutputBroadcast>>) -> Self {
+        self.output_broadcast = broadcast;
+        self
+    }
+
+    /// Print the last `lines` output lines the [`output_broadcast`](Self::output_broadcast)
+    /// kept of a command when it failed, after its output, see `--tail`.
+    /// Not with [`group_output`](Self::group_output), where the output is printed as a whole.
+    pub fn tail_lines(mut self, lines: usize) -> Self {
+        self.tail_lines = lines;
+        self
+    }
+
+    /// Hold back output until all commands without prerequisites were spawned
+    pub fn defer_output_until_started(mut self, defer: bool) -> Self {
+        self.defer_output_until_started = defer;
+        self.output = self.new_output();
+        self
+    }
+
+    /// What happens to output of commands while the terminal doesn't read it
+    pub fn output_backpressure(mut self, backpressure: OutputBackpressure) -> Self {
+        self.output_backpressure = backpressure;
+        self.output = self.new_output();
+        self
+    }
+
+    /// Limit how many bytes of command output are buffered, see [`Output::max_buffer_memory`]
+    pub fn max_buffer_memory(mut self, max_buffer_memory: Option<usize>) -> Self {
+        self.max_buffer_memory = max_buffer_memory;
+        self.output = self.new_output();
+        self
+    }
+
+    /// Print consecutive identical lines only once, followed by how many times they were repeated
+    pub fn collapse_repeats(mut self, collapse_repeats: bool) -> Self {
+        self.collapse_repeats = collapse_repeats;
+        self
+    }
+
+    /// Write indented lines, like frames of a stack trace, together with the line before them,
+    /// so that output of other commands doesn't interleave them
+    pub fn interleave_guard(mut self, interleave_guard: bool) -> Self {
+        self.interleave_guard = interleave_guard;
+        self
+    }
+
+    /// Print the output of each command run as one block once it exited, in the order of the
+    /// commands, see [`RunConfig::group_output`](crate::RunConfig::group_output)
+    pub fn group_output(mut self, group_output: bool) -> Self {
+        self.groups = group_output.then(|| Arc::new(OutputGroups::default()));
+        self
+    }
+
+    /// Print the start of a line once a command printed nothing more for this long,
+    /// so that prompts without a newline are visible
+    pub fn partial_line_flush(mut self, partial_line_flush: Option<Duration>) -> Self {
+        self.partial_line_flush = partial_line_flush;
+        self
+    }
+
+    /// Print the output of each command run at once, in a collapsible section of the CI log,
+    /// once it was read to the end
+    pub fn ci_format(mut self, ci_format: Option<CiFormat>) -> Self {
+        self.ci_format = ci_format;
+        self
+    }
+
+    /// With [`OutputFormat::Json`], the options for labels, timestamps, grouping
+    /// and collapsing lines don't apply
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Print a time in front of each output line of commands
+    pub fn timestamps(mut self, timestamps: Option<OutputTimestamps>) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Add the working directory of commands to the label of their output lines
+    pub fn show_cwd(mut self, show_cwd: bool) -> Self {
+        self.show_cwd = show_cwd;
+        self
+    }
+
+    /// Whether to print the exit line of commands which succeeded without any output
+    pub fn show_silent_commands(mut self, show_silent_commands: bool) -> Self {
+        self.show_silent_commands = show_silent_commands;
+        self
+    }
+
+    /// The line printed before a command runs again because its watched files changed
+    pub fn restart_separator(mut self, restart_separator: RestartSeparator) -> Self {
+        self.restart_separator = restart_separator;
+        self
+    }
+
+    /// When labels, lines and exit messages are colored, on streams which are a terminal
+    /// and unless `NO_COLOR` is set by default
+    pub fn color(mut self, color: ColorChoice) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Print the label of each command in a color of its own, its
+    /// [`CommandConfig::color`](crate::CommandConfig::color) or one picked by its index
+    pub fn colored_labels(mut self, colored_labels: bool) -> Self {
+        self.colored_labels = colored_labels;
+        self
+    }
+
+    /// The time printed in front of the lines that `cmd` exited
+    fn exited_at(
+        &self,
+        cmd: &CommandStopped<LabeledCommandData, LabeledCommandData>,
+    ) -> Option<String> {
+        let mode = self.timestamps?;
+        let now = Instant::now();
+        let spawned_at = cmd
+            .ran_for
+            .and_then(|ran_for| now.checked_sub(ran_for))
+            .unwrap_or(now);
+        Some(LineTimestamps::new(mode, self.started_at, spawned_at).at(now))
+    }
+
+    /// Pick label colors from a hash of the label and `seed` instead of the index of commands
+    pub fn color_seed(mut self, seed: Option<u64>) -> Self {
+        self.color_seed = seed;
+        self
+    }
+
+    /// Color output lines by the log level they contain
+    pub fn level_colors(mut self, colors: Option<LogLevelColors>) -> Self {
+        self.level_colors = colors.map(Arc::new);
+        self
+    }
+
+    /// Render the prefix of lines with `renderer` instead of `[label]`
+    pub fn prefix_renderer(mut self, renderer: Option<Arc<dyn PrefixRenderer>>) -> Self {
+        self.prefix_renderer = renderer;
+        self
+    }
+
+    /// The prefix of lines about `data` written to `stream`
+    fn prefix(&self, data: &LabeledCommandData, label: &str, stream: Stream) -> String {
+        match &self.prefix_renderer {
+            Some(renderer) => renderer.render_prefix(data, stream),
+            None => {
+                let color = self.label_color(data).filter(|_| self.color.colors(stream));
+                label_prefix(label, color)
+            }
+        }
+    }
+
+    fn label_color(&self, data: &LabeledCommandData) -> Option<&'static str> {
+        let pick = match self.color_seed {
+            Some(seed) => seeded_hash(seed, data.label.label()) as usize,
+            None => data.index,
+        };
+        self.colored_labels.then(|| match data.label.color() {
+            Some(color) => color.ansi(),
+            None => LABEL_COLORS[pick % LABEL_COLORS.len()],
+        })
+    }
+}
+
+/// FNV-1a of `seed` and `label`, which unlike the hasher of std is the same for every build
+fn seeded_hash(seed: u64, label: &str) -> u64 {
+    seed.to_le_bytes()
+        .iter()
+        .chain(label.as_bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// ANSI colors of labels, repeated if there are more commands
+const LABEL_COLORS: [&str; 10] = ["36", "35", "33", "34", "32", "96", "95", "93", "94", "92"];
+
+/// Width of progress bars between their brackets
+const PROGRESS_BAR_WIDTH: usize = 20;
+
+/// `[#####---------------]  25%`
+fn progress_bar(percent: u8) -> String {
+    let done = usize::from(percent) * PROGRESS_BAR_WIDTH / 100;
+    format!(
+        "[{}{}] {:>3}%",
+        "#".repeat(done),
+        "-".repeat(PROGRESS_BAR_WIDTH - done),
+        percent
+    )
+}
+
+/// Renders the prefix in front of the lines of commands instead of `[label]`,
+/// see [`run_with_prefix_renderer`](super::run_with_prefix_renderer)
+pub trait PrefixRenderer: Send + Sync {
+    /// The prefix of lines of the command of `data` written to `stream` of runcc,
+    /// which is followed by a space
+    fn render_prefix(&self, data: &LabeledCommandData, stream: Stream) -> String;
+}
+
+/// `[label]` in front of lines, in `color` if it is given
+fn label_prefix(label: &str, color: Option<&str>) -> String {
+    match color {
+        Some(color) => format!("\x1b[{}m[{}]\x1b[0m", color, label),
+        None => format!("[{}]", label),
+    }
+}
+
+/// Counts consecutive identical lines
+#[derive(Default)]
+struct Repeats {
+    last: Option<String>,
+    count: usize,
+}
+
+impl Repeats {
+    /// Returns whether `line` should be printed, and how many times the previous line
+    /// was repeated if `line` ends a run of repeats.
+    fn push(&mut self, line: &str) -> (bool, Option<usize>) {
+        if self.last.as_deref() == Some(line) {
+            self.count += 1;
+            (false, None)
+        } else {
+            self.last = Some(line.to_string());
+            (true, self.finish())
+        }
+    }
+
+    /// Returns how many times the last line was repeated, if it was.
+    fn finish(&mut self) -> Option<usize> {
+        let count = std::mem::take(&mut self.count);
+        if count > 0 {
+            Some(count)
+        } else {
+            None
+        }
+    }
+}
+
+/// Prints times in front of output lines of a command
+#[derive(Debug, Clone, Copy)]
+struct LineTimestamps {
+    mode: OutputTimestamps,
+    /// origin of relative times
+    since: Instant,
+}
+
+impl LineTimestamps {
+    fn new(mode: OutputTimestamps, run_started_at: Instant, spawned_at: Instant) -> Self {
+        let since = match mode {
+            OutputTimestamps::PerCommandRelative => spawned_at,
+            OutputTimestamps::Absolute | OutputTimestamps::Relative => run_started_at,
+        };
+        Self { mode, since }
+    }
+
+    /// The time printed in front of a line printed at `now`
+    fn at(&self, now: Instant) -> String {
+        match self.mode {
+            OutputTimestamps::Absolute => {
+                humantime::format_rfc3339_millis(SystemTime::now()).to_string()
+            }
+            OutputTimestamps::Relative | OutputTimestamps::PerCommandRelative => {
+                let elapsed = now.saturating_duration_since(self.since);
+                format!("{:8.3}s", elapsed.as_secs_f64())
+            }
+        }
+    }
+}
+
+/// Paths longer than this are shortened in labels
+const MAX_CWD_LENGTH: usize = 24;
+
+/// `label` followed by `cwd`, shortened to its last [`MAX_CWD_LENGTH`] chars
+fn label_with_cwd(label: &str, cwd: &str) -> String {
+    let len = cwd.chars().count();
+    if len > MAX_CWD_LENGTH {
+        let tail: String = cwd.chars().skip(len - MAX_CWD_LENGTH + 1).collect();
+        format!("{} …{}", label, tail)
+    } else {
+        format!("{} {}", label, cwd)
+    }
+}
+
+fn mask(line: String, masked: &[String]) -> String {
+    masked
+        .iter()
+        .fold(line, |line, value| line.replace(value.as_str(), "***"))
+}
+
+fn mask_bytes(line: &[u8], masked: &[String]) -> Vec<u8> {
+    let mut line = line.to_vec();
+    for value in masked {
+        let value = value.as_bytes();
+        let mut masked_line = Vec::with_capacity(line.len());
+        let mut i = 0;
+        while i < line.len() {
+            if line[i..].starts_with(value) {
+                masked_line.extend_from_slice(b"***");
+                i += value.len();
+            } else {
+                masked_line.push(line[i]);
+                i += 1;
+            }
+        }
+        line = masked_line;
+    }
+    line
+}
+
+/// How lines of both output streams of a command are logged
+struct LogOptions<'a> {
+    label: &'a str,
+    ready: Option<&'a ReadySignal>,
+    masked: &'a [String],
+    collapse_repeats: bool,
+    binary_safe: bool,
+    encoding: OutputEncoding,
+    /// Write indented lines following a line together with it, see [`Block`]
+    interleave_guard: bool,
+    /// Print the start of a line once nothing more was read for this long
+    partial_line_flush: Option<Duration>,
+    timestamps: Option<LineTimestamps>,
+    /// Whether lines written to a stream are colored
+    color: ColorChoice,
+    /// Color of the label on colored streams
+    label_color: Option<&'a str>,
+    label_from: Option<&'a LineLabel>,
+    /// Tracks progress in lines, shown as a bar on streams which are a terminal
+    progress: Option<&'a Progress>,
+    /// Drops and rewrites lines after they were checked for readiness and progress
+    line_filter: Option<&'a LineFilter>,
+    /// Counts the lines of both streams
+    line_count: Option<&'a AtomicUsize>,
+    /// Where lines of both streams are written without label
+    log_file: Option<&'a LogFile>,
+    /// Where lines of both streams are published without label, under the label of the command
+    broadcast: Option<(&'a OutputBroadcast, &'a str)>,
+    /// Its [`Label::relabeled`] replaces `label` once it is set
+    relabel: Option<&'a Label>,
+    /// Colors of lines by their log level on colored streams
+    level_colors: Option<&'a LogLevelColors>,
+    /// Renders the prefix of lines of the command instead of `label`
+    prefix_renderer: Option<(&'a dyn PrefixRenderer, &'a LabeledCommandData)>,
+}
+
+impl LogOptions<'_> {
+    /// Writes a masked line read from `stream` to the log file and publishes it
+    fn record_line(&self, stream: Stream, line: &[u8]) {
+        if let Some(log_file) = self.log_file {
+            log_file.write_line(line);
+        }
+        if let Some((broadcast, label)) = self.broadcast {
+            let stream = match stream {
+                Stream::Stdout => OutputStream::Stdout,
+                Stream::Stderr => OutputStream::Stderr,
+            };
+            broadcast.publish(label, stream, &String::from_utf8_lossy(line));
+        }
+    }
+
+    fn prefix(&self, stream: Stream) -> String {
+        if let Some((renderer, data)) = self.prefix_renderer {
+            return renderer.render_prefix(data, stream);
+        }
+        let color = self.label_color.filter(|_| self.color.colors(stream));
+        match self.relabel.and_then(Label::relabeled) {
+            Some(label) => label_prefix(&label, color),
+            None => label_prefix(self.label, color),
+        }
+    }
+
+    /// `line` in the color of its log level on a colored stream
+    fn level_colored<'l>(&self, line: &'l str, stream: Stream) -> std::borrow::Cow<'l, str> {
+        let color = self
+            .level_colors
+            .filter(|_| self.color.colors(stream))
+            .and_then(|colors| colors.color_of(line));
+        match color {
+            Some(color) => format!("\x1b[{}m{}\x1b[0m", color.ansi(), line).into(),
+            None => line.into(),
+        }
+    }
+
+    /// The progress bar shown after the label if `line` reports progress on a terminal,
+    /// see [`CommandConfig::progress_from`](crate::CommandConfig::progress_from)
+    fn progress_of(&self, line: &str, stream: Stream) -> Option<String> {
+        let percent = self.progress?.check_line(line)?;
+        stream.is_terminal().then(|| progress_bar(percent))
+    }
+
+    /// `line` as it is logged, `None` if the [`LineFilter`] drops it
+    fn filtered(&self, line: String) -> Option<String> {
+        match self.line_filter {
+            Some(filter) => filter.apply(&line).map(std::borrow::Cow::into_owned),
+            None => Some(line),
+        }
+    }
+
+    /// Like [`filtered`](Self::filtered) for `text` decoded from the bytes of `line`.
+    /// Returns `false` if the line is dropped. Both are only replaced if the filter
+    /// rewrites the line, so that bytes which weren't decoded stay intact otherwise.
+    fn filter_line(&self, text: &mut String, line: &mut Vec<u8>) -> bool {
+        let filtered = match self.line_filter {
+            Some(filter) => filter.apply(text).map(|line| match line {
+                std::borrow::Cow::Borrowed(_) => None,
+                std::borrow::Cow::Owned(rewritten) => Some(rewritten),
+            }),
+            None => return true,
+        };
+        match filtered {
+            None => false,
+            Some(None) => true,
+            Some(Some(rewritten)) => {
+                *line = rewritten.clone().into_bytes();
+                *text = rewritten;
+                true
+            }
+        }
+    }
+
+    /// `[value]` of `line` shown after the label,
+    /// see [`CommandConfig::label_from`](crate::CommandConfig::label_from)
+    fn value_of(&self, line: &str) -> String {
+        match self
+            .label_from
+            .and_then(|label_from| label_from.extract(line))
+        {
+            Some(value) => format!("[{}]", mask(value.into_owned(), self.masked)),
+            None => String::new(),
+        }
+    }
+}
+
+/// Writes each line of `reader` prefixed with the label without decoding it,
+/// so that non UTF-8 output passes through intact.
+///
+/// A newline is added to the last line if it has none.
+/// Sets `printed` once `reader` had any output.
+async fn copy_lines<R: AsyncRead + Unpin>(
+    reader: R,
+    stream: Stream,
+    options: &LogOptions<'_>,
+    last_line: Option<&Mutex<Option<String>>>,
+    output: &Output,
+    printed: &mut bool,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(());
+        }
+        *printed = true;
+        if let Some(line_count) = options.line_count {
+            line_count.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let text = String::from_utf8_lossy(&line);
+        let mut text = text.trim_end_matches(['\n', '\r']).to_string();
+        if let Some(ready) = options.ready {
+            ready.check_line(&text);
+        }
+        if !options.filter_line(&mut text, &mut line) {
+            continue;
+        }
+        if let Some(last_line) = last_line {
+            if !text.trim().is_empty() {
+                *last_line.lock().unwrap() = Some(mask(text.clone(), options.masked));
+            }
+        }
+
+        let masked_line = mask_bytes(&line, options.masked);
+        options.record_line(stream, &masked_line);
+
+        let value = options.value_of(&text);
+        let prefix = options.prefix(stream);
+        let mut out = match options.timestamps {
+            Some(timestamps) => format!("{} {}{} ", timestamps.at(Instant::now()), prefix, value),
+            None => format!("{}{} ", prefix, value),
+        }
+        .into_bytes();
+        out.extend(masked_line);
+        if !out.ends_with(b"\n") {
+            out.push(b'\n');
+        }
+        output.write_command_output(stream, out).await;
+    }
+}
+
+/// Writes each line of `reader`, see [`read_line`], as a [`JsonEvent::Line`] to stdout.
+/// Lines which can't be decoded are converted lossily.
+///
+/// Returns whether `reader` had any output.
+async fn json_lines<R: AsyncRead + Unpin>(
+    reader: R,
+    stream: Stream,
+    options: &LogOptions<'_>,
+    last_line: Option<&Mutex<Option<String>>>,
+    output: &Output,
+) -> bool {
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    let mut after_cr = false;
+    let mut printed = false;
+
+    loop {
+        buf.clear();
+        match read_line(&mut reader, &mut buf, &mut after_cr).await {
+            Ok(false) => break,
+            Ok(true) => {}
+            Err(err) => {
+                eprintln!(
+                    "[runcc error] failed to read line from [{}] {}: {}",
+                    options.label,
+                    stream.name(),
+                    err
+                );
+                break;
+            }
+        }
+        printed = true;
+        if let Some(line_count) = options.line_count {
+            line_count.fetch_add(1, Ordering::Relaxed);
+        }
+        if buf.ends_with(b"\n") || buf.ends_with(b"\r") {
+            buf.pop();
+        }
+
+        let mut line = match options.binary_safe {
+            true => String::from_utf8_lossy(&buf).into_owned(),
+            false => options
+                .encoding
+                .decode(buf.clone())
+                .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned()),
+        };
+        if let Some(ready) = options.ready {
+            ready.check_line(&line);
+        }
+        if !options.filter_line(&mut line, &mut buf) {
+            continue;
+        }
+
+        let masked_line = mask_bytes(&buf, options.masked);
+        options.record_line(stream, &masked_line);
+
+        let line = mask(line, options.masked);
+        if let Some(last_line) = last_line {
+            if !line.trim().is_empty() {
+                *last_line.lock().unwrap() = Some(line.clone());
+            }
+        }
+
+        let event = JsonEvent::Line {
+            label: options.label,
+            stream: stream.name(),
+            line: &line,
+        };
+        output
+            .write_command_line(Stream::Stdout, &event.into_line())
+            .await;
+    }
+
+    printed
+}
+
+/// Reads a line ended by `\n` or `\r` into `buf`, so that each update of a progress bar
+/// is a line of its own. Returns `false` at the end of `reader` if nothing was read.
+///
+/// `after_cr` is set when a line ended with `\r`, so that the `\n` of a `\r\n` which
+/// follows is skipped instead of ending an empty line. Empty lines ended by `\r` are skipped.
+async fn read_line<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    buf: &mut Vec<u8>,
+    after_cr: &mut bool,
+) -> io::Result<bool> {
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            return Ok(!buf.is_empty());
+        }
+
+        let start = match std::mem::take(after_cr) {
+            true if available[0] == b'\n' => 1,
+            _ => 0,
+        };
+        match available[start..]
+            .iter()
+            .position(|&b| b == b'\n' || b == b'\r')
+        {
+            Some(i) => {
+                let end = start + i;
+                buf.extend_from_slice(&available[start..=end]);
+                reader.consume(end + 1);
+                if buf.ends_with(b"\r") {
+                    *after_cr = true;
+                    if buf.len() == 1 {
+                        buf.clear();
+                        continue;
+                    }
+                }
+                return Ok(true);
+            }
+            None => {
+                let len = available.len();
+                buf.extend_from_slice(&available[start..]);
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// Reads a line without the line ending and decodes it from `encoding`, see [`read_line`].
+///
+/// A partially read line is kept in `buf`, so that reading can be cancelled and resumed.
+async fn next_line<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    buf: &mut Vec<u8>,
+    after_cr: &mut bool,
+    encoding: OutputEncoding,
+) -> io::Result<Option<String>> {
+    if !read_line(reader, buf, after_cr).await? {
+        return Ok(None);
+    }
+    let mut line = std::mem::take(buf);
+    if line.ends_with(b"\n") || line.ends_with(b"\r") {
+        line.pop();
+    }
+    encoding
+        .decode(line)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Takes the start of a partially read line from `buf` which can be decoded,
+/// an incomplete UTF-8 sequence at its end is left in `buf`
+fn take_partial_line(buf: &mut Vec<u8>, encoding: OutputEncoding) -> Option<String> {
+    let len = match encoding {
+        OutputEncoding::Utf8 => match std::str::from_utf8(buf) {
+            Ok(_) => buf.len(),
+            Err(err) => err.valid_up_to(),
+        },
+        OutputEncoding::Latin1 | OutputEncoding::Windows1252 => buf.len(),
+    };
+    if len == 0 {
+        return None;
+    }
+    let rest = buf.split_off(len);
+    encoding.decode(std::mem::replace(buf, rest)).ok()
+}
+
+/// A block is written once its command printed nothing for this long
+const BLOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Longer blocks are split, so that a command printing only indented lines isn't held back
+const MAX_BLOCK_LINES: usize = 1000;
+
+/// Lines of a stack trace or similar block which are written at once,
+/// so that they aren't interleaved with output of other commands
+#[derive(Default)]
+struct Block {
+    text: String,
+    lines: usize,
+}
+
+impl Block {
+    /// Whether `line` continues the block, i.e. it is indented like frames of a stack trace
+    fn continues(&self, line: &str) -> bool {
+        self.lines > 0 && self.lines < MAX_BLOCK_LINES && line.starts_with([' ', '\t'])
+    }
+
+    fn push(&mut self, line: &str) {
+        self.text.push_str(line);
+        self.text.push('\n');
+        self.lines += 1;
+    }
+
+    async fn write(&mut self, stream: Stream, output: &Output) {
+        if self.lines > 0 {
+            self.lines = 0;
+            let text = std::mem::take(&mut self.text);
+            output.write_command_output(stream, text.into_bytes()).await;
+        }
+    }
+}
+
+/// The stream lines of `stream` are written to, the `other` one if they are merged into it.
+/// `None` if they are discarded, see [`CommandConfig::stdout`](crate::CommandConfig::stdout).
+fn routed_stream(route: StreamRoute, stream: Stream, other: Stream) -> Option<Stream> {
+    match route {
+        StreamRoute::Console | StreamRoute::File => Some(stream),
+        StreamRoute::Merge => Some(other),
+        StreamRoute::Discard => None,
+    }
+}
+
+/// Reads a stream of a command to the end without logging it, so that the command doesn't
+/// block on a full pipe, see [`CommandConfig::discard_stdout`](crate::CommandConfig::discard_stdout).
+/// Returns that nothing was printed.
+async fn discard<R: AsyncRead + Unpin>(mut reader: R) -> bool {
+    let _ = tokio::io::copy(&mut reader, &mut tokio::io::sink()).await;
+    false
+}
+
+/// Returns whether `reader` had any output
+async fn log_lines<R: AsyncRead + Unpin>(
+    reader: R,
+    stream: Stream,
+    options: &LogOptions<'_>,
+    last_line: Option<&Mutex<Option<String>>>,
+    output: &Output,
+) -> bool {
+    let LogOptions {
+        label,
+        ready,
+        masked,
+        collapse_repeats,
+        binary_safe,
+        encoding,
+        interleave_guard,
+        partial_line_flush,
+        timestamps,
+        ..
+    } = *options;
+
+    let mut printed = false;
+
+    if binary_safe {
+        let copied = copy_lines(reader, stream, options, last_line, output, &mut printed);
+        if let Err(err) = copied.await {
+            eprintln!(
+                "[runcc error] failed to read from [{}] {}: {}",
+                label,
+                stream.name(),
+                err
+            );
+        }
+        return printed;
+    }
+
+    let repeated_line = |count: usize| {
+        let times = if count == 1 { "time" } else { "times" };
+        let prefix = options.prefix(stream);
+        let line = format!("{} (last line repeated {} {})", prefix, count, times);
+        match timestamps {
+            Some(timestamps) => format!("{} {}", timestamps.at(Instant::now()), line),
+            None => line,
+        }
+    };
+
+    let mut repeats = Repeats::default();
+    let mut block = Block::default();
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    let mut after_cr = false;
+    let mut last_line_at = Instant::now();
+    // the start of the current line which was printed already
+    let mut flushed: Option<String> = None;
+    // how much of the current line was read at the last timeout
+    let mut partial_len = 0;
+    loop {
+        let block_timeout =
+            (block.lines > 0).then(|| BLOCK_TIMEOUT.saturating_sub(last_line_at.elapsed()));
+        let timeout = match (block_timeout, partial_line_flush) {
+            (Some(block_timeout), Some(flush)) => Some(block_timeout.min(flush)),
+            (block_timeout, flush) => block_timeout.or(flush),
+        };
+
+        let next = next_line(&mut reader, &mut buf, &mut after_cr, encoding);
+        let next = match timeout {
+            None => next.await,
+            Some(timeout) => match tokio::time::timeout(timeout, next).await {
+                Ok(next) => next,
+                Err(_) => {
+                    if block.lines > 0 && last_line_at.elapsed() >= BLOCK_TIMEOUT {
+                        // the block ended, the command didn't print anything since
+                        block.write(stream, output).await;
+                    }
+                    // nothing was read since the last timeout, e.g. the line is a prompt
+                    if partial_line_flush.is_some() && !buf.is_empty() && buf.len() == partial_len {
+                        if let Some(partial) = take_partial_line(&mut buf, encoding) {
+                            printed = true;
+                            block.write(stream, output).await;
+                            let text = mask(partial.clone(), masked);
+                            let prefix = options.prefix(stream);
+                            let text = match (&flushed, timestamps) {
+                                (Some(_), _) => text,
+                                (None, Some(timestamps)) => {
+                                    let at = timestamps.at(Instant::now());
+                                    format!("{} {} {}", at, prefix, text)
+                                }
+                                (None, None) => format!("{} {}", prefix, text),
+                            };
+                            output.write_command_output(stream, text.into_bytes()).await;
+                            flushed.get_or_insert_with(String::new).push_str(&partial);
+                        }
+                    }
+                    partial_len = buf.len();
+                    continue;
+                }
+            },
+        };
+        partial_len = 0;
+
+        match next {
+            Ok(Some(line)) => {
+                printed = true;
+                last_line_at = Instant::now();
+                if let Some(line_count) = options.line_count {
+                    line_count.fetch_add(1, Ordering::Relaxed);
+                }
+
+                if let Some(start) = flushed.take() {
+                    // only the rest of the line is printed, after its start
+                    let whole = start + &line;
+                    if let Some(ready) = ready {
+                        ready.check_line(&whole);
+                    }
+                    if let Some(progress) = options.progress {
+                        progress.check_line(&whole);
+                    }
+                    let whole = mask(whole, masked);
+                    options.record_line(stream, whole.as_bytes());
+                    if let Some(last_line) = last_line {
+                        if !whole.trim().is_empty() {
+                            *last_line.lock().unwrap() = Some(whole);
+                        }
+                    }
+                    let rest = format!("{}\n", mask(line, masked));
+                    output.write_command_output(stream, rest.into_bytes()).await;
+                    continue;
+                }
+
+                if let Some(ready) = ready {
+                    ready.check_line(&line);
+                }
+                let bar = options.progress_of(&line, stream);
+                let line = match options.filtered(line) {
+                    Some(line) => line,
+                    None => continue,
+                };
+
+                let line = mask(line, masked);
+                options.record_line(stream, line.as_bytes());
+
+                if let Some(last_line) = last_line {
+                    if !line.trim().is_empty() {
+                        *last_line.lock().unwrap() = Some(line.clone());
+                    }
+                }
+
+                if collapse_repeats {
+                    let (print_line, repeated) = repeats.push(&line);
+                    if let Some(count) = repeated {
+                        block.write(stream, output).await;
+                        output
+                            .write_command_line(stream, &repeated_line(count))
+                            .await;
+                    }
+                    if !print_line {
+                        continue;
+                    }
+                }
+
+                let continues_block = block.continues(&line);
+                let value = options.value_of(&line);
+                let value = match &bar {
+                    Some(bar) => format!("{} {}", value, bar),
+                    None => value,
+                };
+
+                #[cfg(feature = "auto_ansi_escape")]
+                let line = crate::ansi_escape::process_ansi_escape_line(
+                    label.len() + value.len() + 3,
+                    &line,
+                );
+
+                let line = options.level_colored(&line, stream);
+                let prefix = options.prefix(stream);
+                let line = match timestamps {
+                    Some(timestamps) => {
+                        let at = timestamps.at(Instant::now());
+                        format!("{} {}{} {}", at, prefix, value, line)
+                    }
+                    None => format!("{}{} {}", prefix, value, line),
+                };
+                if bar.is_some() {
+                    block.write(stream, output).await;
+                    output.write_command_progress(stream, label, &line).await;
+                } else if interleave_guard {
+                    if !continues_block {
+                        block.write(stream, output).await;
+                    }
+                    block.push(&line);
+                } else {
+                    output.write_command_line(stream, &line).await;
+                }
+            }
+            Ok(None) => {
+                if flushed.is_some() {
+                    output.write_command_output(stream, b"\n".to_vec()).await;
+                }
+                break;
+            }
+            Err(err) => {
+                eprintln!(
+                    "[runcc error] failed to read line from [{}] {}: {}",
+                    label,
+                    stream.name(),
+                    err
+                );
+                break;
+            }
+        }
     }
+
+    block.write(stream, output).await;
+    if let Some(count) = repeats.finish() {
+        output
+            .write_command_line(stream, &repeated_line(count))
+            .await;
+    }
+
+    printed
 }
 
 impl CommandSystemPlugin<LabeledCommandData> for CommandSystemLogPlugin {
     type CommandInitialData = LabeledCommandData;
 
+    fn rewrite_command(&self, data: &Self::CommandInitialData, command: &mut Command) {
+        if let Some(syscall_tracing) = &self.syscall_tracing {
+            syscall_tracing.wrap(data.label.label(), command);
+        }
+
+        #[cfg(feature = "tmux")]
+        if let Some(tmux) = &self.tmux {
+            tmux.wrap(command);
+        }
+    }
+
     fn initialize_command_data(
         &self,
         data: Self::CommandInitialData,
         stdout: tokio::process::ChildStdout,
         stderr: tokio::process::ChildStderr,
     ) -> LabeledCommandData {
-        let label = data.label.display().to_string();
+        let json = self.format == OutputFormat::Json;
+        let label = match &data.cwd {
+            _ if json => data.label.label().to_string(),
+            Some(cwd) if self.show_cwd => label_with_cwd(data.label.display(), cwd),
+            _ => data.label.display().to_string(),
+        };
+
+        if let Some(trace) = &self.trace {
+            trace.spawned(data.label.label(), Instant::now());
+        }
+        let line_count = self
+            .metrics
+            .as_ref()
+            .map(|metrics| metrics.spawned(data.label.label(), Instant::now()));
+        let log_file = self.log_files.as_ref().and_then(|log_files| {
+            log_files.command_spawned(data.label.label(), data.log_file.as_deref())
+        });
+        self.shutdown_progress.command_spawned();
+        if let Some(status) = &self.status {
+            status.command_spawned(data.label.label(), data.ready.clone(), Instant::now());
+        }
+        if let Some(events) = &self.events {
+            events.command_spawned(data.label.label(), data.pane.as_deref(), data.ready.clone());
+        }
+        let last_lines = self
+            .github
+            .as_ref()
+            .map(|github| github.command_spawned(data.label.label()));
+
+        let collapse_repeats = self.collapse_repeats;
+        let interleave_guard = self.interleave_guard;
+        let partial_line_flush = self.partial_line_flush;
+        let timestamps = self
+            .timestamps
+            .map(|mode| LineTimestamps::new(mode, self.started_at, Instant::now()));
+        let masked = self.masked.clone();
+        let output = self.output.clone();
+        let broadcast = self
+            .output_broadcast
+            .clone()
+            .map(|broadcast| (broadcast, data.label.label().to_string()));
+        let binary_safe = data.binary_safe;
+        let (stdout_route, stderr_route) = (data.stdout, data.stderr);
+        let encoding = data.encoding;
+        let color = self.color;
+        let label_color = self.label_color(&data);
+        // stdout of runcc is left to the report
+        let stdout_stream = match self.format {
+            OutputFormat::Tap => Stream::Stderr,
+            _ => Stream::Stdout,
+        };
+        let level_colors = self.level_colors.clone();
+        let prefix_renderer = self.prefix_renderer.clone().map(|r| (r, data.clone()));
+        let label_from = data.label_from.clone();
+        let progress = data.progress.clone();
+        let line_filter = data.line_filter.clone();
+        let ready = data.ready.clone();
+        // events of `--format json` keep the label which identifies the command
+        let relabel = (!json).then(|| data.label.clone());
+        let group = self
+            .ci_format
+            .filter(|_| !json)
+            .map(|format| (format, data.label.label().to_string(), SystemTime::now()));
+        let started = json.then(|| {
+            let (started_tx, started_rx) = oneshot::channel();
+            self.started
+                .lock()
+                .unwrap()
+                .insert(data.label.label().to_string(), started_tx);
+            started_rx
+        });
+        let groups = self.groups.clone().filter(|_| !json);
+        if let Some(groups) = &groups {
+            groups.spawned(data.index);
+        }
+        let index = data.index;
+        let (printed_tx, printed_rx) = oneshot::channel();
+        // the exited event and the tail are printed once the output was read to the end
+        if !self.show_silent_commands || json || self.tail_lines > 0 {
+            self.printed_output
+                .lock()
+                .unwrap()
+                .insert(data.label.label().to_string(), printed_rx);
+        }
 
         let join = tokio::spawn(async move {
-            tokio::join!(
-                async {
-                    let stdout = BufReader::new(stdout);
-                    let mut lines = stdout.lines();
-                    loop {
-                        match lines.next_line().await {
-                            Ok(line) => {
-                                if let Some(line) = line {
-                                    #[cfg(feature = "auto_ansi_escape")]
-                                    let line = crate::ansi_escape::process_ansi_escape_line(
-                                        label.len() + 3,
-                                        &line,
-                                    );
-
-                                    let line = format!("[{}] {}", label, line);
-                                    println!("{}", line);
-                                } else {
-                                    break;
+            let options = LogOptions {
+                label: &label,
+                ready: ready.as_ref(),
+                masked: &masked,
+                collapse_repeats,
+                binary_safe,
+                encoding,
+                interleave_guard,
+                partial_line_flush,
+                timestamps,
+                color,
+                label_color,
+                label_from: label_from.as_ref(),
+                progress: progress.as_ref(),
+                line_filter: line_filter.as_ref(),
+                line_count: line_count.as_deref(),
+                log_file: log_file.as_deref(),
+                broadcast: broadcast
+                    .as_ref()
+                    .map(|(broadcast, label)| (broadcast.as_ref(), label.as_str())),
+                relabel: relabel.as_ref(),
+                level_colors: level_colors.as_deref(),
+                prefix_renderer: prefix_renderer
+                    .as_ref()
+                    .map(|(renderer, data)| (renderer.as_ref(), data)),
+            };
+            // output of a grouped run is collected and written at once, so that output of
+            // other commands doesn't end up in its section
+            let grouped = (group.is_some() || groups.is_some())
+                .then(|| Arc::new(Mutex::new(Vec::<(Stream, Vec<u8>)>::new())));
+            let command_output = match &grouped {
+                Some(grouped) => {
+                    let grouped = grouped.clone();
+                    Arc::new(Output::with_sink(
+                        false,
+                        OutputBackpressure::Buffer,
+                        Box::new(move |stream, bytes| {
+                            let mut grouped = grouped.lock().unwrap();
+                            match grouped.last_mut() {
+                                Some((last, output)) if *last == stream => {
+                                    output.extend_from_slice(bytes)
                                 }
+                                _ => grouped.push((stream, bytes.to_vec())),
                             }
-                            Err(err) => {
-                                eprintln!(
-                                    "[runcc error] failed to read line from [{}] stdout: {}",
-                                    label, err
-                                );
-                                break;
+                        }),
+                    ))
+                }
+                None => output.clone(),
+            };
+            let last_lines = last_lines.as_deref();
+            // lines of streams routed to the log file are written there and dropped then
+            let file_only = [stdout_route, stderr_route]
+                .contains(&StreamRoute::File)
+                .then(|| {
+                    Arc::new(Output::with_sink(
+                        false,
+                        OutputBackpressure::Buffer,
+                        Box::new(|_, _| {}),
+                    ))
+                });
+            let routed_output = |route, output| match (route, &file_only) {
+                (StreamRoute::File, Some(file_only)) => file_only,
+                _ => output,
+            };
+            if let Some(started) = started {
+                let _ = started.await;
+                let (stdout_printed, stderr_printed) = tokio::join!(
+                    async {
+                        match routed_stream(stdout_route, Stream::Stdout, Stream::Stderr) {
+                            None => discard(stdout).await,
+                            Some(stream) => {
+                                let last_line = last_lines.map(|lines| &lines.stdout);
+                                let output = routed_output(stdout_route, &output);
+                                json_lines(stdout, stream, &options, last_line, output).await
+                                    && stdout_route != StreamRoute::File
+                            }
+                        }
+                    },
+                    async {
+                        match routed_stream(stderr_route, Stream::Stderr, Stream::Stdout) {
+                            None => discard(stderr).await,
+                            Some(stream) => {
+                                let last_line = last_lines.map(|lines| &lines.stderr);
+                                let output = routed_output(stderr_route, &output);
+                                json_lines(stderr, stream, &options, last_line, output).await
+                                    && stderr_route != StreamRoute::File
                             }
                         }
+                    },
+                );
+                if let Some(log_file) = &log_file {
+                    log_file.flush();
+                }
+                if let Some((broadcast, label)) = &broadcast {
+                    broadcast.end(label);
+                }
+                let _ = printed_tx.send(stdout_printed || stderr_printed);
+                return;
+            }
+            let (stdout_printed, stderr_printed) = tokio::join!(
+                async {
+                    match routed_stream(stdout_route, stdout_stream, Stream::Stderr) {
+                        None => discard(stdout).await,
+                        Some(stream) => {
+                            let last_line = last_lines.map(|lines| &lines.stdout);
+                            let output = routed_output(stdout_route, &command_output);
+                            log_lines(stdout, stream, &options, last_line, output).await
+                                && stdout_route != StreamRoute::File
+                        }
                     }
                 },
                 async {
-                    let stderr = BufReader::new(stderr);
-                    let mut lines = stderr.lines();
-                    loop {
-                        match lines.next_line().await {
-                            Ok(line) => {
-                                if let Some(line) = line {
-                                    #[cfg(feature = "auto_ansi_escape")]
-                                    let line = crate::ansi_escape::process_ansi_escape_line(
-                                        label.len() + 3,
-                                        &line,
-                                    );
-
-                                    let line = format!("[{}] {}", label, line);
-                                    eprintln!("{}", line);
-                                } else {
-                                    break;
-                                }
-                            }
-                            Err(err) => {
-                                eprintln!(
-                                    "[runcc error] failed to read line from [{}] stderr: {}",
-                                    label, err
-                                );
-                                break;
-                            }
+                    match routed_stream(stderr_route, Stream::Stderr, stdout_stream) {
+                        None => discard(stderr).await,
+                        Some(stream) => {
+                            let last_line = last_lines.map(|lines| &lines.stderr);
+                            let output = routed_output(stderr_route, &command_output);
+                            log_lines(stderr, stream, &options, last_line, output).await
+                                && stderr_route != StreamRoute::File
                         }
                     }
-                }
+                },
             );
+            if let Some(log_file) = &log_file {
+                log_file.flush();
+            }
+            if let Some((broadcast, label)) = &broadcast {
+                broadcast.end(label);
+            }
+
+            if let Some(grouped) = grouped {
+                let flushed = command_output.clone();
+                let _ = tokio::task::spawn_blocking(move || flushed.flush()).await;
+                let mut items = std::mem::take(&mut *grouped.lock().unwrap());
+                // a CI section keeps stderr with stdout
+                if let Some((format, title, started_at)) = group {
+                    let grouped: Vec<u8> = items.into_iter().flat_map(|(_, bytes)| bytes).collect();
+                    items = match grouped.is_empty() {
+                        true => vec![],
+                        false => {
+                            let section =
+                                format.group(&title, &grouped, started_at, SystemTime::now());
+                            vec![(stdout_stream, section)]
+                        }
+                    };
+                }
+                match &groups {
+                    Some(groups) => groups.read(&output, index, items),
+                    None => {
+                        for (stream, bytes) in items {
+                            output.write_command_output(stream, bytes).await;
+                        }
+                    }
+                }
+            }
+            let _ = printed_tx.send(stdout_printed || stderr_printed);
         });
 
-        let mut joins = self.0.lock().unwrap();
+        let mut joins = self.joins.lock().unwrap();
         joins.push(join);
 
         data
     }
 
     fn on_command_exited(&self, cmd: Arc<CommandStopped<LabeledCommandData, LabeledCommandData>>) {
-        let label = cmd.data.label.display();
+        if let Some(trace) = &self.trace {
+            let ready_at = cmd.data.ready.as_ref().and_then(|ready| ready.ready_at());
+            trace.exited(cmd.data.label.label(), ready_at, Instant::now());
+        }
+        if let Some(metrics) = &self.metrics {
+            let ready_at = cmd.data.ready.as_ref().and_then(|ready| ready.ready_at());
+            let exit_code = cmd.exit_status.as_ref().ok().and_then(|s| s.code());
+            metrics.exited(cmd.data.label.label(), ready_at, exit_code);
+        }
+        if let Some(log_files) = &self.log_files {
+            log_files.command_exited(cmd.data.label.label());
+        }
+
+        let relabeled = cmd.data.label.relabeled();
+        let label = relabeled.as_deref().unwrap_or(cmd.data.label.display());
         let status = &cmd.exit_status;
         let killed = &cmd.killed;
         let status = match status {
@@ -111,6 +1437,14 @@ impl CommandSystemPlugin<LabeledCommandData> for CommandSystemLogPlugin {
                 use crate::run::kill::KillJoinHandleFinalStatus as KS;
                 match kill_status {
                     KS::Killed(reason) => format!(" (killed due to {})", reason).into(),
+                    KS::ForceKilled(reason) => {
+                        format!(" (killed forcefully due to {})", reason).into()
+                    }
+                    KS::Abandoned(reason) => format!(
+                        " (killed forcefully due to {} but it didn't exit, stopped waiting)",
+                        reason
+                    )
+                    .into(),
                     KS::FailedToKill { reason, error } => {
                         format!(" (tried to kill due to {} but failed: {})", reason, error).into()
                     }
@@ -120,12 +1454,225 @@ impl CommandSystemPlugin<LabeledCommandData> for CommandSystemLogPlugin {
             None => "".into(),
         };
 
-        let line = format!("[{}] exited with status {}{}", label, status, killed);
-        eprintln!("{}", line);
+        if let Some(board) = &self.status {
+            let exit_code = cmd.exit_status.as_ref().ok().and_then(|s| s.code());
+            board.command_exited(cmd.data.label.label(), exit_code);
+        }
+
+        if let Some(events) = &self.events {
+            use crate::run::kill::KillJoinHandleFinalStatus as KS;
+            let reason = match &cmd.killed {
+                Some(KS::Killed(reason))
+                | Some(KS::ForceKilled(reason))
+                | Some(KS::Abandoned(reason)) => {
+                    if let kill::KillCommandReason::OtherCommandExited(_) = reason {
+                        events.shutdown(&reason.to_string());
+                    }
+                    Some(reason.to_string())
+                }
+                _ => None,
+            };
+            let exit_code = cmd.exit_status.as_ref().ok().and_then(|s| s.code());
+            let ready = cmd.data.ready.as_ref();
+            events.command_exited(
+                cmd.data.label.label(),
+                ready,
+                cmd.outcome(),
+                reason.as_deref(),
+                exit_code,
+            );
+        }
+
+        if let Some(github) = &self.github {
+            github.command_exited(cmd.data.label.label(), cmd.outcome(), status.clone());
+        }
+
+        let prefix = self.prefix(&cmd.data, label, Stream::Stderr);
+        let line = format!("{} exited with status {}{}", prefix, status, killed);
+        let exited_at = self.exited_at(&cmd);
+        let line = match &exited_at {
+            Some(exited_at) => format!("{} {}", exited_at, line),
+            None => line,
+        };
+        let json = self.format == OutputFormat::Json;
+        let (line, line_stream) = match json {
+            true => {
+                let event = JsonEvent::exited(cmd.data.label.label(), &cmd.exit_status);
+                (event.into_line(), Stream::Stdout)
+            }
+            false => (line, Stream::Stderr),
+        };
+
+        let printed_output = self
+            .printed_output
+            .lock()
+            .unwrap()
+            .remove(cmd.data.label.label());
+        // output may still be read after the command exited
+        let hide_if_silent = (cmd.success() && !self.show_silent_commands) || json;
+        let (printed_output, output_read) = match hide_if_silent {
+            true => (printed_output, None),
+            false => (None, printed_output),
+        };
+        let quiet = cmd.data.quiet_exit && cmd.success() && !json;
+        // written after the output of the command, see `group_output`
+        let groups = self.groups.as_ref().filter(|_| !json);
+        let tail = self.output_broadcast.as_ref().filter(|_| {
+            self.tail_lines > 0 && cmd.outcome() == CommandOutcome::Failed && groups.is_none()
+        });
+        if let (Some(broadcast), Some(output_read)) = (tail, output_read) {
+            let (broadcast, output) = (broadcast.clone(), self.output.clone());
+            let (label, tail_lines) = (cmd.data.label.label().to_string(), self.tail_lines);
+            let join = tokio::spawn(async move {
+                let _ = output_read.await;
+                let mut events = broadcast.tail(&label);
+                events.drain(..events.len().saturating_sub(tail_lines));
+                for line in tail_of(&label, &events) {
+                    output.write_line(Stream::Stderr, &line);
+                }
+            });
+            self.joins.lock().unwrap().push(join);
+        }
+        if printed_output.is_none() && !quiet && groups.is_none() {
+            self.output.write_line(line_stream, &line);
+        }
+
+        // commands which failed to spawn or were skipped have no exit status
+        // and were never counted as spawned
+        if cmd.exit_status.is_ok() || cmd.killed.is_some() {
+            if let Some(message) = self.shutdown_progress.command_exited() {
+                self.output.write_line(Stream::Stderr, &message);
+            }
+        }
+
+        let (message, color) = if cmd.success() {
+            (&cmd.data.success_message, "32")
+        } else {
+            (&cmd.data.failure_message, "31")
+        };
+
+        let message = message.as_ref().map(|message| {
+            let message = if self.color.colors(Stream::Stderr) {
+                format!("{} \x1b[{}m{}\x1b[0m", prefix, color, message)
+            } else {
+                format!("{} {}", prefix, message)
+            };
+            match &exited_at {
+                Some(exited_at) => format!("{} {}", exited_at, message),
+                None => message,
+            }
+        });
+
+        match (groups, printed_output) {
+            (Some(groups), printed_output) => {
+                let line = (!quiet).then_some((line_stream, line));
+                let hide_if_silent = printed_output.is_some();
+                groups.exited(&self.output, cmd.data.index, line, hide_if_silent, message);
+            }
+            (None, Some(printed_output)) => {
+                let output = self.output.clone();
+                let join = tokio::spawn(async move {
+                    let printed = printed_output.await.unwrap_or(true);
+                    if (printed || json) && !quiet {
+                        output.write_line(line_stream, &line);
+                    }
+                    if let Some(message) = message {
+                        output.write_line(Stream::Stderr, &message);
+                    }
+                });
+                self.joins.lock().unwrap().push(join);
+            }
+            (None, None) => {
+                if let Some(message) = message {
+                    self.output.write_line(Stream::Stderr, &message);
+                }
+            }
+        }
+
+        if let Some(kill::KillCommandReason::FilesChanged(path)) = kill_reason(&cmd) {
+            if !json {
+                let line = restart_separator_line(
+                    &self.restart_separator,
+                    Stream::Stderr.width(),
+                    cmd.data.label.label(),
+                    path,
+                );
+                let clear = self.restart_separator.clear_screen && Stream::Stderr.is_terminal();
+                let line = match clear {
+                    true => format!("\x1b[2J\x1b[H{}", line),
+                    false => line,
+                };
+                self.output.write_line(Stream::Stderr, &line);
+            }
+        }
+    }
+
+    fn on_restarts_paused(&self, data: &LabeledCommandData, cooldown: Duration) {
+        let prefix = self.prefix(data, data.label.display(), Stream::Stderr);
+        let line = format!(
+            "{} restarted too often, pausing restarts for {}",
+            prefix,
+            humantime::format_duration(cooldown)
+        );
+        self.output.write_line(Stream::Stderr, &line);
+    }
+
+    fn on_early_exit(
+        &self,
+        data: &LabeledCommandData,
+        attempt: u32,
+        attempts: u32,
+        delay: Duration,
+    ) {
+        let prefix = self.prefix(data, data.label.display(), Stream::Stderr);
+        let line = format!(
+            "{} failed early, attempt {}/{} in {}",
+            prefix,
+            attempt,
+            attempts,
+            humantime::format_duration(delay)
+        );
+        self.output.write_line(Stream::Stderr, &line);
+    }
+
+    fn on_probe_failed(&self, data: &LabeledCommandData, attempt: u32, attempts: u32) {
+        let prefix = self.prefix(data, data.label.display(), Stream::Stderr);
+        let line = format!(
+            "{} readiness probe failed ({}/{})",
+            prefix, attempt, attempts
+        );
+        self.output.write_line(Stream::Stderr, &line);
+    }
+
+    fn on_unhealthy(&self, data: &LabeledCommandData, failures: u32) {
+        let prefix = self.prefix(data, data.label.display(), Stream::Stderr);
+        let line = format!("{} unhealthy, {} health checks failed", prefix, failures);
+        self.output.write_line(Stream::Stderr, &line);
+    }
+
+    fn on_command_started(&self, data: &LabeledCommandData, pid: Option<u32>) {
+        let started = self.started.lock().unwrap().remove(data.label.label());
+        if let Some(started) = started {
+            let event = JsonEvent::Started {
+                label: data.label.label(),
+                pid,
+                pane: data.pane.as_deref(),
+            };
+            self.output.write_line(Stream::Stdout, &event.into_line());
+            let _ = started.send(());
+        }
+    }
+
+    fn on_system_started(&self) {
+        self.output.start();
+    }
+
+    fn output_broadcast(&self) -> Option<&OutputBroadcast> {
+        self.output_broadcast.as_deref()
     }
 
     fn join(&self) -> Option<tokio::task::JoinHandle<()>> {
-        let mut joins = self.0.lock().unwrap();
+        let mut joins = self.joins.lock().unwrap();
 
         let mut joins: Vec<_> = joins.drain(0..).collect();
 
@@ -144,6 +1691,25 @@ impl CommandSystemPlugin<LabeledCommandData> for CommandSystemLogPlugin {
     }
 }
 
+/// `label` restarting due to `path` between rules of the separator character,
+/// together `width` characters wide
+fn restart_separator_line(
+    separator: &RestartSeparator,
+    width: usize,
+    label: &str,
+    path: &std::path::Path,
+) -> String {
+    let text = format!(
+        " {} restarting {} due to change in {} ",
+        humantime::format_rfc3339_seconds(SystemTime::now()),
+        label,
+        path.display()
+    );
+    let rule = |len| separator.character.to_string().repeat(len);
+    let right = width.saturating_sub(text.chars().count() + 4);
+    format!("{}{}{}", rule(4), text, rule(right))
+}
+
 impl Display for kill::KillCommandReason<LabeledCommandData> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -151,6 +1717,880 @@ impl Display for kill::KillCommandReason<LabeledCommandData> {
                 write!(f, "command[{}] exited", cmd.data.label.label())
             }
             kill::KillCommandReason::MainProcessGotSignal => write!(f, "Ctrl-C signal"),
+            kill::KillCommandReason::NotReadyInTime => write!(f, "not being ready in time"),
+            kill::KillCommandReason::ProbeFailed { attempts } => {
+                write!(f, "the readiness probe failing {} times", attempts)
+            }
+            kill::KillCommandReason::TimedOut(timeout) => {
+                write!(
+                    f,
+                    "timing out after {}",
+                    humantime::format_duration(*timeout)
+                )
+            }
+            kill::KillCommandReason::FilesChanged(_) => write!(f, "watched files changed"),
+            kill::KillCommandReason::SystemPanicked => write!(f, "a panic in runcc"),
+            kill::KillCommandReason::Requested => write!(f, "a kill request"),
+            kill::KillCommandReason::RestartRequested => write!(f, "a restart request"),
+            kill::KillCommandReason::Unhealthy { failures } => {
+                write!(f, "the health check failing {} times", failures)
+            }
+            kill::KillCommandReason::StopWhenReady { label, exited } => match exited {
+                true => write!(f, "command[{}] exited", label),
+                false => write!(f, "command[{}] being ready", label),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use tokio::io::BufReader;
+
+    use super::{
+        copy_lines, json_lines, label_prefix, label_with_cwd, log_lines, mask, next_line,
+        ColorChoice, CommandSystemLogPlugin, LineTimestamps, LogOptions, Output, Repeats, Stream,
+    };
+    use crate::run::{CommandStopped, LabeledCommandData, OutputBroadcast, OutputStream};
+    use crate::{label::Label, LogColor, OutputBackpressure, OutputEncoding, OutputTimestamps};
+
+    /// Logs the lines of `label` as they are, without any options
+    fn log_options(label: &str) -> LogOptions<'_> {
+        LogOptions {
+            label,
+            ready: None,
+            masked: &[],
+            collapse_repeats: false,
+            binary_safe: false,
+            encoding: OutputEncoding::Utf8,
+            interleave_guard: false,
+            partial_line_flush: None,
+            timestamps: None,
+            color: ColorChoice::Auto,
+            label_color: None,
+            label_from: None,
+            progress: None,
+            line_filter: None,
+            line_count: None,
+            log_file: None,
+            broadcast: None,
+            relabel: None,
+            level_colors: None,
+            prefix_renderer: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn copy_non_utf8_lines() {
+        let masked = ["hunter2".to_string()];
+        let options = LogOptions {
+            masked: &masked,
+            binary_safe: true,
+            ..log_options("bin")
+        };
+
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let written = sink.clone();
+        let output = Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| written.lock().unwrap().extend_from_slice(bytes)),
+        );
+        let reader = &b"ok\n\xff\xfe\x00 hunter2\r\nend"[..];
+        let mut printed = false;
+        copy_lines(
+            reader,
+            Stream::Stdout,
+            &options,
+            None,
+            &output,
+            &mut printed,
+        )
+        .await
+        .unwrap();
+        output.flush();
+
+        assert!(printed);
+        assert_eq!(
+            *sink.lock().unwrap(),
+            b"[bin] ok\n[bin] \xff\xfe\x00 ***\r\n[bin] end\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn json_lines_stay_valid() {
+        let masked = ["hunter2".to_string()];
+        let options = LogOptions {
+            masked: &masked,
+            ..log_options("bin")
+        };
+
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let written = sink.clone();
+        let output = Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            // events of both streams are written to stdout
+            Box::new(move |stream, bytes| {
+                if stream == Stream::Stdout {
+                    written.lock().unwrap().extend_from_slice(bytes)
+                }
+            }),
+        );
+        let reader = &b"\x1b[1mok\x1b[0m\n\xff\x00 hunter2\r\nend"[..];
+        assert!(json_lines(reader, Stream::Stderr, &options, None, &output).await);
+        output.flush();
+
+        let sink = String::from_utf8(sink.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = sink
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let text: Vec<_> = lines.iter().map(|line| &line["line"]).collect();
+        assert_eq!(text, ["\x1b[1mok\x1b[0m", "\u{fffd}\0 ***", "end"]);
+        assert!(lines
+            .iter()
+            .all(|line| line["label"] == "bin" && line["stream"] == "stderr"));
+    }
+
+    #[tokio::test]
+    async fn publish_lines_without_label() {
+        let masked = ["hunter2".to_string()];
+        let broadcast = OutputBroadcast::default();
+        let options = LogOptions {
+            masked: &masked,
+            broadcast: Some((&broadcast, "web")),
+            ..log_options("web (./app)")
+        };
+
+        let output = Output::with_sink(false, OutputBackpressure::Block, Box::new(|_, _| {}));
+        let reader = &b"listening\npassword hunter2\n"[..];
+        assert!(log_lines(reader, Stream::Stderr, &options, None, &output).await);
+
+        let mut lines = broadcast.subscribe("web", true);
+        for line in ["listening", "password ***"] {
+            let event = lines.recv().await.unwrap();
+            assert_eq!(event.line, line);
+            assert_eq!(event.stream, OutputStream::Stderr);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn end_published_output_on_exit() {
+        let broadcast = Arc::new(OutputBroadcast::default());
+        let plugin = CommandSystemLogPlugin {
+            output: Arc::new(Output::with_sink(
+                false,
+                OutputBackpressure::Block,
+                Box::new(|_, _| {}),
+            )),
+            ..CommandSystemLogPlugin::new()
         }
+        .output_broadcast(Some(broadcast.clone()));
+        let input: crate::RunConfigInput =
+            serde_yaml::from_str("commands: { web: 'echo one; echo two >&2' }").unwrap();
+        let mut system = crate::run::spawn_from_run_config_with_plugin(input.into(), plugin);
+
+        let mut lines = broadcast.subscribe("web", true);
+        let mut received = vec![];
+        while let Some(event) = lines.recv().await {
+            received.push(event.line);
+        }
+        received.sort();
+        assert_eq!(received, ["one", "two"]);
+        system.wait().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn print_tail_after_output_of_failed_command() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let sink = written.clone();
+        let output = Arc::new(Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| {
+                let text = String::from_utf8_lossy(bytes);
+                sink.lock()
+                    .unwrap()
+                    .extend(text.lines().map(str::to_string))
+            }),
+        ));
+        let plugin = CommandSystemLogPlugin {
+            output: output.clone(),
+            ..CommandSystemLogPlugin::new()
+        }
+        .output_broadcast(Some(Arc::new(OutputBroadcast::default())))
+        .tail_lines(2);
+        let input: crate::RunConfigInput = serde_yaml::from_str(
+            "commands: { web: 'echo one; echo two; echo three; exit 1', api: 'echo ok' }",
+        )
+        .unwrap();
+        let mut system = crate::run::spawn_from_run_config_with_plugin(input.into(), plugin);
+        system.wait().await.unwrap();
+        output.flush();
+
+        let written = written.lock().unwrap();
+        let tail = written
+            .iter()
+            .position(|line| line.starts_with("last"))
+            .unwrap();
+        assert_eq!(
+            written[tail..tail + 3],
+            ["last 2 lines of web:", "  two", "  three"]
+        );
+        assert!(written[..tail].iter().any(|line| line.ends_with("three")));
+        assert!(!written.iter().any(|line| line.ends_with("of api:")));
+    }
+
+    #[tokio::test]
+    async fn label_lines_from_regex() {
+        let label_from = crate::LineExtractor::Regex(r"module=(\w+)".to_string())
+            .compile()
+            .unwrap();
+        let options = LogOptions {
+            label_from: Some(&label_from),
+            ..log_options("api")
+        };
+
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let written = sink.clone();
+        let output = Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| written.lock().unwrap().extend_from_slice(bytes)),
+        );
+        let reader = &b"module=db connected
+starting
+module=http listening
+"[..];
+        log_lines(reader, Stream::Stdout, &options, None, &output).await;
+        output.flush();
+
+        assert_eq!(
+            String::from_utf8(sink.lock().unwrap().clone()).unwrap(),
+            "[api][db] module=db connected\n[api] starting\n[api][http] module=http listening\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn filter_lines_before_logging() {
+        let cmd = crate::CommandConfig {
+            ignore: Some(vec!["^webpack compiled".to_string()]),
+            strip_ansi: true,
+            max_line_length: Some(12),
+            ..Default::default()
+        };
+        let line_filter = crate::LineFilter::of(&cmd).unwrap().unwrap();
+        let ready = crate::run::ReadySignal::new(regex::Regex::new("^webpack compiled").unwrap());
+        let options = |binary_safe| LogOptions {
+            ready: Some(&ready),
+            binary_safe,
+            line_filter: Some(&line_filter),
+            ..log_options("web")
+        };
+        let sink = || {
+            let sink = Arc::new(Mutex::new(Vec::new()));
+            let written = sink.clone();
+            let output = Output::with_sink(
+                false,
+                OutputBackpressure::Block,
+                Box::new(move |_, bytes| written.lock().unwrap().extend_from_slice(bytes)),
+            );
+            (sink, output)
+        };
+        let reader = &b"\x1b[32mstarting\x1b[0m
+webpack compiled in 3s
+error: module not found
+"[..];
+
+        let (logged, output) = sink();
+        log_lines(reader, Stream::Stdout, &options(false), None, &output).await;
+        output.flush();
+        assert_eq!(
+            String::from_utf8(logged.lock().unwrap().clone()).unwrap(),
+            "[web] starting\n[web] error: modul…\n"
+        );
+        // dropped lines are still checked
+        assert!(ready.is_ready());
+
+        let (copied, output) = sink();
+        let mut printed = false;
+        let binary_safe = options(true);
+        let copy = copy_lines(
+            reader,
+            Stream::Stdout,
+            &binary_safe,
+            None,
+            &output,
+            &mut printed,
+        );
+        copy.await.unwrap();
+        output.flush();
+        assert_eq!(*copied.lock().unwrap(), *logged.lock().unwrap());
+
+        let (json, output) = sink();
+        json_lines(reader, Stream::Stdout, &options(false), None, &output).await;
+        output.flush();
+        let json = String::from_utf8(json.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = json
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["line"].clone())
+            .collect();
+        assert_eq!(lines, ["starting", "error: modul…"]);
+    }
+
+    #[tokio::test]
+    async fn flush_partial_lines() {
+        use std::time::Duration;
+        use tokio::io::AsyncWriteExt;
+
+        let written = Arc::new(Mutex::new(String::new()));
+        let sink = written.clone();
+        let output = Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| {
+                sink.lock()
+                    .unwrap()
+                    .push_str(&String::from_utf8_lossy(bytes))
+            }),
+        );
+        let options = LogOptions {
+            partial_line_flush: Some(Duration::from_millis(20)),
+            ..log_options("cli")
+        };
+
+        let (mut writer, reader) = tokio::io::duplex(1024);
+        let prompting = async {
+            writer.write_all(b"Enter password: ").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            output.flush();
+            // the prompt is visible before the line ends
+            assert_eq!(*written.lock().unwrap(), "[cli] Enter password: ");
+
+            // the first half of a UTF-8 sequence is held back
+            writer.write_all(b"ok\nwaiting \xe2").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            writer.write_all(b"\x80\xa6").await.unwrap();
+            drop(writer);
+        };
+        tokio::join!(
+            prompting,
+            log_lines(reader, Stream::Stdout, &options, None, &output),
+        );
+        output.flush();
+
+        assert_eq!(
+            *written.lock().unwrap(),
+            "[cli] Enter password: ok\n[cli] waiting \u{2026}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn split_lines_at_carriage_returns() {
+        use std::time::Duration;
+        use tokio::io::AsyncWriteExt;
+
+        let written = Arc::new(Mutex::new(String::new()));
+        let sink = written.clone();
+        let output = Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| {
+                sink.lock()
+                    .unwrap()
+                    .push_str(&String::from_utf8_lossy(bytes))
+            }),
+        );
+        let options = log_options("dl");
+
+        let (mut writer, reader) = tokio::io::duplex(1024);
+        let downloading = async {
+            writer.write_all(b"fetching\r\n\r10%\r").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            output.flush();
+            // the progress is visible before the next update
+            assert_eq!(*written.lock().unwrap(), "[dl] fetching\n[dl] 10%\n");
+
+            // the \n of a \r\n split across reads doesn't end an empty line
+            writer.write_all(b"\r55%\r").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            writer.write_all(b"\ndone").await.unwrap();
+            drop(writer);
+        };
+        tokio::join!(
+            downloading,
+            log_lines(reader, Stream::Stdout, &options, None, &output),
+        );
+        output.flush();
+
+        assert_eq!(
+            *written.lock().unwrap(),
+            "[dl] fetching\n[dl] 10%\n[dl] 55%\n[dl] done\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn track_progress_of_lines() {
+        let written = Arc::new(Mutex::new(String::new()));
+        let sink = written.clone();
+        let output = Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| {
+                sink.lock()
+                    .unwrap()
+                    .push_str(&String::from_utf8_lossy(bytes))
+            }),
+        );
+        let progress = crate::run::Progress::new(regex::Regex::new(r"\[(\d+)/(\d+)\]").unwrap());
+        let options = LogOptions {
+            progress: Some(&progress),
+            ..log_options("make")
+        };
+
+        let reader = BufReader::new(&b"[1/4] CC a.o\n[3/4] CC c.o\nwarning: unused\n"[..]);
+        log_lines(reader, Stream::Stdout, &options, None, &output).await;
+        output.flush();
+
+        assert_eq!(progress.percent(), Some(75));
+        // stdout is no terminal in tests, so lines are logged without a bar
+        assert_eq!(
+            *written.lock().unwrap(),
+            "[make] [1/4] CC a.o\n[make] [3/4] CC c.o\n[make] warning: unused\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn relabel_mid_run() {
+        use std::time::Duration;
+        use tokio::io::AsyncWriteExt;
+
+        let written = Arc::new(Mutex::new(String::new()));
+        let sink = written.clone();
+        let output = Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| {
+                sink.lock()
+                    .unwrap()
+                    .push_str(&String::from_utf8_lossy(bytes))
+            }),
+        );
+        let label = Label::from_label("web".to_string(), 3);
+        let options = LogOptions {
+            relabel: Some(&label),
+            ..log_options("web")
+        };
+
+        let (mut writer, reader) = tokio::io::duplex(1024);
+        let running = async {
+            writer.write_all(b"starting\n").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            label.relabel("api");
+            writer.write_all(b"listening\n").await.unwrap();
+            drop(writer);
+        };
+        tokio::join!(
+            running,
+            log_lines(reader, Stream::Stdout, &options, None, &output),
+        );
+        output.flush();
+
+        assert_eq!(
+            *written.lock().unwrap(),
+            "[web] starting\n[api] listening\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn decode_lines() {
+        let mut reader = BufReader::new(&b"caf\xe9\r\n\x80 5\n"[..]);
+        let buf = &mut Vec::new();
+        let after_cr = &mut false;
+        let encoding = OutputEncoding::Windows1252;
+        assert_eq!(
+            next_line(&mut reader, buf, after_cr, encoding)
+                .await
+                .unwrap()
+                .unwrap(),
+            "café"
+        );
+        assert_eq!(
+            next_line(&mut reader, buf, after_cr, encoding)
+                .await
+                .unwrap()
+                .unwrap(),
+            "€ 5"
+        );
+        assert_eq!(
+            next_line(&mut reader, buf, after_cr, encoding)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let mut reader = BufReader::new(&b"caf\xe9\n"[..]);
+        let utf8 = OutputEncoding::Utf8;
+        assert!(next_line(&mut reader, buf, after_cr, utf8).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn keep_blocks_together() {
+        use std::time::Duration;
+        use tokio::io::AsyncWriteExt;
+
+        let written = Arc::new(Mutex::new(String::new()));
+        let sink = written.clone();
+        let output = Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| {
+                sink.lock()
+                    .unwrap()
+                    .push_str(&String::from_utf8_lossy(bytes))
+            }),
+        );
+
+        // prints lines with a pause before each one
+        let print = |lines: &'static [&'static str], pause: u64| {
+            let (mut writer, reader) = tokio::io::duplex(1024);
+            let printing = async move {
+                for line in lines {
+                    tokio::time::sleep(Duration::from_millis(pause)).await;
+                    writer.write_all(line.as_bytes()).await.unwrap();
+                }
+            };
+            (printing, reader)
+        };
+        let (trace, trace_reader) = print(
+            &[
+                "panicked at main.rs\n",
+                "   0: main\n",
+                "   1: start\n",
+                "done\n",
+            ],
+            20,
+        );
+        let (other, other_reader) = print(&["a\n", "b\n", "c\n", "d\n", "e\n"], 15);
+
+        let options = |label| LogOptions {
+            interleave_guard: true,
+            ..log_options(label)
+        };
+        let (api, web) = (options("api"), options("web"));
+        let _ = tokio::join!(
+            trace,
+            other,
+            log_lines(trace_reader, Stream::Stdout, &api, None, &output),
+            log_lines(other_reader, Stream::Stdout, &web, None, &output),
+        );
+        output.flush();
+
+        let written = written.lock().unwrap();
+        assert!(
+            written.contains("[api] panicked at main.rs\n[api]    0: main\n[api]    1: start\n"),
+            "{}",
+            written
+        );
+        assert_eq!(written.lines().count(), 9);
+    }
+
+    #[test]
+    fn per_command_relative_timestamps() {
+        use std::time::{Duration, Instant};
+
+        let run_started_at = Instant::now();
+        let now = run_started_at + Duration::from_millis(2500);
+        let timestamps = |mode, spawned_after_ms| {
+            let spawned_at = run_started_at + Duration::from_millis(spawned_after_ms);
+            LineTimestamps::new(mode, run_started_at, spawned_at).at(now)
+        };
+
+        let per_command = OutputTimestamps::PerCommandRelative;
+        assert_eq!(timestamps(per_command, 0), "   2.500s");
+        assert_eq!(timestamps(per_command, 2000), "   0.500s");
+        assert_eq!(timestamps(OutputTimestamps::Relative, 2000), "   2.500s");
+        assert!(timestamps(OutputTimestamps::Absolute, 2000).ends_with('Z'));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exit_line_timestamps() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::time::Duration;
+
+        let stopped = |ran_for| CommandStopped {
+            data: LabeledCommandData::labeled("web"),
+            exit_status: Ok(std::process::ExitStatus::from_raw(0)),
+            killed: None,
+            ran_for,
+        };
+
+        let plugin = CommandSystemLogPlugin::new();
+        assert_eq!(plugin.exited_at(&stopped(None)), None);
+
+        let plugin = plugin.timestamps(Some(OutputTimestamps::PerCommandRelative));
+        let exited_at = plugin.exited_at(&stopped(Some(Duration::from_millis(1500))));
+        assert_eq!(exited_at.as_deref(), Some("   1.500s"));
+        // commands which failed to spawn exited right away
+        assert_eq!(
+            plugin.exited_at(&stopped(None)).as_deref(),
+            Some("   0.000s")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn separator_on_watch_restart() {
+        use crate::run::kill::{KillCommandReason, KillJoinHandleFinalStatus};
+        use crate::run::CommandSystemPlugin;
+        use crate::RestartSeparator;
+        use std::os::unix::process::ExitStatusExt;
+
+        let written = Arc::new(Mutex::new(String::new()));
+        let sink = written.clone();
+        let output = Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| {
+                sink.lock()
+                    .unwrap()
+                    .push_str(&String::from_utf8_lossy(bytes))
+            }),
+        );
+        let plugin = CommandSystemLogPlugin {
+            output: Arc::new(output),
+            ..CommandSystemLogPlugin::new()
+        }
+        .restart_separator(RestartSeparator {
+            character: '=',
+            clear_screen: true,
+        });
+        let stopped = |killed| CommandStopped {
+            data: LabeledCommandData::labeled("web"),
+            exit_status: Ok(std::process::ExitStatus::from_raw(15)),
+            killed,
+            ran_for: None,
+        };
+
+        // both runs were spawned by runcc
+        plugin.shutdown_progress.command_spawned();
+        plugin.shutdown_progress.command_spawned();
+        let changed = KillCommandReason::FilesChanged("src/main.rs".into());
+        plugin.on_command_exited(Arc::new(stopped(Some(KillJoinHandleFinalStatus::Killed(
+            changed,
+        )))));
+        let other = KillCommandReason::MainProcessGotSignal;
+        plugin.on_command_exited(Arc::new(stopped(Some(KillJoinHandleFinalStatus::Killed(
+            other,
+        )))));
+        plugin.output.flush();
+
+        let written = written.lock().unwrap();
+        let lines: Vec<_> = written.lines().collect();
+        // the exit lines of both runs and a separator after the first one
+        assert_eq!(lines.len(), 3, "{:?}", lines);
+        assert!(lines[0].contains("watched files changed"));
+        let separator = lines[1];
+        assert!(separator.starts_with("==== "));
+        assert!(separator.contains(" restarting web due to change in src/main.rs "));
+        assert!(separator.ends_with('='));
+        assert_eq!(separator.chars().count(), 80);
+        // stderr is no terminal in tests
+        assert!(!separator.contains('\x1b'));
+        assert!(lines[2].contains("Ctrl-C signal"));
+    }
+
+    #[test]
+    fn cwd_in_label() {
+        assert_eq!(label_with_cwd("web", "apps/web"), "web apps/web");
+        assert_eq!(
+            label_with_cwd("api", "/home/dev/monorepo/services/api"),
+            "api …v/monorepo/services/api"
+        );
+    }
+
+    #[test]
+    fn label_colors() {
+        let data = |index| LabeledCommandData {
+            index,
+            ..LabeledCommandData::labeled("web")
+        };
+        assert_eq!(label_prefix("web", None), "[web]");
+        assert_eq!(label_prefix("web", Some("36")), "\x1b[36m[web]\x1b[0m");
+
+        let plugin = CommandSystemLogPlugin::new();
+        assert_eq!(plugin.label_color(&data(0)), None);
+
+        let plugin = CommandSystemLogPlugin {
+            colored_labels: true,
+            ..CommandSystemLogPlugin::new()
+        };
+        let colors: Vec<_> = (0..12)
+            .map(|i| plugin.label_color(&data(i)).unwrap())
+            .collect();
+        assert_eq!(colors[0], "36");
+        assert_eq!(colors[1], "35");
+        // more commands than colors
+        assert_eq!(colors[10], colors[0]);
+        assert_eq!(colors[11], colors[1]);
+        let distinct: std::collections::HashSet<_> = colors[..10].iter().collect();
+        assert_eq!(distinct.len(), 10);
+
+        // the color of the config replaces the picked one
+        let mut red = data(0);
+        red.label = red.label.with_color(Some(LogColor::Red));
+        assert_eq!(plugin.label_color(&red), Some("31"));
+
+        let always = CommandSystemLogPlugin {
+            color: ColorChoice::Always,
+            ..plugin
+        };
+        assert_eq!(
+            always.prefix(&red, "web", Stream::Stdout),
+            "\x1b[31m[web]\x1b[0m"
+        );
+        let never = CommandSystemLogPlugin {
+            color: ColorChoice::Never,
+            ..always
+        };
+        assert_eq!(never.prefix(&red, "web", Stream::Stdout), "[web]");
+    }
+
+    #[test]
+    fn seeded_label_colors() {
+        let data = |label: &str, index| LabeledCommandData {
+            index,
+            ..LabeledCommandData::labeled(label)
+        };
+        let plugin = |seed| CommandSystemLogPlugin {
+            colored_labels: true,
+            ..CommandSystemLogPlugin::new().color_seed(Some(seed))
+        };
+        let colors = |seed, labels: &[&'static str]| -> Vec<_> {
+            let plugin = plugin(seed);
+            let mut colors: Vec<_> = labels
+                .iter()
+                .enumerate()
+                .map(|(i, label)| (*label, plugin.label_color(&data(label, i)).unwrap()))
+                .collect();
+            colors.sort();
+            colors
+        };
+
+        // the order of commands doesn't matter, only the label and the seed
+        let labels = ["web", "api", "worker", "db", "cache"];
+        let reordered = ["cache", "worker", "db", "web", "api"];
+        assert_eq!(colors(7, &labels), colors(7, &reordered));
+        assert_eq!(colors(7, &labels[..2]), colors(7, &reordered[3..]));
+        assert_ne!(colors(7, &labels), colors(8, &labels));
+    }
+
+    #[test]
+    fn mask_values() {
+        let masked = ["hunter2".to_string(), "s3cr3t".to_string()];
+        assert_eq!(
+            mask("login hunter2:s3cr3t".to_string(), &masked),
+            "login ***:***"
+        );
+        assert_eq!(mask("nothing here".to_string(), &masked), "nothing here");
+    }
+
+    #[test]
+    fn collapse_repeated_lines() {
+        let mut repeats = Repeats::default();
+        let pushed: Vec<_> = ["a", "a", "a", "b", "c", "c"]
+            .iter()
+            .map(|line| repeats.push(line))
+            .collect();
+
+        assert_eq!(
+            pushed,
+            [
+                (true, None),
+                (false, None),
+                (false, None),
+                (true, Some(2)),
+                (true, None),
+                (false, None),
+            ]
+        );
+        assert_eq!(repeats.finish(), Some(1));
+        assert_eq!(repeats.finish(), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn custom_prefix() {
+        use super::PrefixRenderer;
+        use crate::run::CommandSystemPlugin;
+        use std::process::Stdio;
+
+        /// `  web |` in front of stdout lines, `  web !` in front of stderr lines
+        struct Columns;
+
+        impl PrefixRenderer for Columns {
+            fn render_prefix(&self, data: &LabeledCommandData, stream: Stream) -> String {
+                let mark = match stream {
+                    Stream::Stdout => '|',
+                    Stream::Stderr => '!',
+                };
+                format!("{:>5} {}", data.label.label(), mark)
+            }
+        }
+
+        let written = Arc::new(Mutex::new(String::new()));
+        let sink = written.clone();
+        let output = Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| {
+                sink.lock()
+                    .unwrap()
+                    .push_str(&String::from_utf8_lossy(bytes))
+            }),
+        );
+        let plugin = CommandSystemLogPlugin {
+            output: Arc::new(output),
+            ..CommandSystemLogPlugin::new()
+        }
+        .prefix_renderer(Some(Arc::new(Columns)));
+        let data = LabeledCommandData::labeled("web");
+
+        let mut child = tokio::process::Command::new("sh")
+            .args(["-c", "echo listening; echo deprecated >&2"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let data = plugin.initialize_command_data(data, stdout, stderr);
+        let exit_status = child.wait().await;
+        plugin.join().unwrap().await.unwrap();
+        plugin.on_command_exited(Arc::new(CommandStopped {
+            data,
+            exit_status,
+            killed: None,
+            ran_for: None,
+        }));
+        plugin.output.flush();
+
+        let written = written.lock().unwrap();
+        let mut lines: Vec<_> = written.lines().collect();
+        // stdout and stderr are read concurrently
+        lines[..2].sort_unstable();
+        assert_eq!(
+            lines,
+            [
+                "  web ! deprecated",
+                "  web | listening",
+                "  web ! exited with status code 0",
+            ]
+        );
     }
 }
@@ -0,0 +1,231 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// The log file of a command run, written while its output is read
+pub struct LogFile {
+    path: PathBuf,
+    max_size: Option<u64>,
+    file: Mutex<OpenLogFile>,
+}
+
+struct OpenLogFile {
+    file: BufWriter<File>,
+    /// bytes in the file, including ones written by earlier runs
+    size: u64,
+}
+
+/// `<file>.1`, where a full log file is moved to
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".1");
+    path.with_file_name(name)
+}
+
+impl LogFile {
+    /// Writes `line` and a newline if it has none.
+    ///
+    /// Rotates the file first if the line would make it larger than its max size.
+    pub fn write_line(&self, line: &[u8]) {
+        let mut open = self.file.lock().unwrap();
+        let newline = !line.ends_with(b"\n");
+        let len = line.len() as u64 + newline as u64;
+        if let Some(max_size) = self.max_size {
+            if open.size > 0 && open.size + len > max_size {
+                if let Err(err) = self.rotate(&mut open) {
+                    eprintln!(
+                        "[runcc][warning] failed to rotate log file {}: {}",
+                        self.path.display(),
+                        err
+                    );
+                }
+            }
+        }
+        let written = open.file.write_all(line).and_then(|_| match newline {
+            true => open.file.write_all(b"\n"),
+            false => Ok(()),
+        });
+        match written {
+            Ok(()) => open.size += len,
+            Err(err) => eprintln!("[runcc][warning] failed to write log file: {}", err),
+        }
+    }
+
+    fn rotate(&self, open: &mut OpenLogFile) -> io::Result<()> {
+        open.file.flush()?;
+        std::fs::rename(&self.path, rotated_path(&self.path))?;
+        open.file = BufWriter::new(File::create(&self.path)?);
+        open.size = 0;
+        Ok(())
+    }
+
+    pub fn flush(&self) {
+        if let Err(err) = self.file.lock().unwrap().file.flush() {
+            eprintln!("[runcc][warning] failed to flush log file: {}", err);
+        }
+    }
+}
+
+/// Tees the output of commands to their [`log_file`](crate::CommandConfig::log_file),
+/// or to `<dir>/<label>.log` if there is a dir, without labels and colors.
+///
+/// A file is truncated when a command is spawned first and appended to by restarts.
+/// It is moved to `<file>.1` once it reaches the max size, if there is one.
+pub struct LogFiles {
+    dir: Option<PathBuf>,
+    max_size: Option<u64>,
+    /// paths of files which were opened before
+    created: Mutex<HashSet<PathBuf>>,
+    /// by label, of the running commands
+    open: Mutex<HashMap<String, Arc<LogFile>>>,
+}
+
+fn log_file_name(label: &str) -> String {
+    let name: String = label
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.log", name)
+}
+
+impl LogFiles {
+    /// Creates `dir` if it doesn't exist
+    pub fn new(dir: Option<PathBuf>) -> io::Result<Self> {
+        if let Some(dir) = &dir {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(Self {
+            dir,
+            max_size: None,
+            created: Default::default(),
+            open: Default::default(),
+        })
+    }
+
+    /// Rotate files once they reach this many bytes,
+    /// see [`RunConfig::log_file_max_size`](crate::RunConfig::log_file_max_size)
+    pub fn max_size(mut self, max_size: Option<u64>) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Opens the log file of a run of the command, `None` if it has none or it can't be opened
+    pub fn command_spawned(&self, label: &str, log_file: Option<&str>) -> Option<Arc<LogFile>> {
+        let path = match (log_file, &self.dir) {
+            (Some(log_file), _) => PathBuf::from(log_file),
+            (None, Some(dir)) => dir.join(log_file_name(label)),
+            (None, None) => return None,
+        };
+        let first_run = self.created.lock().unwrap().insert(path.clone());
+        let file = match path.parent() {
+            Some(parent) if first_run && !parent.as_os_str().is_empty() => {
+                std::fs::create_dir_all(parent)
+            }
+            _ => Ok(()),
+        }
+        .and_then(|_| {
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(first_run)
+                .append(!first_run)
+                .open(&path)
+        });
+        match file {
+            Ok(file) => {
+                let size = file.metadata().map_or(0, |metadata| metadata.len());
+                let file = Arc::new(LogFile {
+                    path,
+                    max_size: self.max_size,
+                    file: Mutex::new(OpenLogFile {
+                        file: BufWriter::new(file),
+                        size,
+                    }),
+                });
+                self.open
+                    .lock()
+                    .unwrap()
+                    .insert(label.to_string(), file.clone());
+                Some(file)
+            }
+            Err(err) => {
+                eprintln!(
+                    "[runcc][warning] failed to open log file {}: {}",
+                    path.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Flushes the log file of the command. It is closed once its output was read to the end.
+    pub fn command_exited(&self, label: &str) {
+        if let Some(file) = self.open.lock().unwrap().remove(label) {
+            file.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{log_file_name, rotated_path, LogFiles};
+
+    #[test]
+    fn append_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = LogFiles::new(Some(dir.path().join("logs"))).unwrap();
+        let path = dir.path().join("logs").join(log_file_name("npm run dev"));
+        std::fs::write(&path, "of a previous run\n").unwrap();
+
+        let file = files.command_spawned("npm run dev", None).unwrap();
+        file.write_line(b"compiling");
+        files.command_exited("npm run dev");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "compiling\n");
+
+        // restarted
+        let file = files.command_spawned("npm run dev", None).unwrap();
+        file.write_line(b"listening\n");
+        drop(file);
+        files.command_exited("npm run dev");
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "compiling\nlistening\n"
+        );
+    }
+
+    #[test]
+    fn rotate_command_log_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = LogFiles::new(None).unwrap().max_size(Some(12));
+        let path = dir.path().join("api").join("out.log");
+        assert!(files.command_spawned("web", None).is_none());
+
+        let log_file = path.to_str().unwrap();
+        let file = files.command_spawned("api", Some(log_file)).unwrap();
+        file.write_line(b"starting");
+        file.write_line(b"ready");
+        file.write_line(b"GET /");
+        files.command_exited("api");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ready\nGET /\n");
+        let rotated = rotated_path(&path);
+        assert_eq!(std::fs::read_to_string(&rotated).unwrap(), "starting\n");
+
+        // a restart continues the file and rotates it once it is full
+        let file = files.command_spawned("api", Some(log_file)).unwrap();
+        file.write_line(b"ready");
+        files.command_exited("api");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ready\n");
+        assert_eq!(std::fs::read_to_string(&rotated).unwrap(), "ready\nGET /\n");
+    }
+}
@@ -0,0 +1,379 @@
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Records aggregate stats of a run, e.g. to track how long a dev environment
+/// takes to start over time, or to scrape them while it runs, see [`MetricsServer`].
+pub struct Metrics {
+    started_at: Instant,
+    state: Mutex<MetricsState>,
+}
+
+#[derive(Default)]
+struct MetricsState {
+    /// in the order the commands were spawned first
+    commands: Vec<CommandMetrics>,
+    indexes: HashMap<String, usize>,
+    running: usize,
+    peak_running: usize,
+}
+
+struct CommandMetrics {
+    label: String,
+    spawn_count: usize,
+    /// output lines of all runs, counted while they are read
+    lines: Arc<AtomicUsize>,
+    /// spawn time of the current run
+    spawned_at: Option<Instant>,
+    /// of the first run which became ready
+    time_to_ready: Option<Duration>,
+    /// of the last run which exited with a code
+    last_exit_code: Option<i32>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct MetricsFile<'a> {
+    total_runtime_ms: u64,
+    peak_concurrent_commands: usize,
+    commands: Vec<CommandMetricsFile<'a>>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct CommandMetricsFile<'a> {
+    label: &'a str,
+    restarts: usize,
+    lines: usize,
+    time_to_ready_ms: Option<u64>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            state: Default::default(),
+        }
+    }
+
+    /// Returns the counter of output lines of the command
+    pub fn spawned(&self, label: &str, at: Instant) -> Arc<AtomicUsize> {
+        let mut state = self.state.lock().unwrap();
+        state.running += 1;
+        state.peak_running = state.peak_running.max(state.running);
+
+        let index = match state.indexes.get(label) {
+            Some(&index) => index,
+            None => {
+                let index = state.commands.len();
+                state.indexes.insert(label.to_string(), index);
+                state.commands.push(CommandMetrics {
+                    label: label.to_string(),
+                    spawn_count: 0,
+                    lines: Default::default(),
+                    spawned_at: None,
+                    time_to_ready: None,
+                    last_exit_code: None,
+                });
+                index
+            }
+        };
+        let command = &mut state.commands[index];
+        command.spawn_count += 1;
+        command.spawned_at = Some(at);
+        command.lines.clone()
+    }
+
+    /// Records how long the run took to become ready, if it became ready,
+    /// and its exit code, `None` if it was killed by a signal
+    pub fn exited(&self, label: &str, ready_at: Option<Instant>, exit_code: Option<i32>) {
+        let mut state = self.state.lock().unwrap();
+        let index = match state.indexes.get(label) {
+            Some(&index) => index,
+            None => return,
+        };
+        let command = &mut state.commands[index];
+        let spawned_at = match command.spawned_at.take() {
+            Some(spawned_at) => spawned_at,
+            None => return,
+        };
+        if command.time_to_ready.is_none() {
+            command.time_to_ready = ready_at.map(|at| at.saturating_duration_since(spawned_at));
+        }
+        command.last_exit_code = exit_code.or(command.last_exit_code);
+        state.running -= 1;
+    }
+
+    /// The metrics of each command at `at` in the Prometheus text format
+    pub fn to_prometheus(&self, at: Instant) -> String {
+        let state = self.state.lock().unwrap();
+        let metrics: [(&str, &str, &str, CommandValue); 5] = [
+            (
+                "runcc_command_running",
+                "gauge",
+                "Whether the command is running",
+                |cmd, _| Some(cmd.spawned_at.is_some() as u8 as f64),
+            ),
+            (
+                "runcc_command_restarts_total",
+                "counter",
+                "How often the command was spawned again",
+                |cmd, _| Some(cmd.spawn_count.saturating_sub(1) as f64),
+            ),
+            (
+                "runcc_command_uptime_seconds",
+                "gauge",
+                "How long the current run of the command is running, 0 if it isn't",
+                |cmd, at| {
+                    let spawned_at = cmd.spawned_at.unwrap_or(at);
+                    Some(at.saturating_duration_since(spawned_at).as_secs_f64())
+                },
+            ),
+            (
+                "runcc_command_output_lines_total",
+                "counter",
+                "Output lines of all runs of the command",
+                |cmd, _| Some(cmd.lines.load(Ordering::Relaxed) as f64),
+            ),
+            (
+                "runcc_command_last_exit_code",
+                "gauge",
+                "Exit code of the last run of the command which exited with one",
+                |cmd, _| cmd.last_exit_code.map(f64::from),
+            ),
+        ];
+
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+            for cmd in &state.commands {
+                if let Some(value) = value(cmd, at) {
+                    let label = escape_label_value(&cmd.label);
+                    let _ = writeln!(text, "{}{{label=\"{}\"}} {}", name, label, value);
+                }
+            }
+        }
+        text
+    }
+
+    /// The metrics of the run until `at`
+    pub fn to_json(&self, at: Instant) -> String {
+        let state = self.state.lock().unwrap();
+        let millis = |duration: Duration| duration.as_millis() as u64;
+        serde_json::to_string_pretty(&MetricsFile {
+            total_runtime_ms: millis(at.saturating_duration_since(self.started_at)),
+            peak_concurrent_commands: state.peak_running,
+            commands: state
+                .commands
+                .iter()
+                .map(|cmd| CommandMetricsFile {
+                    label: &cmd.label,
+                    restarts: cmd.spawn_count.saturating_sub(1),
+                    lines: cmd.lines.load(Ordering::Relaxed),
+                    time_to_ready_ms: cmd.time_to_ready.map(millis),
+                })
+                .collect(),
+        })
+        .expect("metrics should serialize")
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_json(Instant::now()))
+    }
+}
+
+/// The value of a metric of a command at an instant, `None` if it has none
+type CommandValue = fn(&CommandMetrics, Instant) -> Option<f64>;
+
+/// `value` with `\`, `"` and line feeds escaped, for a label in the Prometheus text format
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves [`Metrics`] over HTTP for Prometheus to scrape, see `--metrics-addr`
+pub struct MetricsServer {
+    listener: TcpListener,
+}
+
+impl MetricsServer {
+    /// Listens at `addr`, e.g. `127.0.0.1:9123`
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener: TcpListener::from_std(listener)?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Answers requests for `/metrics` until runcc exits
+    pub fn serve(self, metrics: Arc<Metrics>) {
+        tokio::spawn(async move {
+            loop {
+                let stream = match self.listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        eprintln!(
+                            "[runcc][warning] metrics server stopped accepting connections: {}",
+                            err
+                        );
+                        return;
+                    }
+                };
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    // the client went away
+                    let _ = answer(stream, &metrics).await;
+                });
+            }
+        });
+    }
+}
+
+/// Requests with longer heads are answered without reading them to the end
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// Answers the request of `stream`, the connection is closed afterwards
+async fn answer(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|end| end == b"\r\n\r\n") && head.len() < MAX_REQUEST_HEAD {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let (method, path) = (request_line.next(), request_line.next());
+    let (status, body) = match (method, path) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.to_prometheus(Instant::now())),
+        (Some("GET"), _) => ("404 Not Found", "not found, see /metrics\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "only GET is supported\n".to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::Ordering, Arc};
+    use std::time::{Duration, Instant};
+
+    use super::{Metrics, MetricsServer};
+
+    #[test]
+    fn aggregate_runs() {
+        let metrics = Metrics::new();
+        let at = |ms| metrics.started_at + Duration::from_millis(ms);
+
+        let db_lines = metrics.spawned("db", at(0));
+        let web_lines = metrics.spawned("web", at(5));
+        db_lines.fetch_add(3, Ordering::Relaxed);
+        metrics.exited("web", None, Some(1));
+        // web restarts and becomes ready
+        let restarted_lines = metrics.spawned("web", at(60));
+        restarted_lines.fetch_add(2, Ordering::Relaxed);
+        web_lines.fetch_add(1, Ordering::Relaxed);
+        metrics.exited("web", Some(at(100)), None);
+        metrics.exited("db", Some(at(20)), Some(0));
+        // never spawned, e.g. skipped
+        metrics.exited("migrate", None, None);
+
+        let json: serde_json::Value = serde_json::from_str(&metrics.to_json(at(1500))).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "total_runtime_ms": 1500,
+                "peak_concurrent_commands": 2,
+                "commands": [
+                    {"label": "db", "restarts": 0, "lines": 3, "time_to_ready_ms": 20},
+                    {"label": "web", "restarts": 1, "lines": 3, "time_to_ready_ms": 40},
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn prometheus_text() {
+        let metrics = Metrics::new();
+        let at = |ms| metrics.started_at + Duration::from_millis(ms);
+
+        metrics.spawned("db", at(0)).fetch_add(3, Ordering::Relaxed);
+        metrics.spawned("web \"1\"", at(0));
+        metrics.exited("web \"1\"", None, Some(1));
+        metrics.spawned("web \"1\"", at(500));
+
+        let text = metrics.to_prometheus(at(2000));
+        let samples: Vec<_> = text.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(
+            samples,
+            [
+                r#"runcc_command_running{label="db"} 1"#,
+                r#"runcc_command_running{label="web \"1\""} 1"#,
+                r#"runcc_command_restarts_total{label="db"} 0"#,
+                r#"runcc_command_restarts_total{label="web \"1\""} 1"#,
+                r#"runcc_command_uptime_seconds{label="db"} 2"#,
+                r#"runcc_command_uptime_seconds{label="web \"1\""} 1.5"#,
+                r#"runcc_command_output_lines_total{label="db"} 3"#,
+                r#"runcc_command_output_lines_total{label="web \"1\""} 0"#,
+                r#"runcc_command_last_exit_code{label="web \"1\""} 1"#,
+            ]
+        );
+        assert!(text.contains("# TYPE runcc_command_restarts_total counter\n"));
+    }
+
+    #[tokio::test]
+    async fn serve_metrics() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let metrics = Arc::new(Metrics::new());
+        metrics.spawned("web", Instant::now());
+        let server = MetricsServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        server.serve(metrics);
+
+        let get = |path: &'static str| async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\n\r\n# HELP runcc_command_running"));
+        assert!(response.contains("runcc_command_running{label=\"web\"} 1\n"));
+        assert!(get("/").await.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
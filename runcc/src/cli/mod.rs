@@ -1,9 +1,73 @@
 mod app;
+mod cargo;
+mod ci;
+#[cfg(unix)]
+mod control;
+mod dry_run;
 mod error;
+mod events;
+mod github;
+mod group;
+mod input;
+mod json;
 mod log;
+mod log_files;
+mod metrics;
+#[cfg(feature = "notify-desktop")]
+mod notify;
 mod options;
+mod output;
+#[cfg(unix)]
+mod pause;
+mod pick;
+mod readiness;
+mod report;
+mod requests;
+mod shutdown;
+mod status;
+mod summary;
+mod syscalls;
+#[cfg(feature = "tmux")]
+mod tmux;
+mod trace;
+#[cfg(all(unix, feature = "tui"))]
+mod tui;
 pub use app::*;
 pub use error::*;
+pub use log::PrefixRenderer;
 pub use options::*;
+pub use output::Stream;
 
-pub(self) use log::*;
+use cargo::{expand_commands, CargoMetadata};
+use ci::{CiFormat, CiFormatArg};
+#[cfg(unix)]
+use control::ControlSocket;
+use dry_run::dry_run;
+use events::EventsLog;
+use github::GithubAnnotations;
+use group::OutputGroups;
+use input::control_from_stdin;
+use json::{JsonEvent, OutputFormat};
+use log::*;
+use log_files::{LogFile, LogFiles};
+use metrics::{Metrics, MetricsServer};
+use output::{ColorChoice, Output};
+#[cfg(unix)]
+use pause::pause_on_key;
+#[cfg(all(unix, feature = "tui"))]
+use pause::{read_keys, TerminalMode};
+use pick::{can_pick_commands, pick_commands};
+use readiness::ReadinessNotifier;
+use report::RunReport;
+#[cfg(unix)]
+use requests::ControlResponse;
+use requests::{is_ctl, respond, ControlRequest, ControlledCommands};
+use shutdown::ShutdownProgress;
+use status::StatusBoard;
+use summary::{sort_commands, summary_line, summary_table, tail_of, tap_report};
+use syscalls::{SyscallTracing, Tracer};
+#[cfg(feature = "tmux")]
+use tmux::TmuxWindow;
+use trace::Trace;
+#[cfg(all(unix, feature = "tui"))]
+use tui::Tui;
@@ -0,0 +1,35 @@
+use tokio::process::Command;
+
+use crate::run::CommandSystemSimpleReport;
+
+/// Shows a desktop notification summarizing `report`.
+///
+/// Does nothing in headless environments.
+pub async fn notify_desktop(report: &CommandSystemSimpleReport) {
+    let summary = report.to_string();
+
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification {:?} with title \"runcc\"",
+            summary
+        ));
+        command
+    } else if cfg!(unix) {
+        if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            return;
+        }
+        let mut command = Command::new("notify-send");
+        command.arg("runcc").arg(summary);
+        command
+    } else {
+        return;
+    };
+
+    if let Err(err) = command.status().await {
+        eprintln!(
+            "[runcc][warning] failed to show desktop notification: {}",
+            err
+        );
+    }
+}
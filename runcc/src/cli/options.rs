@@ -1,34 +1,69 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
-use clap::{AppSettings, Parser};
+use clap::Parser;
 
-use super::OptionsError;
-use crate::{read, KillBehavior, RunConfig};
+use super::{
+    expand_commands, CargoMetadata, CiFormat, CiFormatArg, ColorChoice, OptionsError, OutputFormat,
+};
+use crate::{
+    read, ExitCodePolicy, KillBehavior, LabelWidth, OutputBackpressure, OutputTimestamps,
+    RunConfig, Signal, SummaryOrder,
+};
 
 /// Run commands concurrently
 #[derive(Parser)]
 #[clap(version, author, bin_name = "cargo runcc")]
 pub struct Opts {
-    /// Commands to run concurrently
+    /// Commands to run concurrently.
+    ///
+    /// `label:command` labels the command, e.g. "check:cargo check".
+    /// `@bin <name> [args]` and `@example <name> [args]` run a binary or example of the cargo
+    /// workspace with `cargo run -p <package>` in the workspace root, labeled <name>
     command: Vec<String>,
     /// Config file path.
     ///
     /// Can't be used with positional arguments.
+    /// On Unix, SIGHUP reads it again to start new commands, stop removed ones
+    /// and restart changed ones.
     /// See https://github.com/runcc-rs/runcc#usage for details
     #[clap(short, long)]
     config: Option<Option<String>>,
+    /// Add the commands of each config file in this directory, sorted by filename, like
+    /// drop-ins. A file has `commands` and optionally `defaults` for them.
+    ///
+    /// The other options come from the config file, which may be missing then.
+    /// Labels must be unique across all files
+    #[clap(long, conflicts_with = "command")]
+    config_dir: Option<String>,
+    /// Read the config file again whenever it changed, like on SIGHUP. The commands keep
+    /// running with the old config if the new one is invalid
+    #[clap(long, conflicts_with = "command")]
+    watch_config: bool,
     /// Max length to print label in logs
     ///
     /// Defaults to the max length of all labels
     #[clap(long)]
     max_label_length: Option<usize>,
+    /// Width labels are padded to in logs, independent of where they are truncated
+    ///
+    /// `auto` pads to the longest label of the commands which run. Defaults to max_label_length
+    #[clap(long)]
+    label_width: Option<LabelWidth>,
     /// Specify env vars with K=V
     #[clap(short, long)]
     env: Vec<String>,
+    /// Set a variable for `${KEY}` in `program`, `args` and `cwd` of the config, with KEY=VALUE.
+    ///
+    /// Takes precedence over the envs of commands and the config, and the env of runcc
+    #[clap(long = "set", value_name = "KEY=VALUE")]
+    vars: Vec<String>,
     /// What to do after some command exits
     ///
     /// -k None (default)   : do nothing
     ///
+    /// -k WhenAllExited    : wait until all commands exited, killing none
+    ///
     /// -k WhenAnyExited    : kill all commands when any exited
     ///
     /// -k WhenAnySucceeded : kill all commands when any exited with status == 0
@@ -36,30 +71,507 @@ pub struct Opts {
     /// -k WhenAnyFailed    : kill all commands when any exited with status != 0
     ///
     /// -k <NUMBER>         : kill all commands when any exited with status == <NUMBER>
+    ///
+    /// -k "WhenAnyCrashedWithin: <DURATION>" : kill all commands when any exited within
+    /// <DURATION> after it was spawned, e.g. 2s
+    ///
+    /// -k "when_any_exited_with_status: <PATTERN>" : kill all commands when any exited with a
+    /// status matching <PATTERN>, e.g. 1..=2, "{ signal: 9 }", "[1, 3]" or "{ not: 0 }"
     #[clap(short, long)]
     kill: Option<KillBehavior>,
+    /// Signal sent to all commands on Ctrl-C
+    ///
+    /// Defaults to SIGKILL. Use SIGINT for commands which only clean up on interrupt.
+    #[clap(long)]
+    ctrl_c_signal: Option<Signal>,
+    /// Send Ctrl-C to the interactive command instead of shutting down, Ctrl-C twice
+    /// within a second still shuts down. Unix only.
+    #[clap(long)]
+    ctrl_c_to_foreground: bool,
+    /// How long to wait for commands to exit after a signal before killing them forcefully
+    ///
+    /// Defaults to 10s
+    #[clap(long, parse(try_from_str = humantime::parse_duration))]
+    shutdown_timeout: Option<Duration>,
+    /// Send SIGTERM when killing all commands, e.g. because one exited, and kill the
+    /// commands still running forcefully after this duration, e.g. 5s
+    ///
+    /// Without it, commands are killed forcefully right away
+    #[clap(long, parse(try_from_str = humantime::parse_duration))]
+    kill_grace: Option<Duration>,
+    /// Kill all commands once the run took this long, e.g. 10m. They count as failed
+    #[clap(long, parse(try_from_str = humantime::parse_duration))]
+    timeout: Option<Duration>,
+    /// Signal sent instead of SIGTERM with --kill-grace
+    ///
+    /// Without --kill-grace, commands are killed forcefully after the shutdown timeout
+    #[clap(long)]
+    kill_signal: Option<Signal>,
+    /// Spawn commands in process groups of their own and kill the whole group,
+    /// including descendants of the command. Unix only, Windows always kills descendants
+    #[clap(long)]
+    kill_process_groups: bool,
+    /// Write the output of commands to pseudo-terminals instead of pipes, so that they
+    /// print colors and progress like on a terminal. Unix only.
+    #[clap(long)]
+    pty: bool,
+    /// Run at most this many commands at the same time,
+    /// the others are spawned in order as running ones stop
+    #[clap(long, alias = "max-parallel")]
+    max_concurrent: Option<usize>,
+    /// Print consecutive identical output lines only once,
+    /// followed by how many times they were repeated
+    #[clap(long)]
+    collapse_repeats: bool,
+    /// Print indented lines, like frames of a stack trace, together with the line before them,
+    /// so that output of other commands doesn't interleave them
+    #[clap(long)]
+    interleave_guard: bool,
+    /// Print the output of each command as one block once it exited, in the order of the
+    /// commands, instead of interleaving their lines while they run, e.g. for
+    /// `cargo runcc "cargo fmt --check" "cargo clippy" "cargo test"`
+    #[clap(long)]
+    group_output: bool,
+    /// Print the start of a line, e.g. a prompt without a newline, once a command printed
+    /// nothing more for this long, e.g. 200ms
+    #[clap(long, parse(try_from_str = humantime::parse_duration))]
+    partial_line_flush: Option<Duration>,
+    /// Print a time in front of each output line
+    ///
+    /// absolute: UTC time
+    ///
+    /// relative: seconds since the run started
+    ///
+    /// per_command_relative: seconds since the command was spawned
+    #[clap(long)]
+    timestamps: Option<OutputTimestamps>,
+    /// Print the seconds since the run started in front of each output line,
+    /// short for --timestamps relative
+    #[clap(long, conflicts_with = "timestamps")]
+    elapsed: bool,
+    /// When to color labels and lines
+    ///
+    /// auto (default): on a terminal, unless NO_COLOR is set
+    ///
+    /// always: also when piped, e.g. into `less -R`
+    ///
+    /// never: no colors
+    #[clap(long)]
+    color: Option<ColorChoice>,
+    /// Print no colors, short for --color never
+    #[clap(long, conflicts_with = "color")]
+    no_color: bool,
+    /// Print nothing for commands which succeeded without any output
+    #[clap(long)]
+    hide_silent_commands: bool,
+    /// What happens to output while the terminal doesn't read it, e.g. while piped into `less`
+    ///
+    /// block (default): commands wait until the terminal reads, like with a full pipe
+    ///
+    /// drop: lines which don't fit in the output queue are dropped
+    ///
+    /// buffer: lines which don't fit in the output queue are kept in memory
+    #[clap(long)]
+    output_backpressure: Option<OutputBackpressure>,
+    /// Keep at most this many bytes of output in memory while it is deferred or waits
+    /// for the terminal, the oldest lines are dropped beyond that
+    #[clap(long)]
+    max_buffer_memory: Option<usize>,
+    /// Keep the last <TAIL> output lines of each command and print them after the output of
+    /// a command when it failed, e.g. to find why one crashed among the output of others
+    #[clap(long, value_name = "TAIL")]
+    tail: Option<usize>,
+    /// Give the command with this label the terminal, e.g. an interactive REPL,
+    /// so that it reads stdin and gets Ctrl-C and Ctrl-Z instead of runcc (Unix only)
+    #[clap(long)]
+    foreground: Option<String>,
+    /// Let the command with this label, or at this index, read the stdin of runcc,
+    /// e.g. a REPL or a command asking for input
+    #[clap(long, alias = "stdin")]
+    interactive: Option<String>,
+    /// Only run the commands of these profiles of the config
+    #[clap(long)]
+    profile: Vec<String>,
+    /// Only run commands with any of these tags
+    #[clap(long)]
+    tag: Vec<String>,
+    /// Don't run commands with any of these tags
+    #[clap(long)]
+    exclude_tag: Vec<String>,
+    /// Run all commands of the config file instead of asking which ones to run in a terminal
+    #[clap(long)]
+    no_pick: bool,
+    /// Fail without running any command if --tag, --exclude-tag or --profile leave out
+    /// commands, and fail the run if any command is skipped because a command it runs after
+    /// failed, also with an --exit-code which would ignore the skip. Lists which ones and why
+    #[clap(long)]
+    no_skips: bool,
+    /// Write a span per command to this file in Chrome tracing format
+    #[clap(long)]
+    trace: Option<String>,
+    /// Write aggregate stats of the run as JSON to this file when all commands stopped:
+    /// total runtime, peak concurrent commands and restarts, output lines
+    /// and time to ready of each command
+    #[clap(long)]
+    metrics_out: Option<String>,
+    /// Serve metrics of each command over HTTP at /metrics on this address while the commands
+    /// run, e.g. 127.0.0.1:9123, for Prometheus to scrape: whether it runs, restarts,
+    /// uptime of the current run, output lines and last exit code
+    #[clap(long)]
+    metrics_addr: Option<String>,
+    /// Write the output lines of each command, without labels, to `<label>.log` in this
+    /// directory as well, unless they have a `log_file`. The files are truncated when runcc
+    /// starts and appended to by restarts
+    #[clap(long)]
+    log_dir: Option<String>,
+    /// Move log files of commands to `<file>.1` once they reach this many bytes
+    /// and start new ones
+    #[clap(long)]
+    log_file_max_size: Option<u64>,
+    /// Run each command under strace (Linux) or dtrace (macOS)
+    /// and write its syscalls to a file in this directory
+    #[clap(long)]
+    trace_syscalls: Option<String>,
+    /// Print an error annotation for each failed command when all commands stopped
+    /// and append a job summary to $GITHUB_STEP_SUMMARY, for GitHub Actions
+    #[clap(long)]
+    github_annotations: bool,
+    /// Print the output of each command at once when it was read to the end,
+    /// in a collapsible section of the CI log
+    ///
+    /// github: GitHub Actions groups
+    ///
+    /// gitlab: GitLab CI sections
+    ///
+    /// auto: detected from $GITHUB_ACTIONS and $GITLAB_CI, not grouped outside of CI
+    #[clap(long)]
+    ci_format: Option<CiFormatArg>,
+    /// How to print the output of commands, defaults to text
+    ///
+    /// text: lines prefixed with the label of their command
+    ///
+    /// json: one JSON object per line on stdout when a command started, for each output line
+    /// and when a command exited, with its label and a timestamp
+    ///
+    /// tap: text on stderr, and on stdout once all commands stopped an `ok` or `not ok` line
+    /// per command in the Test Anything Protocol
+    #[clap(long, alias = "log-format")]
+    format: Option<OutputFormat>,
+    /// Write the config and how each command stopped as JSON to this file
+    /// when all commands stopped: its command line, when it started and ended, its exit
+    /// status, restarts and why runcc killed it, e.g. for CI to publish which command failed
+    #[clap(long)]
+    report: Option<String>,
+    /// Run the commands of a report written with --report again
+    #[clap(long, conflicts_with_all = &["command", "config"])]
+    replay: Option<String>,
+    /// Write the state of each command as JSON to this file on SIGUSR1,
+    /// like `docker compose ps`
+    #[clap(long)]
+    status_json: Option<String>,
+    /// Listen for requests to list, restart or kill commands or to stop all of them on this
+    /// Unix domain socket, e.g. .runcc.sock. Send them with `cargo runcc ctl`, e.g.
+    /// `cargo runcc ctl restart web`, or as lines of JSON, e.g. {"command":"restart","label":"web"}
+    #[clap(long)]
+    control_socket: Option<String>,
+    /// Read control lines from stdin: `restart <label>`, `kill <label>`, `send <label> <text>`
+    /// or `<label>: <text>` to write a line to the stdin of a command. Any other line is written
+    /// to the stdin of all running commands. Not with --pausable or commands reading stdin
+    #[clap(long)]
+    control_stdin: bool,
+    /// Write lifecycle events of commands (spawned, ready, restarted, exited, killed)
+    /// and of runcc (shutdown, stopped) to this file, one JSON object per line
+    #[clap(long)]
+    events_log: Option<String>,
+    /// Write `ready` to this file once all commands are ready, i.e. the command of
+    /// system_ready_when or all commands with ready_when or a startup probe.
+    /// E.g. /dev/fd/3 to notify a supervisor through a file descriptor
+    #[clap(long)]
+    ready_file: Option<String>,
+    /// Add the working directory of commands to the label of their output lines
+    #[clap(long)]
+    show_cwd: bool,
+    /// Pause the output when space is pressed and resume it on the next press,
+    /// while commands keep running. Only on Unix, if stdin is a terminal
+    /// and no command reads it
+    #[clap(long)]
+    pausable: bool,
+    /// Don't print the summary once all commands stopped, neither its table nor its last line
+    #[clap(short, long)]
+    quiet: bool,
+    /// Don't print the summary once all commands stopped, like --quiet, but still print the
+    /// last output lines of failed commands
+    #[clap(long)]
+    no_summary: bool,
+    /// The order of commands in the summary and the --report, defaults to outcome
+    ///
+    /// outcome: those which succeeded first, then killed and failed ones
+    ///
+    /// status: those which failed first, then killed and succeeded ones, each by label
+    ///
+    /// config, exit, label: in the order of the config, in the order they exited,
+    /// alphabetically by label
+    ///
+    /// duration: the longest running first
+    #[clap(long)]
+    summary_order: Option<SummaryOrder>,
+    /// Which exit code runcc exits with when commands failed
+    ///
+    /// first-failure (default): the code of the command which failed first
+    ///
+    /// last: the code of the command which stopped on its own last, 0 if it succeeded
+    ///
+    /// success-only: 1
+    #[clap(long)]
+    exit_code: Option<ExitCodePolicy>,
+    /// Run each command in its own pane of a new tmux window (needs the tmux feature)
+    #[clap(long)]
+    tmux: bool,
+    /// Show the output of each command in its own pane of a terminal UI, with its state,
+    /// and restart or kill commands by key. Unix only, if stdin and stdout are a terminal
+    /// (needs the tui feature)
+    #[clap(long)]
+    tui: bool,
+    /// Show a desktop notification when all commands stopped (needs the notify-desktop feature)
+    #[clap(long)]
+    notify: bool,
+    /// Send READY=1 to the socket in NOTIFY_SOCKET once all commands are ready like
+    /// --ready-file, for systemd services with Type=notify (Unix only, needs the sd-notify
+    /// feature)
+    #[clap(long)]
+    sd_notify: bool,
+    /// Print the resolved config as TOML and exit without running commands
+    #[clap(long)]
+    print_config: bool,
+    /// Print what each command would run, e.g. wrapped in its shell, with its cwd, envs and
+    /// prerequisites, and where its program is found on PATH, without running commands.
+    /// Prints JSON with --format json. Fails if any program is missing
+    #[clap(long)]
+    dry_run: bool,
 }
 
 impl Opts {
+    pub fn print_config(&self) -> bool {
+        self.print_config
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn trace(&self) -> Option<&str> {
+        self.trace.as_deref()
+    }
+
+    pub fn metrics_out(&self) -> Option<&str> {
+        self.metrics_out.as_deref()
+    }
+
+    pub fn metrics_addr(&self) -> Option<&str> {
+        self.metrics_addr.as_deref()
+    }
+
+    pub fn log_dir(&self) -> Option<&str> {
+        self.log_dir.as_deref()
+    }
+
+    pub fn trace_syscalls(&self) -> Option<&str> {
+        self.trace_syscalls.as_deref()
+    }
+
+    pub fn github_annotations(&self) -> bool {
+        self.github_annotations
+    }
+
+    pub fn ci_format(&self) -> Option<CiFormat> {
+        self.ci_format.and_then(CiFormatArg::resolve)
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format.unwrap_or_default()
+    }
+
+    pub fn color(&self) -> ColorChoice {
+        match self.no_color {
+            true => ColorChoice::Never,
+            false => self.color.unwrap_or_default(),
+        }
+    }
+
+    /// Whether commands were given, replayed or selected by tags,
+    /// instead of all commands of a config file
+    pub fn selects_commands(&self) -> bool {
+        !self.command.is_empty()
+            || !self.profile.is_empty()
+            || !self.tag.is_empty()
+            || !self.exclude_tag.is_empty()
+            || self.replay.is_some()
+    }
+
+    /// Whether runcc may ask which commands of the config file to run
+    pub fn picks_commands(&self) -> bool {
+        !self.no_pick && !self.selects_commands()
+    }
+
+    /// Whether the commands come from a config file, not from positional arguments
+    pub fn reads_config_file(&self) -> bool {
+        self.command.is_empty()
+    }
+
+    pub fn watch_config(&self) -> bool {
+        self.watch_config
+    }
+
+    /// The path of the config file the commands come from, if they come from one
+    pub fn config_file(&self) -> Option<String> {
+        if !self.reads_config_file() {
+            return None;
+        }
+        // without -c, the default file is searched like with -c
+        let config = self.config.as_ref().and_then(Option::as_deref);
+        let found = read::find_config_file::<crate::RunConfigInput>(config, "runcc");
+        found.ok().map(|data| data.filename)
+    }
+
+    pub fn report(&self) -> Option<&str> {
+        self.report.as_deref()
+    }
+
+    pub fn replay(&self) -> Option<&str> {
+        self.replay.as_deref()
+    }
+
+    pub fn status_json(&self) -> Option<&str> {
+        self.status_json.as_deref()
+    }
+
+    pub fn control_socket(&self) -> Option<&str> {
+        self.control_socket.as_deref()
+    }
+
+    pub fn control_stdin(&self) -> bool {
+        self.control_stdin
+    }
+
+    pub fn events_log(&self) -> Option<&str> {
+        self.events_log.as_deref()
+    }
+
+    pub fn ready_file(&self) -> Option<&str> {
+        self.ready_file.as_deref()
+    }
+
+    pub fn show_cwd(&self) -> bool {
+        self.show_cwd
+    }
+
+    pub fn pausable(&self) -> bool {
+        self.pausable
+    }
+
+    pub fn quiet(&self) -> bool {
+        self.quiet
+    }
+
+    pub fn no_summary(&self) -> bool {
+        self.no_summary
+    }
+
+    pub fn tmux(&self) -> bool {
+        self.tmux
+    }
+
+    pub fn tui(&self) -> bool {
+        self.tui
+    }
+
+    pub fn notify(&self) -> bool {
+        self.notify
+    }
+
+    pub fn sd_notify(&self) -> bool {
+        self.sd_notify
+    }
+
+    /// The options given which need a feature runcc was built without, with that feature.
+    /// They are parsed anyway, so that the options are the same with all features.
+    pub fn without_feature(&self) -> Vec<(&'static str, &'static str)> {
+        let options = [
+            ("--tmux", "tmux", self.tmux, cfg!(feature = "tmux")),
+            ("--tui", "tui", self.tui, cfg!(feature = "tui")),
+            (
+                "--notify",
+                "notify-desktop",
+                self.notify,
+                cfg!(feature = "notify-desktop"),
+            ),
+            (
+                "--sd-notify",
+                "sd-notify",
+                self.sd_notify,
+                cfg!(feature = "sd-notify"),
+            ),
+        ];
+        options
+            .iter()
+            .filter(|(_, _, given, built)| *given && !built)
+            .map(|(option, feature, _, _)| (*option, *feature))
+            .collect()
+    }
+
     pub fn try_into_config(self) -> Result<RunConfig, OptionsError> {
         use crate::{CommandConfigInput, CommandConfigsInput, RunConfigInput};
 
         let Self {
             command: commands,
             config,
+            config_dir,
             max_label_length,
+            label_width,
             env,
+            vars,
             kill,
+            ctrl_c_signal,
+            ctrl_c_to_foreground,
+            shutdown_timeout,
+            kill_grace,
+            timeout,
+            kill_signal,
+            kill_process_groups,
+            pty,
+            control_stdin,
+            max_concurrent,
+            collapse_repeats,
+            interleave_guard,
+            group_output,
+            partial_line_flush,
+            timestamps,
+            elapsed,
+            hide_silent_commands,
+            output_backpressure,
+            summary_order,
+            exit_code,
+            max_buffer_memory,
+            tail,
+            log_file_max_size,
+            foreground,
+            interactive,
+            profile,
+            tag,
+            exclude_tag,
+            no_skips,
+            ..
         } = self;
+        let timestamps = timestamps.or_else(|| elapsed.then_some(OutputTimestamps::Relative));
 
-        let envs = if env.len() > 0 {
+        let envs = if !env.is_empty() {
             let envs: HashMap<String, String> = env
                 .into_iter()
                 .map(|env| {
                     let (kv, program) = crate::env::match_one_env(&env);
 
                     if let Some(kv) = kv {
-                        if program == "" {
+                        if program.is_empty() {
                             Ok((kv.0.to_string(), kv.1.to_string()))
                         } else {
                             Err(env)
@@ -69,13 +581,22 @@ impl Opts {
                     }
                 })
                 .collect::<Result<_, _>>()
-                .or_else(|invalid_env| Err(OptionsError::EnvSyntaxError(invalid_env)))?;
+                .map_err(OptionsError::EnvSyntaxError)?;
 
             Some(envs)
         } else {
             None
         };
 
+        let vars: HashMap<String, String> = vars
+            .into_iter()
+            .map(|var| match var.split_once('=') {
+                Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+                _ => Err(var),
+            })
+            .collect::<Result<_, _>>()
+            .map_err(OptionsError::VarSyntaxError)?;
+
         // if no commands and no config are given, act as if -c was passed, i.e.
         // default to searching for the default runcc.* file in the local
         // directory
@@ -84,32 +605,109 @@ impl Opts {
             _ => (commands, config),
         };
 
-        if commands.len() > 0 {
+        let mut config = if !commands.is_empty() {
             if config.is_some() {
                 return Err(OptionsError::DuplicateConfigs);
             }
+            let commands = expand_commands(commands, CargoMetadata::read)?;
+            // labeled before the config is built, so that labels pad to the longest one
+            let commands = commands.into_iter().map(|command| {
+                let script = CommandConfigInput::Command(command.script);
+                if command.label.is_none() && command.cwd.is_none() {
+                    return script;
+                }
+                let mut config = script.into_config(&Default::default());
+                config.label = command.label.or(config.label);
+                config.cwd = command.cwd.or(config.cwd);
+                CommandConfigInput::CommandConfig(Box::new(config))
+            });
 
-            Ok(RunConfigInput {
-                commands: CommandConfigsInput::Commands(
-                    commands
-                        .into_iter()
-                        .map(CommandConfigInput::Command)
-                        .collect(),
-                ),
+            let mut config: RunConfig = RunConfigInput {
+                commands: CommandConfigsInput::Commands(commands.collect()),
+                before: vec![],
+                after: vec![],
+                defaults: Default::default(),
                 max_label_length,
+                label_width,
                 kill: kill.unwrap_or_default(),
+                exit_when_only_optional_remain: false,
+                fail_on_skip: no_skips,
+                profiles: Default::default(),
                 envs,
+                env_file: None,
+                computed_env: Default::default(),
+                secrets: None,
                 windows_call_cmd_with_env: Default::default(),
+                ctrl_c_signal,
+                ctrl_c_to_foreground,
+                shutdown_timeout,
+                kill_grace,
+                timeout,
+                kill_signal,
+                kill_process_groups,
+                pty,
+                pipe_stdin: control_stdin,
+                collapse_repeats,
+                interleave_guard,
+                group_output,
+                partial_line_flush,
+                timestamps,
+                defer_output_until_started: false,
+                output_backpressure: output_backpressure.unwrap_or_default(),
+                summary_order: summary_order.unwrap_or_default(),
+                exit_code: exit_code.unwrap_or_default(),
+                max_buffer_memory,
+                log_file_max_size,
+                foreground,
+                history_limit: None,
+                tail_lines: tail,
+                max_concurrent,
+                mode: None,
+                early_exit_backoff: None,
+                system_ready_when: None,
+                color_seed: None,
+                restart_separator: Default::default(),
+                show_silent_commands: !hide_silent_commands,
+                colorize_log_levels: false,
+                log_level_colors: Default::default(),
             }
-            .into())
-        } else if let Some(config) = config {
-            let data: read::ConfigFileData<RunConfigInput> =
-                read::find_config_file(config.as_ref().and_then(|s| Some(s.as_str())), "runcc")
-                    .or_else(|err| Err(OptionsError::ConfigFileError(err)))?;
+            .into();
 
-            eprintln!("[runcc][info] using config file {:?}", data.filename);
+            if let Some(interactive) = &interactive {
+                config
+                    .make_interactive(interactive)
+                    .map_err(OptionsError::InvalidConfig)?;
+            }
 
-            let mut config: RunConfig = data.data.into();
+            config.validate().map_err(OptionsError::InvalidConfig)?;
+
+            config
+        } else if let Some(config) = config {
+            let mut parent_dir = None;
+            let input = match read::find_config_file(config.as_deref(), "runcc") {
+                Ok(data) => {
+                    eprintln!("[runcc][info] using config file {:?}", data.filename);
+                    parent_dir = data.parent_dir;
+                    data.data
+                }
+                // the commands may all come from the directory
+                Err(read::error::FindConfigError::NoFileMatch { .. })
+                    if config.is_none() && config_dir.is_some() =>
+                {
+                    RunConfigInput::empty()
+                }
+                Err(err) => return Err(OptionsError::ConfigFileError(err)),
+            };
+            let input = match &config_dir {
+                Some(dir) => with_fragments(input, dir)?,
+                None => input,
+            };
+
+            let mut config: RunConfig = input.into();
+            // a config file found in a parent applies to its directory, like Cargo.toml
+            if let Some(dir) = &parent_dir {
+                config.resolve_paths_in(dir);
+            }
 
             if let Some(envs) = envs {
                 eprintln!("[runcc][warning] env vars from cli args will be appended to envs from config file");
@@ -127,6 +725,13 @@ impl Opts {
                 }
             }
 
+            if let Some(label_width) = label_width {
+                if config.label_width != Some(label_width) {
+                    eprintln!("[runcc][warning] label_width from cli args will override the value from config file");
+                    config.label_width = Some(label_width);
+                }
+            }
+
             if let Some(kill) = kill {
                 if kill != config.kill {
                     eprintln!("[runcc][warning] kill from cli args will override the value from config file");
@@ -134,16 +739,206 @@ impl Opts {
                 }
             }
 
-            Ok(config)
+            if let Some(ctrl_c_signal) = ctrl_c_signal {
+                if config.ctrl_c_signal != Some(ctrl_c_signal) {
+                    eprintln!("[runcc][warning] ctrl_c_signal from cli args will override the value from config file");
+                    config.ctrl_c_signal = Some(ctrl_c_signal);
+                }
+            }
+
+            if ctrl_c_to_foreground {
+                config.ctrl_c_to_foreground = true;
+            }
+
+            if let Some(shutdown_timeout) = shutdown_timeout {
+                if config.shutdown_timeout != Some(shutdown_timeout) {
+                    eprintln!("[runcc][warning] shutdown_timeout from cli args will override the value from config file");
+                    config.shutdown_timeout = Some(shutdown_timeout);
+                }
+            }
+
+            if let Some(kill_grace) = kill_grace {
+                if config.kill_grace != Some(kill_grace) {
+                    eprintln!("[runcc][warning] kill_grace from cli args will override the value from config file");
+                    config.kill_grace = Some(kill_grace);
+                }
+            }
+
+            if let Some(timeout) = timeout {
+                if config.timeout != Some(timeout) {
+                    eprintln!("[runcc][warning] timeout from cli args will override the value from config file");
+                    config.timeout = Some(timeout);
+                }
+            }
+
+            if kill_process_groups {
+                config.kill_process_groups = true;
+            }
+
+            if pty {
+                config.pty = true;
+            }
+
+            if control_stdin {
+                config.pipe_stdin = true;
+            }
+
+            if let Some(kill_signal) = kill_signal {
+                if config.kill_signal != Some(kill_signal) {
+                    eprintln!("[runcc][warning] kill_signal from cli args will override the value from config file");
+                    config.kill_signal = Some(kill_signal);
+                }
+            }
+
+            if let Some(max_concurrent) = max_concurrent {
+                if config.max_concurrent != Some(max_concurrent) {
+                    eprintln!("[runcc][warning] max_concurrent from cli args will override the value from config file");
+                    config.max_concurrent = Some(max_concurrent);
+                }
+            }
+
+            if collapse_repeats {
+                config.collapse_repeats = true;
+            }
+
+            if interleave_guard {
+                config.interleave_guard = true;
+            }
+
+            if group_output {
+                config.group_output = true;
+            }
+
+            if let Some(partial_line_flush) = partial_line_flush {
+                if config.partial_line_flush != Some(partial_line_flush) {
+                    eprintln!("[runcc][warning] partial_line_flush from cli args will override the value from config file");
+                    config.partial_line_flush = Some(partial_line_flush);
+                }
+            }
+
+            if let Some(timestamps) = timestamps {
+                if config.timestamps != Some(timestamps) {
+                    eprintln!("[runcc][warning] timestamps from cli args will override the value from config file");
+                    config.timestamps = Some(timestamps);
+                }
+            }
+
+            if hide_silent_commands {
+                config.show_silent_commands = false;
+            }
+
+            if no_skips {
+                config.fail_on_skip = true;
+            }
+
+            if let Some(summary_order) = summary_order {
+                if config.summary_order != summary_order {
+                    eprintln!("[runcc][warning] summary_order from cli args will override the value from config file");
+                    config.summary_order = summary_order;
+                }
+            }
+
+            if let Some(exit_code) = exit_code {
+                if config.exit_code != exit_code {
+                    eprintln!("[runcc][warning] exit_code from cli args will override the value from config file");
+                    config.exit_code = exit_code;
+                }
+            }
+
+            if let Some(output_backpressure) = output_backpressure {
+                if config.output_backpressure != output_backpressure {
+                    eprintln!("[runcc][warning] output_backpressure from cli args will override the value from config file");
+                    config.output_backpressure = output_backpressure;
+                }
+            }
+
+            if let Some(max_buffer_memory) = max_buffer_memory {
+                if config.max_buffer_memory != Some(max_buffer_memory) {
+                    eprintln!("[runcc][warning] max_buffer_memory from cli args will override the value from config file");
+                    config.max_buffer_memory = Some(max_buffer_memory);
+                }
+            }
+
+            if let Some(tail) = tail {
+                if config.tail_lines != Some(tail) {
+                    eprintln!("[runcc][warning] tail_lines from cli args will override the value from config file");
+                    config.tail_lines = Some(tail);
+                }
+            }
+
+            if let Some(log_file_max_size) = log_file_max_size {
+                if config.log_file_max_size != Some(log_file_max_size) {
+                    eprintln!("[runcc][warning] log_file_max_size from cli args will override the value from config file");
+                    config.log_file_max_size = Some(log_file_max_size);
+                }
+            }
+
+            if let Some(foreground) = foreground {
+                if config.foreground.as_ref() != Some(&foreground) {
+                    eprintln!("[runcc][warning] foreground from cli args will override the value from config file");
+                    config.foreground = Some(foreground);
+                }
+            }
+
+            config.expand_vars(&vars);
+
+            if let Some(interactive) = &interactive {
+                let others = config
+                    .make_interactive(interactive)
+                    .map_err(OptionsError::InvalidConfig)?;
+                if others {
+                    eprintln!("[runcc][warning] interactive from cli args will override the value from config file");
+                }
+            }
+
+            config.validate().map_err(OptionsError::InvalidConfig)?;
+
+            config
         } else {
-            Err(OptionsError::NoConfigs)
+            return Err(OptionsError::NoConfigs);
+        };
+
+        let mut skipped = config
+            .select_profiles(&profile)
+            .map_err(OptionsError::InvalidConfig)?;
+        skipped.extend(config.filter_tags(&tag, &exclude_tag));
+        if config.fail_on_skip && !skipped.is_empty() {
+            return Err(OptionsError::CommandsSkipped(skipped));
+        }
+
+        if config.commands.is_empty() {
+            return Err(OptionsError::NoCommandsMatchTags);
         }
+
+        Ok(config)
     }
 }
 
+/// Adds the commands of the config fragments in `dir` to `input`, see `--config-dir`
+fn with_fragments(
+    mut input: crate::RunConfigInput,
+    dir: &str,
+) -> Result<crate::RunConfigInput, OptionsError> {
+    let fragments: Vec<read::ConfigFileData<crate::ConfigFragmentInput>> =
+        read::read_config_dir(std::path::Path::new(dir))
+            .map_err(|err| OptionsError::ConfigFileError(err.into()))?;
+
+    for read::ConfigFileData { filename, data, .. } in fragments {
+        eprintln!("[runcc][info] using config fragment {:?}", filename);
+        let commands = data.into_configs(&input.script_options());
+        input
+            .extend_commands(commands)
+            .map_err(|error| OptionsError::InvalidFragment {
+                file: filename,
+                error,
+            })?;
+    }
+    Ok(input)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Opts;
+    use super::{ColorChoice, OptionsError, Opts, OutputFormat, OutputTimestamps};
     use clap::Parser;
 
     #[test]
@@ -151,4 +946,118 @@ mod tests {
         let opts = Opts::parse_from(["test", "--env", "A=a", "--env", "B=1"]);
         assert_eq!(opts.env, ["A=a", "B=1"]);
     }
+
+    #[test]
+    fn parse_log_format() {
+        let opts = Opts::parse_from(["test", "--log-format", "json"]);
+        assert_eq!(opts.format(), OutputFormat::Json);
+    }
+
+    #[test]
+    fn label_positional_commands() {
+        let opts = Opts::parse_from(["test", "web:npm run dev --watch", "api:cargo run"]);
+        let config = opts.try_into_config().unwrap();
+        let labels: Vec<_> = config.commands.iter().map(|c| c.resolved_label()).collect();
+        assert_eq!(labels, ["web", "api"]);
+        // labels pad to the longest label, not to the longest command
+        assert_eq!(config.max_label_length, 3);
+    }
+
+    #[test]
+    fn pick_commands_unless_selected() {
+        let picks = |args: &[&str]| Opts::parse_from(args).picks_commands();
+        assert!(picks(&["test"]));
+        assert!(picks(&["test", "--watch-config"]));
+        assert!(!picks(&["test", "--no-pick"]));
+        assert!(!picks(&["test", "--tag", "web"]));
+        assert!(!picks(&["test", "npm start"]));
+    }
+
+    #[test]
+    fn parse_stdin() {
+        let opts = Opts::parse_from(["test", "--stdin", "rm -ri tmp", "ls", "rm -ri tmp"]);
+        assert_eq!(opts.interactive.as_deref(), Some("rm -ri tmp"));
+    }
+
+    #[test]
+    fn parse_elapsed() {
+        let opts = Opts::parse_from(["test", "--elapsed", "npm start"]);
+        let config = opts.try_into_config().unwrap();
+        assert_eq!(config.timestamps, Some(OutputTimestamps::Relative));
+        assert!(Opts::try_parse_from(["test", "--elapsed", "--timestamps", "absolute"]).is_err());
+    }
+
+    #[test]
+    fn parse_color() {
+        assert_eq!(Opts::parse_from(["test", "ls"]).color(), ColorChoice::Auto);
+        let opts = Opts::parse_from(["test", "--color", "always", "ls"]);
+        assert_eq!(opts.color(), ColorChoice::Always);
+        let opts = Opts::parse_from(["test", "--no-color", "ls"]);
+        assert_eq!(opts.color(), ColorChoice::Never);
+        assert!(Opts::try_parse_from(["test", "--color", "sometimes", "ls"]).is_err());
+        assert!(Opts::try_parse_from(["test", "--no-color", "--color", "always"]).is_err());
+    }
+
+    #[test]
+    fn parse_set_vars() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("runcc.yml");
+        let yaml =
+            "envs: { PORT: \"3000\" }\ncommands:\n  - { program: serve, args: [\"${PORT}\"] }\n";
+        std::fs::write(&path, yaml).unwrap();
+        let path = path.to_str().unwrap();
+        let args = |config: Result<crate::RunConfig, _>| config.unwrap().commands[0].args.clone();
+
+        let config = Opts::parse_from(["test", "-c", path]).try_into_config();
+        assert_eq!(args(config).unwrap(), ["3000"]);
+        let config = Opts::parse_from(["test", "-c", path, "--set", "PORT=8080"]).try_into_config();
+        assert_eq!(args(config).unwrap(), ["8080"]);
+        let config = Opts::parse_from(["test", "-c", path, "--set", "PORT"]).try_into_config();
+        assert!(matches!(config, Err(OptionsError::VarSyntaxError(_))));
+    }
+
+    #[test]
+    fn merge_config_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = dir.path().to_str().unwrap();
+        let main = dir.path().join("runcc.yml");
+        let yaml = format!("commands:\n  db: postgres\ndefaults:\n  cwd: {}\n", cwd);
+        std::fs::write(&main, yaml).unwrap();
+        let fragments = dir.path().join("runcc.d");
+        std::fs::create_dir(&fragments).unwrap();
+        std::fs::write(
+            fragments.join("20-web.toml"),
+            "[commands.web]\nprogram = \"npm\"\nargs = [\"start\"]\ncwd = \".\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            fragments.join("10-api.yaml"),
+            "commands:\n  - cargo run\n  - cargo watch\ndefaults:\n  envs: { RUST_LOG: info }\n",
+        )
+        .unwrap();
+        std::fs::write(fragments.join("README.md"), "not a config").unwrap();
+
+        let read = |fragments: &std::path::Path| {
+            let (main, fragments) = (main.to_str().unwrap(), fragments.to_str().unwrap());
+            Opts::parse_from(["test", "-c", main, "--config-dir", fragments]).try_into_config()
+        };
+        let config = read(&fragments).unwrap();
+
+        let labels: Vec<_> = config.commands.iter().map(|c| c.resolved_label()).collect();
+        assert_eq!(labels, ["db", "cargo run", "cargo watch", "web"]);
+        let api = &config.commands[1];
+        assert_eq!(api.cwd.as_deref(), Some(cwd));
+        assert_eq!(
+            api.envs,
+            Some(vec![("RUST_LOG".to_string(), "info".to_string())])
+        );
+        assert_eq!(config.commands[3].cwd.as_deref(), Some("."));
+        assert_eq!(config.max_label_length, "cargo watch".len());
+
+        std::fs::write(fragments.join("30-db.yaml"), "commands:\n  db: mysqld\n").unwrap();
+        assert!(matches!(
+            read(&fragments),
+            Err(OptionsError::InvalidFragment { file, .. }) if file.ends_with("30-db.yaml")
+        ));
+    }
 }
@@ -0,0 +1,719 @@
+use std::{
+    collections::VecDeque,
+    io::{self, IsTerminal, Write},
+    sync::{Arc, Condvar, Mutex},
+};
+use tokio::sync::Semaphore;
+
+use crate::OutputBackpressure;
+
+/// How many writes of command output may wait for the terminal before
+/// [`OutputBackpressure`] applies
+const QUEUE_CAPACITY: usize = 1024;
+
+/// How many bytes of command output are held while the output is paused,
+/// unless [`Output::max_buffer_memory`] limits it
+const PAUSED_BUFFER_MEMORY: usize = 16 * 1024 * 1024;
+
+/// Moves the cursor of a terminal to the start of the previous line and clears it
+pub(super) const REPLACE_LAST_LINE: &[u8] = b"\x1b[1A\r\x1b[2K";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+
+    /// Whether the stream of runcc is a terminal
+    pub fn is_terminal(&self) -> bool {
+        match self {
+            Stream::Stdout => io::stdout().is_terminal(),
+            Stream::Stderr => io::stderr().is_terminal(),
+        }
+    }
+
+    /// Columns of the terminal of the stream, 80 if it is no terminal
+    pub fn width(&self) -> usize {
+        #[cfg(unix)]
+        {
+            let fd = match self {
+                Stream::Stdout => libc::STDOUT_FILENO,
+                Stream::Stderr => libc::STDERR_FILENO,
+            };
+            let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+            if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+                return size.ws_col as usize;
+            }
+        }
+        80
+    }
+}
+
+/// Whether `NO_COLOR` asks not to color output
+fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+}
+
+/// When runcc colors labels and lines, see `--color`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// On streams which are a terminal, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether output written to `stream` is colored
+    pub fn colors(self, stream: Stream) -> bool {
+        match self {
+            ColorChoice::Auto => stream.is_terminal() && !no_color(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(format!(
+                "unknown color choice {:?}, expected auto, always or never",
+                s
+            )),
+        }
+    }
+}
+
+fn write_stream(stream: Stream, bytes: &[u8]) {
+    match stream {
+        Stream::Stdout => {
+            let mut stdout = io::stdout().lock();
+            let _ = stdout.write_all(bytes);
+            let _ = stdout.flush();
+        }
+        Stream::Stderr => {
+            let _ = io::stderr().lock().write_all(bytes);
+        }
+    }
+}
+
+type Sink = Box<dyn Fn(Stream, &[u8]) + Send + Sync>;
+
+/// A sink shared with a plugin, which writes the output instead of stdout and stderr
+pub(super) type SharedSink = Arc<dyn Fn(Stream, &[u8]) + Send + Sync>;
+
+/// output held back, in the order it was written
+type Deferred = VecDeque<Queued>;
+
+#[derive(Default)]
+struct Held {
+    items: Deferred,
+    /// until [`Output::start`]
+    until_started: bool,
+    /// see [`Output::pause`]
+    paused: bool,
+}
+
+impl Held {
+    fn holds(&self) -> bool {
+        self.until_started || self.paused
+    }
+}
+
+struct Queued {
+    stream: Stream,
+    bytes: Vec<u8>,
+    /// Whether this holds a permit of [`Shared::space`]
+    permit: bool,
+    /// Whether this is output of a command, which counts for the memory budget
+    command: bool,
+    /// The key of a progress line, which replaces the line written before it
+    /// if that was a progress line with the same key
+    progress: Option<String>,
+}
+
+#[derive(Default)]
+struct Queue {
+    items: VecDeque<Queued>,
+    /// Whether the writer thread is writing an item it took from `items`
+    writing: bool,
+    /// Lines of commands dropped since the last write which fit in the queue
+    dropped: usize,
+    /// Bytes of command output in `items` and in deferred output
+    buffered: usize,
+    /// Oldest lines of commands evicted since the last write to stay within the memory budget
+    evicted: usize,
+    /// Set when the [`Output`] is dropped, the writer thread stops once `items` are written
+    closed: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    /// Notified when items are queued and when the queue ran empty
+    changed: Condvar,
+    /// Free slots for command output in the queue
+    space: Semaphore,
+    sink: Sink,
+}
+
+impl Shared {
+    /// Writes queued output to the sink on its own thread,
+    /// so that a terminal which doesn't read only blocks this thread
+    fn write_queued(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        // key of the progress line written last, if nothing was written after it
+        let mut last_progress = None;
+        loop {
+            match queue.items.pop_front() {
+                Some(item) => {
+                    // written in front of the output of commands which was kept
+                    let mut evicted = 0;
+                    if item.command {
+                        queue.buffered -= item.bytes.len();
+                        evicted = std::mem::take(&mut queue.evicted);
+                    }
+                    queue.writing = true;
+                    drop(queue);
+                    if evicted > 0 {
+                        let lines = if evicted == 1 { "line" } else { "lines" };
+                        let notice = format!(
+                            "[runcc][warning] dropped {} {} of output to stay within max_buffer_memory\n",
+                            evicted, lines
+                        );
+                        (self.sink)(Stream::Stderr, notice.as_bytes());
+                        last_progress = None;
+                    }
+                    if item.progress.is_some() && item.progress == last_progress {
+                        (self.sink)(item.stream, REPLACE_LAST_LINE);
+                    }
+                    (self.sink)(item.stream, &item.bytes);
+                    last_progress = item.progress;
+                    if item.permit {
+                        self.space.add_permits(1);
+                    }
+                    queue = self.queue.lock().unwrap();
+                    queue.writing = false;
+                }
+                None if queue.closed => return,
+                None => {
+                    self.changed.notify_all();
+                    queue = self.changed.wait(queue).unwrap();
+                }
+            }
+        }
+    }
+
+    /// Queues a notice of how many lines were dropped, if any
+    fn push_dropped(&self, queue: &mut Queue) {
+        if queue.dropped > 0 {
+            let dropped = std::mem::take(&mut queue.dropped);
+            let lines = if dropped == 1 { "line" } else { "lines" };
+            let notice = format!(
+                "[runcc][warning] dropped {} {} of output which the terminal didn't read in time\n",
+                dropped, lines
+            );
+            queue.items.push_back(Queued {
+                stream: Stream::Stderr,
+                bytes: notice.into_bytes(),
+                permit: false,
+                command: false,
+                progress: None,
+            });
+            self.changed.notify_all();
+        }
+    }
+
+    fn push(&self, queue: &mut Queue, item: Queued) {
+        self.push_dropped(queue);
+        queue.items.push_back(item);
+        self.changed.notify_all();
+    }
+
+    /// Evicts the oldest output of commands, first from the queue, then from `deferred`,
+    /// until `len` more bytes fit in `max` bytes or nothing is left to evict
+    fn make_room(
+        &self,
+        queue: &mut Queue,
+        mut deferred: Option<&mut Deferred>,
+        len: usize,
+        max: usize,
+    ) {
+        while queue.buffered + len > max {
+            let evicted = match queue.items.iter().position(|item| item.command) {
+                Some(i) => queue.items.remove(i),
+                None => deferred.as_deref_mut().and_then(|deferred| {
+                    let i = deferred.iter().position(|item| item.command)?;
+                    deferred.remove(i)
+                }),
+            };
+            let evicted = match evicted {
+                Some(evicted) => evicted,
+                None => return,
+            };
+
+            queue.buffered -= evicted.bytes.len();
+            queue.evicted += 1;
+            if evicted.permit {
+                self.space.add_permits(1);
+            }
+        }
+    }
+}
+
+/// Where runcc prints output of commands, optionally held back until all commands started
+/// or while it is paused.
+///
+/// Output is written by a thread of its own, so that commands and runcc keep running
+/// while the terminal doesn't read, see [`OutputBackpressure`].
+pub struct Output {
+    held: Mutex<Held>,
+    backpressure: OutputBackpressure,
+    /// How many bytes of command output may wait to be written, see [`Output::max_buffer_memory`]
+    max_buffer_memory: Option<usize>,
+    shared: Arc<Shared>,
+}
+
+impl Output {
+    pub fn new(defer_until_started: bool, backpressure: OutputBackpressure) -> Self {
+        Self::with_sink(defer_until_started, backpressure, Box::new(write_stream))
+    }
+
+    pub(super) fn with_sink(
+        defer_until_started: bool,
+        backpressure: OutputBackpressure,
+        sink: Sink,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Default::default(),
+            changed: Condvar::new(),
+            space: Semaphore::new(QUEUE_CAPACITY),
+            sink,
+        });
+        let writer = shared.clone();
+        std::thread::spawn(move || writer.write_queued());
+
+        Self {
+            held: Mutex::new(Held {
+                until_started: defer_until_started,
+                ..Default::default()
+            }),
+            backpressure,
+            max_buffer_memory: None,
+            shared,
+        }
+    }
+
+    /// Limits how many bytes of command output, deferred or waiting for the terminal,
+    /// are kept in memory. The oldest output is dropped beyond that, with a warning.
+    pub fn max_buffer_memory(mut self, max_buffer_memory: Option<usize>) -> Self {
+        self.max_buffer_memory = max_buffer_memory;
+        self
+    }
+
+    /// Writes output of runcc itself, which is never held back by backpressure
+    pub fn write(&self, stream: Stream, bytes: &[u8]) {
+        let item = Queued {
+            stream,
+            bytes: bytes.to_vec(),
+            permit: false,
+            command: false,
+            progress: None,
+        };
+        let mut held = self.held.lock().unwrap();
+        if held.holds() {
+            held.items.push_back(item);
+        } else {
+            let mut queue = self.shared.queue.lock().unwrap();
+            self.shared.push(&mut queue, item);
+        }
+    }
+
+    pub fn write_line(&self, stream: Stream, line: &str) {
+        self.write(stream, format!("{}\n", line).as_bytes())
+    }
+
+    /// Writes output of a command, which waits or is dropped while the queue is full,
+    /// depending on the [`OutputBackpressure`]
+    pub async fn write_command_output(&self, stream: Stream, bytes: Vec<u8>) {
+        self.write_command_item(stream, bytes, None).await
+    }
+
+    /// Writes a line of a command with its progress, which replaces the line written
+    /// before it on a terminal if that was a progress line with the same `key`
+    pub async fn write_command_progress(&self, stream: Stream, key: &str, line: &str) {
+        let bytes = format!("{}\n", line).into_bytes();
+        self.write_command_item(stream, bytes, Some(key.to_string()))
+            .await
+    }
+
+    async fn write_command_item(&self, stream: Stream, bytes: Vec<u8>, progress: Option<String>) {
+        {
+            let mut held = self.held.lock().unwrap();
+            if held.holds() {
+                let mut queue = self.shared.queue.lock().unwrap();
+                let max = match held.paused {
+                    true => Some(self.max_buffer_memory.unwrap_or(PAUSED_BUFFER_MEMORY)),
+                    false => self.max_buffer_memory,
+                };
+                if let Some(max) = max {
+                    self.shared
+                        .make_room(&mut queue, Some(&mut held.items), bytes.len(), max);
+                }
+                queue.buffered += bytes.len();
+                held.items.push_back(Queued {
+                    stream,
+                    bytes,
+                    permit: false,
+                    command: true,
+                    progress,
+                });
+                return;
+            }
+        }
+
+        let permit = match self.backpressure {
+            OutputBackpressure::Block => {
+                let permit = self.shared.space.acquire().await;
+                permit.expect("output queue is never closed").forget();
+                true
+            }
+            OutputBackpressure::Drop => match self.shared.space.try_acquire() {
+                Ok(permit) => {
+                    permit.forget();
+                    true
+                }
+                Err(_) => {
+                    self.shared.queue.lock().unwrap().dropped += 1;
+                    return;
+                }
+            },
+            OutputBackpressure::Buffer => false,
+        };
+        let mut queue = self.shared.queue.lock().unwrap();
+        if let Some(max) = self.max_buffer_memory {
+            self.shared.make_room(&mut queue, None, bytes.len(), max);
+        }
+        queue.buffered += bytes.len();
+        let item = Queued {
+            stream,
+            bytes,
+            permit,
+            command: true,
+            progress,
+        };
+        self.shared.push(&mut queue, item);
+    }
+
+    pub async fn write_command_line(&self, stream: Stream, line: &str) {
+        self.write_command_output(stream, format!("{}\n", line).into_bytes())
+            .await
+    }
+
+    /// Writes deferred output in the order it was written and stops deferring,
+    /// unless the output is paused
+    pub fn start(&self) {
+        let mut held = self.held.lock().unwrap();
+        held.until_started = false;
+        self.release(&mut held);
+    }
+
+    /// Holds back all output until [`resume`](Self::resume), e.g. while the user reads.
+    ///
+    /// Commands keep running. Their oldest output is dropped beyond
+    /// [`max_buffer_memory`](Self::max_buffer_memory), or 16 MiB if it is unlimited.
+    pub fn pause(&self) {
+        self.held.lock().unwrap().paused = true;
+    }
+
+    /// Writes the output held since [`pause`](Self::pause), unless it is deferred
+    pub fn resume(&self) {
+        let mut held = self.held.lock().unwrap();
+        held.paused = false;
+        self.release(&mut held);
+    }
+
+    fn release(&self, held: &mut Held) {
+        if held.holds() {
+            return;
+        }
+        let mut queue = self.shared.queue.lock().unwrap();
+        for item in held.items.drain(..) {
+            self.shared.push(&mut queue, item);
+        }
+    }
+
+    /// Blocks until all queued output was written, including how many lines were dropped
+    pub fn flush(&self) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        self.shared.push_dropped(&mut queue);
+        while !queue.items.is_empty() || queue.writing {
+            queue = self.shared.changed.wait(queue).unwrap();
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::Duration;
+
+    use super::{Output, Stream, QUEUE_CAPACITY};
+    use crate::OutputBackpressure;
+
+    #[test]
+    fn defer_until_started() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let sink = written.clone();
+        let output = Output::with_sink(
+            true,
+            OutputBackpressure::Block,
+            Box::new(move |stream, bytes| {
+                sink.lock()
+                    .unwrap()
+                    .push((stream, String::from_utf8_lossy(bytes).into_owned()))
+            }),
+        );
+
+        output.write_line(Stream::Stdout, "[web] starting");
+        output.write_line(Stream::Stderr, "[api] warning");
+        assert!(written.lock().unwrap().is_empty());
+
+        output.start();
+        output.write_line(Stream::Stdout, "[web] ready");
+        output.flush();
+
+        assert_eq!(
+            *written.lock().unwrap(),
+            [
+                (Stream::Stdout, "[web] starting\n".to_string()),
+                (Stream::Stderr, "[api] warning\n".to_string()),
+                (Stream::Stdout, "[web] ready\n".to_string()),
+            ]
+        );
+    }
+
+    /// An output whose terminal doesn't read until the returned sender is dropped
+    fn blocked_output(
+        backpressure: OutputBackpressure,
+    ) -> (Output, mpsc::Sender<()>, Arc<Mutex<Vec<String>>>) {
+        let (unblock, blocked) = mpsc::channel::<()>();
+        let blocked = Mutex::new(blocked);
+        let written = Arc::new(Mutex::new(vec![]));
+        let sink = written.clone();
+        let output = Output::with_sink(
+            false,
+            backpressure,
+            Box::new(move |_, bytes| {
+                let _ = blocked.lock().unwrap().recv();
+                sink.lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(bytes).into_owned())
+            }),
+        );
+        (output, unblock, written)
+    }
+
+    async fn write_lines(output: &Output, count: usize) {
+        for i in 0..count {
+            output
+                .write_command_line(Stream::Stdout, &format!("[web] {}", i))
+                .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn blocked_terminal() {
+        let timeout = Duration::from_secs(5);
+
+        let (output, unblock, written) = blocked_output(OutputBackpressure::Drop);
+        tokio::time::timeout(timeout, write_lines(&output, 2 * QUEUE_CAPACITY))
+            .await
+            .expect("commands should not wait for a blocked terminal");
+        output.write_line(Stream::Stderr, "[runcc] web exited");
+        drop(unblock);
+        output.flush();
+        let written = written.lock().unwrap().clone();
+        assert_eq!(written.len(), QUEUE_CAPACITY + 2);
+        assert_eq!(
+            written[QUEUE_CAPACITY - 1],
+            format!("[web] {}\n", QUEUE_CAPACITY - 1)
+        );
+        assert_eq!(written[QUEUE_CAPACITY], "[runcc][warning] dropped 1024 lines of output which the terminal didn't read in time\n");
+        assert_eq!(written[QUEUE_CAPACITY + 1], "[runcc] web exited\n");
+
+        let (output, unblock, written) = blocked_output(OutputBackpressure::Buffer);
+        tokio::time::timeout(timeout, write_lines(&output, 2 * QUEUE_CAPACITY))
+            .await
+            .expect("commands should not wait for a blocked terminal");
+        drop(unblock);
+        output.flush();
+        assert_eq!(written.lock().unwrap().len(), 2 * QUEUE_CAPACITY);
+
+        let (output, unblock, written) = blocked_output(OutputBackpressure::Block);
+        let blocked = tokio::time::timeout(
+            Duration::from_millis(100),
+            write_lines(&output, 2 * QUEUE_CAPACITY),
+        )
+        .await;
+        assert!(
+            blocked.is_err(),
+            "commands should wait for a blocked terminal"
+        );
+        output.write_line(Stream::Stderr, "[runcc] still running");
+        drop(unblock);
+        output.flush();
+        let written = written.lock().unwrap();
+        assert_eq!(written.len(), QUEUE_CAPACITY + 1);
+        assert_eq!(written[QUEUE_CAPACITY], "[runcc] still running\n");
+    }
+
+    #[tokio::test]
+    async fn max_buffer_memory() {
+        // each line is 8 bytes, so 4 lines fit
+        let max = Some(32);
+
+        let (output, unblock, written) = blocked_output(OutputBackpressure::Buffer);
+        let output = output.max_buffer_memory(max);
+        output.write_line(Stream::Stderr, "[runcc] web started");
+        write_lines(&output, 10).await;
+        output.write_line(Stream::Stderr, "[runcc] web exited");
+        drop(unblock);
+        output.flush();
+        assert_eq!(
+            *written.lock().unwrap(),
+            [
+                "[runcc] web started\n",
+                "[runcc][warning] dropped 6 lines of output to stay within max_buffer_memory\n",
+                "[web] 6\n",
+                "[web] 7\n",
+                "[web] 8\n",
+                "[web] 9\n",
+                "[runcc] web exited\n",
+            ]
+        );
+
+        let written = Arc::new(Mutex::new(vec![]));
+        let sink = written.clone();
+        let output = Output::with_sink(
+            true,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| {
+                sink.lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(bytes).into_owned())
+            }),
+        )
+        .max_buffer_memory(max);
+        write_lines(&output, 6).await;
+        output.start();
+        output.flush();
+        let written = written.lock().unwrap();
+        assert_eq!(written.len(), 5);
+        assert_eq!(
+            written[0],
+            "[runcc][warning] dropped 2 lines of output to stay within max_buffer_memory\n"
+        );
+        assert_eq!(written[1], "[web] 2\n");
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume() {
+        let written = Arc::new(Mutex::new(vec![]));
+        let sink = written.clone();
+        let output = Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| {
+                sink.lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(bytes).into_owned())
+            }),
+        )
+        .max_buffer_memory(Some(32));
+
+        write_lines(&output, 1).await;
+        output.flush();
+        output.pause();
+        // more than the queue holds, commands don't wait while paused
+        let timeout = Duration::from_secs(5);
+        tokio::time::timeout(timeout, write_lines(&output, 2 * QUEUE_CAPACITY))
+            .await
+            .expect("commands should not wait while the output is paused");
+        output.write_line(Stream::Stderr, "[runcc] web exited");
+        output.flush();
+        assert_eq!(*written.lock().unwrap(), ["[web] 0\n"]);
+
+        output.resume();
+        output.flush();
+        let written = written.lock().unwrap();
+        assert_eq!(
+            *written,
+            [
+                "[web] 0\n",
+                "[runcc][warning] dropped 2046 lines of output to stay within max_buffer_memory\n",
+                "[web] 2046\n",
+                "[web] 2047\n",
+                "[runcc] web exited\n",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn progress_replaces_last_line() {
+        let written = Arc::new(Mutex::new(String::new()));
+        let sink = written.clone();
+        let output = Output::with_sink(
+            false,
+            OutputBackpressure::Block,
+            Box::new(move |_, bytes| {
+                sink.lock()
+                    .unwrap()
+                    .push_str(&String::from_utf8_lossy(bytes))
+            }),
+        );
+
+        output
+            .write_command_progress(Stream::Stdout, "build", "[build] 10%")
+            .await;
+        output
+            .write_command_progress(Stream::Stdout, "build", "[build] 20%")
+            .await;
+        output
+            .write_command_progress(Stream::Stdout, "test", "[test ] 50%")
+            .await;
+        output
+            .write_command_line(Stream::Stdout, "[test ] ok")
+            .await;
+        output
+            .write_command_progress(Stream::Stdout, "build", "[build] 30%")
+            .await;
+        output.flush();
+
+        assert_eq!(
+            *written.lock().unwrap(),
+            "[build] 10%\n\x1b[1A\r\x1b[2K[build] 20%\n[test ] 50%\n[test ] ok\n[build] 30%\n"
+        );
+    }
+}
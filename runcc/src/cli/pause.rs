@@ -0,0 +1,71 @@
+//! Pauses the output on a key press, see `--pausable`.
+
+use std::io::{self, IsTerminal, Read};
+use std::sync::Arc;
+
+use super::{Output, Stream};
+
+const PAUSE_KEY: u8 = b' ';
+
+/// Restores the mode of the terminal on stdin when dropped
+pub struct TerminalMode(libc::termios);
+
+impl Drop for TerminalMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+    }
+}
+
+/// Makes the terminal on stdin send keys without Enter and not echo them until the returned
+/// mode is dropped. Ctrl-C still sends a signal. `None` if stdin is no terminal.
+pub fn read_keys() -> Option<TerminalMode> {
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+            return None;
+        }
+        let original = TerminalMode(termios);
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+            return None;
+        }
+        Some(original)
+    }
+}
+
+/// Pauses `output` each time space is pressed on the terminal on stdin and resumes it
+/// on the next press. Commands keep running, see [`Output::pause`].
+///
+/// Keys are read until the returned mode is dropped, see [`read_keys`].
+pub fn pause_on_key(output: Arc<Output>) -> Option<TerminalMode> {
+    let mode = read_keys()?;
+
+    // blocks on stdin until runcc exits
+    std::thread::spawn(move || {
+        let mut paused = false;
+        let mut key = [0u8];
+        while let Ok(1) = io::stdin().lock().read(&mut key) {
+            if key[0] != PAUSE_KEY {
+                continue;
+            }
+            paused = !paused;
+            if paused {
+                output.write_line(
+                    Stream::Stderr,
+                    "[runcc][info] output paused, press space to resume",
+                );
+                output.pause();
+            } else {
+                output.resume();
+            }
+        }
+    });
+
+    Some(mode)
+}
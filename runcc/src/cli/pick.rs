@@ -0,0 +1,106 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::RunConfig;
+
+/// Whether runcc can ask which commands to run
+pub fn can_pick_commands() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+/// Parses 1-based numbers separated by spaces or commas into indexes of commands.
+///
+/// Empty input picks all commands.
+fn parse_selection(input: &str, count: usize) -> Result<Vec<usize>, String> {
+    let mut picked = vec![];
+    for number in input.split([' ', ',']).filter(|s| !s.is_empty()) {
+        match number.parse::<usize>() {
+            Ok(n) if (1..=count).contains(&n) => {
+                if !picked.contains(&(n - 1)) {
+                    picked.push(n - 1);
+                }
+            }
+            _ => return Err(format!("{} is not a number from 1 to {}", number, count)),
+        }
+    }
+
+    if picked.is_empty() {
+        Ok((0..count).collect())
+    } else {
+        Ok(picked)
+    }
+}
+
+/// Keep only the commands at `picked`, in their configured order
+fn keep_picked(config: &mut RunConfig, picked: &[usize]) {
+    let mut i = 0;
+    config.commands.retain(|_| {
+        i += 1;
+        picked.contains(&(i - 1))
+    });
+}
+
+/// Lists the commands of `config` and keeps the ones the user picks on stdin.
+///
+/// Returns the labels of the picked commands, or `None` if all were picked.
+pub fn pick_commands(config: &mut RunConfig) -> io::Result<Option<Vec<String>>> {
+    let mut stderr = io::stderr();
+    writeln!(stderr, "[runcc] pick commands to run:")?;
+    for (i, cmd) in config.commands.iter().enumerate() {
+        writeln!(stderr, "  {}) {}", i + 1, cmd.resolved_label())?;
+    }
+
+    let count = config.commands.len();
+    let stdin = io::stdin();
+    loop {
+        write!(
+            stderr,
+            "[runcc] numbers separated by spaces, empty for all: "
+        )?;
+        stderr.flush()?;
+
+        let mut input = String::new();
+        if stdin.lock().read_line(&mut input)? == 0 {
+            return Ok(None);
+        }
+
+        match parse_selection(input.trim(), count) {
+            Ok(picked) if picked.len() == count => return Ok(None),
+            Ok(picked) => {
+                keep_picked(config, &picked);
+                let labels = config.commands.iter().map(|cmd| cmd.resolved_label());
+                return Ok(Some(labels.collect()));
+            }
+            Err(err) => writeln!(stderr, "[runcc] {}", err)?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{keep_picked, parse_selection};
+    use crate::{RunConfig, RunConfigInput};
+
+    #[test]
+    fn pick_commands_by_number() {
+        let labels = |selection: &str| {
+            let input: RunConfigInput =
+                serde_yaml::from_str("commands: [web, api, worker]").unwrap();
+            let mut config: RunConfig = input.into();
+            let picked = parse_selection(selection, config.commands.len())?;
+            keep_picked(&mut config, &picked);
+            Ok::<_, String>(
+                config
+                    .commands
+                    .iter()
+                    .map(|cmd| cmd.resolved_label())
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        assert_eq!(labels("3, 1").unwrap(), ["web", "worker"]);
+        assert_eq!(labels("2 2").unwrap(), ["api"]);
+        assert_eq!(labels("").unwrap(), ["web", "api", "worker"]);
+        assert_eq!(labels("4").unwrap_err(), "4 is not a number from 1 to 3");
+        assert!(labels("web").is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! Tells a supervisor of runcc once all commands are ready, see `--ready-file` and `--sd-notify`
+
+use std::future::Future;
+use std::path::PathBuf;
+
+/// Where to report that all commands are ready,
+/// see [`CommandSystem::wait_all_ready`](crate::run::CommandSystem::wait_all_ready)
+#[derive(Debug, Default)]
+pub struct ReadinessNotifier {
+    ready_file: Option<PathBuf>,
+    /// The socket of `sd_notify(3)`, a path or an abstract name starting with `@`
+    #[cfg(all(unix, feature = "sd-notify"))]
+    notify_socket: Option<std::ffi::OsString>,
+}
+
+impl ReadinessNotifier {
+    /// Write `ready` to this file, e.g. `/dev/fd/3` to notify through a file descriptor
+    pub fn ready_file(mut self, path: Option<PathBuf>) -> Self {
+        self.ready_file = path;
+        self
+    }
+
+    /// Send `READY=1` to this socket like `sd_notify(3)`,
+    /// e.g. `NOTIFY_SOCKET` of a systemd service with `Type=notify`
+    #[cfg(all(unix, feature = "sd-notify"))]
+    pub fn notify_socket(mut self, socket: Option<std::ffi::OsString>) -> Self {
+        self.notify_socket = socket;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        #[cfg(all(unix, feature = "sd-notify"))]
+        if self.notify_socket.is_some() {
+            return false;
+        }
+        self.ready_file.is_none()
+    }
+
+    /// Reports readiness once `ready` resolves, failures are only warned about.
+    /// Nothing is reported if `ready` fails with why the commands never become ready.
+    pub async fn notify_when(self, ready: impl Future<Output = Result<(), String>>) {
+        if let Err(err) = ready.await {
+            eprintln!("[runcc][warning] readiness isn't reported: {}", err);
+            return;
+        }
+
+        if let Some(path) = &self.ready_file {
+            if let Err(err) = std::fs::write(path, "ready\n") {
+                eprintln!(
+                    "[runcc][warning] failed to write ready file {}: {}",
+                    path.display(),
+                    err
+                );
+            }
+        }
+
+        #[cfg(all(unix, feature = "sd-notify"))]
+        if let Some(socket) = &self.notify_socket {
+            if let Err(err) = sd_notify(socket, "READY=1") {
+                eprintln!(
+                    "[runcc][warning] failed to notify {:?} of readiness: {}",
+                    socket, err
+                );
+            }
+        }
+    }
+}
+
+/// Sends `state` to the socket of systemd like `sd_notify(3)`
+#[cfg(all(unix, feature = "sd-notify"))]
+fn sd_notify(socket: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let datagram = UnixDatagram::unbound()?;
+    match socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            datagram.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+
+    use super::ReadinessNotifier;
+
+    #[tokio::test]
+    async fn write_ready_file_once_ready() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ready");
+        let (tx, rx) = oneshot::channel::<()>();
+
+        let notifier = ReadinessNotifier::default().ready_file(Some(path.clone()));
+        let notified = tokio::spawn(notifier.notify_when(async {
+            let _ = rx.await;
+            Ok(())
+        }));
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!path.exists());
+
+        tx.send(()).unwrap();
+        notified.await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ready\n");
+    }
+
+    #[tokio::test]
+    async fn no_ready_file_if_never_ready() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ready");
+
+        let notifier = ReadinessNotifier::default().ready_file(Some(path.clone()));
+        notifier
+            .notify_when(async { Err("command[db] stopped before it was ready".to_string()) })
+            .await;
+        assert!(!path.exists());
+    }
+
+    #[cfg(feature = "sd-notify")]
+    #[tokio::test]
+    async fn sd_notify_once_ready() {
+        use std::os::unix::net::UnixDatagram;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let (tx, rx) = oneshot::channel::<()>();
+
+        let notifier = ReadinessNotifier::default().notify_socket(Some(path.into()));
+        let notified = tokio::spawn(notifier.notify_when(async {
+            let _ = rx.await;
+            Ok(())
+        }));
+
+        let mut buf = [0; 64];
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(socket.recv(&mut buf).is_err(), "notified before ready");
+
+        tx.send(()).unwrap();
+        notified.await.unwrap();
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+    }
+}
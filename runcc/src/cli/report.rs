@@ -0,0 +1,353 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io,
+    path::Path,
+    sync::{Arc, Mutex, Weak},
+    time::SystemTime,
+};
+
+use crate::run::{
+    kill_reason, CommandOutcome, CommandStopped, CommandSystemObserver, LabeledCommandData,
+};
+use crate::RunConfig;
+
+/// How a command of a run stopped
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CommandReport {
+    pub label: String,
+    /// The program and args, e.g. `sh -c 'npm start'` for commands given as a string,
+    /// or the script of the command
+    #[serde(default)]
+    pub command: String,
+    /// `succeeded`, `failed` or `killed`
+    pub outcome: String,
+    pub exit_code: Option<i32>,
+    /// The signal which terminated the last run, on Unix
+    #[serde(default)]
+    pub signal: Option<i32>,
+    /// Why the command has no exit status, e.g. it was skipped or failed to spawn
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Why runcc killed the last run, e.g. `command[db] exited`
+    #[serde(default)]
+    pub kill_reason: Option<String>,
+    /// When the last run was spawned, RFC 3339
+    #[serde(default)]
+    pub started_at: Option<String>,
+    /// When the last run exited, RFC 3339
+    #[serde(default)]
+    pub ended_at: Option<String>,
+    /// How long the last run took
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    /// How often the command was restarted since its last run of a
+    /// [`repeat`](crate::CommandConfig::repeat) or a schedule began
+    #[serde(default)]
+    pub restarts: usize,
+}
+
+type Stopped = CommandStopped<LabeledCommandData, LabeledCommandData>;
+
+/// A run of a command seen by a [`RunRecorder`]
+#[derive(Debug)]
+struct CommandRun {
+    started_at: SystemTime,
+    ended_at: Option<SystemTime>,
+    /// How it exited, which ends a command in the report if it stopped for good
+    exited: Weak<Stopped>,
+}
+
+/// Records when the commands of a [`RunReport`] are spawned and exit,
+/// by their index in the config
+pub struct RunRecorder(Arc<Mutex<HashMap<usize, Vec<CommandRun>>>>);
+
+impl CommandSystemObserver<LabeledCommandData> for RunRecorder {
+    fn on_command_started(&self, data: &LabeledCommandData, _pid: Option<u32>) {
+        let mut runs = self.0.lock().unwrap();
+        runs.entry(data.index).or_default().push(CommandRun {
+            started_at: SystemTime::now(),
+            ended_at: None,
+            exited: Weak::new(),
+        });
+    }
+
+    fn on_command_exited(&self, cmd: &Arc<Stopped>) {
+        let mut runs = self.0.lock().unwrap();
+        // commands which were skipped or failed to spawn have no run
+        let run = runs
+            .get_mut(&cmd.data.index)
+            .and_then(|runs| runs.last_mut());
+        if let Some(run) = run.filter(|run| run.ended_at.is_none()) {
+            run.ended_at = Some(SystemTime::now());
+            run.exited = Arc::downgrade(cmd);
+        }
+    }
+}
+
+/// A run written with `--report`, which `--replay` runs again
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RunReport {
+    /// The config the commands ran with, before secrets and computed envs were resolved,
+    /// so that the report contains no secrets and they are resolved again on replay
+    pub config: serde_json::Value,
+    pub commands: Vec<CommandReport>,
+    /// The command lines of the commands, in the order of the config
+    #[serde(skip)]
+    command_lines: Vec<String>,
+    #[serde(skip)]
+    runs: Arc<Mutex<HashMap<usize, Vec<CommandRun>>>>,
+}
+
+impl RunReport {
+    /// Captures `config` before it runs
+    pub fn new(config: &RunConfig) -> serde_json::Result<Self> {
+        let plan = config.plan();
+        let planned: HashMap<_, _> = plan
+            .stages
+            .iter()
+            .flatten()
+            .map(|cmd| (cmd.label.as_str(), cmd))
+            .collect();
+        let command_lines = config
+            .commands
+            .iter()
+            .map(|cmd| match planned.get(cmd.resolved_label().as_str()) {
+                Some(planned) if planned.spawned.is_empty() => {
+                    planned.script.clone().unwrap_or_default()
+                }
+                Some(planned) => planned
+                    .spawned
+                    .iter()
+                    .map(|word| crate::config::quote(word))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                None => String::new(),
+            })
+            .collect();
+        Ok(Self {
+            config: serde_json::to_value(config)?,
+            commands: vec![],
+            command_lines,
+            runs: Default::default(),
+        })
+    }
+
+    /// Records the runs of the commands for the report, run it next to the plugin of the system
+    pub fn recorder(&self) -> RunRecorder {
+        RunRecorder(self.runs.clone())
+    }
+
+    /// Records how the commands of the run stopped, `history` are all commands which stopped,
+    /// see [`CommandSystem::history`](crate::run::CommandSystem::history)
+    pub fn stopped(&mut self, commands: &[Arc<Stopped>], history: &[Arc<Stopped>]) {
+        let runs = self.runs.lock().unwrap();
+        let time = |time: Option<SystemTime>| {
+            time.map(|time| humantime::format_rfc3339_millis(time).to_string())
+        };
+        // e.g. a run of a repeated command, not one which was restarted
+        let stopped_by = |run: &CommandRun| {
+            let exited = run.exited.as_ptr();
+            history.iter().any(|cmd| Arc::as_ptr(cmd) == exited)
+        };
+        self.commands = commands
+            .iter()
+            .map(|cmd| {
+                let label = cmd.data.label.label();
+                let index = cmd.data.index;
+                // the runs since the previous run of a repeated or scheduled command
+                let runs = runs.get(&index).map(Vec::as_slice).unwrap_or_default();
+                let end = runs
+                    .iter()
+                    .position(|run| run.exited.as_ptr() == Arc::as_ptr(cmd));
+                let start = end.map_or(0, |end| {
+                    let previous = runs[..end].iter().rposition(stopped_by);
+                    previous.map_or(0, |previous| previous + 1)
+                });
+                let last = end.map(|end| &runs[end]);
+                CommandReport {
+                    label: label.to_string(),
+                    command: self.command_lines.get(index).cloned().unwrap_or_default(),
+                    outcome: match cmd.outcome() {
+                        CommandOutcome::Succeeded => "succeeded",
+                        CommandOutcome::Failed => "failed",
+                        CommandOutcome::Killed => "killed",
+                    }
+                    .to_string(),
+                    exit_code: cmd.exit_status.as_ref().ok().and_then(|s| s.code()),
+                    signal: cmd.exit_status.as_ref().ok().and_then(super::json::signal),
+                    error: cmd.exit_status.as_ref().err().map(ToString::to_string),
+                    kill_reason: kill_reason(cmd).map(ToString::to_string),
+                    started_at: time(last.map(|run| run.started_at)),
+                    ended_at: time(last.and_then(|run| run.ended_at)),
+                    duration_ms: cmd.ran_for.map(|ran_for| ran_for.as_millis() as u64),
+                    restarts: end.map_or(0, |end| end - start),
+                }
+            })
+            .collect();
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// The config to run the same commands again
+    pub fn into_config(self) -> serde_json::Result<RunConfig> {
+        serde_json::from_value(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RunReport;
+    use crate::{RunConfig, RunConfigInput};
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn report_runs_of_commands() {
+        let input: RunConfigInput = serde_yaml::from_str(
+            r#"
+commands:
+  build:
+    program: sh
+    args: [-c, "exit 3"]
+  web: exec sleep 5
+  test:
+    program: "true"
+    after: [build]
+kill: WhenAnyFailed
+"#,
+        )
+        .unwrap();
+        let config: RunConfig = input.into();
+        let mut report = RunReport::new(&config).unwrap();
+        let plugin = crate::run::PluginStack::new(super::super::CommandSystemLogPlugin::new())
+            .with(report.recorder());
+        let mut system = crate::run::spawn_from_run_config_with_plugin(config, plugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+        report.stopped(&stopped, &system.history());
+
+        let command = |label: &str| {
+            let found = report.commands.iter().find(|cmd| cmd.label == label);
+            found.unwrap()
+        };
+        let (build, web, test) = (command("build"), command("web"), command("test"));
+        assert_eq!(
+            (
+                build.command.as_str(),
+                build.outcome.as_str(),
+                build.exit_code
+            ),
+            ("sh -c 'exit 3'", "failed", Some(3))
+        );
+        assert!(build.started_at.is_some() && build.ended_at.is_some());
+        assert!(build.duration_ms.is_some());
+        assert_eq!(build.restarts, 0);
+
+        assert_eq!(web.outcome, "killed");
+        assert_eq!(web.signal, Some(9));
+        assert_eq!(web.kill_reason.as_deref(), Some("command[build] exited"));
+
+        assert_eq!(test.command, "true");
+        assert_eq!(
+            test.error.as_deref(),
+            Some("skipped: command[build] didn't exit successfully")
+        );
+        assert_eq!((test.started_at.as_ref(), test.duration_ms), (None, None));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn report_restarts_of_each_repetition() {
+        let input: RunConfigInput = serde_yaml::from_str(
+            r#"
+commands:
+  soak:
+    program: sh
+    args: [-c, "sleep 0.1; exit 1"]
+    restart: { OnFailure: { max: 1 } }
+    repeat: 2
+  flaky:
+    program: sh
+    args: [-c, "exit 1"]
+    restart: { OnFailure: { max: 2 } }
+"#,
+        )
+        .unwrap();
+        let config: RunConfig = input.into();
+        let mut report = RunReport::new(&config).unwrap();
+        let plugin = crate::run::PluginStack::new(super::super::CommandSystemLogPlugin::new())
+            .with(report.recorder());
+        let mut system = crate::run::spawn_from_run_config_with_plugin(config, plugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+        report.stopped(&stopped, &system.history());
+
+        let restarts = |label: &str| -> Vec<_> {
+            let commands = report.commands.iter().filter(|cmd| cmd.label == label);
+            commands.map(|cmd| cmd.restarts).collect()
+        };
+        assert_eq!(restarts("soak"), [1]);
+        assert_eq!(restarts("flaky"), [2]);
+
+        // the times are those of the last run
+        for cmd in &report.commands {
+            let time =
+                |time: &Option<String>| humantime::parse_rfc3339(time.as_deref().unwrap()).unwrap();
+            let took = time(&cmd.ended_at).duration_since(time(&cmd.started_at));
+            let took = took.unwrap().as_millis() as u64;
+            let duration = cmd.duration_ms.unwrap();
+            assert!(took.abs_diff(duration) < 50, "{} {}", took, duration);
+        }
+    }
+
+    #[test]
+    fn replay_report() {
+        let input: RunConfigInput = serde_yaml::from_str(
+            r#"
+commands:
+  web:
+    program: npm
+    args: [start]
+    cwd: apps/web
+    envs: [[PORT, "3000"]]
+  db: docker compose up db
+envs:
+  TOKEN: secret://token
+secrets:
+  provider: env
+"#,
+        )
+        .unwrap();
+        let config: RunConfig = input.into();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        RunReport::new(&config).unwrap().write(&path).unwrap();
+        let replayed = RunReport::read(&path).unwrap().into_config().unwrap();
+
+        let commands = |config: &RunConfig| {
+            config
+                .commands
+                .iter()
+                .map(|cmd| {
+                    (
+                        cmd.resolved_label(),
+                        cmd.program.clone(),
+                        cmd.args.clone(),
+                        cmd.cwd.clone(),
+                        cmd.envs.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(commands(&replayed), commands(&config));
+        assert_eq!(replayed.envs, config.envs);
+        assert_eq!(replayed.secrets, config.secrets);
+    }
+}
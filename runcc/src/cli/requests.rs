@@ -0,0 +1,197 @@
+//! Requests to control the commands of a running runcc, from the control socket,
+//! `--control-stdin` or the keys of the tui.
+
+use std::ffi::OsString;
+use std::sync::Mutex;
+
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+
+use crate::run::{
+    kill::KillResult, CommandHandle, CommandSystemPlugin, LabeledCommandData, Reloader,
+};
+
+#[derive(Subcommand, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// List the commands, whether they run and their process ids
+    List,
+    /// Kill the current run of a command and spawn it again, also if it exited
+    Restart { label: String },
+    /// Kill a command, the other commands keep running
+    Kill { label: String },
+    /// Write a line to the stdin of a command, which must be piped like with --control-stdin
+    Send { label: String, text: String },
+    /// Write a line to the stdin of all commands whose stdin is piped
+    Broadcast { text: String },
+    /// Kill all commands and stop runcc, like on SIGTERM
+    Stop,
+}
+
+/// Whether `args` of runcc, without the program, are `ctl` and a request or an option of
+/// `cargo runcc ctl`. Otherwise `ctl` is a command to run.
+pub fn is_ctl(args: &[OsString]) -> bool {
+    let (first, second) = match args {
+        [first, second, ..] => (first, second.to_string_lossy()),
+        _ => return false,
+    };
+    let option =
+        ["-s", "--socket", "-h", "--help"].contains(&&*second) || second.starts_with("--socket=");
+    first == "ctl" && (option || ControlRequest::has_subcommand(&second))
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct CommandInfo {
+    pub label: String,
+    pub running: bool,
+    /// `None` if the command isn't running
+    pub pid: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The commands on `list`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commands: Option<Vec<CommandInfo>>,
+}
+
+impl ControlResponse {
+    fn ok() -> Self {
+        Self {
+            ok: true,
+            error: None,
+            commands: None,
+        }
+    }
+
+    pub fn error(error: String) -> Self {
+        Self {
+            ok: false,
+            error: Some(error),
+            commands: None,
+        }
+    }
+}
+
+/// The commands requests are applied to, which change when the config is reloaded
+pub trait ControlledCommands: Send + Sync {
+    /// The label and the handle of each command, in the order of the config
+    fn commands(&self) -> Vec<(String, CommandHandle<LabeledCommandData>)>;
+
+    /// Restarts the command with `label`, also if it stopped for good,
+    /// `None` if no command has `label`
+    fn restart(&self, label: &str) -> Option<KillResult>;
+}
+
+impl<P> ControlledCommands for Mutex<Reloader<LabeledCommandData, P>>
+where
+    P: CommandSystemPlugin<LabeledCommandData, CommandInitialData = LabeledCommandData>,
+{
+    fn commands(&self) -> Vec<(String, CommandHandle<LabeledCommandData>)> {
+        self.lock().unwrap().commands()
+    }
+
+    fn restart(&self, label: &str) -> Option<KillResult> {
+        // after the system stopped, runcc exits
+        let restarted = self.lock().unwrap().restart(label)?;
+        Some(restarted.unwrap_or(KillResult::AlreadyExited))
+    }
+}
+
+fn killed(label: &str, result: KillResult) -> ControlResponse {
+    match result {
+        KillResult::SentSuccess => ControlResponse::ok(),
+        KillResult::AlreadySent => {
+            ControlResponse::error(format!("command[{}] is being killed already", label))
+        }
+        KillResult::AlreadyExited => {
+            ControlResponse::error(format!("command[{}] isn't running", label))
+        }
+    }
+}
+
+/// Applies `request` to `commands` and answers it, `stop` stops all of them
+pub fn respond(
+    request: ControlRequest,
+    commands: &dyn ControlledCommands,
+    stop: &(dyn Fn() + Send + Sync),
+) -> ControlResponse {
+    let labeled = commands.commands();
+    let find = |label: &str| {
+        let handle = labeled.iter().find(|(l, _)| l == label).map(|(_, h)| h);
+        handle.ok_or_else(|| ControlResponse::error(format!("no command[{}]", label)))
+    };
+    match request {
+        ControlRequest::List => ControlResponse {
+            commands: Some(
+                labeled
+                    .iter()
+                    .map(|(label, handle)| CommandInfo {
+                        label: label.clone(),
+                        running: handle.is_running(),
+                        pid: handle.pid(),
+                    })
+                    .collect(),
+            ),
+            ..ControlResponse::ok()
+        },
+        ControlRequest::Restart { label } => match commands.restart(&label) {
+            Some(result) => killed(&label, result),
+            None => ControlResponse::error(format!("no command[{}]", label)),
+        },
+        ControlRequest::Kill { label } => match find(&label) {
+            Ok(handle) => killed(&label, handle.kill()),
+            Err(response) => response,
+        },
+        ControlRequest::Send { label, text } => match find(&label) {
+            Ok(handle) if handle.write_line(&text) => ControlResponse::ok(),
+            Ok(_) => ControlResponse::error(format!(
+                "command[{}] isn't running or doesn't read input",
+                label
+            )),
+            Err(response) => response,
+        },
+        ControlRequest::Broadcast { text } => {
+            // written to each command, not only until the first one takes it
+            let sent = labeled
+                .iter()
+                .filter(|(_, handle)| handle.write_line(&text))
+                .count();
+            match sent {
+                0 => ControlResponse::error("no running command reads input".to_string()),
+                _ => ControlResponse::ok(),
+            }
+        }
+        ControlRequest::Stop => {
+            stop();
+            ControlResponse::ok()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+
+    use super::is_ctl;
+
+    #[test]
+    fn ctl_or_command_named_ctl() {
+        let is_ctl = |args: &[&str]| {
+            let args: Vec<OsString> = args.iter().map(Into::into).collect();
+            is_ctl(&args)
+        };
+        assert!(is_ctl(&["ctl", "restart", "web"]));
+        assert!(is_ctl(&["ctl", "list"]));
+        assert!(is_ctl(&["ctl", "-s", "/tmp/runcc.sock", "stop"]));
+        assert!(is_ctl(&["ctl", "--help"]));
+
+        assert!(!is_ctl(&["ctl"]));
+        assert!(!is_ctl(&["ctl", "npm start"]));
+        assert!(!is_ctl(&["--", "ctl", "list"]));
+        assert!(!is_ctl(&["npm start", "ctl"]));
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Mutex,
+};
+
+/// Tells users how many commands runcc is still waiting for while shutting down
+#[derive(Default)]
+pub struct ShutdownProgress {
+    running: AtomicUsize,
+    shutting_down: AtomicBool,
+    /// serializes updates so that messages are printed in order
+    lock: Mutex<()>,
+}
+
+fn message(running: usize) -> String {
+    let commands = if running == 1 { "command" } else { "commands" };
+    format!(
+        "[runcc] shutting down (waiting for {} {})...",
+        running, commands
+    )
+}
+
+impl ShutdownProgress {
+    pub fn command_spawned(&self) {
+        self.running.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Returns the message to print if runcc is shutting down and still waiting for commands
+    pub fn command_exited(&self) -> Option<String> {
+        let _lock = self.lock.lock().unwrap();
+        let running = self.running.fetch_sub(1, Ordering::SeqCst) - 1;
+
+        if self.shutting_down.load(Ordering::SeqCst) && running > 0 {
+            Some(message(running))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the message to print when shutting down starts
+    pub fn start(&self) -> Option<String> {
+        let _lock = self.lock.lock().unwrap();
+        if self.shutting_down.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+
+        match self.running.load(Ordering::SeqCst) {
+            0 => None,
+            running => Some(message(running)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShutdownProgress;
+
+    #[test]
+    fn shutdown_progress_messages() {
+        let progress = ShutdownProgress::default();
+        for _ in 0..3 {
+            progress.command_spawned();
+        }
+
+        assert_eq!(progress.command_exited(), None);
+        assert_eq!(
+            progress.start().as_deref(),
+            Some("[runcc] shutting down (waiting for 2 commands)...")
+        );
+        assert_eq!(progress.start(), None);
+        assert_eq!(
+            progress.command_exited().as_deref(),
+            Some("[runcc] shutting down (waiting for 1 command)...")
+        );
+        assert_eq!(progress.command_exited(), None);
+    }
+}
@@ -0,0 +1,150 @@
+use serde::Serialize;
+use std::{io, path::Path, sync::Mutex, time::Instant};
+
+use crate::run::ReadySignal;
+
+struct CommandRecord {
+    label: String,
+    ready: Option<ReadySignal>,
+    /// `None` once the command exited
+    spawned_at: Option<Instant>,
+    spawn_count: usize,
+    exit_code: Option<i32>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandState {
+    Running,
+    /// running and its `ready_when` pattern matched or its startup probe succeeded
+    Ready,
+    Exited,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct CommandStatus {
+    pub label: String,
+    pub state: CommandState,
+    /// milliseconds since the current run was spawned, `None` if it exited
+    pub uptime_ms: Option<u64>,
+    pub restarts: usize,
+    /// `None` while running, or if the command was killed by a signal or never spawned
+    pub exit_code: Option<i32>,
+}
+
+/// Tracks the state of each command, like `docker compose ps`
+#[derive(Default)]
+pub struct StatusBoard {
+    commands: Mutex<Vec<CommandRecord>>,
+}
+
+impl StatusBoard {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn record<'a>(commands: &'a mut Vec<CommandRecord>, label: &str) -> &'a mut CommandRecord {
+        let i = match commands.iter().position(|cmd| cmd.label == label) {
+            Some(i) => i,
+            None => {
+                commands.push(CommandRecord {
+                    label: label.to_string(),
+                    ready: None,
+                    spawned_at: None,
+                    spawn_count: 0,
+                    exit_code: None,
+                });
+                commands.len() - 1
+            }
+        };
+        &mut commands[i]
+    }
+
+    pub fn command_spawned(&self, label: &str, ready: Option<ReadySignal>, at: Instant) {
+        let mut commands = self.commands.lock().unwrap();
+        let cmd = Self::record(&mut commands, label);
+        cmd.ready = ready;
+        cmd.spawned_at = Some(at);
+        cmd.spawn_count += 1;
+        cmd.exit_code = None;
+    }
+
+    pub fn command_exited(&self, label: &str, exit_code: Option<i32>) {
+        let mut commands = self.commands.lock().unwrap();
+        let cmd = Self::record(&mut commands, label);
+        cmd.spawned_at = None;
+        cmd.exit_code = exit_code;
+    }
+
+    /// The status of each command at `now`, in the order they were first spawned
+    pub fn statuses_at(&self, now: Instant) -> Vec<CommandStatus> {
+        let commands = self.commands.lock().unwrap();
+        commands
+            .iter()
+            .map(|cmd| {
+                let state = match cmd.spawned_at {
+                    None => CommandState::Exited,
+                    Some(_) if cmd.ready.as_ref().is_some_and(|r| r.is_ready()) => {
+                        CommandState::Ready
+                    }
+                    Some(_) => CommandState::Running,
+                };
+                CommandStatus {
+                    label: cmd.label.clone(),
+                    state,
+                    uptime_ms: cmd
+                        .spawned_at
+                        .map(|at| now.saturating_duration_since(at).as_millis() as u64),
+                    restarts: cmd.spawn_count.saturating_sub(1),
+                    exit_code: cmd.exit_code,
+                }
+            })
+            .collect()
+    }
+
+    /// A JSON array with the status of each command at `now`
+    fn to_json_at(&self, now: Instant) -> String {
+        serde_json::to_string(&self.statuses_at(now)).expect("command statuses should serialize")
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_json_at(Instant::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+    use std::time::{Duration, Instant};
+
+    use super::StatusBoard;
+    use crate::run::ReadySignal;
+
+    #[test]
+    fn snapshot_of_running_system() {
+        let board = StatusBoard::new();
+        let start = Instant::now();
+
+        let ready = ReadySignal::new(Regex::new("listening").unwrap());
+        board.command_spawned("web", Some(ready.clone()), start);
+        ready.check_line("listening on :3000");
+
+        board.command_spawned("worker", None, start);
+        board.command_exited("worker", Some(1));
+        board.command_spawned("worker", None, start + Duration::from_millis(500));
+
+        board.command_spawned("migrate", None, start);
+        board.command_exited("migrate", Some(0));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&board.to_json_at(start + Duration::from_secs(2))).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                { "label": "web", "state": "ready", "uptime_ms": 2000, "restarts": 0, "exit_code": null },
+                { "label": "worker", "state": "running", "uptime_ms": 1500, "restarts": 1, "exit_code": null },
+                { "label": "migrate", "state": "exited", "uptime_ms": null, "restarts": 0, "exit_code": 0 },
+            ])
+        );
+    }
+}
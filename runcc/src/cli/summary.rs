@@ -0,0 +1,361 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::run::{
+    kill, kill_reason, CommandOutcome, CommandStopped, CommandSystemSimpleReport,
+    LabeledCommandData, OutputEvent,
+};
+use crate::SummaryOrder;
+
+fn outcome_color(outcome: CommandOutcome) -> &'static str {
+    match outcome {
+        CommandOutcome::Succeeded => "32",
+        CommandOutcome::Killed => "33",
+        CommandOutcome::Failed => "31",
+    }
+}
+
+type Stopped = Arc<CommandStopped<LabeledCommandData, LabeledCommandData>>;
+
+/// Sorts `commands`, which are in config order, for the summary and the report.
+/// `history` are the commands which stopped, oldest first, see
+/// [`CommandSystem::history`](crate::run::CommandSystem::history).
+pub fn sort_commands(commands: &mut [Stopped], order: SummaryOrder, history: &[Stopped]) {
+    match order {
+        SummaryOrder::Outcome => commands.sort_by_key(|cmd| match cmd.outcome() {
+            CommandOutcome::Succeeded => 0,
+            CommandOutcome::Killed => 1,
+            CommandOutcome::Failed => 2,
+        }),
+        SummaryOrder::Status => commands.sort_by(|a, b| {
+            let status = |cmd: &Stopped| match cmd.outcome() {
+                CommandOutcome::Failed => 0,
+                CommandOutcome::Killed => 1,
+                CommandOutcome::Succeeded => 2,
+            };
+            let label = |cmd: &Stopped| cmd.data.label.label().to_string();
+            (status(a), label(a)).cmp(&(status(b), label(b)))
+        }),
+        SummaryOrder::Config => {}
+        SummaryOrder::Exit => commands.sort_by_key(|cmd| {
+            let stopped_at = history.iter().position(|other| Arc::ptr_eq(cmd, other));
+            stopped_at.unwrap_or(usize::MAX)
+        }),
+        SummaryOrder::Label => {
+            commands.sort_by(|a, b| a.data.label.label().cmp(b.data.label.label()))
+        }
+        SummaryOrder::Duration => commands.sort_by_key(|cmd| std::cmp::Reverse(cmd.ran_for)),
+    }
+}
+
+/// One line per command with how it stopped and how long it ran, printed before the
+/// [`summary_line`] in the order of `commands`, see [`sort_commands`].
+///
+/// If `colored`, the outcome of each command is colored like the summary line.
+pub fn summary_table(commands: &[Stopped], colored: bool) -> Vec<String> {
+    let width = commands
+        .iter()
+        .map(|cmd| cmd.data.label.label().chars().count())
+        .max()
+        .unwrap_or(0);
+
+    commands
+        .iter()
+        .map(|cmd| {
+            let (outcome, status) = (cmd.outcome(), exit_status(cmd));
+            let name = format!("{:<9}", outcome_name(outcome));
+            let name = match colored {
+                true => format!("\x1b[{}m{}\x1b[0m", outcome_color(outcome), name),
+                false => name,
+            };
+            let ran_for = match cmd.ran_for {
+                Some(ran_for) => format!("{:.1}s", ran_for.as_secs_f64()),
+                None => "-".to_string(),
+            };
+            format!(
+                "  {:<width$}  {}  {:>7}  {}",
+                cmd.data.label.label(),
+                name,
+                ran_for,
+                status,
+                width = width
+            )
+        })
+        .collect()
+}
+
+fn outcome_name(outcome: CommandOutcome) -> &'static str {
+    match outcome {
+        CommandOutcome::Succeeded => "succeeded",
+        CommandOutcome::Failed => "failed",
+        CommandOutcome::Killed => "killed",
+    }
+}
+
+/// The exit code, the signal which terminated the command or why it has neither
+fn exit_status(cmd: &Stopped) -> String {
+    if let Some(kill::KillCommandReason::ProbeFailed { attempts }) = kill_reason(cmd) {
+        return format!("readiness probe failed {} times", attempts);
+    }
+    if let Some(timeout) = cmd.timed_out() {
+        return format!("timed out after {}", humantime::format_duration(timeout));
+    }
+    match &cmd.exit_status {
+        Ok(status) => match (status.code(), super::json::signal(status)) {
+            (Some(code), _) => format!("code {}", code),
+            (None, Some(signal)) => format!("signal {}", signal),
+            (None, None) => "no exit code".to_string(),
+        },
+        Err(err) => err.to_string(),
+    }
+}
+
+/// A report of how `commands` stopped in the Test Anything Protocol, for `--format tap`:
+/// the plan and an `ok` line per command which succeeded or was skipped, in the order of
+/// `commands`.
+/// Commands which failed or were killed are `not ok`, followed by a diagnostic line.
+pub fn tap_report(commands: &[Stopped]) -> Vec<String> {
+    let mut lines = vec![format!("1..{}", commands.len())];
+    for (i, cmd) in commands.iter().enumerate() {
+        // `#` starts a directive
+        let label = cmd.data.label.label().replace('#', "\\#");
+        let number = i + 1;
+        match (cmd.outcome(), cmd.skipped()) {
+            (_, Some(skipped)) => {
+                lines.push(format!("ok {} - {} # SKIP {}", number, label, skipped.0))
+            }
+            (CommandOutcome::Succeeded, None) => lines.push(format!("ok {} - {}", number, label)),
+            (outcome, None) => {
+                lines.push(format!("not ok {} - {}", number, label));
+                lines.push(format!("# {}: {}", outcome_name(outcome), exit_status(cmd)));
+            }
+        }
+    }
+    lines
+}
+
+/// The last output lines `events` of the command with `label` after a line like
+/// `last 20 lines of build:`, see `--tail`. Nothing if there are none.
+pub fn tail_of(label: &str, events: &[OutputEvent]) -> Vec<String> {
+    if events.is_empty() {
+        return vec![];
+    }
+    let noun = if events.len() == 1 { "line" } else { "lines" };
+    let mut lines = vec![format!("last {} {} of {}:", events.len(), noun, label)];
+    lines.extend(events.iter().map(|event| format!("  {}", event.line)));
+    lines
+}
+
+/// The line printed once all commands stopped, e.g. `runcc: 3 succeeded, 1 failed, 0 killed (12.4s)`.
+///
+/// If `colored`, it is bold and green if all commands succeeded, red if any failed
+/// and yellow if some were killed.
+pub fn summary_line(report: &CommandSystemSimpleReport, took: Duration, colored: bool) -> String {
+    let failed = report.command_count_failed();
+    let line = format!(
+        "runcc: {} succeeded, {} failed, {} killed ({:.1}s)",
+        report.command_count_success,
+        failed,
+        report.command_count_killed,
+        took.as_secs_f64()
+    );
+    if !colored {
+        return line;
+    }
+
+    let color = match (failed, report.command_count_killed) {
+        (0, 0) => outcome_color(CommandOutcome::Succeeded),
+        (0, _) => outcome_color(CommandOutcome::Killed),
+        _ => outcome_color(CommandOutcome::Failed),
+    };
+    format!("\x1b[1;{}m{}\x1b[0m", color, line)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::ExitStatus;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{sort_commands, summary_line, summary_table, tail_of, tap_report};
+    use crate::run::kill::KillJoinHandleFinalStatus;
+    use crate::run::{
+        CommandStopped, CommandSystemSimpleReport, LabeledCommandData, OutputEvent, OutputStream,
+    };
+    use crate::SummaryOrder;
+
+    fn stopped(
+        label: &str,
+        status: std::io::Result<ExitStatus>,
+        killed: Option<KillJoinHandleFinalStatus<LabeledCommandData>>,
+        ran_for: Option<Duration>,
+    ) -> Arc<CommandStopped<LabeledCommandData, LabeledCommandData>> {
+        Arc::new(CommandStopped {
+            data: LabeledCommandData::labeled(label),
+            exit_status: status,
+            killed,
+            ran_for,
+        })
+    }
+
+    #[test]
+    fn color_by_outcome() {
+        let report = |success, killed| CommandSystemSimpleReport {
+            command_count_total: 4,
+            command_count_success: success,
+            command_count_killed: killed,
+            first_failed_code: Some(1),
+            last_exit_code: None,
+            last_failed: false,
+            exit_code_policy: Default::default(),
+        };
+        let took = Duration::from_millis(12_440);
+
+        let mixed = report(2, 1);
+        assert_eq!(
+            summary_line(&mixed, took, false),
+            "runcc: 2 succeeded, 1 failed, 1 killed (12.4s)"
+        );
+        assert_eq!(
+            summary_line(&mixed, took, true),
+            "\x1b[1;31mruncc: 2 succeeded, 1 failed, 1 killed (12.4s)\x1b[0m"
+        );
+        assert!(summary_line(&report(4, 0), took, true).starts_with("\x1b[1;32m"));
+        assert!(summary_line(&report(3, 1), took, true).starts_with("\x1b[1;33m"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn table_puts_failures_last() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let killed = KillJoinHandleFinalStatus::Killed(
+            crate::run::kill::KillCommandReason::MainProcessGotSignal,
+        );
+        let never_spawned = std::io::Error::other("command[build] failed");
+        let commands = [
+            stopped(
+                "build",
+                Ok(ExitStatus::from_raw(1 << 8)),
+                None,
+                Some(Duration::from_millis(440)),
+            ),
+            stopped("web", Ok(ExitStatus::from_raw(15)), Some(killed), None),
+            stopped("test", Err(never_spawned), None, None),
+            stopped(
+                "lint",
+                Ok(ExitStatus::from_raw(0)),
+                None,
+                Some(Duration::from_millis(12_040)),
+            ),
+        ];
+        let mut sorted = commands.to_vec();
+        sort_commands(&mut sorted, SummaryOrder::Outcome, &[]);
+
+        assert_eq!(
+            summary_table(&sorted, false),
+            [
+                "  lint   succeeded    12.0s  code 0",
+                "  web    killed           -  signal 15",
+                "  build  failed        0.4s  code 1",
+                "  test   failed           -  command[build] failed",
+            ]
+        );
+        let colored = summary_table(&sorted, true);
+        assert!(colored[0].contains("\x1b[32msucceeded\x1b[0m"));
+        assert!(colored[3].contains("\x1b[31mfailed   \x1b[0m"));
+
+        assert_eq!(
+            tap_report(&commands),
+            [
+                "1..4",
+                "not ok 1 - build",
+                "# failed: code 1",
+                "not ok 2 - web",
+                "# killed: signal 15",
+                "not ok 3 - test",
+                "# failed: command[build] failed",
+                "ok 4 - lint",
+            ]
+        );
+
+        let events: Vec<_> = ["compiling", "error: oops"]
+            .iter()
+            .map(|line| OutputEvent {
+                stream: OutputStream::Stderr,
+                line: line.to_string(),
+            })
+            .collect();
+        assert_eq!(
+            tail_of("build", &events),
+            ["last 2 lines of build:", "  compiling", "  error: oops"]
+        );
+        assert_eq!(
+            tail_of("build", &events[1..]),
+            ["last 1 line of build:", "  error: oops"]
+        );
+        assert!(tail_of("web", &[]).is_empty());
+    }
+
+    #[test]
+    fn sort_by_summary_order() {
+        let never_spawned = std::io::Error::other("command[build] failed");
+        let commands = [
+            stopped(
+                "web",
+                Err(std::io::Error::other("no such file")),
+                None,
+                Some(Duration::from_secs(3)),
+            ),
+            stopped("test", Err(never_spawned), None, None),
+            stopped(
+                "api",
+                Ok(ExitStatus::default()),
+                None,
+                Some(Duration::from_secs(9)),
+            ),
+            stopped(
+                "db",
+                Ok(ExitStatus::default()),
+                None,
+                Some(Duration::from_secs(1)),
+            ),
+        ];
+        let history = [2, 0, 3, 1].map(|i| commands[i].clone());
+        let labels = |order| {
+            let mut sorted = commands.to_vec();
+            sort_commands(&mut sorted, order, &history);
+            let labels = sorted.iter().map(|cmd| cmd.data.label.label().to_string());
+            labels.collect::<Vec<_>>()
+        };
+
+        assert_eq!(labels(SummaryOrder::Outcome), ["api", "db", "web", "test"]);
+        assert_eq!(labels(SummaryOrder::Config), ["web", "test", "api", "db"]);
+        assert_eq!(labels(SummaryOrder::Exit), ["api", "web", "db", "test"]);
+        assert_eq!(labels(SummaryOrder::Label), ["api", "db", "test", "web"]);
+        assert_eq!(labels(SummaryOrder::Duration), ["api", "web", "db", "test"]);
+    }
+
+    #[test]
+    fn group_by_status() {
+        let failed = |label| stopped(label, Err(std::io::Error::other("oops")), None, None);
+        let killed = |label| {
+            let reason = crate::run::kill::KillCommandReason::MainProcessGotSignal;
+            let killed = Some(KillJoinHandleFinalStatus::Killed(reason));
+            stopped(label, Err(std::io::Error::other("killed")), killed, None)
+        };
+        let succeeded = |label| stopped(label, Ok(ExitStatus::default()), None, None);
+        let mut commands = vec![
+            succeeded("web"),
+            failed("test"),
+            killed("worker"),
+            succeeded("api"),
+            failed("lint"),
+            killed("db"),
+        ];
+
+        sort_commands(&mut commands, SummaryOrder::Status, &[]);
+        let labels: Vec<_> = commands.iter().map(|cmd| cmd.data.label.label()).collect();
+        assert_eq!(labels, ["lint", "test", "db", "worker", "api", "web"]);
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    process::{Command as StdCommand, Stdio},
+};
+
+use tokio::process::Command;
+
+/// A program which records syscalls of a command and its children
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tracer {
+    /// `strace` on Linux
+    Strace,
+    /// `dtrace` on macOS
+    Dtrace,
+}
+
+impl Tracer {
+    /// The tracer for the current platform, if it is installed
+    pub fn detect() -> Option<Self> {
+        let (tracer, version_arg) = if cfg!(target_os = "linux") {
+            (Tracer::Strace, "-V")
+        } else if cfg!(target_os = "macos") {
+            (Tracer::Dtrace, "-V")
+        } else {
+            return None;
+        };
+
+        let installed = StdCommand::new(tracer.program())
+            .arg(version_arg)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok();
+
+        if installed {
+            Some(tracer)
+        } else {
+            None
+        }
+    }
+
+    pub fn program(&self) -> &'static str {
+        match self {
+            Tracer::Strace => "strace",
+            Tracer::Dtrace => "dtrace",
+        }
+    }
+
+    /// Args of the tracer to run `command` and write the trace to `output`
+    fn args(&self, output: &Path, command: &StdCommand) -> Vec<OsString> {
+        match self {
+            Tracer::Strace => {
+                let mut args: Vec<OsString> =
+                    vec!["-f".into(), "-o".into(), output.into(), "--".into()];
+                args.push(command.get_program().into());
+                args.extend(command.get_args().map(Into::into));
+                args
+            }
+            // dtrace takes the command as a single argument
+            Tracer::Dtrace => {
+                let mut cmd: OsString = command.get_program().into();
+                for arg in command.get_args() {
+                    cmd.push(" ");
+                    cmd.push(arg);
+                }
+                vec![
+                    "-q".into(),
+                    "-o".into(),
+                    output.into(),
+                    "-n".into(),
+                    r#"syscall:::entry /pid == $target || progenyof($target)/ { printf("%d %s\n", pid, probefunc); }"#.into(),
+                    "-c".into(),
+                    cmd,
+                ]
+            }
+        }
+    }
+}
+
+/// Runs each command under a [`Tracer`], writing a trace file per command into `dir`
+pub struct SyscallTracing {
+    tracer: Tracer,
+    dir: PathBuf,
+}
+
+fn trace_file_name(label: &str) -> String {
+    let name: String = label
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.trace", name)
+}
+
+/// A command running `command` under `tracer`, with the same cwd and envs
+fn wrap_command(tracer: Tracer, output: &Path, command: &StdCommand) -> Command {
+    let mut wrapper = Command::new(tracer.program());
+    wrapper.args(tracer.args(output, command));
+
+    if let Some(cwd) = command.get_current_dir() {
+        wrapper.current_dir(cwd);
+    }
+
+    for (k, v) in command.get_envs() {
+        match v {
+            Some(v) => wrapper.env(k, v),
+            None => wrapper.env_remove(k),
+        };
+    }
+
+    wrapper
+}
+
+impl SyscallTracing {
+    /// Creates `dir` if it doesn't exist
+    pub fn new(tracer: Tracer, dir: PathBuf) -> io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        // the trace file is opened by the tracer in the cwd of the command
+        let dir = dir.canonicalize()?;
+        Ok(Self { tracer, dir })
+    }
+
+    /// Replaces `command` with the tracer running it
+    pub fn wrap(&self, label: &str, command: &mut Command) {
+        let output = self.dir.join(trace_file_name(label));
+        *command = wrap_command(self.tracer, &output, command.as_std());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, process::Command};
+
+    use super::{trace_file_name, wrap_command, Tracer};
+
+    #[test]
+    fn wrap_with_strace() {
+        let mut command = Command::new("cargo");
+        command.args(["run", "--release"]).current_dir("server");
+        command.env("PORT", "3000");
+
+        let wrapper = wrap_command(Tracer::Strace, Path::new("/tmp/api.trace"), &command);
+        let wrapper = wrapper.as_std();
+
+        assert_eq!(wrapper.get_program(), "strace");
+        assert_eq!(
+            wrapper.get_args().collect::<Vec<_>>(),
+            [
+                "-f",
+                "-o",
+                "/tmp/api.trace",
+                "--",
+                "cargo",
+                "run",
+                "--release"
+            ]
+        );
+        assert_eq!(wrapper.get_current_dir(), Some(Path::new("server")));
+        assert_eq!(
+            wrapper.get_envs().collect::<Vec<_>>(),
+            [("PORT".as_ref(), Some("3000".as_ref()))]
+        );
+
+        assert_eq!(trace_file_name("cargo run"), "cargo_run.trace");
+    }
+}
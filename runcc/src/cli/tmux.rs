@@ -0,0 +1,177 @@
+use std::{
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+    process::Command as StdCommand,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use tokio::process::Command;
+
+/// A tmux window in the current session where each command runs in its own pane.
+///
+/// Commands are replaced by a wrapper which opens the pane and waits until the
+/// command in the pane exits, so that runcc still supervises them.
+pub struct TmuxWindow {
+    id: String,
+    next_pane: AtomicUsize,
+}
+
+fn quote(s: &OsStr) -> String {
+    format!("'{}'", s.to_string_lossy().replace('\'', r"'\''"))
+}
+
+fn tmux<I: IntoIterator<Item = S>, S: AsRef<OsStr>>(args: I) -> io::Result<String> {
+    let output = StdCommand::new("tmux").args(args).output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(io::Error::other(format!(
+            "tmux failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// Script run in the pane, it reports the exit status of the command
+fn pane_script(command: &StdCommand, status_file: &Path, channel: &str) -> String {
+    let mut words = vec![];
+
+    let envs: Vec<_> = command
+        .get_envs()
+        .filter_map(|(k, v)| v.map(|v| (k, v)))
+        .collect();
+    if !envs.is_empty() {
+        words.push("env".to_string());
+        for (k, v) in envs {
+            let mut kv = k.to_os_string();
+            kv.push("=");
+            kv.push(v);
+            words.push(quote(&kv));
+        }
+    }
+
+    words.push(quote(command.get_program()));
+    words.extend(command.get_args().map(quote));
+
+    format!(
+        "{}; echo $? > {}; tmux wait-for -S {}",
+        words.join(" "),
+        quote(status_file.as_os_str()),
+        channel
+    )
+}
+
+/// Script run by runcc, it opens the pane and exits with the status of the command
+fn wrapper_script(
+    window: &str,
+    cwd: Option<&Path>,
+    pane: &str,
+    status_file: &Path,
+    channel: &str,
+) -> String {
+    let cwd = cwd
+        .map(|cwd| format!(" -c {}", quote(cwd.as_os_str())))
+        .unwrap_or_default();
+    let status_file = quote(status_file.as_os_str());
+
+    format!(
+        "tmux split-window -d -t {window}{cwd} {pane} && \
+         tmux select-layout -t {window} tiled > /dev/null && \
+         tmux wait-for {channel}; \
+         code=$(cat {status_file} 2> /dev/null || echo 1); rm -f {status_file}; exit $code",
+        window = window,
+        cwd = cwd,
+        pane = quote(OsStr::new(pane)),
+        channel = channel,
+        status_file = status_file,
+    )
+}
+
+impl TmuxWindow {
+    /// Creates a new window in the tmux session runcc is running in
+    pub fn create() -> io::Result<Self> {
+        if std::env::var_os("TMUX").is_none() {
+            return Err(io::Error::other("--tmux requires running inside tmux"));
+        }
+
+        let id = tmux([
+            "new-window",
+            "-d",
+            "-P",
+            "-F",
+            "#{window_id}",
+            "-n",
+            "runcc",
+            "echo '[runcc] commands are running in the other panes'; exec tail -f /dev/null",
+        ])?;
+
+        Ok(Self {
+            id,
+            next_pane: AtomicUsize::new(0),
+        })
+    }
+
+    /// Replaces `command` with a wrapper running it in a new pane
+    pub fn wrap(&self, command: &mut Command) {
+        let i = self.next_pane.fetch_add(1, Ordering::SeqCst);
+        let channel = format!("runcc-{}-{}", std::process::id(), i);
+        let status_file: PathBuf = std::env::temp_dir().join(format!("{}.status", channel));
+
+        let std_command = command.as_std();
+        let pane = pane_script(std_command, &status_file, &channel);
+        let script = wrapper_script(
+            &self.id,
+            std_command.get_current_dir(),
+            &pane,
+            &status_file,
+            &channel,
+        );
+
+        let mut wrapper = Command::new("sh");
+        wrapper.arg("-c").arg(script);
+        *command = wrapper;
+    }
+
+    /// Closes the window with all panes
+    pub fn kill(&self) {
+        if let Err(err) = tmux(["kill-window", "-t", &self.id]) {
+            eprintln!("[runcc][warning] failed to close tmux window: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process::Command;
+
+    use super::{pane_script, wrapper_script};
+
+    #[test]
+    fn tmux_scripts() {
+        let mut command = Command::new("npm");
+        command.arg("run").arg("it's").env("PORT", "3000");
+
+        let pane = pane_script(&command, Path::new("/tmp/s"), "ch");
+        assert_eq!(
+            pane,
+            r"env 'PORT=3000' 'npm' 'run' 'it'\''s'; echo $? > '/tmp/s'; tmux wait-for -S ch"
+        );
+
+        let wrapper = wrapper_script(
+            "@1",
+            Some(Path::new("web")),
+            "echo hi",
+            Path::new("/tmp/s"),
+            "ch",
+        );
+        assert_eq!(
+            wrapper,
+            "tmux split-window -d -t @1 -c 'web' 'echo hi' && \
+             tmux select-layout -t @1 tiled > /dev/null && \
+             tmux wait-for ch; \
+             code=$(cat '/tmp/s' 2> /dev/null || echo 1); rm -f '/tmp/s'; exit $code"
+        );
+    }
+}
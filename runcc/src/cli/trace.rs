@@ -0,0 +1,137 @@
+use serde::Serialize;
+use std::{collections::HashMap, io, path::Path, sync::Mutex, time::Instant};
+
+/// Records a span per command run in the Chrome tracing format,
+/// which can be viewed in `chrome://tracing`.
+pub struct Trace {
+    started_at: Instant,
+    state: Mutex<TraceState>,
+}
+
+#[derive(Default)]
+struct TraceState {
+    events: Vec<TraceEvent>,
+    /// thread id and spawn time of each label
+    commands: HashMap<String, (usize, Option<Instant>)>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct TraceEvent {
+    name: String,
+    ph: &'static str,
+    /// microseconds since the run started
+    ts: u64,
+    pid: u32,
+    tid: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TraceFile<'a> {
+    trace_events: &'a [TraceEvent],
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            state: Default::default(),
+        }
+    }
+
+    pub fn spawned(&self, label: &str, at: Instant) {
+        let mut state = self.state.lock().unwrap();
+        let tid = state.commands.len();
+        state
+            .commands
+            .entry(label.to_string())
+            .or_insert((tid, None))
+            .1 = Some(at);
+    }
+
+    /// Records the span of the run from spawn to exit, and from spawn to ready if it became ready.
+    pub fn exited(&self, label: &str, ready_at: Option<Instant>, at: Instant) {
+        let mut state = self.state.lock().unwrap();
+        let (tid, spawned_at) = match state.commands.get_mut(label) {
+            Some((tid, spawned_at)) => match spawned_at.take() {
+                Some(spawned_at) => (*tid, spawned_at),
+                None => return,
+            },
+            None => return,
+        };
+
+        let event = |name: &str, ph, at: Instant| TraceEvent {
+            name: name.to_string(),
+            ph,
+            ts: at.saturating_duration_since(self.started_at).as_micros() as u64,
+            pid: 1,
+            tid,
+        };
+
+        state.events.push(event(label, "B", spawned_at));
+        if let Some(ready_at) = ready_at {
+            let name = format!("{} startup", label);
+            state.events.push(event(&name, "B", spawned_at));
+            state.events.push(event(&name, "E", ready_at));
+        }
+        state.events.push(event(label, "E", at));
+    }
+
+    pub fn to_json(&self) -> String {
+        let state = self.state.lock().unwrap();
+        serde_json::to_string(&TraceFile {
+            trace_events: &state.events,
+        })
+        .expect("trace events should serialize")
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Trace;
+
+    #[test]
+    fn span_per_command() {
+        let trace = Trace::new();
+        let at = |ms| trace.started_at + Duration::from_millis(ms);
+
+        trace.spawned("db", at(0));
+        trace.spawned("web", at(5));
+        trace.exited("db", Some(at(20)), at(100));
+        trace.exited("web", None, at(50));
+
+        let json: serde_json::Value = serde_json::from_str(&trace.to_json()).unwrap();
+        let events: Vec<_> = json["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                (
+                    e["name"].as_str().unwrap().to_string(),
+                    e["ph"].as_str().unwrap().to_string(),
+                    e["ts"].as_u64().unwrap(),
+                    e["tid"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+
+        let event = |name: &str, ph: &str, ts, tid| (name.to_string(), ph.to_string(), ts, tid);
+        assert_eq!(
+            events,
+            [
+                event("db", "B", 0, 0),
+                event("db startup", "B", 0, 0),
+                event("db startup", "E", 20_000, 0),
+                event("db", "E", 100_000, 0),
+                event("web", "B", 5_000, 1),
+                event("web", "E", 50_000, 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,566 @@
+//! A terminal UI with a pane for the output of each command, see `--tui`.
+//!
+//! A sidebar lists the commands with their state, the selected pane shows either the output
+//! of all commands as runcc prints it without the UI, or the lines of one command.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use super::output::{SharedSink, REPLACE_LAST_LINE};
+use super::status::{CommandState, CommandStatus};
+use super::{read_keys, respond, ControlRequest, ControlledCommands, StatusBoard, TerminalMode};
+use crate::run::OutputBroadcast;
+
+/// How many lines each pane keeps
+const SCROLLBACK_LINES: usize = 10_000;
+
+/// How often the screen is drawn if it changed
+const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often the screen is drawn even if it didn't change,
+/// e.g. after runcc printed a warning over it
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+const HELP: &str = "↑/↓ select  PgUp/PgDn scroll  r restart  k kill  q quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Char(u8),
+}
+
+/// The key `bytes` start with and how many bytes it took, `bytes` must not be empty
+fn parse_key(bytes: &[u8]) -> (Key, usize) {
+    match bytes {
+        [b'\t', ..] => (Key::Down, 1),
+        [0x1b, b'[', b'A', ..] => (Key::Up, 3),
+        [0x1b, b'[', b'B', ..] => (Key::Down, 3),
+        [0x1b, b'[', b'H', ..] => (Key::Home, 3),
+        [0x1b, b'[', b'F', ..] => (Key::End, 3),
+        [0x1b, b'[', b'5', b'~', ..] => (Key::PageUp, 4),
+        [0x1b, b'[', b'6', b'~', ..] => (Key::PageDown, 4),
+        [key, ..] => (Key::Char(*key), 1),
+        [] => unreachable!("no key without bytes"),
+    }
+}
+
+/// Reads keys from the terminal.
+///
+/// The terminal writes the bytes of a key at once, so Esc alone is a key of its own
+/// instead of waiting for the rest of an escape sequence.
+struct Keys<R> {
+    input: R,
+    /// read but not parsed yet
+    pending: VecDeque<u8>,
+}
+
+impl<R: Read> Keys<R> {
+    fn new(input: R) -> Self {
+        Self {
+            input,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// The next key, `None` at the end of the input
+    fn read_key(&mut self) -> Option<Key> {
+        if self.pending.is_empty() {
+            let mut buf = [0u8; 64];
+            let read = loop {
+                match self.input.read(&mut buf) {
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Ok(0) | Err(_) => return None,
+                    Ok(read) => break read,
+                }
+            };
+            self.pending.extend(&buf[..read]);
+        }
+        let (key, len) = parse_key(self.pending.make_contiguous());
+        self.pending.drain(..len);
+        Some(key)
+    }
+}
+
+/// `line` cut to `width` columns, padded to them if `pad`.
+///
+/// Colors are kept, other escape sequences and carriage returns are dropped,
+/// since they would move the cursor out of the pane.
+fn fit(line: &str, width: usize, pad: bool) -> String {
+    if width == 0 {
+        return String::new();
+    }
+    let mut fitted = String::with_capacity(line.len());
+    let mut columns = 0;
+    let mut colored = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                if chars.peek() != Some(&'[') {
+                    chars.next();
+                    continue;
+                }
+                let mut sequence = String::from("\x1b");
+                for c in chars.by_ref() {
+                    sequence.push(c);
+                    if ('\x40'..='\x7e').contains(&c) && c != '[' {
+                        break;
+                    }
+                }
+                if sequence.ends_with('m') {
+                    colored = true;
+                    fitted.push_str(&sequence);
+                }
+            }
+            '\r' => {}
+            '\t' => {
+                let spaces = (4 - columns % 4).min(width - columns);
+                fitted.extend(std::iter::repeat_n(' ', spaces));
+                columns += spaces;
+            }
+            c if c.is_control() => {}
+            c => {
+                fitted.push(c);
+                columns += 1;
+            }
+        }
+        if columns >= width {
+            break;
+        }
+    }
+    if colored {
+        fitted.push_str("\x1b[0m");
+    }
+    if pad {
+        fitted.extend(std::iter::repeat_n(' ', width - columns));
+    }
+    fitted
+}
+
+/// Rows and columns of the terminal on stdout, 24x80 if it has no size
+fn terminal_size() -> (usize, usize) {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+        && size.ws_row > 0
+        && size.ws_col > 0
+    {
+        return (size.ws_row as usize, size.ws_col as usize);
+    }
+    (24, 80)
+}
+
+/// The colored symbol and the exit code of a command in the sidebar
+fn indicator(status: Option<&CommandStatus>) -> (&'static str, String) {
+    match status {
+        None => ("\x1b[2m○\x1b[22m", String::new()),
+        Some(status) => match (status.state, status.exit_code) {
+            (CommandState::Running, _) => ("\x1b[33m●\x1b[39m", String::new()),
+            (CommandState::Ready, _) => ("\x1b[32m●\x1b[39m", String::new()),
+            (CommandState::Exited, Some(0)) => ("\x1b[32m✓\x1b[39m", String::new()),
+            (CommandState::Exited, Some(code)) => ("\x1b[31m✗\x1b[39m", format!(" {}", code)),
+            (CommandState::Exited, None) => ("\x1b[31m✗\x1b[39m", String::new()),
+        },
+    }
+}
+
+#[derive(Default)]
+struct Pane {
+    /// oldest first
+    lines: VecDeque<String>,
+    /// how many lines the pane is scrolled up from its end
+    scroll: usize,
+}
+
+impl Pane {
+    fn push(&mut self, line: String) {
+        if self.lines.len() == SCROLLBACK_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// of the commands with a pane, in the order of their panes
+    labels: Vec<String>,
+    /// the output of all commands first, then one pane per command
+    panes: Vec<Pane>,
+    /// output of all commands after its last complete line
+    partial: Vec<u8>,
+    selected: usize,
+    /// shown instead of the help until the next key
+    message: Option<String>,
+}
+
+/// The state of the terminal UI, shared by the threads drawing it and reading keys
+pub struct Tui {
+    status: Arc<StatusBoard>,
+    state: Mutex<State>,
+    closed: AtomicBool,
+}
+
+impl Tui {
+    /// A UI with a pane for each of the commands with `labels`, in this order
+    pub fn new(labels: Vec<String>, status: Arc<StatusBoard>) -> Arc<Self> {
+        let panes = (0..=labels.len()).map(|_| Pane::default()).collect();
+        Arc::new(Self {
+            status,
+            state: Mutex::new(State {
+                labels,
+                panes,
+                ..Default::default()
+            }),
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// Where runcc writes its output and that of all commands instead of the terminal
+    pub fn sink(self: &Arc<Self>) -> SharedSink {
+        let tui = self.clone();
+        Arc::new(move |_, bytes| tui.write_output(bytes))
+    }
+
+    fn write_output(&self, bytes: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        let State { panes, partial, .. } = &mut *state;
+        if bytes == REPLACE_LAST_LINE {
+            panes[0].lines.pop_back();
+            return;
+        }
+        partial.extend_from_slice(bytes);
+        while let Some(end) = partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = partial.drain(..=end).collect();
+            panes[0].push(String::from_utf8_lossy(&line[..end]).into_owned());
+        }
+    }
+
+    fn write_line(&self, pane: usize, line: String) {
+        self.state.lock().unwrap().panes[pane].push(line);
+    }
+
+    /// Writes the lines of the command with the pane at `index` from `broadcast` to its pane
+    fn follow(self: &Arc<Self>, index: usize, label: &str, broadcast: &OutputBroadcast) {
+        let mut lines = broadcast.subscribe(label, true);
+        let tui = self.clone();
+        // across the runs of the command
+        tokio::spawn(async move {
+            while !lines.is_closed() {
+                while let Some(event) = lines.recv().await {
+                    tui.write_line(index + 1, event.line);
+                }
+            }
+        });
+    }
+
+    /// Adds a pane for each of `commands` without one, e.g. after the config was reloaded
+    fn add_panes(self: &Arc<Self>, commands: &dyn ControlledCommands, broadcast: &OutputBroadcast) {
+        let labels = commands.commands().into_iter().map(|(label, _)| label);
+        let mut state = self.state.lock().unwrap();
+        for label in labels {
+            if state.labels.contains(&label) {
+                continue;
+            }
+            self.follow(state.labels.len(), &label, broadcast);
+            state.labels.push(label);
+            state.panes.push(Pane::default());
+        }
+    }
+
+    /// The rows of the screen with `rows` and `columns`, given the `statuses` of commands
+    fn draw(&self, rows: usize, columns: usize, statuses: &[CommandStatus]) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let longest = state.labels.iter().map(|l| l.chars().count()).max();
+        // the symbol, the label, an exit code of up to 3 digits
+        let sidebar = (longest.unwrap_or_default() + 6).max(8).min(columns / 3);
+        let pane_width = columns.saturating_sub(sidebar + 1);
+        let body = rows.saturating_sub(1);
+
+        let Pane {
+            lines: pane,
+            scroll,
+        } = &state.panes[state.selected];
+        let scroll = *scroll;
+        let end = pane.len() - scroll.min(pane.len());
+        let visible = pane.range(end.saturating_sub(body)..end);
+        let mut visible = visible.map(String::as_str);
+
+        let mut screen = Vec::with_capacity(rows);
+        for row in 0..body {
+            let name_width = sidebar.saturating_sub(2);
+            let entry = match row {
+                0 => format!("  {}", fit("all", name_width, true)),
+                i if i <= state.labels.len() => {
+                    let label = &state.labels[i - 1];
+                    let status = statuses.iter().find(|s| s.label == *label);
+                    let (symbol, code) = indicator(status);
+                    let name = format!("{}{}", label, code);
+                    format!("{} {}", symbol, fit(&name, name_width, true))
+                }
+                _ => " ".repeat(sidebar),
+            };
+            let entry = match row == state.selected {
+                true => format!("\x1b[7m{}\x1b[27m", entry),
+                false => entry,
+            };
+            let line = fit(visible.next().unwrap_or_default(), pane_width, false);
+            screen.push(format!("{}│{}", entry, line));
+        }
+
+        let help = match (&state.message, scroll) {
+            (Some(message), _) => message.clone(),
+            (None, 0) => HELP.to_string(),
+            (None, scroll) => format!("scrolled up {} lines, End to follow  {}", scroll, HELP),
+        };
+        screen.push(format!("\x1b[2m{}\x1b[22m", fit(&help, columns, false)));
+        screen.truncate(rows);
+        screen
+    }
+
+    fn handle_key(
+        &self,
+        key: Key,
+        commands: &dyn ControlledCommands,
+        stop: &(dyn Fn() + Send + Sync),
+    ) {
+        let page = terminal_size().0.saturating_sub(1).max(1);
+        let mut state = self.state.lock().unwrap();
+        state.message = None;
+        let panes = state.panes.len();
+        let selected = state.selected;
+        let pane = &mut state.panes[selected];
+        match key {
+            Key::PageUp => pane.scroll += page,
+            Key::PageDown => pane.scroll = pane.scroll.saturating_sub(page),
+            Key::Home => pane.scroll = pane.lines.len(),
+            Key::End => pane.scroll = 0,
+            _ => {}
+        }
+        pane.scroll = pane.scroll.min(pane.lines.len());
+        match key {
+            Key::Up => state.selected = (state.selected + panes - 1) % panes,
+            Key::Down => state.selected = (state.selected + 1) % panes,
+            Key::Char(digit @ b'0'..=b'9') if ((digit - b'0') as usize) < panes => {
+                state.selected = (digit - b'0') as usize
+            }
+            Key::PageUp | Key::PageDown | Key::Home | Key::End => {}
+            Key::Char(key @ (b'r' | b'k')) => {
+                let selected = state.selected.checked_sub(1);
+                let label = selected.and_then(|i| state.labels.get(i)).cloned();
+                state.message = Some(match label {
+                    None => "select a command to restart or kill it".to_string(),
+                    Some(label) => {
+                        let (request, action) = match key {
+                            b'r' => (ControlRequest::Restart { label }, "restarting"),
+                            _ => (ControlRequest::Kill { label }, "killing"),
+                        };
+                        let label = state.labels[selected.unwrap_or_default()].clone();
+                        match respond(request, commands, stop).error {
+                            Some(error) => error,
+                            None => format!("{} command[{}]", action, label),
+                        }
+                    }
+                });
+            }
+            Key::Char(b'q') => {
+                state.message = Some("stopping all commands".to_string());
+                drop(state);
+                stop();
+            }
+            Key::Char(_) => {}
+        }
+    }
+
+    /// Shows the UI on the terminal until the returned guard is dropped.
+    ///
+    /// Lines of the commands come from `broadcast`, `commands` are restarted or killed by
+    /// their labels and get a pane once they are added. `stop` is called when q is pressed.
+    pub fn start(
+        self: &Arc<Self>,
+        broadcast: Arc<OutputBroadcast>,
+        commands: Arc<dyn ControlledCommands>,
+        stop: impl Fn() + Send + Sync + 'static,
+    ) -> TuiGuard {
+        let labels = self.state.lock().unwrap().labels.clone();
+        for (i, label) in labels.iter().enumerate() {
+            self.follow(i, label, &broadcast);
+        }
+
+        let mode = read_keys();
+        let tui = self.clone();
+        let keys_commands = commands.clone();
+        std::thread::spawn(move || {
+            let mut keys = Keys::new(io::stdin());
+            while let Some(key) = keys.read_key() {
+                if tui.closed.load(Ordering::SeqCst) {
+                    return;
+                }
+                tui.handle_key(key, &*keys_commands, &stop);
+            }
+        });
+
+        // the alternate screen keeps the terminal as it was, hiding the cursor
+        write_stdout("\x1b[?1049h\x1b[?25l");
+        let tui = self.clone();
+        // the panes of added commands subscribe to their lines in the runtime
+        let runtime = tokio::runtime::Handle::current();
+        let drawing = std::thread::spawn(move || {
+            let _runtime = runtime.enter();
+            let mut drawn = String::new();
+            let mut drawn_at = Instant::now();
+            while !tui.closed.load(Ordering::SeqCst) {
+                tui.add_panes(&*commands, &broadcast);
+                let (rows, columns) = terminal_size();
+                let statuses = tui.status.statuses_at(Instant::now());
+                let screen = tui.draw(rows, columns, &statuses).join("\x1b[K\r\n");
+                if screen != drawn || drawn_at.elapsed() >= REDRAW_INTERVAL {
+                    write_stdout(&format!("\x1b[H{}\x1b[K\x1b[J", screen));
+                    drawn = screen;
+                    drawn_at = Instant::now();
+                }
+                std::thread::sleep(FRAME_INTERVAL);
+            }
+        });
+
+        TuiGuard {
+            tui: self.clone(),
+            drawing: Some(drawing),
+            _mode: mode,
+        }
+    }
+}
+
+fn write_stdout(text: &str) {
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(text.as_bytes());
+    let _ = stdout.flush();
+}
+
+/// Restores the terminal when dropped, see [`Tui::start`]
+pub struct TuiGuard {
+    tui: Arc<Tui>,
+    drawing: Option<JoinHandle<()>>,
+    _mode: Option<TerminalMode>,
+}
+
+impl Drop for TuiGuard {
+    fn drop(&mut self) {
+        self.tui.closed.store(true, Ordering::SeqCst);
+        if let Some(drawing) = self.drawing.take() {
+            let _ = drawing.join();
+        }
+        write_stdout("\x1b[?25h\x1b[?1049l");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use super::{fit, Key, Keys, Tui};
+    use crate::cli::{ControlledCommands, StatusBoard, Stream};
+    use crate::run::{kill::KillResult, CommandHandle, LabeledCommandData};
+
+    struct NoCommands;
+
+    impl ControlledCommands for NoCommands {
+        fn commands(&self) -> Vec<(String, CommandHandle<LabeledCommandData>)> {
+            vec![]
+        }
+
+        fn restart(&self, _: &str) -> Option<KillResult> {
+            None
+        }
+    }
+
+    #[test]
+    fn fit_lines() {
+        assert_eq!(fit("hello world", 5, false), "hello");
+        assert_eq!(fit("hi", 5, true), "hi   ");
+        assert_eq!(
+            fit("\x1b[31merror\x1b[0m: x", 3, false),
+            "\x1b[31merr\x1b[0m"
+        );
+        // moving the cursor or clearing the screen is dropped
+        assert_eq!(fit("\x1b[2J\x1b[Ha\rb\tc", 8, false), "ab  c");
+    }
+
+    #[test]
+    fn parse_keys() {
+        let read = |input: &'static [u8]| {
+            let mut keys = Keys::new(input);
+            std::iter::from_fn(move || keys.read_key()).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            read(b"\x1b[Ar\x1b[6~\tq"),
+            [
+                Key::Up,
+                Key::Char(b'r'),
+                Key::PageDown,
+                Key::Down,
+                Key::Char(b'q')
+            ]
+        );
+
+        // Esc alone is read without waiting for the next key
+        use std::io::Read;
+        let mut keys = Keys::new((&b"\x1b"[..]).chain(&b"[A"[..]));
+        assert_eq!(keys.read_key(), Some(Key::Char(0x1b)));
+        assert_eq!(keys.read_key(), Some(Key::Char(b'[')));
+    }
+
+    #[test]
+    fn draw_panes() {
+        let status = Arc::new(StatusBoard::new());
+        let tui = Tui::new(vec!["web".to_string(), "db".to_string()], status.clone());
+        let sink = tui.sink();
+        sink(Stream::Stdout, b"[web] started\n[db ] rea");
+        sink(Stream::Stdout, b"dy\n");
+        tui.write_line(1, "started".to_string());
+        status.command_spawned("web", None, Instant::now());
+        status.command_spawned("db", None, Instant::now());
+        status.command_exited("db", Some(1));
+
+        let statuses = status.statuses_at(Instant::now());
+        let screen = tui.draw(5, 40, &statuses);
+        assert_eq!(
+            screen,
+            [
+                "\x1b[7m  all    \x1b[27m│[web] started",
+                "\x1b[33m●\x1b[39m web    │[db ] ready",
+                "\x1b[31m✗\x1b[39m db 1   │",
+                "         │",
+                "\x1b[2m↑/↓ select  PgUp/PgDn scroll  r restart \x1b[22m",
+            ]
+        );
+
+        let stop = || unreachable!();
+        tui.handle_key(Key::Down, &NoCommands, &stop);
+        let screen = tui.draw(5, 40, &statuses);
+        assert_eq!(&screen[0], "  all    │started");
+        assert_eq!(&screen[1], "\x1b[7m\x1b[33m●\x1b[39m web    \x1b[27m│");
+        tui.handle_key(Key::Up, &NoCommands, &stop);
+        tui.handle_key(Key::Char(b'k'), &NoCommands, &stop);
+        assert!(tui.draw(5, 40, &statuses)[4].contains("select a command"));
+
+        // each pane keeps how far it is scrolled
+        tui.handle_key(Key::Home, &NoCommands, &stop);
+        assert_eq!(&tui.draw(5, 40, &statuses)[0], "\x1b[7m  all    \x1b[27m│");
+        tui.handle_key(Key::Down, &NoCommands, &stop);
+        assert_eq!(&tui.draw(5, 40, &statuses)[0], "  all    │started");
+        tui.handle_key(Key::Up, &NoCommands, &stop);
+        let screen = tui.draw(5, 40, &statuses);
+        assert_eq!(&screen[0], "\x1b[7m  all    \x1b[27m│");
+        assert!(screen[4].contains("scrolled up 2 lines"));
+    }
+}
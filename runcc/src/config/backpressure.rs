@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+/// What happens to output of commands while the terminal doesn't read it,
+/// e.g. because it is paused or piped into `less`
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputBackpressure {
+    /// Commands wait until the terminal read their queued lines, like with a full pipe
+    #[default]
+    Block,
+    /// Lines which don't fit in the queue are dropped
+    Drop,
+    /// Lines which don't fit in the queue are kept in memory
+    Buffer,
+}
+
+impl std::str::FromStr for OutputBackpressure {
+    type Err = serde_yaml::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_yaml::from_str(s)
+    }
+}
@@ -1,15 +1,269 @@
+use std::borrow::Cow;
+
 use serde::{Deserialize, Serialize};
 
+use super::{
+    EarlyExitBackoff, FailureHook, HealthCheck, LineExtractor, LogColor, PortWait, ResourceLimits,
+    RestartPolicy, RestartRateLimit, Signal, SpawnRetries, StartupProbe, StartupRetries,
+    StreamRoute,
+};
 use crate::env::match_program_with_envs;
 
 #[non_exhaustive]
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
 pub struct CommandConfig {
+    /// Empty for commands with a [`script`](Self::script).
+    ///
+    /// `program`, `args` and `cwd` may contain variables like `${PORT}` in config files,
+    /// see [`RunConfig::expand_vars`](crate::RunConfig::expand_vars).
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub program: String,
     pub args: Option<Vec<String>>,
+    /// A multi-line script run instead of `program`, from a temporary file removed on exit.
+    ///
+    /// Run by its shebang if it starts with one and by `sh`, or `cmd` on Windows, otherwise.
+    pub script: Option<String>,
+    /// The shell running the `script`, or `program` and `args` quoted and joined by spaces, as
+    /// its last argument, e.g. `bash -c` or `pwsh -c`. `-c`, or `/C` for `cmd`, is added to a shell
+    /// without arguments.
+    pub shell: Option<String>,
     pub label: Option<String>,
+    /// The color of the label, e.g. `red`, instead of the one picked for the command
+    pub color: Option<LogColor>,
+    /// Set after the `envs` of the [`RunConfig`](crate::RunConfig), so they take precedence.
+    ///
+    /// A list of pairs like `[[PORT, "3000"]]` or a map like `{ PORT = "3000" }`.
+    #[serde(default, deserialize_with = "envs::deserialize")]
     pub envs: Option<Vec<(String, String)>>,
+    /// A `.env` file whose vars are set before `envs`, relative to the directory runcc
+    /// is run in, see [`RunConfig::env_file`](crate::RunConfig::env_file)
+    pub env_file: Option<String>,
+    /// Whether the command inherits the environment of runcc and the `envs` of the
+    /// [`RunConfig`](crate::RunConfig). If `false`, it only gets its own `envs`,
+    /// e.g. for hermetic builds. Defaults to `true`.
+    pub inherit_env: Option<bool>,
+    /// Relative to the directory runcc is run in
     pub cwd: Option<String>,
+    /// Name of the output pane this command belongs to.
+    ///
+    /// Commands with the same pane can be rendered together by a split view.
+    pub pane: Option<String>,
+    /// Retry this command when it fails shortly after the run started
+    pub startup_retries: Option<StartupRetries>,
+    /// Retry spawning this command when it fails to spawn, before it counts as failed.
+    ///
+    /// Unlike `startup_retries` and `restart`, the command didn't run and isn't reported
+    /// as exited between the attempts.
+    pub spawn_retries: Option<SpawnRetries>,
+    /// Spawn this command again with growing delays when it fails to spawn or fails within
+    /// `within` after it was spawned, e.g. because its port is still in use by a previous run.
+    ///
+    /// Takes precedence over [`RunConfig::early_exit_backoff`](crate::RunConfig::early_exit_backoff).
+    pub early_exit_backoff: Option<EarlyExitBackoff>,
+    /// Signal sent to this command whenever runcc kills it, e.g. `SIGINT` for a worker which
+    /// checkpoints on it, instead of the signal sent to all commands or killing it forcefully.
+    ///
+    /// It is killed forcefully if it still runs after the
+    /// [`shutdown_timeout`](crate::RunConfig::shutdown_timeout). See [`Signal`] for Windows.
+    pub kill_signal: Option<Signal>,
+    /// Labels of commands which must exit successfully before this command is spawned.
+    ///
+    /// If any of them fails, this command is skipped.
+    pub after: Option<Vec<String>>,
+    /// Labels of commands which must be ready, see [`ready_when`](Self::ready_when)
+    /// and [`startup_probe`](Self::startup_probe), before this command is spawned.
+    ///
+    /// If any of them exits before it is ready or isn't ready in time, this command is skipped.
+    pub after_ready: Option<Vec<String>>,
+    /// Labels of commands whose readiness ends this command, e.g. a mock server which is
+    /// only needed until its client started up. This command is stopped, with its
+    /// [`kill_signal`](Self::kill_signal) if it has one, once any of them is ready or exited,
+    /// while the others keep running.
+    pub stop_when_ready: Option<Vec<String>>,
+    /// How long to wait for the commands in `after_ready`, defaults to 60s
+    #[serde(default, with = "humantime_serde")]
+    pub after_ready_timeout: Option<std::time::Duration>,
+    /// A port which must accept connections before this command is spawned, e.g. of a
+    /// database started outside of runcc: `{ port: 5432, timeout: 30s }`. `host` defaults to
+    /// `localhost` and `timeout` to 60s, after which the command is skipped.
+    ///
+    /// `port` may be the env name of an [`assign_port`](Self::assign_port), to wait for
+    /// another command listening on it.
+    pub wait_for: Option<PortWait>,
+    /// Env name of a free port picked by runcc and set as env var of the command, e.g.
+    /// `PORT`. Commands with the same name get the same port, e.g. a server and its client.
+    ///
+    /// See [`RunConfig::assign_ports`](crate::RunConfig::assign_ports)
+    pub assign_port: Option<String>,
+    /// Kill the command once a run of it took this long, e.g. `30s`. It counts as failed
+    /// and isn't restarted.
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<std::time::Duration>,
+    /// Message printed when this command exits successfully
+    pub success_message: Option<String>,
+    /// Message printed when this command fails
+    pub failure_message: Option<String>,
+    /// Also write the output lines of this command to this file, without labels.
+    /// Relative to the directory runcc is run in, takes precedence over `--log-dir`.
+    ///
+    /// The file is truncated when the command is spawned first and appended to by restarts,
+    /// see [`RunConfig::log_file_max_size`](crate::RunConfig::log_file_max_size) to rotate it.
+    pub log_file: Option<String>,
+    /// Tags to select this command with `--tag` and `--exclude-tag`
+    pub tags: Option<Vec<String>>,
+    /// Regex matched against output lines, the command is ready once a line matches
+    pub ready_when: Option<String>,
+    /// Shows a value of each output line next to the label, e.g. the module of structured logs.
+    ///
+    /// Lines without a value only have the label.
+    pub label_from: Option<LineExtractor>,
+    /// Regex matching progress in output lines, capturing a percentage like `(\d+)%`
+    /// or done and total like `\[(\d+)/(\d+)\]`.
+    ///
+    /// On a terminal, matching lines show a progress bar after the label and replace the
+    /// previous one of the command if nothing was printed in between.
+    pub progress_from: Option<String>,
+    /// The command is ready once this probe succeeds
+    pub startup_probe: Option<StartupProbe>,
+    /// Restart the command once it is unhealthy, e.g.
+    /// `{ url: "http://localhost:3000/health", interval: 5s, retries: 3 }`
+    pub healthcheck: Option<HealthCheck>,
+    pub restart: Option<RestartPolicy>,
+    /// Pause restarts for a while once there were too many of them, e.g.
+    /// `{ max: 5, within: 1m }`, instead of restarting a broken command in a tight loop
+    pub restart_rate_limit: Option<RestartRateLimit>,
+    /// Run the command this many times one after another, regardless of how each run exits,
+    /// e.g. for soak tests.
+    ///
+    /// Each run counts as a command of its own in the report. Unlike `restart`, a run which
+    /// runcc killed ends the repetitions. Retries and restarts happen within each run.
+    pub repeat: Option<u32>,
+    /// Run the command whenever this cron expression in local time matches, e.g. `*/5 * * * *`
+    /// for every 5 minutes, while the other commands keep running, see [`CronSchedule`](crate::CronSchedule).
+    ///
+    /// Each run counts as a command of its own like with [`repeat`](Self::repeat), and its
+    /// exit never makes [`RunConfig::kill`](crate::RunConfig::kill) kill the other commands.
+    /// A run is skipped if the previous one still runs.
+    pub schedule: Option<String>,
+    /// Like [`schedule`](Self::schedule), run the command right away and then every this long,
+    /// e.g. `30s`, counted from when the first run was spawned
+    #[serde(default, with = "humantime_serde")]
+    pub every: Option<std::time::Duration>,
+    /// Run once this command failed for good, i.e. it won't be restarted.
+    ///
+    /// Not run if runcc killed the command or it couldn't be spawned.
+    pub on_failure: Option<FailureHook>,
+    /// Restart this command when files under its `cwd` change
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub watch_dir: bool,
+    /// Restart this command when files matching these globs relative to `cwd` change,
+    /// e.g. `["src/**/*.rs", "Cargo.toml"]`.
+    ///
+    /// Short for `watch_dir` with these globs in `watch_include`.
+    pub watch: Option<Vec<String>>,
+    /// Globs relative to `cwd` of files to watch, defaults to all files
+    pub watch_include: Option<Vec<String>>,
+    /// Globs relative to `cwd` of files not to watch, e.g. `target/**`
+    pub watch_exclude: Option<Vec<String>>,
+    /// A background helper which is killed once all other commands exited,
+    /// see [`RunConfig::exit_when_only_optional_remain`](crate::RunConfig::exit_when_only_optional_remain)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub optional: bool,
+    /// Kill all other commands once this one exited for good, whatever
+    /// [`RunConfig::kill`](crate::RunConfig::kill) says, e.g. for a server the others need
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub critical: bool,
+    /// The exit of this command is expected, so it never makes
+    /// [`RunConfig::kill`](crate::RunConfig::kill) kill the other commands, e.g. for a migration
+    /// which runs once while the others keep running
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub transient: bool,
+    /// Read the stdin of runcc, e.g. for a REPL or a command asking for input.
+    ///
+    /// Only one command may read stdin, either this one or
+    /// [`RunConfig::foreground`](crate::RunConfig::foreground).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interactive: bool,
+    /// Pass output through as bytes instead of decoding lines as UTF-8,
+    /// for commands printing binary data
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub binary_safe: bool,
+    /// Write stdout and stderr to a pseudo-terminal instead of pipes, for programs which
+    /// only print colors and progress on a terminal. The terminal has the size of the
+    /// terminal of runcc and is resized with it. Unix only.
+    ///
+    /// Both are read as stdout then, so [`stderr`](Self::stderr) has no effect.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pty: bool,
+    /// Where the lines of stdout go, e.g. `discard` to keep only the errors of a noisy
+    /// command or `file` to only write them to its `log_file`. Printed by default.
+    pub stdout: Option<StreamRoute>,
+    /// Like [`stdout`](Self::stdout), for stderr, e.g. `merge` to print it to stdout
+    pub stderr: Option<StreamRoute>,
+    /// Short for `stdout: discard`, `stdout` takes precedence
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub discard_stdout: bool,
+    /// Short for `stderr: discard`, `stderr` takes precedence
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub discard_stderr: bool,
+    /// Regexes of output lines which aren't logged, e.g. `["^webpack compiled"]`.
+    ///
+    /// Dropped lines still make the command ready and report progress, but they are left
+    /// out of log files and `--format json` as well, like lines filtered by the others below.
+    pub ignore: Option<Vec<String>>,
+    /// Regexes of the only output lines which are logged, e.g. `["^error", "^warning"]`
+    pub show_only: Option<Vec<String>>,
+    /// Remove ANSI escapes like colors from output lines before they are matched and logged
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strip_ansi: bool,
+    /// Cut output lines after this many characters, marked by `…`
+    pub max_line_length: Option<usize>,
+    /// Don't print the `exited with status` line when the command succeeds, e.g. for
+    /// setup steps which are expected to exit. Failures are still printed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub quiet_exit: bool,
+    /// Run the command under a minimal init which reaps its orphaned descendants
+    /// and kills the remaining ones when the command exits. Only supported on Linux.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub init: bool,
+    /// Reset SIGPIPE to its default disposition, so that the command is terminated
+    /// when it writes to a closed pipe, e.g. `yes | head`.
+    /// If `false`, SIGPIPE stays ignored like in runcc. Defaults to `true`, only used on Unix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reset_sigpipe: Option<bool>,
+    /// Encoding of the output, decoded to UTF-8 before it is logged.
+    /// One of `utf-8`, `latin1` and `windows-1252`, defaults to `utf-8`.
+    /// Other encodings like Shift-JIS aren't supported, the config is invalid with them.
+    pub encoding: Option<String>,
+    /// Run the command with a fake clock via libfaketime, e.g. `2024-01-01 12:00:00` or `+2d`.
+    ///
+    /// Sets `FAKETIME` and preloads libfaketime if it is installed. Only supported on Unix.
+    pub faketime: Option<String>,
+    /// Limit the memory and CPU the command may use and lower its priority, e.g.
+    /// `{ memory: 512M, cpu: 50%, nice: 10 }`, see [`ResourceLimits`] for how they are enforced
+    pub limits: Option<ResourceLimits>,
+}
+
+/// Deserializes [`CommandConfig::envs`] from a list of pairs or a map
+pub(super) mod envs {
+    use serde::{Deserialize, Deserializer};
+    use std::collections::BTreeMap;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Envs {
+        Pairs(Vec<(String, String)>),
+        Map(BTreeMap<String, String>),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Option<Vec<(String, String)>>, D::Error> {
+        Ok(Option::<Envs>::deserialize(d)?.map(|envs| match envs {
+            Envs::Pairs(pairs) => pairs,
+            Envs::Map(map) => map.into_iter().collect(),
+        }))
+    }
 }
 
 #[non_exhaustive]
@@ -18,24 +272,60 @@ pub struct CommandConfigFromScriptOptions {
     pub windows_call_cmd_with_env: super::WindowsCallCmdWithEnv,
 }
 
+/// `pre_exec` of `$cmd_type` is a method of the trait `$pre_exec` if given, else its own
 macro_rules! def_into_command_and_label {
-    ($name:ident -> $cmd_type:ty) => {
-        pub fn $name<I, K, V>(self, inherited_envs: Option<I>) -> ($cmd_type, String)
+    ($name:ident -> $cmd_type:ty $(, pre_exec from $pre_exec:path)?) => {
+        pub fn $name<I, K, V>(mut self, inherited_envs: Option<I>) -> ($cmd_type, String)
         where
             I: IntoIterator<Item = (K, V)>,
             K: AsRef<std::ffi::OsStr>,
             V: AsRef<std::ffi::OsStr>,
         {
+            self.resolve_shell();
             let Self {
                 program,
                 args,
                 label,
                 envs,
+                inherit_env,
                 cwd,
+                init,
+                reset_sigpipe,
+                faketime,
+                limits,
+                ..
             } = self;
+            $(
+                #[cfg(unix)]
+                use $pre_exec;
+            )?
 
             let mut command = <$cmd_type>::new(&program);
 
+            if init {
+                #[cfg(target_os = "linux")]
+                unsafe { command.pre_exec(crate::run::init::reap_as_init) };
+            }
+
+            // std resets SIGPIPE of children to the default disposition
+            if reset_sigpipe == Some(false) {
+                #[cfg(unix)]
+                unsafe {
+                    command.pre_exec(|| {
+                        libc::signal(libc::SIGPIPE, libc::SIG_IGN);
+                        Ok(())
+                    })
+                };
+            }
+
+            // enforced by the job object of the command on Windows
+            #[cfg(unix)]
+            if let Some(limits) = limits {
+                unsafe { command.pre_exec(move || super::apply_limits(&limits)) };
+            }
+            #[cfg(not(unix))]
+            let _ = limits;
+
             if let Some(cwd) = cwd {
                 command.current_dir(cwd);
             }
@@ -44,7 +334,9 @@ macro_rules! def_into_command_and_label {
                 command.args(args);
             }
 
-            if let Some(envs) = inherited_envs {
+            if inherit_env == Some(false) {
+                command.env_clear();
+            } else if let Some(envs) = inherited_envs {
                 command.envs(envs);
             }
 
@@ -52,6 +344,13 @@ macro_rules! def_into_command_and_label {
                 command.envs(envs);
             }
 
+            #[cfg(unix)]
+            if let Some(faketime) = &faketime {
+                command.envs(super::faketime_envs(faketime));
+            }
+            #[cfg(not(unix))]
+            let _ = faketime;
+
             let label = label.unwrap_or_else(move || {
                 if let Some(args) = args {
                     format!("{} {}", program, args.join(" "))
@@ -65,6 +364,24 @@ macro_rules! def_into_command_and_label {
     };
 }
 
+/// `arg` quoted for a POSIX shell if it needs to be
+pub(crate) fn quote(arg: &str) -> Cow<'_, str> {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return Cow::Borrowed(arg);
+    }
+    Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
+}
+
+/// `arg` quoted for `cmd` if it needs to be, without any `"` as `cmd` can't escape them
+fn quote_for_cmd(arg: &str) -> Cow<'_, str> {
+    let plain = |c: char| !c.is_whitespace() && !"\"&|<>^()%!".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return Cow::Borrowed(arg);
+    }
+    Cow::Owned(format!("\"{}\"", arg.replace('"', "")))
+}
+
 impl CommandConfig {
     pub fn from_script(script: &str, options: &CommandConfigFromScriptOptions) -> CommandConfig {
         let script = script.trim();
@@ -72,15 +389,11 @@ impl CommandConfig {
         let (program, envs) = match_program_with_envs(script);
 
         let program = program.to_string();
-        let envs = if let Some(envs) = envs {
-            Some(
-                envs.into_iter()
-                    .map(|(k, v)| (k.to_string(), v.to_string()))
-                    .collect(),
-            )
-        } else {
-            None
-        };
+        let envs = envs.map(|envs| {
+            envs.into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        });
 
         if cfg!(target_os = "windows") {
             let with_env = &options.windows_call_cmd_with_env;
@@ -97,7 +410,7 @@ impl CommandConfig {
                 args: Some(vec!["/C".to_string(), arg]),
                 label: Some(program.clone()),
                 envs,
-                cwd: None,
+                ..Default::default()
             };
 
             if let Some(env) = env {
@@ -111,7 +424,7 @@ impl CommandConfig {
                 args: Some(vec!["-c".to_string(), program.clone()]),
                 label: Some(program),
                 envs,
-                cwd: None,
+                ..Default::default()
             }
         }
     }
@@ -120,14 +433,60 @@ impl CommandConfig {
         CommandConfig {
             program,
             args,
-            label: None,
-            envs: None,
-            cwd: None,
+            ..Default::default()
+        }
+    }
+
+    /// The label of this command, defaults to program and args
+    /// or the first line of the script without its shebang
+    pub fn resolved_label(&self) -> String {
+        match &self.label {
+            Some(label) => label.clone(),
+            None if self.script.is_some() => self.script_label().to_string(),
+            None => match &self.args {
+                Some(args) => format!("{} {}", self.program, args.join(" ")),
+                None => self.program.clone(),
+            },
+        }
+    }
+
+    pub fn has_any_tag(&self, tags: &[String]) -> bool {
+        self.tags
+            .as_ref()
+            .is_some_and(|own| own.iter().any(|tag| tags.contains(tag)))
+    }
+
+    fn script_label(&self) -> &str {
+        let script = self.script.as_deref().unwrap_or_default();
+        let mut lines = script.lines().map(str::trim);
+        let mut first = lines.next().unwrap_or_default();
+        if first.starts_with("#!") {
+            first = lines.next().unwrap_or_default();
+        }
+        first
+    }
+
+    /// Where the lines of stdout go, see [`stdout`](Self::stdout)
+    pub fn stdout_route(&self) -> StreamRoute {
+        match (self.stdout, self.discard_stdout) {
+            (Some(route), _) => route,
+            (None, true) => StreamRoute::Discard,
+            (None, false) => StreamRoute::Console,
+        }
+    }
+
+    /// Where the lines of stderr go, see [`stderr`](Self::stderr)
+    pub fn stderr_route(&self) -> StreamRoute {
+        match (self.stderr, self.discard_stderr) {
+            (Some(route), _) => route,
+            (None, true) => StreamRoute::Discard,
+            (None, false) => StreamRoute::Console,
         }
     }
 
     pub fn label_length(&self) -> usize {
         match &self.label {
+            None if self.script.is_some() => self.script_label().len(),
             None => {
                 self.program.len()
                     + self
@@ -139,12 +498,265 @@ impl CommandConfig {
         }
     }
 
+    /// Runs the command line by its [`shell`](Self::shell) if it has one, i.e. makes the shell
+    /// the `program` and the script or `program` and `args` its last argument.
+    ///
+    /// The label stays the one of the command line.
+    pub fn resolve_shell(&mut self) {
+        let shell = match self.shell.take() {
+            Some(shell) => shell,
+            None => return,
+        };
+        self.label = Some(self.resolved_label());
+
+        let mut words = shell.split_whitespace().map(str::to_string);
+        let program = words.next().unwrap_or_default();
+        let is_cmd = ["cmd", "cmd.exe"].contains(&program.to_lowercase().as_str());
+        // each word stays one argument of the program, also with spaces or metacharacters
+        let line = match self.script.take() {
+            Some(script) => script,
+            None => std::iter::once(std::mem::take(&mut self.program))
+                .chain(self.args.take().into_iter().flatten())
+                .map(|word| match is_cmd {
+                    true => quote_for_cmd(&word).into_owned(),
+                    false => quote(&word).into_owned(),
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+        };
+        self.program = program;
+        let mut args: Vec<_> = words.collect();
+        if args.is_empty() {
+            args.push(if is_cmd { "/C" } else { "-c" }.to_string());
+        }
+        args.push(line);
+        self.args = Some(args);
+    }
+
     pub fn env(&mut self, env: (String, String)) -> &mut Self {
-        self.envs.get_or_insert_with(|| vec![]).push(env);
+        self.envs.get_or_insert_with(Vec::new).push(env);
         self
     }
 
-    def_into_command_and_label! {into_command_and_label->std::process::Command}
+    def_into_command_and_label! {
+        into_command_and_label->std::process::Command,
+        pre_exec from std::os::unix::process::CommandExt
+    }
 
     def_into_command_and_label! {into_tokio_command_and_label->tokio::process::Command}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{quote, CommandConfig};
+
+    #[cfg(unix)]
+    #[test]
+    fn reset_sigpipe() {
+        // prints the exit status of `yes` after `head` closed the pipe
+        let status_of_yes = |reset_sigpipe| {
+            let config = CommandConfig {
+                program: "sh".to_string(),
+                args: Some(vec![
+                    "-c".to_string(),
+                    "(yes 2>/dev/null; echo $? >&2) | head -n 1 >/dev/null".to_string(),
+                ]),
+                reset_sigpipe,
+                ..Default::default()
+            };
+            let (mut command, _) = config.into_command_and_label(None::<[(&str, &str); 0]>);
+            let output = command.output().unwrap();
+            String::from_utf8(output.stderr).unwrap().trim().to_string()
+        };
+
+        // terminated by SIGPIPE
+        assert_eq!(status_of_yes(None), "141");
+        // failed to write
+        assert_eq!(status_of_yes(Some(false)), "1");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn resource_limits() {
+        let config = CommandConfig {
+            program: "sh".to_string(),
+            args: Some(vec!["-c".to_string(), "ulimit -d; nice".to_string()]),
+            limits: serde_yaml::from_str("{ memory: 256M, nice: 19 }").unwrap(),
+            ..Default::default()
+        };
+        let (mut command, _) = config.into_command_and_label(None::<[(&str, &str); 0]>);
+        let output = command.output().unwrap();
+        // in KiB
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "262144\n19\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn clear_inherited_env() {
+        // CARGO_MANIFEST_DIR is set by cargo for tests, i.e. in the environment of runcc
+        let env_of = |inherit_env| {
+            let config = CommandConfig {
+                program: "/bin/sh".to_string(),
+                args: Some(vec![
+                    "-c".to_string(),
+                    "echo \"$GLOBAL $OWN ${CARGO_MANIFEST_DIR:+runcc}\"".to_string(),
+                ]),
+                envs: Some(vec![("OWN".to_string(), "own".to_string())]),
+                inherit_env,
+                ..Default::default()
+            };
+            let (mut command, _) = config.into_command_and_label(Some([("GLOBAL", "global")]));
+            let output = command.output().unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        assert_eq!(env_of(None), "global own runcc\n");
+        assert_eq!(env_of(Some(false)), " own \n");
+    }
+
+    #[test]
+    fn stream_routes() {
+        use crate::StreamRoute;
+
+        let routes = |yaml| {
+            let config: CommandConfig = serde_yaml::from_str(yaml).unwrap();
+            (config.stdout_route(), config.stderr_route())
+        };
+        assert_eq!(
+            routes("program: a"),
+            (StreamRoute::Console, StreamRoute::Console)
+        );
+        assert_eq!(
+            routes("{ program: a, stdout: file, stderr: merge }"),
+            (StreamRoute::File, StreamRoute::Merge)
+        );
+        assert_eq!(
+            routes("{ program: a, discard_stdout: true, discard_stderr: true, stderr: console }"),
+            (StreamRoute::Discard, StreamRoute::Console)
+        );
+    }
+
+    #[test]
+    fn resolve_shell() {
+        let resolved = |yaml| {
+            let mut config: CommandConfig = serde_yaml::from_str(yaml).unwrap();
+            config.resolve_shell();
+            (
+                config.program,
+                config.args.unwrap_or_default(),
+                config.label,
+            )
+        };
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            resolved("{ script: 'cd web && npm start', shell: 'bash -c' }"),
+            (
+                "bash".to_string(),
+                strings(&["-c", "cd web && npm start"]),
+                Some("cd web && npm start".to_string())
+            )
+        );
+        let (program, args, label) = resolved("{ program: npm, args: [start], shell: pwsh }");
+        assert_eq!(
+            (program.as_str(), label.as_deref()),
+            ("pwsh", Some("npm start"))
+        );
+        assert_eq!(args, strings(&["-c", "npm start"]));
+        let (program, args, _) = resolved("{ program: dir, shell: cmd }");
+        assert_eq!((program.as_str(), args), ("cmd", strings(&["/C", "dir"])));
+        let (program, args, _) = resolved("{ program: npm, args: [start] }");
+        assert_eq!((program.as_str(), args), ("npm", strings(&["start"])));
+
+        // args are quoted, they don't run anything
+        let (_, args, label) =
+            resolved(r#"{ program: echo, args: ["a b", "$HOME; rm -rf x", "it's"], shell: sh }"#);
+        assert_eq!(
+            args,
+            strings(&["-c", r"echo 'a b' '$HOME; rm -rf x' 'it'\''s'"])
+        );
+        assert_eq!(label.as_deref(), Some("echo a b $HOME; rm -rf x it's"));
+        let (_, args, _) =
+            resolved(r#"{ program: type, args: ["my file.txt", "a&b"], shell: cmd }"#);
+        assert_eq!(args, strings(&["/C", r#"type "my file.txt" "a&b""#]));
+    }
+
+    #[test]
+    fn quote_args() {
+        assert_eq!(quote("--port=3000"), "--port=3000");
+        assert_eq!(quote("npm start"), "'npm start'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote(""), "''");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_by_shell() {
+        let mut config: CommandConfig = serde_yaml::from_str(
+            r#"{ program: printf, args: ["%s|", "a  b", "$HOME", "x;echo injected"], shell: sh }"#,
+        )
+        .unwrap();
+        config.resolve_shell();
+        let (mut command, _) = config.into_command_and_label(None::<[(&str, &str); 0]>);
+        let output = command.output().unwrap();
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "a  b|$HOME|x;echo injected|"
+        );
+
+        let config: CommandConfig =
+            serde_yaml::from_str("{ script: 'echo one; false; echo two', shell: 'sh -e -c' }")
+                .unwrap();
+        let (mut command, label) = config.into_command_and_label(None::<[(&str, &str); 0]>);
+        assert_eq!(label, "echo one; false; echo two");
+        let output = command.output().unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "one\n");
+    }
+
+    #[test]
+    fn deserialize_envs() {
+        let config: CommandConfig = toml::from_str(
+            r#"
+program = "npm"
+envs = { PORT = "3001", NODE_ENV = "development" }
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.envs.unwrap(),
+            [
+                ("NODE_ENV".to_string(), "development".to_string()),
+                ("PORT".to_string(), "3001".to_string())
+            ]
+        );
+
+        let config: CommandConfig = toml::from_str(
+            r#"
+program = "npm"
+envs = [["PORT", "3001"]]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.envs.unwrap(),
+            [("PORT".to_string(), "3001".to_string())]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn faketime_env() {
+        let config = CommandConfig {
+            program: "sh".to_string(),
+            args: Some(vec!["-c".to_string(), "echo \"$FAKETIME\"".to_string()]),
+            faketime: Some("@2024-01-01 12:00:00".to_string()),
+            ..Default::default()
+        };
+        let (mut command, _) = config.into_command_and_label(None::<[(&str, &str); 0]>);
+        let output = command.output().unwrap();
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "@2024-01-01 12:00:00\n"
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use std::{error, fmt::Display, io, process::ExitStatus, process::Stdio};
+
+use super::RunConfig;
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum ComputedEnvError {
+    /// The helper command of an env var could not be run
+    Spawn { name: String, error: io::Error },
+    /// The helper command of an env var exited unsuccessfully
+    Failed { name: String, status: ExitStatus },
+}
+
+impl Display for ComputedEnvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComputedEnvError::Spawn { name, error } => {
+                write!(f, "failed to run helper of env var {}: {}", name, error)
+            }
+            ComputedEnvError::Failed { name, status } => {
+                write!(f, "helper of env var {} exited with {}", name, status)
+            }
+        }
+    }
+}
+
+impl error::Error for ComputedEnvError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ComputedEnvError::Spawn { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl RunConfig {
+    /// Run the helper of each env var in [`computed_env`](Self::computed_env)
+    /// and add its trimmed stdout to [`envs`](Self::envs).
+    ///
+    /// Helpers see the envs resolved so far,
+    /// they should run before [`spawn_from_run_config_with_plugin`](crate::run::spawn_from_run_config_with_plugin).
+    pub async fn resolve_computed_env(&mut self) -> Result<(), ComputedEnvError> {
+        for (name, helper) in std::mem::take(&mut self.computed_env) {
+            let (mut command, _) = helper.into_tokio_command_and_label(self.envs.as_ref());
+
+            let output = command
+                .stdin(Stdio::null())
+                .stderr(Stdio::inherit())
+                .output()
+                .await;
+
+            let output = match output {
+                Ok(output) => output,
+                Err(error) => return Err(ComputedEnvError::Spawn { name, error }),
+            };
+
+            if !output.status.success() {
+                return Err(ComputedEnvError::Failed {
+                    name,
+                    status: output.status,
+                });
+            }
+
+            let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            self.envs
+                .get_or_insert_with(Default::default)
+                .insert(name, value);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+use super::{CommandConfig, FailureHook, RestartPolicy, SpawnRetries, StartupRetries};
+
+/// Values of the `defaults` table of a config, used by every command which doesn't set them
+/// itself. See [`CommandConfig`] for what they do.
+#[non_exhaustive]
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct CommandDefaults {
+    /// Merged with the `envs` of each command, whose values take precedence
+    #[serde(default, deserialize_with = "super::command::envs::deserialize")]
+    pub envs: Option<Vec<(String, String)>>,
+    pub inherit_env: Option<bool>,
+    pub cwd: Option<String>,
+    pub startup_retries: Option<StartupRetries>,
+    pub spawn_retries: Option<SpawnRetries>,
+    #[serde(default, with = "humantime_serde")]
+    pub after_ready_timeout: Option<std::time::Duration>,
+    pub restart: Option<RestartPolicy>,
+    pub on_failure: Option<FailureHook>,
+    pub reset_sigpipe: Option<bool>,
+    pub encoding: Option<String>,
+    pub faketime: Option<String>,
+}
+
+impl CommandDefaults {
+    pub fn apply_to(&self, command: &mut CommandConfig) {
+        fn or_default<T: Clone>(value: &mut Option<T>, default: &Option<T>) {
+            if value.is_none() {
+                *value = default.clone();
+            }
+        }
+
+        if let Some(default_envs) = &self.envs {
+            let own = command.envs.take().unwrap_or_default();
+            let mut envs: Vec<_> = default_envs
+                .iter()
+                .filter(|(name, _)| !own.iter().any(|(own, _)| own == name))
+                .cloned()
+                .collect();
+            envs.extend(own);
+            command.envs = Some(envs);
+        }
+        or_default(&mut command.inherit_env, &self.inherit_env);
+        or_default(&mut command.cwd, &self.cwd);
+        or_default(&mut command.startup_retries, &self.startup_retries);
+        or_default(&mut command.spawn_retries, &self.spawn_retries);
+        or_default(&mut command.after_ready_timeout, &self.after_ready_timeout);
+        or_default(&mut command.restart, &self.restart);
+        or_default(&mut command.on_failure, &self.on_failure);
+        or_default(&mut command.reset_sigpipe, &self.reset_sigpipe);
+        or_default(&mut command.encoding, &self.encoding);
+        or_default(&mut command.faketime, &self.faketime);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RestartPolicy, RunConfig, RunConfigInput};
+
+    #[test]
+    fn apply_defaults() {
+        let input: RunConfigInput = toml::from_str(
+            r#"
+[defaults]
+cwd = "./services"
+envs = { RUST_LOG = "info", PORT = "3000" }
+restart = { OnFailure = { max = 3 } }
+
+[[commands]]
+program = "api"
+
+[[commands]]
+program = "web"
+cwd = "./web"
+envs = { PORT = "8080" }
+restart = { Always = {} }
+
+[[commands]]
+program = "migrate"
+restart = "never"
+"#,
+        )
+        .unwrap();
+        let config: RunConfig = input.into();
+        let (api, web) = (&config.commands[0], &config.commands[1]);
+
+        assert_eq!(api.cwd.as_deref(), Some("./services"));
+        let on_failure = RestartPolicy::OnFailure {
+            max: Some(3),
+            backoff_schedule: vec![],
+        };
+        assert_eq!(api.restart, Some(on_failure));
+        let envs = |pairs: &[(&str, &str)]| {
+            let pairs = pairs.iter();
+            Some(pairs.map(|(k, v)| (k.to_string(), v.to_string())).collect())
+        };
+        assert_eq!(api.envs, envs(&[("PORT", "3000"), ("RUST_LOG", "info")]));
+
+        assert_eq!(web.cwd.as_deref(), Some("./web"));
+        assert!(matches!(web.restart, Some(RestartPolicy::Always { .. })));
+        assert_eq!(web.envs, envs(&[("RUST_LOG", "info"), ("PORT", "8080")]));
+
+        assert_eq!(config.commands[2].restart, Some(RestartPolicy::Never));
+    }
+}
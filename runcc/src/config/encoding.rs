@@ -0,0 +1,65 @@
+/// Encoding of the output of a command, see [`CommandConfig::encoding`](super::CommandConfig::encoding)
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputEncoding {
+    Utf8,
+    /// ISO-8859-1, each byte is the code point of the same value
+    Latin1,
+    /// The codepage of western european Windows systems
+    Windows1252,
+}
+
+/// Chars of windows-1252 bytes 0x80 to 0x9f, bytes which are undefined
+/// map to the control char of the same value like in Latin-1
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+impl OutputEncoding {
+    /// Looks up an encoding by name, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(OutputEncoding::Utf8),
+            "iso-8859-1" | "latin1" => Some(OutputEncoding::Latin1),
+            "windows-1252" | "cp1252" => Some(OutputEncoding::Windows1252),
+            _ => None,
+        }
+    }
+
+    /// Decodes `bytes` to UTF-8, fails only for invalid UTF-8
+    pub fn decode(&self, bytes: Vec<u8>) -> Result<String, std::string::FromUtf8Error> {
+        match self {
+            OutputEncoding::Utf8 => String::from_utf8(bytes),
+            OutputEncoding::Latin1 => Ok(bytes.into_iter().map(char::from).collect()),
+            OutputEncoding::Windows1252 => Ok(bytes
+                .into_iter()
+                .map(|b| match b {
+                    0x80..=0x9f => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+                    _ => char::from(b),
+                })
+                .collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OutputEncoding;
+
+    #[test]
+    fn decode_windows_1252() {
+        let encoding = OutputEncoding::from_name("CP1252").unwrap();
+        assert_eq!(
+            encoding
+                .decode(b"caf\xe9 \x80 5 \x93ok\x94".to_vec())
+                .unwrap(),
+            "café € 5 “ok”"
+        );
+        assert_eq!(
+            OutputEncoding::Latin1.decode(b"caf\xe9".to_vec()).unwrap(),
+            "café"
+        );
+        assert!(OutputEncoding::Utf8.decode(b"caf\xe9".to_vec()).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use std::{error, fmt::Display, io};
+
+use super::RunConfig;
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum EnvFileError {
+    /// The env file could not be read
+    Read { path: String, error: io::Error },
+    /// A line of the env file is no `NAME=value` assignment
+    Parse { path: String, line: usize },
+}
+
+impl Display for EnvFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnvFileError::Read { path, error } => {
+                write!(f, "failed to read env file {}: {}", path, error)
+            }
+            EnvFileError::Parse { path, line } => {
+                write!(f, "line {} of env file {} is no NAME=value", line, path)
+            }
+        }
+    }
+}
+
+impl error::Error for EnvFileError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            EnvFileError::Read { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// The value of an assignment, without its quotes or a trailing comment
+fn parse_value(value: &str) -> String {
+    let value = value.trim();
+    let quoted = |quote| value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote);
+    if quoted('\'') {
+        return value[1..value.len() - 1].to_string();
+    }
+    if quoted('"') {
+        let mut unescaped = String::with_capacity(value.len());
+        let mut chars = value[1..value.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            match (c, chars.clone().next()) {
+                ('\\', Some('n')) => unescaped.push('\n'),
+                ('\\', Some(c @ ('"' | '\\'))) => unescaped.push(c),
+                (c, _) => {
+                    unescaped.push(c);
+                    continue;
+                }
+            }
+            chars.next();
+        }
+        return unescaped;
+    }
+    match value.find(" #") {
+        Some(comment) => value[..comment].trim_end().to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// Parses the `NAME=value` lines of a `.env` file in order.
+///
+/// Blank lines and lines starting with `#` are skipped, names may be prefixed by `export`.
+/// Values may be quoted, `\n`, `\"` and `\\` are unescaped in double quotes.
+/// Returns the number of the first line which is no assignment on errors.
+pub fn parse_env_file(content: &str) -> Result<Vec<(String, String)>, usize> {
+    let mut envs = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (name, value) = line.split_once('=').ok_or(i + 1)?;
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(i + 1);
+        }
+        envs.push((name.to_string(), parse_value(value)));
+    }
+    Ok(envs)
+}
+
+fn read_env_file(path: &str) -> Result<Vec<(String, String)>, EnvFileError> {
+    let content = std::fs::read_to_string(path).map_err(|error| EnvFileError::Read {
+        path: path.to_string(),
+        error,
+    })?;
+    parse_env_file(&content).map_err(|line| EnvFileError::Parse {
+        path: path.to_string(),
+        line,
+    })
+}
+
+impl RunConfig {
+    /// Add the env vars of [`env_file`](Self::env_file) to [`envs`](Self::envs) and those of
+    /// the [`env_file`](super::CommandConfig::env_file) of each command to its `envs`.
+    /// Vars which are set in `envs` already keep their values.
+    ///
+    /// Values may refer to secrets, so this should run before [`resolve_secrets`](Self::resolve_secrets).
+    pub fn load_env_files(&mut self) -> Result<(), EnvFileError> {
+        if let Some(path) = &self.env_file {
+            let envs = self.envs.get_or_insert_with(Default::default);
+            for (name, value) in read_env_file(path)? {
+                envs.entry(name).or_insert(value);
+            }
+        }
+
+        for cmd in &mut self.commands {
+            if let Some(path) = &cmd.env_file {
+                // later envs override earlier ones
+                let mut envs = read_env_file(path)?;
+                envs.extend(cmd.envs.take().unwrap_or_default());
+                cmd.envs = Some(envs);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_env_file;
+    use crate::{RunConfig, RunConfigInput};
+
+    #[test]
+    fn parse_dotenv() {
+        let envs = parse_env_file(
+            r#"
+# database
+export DATABASE_URL=postgres://localhost/app
+PORT = 3000 # of the api
+GREETING="hello \"world\"\nbye"
+RAW='a\nb # c'
+EMPTY=
+"#,
+        )
+        .unwrap();
+        let pairs: Vec<(&str, &str)> = envs
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                ("DATABASE_URL", "postgres://localhost/app"),
+                ("PORT", "3000"),
+                ("GREETING", "hello \"world\"\nbye"),
+                ("RAW", "a\\nb # c"),
+                ("EMPTY", ""),
+            ]
+        );
+
+        assert_eq!(parse_env_file("A=1\n\nnot an assignment\n"), Err(3));
+        assert_eq!(parse_env_file("MY VAR=1"), Err(1));
+    }
+
+    #[test]
+    fn load_env_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let global = dir.path().join(".env");
+        std::fs::write(&global, "RUST_LOG=debug\nPORT=3000\n").unwrap();
+        let web = dir.path().join("web.env");
+        std::fs::write(&web, "PORT=8080\nAPI_URL=http://localhost:3000\n").unwrap();
+
+        let yaml = format!(
+            r#"
+env_file: {}
+envs: {{ RUST_LOG: info }}
+commands:
+  api: cargo run
+  web:
+    program: npm
+    env_file: {}
+    envs: {{ API_URL: http://api }}
+"#,
+            global.display(),
+            web.display()
+        );
+        let input: RunConfigInput = serde_yaml::from_str(&yaml).unwrap();
+        let mut config: RunConfig = input.into();
+        config.commands.sort_by_key(|cmd| cmd.resolved_label());
+        config.load_env_files().unwrap();
+
+        let envs = config.envs.as_ref().unwrap();
+        assert_eq!(envs["RUST_LOG"], "info");
+        assert_eq!(envs["PORT"], "3000");
+        let web_envs = config.commands[1].envs.as_ref().unwrap();
+        let value = |name: &str| {
+            // the last one is set
+            let mut values = web_envs.iter().filter(|(n, _)| n == name);
+            values.next_back().map(|(_, value)| value.as_str())
+        };
+        assert_eq!(value("PORT"), Some("8080"));
+        assert_eq!(value("API_URL"), Some("http://api"));
+        assert!(config.commands[0].envs.is_none());
+
+        config.env_file = Some(dir.path().join("missing").display().to_string());
+        assert!(config.load_env_files().is_err());
+    }
+}
@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+
+/// Which exit code runcc exits with once all commands stopped, 0 if all of them succeeded
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExitCodePolicy {
+    /// The code of the command which failed first
+    #[default]
+    FirstFailure,
+    /// The code of the command which stopped on its own last, 0 if it succeeded,
+    /// even if others failed before
+    Last,
+    /// 1 if any command failed, regardless of their codes
+    SuccessOnly,
+}
+
+impl ExitCodePolicy {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl std::str::FromStr for ExitCodePolicy {
+    type Err = serde_yaml::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_yaml::from_str(s)
+    }
+}
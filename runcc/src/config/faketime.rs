@@ -0,0 +1,78 @@
+//! Fake the clock of commands with [libfaketime](https://github.com/wolfcw/libfaketime),
+//! see [`CommandConfig::faketime`](super::CommandConfig::faketime)
+
+/// Where package managers install libfaketime
+#[cfg(target_os = "macos")]
+const LIBRARY_PATHS: &[&str] = &[
+    "/opt/homebrew/lib/faketime/libfaketime.1.dylib",
+    "/usr/local/lib/faketime/libfaketime.1.dylib",
+];
+#[cfg(all(unix, not(target_os = "macos")))]
+const LIBRARY_PATHS: &[&str] = &[
+    "/usr/lib/x86_64-linux-gnu/faketime/libfaketime.so.1",
+    "/usr/lib/aarch64-linux-gnu/faketime/libfaketime.so.1",
+    "/usr/lib64/faketime/libfaketime.so.1",
+    "/usr/lib/faketime/libfaketime.so.1",
+    "/usr/local/lib/faketime/libfaketime.so.1",
+];
+
+/// Whether `spec` is a timestamp libfaketime understands, like
+/// `2024-01-01 12:00:00` (frozen), `@2024-01-01 12:00:00` (starting at),
+/// `+2d` or `-1.5h` (offset), each optionally followed by a speed like `x10`
+pub fn is_valid_faketime(spec: &str) -> bool {
+    let pattern = regex::Regex::new(
+        r"^(@?\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}|[+-]\d+(\.\d+)?[smhdy]?)( ?[xi]\d+(\.\d+)?)?$",
+    )
+    .expect("faketime pattern should be valid");
+    pattern.is_match(spec)
+}
+
+/// Env vars which make a command see `spec` as the current time.
+///
+/// libfaketime is preloaded if installed at a known path,
+/// otherwise commands have to load it themselves, e.g. with the `faketime` wrapper.
+#[cfg(unix)]
+pub(crate) fn faketime_envs(spec: &str) -> Vec<(&'static str, String)> {
+    let mut envs = vec![("FAKETIME", spec.to_string())];
+
+    let library = LIBRARY_PATHS
+        .iter()
+        .find(|path| std::path::Path::new(path).exists());
+    if let Some(library) = library {
+        let preload = if cfg!(target_os = "macos") {
+            envs.push(("DYLD_FORCE_FLAT_NAMESPACE", "1".to_string()));
+            "DYLD_INSERT_LIBRARIES"
+        } else {
+            "LD_PRELOAD"
+        };
+        let value = match std::env::var(preload) {
+            Ok(preloaded) if !preloaded.is_empty() => format!("{}:{}", library, preloaded),
+            _ => library.to_string(),
+        };
+        envs.push((preload, value));
+    }
+
+    envs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_valid_faketime;
+
+    #[test]
+    fn valid_faketime() {
+        for spec in [
+            "2024-01-01 12:00:00",
+            "@2024-01-01 12:00:00",
+            "+2d",
+            "-1.5h",
+            "+0 x10",
+            "@2024-01-01 00:00:00 x0.5",
+        ] {
+            assert!(is_valid_faketime(spec), "{}", spec);
+        }
+        for spec in ["tomorrow", "2024-01-01", "+2 days", ""] {
+            assert!(!is_valid_faketime(spec), "{}", spec);
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// A command run once a command failed, e.g. to collect logs or core files.
+///
+/// Its output is written to the terminal of runcc as is.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FailureHook {
+    /// Run like commands given as a string, in the `cwd` of the command
+    pub command: String,
+    /// The hook is killed if it runs longer
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+}
@@ -1,5 +1,5 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
 
 use super::super::command::*;
 
@@ -9,12 +9,16 @@ use super::super::command::*;
 pub enum CommandConfigInput {
     Command(String),
     ProgramAndArgs(Vec<String>),
-    CommandConfig(CommandConfig),
+    CommandConfig(Box<CommandConfig>),
 }
 
 impl CommandConfigInput {
     pub fn into_config(self, options: &CommandConfigFromScriptOptions) -> CommandConfig {
         match self {
+            // a blank command has no program, which validation reports
+            CommandConfigInput::Command(script) if script.trim().is_empty() => {
+                CommandConfig::default()
+            }
             CommandConfigInput::Command(script) => CommandConfig::from_script(&script, options),
             CommandConfigInput::ProgramAndArgs(mut names) => {
                 let program = if names.is_empty() {
@@ -27,7 +31,7 @@ impl CommandConfigInput {
                     if names.is_empty() { None } else { Some(names) },
                 )
             }
-            CommandConfigInput::CommandConfig(config) => config,
+            CommandConfigInput::CommandConfig(config) => *config,
         }
     }
 }
@@ -37,7 +41,41 @@ impl CommandConfigInput {
 #[serde(untagged)]
 pub enum CommandConfigsInput {
     Commands(Vec<CommandConfigInput>),
-    LabeledCommands(HashMap<String, Option<CommandConfigInput>>),
+    LabeledCommands(LabeledCommands),
+}
+
+/// Commands by their label, in the order of the config, which picks the colors of labels
+#[derive(Default)]
+pub struct LabeledCommands(pub Vec<(String, Option<CommandConfigInput>)>);
+
+impl<'de> Deserialize<'de> for LabeledCommands {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        struct CommandsVisitor;
+
+        impl<'de> de::Visitor<'de> for CommandsVisitor {
+            type Value = LabeledCommands;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map of labels to commands")
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut commands = Vec::with_capacity(map.size_hint().unwrap_or_default());
+                while let Some(entry) = map.next_entry()? {
+                    commands.push(entry);
+                }
+                Ok(LabeledCommands(commands))
+            }
+        }
+
+        d.deserialize_map(CommandsVisitor)
+    }
+}
+
+impl Serialize for LabeledCommands {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_map(self.0.iter().map(|(label, command)| (label, command)))
+    }
 }
 
 impl CommandConfigsInput {
@@ -47,7 +85,7 @@ impl CommandConfigsInput {
                 .into_iter()
                 .map(|cmd| cmd.into_config(options))
                 .collect(),
-            CommandConfigsInput::LabeledCommands(map) => map
+            CommandConfigsInput::LabeledCommands(LabeledCommands(commands)) => commands
                 .into_iter()
                 .map(|(label, command)| match command {
                     Some(command) => {
@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+use super::super::{CommandConfig, CommandConfigFromScriptOptions, CommandDefaults};
+use super::CommandConfigsInput;
+
+/// A drop-in file adding commands to a [`RunConfigInput`](super::RunConfigInput),
+/// see [`RunConfigInput::extend_commands`](super::RunConfigInput::extend_commands)
+#[non_exhaustive]
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFragmentInput {
+    pub commands: CommandConfigsInput,
+    /// Applied to the commands of this fragment before the defaults of the config
+    #[serde(default)]
+    pub defaults: CommandDefaults,
+}
+
+impl ConfigFragmentInput {
+    pub fn into_configs(self, options: &CommandConfigFromScriptOptions) -> Vec<CommandConfig> {
+        let mut commands = self.commands.into_configs(options);
+        for command in &mut commands {
+            self.defaults.apply_to(command);
+        }
+        commands
+    }
+}
@@ -1,4 +1,6 @@
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::time::Duration;
 
 use super::super::{ExitStatusPattern, KillBehavior};
 
@@ -6,6 +8,7 @@ use super::super::{ExitStatusPattern, KillBehavior};
 #[non_exhaustive]
 pub enum KillBehaviorInputStr {
     None,
+    WhenAllExited,
     WhenAnyExited,
     WhenAnySucceeded,
     WhenAnyFailed,
@@ -17,6 +20,107 @@ pub enum KillBehaviorInputStr {
 pub enum KillBehaviorInput {
     Str(KillBehaviorInputStr),
     WhenAnyExitedWithStatus(i32),
+    WhenAnyCrashedWithin {
+        #[serde(rename = "WhenAnyCrashedWithin", with = "humantime_serde")]
+        within: Duration,
+    },
+    /// e.g. `{ when_any_exited_with_status: { not: 0 } }`
+    WhenAnyExitedWithStatusPattern {
+        #[serde(
+            rename = "when_any_exited_with_status",
+            alias = "WhenAnyExitedWithStatus"
+        )]
+        status: ExitStatusPattern,
+    },
+}
+
+/// An [`ExitStatusPattern`] as written in config files
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+#[non_exhaustive]
+pub enum ExitStatusPatternInput {
+    StatusCode(i32),
+    /// `success`, `failed` or a range of status codes like `1..=2`
+    Str(String),
+    AnyOf(Vec<ExitStatusPatternInput>),
+    Not {
+        not: Box<ExitStatusPatternInput>,
+    },
+    Signal {
+        signal: i32,
+    },
+    CrashedWithin {
+        #[serde(with = "humantime_serde")]
+        crashed_within: Duration,
+    },
+    /// Any pattern as a table, e.g. `{ status: 1 }`, which lists are written with
+    /// since TOML can't mix tables with other values in a list
+    Status {
+        status: Box<ExitStatusPatternInput>,
+    },
+}
+
+impl ExitStatusPatternInput {
+    /// The pattern as a table
+    fn into_table(self) -> Self {
+        match self {
+            Self::StatusCode(_) | Self::Str(_) | Self::AnyOf(_) => Self::Status {
+                status: Box::new(self),
+            },
+            table => table,
+        }
+    }
+}
+
+impl TryFrom<ExitStatusPatternInput> for ExitStatusPattern {
+    type Error = String;
+
+    fn try_from(input: ExitStatusPatternInput) -> Result<Self, Self::Error> {
+        Ok(match input {
+            ExitStatusPatternInput::StatusCode(code) => ExitStatusPattern::StatusCode(code),
+            ExitStatusPatternInput::Str(s) => s.parse()?,
+            ExitStatusPatternInput::AnyOf(patterns) => ExitStatusPattern::AnyOf(
+                patterns
+                    .into_iter()
+                    .map(ExitStatusPattern::try_from)
+                    .collect::<Result<_, _>>()?,
+            ),
+            ExitStatusPatternInput::Not { not } => {
+                ExitStatusPattern::Not(Box::new(ExitStatusPattern::try_from(*not)?))
+            }
+            ExitStatusPatternInput::Signal { signal } => ExitStatusPattern::Signal(signal),
+            ExitStatusPatternInput::CrashedWithin { crashed_within } => {
+                ExitStatusPattern::CrashedWithin(crashed_within)
+            }
+            ExitStatusPatternInput::Status { status } => ExitStatusPattern::try_from(*status)?,
+        })
+    }
+}
+
+impl From<ExitStatusPattern> for ExitStatusPatternInput {
+    fn from(pattern: ExitStatusPattern) -> Self {
+        match pattern {
+            ExitStatusPattern::Success => Self::Str("success".to_string()),
+            ExitStatusPattern::Failed => Self::Str("failed".to_string()),
+            ExitStatusPattern::StatusCode(code) => Self::StatusCode(code),
+            ExitStatusPattern::StatusCodeRange(codes) => {
+                Self::Str(format!("{}..={}", codes.start(), codes.end()))
+            }
+            ExitStatusPattern::Signal(signal) => Self::Signal { signal },
+            ExitStatusPattern::CrashedWithin(crashed_within) => {
+                Self::CrashedWithin { crashed_within }
+            }
+            ExitStatusPattern::AnyOf(patterns) => Self::AnyOf(
+                patterns
+                    .into_iter()
+                    .map(|pattern| Self::from(pattern).into_table())
+                    .collect(),
+            ),
+            ExitStatusPattern::Not(pattern) => Self::Not {
+                not: Box::new((*pattern).into()),
+            },
+        }
+    }
 }
 
 impl From<KillBehaviorInput> for KillBehavior {
@@ -24,6 +128,7 @@ impl From<KillBehaviorInput> for KillBehavior {
         match val {
             KillBehaviorInput::Str(val) => match val {
                 KillBehaviorInputStr::None => KillBehavior::None,
+                KillBehaviorInputStr::WhenAllExited => KillBehavior::WhenAllExited,
                 KillBehaviorInputStr::WhenAnyExited => KillBehavior::WhenAnyExited,
                 KillBehaviorInputStr::WhenAnySucceeded => {
                     KillBehavior::WhenAnyExitedWithStatus(ExitStatusPattern::Success)
@@ -35,6 +140,37 @@ impl From<KillBehaviorInput> for KillBehavior {
             KillBehaviorInput::WhenAnyExitedWithStatus(s) => {
                 KillBehavior::WhenAnyExitedWithStatus(ExitStatusPattern::StatusCode(s))
             }
+            KillBehaviorInput::WhenAnyCrashedWithin { within } => {
+                KillBehavior::WhenAnyExitedWithStatus(ExitStatusPattern::CrashedWithin(within))
+            }
+            KillBehaviorInput::WhenAnyExitedWithStatusPattern { status } => {
+                KillBehavior::WhenAnyExitedWithStatus(status)
+            }
+        }
+    }
+}
+
+impl From<KillBehavior> for KillBehaviorInput {
+    fn from(val: KillBehavior) -> Self {
+        match val {
+            KillBehavior::None => Self::Str(KillBehaviorInputStr::None),
+            KillBehavior::WhenAllExited => Self::Str(KillBehaviorInputStr::WhenAllExited),
+            KillBehavior::WhenAnyExited => Self::Str(KillBehaviorInputStr::WhenAnyExited),
+            KillBehavior::WhenAnyExitedWithStatus(ExitStatusPattern::Success) => {
+                Self::Str(KillBehaviorInputStr::WhenAnySucceeded)
+            }
+            KillBehavior::WhenAnyExitedWithStatus(ExitStatusPattern::Failed) => {
+                Self::Str(KillBehaviorInputStr::WhenAnyFailed)
+            }
+            KillBehavior::WhenAnyExitedWithStatus(ExitStatusPattern::StatusCode(code)) => {
+                Self::WhenAnyExitedWithStatus(code)
+            }
+            KillBehavior::WhenAnyExitedWithStatus(ExitStatusPattern::CrashedWithin(within)) => {
+                Self::WhenAnyCrashedWithin { within }
+            }
+            KillBehavior::WhenAnyExitedWithStatus(status) => {
+                Self::WhenAnyExitedWithStatusPattern { status }
+            }
         }
     }
 }
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+use super::super::LineExtractor;
+
+/// A [`LineExtractor`] as a table like `{ regex = "..." }`, which toml can serialize
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LineExtractorInput {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    regex: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    json_field: Option<String>,
+}
+
+impl TryFrom<LineExtractorInput> for LineExtractor {
+    type Error = &'static str;
+
+    fn try_from(val: LineExtractorInput) -> Result<Self, Self::Error> {
+        match val {
+            LineExtractorInput {
+                regex: Some(regex),
+                json_field: None,
+            } => Ok(LineExtractor::Regex(regex)),
+            LineExtractorInput {
+                regex: None,
+                json_field: Some(field),
+            } => Ok(LineExtractor::JsonField(field)),
+            _ => Err("label_from needs either regex or json_field"),
+        }
+    }
+}
+
+impl From<LineExtractor> for LineExtractorInput {
+    fn from(val: LineExtractor) -> Self {
+        match val {
+            LineExtractor::Regex(regex) => Self {
+                regex: Some(regex),
+                json_field: None,
+            },
+            LineExtractor::JsonField(field) => Self {
+                regex: None,
+                json_field: Some(field),
+            },
+        }
+    }
+}
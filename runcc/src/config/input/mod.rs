@@ -1,9 +1,13 @@
 mod command;
+mod fragment;
 mod kill;
+mod label_from;
 mod run;
 mod win_cmd;
 
 pub use command::*;
+pub use fragment::*;
 pub use kill::*;
+pub use label_from::*;
 pub use run::*;
 pub use win_cmd::*;
@@ -1,35 +1,229 @@
 use serde::{Deserialize, Serialize};
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use std::time::Duration;
 
-use super::super::{run::*, CommandConfig, CommandConfigFromScriptOptions, KillBehavior};
-use super::CommandConfigsInput;
+use super::super::{
+    run::*, CommandConfig, CommandConfigFromScriptOptions, CommandDefaults, ConfigError,
+    EarlyExitBackoff, ExitCodePolicy, KillBehavior, LabelWidth, LogLevelColors, OutputBackpressure,
+    OutputTimestamps, RestartSeparator, SecretsConfig, Signal, SummaryOrder,
+};
+use super::{CommandConfigInput, CommandConfigsInput};
+
+/// How the commands of a config run, see [`RunConfigInput::mode`]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum RunMode {
+    /// At the same time, as many as [`RunConfig::max_concurrent`] allows
+    Parallel,
+    /// One after another in the order of `commands`, short for `max_concurrent: 1`
+    Sequential,
+}
 
 #[non_exhaustive]
 #[derive(Deserialize, Serialize)]
 pub struct RunConfigInput {
     pub commands: CommandConfigsInput,
+    #[serde(default)]
+    pub before: Vec<CommandConfigInput>,
+    #[serde(default)]
+    pub after: Vec<CommandConfigInput>,
+    /// Applied to each command in `commands`, before it is validated
+    #[serde(default)]
+    pub defaults: CommandDefaults,
     pub max_label_length: Option<usize>,
+    pub label_width: Option<LabelWidth>,
     pub envs: Option<HashMap<String, String>>,
+    pub env_file: Option<String>,
+    #[serde(default)]
+    pub computed_env: HashMap<String, CommandConfigInput>,
+    pub secrets: Option<SecretsConfig>,
     #[serde(default)]
     pub windows_call_cmd_with_env: super::WindowsCallCmdWithEnv,
     #[serde(default)]
     pub kill: KillBehavior,
+    #[serde(default)]
+    pub exit_when_only_optional_remain: bool,
+    #[serde(default)]
+    pub fail_on_skip: bool,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Vec<String>>,
+    pub ctrl_c_signal: Option<Signal>,
+    #[serde(default)]
+    pub ctrl_c_to_foreground: bool,
+    #[serde(default, with = "humantime_serde")]
+    pub shutdown_timeout: Option<Duration>,
+    #[serde(default, with = "humantime_serde")]
+    pub kill_grace: Option<Duration>,
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+    pub kill_signal: Option<Signal>,
+    #[serde(default)]
+    pub kill_process_groups: bool,
+    #[serde(default)]
+    pub pty: bool,
+    #[serde(default)]
+    pub pipe_stdin: bool,
+    #[serde(default)]
+    pub collapse_repeats: bool,
+    #[serde(default)]
+    pub interleave_guard: bool,
+    #[serde(default)]
+    pub group_output: bool,
+    #[serde(default, with = "humantime_serde")]
+    pub partial_line_flush: Option<Duration>,
+    pub timestamps: Option<OutputTimestamps>,
+    #[serde(default)]
+    pub defer_output_until_started: bool,
+    #[serde(default)]
+    pub output_backpressure: OutputBackpressure,
+    #[serde(default)]
+    pub summary_order: SummaryOrder,
+    #[serde(default)]
+    pub exit_code: ExitCodePolicy,
+    pub max_buffer_memory: Option<usize>,
+    pub log_file_max_size: Option<u64>,
+    pub foreground: Option<String>,
+    pub history_limit: Option<usize>,
+    pub tail_lines: Option<usize>,
+    #[serde(alias = "max_parallel")]
+    pub max_concurrent: Option<usize>,
+    /// `sequential` takes precedence over `max_concurrent`
+    pub mode: Option<RunMode>,
+    pub early_exit_backoff: Option<EarlyExitBackoff>,
+    pub system_ready_when: Option<String>,
+    pub color_seed: Option<u64>,
+    #[serde(default)]
+    pub restart_separator: RestartSeparator,
+    #[serde(default = "default_show_silent_commands")]
+    pub show_silent_commands: bool,
+    #[serde(default)]
+    pub colorize_log_levels: bool,
+    #[serde(default)]
+    pub log_level_colors: LogLevelColors,
 }
 
-impl Into<RunConfig> for RunConfigInput {
-    fn into(self) -> RunConfig {
-        let Self {
+impl RunConfigInput {
+    /// A config without commands, e.g. to add the commands of fragments to
+    pub fn empty() -> Self {
+        serde_yaml::from_str("commands: []").expect("a config without commands should parse")
+    }
+
+    /// How commands given as scripts are run
+    pub fn script_options(&self) -> CommandConfigFromScriptOptions {
+        CommandConfigFromScriptOptions {
+            windows_call_cmd_with_env: self.windows_call_cmd_with_env.clone(),
+        }
+    }
+
+    /// Appends `commands`, e.g. of a [`ConfigFragmentInput`](super::ConfigFragmentInput).
+    /// The [`defaults`](Self::defaults) of this config apply to them too.
+    ///
+    /// Fails if any of them has the label of another command, of this config or of `commands`.
+    pub fn extend_commands(&mut self, commands: Vec<CommandConfig>) -> Result<(), ConfigError> {
+        let options = self.script_options();
+        let own = mem::replace(&mut self.commands, CommandConfigsInput::Commands(vec![]));
+        let mut merged = own.into_configs(&options);
+
+        let mut labels: HashSet<_> = merged.iter().map(CommandConfig::resolved_label).collect();
+        for command in &commands {
+            let label = command.resolved_label();
+            if !labels.insert(label.clone()) {
+                self.commands = CommandConfigsInput::Commands(into_inputs(merged));
+                return Err(ConfigError::DuplicateLabel { label });
+            }
+        }
+
+        merged.extend(commands);
+        self.commands = CommandConfigsInput::Commands(into_inputs(merged));
+        Ok(())
+    }
+}
+
+fn into_inputs(commands: Vec<CommandConfig>) -> Vec<CommandConfigInput> {
+    commands
+        .into_iter()
+        .map(|command| CommandConfigInput::CommandConfig(Box::new(command)))
+        .collect()
+}
+
+impl From<RunConfigInput> for RunConfig {
+    fn from(input: RunConfigInput) -> Self {
+        let RunConfigInput {
             commands,
+            before,
+            after,
+            defaults,
             max_label_length,
+            label_width,
             envs,
+            env_file,
+            computed_env,
+            secrets,
             windows_call_cmd_with_env,
             kill,
-        } = self;
+            exit_when_only_optional_remain,
+            fail_on_skip,
+            profiles,
+            ctrl_c_signal,
+            ctrl_c_to_foreground,
+            shutdown_timeout,
+            kill_grace,
+            timeout,
+            kill_signal,
+            kill_process_groups,
+            pty,
+            pipe_stdin,
+            collapse_repeats,
+            interleave_guard,
+            group_output,
+            partial_line_flush,
+            timestamps,
+            defer_output_until_started,
+            output_backpressure,
+            summary_order,
+            exit_code,
+            max_buffer_memory,
+            log_file_max_size,
+            foreground,
+            history_limit,
+            tail_lines,
+            max_concurrent,
+            mode,
+            early_exit_backoff,
+            system_ready_when,
+            color_seed,
+            restart_separator,
+            show_silent_commands,
+            colorize_log_levels,
+            log_level_colors,
+        } = input;
 
-        let commands: Vec<CommandConfig> = commands.into_configs(&CommandConfigFromScriptOptions {
+        let max_concurrent = match mode {
+            Some(RunMode::Sequential) => Some(1),
+            Some(RunMode::Parallel) | None => max_concurrent,
+        };
+
+        let options = CommandConfigFromScriptOptions {
             windows_call_cmd_with_env,
-        });
+        };
+        let mut commands: Vec<CommandConfig> = commands.into_configs(&options);
+        for command in &mut commands {
+            defaults.apply_to(command);
+        }
+        let into_steps = |steps: Vec<CommandConfigInput>| -> Vec<CommandConfig> {
+            steps
+                .into_iter()
+                .map(|step| step.into_config(&options))
+                .collect()
+        };
+        let (before, after) = (into_steps(before), into_steps(after));
+        let computed_env = computed_env
+            .into_iter()
+            .map(|(name, helper)| (name, helper.into_config(&options)))
+            .collect();
 
         let real_max_label_length = commands
             .iter()
@@ -44,9 +238,49 @@ impl Into<RunConfig> for RunConfigInput {
 
         RunConfig {
             commands,
+            before,
+            after,
             max_label_length,
+            label_width,
             envs,
+            env_file,
+            computed_env,
+            secrets,
             kill,
+            exit_when_only_optional_remain,
+            fail_on_skip,
+            profiles,
+            ctrl_c_signal,
+            ctrl_c_to_foreground,
+            shutdown_timeout,
+            kill_grace,
+            timeout,
+            kill_signal,
+            kill_process_groups,
+            pty,
+            pipe_stdin,
+            collapse_repeats,
+            interleave_guard,
+            group_output,
+            partial_line_flush,
+            timestamps,
+            defer_output_until_started,
+            output_backpressure,
+            summary_order,
+            exit_code,
+            max_buffer_memory,
+            log_file_max_size,
+            foreground,
+            history_limit,
+            tail_lines,
+            max_concurrent,
+            early_exit_backoff,
+            system_ready_when,
+            color_seed,
+            restart_separator,
+            show_silent_commands,
+            colorize_log_levels,
+            log_level_colors,
         }
     }
 }
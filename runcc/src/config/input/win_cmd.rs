@@ -26,19 +26,21 @@ impl TryFrom<String> for EnvName {
     }
 }
 
-impl Into<String> for EnvName {
-    fn into(self) -> String {
-        self.0
+impl From<EnvName> for String {
+    fn from(env_name: EnvName) -> Self {
+        env_name.0
     }
 }
 
-impl EnvName {
-    pub fn to_string(self) -> String {
-        self.0
+impl Display for EnvName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
     }
+}
 
+impl EnvName {
     pub fn check_str(s: &str) -> bool {
-        s.len() > 0
+        !s.is_empty()
             && s.chars().enumerate().all(|(i, c)| match c {
                 'a'..='z' | 'A'..='Z' => true,
                 '0'..='9' | '_' => i > 0,
@@ -48,8 +50,9 @@ impl EnvName {
 }
 
 #[non_exhaustive]
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub enum WindowsCallCmdWithEnv {
+    #[default]
     Random,
     EnvName(EnvName),
     Disable,
@@ -73,18 +76,12 @@ impl WindowsCallCmdWithEnv {
     pub fn try_into_env_name(self) -> Option<String> {
         match self {
             Self::Random => Some(format!("RUNCC_WIN_CMD__{}", get_random_env_name())),
-            Self::EnvName(env_name) => Some(env_name.to_string()),
+            Self::EnvName(env_name) => Some(env_name.into()),
             Self::Disable => None,
         }
     }
 }
 
-impl Default for WindowsCallCmdWithEnv {
-    fn default() -> Self {
-        Self::Random
-    }
-}
-
 /// strictly validate env name [a-zA-Z][a-zA-Z0-9_]*
 #[cfg(test)]
 mod tests {
@@ -1,43 +1,143 @@
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
+use std::ops::RangeInclusive;
+use std::process::ExitStatus;
+use std::time::Duration;
 
-use super::input::KillBehaviorInput;
+use super::input::{ExitStatusPatternInput, KillBehaviorInput};
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+/// Which exits of a command match, see [`KillBehavior::WhenAnyExitedWithStatus`].
+///
+/// In config files `success`, `failed`, a status code like `1`, a range of status codes like
+/// `1..=2`, `{ signal: 9 }`, `{ crashed_within: 2s }`, a list of patterns of which any
+/// matches or `{ not: <pattern> }`.
+#[non_exhaustive]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+#[serde(try_from = "ExitStatusPatternInput", into = "ExitStatusPatternInput")]
 pub enum ExitStatusPattern {
     Success,
     Failed,
     StatusCode(i32),
+    /// Exited with a status code within this range
+    StatusCodeRange(RangeInclusive<i32>),
+    /// Killed by this signal, never matches on Windows
+    Signal(i32),
+    /// Exited within this duration after it was spawned, which is taken as a crash,
+    /// or failed to spawn
+    CrashedWithin(Duration),
+    /// Any of these patterns matches
+    AnyOf(Vec<ExitStatusPattern>),
+    /// This pattern doesn't match
+    Not(Box<ExitStatusPattern>),
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
-#[serde(from = "KillBehaviorInput")]
+impl ExitStatusPattern {
+    /// Whether the exit of a command matches, which exited with `status` after it ran for
+    /// `ran_for`. Both are `None` if it failed to spawn.
+    pub fn matches(&self, status: Option<&ExitStatus>, ran_for: Option<Duration>) -> bool {
+        match self {
+            Self::Success => status.is_some_and(ExitStatus::success),
+            Self::Failed => status.is_none_or(|status| !status.success()),
+            Self::StatusCode(code) => status.is_some_and(|status| status.code() == Some(*code)),
+            Self::StatusCodeRange(codes) => status
+                .and_then(ExitStatus::code)
+                .is_some_and(|code| codes.contains(&code)),
+            Self::Signal(signal) => status.and_then(signal_of) == Some(*signal),
+            Self::CrashedWithin(within) => ran_for.is_none_or(|ran_for| ran_for < *within),
+            Self::AnyOf(patterns) => patterns
+                .iter()
+                .any(|pattern| pattern.matches(status, ran_for)),
+            Self::Not(pattern) => !pattern.matches(status, ran_for),
+        }
+    }
+}
+
+/// The signal which killed a command
+#[cfg(unix)]
+fn signal_of(status: &ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(status)
+}
+
+/// The signal which killed a command, commands aren't killed by signals on this platform
+#[cfg(not(unix))]
+fn signal_of(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+impl std::str::FromStr for ExitStatusPattern {
+    type Err = String;
+
+    /// `success`, `failed`, a status code or a range of them like `1..=2`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let code = |code: &str| {
+            code.trim()
+                .parse()
+                .map_err(|_| format!("invalid exit status pattern `{}`", s))
+        };
+        match s {
+            "success" | "Success" => Ok(Self::Success),
+            "failed" | "Failed" | "failure" => Ok(Self::Failed),
+            _ => match s.split_once("..=") {
+                Some((first, last)) => {
+                    let codes = code(first)?..=code(last)?;
+                    match codes.is_empty() {
+                        true => Err(format!("range of status codes `{}` is empty", s)),
+                        false => Ok(Self::StatusCodeRange(codes)),
+                    }
+                }
+                None => code(s).map(Self::StatusCode),
+            },
+        }
+    }
+}
+
+impl Display for ExitStatusPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExitStatusPattern::Success => write!(f, "successfully"),
+            ExitStatusPattern::Failed => write!(f, "with failure"),
+            ExitStatusPattern::StatusCode(code) => write!(f, "with status code {}", code),
+            ExitStatusPattern::StatusCodeRange(codes) => {
+                write!(f, "with status code {} to {}", codes.start(), codes.end())
+            }
+            ExitStatusPattern::Signal(signal) => write!(f, "killed by signal {}", signal),
+            ExitStatusPattern::CrashedWithin(within) => {
+                write!(f, "within {}", humantime::format_duration(*within))
+            }
+            ExitStatusPattern::AnyOf(patterns) => {
+                for (i, pattern) in patterns.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " or ")?;
+                    }
+                    write!(f, "{}", pattern)?;
+                }
+                Ok(())
+            }
+            ExitStatusPattern::Not(pattern) => write!(f, "other than {}", pattern),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Default, Clone)]
+#[serde(from = "KillBehaviorInput", into = "KillBehaviorInput")]
 pub enum KillBehavior {
+    #[default]
     None,
+    /// Wait until every command exited on its own, killing none of them
+    WhenAllExited,
     WhenAnyExited,
     WhenAnyExitedWithStatus(ExitStatusPattern),
     // WhenLabeledExited,
 }
 
-impl Default for KillBehavior {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
 impl Display for KillBehavior {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             KillBehavior::None => write!(f, "none"),
+            KillBehavior::WhenAllExited => write!(f, "wait until all commands exited"),
             KillBehavior::WhenAnyExited => write!(f, "kill other commands when any exited"),
             KillBehavior::WhenAnyExitedWithStatus(s) => {
-                let s: std::borrow::Cow<str> = match s {
-                    ExitStatusPattern::Success => "successfully".into(),
-                    ExitStatusPattern::Failed => "with failure".into(),
-                    ExitStatusPattern::StatusCode(code) => {
-                        format!("with status code {}", code).into()
-                    }
-                };
                 write!(f, "kill other commands when any exited {}", s)
             }
         }
@@ -62,6 +162,7 @@ mod tests {
     fn deserialize_kill_behavior() {
         for (input, val) in [
             ("\"None\"", KillBehavior::None),
+            ("\"WhenAllExited\"", KillBehavior::WhenAllExited),
             ("\"WhenAnyExited\"", KillBehavior::WhenAnyExited),
             (
                 "\"WhenAnySucceeded\"",
@@ -75,9 +176,124 @@ mod tests {
                 "123",
                 KillBehavior::WhenAnyExitedWithStatus(ExitStatusPattern::StatusCode(123)),
             ),
+            (
+                r#"{"WhenAnyCrashedWithin": "2s"}"#,
+                KillBehavior::WhenAnyExitedWithStatus(ExitStatusPattern::CrashedWithin(
+                    std::time::Duration::from_secs(2),
+                )),
+            ),
         ] {
             let res: KillBehavior = serde_json::from_str(input).unwrap();
             assert_eq!(res, val);
         }
     }
+
+    #[test]
+    fn deserialize_exit_status_patterns() {
+        let pattern = |yaml: &str| match serde_yaml::from_str(yaml).unwrap() {
+            KillBehavior::WhenAnyExitedWithStatus(pattern) => pattern,
+            kill => panic!("{:?}", kill),
+        };
+
+        assert_eq!(
+            pattern("when_any_exited_with_status: { not: 0 }"),
+            ExitStatusPattern::Not(Box::new(ExitStatusPattern::StatusCode(0)))
+        );
+        assert_eq!(
+            pattern("when_any_exited_with_status: [1..=2, { signal: 9 }, failed]"),
+            ExitStatusPattern::AnyOf(vec![
+                ExitStatusPattern::StatusCodeRange(1..=2),
+                ExitStatusPattern::Signal(9),
+                ExitStatusPattern::Failed,
+            ])
+        );
+        assert_eq!(
+            pattern("WhenAnyExitedWithStatus: { crashed_within: 2s }"),
+            ExitStatusPattern::CrashedWithin(std::time::Duration::from_secs(2))
+        );
+        for invalid in ["2..=1", "1..2", "sometimes"] {
+            let yaml = format!("when_any_exited_with_status: {}", invalid);
+            assert!(
+                serde_yaml::from_str::<KillBehavior>(&yaml).is_err(),
+                "{}",
+                invalid
+            );
+        }
+
+        // patterns without a short form of their own keep their structure
+        let kill = KillBehavior::WhenAnyExitedWithStatus(pattern(
+            "when_any_exited_with_status: { not: [success, 3..=5] }",
+        ));
+        let yaml = serde_yaml::to_string(&kill).unwrap();
+        assert_eq!(serde_yaml::from_str::<KillBehavior>(&yaml).unwrap(), kill);
+        assert_eq!(
+            kill.to_string(),
+            "kill other commands when any exited other than successfully or with status code 3 to 5"
+        );
+    }
+
+    #[test]
+    fn exit_status_pattern_lists_in_toml() {
+        #[derive(serde::Deserialize, serde::Serialize)]
+        struct Config {
+            kill: KillBehavior,
+        }
+
+        for yaml in [
+            "when_any_exited_with_status: [1, { signal: 9 }]",
+            "when_any_exited_with_status: [success, 3..=5, [2, { crashed_within: 1s }]]",
+            "when_any_exited_with_status: { not: [0, { signal: 15 }] }",
+        ] {
+            let kill: KillBehavior = serde_yaml::from_str(yaml).unwrap();
+            let toml =
+                toml::to_string(&toml::Value::try_from(Config { kill: kill.clone() }).unwrap())
+                    .unwrap();
+            assert_eq!(
+                toml::from_str::<Config>(&toml).unwrap().kill,
+                kill,
+                "{}",
+                toml
+            );
+        }
+
+        let status =
+            serde_yaml::from_str::<KillBehavior>("when_any_exited_with_status: [{ status: 1 }]");
+        assert_eq!(
+            status.unwrap(),
+            KillBehavior::WhenAnyExitedWithStatus(ExitStatusPattern::AnyOf(vec![
+                ExitStatusPattern::StatusCode(1)
+            ]))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn match_exit_status() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::ExitStatus;
+        use std::time::Duration;
+
+        let code = |code| ExitStatus::from_raw(code << 8);
+        let killed = ExitStatus::from_raw(libc::SIGKILL);
+        let matches = |yaml: &str, status: Option<&ExitStatus>| {
+            let pattern: ExitStatusPattern = serde_yaml::from_str(yaml).unwrap();
+            pattern.matches(status, status.map(|_| Duration::from_secs(1)))
+        };
+
+        assert!(matches("success", Some(&code(0))));
+        assert!(!matches("success", None));
+        assert!(matches("failed", Some(&killed)));
+        assert!(matches("failed", None));
+        assert!(matches("1..=2", Some(&code(2))));
+        assert!(!matches("1..=2", Some(&code(3))));
+        assert!(!matches("1..=2", Some(&killed)));
+        assert!(matches("{ signal: 9 }", Some(&killed)));
+        assert!(!matches("{ signal: 9 }", Some(&code(9))));
+        assert!(matches("[3, 4]", Some(&code(4))));
+        assert!(matches("{ not: 0 }", Some(&killed)));
+        assert!(matches("{ not: 0 }", None));
+        assert!(!matches("{ not: 0 }", Some(&code(0))));
+        assert!(matches("{ crashed_within: 2s }", Some(&code(0))));
+        assert!(!matches("{ crashed_within: 500ms }", Some(&code(0))));
+    }
 }
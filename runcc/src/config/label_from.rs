@@ -0,0 +1,98 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use super::input::LineExtractorInput;
+
+/// Where the value shown next to the label of an output line comes from,
+/// see [`CommandConfig::label_from`](super::CommandConfig::label_from)
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+#[serde(try_from = "LineExtractorInput", into = "LineExtractorInput")]
+pub enum LineExtractor {
+    /// A regex matched against the line, the value is its first capture group
+    /// or the whole match if it has none
+    Regex(String),
+    /// A field of lines which are JSON objects, nested fields are separated by dots
+    JsonField(String),
+}
+
+impl LineExtractor {
+    pub fn compile(&self) -> Result<LineLabel, regex::Error> {
+        Ok(match self {
+            LineExtractor::Regex(regex) => LineLabel::Regex(Regex::new(regex)?),
+            LineExtractor::JsonField(field) => LineLabel::JsonField(field.clone()),
+        })
+    }
+}
+
+/// A compiled [`LineExtractor`]
+#[derive(Debug, Clone)]
+pub enum LineLabel {
+    Regex(Regex),
+    JsonField(String),
+}
+
+impl LineLabel {
+    /// The value of `line`, `None` if the line has none
+    pub fn extract<'a>(&self, line: &'a str) -> Option<Cow<'a, str>> {
+        let value = match self {
+            LineLabel::Regex(regex) => {
+                let captures = regex.captures(line)?;
+                let value = captures.get(1).or_else(|| captures.get(0))?;
+                Cow::Borrowed(value.as_str())
+            }
+            LineLabel::JsonField(field) => {
+                if !line.trim_start().starts_with('{') {
+                    return None;
+                }
+                let json: serde_json::Value = serde_json::from_str(line).ok()?;
+                let value = field
+                    .split('.')
+                    .try_fold(&json, |value, name| value.get(name))?;
+                match value {
+                    serde_json::Value::String(value) => Cow::Owned(value.clone()),
+                    serde_json::Value::Null
+                    | serde_json::Value::Array(_)
+                    | serde_json::Value::Object(_) => return None,
+                    value => Cow::Owned(value.to_string()),
+                }
+            }
+        };
+        (!value.is_empty()).then_some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineExtractor;
+
+    #[test]
+    fn extract_values() {
+        let extract = |extractor: LineExtractor, line| {
+            let label = extractor.compile().unwrap();
+            label.extract(line).map(|value| value.into_owned())
+        };
+
+        let regex = || LineExtractor::Regex(r"module=(\w+)".to_string());
+        assert_eq!(extract(regex(), "level=info module=db"), Some("db".into()));
+        assert_eq!(extract(regex(), "level=info"), None);
+        let whole_match = LineExtractor::Regex(r"^\w+:".to_string());
+        assert_eq!(extract(whole_match, "db: connected"), Some("db:".into()));
+
+        let json = |field: &str| LineExtractor::JsonField(field.to_string());
+        let line = r#"{"module": "db", "span": {"id": 7}, "msg": "connected"}"#;
+        assert_eq!(extract(json("module"), line), Some("db".into()));
+        assert_eq!(extract(json("span.id"), line), Some("7".into()));
+        assert_eq!(extract(json("span"), line), None);
+        assert_eq!(extract(json("module"), "module: db"), None);
+    }
+
+    #[test]
+    fn deserialize_line_extractor() {
+        let extractor: LineExtractor = serde_yaml::from_str("json_field: module").unwrap();
+        assert_eq!(extractor, LineExtractor::JsonField("module".to_string()));
+        let extractor: LineExtractor = serde_yaml::from_str(r"regex: '(\w+):'").unwrap();
+        assert_eq!(extractor, LineExtractor::Regex(r"(\w+):".to_string()));
+        assert!(serde_yaml::from_str::<LineExtractor>("{}").is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The width labels are padded to, so that the output of commands starts in the same column
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LabelWidth {
+    /// The length of the longest label of the commands which run, at most
+    /// [`max_label_length`](super::RunConfig::max_label_length). Written as `auto`.
+    Auto,
+    /// This many characters, independent of where labels are truncated
+    Fixed(usize),
+}
+
+impl LabelWidth {
+    /// The width for labels of these lengths
+    pub fn resolve(&self, label_lengths: impl IntoIterator<Item = usize>, max: usize) -> usize {
+        match *self {
+            LabelWidth::Auto => label_lengths.into_iter().max().unwrap_or(0).min(max),
+            LabelWidth::Fixed(width) => width,
+        }
+    }
+}
+
+impl std::str::FromStr for LabelWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(LabelWidth::Auto),
+            _ => s
+                .parse()
+                .map(LabelWidth::Fixed)
+                .map_err(|_| format!("invalid label width {:?}, expected a number or auto", s)),
+        }
+    }
+}
+
+impl Serialize for LabelWidth {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            LabelWidth::Auto => serializer.serialize_str("auto"),
+            LabelWidth::Fixed(width) => serializer.serialize_u64(*width as u64),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for LabelWidth {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Fixed(usize),
+            Name(String),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Fixed(width) => Ok(LabelWidth::Fixed(width)),
+            Repr::Name(name) => name.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::LabelWidth;
+
+    #[test]
+    fn parse_label_width() {
+        assert_eq!("auto".parse(), Ok(LabelWidth::Auto));
+        assert_eq!("12".parse(), Ok(LabelWidth::Fixed(12)));
+        assert!("wide".parse::<LabelWidth>().is_err());
+
+        let width: LabelWidth = serde_yaml::from_str("8").unwrap();
+        assert_eq!(width, LabelWidth::Fixed(8));
+        let width: LabelWidth = serde_yaml::from_str("auto").unwrap();
+        assert_eq!(width, LabelWidth::Auto);
+    }
+
+    #[test]
+    fn resolve_label_width() {
+        assert_eq!(LabelWidth::Auto.resolve([3, 7, 5], 10), 7);
+        assert_eq!(LabelWidth::Auto.resolve([3, 17], 10), 10);
+        assert_eq!(LabelWidth::Fixed(12).resolve([3], 10), 12);
+    }
+}
@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+use std::{convert::TryFrom, fmt::Display};
+
+/// Limits of the resources a command may use, e.g. `{ memory: 512M, cpu: 50%, nice: 10 }`,
+/// see [`CommandConfig::limits`](super::CommandConfig::limits).
+///
+/// On Unix, `memory` limits the data segment of each process with `setrlimit` and `nice`
+/// is set with `setpriority`, and both are inherited by the processes the command spawns.
+/// `cpu` is only enforced on Windows, runcc warns about it elsewhere. On Windows, the command
+/// and its descendants share the limits of its job object, and `nice` picks its priority class.
+///
+/// **Caveat:** `memory` is `RLIMIT_DATA` and not the address space, `RLIMIT_AS`, which the
+/// JVM, Go and node exceed right away by reserving far more than they use. On Linux it counts
+/// the heap and private writable mappings, but no stacks, shared memory or mapped files.
+/// macOS doesn't enforce it for memory from `mmap`, which most allocators use.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Most memory, with a suffix `K`, `M`, `G` or `T` for multiples of 1024 bytes.
+    /// On Unix it is the data segment of each process, see the caveat above.
+    pub memory: Option<MemorySize>,
+    /// Most CPU time, as a percentage of all CPUs, e.g. `50%`
+    pub cpu: Option<CpuShare>,
+    /// Scheduling priority from -20 to 19, higher runs less often than other processes.
+    /// Lowering it below 0 usually needs privileges.
+    pub nice: Option<i32>,
+}
+
+/// A number of bytes, see [`ResourceLimits::memory`]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct MemorySize(pub u64);
+
+/// A percentage from 1 to 100, see [`ResourceLimits::cpu`]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct CpuShare(pub u32);
+
+impl TryFrom<String> for MemorySize {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid memory size `{}`, expected e.g. 512M", value);
+        let s = value.trim();
+        let s = s
+            .strip_suffix("iB")
+            .or_else(|| s.strip_suffix('B'))
+            .unwrap_or(s);
+        let (number, shift) = match s.char_indices().last() {
+            Some((i, 'K' | 'k')) => (&s[..i], 10),
+            Some((i, 'M' | 'm')) => (&s[..i], 20),
+            Some((i, 'G' | 'g')) => (&s[..i], 30),
+            Some((i, 'T' | 't')) => (&s[..i], 40),
+            _ => (s, 0),
+        };
+        match number.trim().parse::<u64>() {
+            Ok(number) if number > 0 => {
+                number.checked_mul(1 << shift).map(Self).ok_or_else(invalid)
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl From<MemorySize> for String {
+    fn from(size: MemorySize) -> Self {
+        size.to_string()
+    }
+}
+
+impl Display for MemorySize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let suffixes = [(40, "T"), (30, "G"), (20, "M"), (10, "K")];
+        match suffixes
+            .iter()
+            .find(|(shift, _)| self.0.trailing_zeros() >= *shift)
+        {
+            Some((shift, suffix)) => write!(f, "{}{}", self.0 >> shift, suffix),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl TryFrom<String> for CpuShare {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let percent = value.trim().strip_suffix('%').map(|p| p.trim().parse());
+        match percent {
+            Some(Ok(percent)) if (1..=100).contains(&percent) => Ok(Self(percent)),
+            _ => Err(format!("invalid cpu share `{}`, expected e.g. 50%", value)),
+        }
+    }
+}
+
+impl From<CpuShare> for String {
+    fn from(share: CpuShare) -> Self {
+        format!("{}%", share.0)
+    }
+}
+
+/// Applies `limits` to the current process, a `pre_exec` hook of commands
+#[cfg(unix)]
+pub(crate) fn apply_limits(limits: &ResourceLimits) -> std::io::Result<()> {
+    if let Some(MemorySize(memory)) = limits.memory {
+        let limit = libc::rlimit {
+            rlim_cur: memory as libc::rlim_t,
+            rlim_max: memory as libc::rlim_t,
+        };
+        if unsafe { libc::setrlimit(libc::RLIMIT_DATA, &limit) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    if let Some(nice) = limits.nice {
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CpuShare, MemorySize, ResourceLimits};
+    use std::convert::TryFrom;
+
+    #[test]
+    fn parse_limits() {
+        let limits: ResourceLimits =
+            serde_yaml::from_str("{ memory: 512M, cpu: 50%, nice: 10 }").unwrap();
+        assert_eq!(
+            limits,
+            ResourceLimits {
+                memory: Some(MemorySize(512 << 20)),
+                cpu: Some(CpuShare(50)),
+                nice: Some(10),
+            }
+        );
+        assert_eq!(
+            serde_yaml::to_string(&limits).unwrap(),
+            "---\nmemory: 512M\ncpu: 50%\nnice: 10\n"
+        );
+
+        let size = |s: &str| MemorySize::try_from(s.to_string()).map(|size| size.0);
+        assert_eq!(size("1G"), Ok(1 << 30));
+        assert_eq!(size("64 KiB"), Ok(64 << 10));
+        assert_eq!(size("1000"), Ok(1000));
+        for invalid in ["", "0M", "-1M", "1.5G", "12X"] {
+            assert!(size(invalid).is_err(), "{}", invalid);
+        }
+
+        for invalid in ["50", "0%", "101%", "half"] {
+            assert!(
+                CpuShare::try_from(invalid.to_string()).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use regex::Regex;
+use std::borrow::Cow;
+
+use super::CommandConfig;
+
+/// Drops and rewrites output lines of a command before they are logged, see
+/// [`CommandConfig::ignore`], [`show_only`](CommandConfig::show_only),
+/// [`strip_ansi`](CommandConfig::strip_ansi) and
+/// [`max_line_length`](CommandConfig::max_line_length)
+#[derive(Debug, Clone)]
+pub struct LineFilter {
+    ignore: Vec<Regex>,
+    show_only: Vec<Regex>,
+    ansi_escape: Option<Regex>,
+    max_line_length: Option<usize>,
+}
+
+impl LineFilter {
+    /// The filter of the output of `cmd`, `None` if it has none
+    pub fn of(cmd: &CommandConfig) -> Result<Option<Self>, regex::Error> {
+        let compile = |patterns: &Option<Vec<String>>| -> Result<Vec<_>, _> {
+            patterns.iter().flatten().map(|p| Regex::new(p)).collect()
+        };
+        let filter = Self {
+            ignore: compile(&cmd.ignore)?,
+            show_only: compile(&cmd.show_only)?,
+            ansi_escape: cmd
+                .strip_ansi
+                .then(|| Regex::new(r"\x1B\[[0-9;?]*[ -/]*[@-~]").unwrap()),
+            max_line_length: cmd.max_line_length,
+        };
+        let empty = filter.ignore.is_empty()
+            && filter.show_only.is_empty()
+            && filter.ansi_escape.is_none()
+            && filter.max_line_length.is_none();
+        Ok((!empty).then_some(filter))
+    }
+
+    /// `line` as it is logged, `None` if it is dropped.
+    ///
+    /// The patterns are matched against the line without ANSI escapes if they are stripped.
+    pub fn apply<'a>(&self, line: &'a str) -> Option<Cow<'a, str>> {
+        let line = match &self.ansi_escape {
+            Some(ansi_escape) => ansi_escape.replace_all(line, ""),
+            None => Cow::Borrowed(line),
+        };
+        if self.ignore.iter().any(|regex| regex.is_match(&line)) {
+            return None;
+        }
+        if !self.show_only.is_empty() && !self.show_only.iter().any(|r| r.is_match(&line)) {
+            return None;
+        }
+        match self.max_line_length {
+            Some(max) if line.chars().count() > max => {
+                let end = line.char_indices().nth(max).map_or(line.len(), |(i, _)| i);
+                Some(Cow::Owned(format!("{}…", &line[..end])))
+            }
+            _ => Some(line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineFilter;
+    use crate::CommandConfig;
+
+    #[test]
+    fn filter_lines() {
+        let patterns = |patterns: &[&str]| Some(patterns.iter().map(|p| p.to_string()).collect());
+        let filter = |cmd: CommandConfig| LineFilter::of(&cmd).unwrap().unwrap();
+        let apply = |filter: &LineFilter, line| filter.apply(line).map(|line| line.into_owned());
+
+        assert!(LineFilter::of(&CommandConfig::default()).unwrap().is_none());
+
+        let ignore = filter(CommandConfig {
+            ignore: patterns(&["^webpack compiled", "^$"]),
+            ..Default::default()
+        });
+        assert_eq!(apply(&ignore, "webpack compiled in 3s"), None);
+        assert_eq!(apply(&ignore, ""), None);
+        assert_eq!(apply(&ignore, "error: oops"), Some("error: oops".into()));
+
+        let show_only = filter(CommandConfig {
+            show_only: patterns(&["^error", "^warning"]),
+            strip_ansi: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            apply(&show_only, "\x1b[31merror\x1b[0m: oops"),
+            Some("error: oops".into())
+        );
+        assert_eq!(apply(&show_only, "info: fine"), None);
+
+        let truncate = filter(CommandConfig {
+            max_line_length: Some(5),
+            ..Default::default()
+        });
+        assert_eq!(apply(&truncate, "héllo"), Some("héllo".into()));
+        assert_eq!(apply(&truncate, "héllo world"), Some("héllo…".into()));
+    }
+}
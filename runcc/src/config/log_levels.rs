@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A color of labels or of lines containing a log level, see [`LogLevelColors`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogColor {
+    Gray,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+}
+
+impl LogColor {
+    /// The ANSI code of the color
+    pub fn ansi(self) -> &'static str {
+        match self {
+            LogColor::Gray => "90",
+            LogColor::Red => "31",
+            LogColor::Green => "32",
+            LogColor::Yellow => "33",
+            LogColor::Blue => "34",
+            LogColor::Magenta => "35",
+            LogColor::Cyan => "36",
+        }
+    }
+}
+
+/// The color of lines by the log level they contain, e.g. `WARN: yellow`.
+///
+/// Levels are words of lines and case sensitive, the first one of a line decides its color.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct LogLevelColors(pub BTreeMap<String, LogColor>);
+
+impl Default for LogLevelColors {
+    fn default() -> Self {
+        let levels = [
+            ("ERROR", LogColor::Red),
+            ("WARN", LogColor::Yellow),
+            ("WARNING", LogColor::Yellow),
+            ("INFO", LogColor::Gray),
+            ("DEBUG", LogColor::Gray),
+            ("TRACE", LogColor::Gray),
+        ];
+        Self(levels.iter().map(|(l, c)| (l.to_string(), *c)).collect())
+    }
+}
+
+impl LogLevelColors {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The color of the first log level in `line`, if it contains any
+    pub fn color_of(&self, line: &str) -> Option<LogColor> {
+        line.split(|c: char| !c.is_alphanumeric())
+            .find_map(|word| self.0.get(word).copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogColor, LogLevelColors};
+
+    #[test]
+    fn color_of_log_levels() {
+        let colors = LogLevelColors::default();
+        assert_eq!(
+            colors.color_of("2024-01-01T00:00:00Z  WARN app: disk almost full"),
+            Some(LogColor::Yellow)
+        );
+        assert_eq!(
+            colors.color_of("[ERROR] failed, see WARN"),
+            Some(LogColor::Red)
+        );
+        assert_eq!(colors.color_of("listening on :8080"), None);
+        // only whole words are levels
+        assert_eq!(colors.color_of("WARNINGS: none, INFORMATION"), None);
+        assert_eq!(colors.color_of("warn: lowercase"), None);
+
+        let colors: LogLevelColors = serde_yaml::from_str("{warn: magenta}").unwrap();
+        assert_eq!(colors.color_of("warn: lowercase"), Some(LogColor::Magenta));
+        assert_eq!(colors.color_of("WARN app"), None);
+    }
+}
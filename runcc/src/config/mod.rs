@@ -1,11 +1,62 @@
+mod backpressure;
 mod command;
+mod computed_env;
+mod defaults;
+mod encoding;
+mod env_file;
+mod exit_code;
+mod faketime;
+mod hook;
 mod input;
 mod kill;
+mod label_from;
+mod label_width;
+mod limits;
+mod line_filter;
+mod log_levels;
+mod plan;
+mod port;
+mod probe;
+mod restart;
 mod run;
+mod schedule;
+mod secret;
+mod separator;
+mod signal;
+mod stream;
+mod summary_order;
+mod template;
+mod timestamps;
+mod validate;
 
+pub use backpressure::*;
 pub use command::*;
+pub use computed_env::*;
+pub use defaults::*;
+pub use encoding::*;
+pub use env_file::*;
+pub use exit_code::*;
+pub use faketime::*;
+pub use hook::*;
 pub use input::*;
 pub use kill::*;
+pub use label_from::*;
+pub use label_width::*;
+pub use limits::*;
+pub use line_filter::*;
+pub use log_levels::*;
+pub use plan::*;
+pub use port::*;
+pub use probe::*;
+pub use restart::*;
 pub use run::*;
+pub use schedule::*;
+pub use secret::*;
+pub use separator::*;
+pub use signal::*;
+pub use stream::*;
+pub use summary_order::*;
+pub use timestamps::*;
+pub use validate::*;
 
 pub mod read;
@@ -0,0 +1,250 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::{CommandConfig, KillBehavior, RunConfig, SECRET_PREFIX};
+
+/// What a [`RunConfig`] would run, see [`RunConfig::plan`]
+#[non_exhaustive]
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RunPlan {
+    /// Commands grouped by when they are spawned.
+    ///
+    /// Commands of the first stage are spawned right away, the others once their
+    /// prerequisites in earlier stages exited or are ready.
+    pub stages: Vec<Vec<PlannedCommand>>,
+    /// How many commands may run at once at most: the size of the largest stage,
+    /// limited by [`RunConfig::max_concurrent`]. Commands of earlier stages may keep
+    /// running, e.g. ones waited for in `after_ready`, so more may run at once.
+    pub max_concurrency: usize,
+    /// See [`RunConfig::kill`]
+    pub kill: KillBehavior,
+}
+
+/// A command of a [`RunPlan`]
+#[non_exhaustive]
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PlannedCommand {
+    pub label: String,
+    /// Empty for commands with a script
+    pub program: String,
+    pub args: Vec<String>,
+    pub script: Option<String>,
+    /// The program and its args as they are spawned, i.e. run by the
+    /// [`shell`](CommandConfig::shell) of the command if it has one, which gets them quoted.
+    /// Empty for commands with a script and no shell, which run from a file
+    pub spawned: Vec<String>,
+    pub cwd: Option<String>,
+    /// The envs runcc sets for the command, sorted by name unless the command sets them.
+    /// Values referring to secrets are masked as `***`.
+    pub envs: Vec<(String, String)>,
+    /// See [`CommandConfig::after`]
+    pub after: Vec<String>,
+    /// See [`CommandConfig::after_ready`]
+    pub after_ready: Vec<String>,
+}
+
+impl PlannedCommand {
+    fn new(cmd: &CommandConfig, run_envs: &[(&String, &String)]) -> Self {
+        let mut envs: Vec<(String, String)> = match cmd.inherit_env {
+            Some(false) => vec![],
+            _ => run_envs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        };
+        for (name, value) in cmd.envs.iter().flatten() {
+            envs.retain(|(own, _)| own != name);
+            envs.push((name.clone(), value.clone()));
+        }
+        for (_, value) in &mut envs {
+            if value.starts_with(SECRET_PREFIX) {
+                *value = "***".to_string();
+            }
+        }
+
+        let mut resolved = cmd.clone();
+        resolved.resolve_shell();
+        let spawned = match resolved.script {
+            Some(_) => vec![],
+            None => std::iter::once(resolved.program)
+                .chain(resolved.args.into_iter().flatten())
+                .collect(),
+        };
+
+        PlannedCommand {
+            label: cmd.resolved_label(),
+            program: cmd.program.clone(),
+            args: cmd.args.clone().unwrap_or_default(),
+            script: cmd.script.clone(),
+            spawned,
+            cwd: cmd.cwd.clone(),
+            envs,
+            after: cmd.after.clone().unwrap_or_default(),
+            after_ready: cmd.after_ready.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl RunConfig {
+    /// Describes what would run without spawning anything, e.g. for editors and wrappers.
+    ///
+    /// The config should be [validated](Self::validate): commands waiting for each other in
+    /// a cycle are put in a last stage, prerequisites which don't exist are ignored.
+    pub fn plan(&self) -> RunPlan {
+        let mut indexes = HashMap::with_capacity(self.commands.len());
+        for (i, cmd) in self.commands.iter().enumerate() {
+            indexes.entry(cmd.resolved_label()).or_insert(i);
+        }
+        let prerequisites: Vec<Vec<usize>> = self
+            .commands
+            .iter()
+            .map(|cmd| {
+                let after = cmd.after.iter().flatten();
+                let after_ready = cmd.after_ready.iter().flatten();
+                after
+                    .chain(after_ready)
+                    .filter_map(|label| indexes.get(label).copied())
+                    .collect()
+            })
+            .collect();
+
+        // each command is in the stage after its latest prerequisite
+        let mut stage_of: Vec<Option<usize>> = vec![None; self.commands.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (i, prerequisites) in prerequisites.iter().enumerate() {
+                if stage_of[i].is_some() {
+                    continue;
+                }
+                let stages: Option<Vec<usize>> =
+                    prerequisites.iter().map(|&p| stage_of[p]).collect();
+                if let Some(stages) = stages {
+                    stage_of[i] = Some(stages.iter().map(|stage| stage + 1).max().unwrap_or(0));
+                    changed = true;
+                }
+            }
+        }
+        let cycle_stage = stage_of.iter().flatten().max().map_or(0, |stage| stage + 1);
+
+        let mut run_envs: Vec<_> = self.envs.iter().flatten().collect();
+        run_envs.sort();
+        let mut stages: Vec<Vec<PlannedCommand>> = vec![];
+        for (cmd, stage) in self.commands.iter().zip(stage_of) {
+            let stage = stage.unwrap_or(cycle_stage);
+            if stages.len() <= stage {
+                stages.resize(stage + 1, vec![]);
+            }
+            stages[stage].push(PlannedCommand::new(cmd, &run_envs));
+        }
+
+        let largest = stages.iter().map(Vec::len).max().unwrap_or(0);
+        RunPlan {
+            stages,
+            max_concurrency: self.max_concurrent.map_or(largest, |max| largest.min(max)),
+            kill: self.kill.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RunConfig, RunConfigInput};
+
+    #[test]
+    fn plan_stages() {
+        let input: RunConfigInput = serde_yaml::from_str(
+            r#"
+envs:
+  RUST_LOG: info
+  TOKEN: secret://api/token
+commands:
+  - program: cargo
+    args: [run]
+    label: api
+    after: [migrate]
+    envs: { RUST_LOG: debug }
+  - program: npm
+    args: [start]
+    label: web
+    after_ready: [api]
+    inherit_env: false
+  - program: diesel
+    args: [migration, run]
+    label: migrate
+  - program: mailhog
+"#,
+        )
+        .unwrap();
+        let mut config: RunConfig = input.into();
+        let plan = config.plan();
+
+        let labels: Vec<Vec<&str>> = plan
+            .stages
+            .iter()
+            .map(|stage| stage.iter().map(|cmd| cmd.label.as_str()).collect())
+            .collect();
+        assert_eq!(
+            labels,
+            [vec!["migrate", "mailhog"], vec!["api"], vec!["web"]]
+        );
+        assert_eq!(plan.max_concurrency, 2);
+
+        let api = &plan.stages[1][0];
+        assert_eq!(api.program, "cargo");
+        assert_eq!(api.args, ["run"]);
+        assert_eq!(api.after, ["migrate"]);
+        let envs = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            let pairs = pairs.iter();
+            pairs.map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        assert_eq!(api.envs, envs(&[("TOKEN", "***"), ("RUST_LOG", "debug")]));
+        let web = &plan.stages[2][0];
+        assert_eq!(web.after_ready, ["api"]);
+        assert!(web.envs.is_empty());
+
+        config.max_concurrent = Some(1);
+        assert_eq!(config.plan().max_concurrency, 1);
+    }
+
+    #[test]
+    fn plan_commands_as_spawned() {
+        let input: RunConfigInput = serde_yaml::from_str(
+            r#"
+kill: WhenAnyExited
+commands:
+  - program: redis-server
+    args: [--port, "6380"]
+    shell: bash -lc
+  - program: echo
+    args: [hello world]
+  - script: "cd web\nnpm start"
+"#,
+        )
+        .unwrap();
+        let config: RunConfig = input.into();
+        let plan = config.plan();
+
+        let redis = &plan.stages[0][0];
+        assert_eq!(redis.label, "redis-server --port 6380");
+        assert_eq!(redis.program, "redis-server");
+        assert_eq!(redis.args, ["--port", "6380"]);
+        assert_eq!(redis.spawned, ["bash", "-lc", "redis-server --port 6380"]);
+        assert_eq!(plan.kill, crate::KillBehavior::WhenAnyExited);
+
+        let shell = |shell: &str| {
+            let mut config = config.clone();
+            config.commands[0].args = Some(vec!["it's".to_string(), "a b".to_string()]);
+            config.commands[0].shell = Some(shell.to_string());
+            config.plan().stages[0][0].spawned.clone()
+        };
+        assert_eq!(shell("sh"), ["sh", "-c", r"redis-server 'it'\''s' 'a b'"]);
+        assert_eq!(shell("cmd"), ["cmd", "/C", r#"redis-server it's "a b""#]);
+
+        let echo = &plan.stages[0][1];
+        assert_eq!(echo.spawned, ["echo", "hello world"]);
+        let script = &plan.stages[0][2];
+        assert!(script.spawned.is_empty());
+        assert_eq!(script.script.as_deref(), Some("cd web\nnpm start"));
+    }
+}
@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, io, net::TcpListener, time::Duration};
+
+use super::RunConfig;
+
+fn default_host() -> String {
+    "localhost".to_string()
+}
+
+fn default_port_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// A port which must accept connections before the command is spawned, e.g.
+/// `{ port: 5432, timeout: 30s }`, see [`CommandConfig::wait_for`](super::CommandConfig::wait_for)
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PortWait {
+    pub port: PortNumber,
+    #[serde(default = "default_host")]
+    pub host: String,
+    /// The command is skipped if the port doesn't accept connections in time
+    #[serde(default = "default_port_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+/// A port number, or the env name of a port assigned by runcc, see
+/// [`CommandConfig::assign_port`](super::CommandConfig::assign_port)
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum PortNumber {
+    Number(u16),
+    Assigned(String),
+}
+
+impl std::fmt::Display for PortNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortNumber::Number(port) => write!(f, "{}", port),
+            PortNumber::Assigned(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl RunConfig {
+    /// Picks a free port for each env name in [`assign_port`](super::CommandConfig::assign_port)
+    /// of the commands, sets it as env var of the commands with that name and in the
+    /// [`wait_for`](super::CommandConfig::wait_for) referring to it.
+    ///
+    /// Names in `assigned` keep their port, e.g. when the config is reloaded, and the picked
+    /// ports are added to it. Another process may still take a port before the command
+    /// listens on it.
+    pub fn assign_ports(&mut self, assigned: &mut HashMap<String, u16>) -> io::Result<()> {
+        // bound until all ports are picked, so that each name gets another port
+        let mut listeners = vec![];
+        for cmd in &mut self.commands {
+            if let Some(name) = &cmd.assign_port {
+                let port = match assigned.get(name) {
+                    Some(port) => *port,
+                    None => {
+                        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+                        let port = listener.local_addr()?.port();
+                        listeners.push(listener);
+                        assigned.insert(name.clone(), port);
+                        port
+                    }
+                };
+                cmd.env((name.clone(), port.to_string()));
+            }
+        }
+        for wait in self
+            .commands
+            .iter_mut()
+            .filter_map(|cmd| cmd.wait_for.as_mut())
+        {
+            if let PortNumber::Assigned(name) = &wait.port {
+                if let Some(port) = assigned.get(name) {
+                    wait.port = PortNumber::Number(*port);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::{PortNumber, PortWait};
+    use crate::{CommandConfig, RunConfig, RunConfigInput};
+
+    #[test]
+    fn parse_port_wait() {
+        let wait: PortWait = serde_yaml::from_str("{ port: 5432, timeout: 30s }").unwrap();
+        assert_eq!(
+            wait,
+            PortWait {
+                port: PortNumber::Number(5432),
+                host: "localhost".to_string(),
+                timeout: Duration::from_secs(30),
+            }
+        );
+        let wait: PortWait = serde_yaml::from_str("{ port: API_PORT, host: 127.0.0.1 }").unwrap();
+        assert_eq!(wait.port, PortNumber::Assigned("API_PORT".to_string()));
+        assert_eq!(wait.timeout, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn assign_ports_to_commands() {
+        let command = |label: &str, assign_port: Option<&str>| CommandConfig {
+            label: Some(label.to_string()),
+            assign_port: assign_port.map(str::to_string),
+            ..Default::default()
+        };
+        let mut client = command("client", Some("API_PORT"));
+        client.wait_for = Some(PortWait {
+            port: PortNumber::Assigned("API_PORT".to_string()),
+            host: "localhost".to_string(),
+            timeout: Duration::from_secs(1),
+        });
+        let mut config: RunConfig = RunConfigInput::empty().into();
+        config.commands = vec![
+            command("api", Some("API_PORT")),
+            client,
+            command("web", Some("WEB_PORT")),
+            command("db", None),
+        ];
+
+        let mut assigned = HashMap::new();
+        config.assign_ports(&mut assigned).unwrap();
+        let api_port = assigned["API_PORT"];
+        assert_ne!(api_port, assigned["WEB_PORT"]);
+
+        let env = |i: usize, name: &str| {
+            let mut envs = config.commands[i].envs.iter().flatten();
+            envs.find(|(env, _)| env == name)
+                .map(|(_, port)| port.clone())
+        };
+        assert_eq!(env(0, "API_PORT"), Some(api_port.to_string()));
+        assert_eq!(env(1, "API_PORT"), Some(api_port.to_string()));
+        assert_eq!(env(2, "WEB_PORT"), Some(assigned["WEB_PORT"].to_string()));
+        assert_eq!(config.commands[3].envs, None);
+        assert_eq!(
+            config.commands[1].wait_for.as_ref().unwrap().port,
+            PortNumber::Number(api_port)
+        );
+
+        // assigned ports are kept
+        let mut again: RunConfig = RunConfigInput::empty().into();
+        again.commands = vec![command("api", Some("API_PORT"))];
+        again.assign_ports(&mut assigned).unwrap();
+        assert_eq!(
+            again.commands[0].envs,
+            Some(vec![("API_PORT".to_string(), api_port.to_string())])
+        );
+    }
+}
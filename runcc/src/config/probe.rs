@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+fn default_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+/// A check run repeatedly after the command is spawned.
+///
+/// The command is ready once `command` exits successfully.
+/// After `failure_threshold` failed attempts, the probe gives up and the command is killed
+/// as failed, which counts for [`KillBehavior`](crate::KillBehavior).
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct StartupProbe {
+    /// Run like commands given as a string, in the `cwd` of the command
+    pub command: String,
+    /// Wait before each attempt
+    #[serde(default = "default_interval", with = "humantime_serde")]
+    pub interval: Duration,
+    /// An attempt running longer fails
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+}
+
+fn default_health_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_restart() -> bool {
+    true
+}
+
+/// A check run repeatedly while the command runs, once it is ready if it has
+/// `ready_when` or a `startup_probe`.
+///
+/// Either `url` or `command` is checked. Once `retries` checks in a row failed, the command
+/// is unhealthy and restarted, which doesn't count as a retry of its restart policy.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    /// Healthy if a GET of this `http://` url answers with a 2xx or 3xx status
+    pub url: Option<String>,
+    /// Healthy if this exits successfully, run like commands given as a string,
+    /// in the `cwd` of the command
+    pub command: Option<String>,
+    /// Wait before each check
+    #[serde(default = "default_health_interval", with = "humantime_serde")]
+    pub interval: Duration,
+    /// A check running longer fails
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    #[serde(default = "default_failure_threshold")]
+    pub retries: u32,
+    /// Restart the command once it is unhealthy, otherwise it is only reported
+    #[serde(default = "default_restart")]
+    pub restart: bool,
+}
@@ -1,5 +1,11 @@
 use serde::de::DeserializeOwned;
-use std::{fs, fs::File, io, path::Path, str::FromStr};
+use std::{
+    fs,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use super::error::*;
 
@@ -49,6 +55,9 @@ pub struct ConfigFileData<T> {
     pub filename: String,
     pub format: ConfigFormat,
     pub data: T,
+    /// The directory the file was found in by [`find_config_file`] if it is a parent of
+    /// the one searched, `None` if it is that directory or the file was given
+    pub parent_dir: Option<PathBuf>,
 }
 
 pub fn find_config_file_in_dir<T: DeserializeOwned>(
@@ -116,6 +125,7 @@ pub fn read_config_from_file_and_format<T: DeserializeOwned>(
                 filename,
                 format,
                 data,
+                parent_dir: None,
             }),
             Err(kind) => Err(ConfigDeserializeError {
                 filename,
@@ -131,6 +141,36 @@ pub fn read_config_from_file_and_format<T: DeserializeOwned>(
     }
 }
 
+/// Reads each file with a known extension in `dir` sorted by filename,
+/// e.g. fragments of a config
+pub fn read_config_dir<T: DeserializeOwned>(
+    dir: &Path,
+) -> Result<Vec<ConfigFileData<T>>, ReadConfigError> {
+    let open_error = |error| ReadConfigError::OpenFileError {
+        file: dir.to_string_lossy().into_owned(),
+        error,
+    };
+
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).map_err(open_error)? {
+        let entry = entry.map_err(open_error)?;
+        let filename = entry.file_name().to_string_lossy().into_owned();
+        let format = EXTENSIONS
+            .iter()
+            .find(|(_, ext)| filename.ends_with(ext))
+            .map(|(format, _)| *format);
+        if let Some(format) = format.filter(|_| entry.path().is_file()) {
+            files.push((filename, entry.path(), format));
+        }
+    }
+    files.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+
+    files
+        .into_iter()
+        .map(|(_, path, format)| read_config_from_file_and_format(&path, format))
+        .collect()
+}
+
 pub fn read_config_from_cargo_toml<T: DeserializeOwned>(
     file_path: &Path,
     app_name: &str,
@@ -164,11 +204,15 @@ pub fn read_config_from_cargo_toml<T: DeserializeOwned>(
 
     match v {
         toml::Value::Table(mut v) => {
-            let pkg = v.remove(&format!("package.metadata.{}", app_name));
-            let wsp = v.remove(&format!("workspace.metadata.{}", app_name));
+            let mut metadata = |section: &str| {
+                let metadata = v.get_mut(section)?.get_mut("metadata")?.as_table_mut()?;
+                metadata.remove(app_name)
+            };
+            let pkg = metadata("package");
+            let wsp = metadata("workspace");
 
             let v = if let Some(pkg) = pkg {
-                if let Some(_) = wsp {
+                if wsp.is_some() {
                     return Err(ConfigDeserializeError {
                         filename: filename.to_string(),
                         format,
@@ -188,35 +232,64 @@ pub fn read_config_from_cargo_toml<T: DeserializeOwned>(
                 }
             };
 
-            let data: T = v.try_into().or_else(|err| {
-                Err(ConfigDeserializeError {
-                    filename: filename.to_string(),
-                    format,
-                    kind: ConfigDeserializeErrorKind::CargoMetadataError(
-                        CargoMetadataError::InvalidDataStructure(err),
-                    ),
-                })
+            let data: T = v.try_into().map_err(|err| ConfigDeserializeError {
+                filename: filename.to_string(),
+                format,
+                kind: ConfigDeserializeErrorKind::CargoMetadataError(
+                    CargoMetadataError::InvalidDataStructure(err),
+                ),
             })?;
 
-            return Ok(Some(ConfigFileData {
-                filename: "Cargo.toml".to_string(),
+            Ok(Some(ConfigFileData {
+                filename,
                 format: ConfigFormat::CargoMetadata,
                 data,
-            }));
+                parent_dir: None,
+            }))
         }
-        _ => {
-            return Err(ConfigDeserializeError {
-                filename: filename.to_string(),
-                format,
-                kind: ConfigDeserializeErrorKind::CargoMetadataError(
-                    CargoMetadataError::CargoTomlIsNotTable,
-                ),
+        _ => Err(ConfigDeserializeError {
+            filename: filename.to_string(),
+            format,
+            kind: ConfigDeserializeErrorKind::CargoMetadataError(
+                CargoMetadataError::CargoTomlIsNotTable,
+            ),
+        }
+        .into()),
+    }
+}
+
+/// Like [`find_config_file_in_dir`], but also looks in the parents of `dir` if it has
+/// no config file, nearest first, like cargo looks for `Cargo.toml`
+pub fn discover_config_file<T: DeserializeOwned>(
+    dir: &Path,
+    app_name: &str,
+) -> Result<ConfigFileData<T>, FindConfigError> {
+    let not_found = match find_config_file_in_dir(dir, app_name) {
+        Err(err @ FindConfigError::NoFileMatch { .. }) => err,
+        found => return found,
+    };
+
+    let dir = match std::env::current_dir() {
+        Ok(cwd) => cwd.join(dir),
+        Err(_) => return Err(not_found),
+    };
+    for parent in dir.ancestors().skip(1) {
+        match find_config_file_in_dir(parent, app_name) {
+            Ok(mut data) => {
+                data.parent_dir = Some(parent.to_path_buf());
+                return Ok(data);
             }
-            .into())
+            Err(FindConfigError::NoFileMatch { .. }) => {}
+            Err(err) => return Err(err),
         }
     }
+    Err(not_found)
 }
 
+/// Reads the given config file, or the one in the given directory.
+///
+/// Without a path, the config file is searched in the current directory and its parents,
+/// see [`discover_config_file`].
 pub fn find_config_file<T: DeserializeOwned>(
     file_or_dir_path: Option<&str>,
     app_name: &str,
@@ -225,7 +298,9 @@ pub fn find_config_file<T: DeserializeOwned>(
 
     let path = Path::new(file_or_dir_path);
 
-    if file_or_dir_path.is_empty() || file_or_dir_path == "." || path.is_dir() {
+    if file_or_dir_path.is_empty() {
+        discover_config_file(path, app_name)
+    } else if file_or_dir_path == "." || path.is_dir() {
         find_config_file_in_dir(path, app_name)
     } else {
         let filename = path.file_name().unwrap_or_default();
@@ -263,3 +338,59 @@ pub fn find_config_file<T: DeserializeOwned>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{discover_config_file, find_config_file_in_dir, ConfigFileData};
+    use crate::RunConfigInput;
+
+    fn labels(data: ConfigFileData<RunConfigInput>) -> Vec<String> {
+        let config: crate::RunConfig = data.data.into();
+        config
+            .commands
+            .iter()
+            .map(|cmd| cmd.resolved_label())
+            .collect()
+    }
+
+    #[test]
+    fn read_cargo_toml_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("Cargo.toml"),
+            r#"
+[package]
+name = "app"
+
+[package.metadata.runcc]
+commands = ["cargo run", "npm start"]
+"#,
+        )
+        .unwrap();
+
+        let data = find_config_file_in_dir::<RunConfigInput>(dir.path(), "runcc").unwrap();
+        assert!(data.filename.ends_with("Cargo.toml"));
+        assert_eq!(labels(data), ["cargo run", "npm start"]);
+    }
+
+    #[test]
+    fn discover_config_file_in_parents() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("crates/app/src");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(dir.path().join("runcc.json"), r#"{"commands": ["web"]}"#).unwrap();
+        // without runcc metadata, so the search goes on
+        std::fs::write(
+            dir.path().join("crates/app/Cargo.toml"),
+            "[package]\nname = \"app\"\n",
+        )
+        .unwrap();
+
+        let data = discover_config_file::<RunConfigInput>(&nested, "runcc").unwrap();
+        assert_eq!(data.parent_dir.as_deref(), Some(dir.path()));
+        assert_eq!(labels(data), ["web"]);
+
+        let data = discover_config_file::<RunConfigInput>(dir.path(), "runcc").unwrap();
+        assert_eq!(data.parent_dir, None);
+    }
+}
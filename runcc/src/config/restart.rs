@@ -0,0 +1,520 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{cmp, time::Duration};
+
+/// Retry a failed command, but only during the startup window of the run.
+///
+/// After `within` has elapsed since the run started, a failure is terminal.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StartupRetries {
+    pub max_retries: u32,
+    #[serde(with = "humantime_serde")]
+    pub within: Duration,
+}
+
+/// Spawn a command again when it fails to spawn, e.g. because its binary is still being
+/// written or a network file system is unavailable for a moment
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnRetries {
+    pub max_retries: u32,
+    /// How long to wait before each retry
+    #[serde(with = "humantime_serde")]
+    pub delay: Duration,
+}
+
+/// Spawn a command again, with exponentially growing delays, when it fails before it is ready
+/// and within `within` after it was spawned, see
+/// [`RunConfig::early_exit_backoff`](crate::RunConfig::early_exit_backoff).
+///
+/// The attempts start over once a run lasted longer than `within`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EarlyExitBackoff {
+    #[serde(with = "humantime_serde")]
+    pub within: Duration,
+    /// The delay before the first attempt
+    #[serde(with = "humantime_serde")]
+    pub base: Duration,
+    /// How much longer each delay is than the one before, defaults to 2
+    #[serde(default = "default_multiplier")]
+    pub multiplier: u32,
+    #[serde(with = "humantime_serde")]
+    pub max_delay: Duration,
+    pub max_attempts: u32,
+}
+
+fn default_multiplier() -> u32 {
+    2
+}
+
+impl EarlyExitBackoff {
+    /// How long to wait before the attempt after `attempt` earlier ones
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.checked_pow(attempt).unwrap_or(u32::MAX);
+        cmp::min(self.base.saturating_mul(factor), self.max_delay)
+    }
+}
+
+/// When to spawn a command again after it exited.
+///
+/// `always`, `on-failure` and `never` are short for the policies without options.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(remote = "Self")]
+pub enum RestartPolicy {
+    /// Restart the command until its output matches
+    /// [`ready_when`](crate::CommandConfig::ready_when) or its
+    /// [`startup_probe`](crate::CommandConfig::startup_probe) succeeds within `ready_timeout`.
+    ///
+    /// A command which isn't ready in time is killed.
+    /// After `max` restarts, the command is stopped for good.
+    UntilReady {
+        max: u32,
+        #[serde(with = "humantime_serde")]
+        ready_timeout: Duration,
+        /// How long to wait before each restart, e.g. `[1s, 5s, 30s]`.
+        ///
+        /// The Nth restart waits for the Nth delay, further restarts wait for the last one.
+        /// Restarts immediately if empty.
+        #[serde(default, with = "durations")]
+        backoff_schedule: Vec<Duration>,
+    },
+    /// Restart the command whenever it exits, unless runcc killed it or couldn't spawn it.
+    ///
+    /// After `max` restarts, the command is stopped for good. Restarts forever if `None`.
+    Always {
+        #[serde(default)]
+        max: Option<u32>,
+        /// See [`UntilReady`](Self::UntilReady)
+        #[serde(default, with = "durations")]
+        backoff_schedule: Vec<Duration>,
+    },
+    /// Like [`Always`](Self::Always), but only restart the command if it failed
+    OnFailure {
+        #[serde(default)]
+        max: Option<u32>,
+        /// See [`UntilReady`](Self::UntilReady)
+        #[serde(default, with = "durations")]
+        backoff_schedule: Vec<Duration>,
+    },
+    /// Never restart the command, e.g. to override the `restart` of `defaults`
+    Never,
+}
+
+/// Writes the short policies as strings and the others as a table with the variant as its only
+/// key, since TOML can't write struct variants
+impl Serialize for RestartPolicy {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let (variant, options) = match self {
+            RestartPolicy::Always {
+                max: None,
+                backoff_schedule,
+            } if backoff_schedule.is_empty() => return s.serialize_str("always"),
+            RestartPolicy::OnFailure {
+                max: None,
+                backoff_schedule,
+            } if backoff_schedule.is_empty() => return s.serialize_str("on-failure"),
+            RestartPolicy::Never => return s.serialize_unit_variant("RestartPolicy", 3, "Never"),
+            RestartPolicy::UntilReady {
+                max,
+                ready_timeout,
+                backoff_schedule,
+            } => (
+                "UntilReady",
+                PolicyOptions {
+                    max: Some(*max),
+                    ready_timeout: Some(*ready_timeout),
+                    backoff_schedule,
+                },
+            ),
+            RestartPolicy::Always {
+                max,
+                backoff_schedule,
+            } => (
+                "Always",
+                PolicyOptions {
+                    max: *max,
+                    ready_timeout: None,
+                    backoff_schedule,
+                },
+            ),
+            RestartPolicy::OnFailure {
+                max,
+                backoff_schedule,
+            } => (
+                "OnFailure",
+                PolicyOptions {
+                    max: *max,
+                    ready_timeout: None,
+                    backoff_schedule,
+                },
+            ),
+        };
+
+        let mut map = s.serialize_map(Some(1))?;
+        map.serialize_entry(variant, &options)?;
+        map.end()
+    }
+}
+
+/// The options of a [`RestartPolicy`] variant
+#[derive(Serialize)]
+struct PolicyOptions<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none", with = "humantime_serde")]
+    ready_timeout: Option<Duration>,
+    #[serde(
+        skip_serializing_if = "<[_]>::is_empty",
+        serialize_with = "durations::serialize"
+    )]
+    backoff_schedule: &'a [Duration],
+}
+
+impl<'de> Deserialize<'de> for RestartPolicy {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_enum("RestartPolicy", VARIANTS, PolicyVisitor)
+    }
+}
+
+const VARIANTS: &[&str] = &["UntilReady", "Always", "OnFailure", "Never"];
+
+/// The policy without options which `short` is short for
+fn short_policy(short: &str) -> Option<RestartPolicy> {
+    match short {
+        "always" => Some(RestartPolicy::Always {
+            max: None,
+            backoff_schedule: vec![],
+        }),
+        "on-failure" => Some(RestartPolicy::OnFailure {
+            max: None,
+            backoff_schedule: vec![],
+        }),
+        "never" | "Never" => Some(RestartPolicy::Never),
+        _ => None,
+    }
+}
+
+/// Accepts the short policies next to the variants, in any format which has enums,
+/// e.g. `always` in YAML, `"always"` in JSON and TOML or `Always(max: Some(2))` in RON
+struct PolicyVisitor;
+
+impl<'de> de::Visitor<'de> for PolicyVisitor {
+    type Value = RestartPolicy;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("always, on-failure, never or a restart policy with options")
+    }
+
+    fn visit_str<E: de::Error>(self, short: &str) -> Result<Self::Value, E> {
+        short_policy(short)
+            .ok_or_else(|| de::Error::invalid_value(de::Unexpected::Str(short), &self))
+    }
+
+    fn visit_enum<A: de::EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        use de::VariantAccess;
+
+        let (VariantName(name), variant) = data.variant()?;
+        if let Some(policy) = short_policy(&name) {
+            variant.unit_variant()?;
+            return Ok(policy);
+        }
+        if !VARIANTS.contains(&name.as_str()) {
+            return Err(de::Error::invalid_value(de::Unexpected::Str(&name), &self));
+        }
+        // hands the variant, which was read already, to the derived implementation
+        RestartPolicy::deserialize(ReadVariant { name, variant })
+    }
+}
+
+/// The name of a variant, which formats like RON only read as an identifier
+struct VariantName(String);
+
+impl<'de> Deserialize<'de> for VariantName {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        struct NameVisitor;
+
+        impl<'de> de::Visitor<'de> for NameVisitor {
+            type Value = VariantName;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a variant name")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Self::Value, E> {
+                Ok(VariantName(name.to_string()))
+            }
+        }
+
+        d.deserialize_identifier(NameVisitor)
+    }
+}
+
+/// An enum whose variant `name` was read already
+struct ReadVariant<A> {
+    name: String,
+    variant: A,
+}
+
+impl<'de, A: de::VariantAccess<'de>> Deserializer<'de> for ReadVariant<A> {
+    type Error = A::Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, A::Error> {
+        visitor.visit_enum(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct enum
+        identifier ignored_any
+    }
+}
+
+impl<'de, A: de::VariantAccess<'de>> de::EnumAccess<'de> for ReadVariant<A> {
+    type Error = A::Error;
+    type Variant = A;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, A), A::Error> {
+        use de::IntoDeserializer;
+
+        let name = seed.deserialize(self.name.into_deserializer())?;
+        Ok((name, self.variant))
+    }
+}
+
+impl RestartPolicy {
+    /// How long to wait before the `restart`th restart, counting from 1
+    pub fn backoff(&self, restart: u32) -> Duration {
+        let backoff_schedule = match self {
+            RestartPolicy::UntilReady {
+                backoff_schedule, ..
+            }
+            | RestartPolicy::Always {
+                backoff_schedule, ..
+            }
+            | RestartPolicy::OnFailure {
+                backoff_schedule, ..
+            } => backoff_schedule,
+            RestartPolicy::Never => return Duration::ZERO,
+        };
+
+        let i = restart.saturating_sub(1) as usize;
+        backoff_schedule
+            .get(i)
+            .or_else(|| backoff_schedule.last())
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Pause restarts of a crash-looping command instead of giving up on it, see
+/// [`CommandConfig::restart_rate_limit`](crate::CommandConfig::restart_rate_limit).
+///
+/// Once the command restarted `max` times within `within`, the next restart waits for
+/// `cooldown`. Restarts due to changed files don't count.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartRateLimit {
+    pub max: u32,
+    #[serde(with = "humantime_serde")]
+    pub within: Duration,
+    /// Defaults to `within`
+    #[serde(default, with = "humantime_serde")]
+    pub cooldown: Option<Duration>,
+}
+
+impl RestartRateLimit {
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown.unwrap_or(self.within)
+    }
+}
+
+/// (De)serializes a list of durations like `[1s, 5s]`
+mod durations {
+    use humantime_serde::Serde;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(durations: &[Duration], s: S) -> Result<S::Ok, S::Error> {
+        let durations: Vec<_> = durations.iter().map(Serde::from).collect();
+        durations.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Duration>, D::Error> {
+        let durations: Vec<Serde<Duration>> = Deserialize::deserialize(d)?;
+        Ok(durations.into_iter().map(Serde::into_inner).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{EarlyExitBackoff, RestartPolicy, RestartRateLimit};
+
+    #[test]
+    fn backoff_schedule() {
+        let policy: RestartPolicy = serde_yaml::from_str(
+            r#"
+UntilReady:
+  max: 5
+  ready_timeout: 10s
+  backoff_schedule: [1s, 5s, 30s]
+"#,
+        )
+        .unwrap();
+
+        let delays: Vec<_> = (1..=5).map(|restart| policy.backoff(restart)).collect();
+        assert_eq!(delays, [1, 5, 30, 30, 30].map(Duration::from_secs),);
+
+        let policy = RestartPolicy::UntilReady {
+            max: 5,
+            ready_timeout: Duration::from_secs(10),
+            backoff_schedule: vec![],
+        };
+        assert_eq!(policy.backoff(1), Duration::ZERO);
+
+        let policy: RestartPolicy =
+            serde_yaml::from_str("OnFailure: { backoff_schedule: [2s] }").unwrap();
+        assert_eq!(
+            policy,
+            RestartPolicy::OnFailure {
+                max: None,
+                backoff_schedule: vec![Duration::from_secs(2)],
+            }
+        );
+        assert_eq!(policy.backoff(3), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn short_restart_policies() {
+        let parse = |yaml| serde_yaml::from_str::<RestartPolicy>(yaml);
+        assert_eq!(
+            parse("always").unwrap(),
+            RestartPolicy::Always {
+                max: None,
+                backoff_schedule: vec![],
+            }
+        );
+        assert!(matches!(
+            parse("on-failure").unwrap(),
+            RestartPolicy::OnFailure { max: None, .. }
+        ));
+        assert_eq!(parse("never").unwrap(), RestartPolicy::Never);
+        assert!(matches!(
+            parse("Always: { max: 2 }").unwrap(),
+            RestartPolicy::Always { max: Some(2), .. }
+        ));
+        let err = parse("sometimes").unwrap_err().to_string();
+        assert!(err.contains("always, on-failure, never"), "{}", err);
+        let err = parse("UntilReady: { max: 2 }").unwrap_err().to_string();
+        assert!(err.contains("ready_timeout"), "{}", err);
+
+        let never = serde_yaml::to_string(&RestartPolicy::Never).unwrap();
+        assert_eq!(parse(&never).unwrap(), RestartPolicy::Never);
+    }
+
+    #[test]
+    fn restart_policies_in_other_formats() {
+        let always = RestartPolicy::Always {
+            max: Some(2),
+            backoff_schedule: vec![Duration::from_secs(1)],
+        };
+        let on_failure = RestartPolicy::OnFailure {
+            max: None,
+            backoff_schedule: vec![],
+        };
+        let until_ready = RestartPolicy::UntilReady {
+            max: 3,
+            ready_timeout: Duration::from_secs(10),
+            backoff_schedule: vec![],
+        };
+        let policies = [
+            always.clone(),
+            on_failure.clone(),
+            until_ready,
+            RestartPolicy::Never,
+        ];
+
+        for policy in &policies {
+            let json = serde_json::to_string(policy).unwrap();
+            assert_eq!(
+                serde_json::from_str::<RestartPolicy>(&json).unwrap(),
+                *policy,
+                "{}",
+                json
+            );
+            let yaml = serde_yaml::to_string(policy).unwrap();
+            assert_eq!(
+                serde_yaml::from_str::<RestartPolicy>(&yaml).unwrap(),
+                *policy,
+                "{}",
+                yaml
+            );
+        }
+        assert_eq!(
+            serde_json::to_string(&on_failure).unwrap(),
+            "\"on-failure\""
+        );
+        let ron = ron::to_string(&RestartPolicy::Never).unwrap();
+        assert_eq!(
+            ron::from_str::<RestartPolicy>(&ron).unwrap(),
+            RestartPolicy::Never
+        );
+        assert_eq!(
+            ron::from_str::<RestartPolicy>("Always(max: Some(2), backoff_schedule: [\"1s\"])")
+                .unwrap(),
+            always
+        );
+        assert_eq!(
+            ron::from_str::<RestartPolicy>("Never").unwrap(),
+            RestartPolicy::Never
+        );
+        assert_eq!(
+            serde_json::from_str::<RestartPolicy>("\"on-failure\"").unwrap(),
+            on_failure
+        );
+
+        // TOML has no bare values, so policies are written and read as a field
+        #[derive(serde::Deserialize, serde::Serialize)]
+        struct Config {
+            restart: RestartPolicy,
+        }
+        let parse_toml = |toml: &str| toml::from_str::<Config>(toml).unwrap().restart;
+        for policy in policies {
+            let toml = toml::to_string(&Config {
+                restart: policy.clone(),
+            })
+            .unwrap();
+            assert_eq!(parse_toml(&toml), policy, "{}", toml);
+        }
+        assert_eq!(
+            parse_toml("restart = { Always = { max = 2, backoff_schedule = [\"1s\"] } }"),
+            always
+        );
+        assert_eq!(parse_toml("restart = \"on-failure\""), on_failure);
+        assert_eq!(parse_toml("restart = \"Never\""), RestartPolicy::Never);
+    }
+
+    #[test]
+    fn early_exit_delays() {
+        let backoff: EarlyExitBackoff =
+            serde_yaml::from_str("{ within: 5s, base: 100ms, max_delay: 1s, max_attempts: 10 }")
+                .unwrap();
+
+        let delays: Vec<_> = (0..6).map(|attempt| backoff.delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1000, 1000].map(Duration::from_millis)
+        );
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn rate_limit_cooldown() {
+        let limit: RestartRateLimit = serde_yaml::from_str("{ max: 5, within: 1m }").unwrap();
+        assert_eq!(limit.cooldown(), Duration::from_secs(60));
+
+        let limit: RestartRateLimit =
+            serde_yaml::from_str("{ max: 5, within: 1m, cooldown: 5s }").unwrap();
+        assert_eq!(limit.cooldown(), Duration::from_secs(5));
+    }
+}
@@ -1,14 +1,601 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+use toml_edit::Item;
 
-use super::{CommandConfig, KillBehavior};
+use super::{
+    CommandConfig, ConfigError, EarlyExitBackoff, ExitCodePolicy, KillBehavior, LabelWidth,
+    LogLevelColors, OutputBackpressure, OutputTimestamps, RestartSeparator, SecretsConfig, Signal,
+    SummaryOrder,
+};
+
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[non_exhaustive]
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct RunConfig {
     pub commands: Vec<CommandConfig>,
+    /// Run one after another before any of `commands` is spawned, e.g. `docker compose up -d`
+    /// or migrations. If one of them fails, the others and `commands` don't run.
+    ///
+    /// Unlike commands, their output isn't labeled and they may read the stdin of runcc,
+    /// see [`run_steps`](crate::run::run_steps). Steps are run by the CLI,
+    /// [`spawn_from_run_config_with_plugin`](crate::run::spawn_from_run_config_with_plugin)
+    /// leaves them out.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub before: Vec<CommandConfig>,
+    /// Run one after another once all `commands` stopped or a step of
+    /// [`before`](Self::before) failed, e.g. for cleanup. A failing step doesn't stop the
+    /// steps after it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<CommandConfig>,
     pub max_label_length: usize,
+    /// Labels are padded to this width, defaults to `max_label_length`
+    pub label_width: Option<LabelWidth>,
     pub envs: Option<HashMap<String, String>>,
+    /// A `.env` file of `NAME=value` lines, relative to the directory runcc is run in.
+    /// Its vars are added to `envs` unless they are set there,
+    /// see [`load_env_files`](Self::load_env_files).
+    pub env_file: Option<String>,
+    /// Env vars whose values are the output of a helper command,
+    /// see [`resolve_computed_env`](Self::resolve_computed_env)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub computed_env: HashMap<String, CommandConfig>,
+    /// Resolves env values like `secret://<path>`,
+    /// see [`resolve_secrets`](Self::resolve_secrets)
+    pub secrets: Option<SecretsConfig>,
+    /// When the exit of a command kills the others, unless it is
+    /// [`critical`](CommandConfig::critical) or [`transient`](CommandConfig::transient)
     #[serde(default)]
     pub kill: KillBehavior,
+    /// Kill all commands once all commands which are not
+    /// [`optional`](CommandConfig::optional) exited
+    #[serde(default)]
+    pub exit_when_only_optional_remain: bool,
+    /// Fail instead of running any command if [`filter_tags`](Self::filter_tags) or
+    /// [`select_profiles`](Self::select_profiles) leave out commands, e.g. in CI where every
+    /// command is expected to run. Commands skipped because of their prerequisites fail the
+    /// run, also with an [`exit_code`](Self::exit_code) which would ignore them.
+    #[serde(default)]
+    pub fail_on_skip: bool,
+    /// Named groups of commands which can be run alone, e.g. `frontend: [web, css]`,
+    /// see [`select_profiles`](Self::select_profiles).
+    ///
+    /// Entries are labels of commands or names of other profiles, e.g. `all: [frontend, api]`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Vec<String>>,
+    /// Signal sent to all commands on Ctrl-C, defaults to SIGKILL
+    pub ctrl_c_signal: Option<Signal>,
+    /// Send Ctrl-C to the [`foreground`](Self::foreground) or
+    /// [`interactive`](CommandConfig::interactive) command instead of shutting down, e.g. to
+    /// interrupt a REPL. Pressing Ctrl-C twice within a second still shuts down. Unix only.
+    ///
+    /// The other commands run in process groups of their own then, so that Ctrl-C on the
+    /// terminal doesn't reach them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ctrl_c_to_foreground: bool,
+    /// How long to wait for commands to exit after they were sent a signal,
+    /// before killing them forcefully. Defaults to [`DEFAULT_SHUTDOWN_TIMEOUT`].
+    #[serde(default, with = "humantime_serde")]
+    pub shutdown_timeout: Option<Duration>,
+    /// Send SIGTERM instead of killing commands forcefully when runcc kills all commands,
+    /// e.g. because a command exited, and kill the ones still running after this duration.
+    ///
+    /// `ctrl_c_signal` still applies on Ctrl-C, with `shutdown_timeout`.
+    /// On Windows, commands are terminated right away.
+    #[serde(default, with = "humantime_serde")]
+    pub kill_grace: Option<Duration>,
+    /// Kill all commands once the run took this long, e.g. so that a hung command doesn't
+    /// block CI forever. They count as failed, see also [`CommandConfig::timeout`].
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+    /// Signal sent instead of SIGTERM with `kill_grace`, e.g. SIGINT for commands which
+    /// only clean up on interrupt. Without `kill_grace`, commands still running
+    /// `shutdown_timeout` after it are killed forcefully.
+    ///
+    /// The [`kill_signal`](CommandConfig::kill_signal) of a command takes precedence.
+    pub kill_signal: Option<Signal>,
+    /// Spawn commands in process groups of their own and send signals to the whole group,
+    /// so that the descendants of commands, e.g. of shell wrappers like `sh -c "npm run dev"`,
+    /// are killed with them. Unix only, on Windows the descendants of commands are always
+    /// killed with them by a job object.
+    ///
+    /// Commands then don't get signals of the terminal, e.g. of Ctrl-C, themselves.
+    /// [`interactive`](CommandConfig::interactive) commands stay in the process group of runcc
+    /// unless they are the [`foreground`](Self::foreground) command.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub kill_process_groups: bool,
+    /// Run all commands with a pseudo-terminal, see [`CommandConfig::pty`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pty: bool,
+    /// Give commands a pipe as stdin which stays open while they run, so that runcc can
+    /// write input to them, see [`CommandHandle::write_line`](crate::run::CommandHandle::write_line).
+    /// Without it, their stdin is closed right away.
+    ///
+    /// [`interactive`](CommandConfig::interactive) and [`foreground`](Self::foreground)
+    /// commands read the stdin of runcc instead.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pipe_stdin: bool,
+    /// Print consecutive identical output lines of a command only once
+    #[serde(default)]
+    pub collapse_repeats: bool,
+    /// Write indented lines, like frames of a stack trace, together with the line before them,
+    /// so that output of other commands doesn't interleave them
+    #[serde(default)]
+    pub interleave_guard: bool,
+    /// Hold back the output of each command until it exited and then print it as one block,
+    /// together with its exit line, in the order of `commands`, e.g. for `fmt`, `clippy`
+    /// and `test` of a build. A command which exited waits for those before it which run.
+    ///
+    /// Not used with JSON output. The blocks aren't limited by
+    /// [`max_buffer_memory`](Self::max_buffer_memory).
+    #[serde(default)]
+    pub group_output: bool,
+    /// Print the start of a line, e.g. a prompt without a newline, once a command printed
+    /// nothing more for this long. The rest of the line is printed after it without a label.
+    ///
+    /// Not used for [`binary_safe`](CommandConfig::binary_safe) commands.
+    #[serde(default, with = "humantime_serde")]
+    pub partial_line_flush: Option<Duration>,
+    /// Print a time in front of each output line of commands
+    pub timestamps: Option<OutputTimestamps>,
+    /// Hold back output until all commands without prerequisites were spawned
+    #[serde(default)]
+    pub defer_output_until_started: bool,
+    /// What happens to output of commands while the terminal doesn't read it
+    #[serde(default)]
+    pub output_backpressure: OutputBackpressure,
+    /// The order of commands in the summary and the report once all commands stopped
+    #[serde(default)]
+    pub summary_order: SummaryOrder,
+    /// Which exit code runcc exits with once all commands stopped
+    #[serde(default, skip_serializing_if = "ExitCodePolicy::is_default")]
+    pub exit_code: ExitCodePolicy,
+    /// How many bytes of command output may be held in memory while deferred or
+    /// waiting for the terminal, the oldest output is dropped beyond that.
+    /// Unlimited if `None`.
+    pub max_buffer_memory: Option<usize>,
+    /// How many bytes a log file of a command may grow to, see [`CommandConfig::log_file`].
+    /// A full file is moved to `<file>.1`, replacing an older one, and a new file is started.
+    /// Unlimited if `None`.
+    pub log_file_max_size: Option<u64>,
+    /// The label of a command which reads the stdin of runcc and becomes the foreground process
+    /// group of its terminal, so that Ctrl-C and Ctrl-Z go to it instead of runcc. Unix only.
+    ///
+    /// The other commands run in process groups of their own then.
+    /// Their output and the output of this command are still labeled by runcc.
+    pub foreground: Option<String>,
+    /// How many commands which stopped for good are kept, the oldest are pruned.
+    /// Keeps all if `None`.
+    pub history_limit: Option<usize>,
+    /// How many output lines of each command are kept in memory, `--tail` prints them after
+    /// the output of a command when it failed, see
+    /// [`CommandSystem::tail`](crate::run::CommandSystem::tail)
+    pub tail_lines: Option<usize>,
+    /// How many commands may run at the same time, the others wait in the order
+    /// of `commands` until a running one stopped for good. Unlimited if `None`.
+    ///
+    /// A command keeps its slot while it is restarted. Commands which wait for
+    /// [`after`](CommandConfig::after) take a slot once their prerequisites are done.
+    pub max_concurrent: Option<usize>,
+    /// Spawn commands again with growing delays when they fail to spawn or fail shortly after
+    /// they were spawned, before they are ready. Waiting for the next attempt ends on shutdown.
+    ///
+    /// Commands may have an [`early_exit_backoff`](CommandConfig::early_exit_backoff) of
+    /// their own.
+    pub early_exit_backoff: Option<EarlyExitBackoff>,
+    /// The label of a command whose readiness means the whole system is ready, e.g. a
+    /// health check. If `None`, the system is ready once all commands with
+    /// [`ready_when`](CommandConfig::ready_when) or a startup probe are.
+    pub system_ready_when: Option<String>,
+    /// Pick the color of each label from a hash of the label and this seed instead of the
+    /// position of its command, so that a label keeps its color across runs and configs
+    pub color_seed: Option<u64>,
+    /// The line printed before a command is spawned again because its watched files changed
+    #[serde(default, skip_serializing_if = "RestartSeparator::is_default")]
+    pub restart_separator: RestartSeparator,
+    /// Print the exit line of commands which succeeded without any output.
+    /// If `false`, those commands print nothing.
+    #[serde(default = "default_show_silent_commands")]
+    pub show_silent_commands: bool,
+    /// Color output lines by the log level they contain, see
+    /// [`log_level_colors`](Self::log_level_colors), on terminals unless `NO_COLOR` is set.
+    /// Lines of [`binary_safe`](CommandConfig::binary_safe) commands keep their colors.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub colorize_log_levels: bool,
+    /// Which words are log levels and the color of their lines
+    #[serde(default, skip_serializing_if = "LogLevelColors::is_default")]
+    pub log_level_colors: LogLevelColors,
+}
+
+pub(super) fn default_show_silent_commands() -> bool {
+    true
+}
+
+impl RunConfig {
+    /// Keep only commands with any of `tags` and without any of `exclude_tags`.
+    ///
+    /// Empty `tags` keeps all commands which are not excluded.
+    /// Returns the labels of the removed commands with why they were removed.
+    pub fn filter_tags(
+        &mut self,
+        tags: &[String],
+        exclude_tags: &[String],
+    ) -> Vec<(String, String)> {
+        let mut removed = Vec::new();
+        self.commands.retain(|cmd| {
+            let excluded = exclude_tags
+                .iter()
+                .find(|tag| cmd.has_any_tag(std::slice::from_ref(tag)));
+            let reason = if !tags.is_empty() && !cmd.has_any_tag(tags) {
+                format!("it has none of the tags {}", tags.join(", "))
+            } else if let Some(tag) = excluded {
+                format!("it has the excluded tag {}", tag)
+            } else {
+                return true;
+            };
+            removed.push((cmd.resolved_label(), reason));
+            false
+        });
+        removed
+    }
+
+    /// Keep only the commands of any of `profiles`, including those of the profiles they contain.
+    ///
+    /// Empty `profiles` keeps all commands.
+    /// Returns the labels of the removed commands with why they were removed.
+    pub fn select_profiles(
+        &mut self,
+        profiles: &[String],
+    ) -> Result<Vec<(String, String)>, ConfigError> {
+        if let Some(profile) = profiles.iter().find(|p| !self.profiles.contains_key(*p)) {
+            return Err(ConfigError::UnknownProfile {
+                profile: profile.clone(),
+            });
+        }
+        if profiles.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut labels = HashSet::new();
+        let mut visited = HashSet::new();
+        let mut pending: Vec<&str> = profiles.iter().map(String::as_str).collect();
+        while let Some(name) = pending.pop() {
+            if !visited.insert(name) {
+                continue;
+            }
+            match self.profiles.get(name) {
+                Some(entries) => pending.extend(entries.iter().map(String::as_str)),
+                None => {
+                    labels.insert(name.to_string());
+                }
+            }
+        }
+
+        let mut removed = Vec::new();
+        self.commands.retain(|cmd| {
+            let label = cmd.resolved_label();
+            if labels.contains(&label) {
+                return true;
+            }
+            let reason = format!("it is in none of the profiles {}", profiles.join(", "));
+            removed.push((label, reason));
+            false
+        });
+        Ok(removed)
+    }
+
+    /// Make the command with this label, or at this index if no label matches, the only
+    /// [`interactive`](CommandConfig::interactive) one.
+    ///
+    /// Returns whether another command was interactive before.
+    pub fn make_interactive(&mut self, label_or_index: &str) -> Result<bool, ConfigError> {
+        let index = self
+            .commands
+            .iter()
+            .position(|cmd| cmd.resolved_label() == label_or_index)
+            .or_else(|| label_or_index.parse().ok())
+            .filter(|&i| i < self.commands.len())
+            .ok_or_else(|| ConfigError::UnknownInteractive {
+                label: label_or_index.to_string(),
+            })?;
+
+        let mut others = false;
+        for (i, cmd) in self.commands.iter_mut().enumerate() {
+            others |= cmd.interactive && i != index;
+            cmd.interactive = i == index;
+        }
+        Ok(others)
+    }
+
+    /// Resolve the paths relative to the directory runcc is run in against `dir` instead,
+    /// e.g. the directory of the config file: the `cwd` of commands, so that commands without
+    /// one run in `dir`, the [`env_file`](Self::env_file)s and the `log_file`s.
+    pub fn resolve_paths_in(&mut self, dir: &Path) {
+        let resolve = |path: &mut String| {
+            if Path::new(path).is_relative() {
+                *path = dir.join(&*path).to_string_lossy().into_owned();
+            }
+        };
+
+        self.env_file.iter_mut().for_each(resolve);
+        let steps = self.before.iter_mut().chain(&mut self.after);
+        for cmd in self.commands.iter_mut().chain(steps) {
+            match &mut cmd.cwd {
+                Some(cwd) => resolve(cwd),
+                None => cmd.cwd = Some(dir.to_string_lossy().into_owned()),
+            }
+            cmd.env_file.iter_mut().for_each(resolve);
+            cmd.log_file.iter_mut().for_each(resolve);
+        }
+    }
+
+    /// Dump the resolved config as TOML.
+    ///
+    /// The output can be read back as a config file.
+    pub fn to_toml_string(&self) -> Result<String, toml::ser::Error> {
+        // serialize to a toml value first so that tables are emitted after plain values
+        let value = toml::Value::try_from(self)?;
+        let dumped = toml::to_string(&value)?;
+
+        // toml reads restart policies with options in arrays of tables only from inline tables
+        let mut doc: toml_edit::Document = dumped.parse().expect("toml writes valid toml");
+        for key in ["before", "commands", "after"] {
+            let commands = doc.get_mut(key).and_then(Item::as_array_of_tables_mut);
+            for cmd in commands.into_iter().flat_map(|cmds| cmds.iter_mut()) {
+                if let Some(restart) = cmd.get_mut("restart") {
+                    restart.make_value();
+                    cmd.fmt();
+                }
+            }
+        }
+        Ok(doc.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RunConfig;
+    use crate::RunConfigInput;
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_paths_in_config_dir() {
+        let input: RunConfigInput = serde_yaml::from_str(
+            r#"
+env_file: .env
+commands:
+  - program: web
+    cwd: web
+    log_file: /tmp/web.log
+  - program: api
+"#,
+        )
+        .unwrap();
+        let mut config: RunConfig = input.into();
+        config.resolve_paths_in(std::path::Path::new("/work/app"));
+
+        assert_eq!(config.env_file.as_deref(), Some("/work/app/.env"));
+        assert_eq!(config.commands[0].cwd.as_deref(), Some("/work/app/web"));
+        assert_eq!(config.commands[0].log_file.as_deref(), Some("/tmp/web.log"));
+        assert_eq!(config.commands[1].cwd.as_deref(), Some("/work/app"));
+    }
+
+    #[test]
+    fn labeled_commands_in_config_order() {
+        let labels = |config: RunConfig| -> Vec<_> {
+            config.commands.iter().map(|c| c.resolved_label()).collect()
+        };
+        let order = ["web", "api", "worker", "db", "cache", "css"];
+
+        let yaml: RunConfigInput = serde_yaml::from_str(
+            "commands: { web: a, api: b, worker: c, db: d, cache: e, css: f }",
+        )
+        .unwrap();
+        assert_eq!(labels(yaml.into()), order);
+        let json: RunConfigInput = serde_json::from_str(
+            r#"{ "commands": { "web": "a", "api": "b", "worker": "c", "db": "d", "cache": "e", "css": "f" } }"#,
+        )
+        .unwrap();
+        assert_eq!(labels(json.into()), order);
+    }
+
+    #[test]
+    fn filter_tags() {
+        let programs = |tags: &[&str], exclude_tags: &[&str]| {
+            let input: RunConfigInput = serde_yaml::from_str(
+                r#"
+commands:
+  - program: web
+    tags: [frontend]
+  - program: e2e
+    tags: [frontend, slow]
+  - program: migrate
+    tags: [db, slow]
+  - program: api
+"#,
+            )
+            .unwrap();
+            let mut config: RunConfig = input.into();
+
+            let to_strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            config.filter_tags(&to_strings(tags), &to_strings(exclude_tags));
+
+            config
+                .commands
+                .into_iter()
+                .map(|cmd| cmd.program)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(programs(&[], &[]), ["web", "e2e", "migrate", "api"]);
+        assert_eq!(programs(&["frontend"], &[]), ["web", "e2e"]);
+        assert_eq!(
+            programs(&["frontend", "db"], &[]),
+            ["web", "e2e", "migrate"]
+        );
+        assert_eq!(programs(&[], &["slow"]), ["web", "api"]);
+        assert_eq!(programs(&["frontend"], &["slow"]), ["web"]);
+    }
+
+    #[test]
+    fn filter_tags_reasons() {
+        let input: RunConfigInput = serde_yaml::from_str(
+            r#"
+commands:
+  - program: web
+    tags: [frontend]
+  - program: e2e
+    tags: [frontend, slow]
+  - program: api
+"#,
+        )
+        .unwrap();
+        let mut config: RunConfig = input.into();
+
+        let removed = config.filter_tags(&["frontend".to_string()], &["slow".to_string()]);
+        assert_eq!(
+            removed,
+            [
+                (
+                    "e2e".to_string(),
+                    "it has the excluded tag slow".to_string()
+                ),
+                (
+                    "api".to_string(),
+                    "it has none of the tags frontend".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn select_profiles() {
+        let labels = |profiles: &[&str]| {
+            let input: RunConfigInput = serde_yaml::from_str(
+                r#"
+commands:
+  - web
+  - css
+  - api
+  - db
+profiles:
+  frontend: [web, css]
+  backend: [api, db]
+  all: [frontend, backend]
+  cycle: [cycle, api]
+"#,
+            )
+            .unwrap();
+            let mut config: RunConfig = input.into();
+            config.validate().unwrap();
+
+            let profiles: Vec<_> = profiles.iter().map(|s| s.to_string()).collect();
+            let removed = config
+                .select_profiles(&profiles)
+                .map_err(|err| err.to_string())?;
+            let labels: Vec<_> = config.commands.iter().map(|c| c.resolved_label()).collect();
+            Ok::<_, String>((labels, removed.len()))
+        };
+
+        assert_eq!(labels(&[]).unwrap().0, ["web", "css", "api", "db"]);
+        assert_eq!(
+            labels(&["frontend"]).unwrap(),
+            (vec!["web".into(), "css".into()], 2)
+        );
+        assert_eq!(labels(&["frontend", "backend"]), labels(&[]));
+        assert_eq!(labels(&["all"]), labels(&[]));
+        assert_eq!(labels(&["cycle"]).unwrap().0, ["api"]);
+        assert_eq!(labels(&["web"]), Err("no profile web".to_string()));
+    }
+
+    #[test]
+    fn make_interactive() {
+        let input: RunConfigInput = serde_yaml::from_str(
+            r#"
+commands:
+  repl: python
+  migrate:
+    program: ./migrate
+    interactive: true
+"#,
+        )
+        .unwrap();
+        let mut config: RunConfig = input.into();
+        config.commands.sort_by_key(|cmd| cmd.resolved_label());
+        let interactive = |config: &RunConfig| {
+            let commands = config.commands.iter();
+            commands.map(|cmd| cmd.interactive).collect::<Vec<_>>()
+        };
+
+        assert!(config.make_interactive("repl").unwrap());
+        assert_eq!(interactive(&config), [false, true]);
+        // by index if no label matches
+        assert!(config.make_interactive("0").unwrap());
+        assert_eq!(interactive(&config), [true, false]);
+        assert!(!config.make_interactive("migrate").unwrap());
+        assert!(config.make_interactive("db").is_err());
+        assert!(config.make_interactive("2").is_err());
+    }
+
+    #[test]
+    fn sequential_mode() {
+        let config = |yaml| -> RunConfig {
+            let input: RunConfigInput = serde_yaml::from_str(yaml).unwrap();
+            input.into()
+        };
+
+        let yaml = "commands: [cargo build, cargo test]\nmax_parallel: 2";
+        assert_eq!(config(yaml).max_concurrent, Some(2));
+        let yaml = "commands: [cargo build, cargo test]\nmode: sequential\nmax_concurrent: 2";
+        assert_eq!(config(yaml).max_concurrent, Some(1));
+        let yaml = "commands: [cargo build, cargo test]\nmode: parallel";
+        assert_eq!(config(yaml).max_concurrent, None);
+    }
+
+    #[test]
+    fn toml_dump_round_trip() {
+        let input: RunConfigInput = serde_yaml::from_str(
+            r#"
+commands:
+  - program: cargo
+    args: [run]
+    envs: [[PORT, "3000"]]
+    cwd: ./server
+    pane: backend
+  - program: web
+    restart: on-failure
+  - program: worker
+    restart: always
+  - program: queue
+    restart: { Always: { max: 3 } }
+  - program: db
+    restart: { UntilReady: { max: 2, ready_timeout: 10s, backoff_schedule: [1s, 5s] } }
+  - program: migrate
+    restart: { OnFailure: { backoff_schedule: [2s] } }
+  - program: lint
+    restart: never
+envs:
+  RUST_LOG: info
+kill: 123
+"#,
+        )
+        .unwrap();
+        let config: RunConfig = input.into();
+
+        let dumped = config.to_toml_string().unwrap();
+        assert!(dumped.contains("kill = 123"));
+        assert!(dumped.contains("max_label_length = 9"));
+
+        let reloaded: RunConfig = toml::from_str(&dumped).unwrap();
+        assert_eq!(reloaded.kill, config.kill);
+        let restarts = |config: &RunConfig| {
+            let commands = config.commands.iter();
+            commands.map(|cmd| cmd.restart.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(restarts(&reloaded), restarts(&config));
+        assert!(restarts(&config)[1..].iter().all(Option::is_some));
+        assert_eq!(reloaded.to_toml_string().unwrap(), dumped);
+    }
 }
@@ -0,0 +1,225 @@
+use std::str::FromStr;
+
+/// When a command runs, see [`CommandConfig::schedule`](super::CommandConfig::schedule).
+///
+/// Five fields separated by spaces: minute, hour, day of month, month and day of week,
+/// with 0 or 7 for Sunday. A field is `*`, a value like `5`, a range like `1-5`, any of
+/// these with a step like `*/15` or `8-18/2`, or a list of them like `0,30`.
+/// `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are short for the usual
+/// expressions.
+///
+/// If both day of month and day of week are restricted, a day matching either of them
+/// matches, like in crontab.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+/// Searched for the next time which matches, long enough for Feb 29 on a given weekday
+const MAX_DAYS_AHEAD: i64 = 366 * 28;
+
+impl FromStr for CronSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = match s.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            s => s,
+        };
+        let fields: Vec<_> = s.split_whitespace().collect();
+        let (minute, hour, day, month, weekday) = match fields[..] {
+            [minute, hour, day, month, weekday] => (minute, hour, day, month, weekday),
+            _ => {
+                return Err(format!(
+                    "expected 5 fields (minute, hour, day of month, month and day of week), got {}",
+                    fields.len()
+                ))
+            }
+        };
+
+        let mut weekdays = parse_field(weekday, 0, 7, "day of week")?;
+        // 7 is Sunday as well
+        if weekdays & 1 << 7 != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        let schedule = Self {
+            minutes: parse_field(minute, 0, 59, "minute")?,
+            hours: parse_field(hour, 0, 23, "hour")?,
+            days: parse_field(day, 1, 31, "day of month")?,
+            months: parse_field(month, 1, 12, "month")?,
+            weekdays,
+            days_restricted: day != "*",
+            weekdays_restricted: weekday != "*",
+        };
+        match schedule.next_after(0) {
+            Some(_) => Ok(schedule),
+            None => Err("it never matches".to_string()),
+        }
+    }
+}
+
+/// The values of a field as bits, e.g. `*/20` of minutes has the bits 0, 20 and 40 set
+fn parse_field(field: &str, min: u32, max: u32, name: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid {} `{}`", name, field);
+    let value = |s: &str| match s.parse() {
+        Ok(value) if (min..=max).contains(&value) => Ok(value),
+        _ => Err(format!("{} `{}` isn't within {}-{}", name, s, min, max)),
+    };
+
+    let mut bits = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(invalid()),
+            },
+            None => (item, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (value(first)?, value(last)?),
+            // a single value with a step runs from it to the end, like in crontab
+            None if item.contains('/') => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if first > last {
+            return Err(invalid());
+        }
+        for value in (first..=last).step_by(step) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl CronSchedule {
+    /// The first minute after `secs` which matches, both in seconds since 1970-01-01 00:00
+    /// of the time zone the schedule is in. `None` if none matches within 28 years.
+    pub fn next_after(&self, secs: i64) -> Option<i64> {
+        let start = secs.div_euclid(60) + 1;
+        let first_day = start.div_euclid(24 * 60);
+
+        for day in first_day..first_day + MAX_DAYS_AHEAD {
+            if !self.matches_day(day) {
+                continue;
+            }
+            let first_minute = match day == first_day {
+                true => start.rem_euclid(24 * 60),
+                false => 0,
+            };
+            for minute_of_day in first_minute..24 * 60 {
+                let (hour, minute) = (minute_of_day / 60, minute_of_day % 60);
+                if self.hours & 1 << hour != 0 && self.minutes & 1 << minute != 0 {
+                    return Some((day * 24 * 60 + minute_of_day) * 60);
+                }
+            }
+        }
+        None
+    }
+
+    /// Whether the `day`-th day since 1970-01-01 matches
+    fn matches_day(&self, day: i64) -> bool {
+        let (_, month, day_of_month) = civil_from_days(day);
+        // 1970-01-01 was a Thursday
+        let weekday = (day + 4).rem_euclid(7);
+        if self.months & 1 << month == 0 {
+            return false;
+        }
+        let matches_day = self.days & 1 << day_of_month != 0;
+        let matches_weekday = self.weekdays & 1 << weekday != 0;
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => matches_day || matches_weekday,
+            _ => matches_day && matches_weekday,
+        }
+    }
+}
+
+/// Year, month and day of the `days`-th day since 1970-01-01,
+/// see <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{civil_from_days, CronSchedule};
+
+    /// Seconds since 1970 of a UTC date and time
+    fn at(year: i64, month: i64, day: i64, hour: i64, minute: i64) -> i64 {
+        // days_from_civil
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        (days * 24 * 60 + hour * 60 + minute) * 60
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(
+            civil_from_days(at(2024, 2, 29, 0, 0) / 86400),
+            (2024, 2, 29)
+        );
+        assert_eq!(
+            civil_from_days(at(2000, 12, 31, 0, 0) / 86400),
+            (2000, 12, 31)
+        );
+    }
+
+    #[test]
+    fn next_matching_minute() {
+        let next =
+            |schedule: &str, after| schedule.parse::<CronSchedule>().unwrap().next_after(after);
+
+        let now = at(2024, 5, 17, 10, 3) + 42;
+        assert_eq!(next("*/5 * * * *", now), Some(at(2024, 5, 17, 10, 5)));
+        assert_eq!(next("* * * * *", now), Some(at(2024, 5, 17, 10, 4)));
+        assert_eq!(next("0,30 9-17 * * *", now), Some(at(2024, 5, 17, 10, 30)));
+        assert_eq!(next("0 9 * * *", now), Some(at(2024, 5, 18, 9, 0)));
+        assert_eq!(next("@monthly", now), Some(at(2024, 6, 1, 0, 0)));
+        // 2024-05-17 is a Friday
+        assert_eq!(next("0 8 * * 1-5", now), Some(at(2024, 5, 20, 8, 0)));
+        assert_eq!(next("0 8 * * 7", now), Some(at(2024, 5, 19, 8, 0)));
+        // either the 1st or a Sunday
+        assert_eq!(next("0 0 1 * 0", now), Some(at(2024, 5, 19, 0, 0)));
+        assert_eq!(next("0 0 29 2 *", now), Some(at(2028, 2, 29, 0, 0)));
+        assert_eq!(next("5/20 * * * *", now), Some(at(2024, 5, 17, 10, 5)));
+    }
+
+    #[test]
+    fn invalid_schedules() {
+        for invalid in [
+            "* * * *",
+            "60 * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+            "0 0 30 2 *",
+        ] {
+            assert!(invalid.parse::<CronSchedule>().is_err(), "{}", invalid);
+        }
+    }
+}
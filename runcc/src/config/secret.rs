@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+use std::{error, fmt::Display, io, path::PathBuf};
+
+use super::RunConfig;
+
+/// Env values starting with this prefix are resolved by a [`SecretProvider`]
+pub const SECRET_PREFIX: &str = "secret://";
+
+/// Resolves the path of a `secret://<path>` env value to the secret
+pub trait SecretProvider {
+    fn resolve(&self, path: &str) -> io::Result<String>;
+}
+
+/// Reads `<dir>/<path>`, e.g. Docker secrets in `/run/secrets`.
+///
+/// A trailing newline is removed.
+pub struct FileSecretProvider {
+    pub dir: PathBuf,
+}
+
+impl SecretProvider for FileSecretProvider {
+    fn resolve(&self, path: &str) -> io::Result<String> {
+        let secret = std::fs::read_to_string(self.dir.join(path))?;
+        Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+/// Reads the env var of runcc named `path`
+pub struct EnvSecretProvider;
+
+impl SecretProvider for EnvSecretProvider {
+    fn resolve(&self, path: &str) -> io::Result<String> {
+        std::env::var(path).map_err(|err| io::Error::new(io::ErrorKind::NotFound, err))
+    }
+}
+
+/// Runs `vault kv get -field=<field> <path>` for `secret://<path>#<field>`,
+/// the field defaults to `value`.
+#[cfg(feature = "vault")]
+pub struct VaultSecretProvider {
+    /// Passed as `VAULT_ADDR`, defaults to the env of runcc
+    pub address: Option<String>,
+}
+
+#[cfg(feature = "vault")]
+impl SecretProvider for VaultSecretProvider {
+    fn resolve(&self, path: &str) -> io::Result<String> {
+        let (path, field) = path.split_once('#').unwrap_or((path, "value"));
+
+        let mut command = std::process::Command::new("vault");
+        command
+            .args(["kv", "get"])
+            .arg(format!("-field={}", field))
+            .arg(path);
+        if let Some(address) = &self.address {
+            command.env("VAULT_ADDR", address);
+        }
+
+        let output = command.output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "vault failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end_matches(['\r', '\n'])
+            .to_string())
+    }
+}
+
+/// Which [`SecretProvider`] resolves `secret://` env values
+#[non_exhaustive]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum SecretsConfig {
+    File {
+        dir: PathBuf,
+    },
+    Env,
+    #[cfg(feature = "vault")]
+    Vault {
+        address: Option<String>,
+    },
+}
+
+impl SecretsConfig {
+    pub fn provider(&self) -> Box<dyn SecretProvider> {
+        match self {
+            SecretsConfig::File { dir } => Box::new(FileSecretProvider { dir: dir.clone() }),
+            SecretsConfig::Env => Box::new(EnvSecretProvider),
+            #[cfg(feature = "vault")]
+            SecretsConfig::Vault { address } => Box::new(VaultSecretProvider {
+                address: address.clone(),
+            }),
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum SecretError {
+    /// An env value refers to a secret but no provider is configured
+    NoProvider { name: String },
+    /// The provider failed to resolve the secret of an env var
+    Resolve { name: String, error: io::Error },
+}
+
+impl Display for SecretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecretError::NoProvider { name } => write!(
+                f,
+                "env var {} refers to a secret but no secrets provider is configured",
+                name
+            ),
+            SecretError::Resolve { name, error } => {
+                write!(f, "failed to resolve secret of env var {}: {}", name, error)
+            }
+        }
+    }
+}
+
+impl error::Error for SecretError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SecretError::Resolve { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+fn resolve(
+    provider: Option<&dyn SecretProvider>,
+    name: &str,
+    value: &mut String,
+    secrets: &mut Vec<String>,
+) -> Result<(), SecretError> {
+    let path = match value.strip_prefix(SECRET_PREFIX) {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let provider = provider.ok_or_else(|| SecretError::NoProvider {
+        name: name.to_string(),
+    })?;
+    let secret = provider
+        .resolve(path)
+        .map_err(|error| SecretError::Resolve {
+            name: name.to_string(),
+            error,
+        })?;
+
+    *value = secret.clone();
+    secrets.push(secret);
+    Ok(())
+}
+
+impl RunConfig {
+    /// Replace `secret://<path>` values of [`envs`](Self::envs) and envs of commands
+    /// with the secrets resolved by the provider of [`secrets`](Self::secrets).
+    ///
+    /// Returns the resolved secrets, so that they can be masked in output.
+    pub fn resolve_secrets(&mut self) -> Result<Vec<String>, SecretError> {
+        let provider = self.secrets.as_ref().map(SecretsConfig::provider);
+        let provider = provider.as_deref();
+        let mut secrets = vec![];
+
+        if let Some(envs) = &mut self.envs {
+            for (name, value) in envs.iter_mut() {
+                resolve(provider, name, value, &mut secrets)?;
+            }
+        }
+
+        let commands = self
+            .commands
+            .iter_mut()
+            .chain(self.computed_env.values_mut());
+        for cmd in commands {
+            for (name, value) in cmd.envs.iter_mut().flatten() {
+                resolve(provider, name, value, &mut secrets)?;
+            }
+        }
+
+        Ok(secrets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RunConfig, RunConfigInput, SecretError};
+
+    #[test]
+    fn resolve_file_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("db")).unwrap();
+        std::fs::write(dir.path().join("db/password"), "hunter2\n").unwrap();
+
+        let yaml = format!(
+            r#"
+commands:
+  - program: psql
+    envs: [[PGPASSWORD, "secret://db/password"], [PGUSER, admin]]
+envs:
+  DATABASE_PASSWORD: secret://db/password
+secrets:
+  provider: file
+  dir: {}
+"#,
+            dir.path().display()
+        );
+        let input: RunConfigInput = serde_yaml::from_str(&yaml).unwrap();
+        let mut config: RunConfig = input.into();
+
+        let secrets = config.resolve_secrets().unwrap();
+
+        assert_eq!(secrets, ["hunter2", "hunter2"]);
+        assert_eq!(config.envs.unwrap()["DATABASE_PASSWORD"], "hunter2");
+        assert_eq!(
+            config.commands[0].envs.as_deref().unwrap(),
+            [
+                ("PGPASSWORD".to_string(), "hunter2".to_string()),
+                ("PGUSER".to_string(), "admin".to_string())
+            ]
+        );
+
+        let input: RunConfigInput = serde_yaml::from_str(
+            r#"
+commands: [psql]
+envs:
+  DATABASE_PASSWORD: secret://db/password
+"#,
+        )
+        .unwrap();
+        let mut config: RunConfig = input.into();
+        assert!(matches!(
+            config.resolve_secrets(),
+            Err(SecretError::NoProvider { .. })
+        ));
+    }
+}
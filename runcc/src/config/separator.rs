@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+/// The line printed before a command runs again because its watched files changed,
+/// see [`CommandConfig::watch_dir`](super::CommandConfig::watch_dir)
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct RestartSeparator {
+    /// Repeated to the width of the terminal around the time and the changed file
+    #[serde(default = "default_separator_character")]
+    pub character: char,
+    /// Clear the screen of the terminal before the line, so that only the new run is visible
+    #[serde(default)]
+    pub clear_screen: bool,
+}
+
+fn default_separator_character() -> char {
+    '─'
+}
+
+impl RestartSeparator {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for RestartSeparator {
+    fn default() -> Self {
+        Self {
+            character: default_separator_character(),
+            clear_screen: false,
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// A signal sent to commands when they are killed.
+///
+/// On Windows, commands which don't read stdin get Ctrl-Break for any signal but `SIGKILL`,
+/// other commands are killed forcefully. On other platforms without signals, commands are
+/// always killed forcefully.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Signal {
+    #[serde(rename = "SIGINT", alias = "INT")]
+    Interrupt,
+    #[serde(rename = "SIGTERM", alias = "TERM")]
+    Terminate,
+    #[serde(rename = "SIGKILL", alias = "KILL")]
+    Kill,
+    #[serde(rename = "SIGHUP", alias = "HUP")]
+    Hangup,
+    #[serde(rename = "SIGQUIT", alias = "QUIT")]
+    Quit,
+}
+
+impl Signal {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Signal::Interrupt => "SIGINT",
+            Signal::Terminate => "SIGTERM",
+            Signal::Kill => "SIGKILL",
+            Signal::Hangup => "SIGHUP",
+            Signal::Quit => "SIGQUIT",
+        }
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn as_raw(&self) -> libc::c_int {
+        match self {
+            Signal::Interrupt => libc::SIGINT,
+            Signal::Terminate => libc::SIGTERM,
+            Signal::Kill => libc::SIGKILL,
+            Signal::Hangup => libc::SIGHUP,
+            Signal::Quit => libc::SIGQUIT,
+        }
+    }
+}
+
+impl Display for Signal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for Signal {
+    type Err = serde_yaml::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_yaml::from_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Signal;
+
+    #[test]
+    fn parse_signal() {
+        for (input, val) in [
+            ("SIGINT", Signal::Interrupt),
+            ("TERM", Signal::Terminate),
+            ("SIGKILL", Signal::Kill),
+        ] {
+            let res: Signal = input.parse().unwrap();
+            assert_eq!(res, val);
+        }
+
+        assert!("SIGFOO".parse::<Signal>().is_err());
+    }
+}
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// Where the lines of a stream of a command go, see
+/// [`CommandConfig::stdout`](super::CommandConfig::stdout)
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamRoute {
+    /// Printed by runcc on the same stream, and written to the log file of the command
+    #[default]
+    Console,
+    /// Printed and logged like lines of the other stream, e.g. `stderr: merge` prints stderr
+    /// to stdout and `--format json` reports its lines as `stdout`
+    Merge,
+    /// Read to the end but neither printed nor logged. The lines don't make the command
+    /// ready either.
+    Discard,
+    /// Only written to the [`log_file`](super::CommandConfig::log_file) of the command or its
+    /// file in `--log-dir`, not printed. The lines still make the command ready.
+    File,
+}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+/// The order in which the summary and the report of a run list commands
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryOrder {
+    /// Commands which succeeded first, then killed and failed ones, each in config order
+    #[default]
+    Outcome,
+    /// Failed commands first, then killed and succeeded ones, each alphabetically by label
+    Status,
+    /// The order of [`RunConfig::commands`](crate::RunConfig::commands)
+    Config,
+    /// The order in which commands stopped for good
+    Exit,
+    /// Alphabetically by label
+    Label,
+    /// The longest running first, commands which never ran last
+    Duration,
+}
+
+impl std::str::FromStr for SummaryOrder {
+    type Err = serde_yaml::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_yaml::from_str(s)
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use super::RunConfig;
+
+/// `s` with `${NAME}` replaced by the value `lookup` returns for `NAME`, or by `default` for
+/// `${NAME:-default}` if it returns none. `$${` is a literal `${`.
+///
+/// Variables without a value or default and anything else after `${`, like `${NAME:+x}`, are
+/// left as they are, so that a shell running the command can still expand them.
+fn expand(s: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest[2..].find('}').and_then(|end| {
+            let inner = &rest[2..2 + end];
+            let (name, default) = match inner.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (inner, None),
+            };
+            let value = is_var_name(name)
+                .then(|| lookup(name).or_else(|| default.map(str::to_string)))
+                .flatten()?;
+            Some((value, 2 + end + 1))
+        });
+        match value {
+            Some((value, len)) => {
+                expanded.push_str(&value);
+                rest = &rest[len..];
+            }
+            None => {
+                expanded.push_str("${");
+                rest = &rest[2..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl RunConfig {
+    /// Replaces `${NAME}` in `program`, `args` and `cwd` of each command by the value of
+    /// `NAME` in `vars`, e.g. from `--set NAME=value`, the `envs` of the command, the
+    /// [`envs`](Self::envs) of this config or the environment of runcc, in this order.
+    ///
+    /// `${NAME:-default}` falls back to `default` and `$${` is a literal `${`. Variables
+    /// without any value are left as they are.
+    pub fn expand_vars(&mut self, vars: &HashMap<String, String>) {
+        let envs = self.envs.as_ref();
+        for cmd in &mut self.commands {
+            let cmd_envs = cmd.envs.as_deref().unwrap_or_default();
+            let lookup = |name: &str| {
+                vars.get(name)
+                    .cloned()
+                    .or_else(|| {
+                        let mut values = cmd_envs.iter().rev();
+                        values.find(|(n, _)| n == name).map(|(_, v)| v.clone())
+                    })
+                    .or_else(|| envs.and_then(|envs| envs.get(name).cloned()))
+                    .or_else(|| std::env::var(name).ok())
+            };
+
+            cmd.program = expand(&cmd.program, &lookup);
+            for arg in cmd.args.iter_mut().flatten() {
+                *arg = expand(arg, &lookup);
+            }
+            if let Some(cwd) = &mut cmd.cwd {
+                *cwd = expand(cwd, &lookup);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand;
+    use crate::{RunConfig, RunConfigInput};
+
+    #[test]
+    fn expand_vars_in_strings() {
+        let lookup = |name: &str| (name == "PORT").then(|| "3000".to_string());
+        let expand = |s| expand(s, &lookup);
+
+        assert_eq!(expand("--port=${PORT}"), "--port=3000");
+        assert_eq!(expand("${PORT}${PORT}"), "30003000");
+        assert_eq!(expand("${HOST:-localhost}:${PORT:-80}"), "localhost:3000");
+        assert_eq!(expand("${HOST:-}"), "");
+        // left for the shell
+        assert_eq!(
+            expand("${HOST} $PORT ${PORT:+set} ${PORT"),
+            "${HOST} $PORT ${PORT:+set} ${PORT"
+        );
+        assert_eq!(expand("$${PORT}"), "${PORT}");
+    }
+
+    #[test]
+    fn expand_vars_of_commands() {
+        let yaml = r#"
+envs:
+  PORT: "3000"
+  DIR: app
+commands:
+  - program: serve
+    args: ["--port", "${PORT}", "--host", "${RUNCC_TEST_HOST:-localhost}"]
+    cwd: "${DIR}"
+  - program: "${BIN}"
+    cwd: "${DIR}"
+    envs: { DIR: web }
+"#;
+        let input: RunConfigInput = serde_yaml::from_str(yaml).unwrap();
+        let mut config: RunConfig = input.into();
+        let vars = std::iter::once(("BIN".to_string(), "./api".to_string())).collect();
+        config.expand_vars(&vars);
+
+        let serve = &config.commands[0];
+        assert_eq!(
+            serve.args.as_deref().unwrap(),
+            ["--port", "3000", "--host", "localhost"]
+        );
+        assert_eq!(serve.cwd.as_deref(), Some("app"));
+        let api = &config.commands[1];
+        assert_eq!(api.program, "./api");
+        assert_eq!(api.cwd.as_deref(), Some("web"));
+    }
+}
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+/// Which time is printed in front of output lines of commands
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputTimestamps {
+    /// UTC time like `2024-01-01T12:00:00.000Z`
+    Absolute,
+    /// Seconds since the run started
+    Relative,
+    /// Seconds since the command was spawned, reset when it is restarted
+    PerCommandRelative,
+}
+
+impl std::str::FromStr for OutputTimestamps {
+    type Err = serde_yaml::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_yaml::from_str(s)
+    }
+}
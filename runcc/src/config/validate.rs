@@ -0,0 +1,1074 @@
+use std::{collections::HashMap, error, fmt::Display};
+
+use super::{
+    is_valid_faketime, LineExtractor, LineFilter, OutputEncoding, PortNumber, RestartPolicy,
+    RunConfig,
+};
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A command refers to a label which no command has
+    UnknownCommand {
+        label: String,
+        referenced_by: String,
+    },
+    /// Commands wait for each other in a cycle
+    DependencyCycle(Vec<String>),
+    /// `ready_when` is not a valid regex
+    InvalidReadyWhen { label: String, error: regex::Error },
+    /// The regex of `label_from` is invalid
+    InvalidLabelFrom { label: String, error: regex::Error },
+    /// A regex of `ignore` or `show_only` is invalid
+    InvalidLineFilter { label: String, error: regex::Error },
+    /// The regex of `progress_from` is invalid or captures neither 1 nor 2 groups
+    InvalidProgressFrom { label: String, reason: String },
+    /// The restart policy of a command needs `ready_when` or `startup_probe`
+    MissingReadyWhen { label: String },
+    /// A command waits in `after_ready` for a command without `ready_when` or `startup_probe`
+    NeverReady {
+        label: String,
+        referenced_by: String,
+    },
+    /// `watch_include` or `watch_exclude` has an invalid glob
+    InvalidWatchGlob {
+        label: String,
+        error: glob::PatternError,
+    },
+    /// `encoding` is not a supported encoding
+    UnknownEncoding { label: String, encoding: String },
+    /// `faketime` is no timestamp libfaketime understands, or the platform isn't Unix
+    InvalidFaketime { label: String, faketime: String },
+    /// No command has the label of `foreground`, or the platform isn't Unix
+    InvalidForeground { label: String },
+    /// No command has the label of `system_ready_when`, or it never becomes ready because
+    /// it has no `ready_when` or `startup_probe`
+    InvalidSystemReadyWhen { label: String },
+    /// A command has both or neither of `program` and `script`
+    ProgramOrScript { label: String },
+    /// The `shell` of a command is blank
+    EmptyShell { label: String },
+    /// More than one command would read stdin, because they are `interactive` or `foreground`
+    MultipleInteractive(Vec<String>),
+    /// `--interactive` refers to no command
+    UnknownInteractive { label: String },
+    /// `--profile` refers to no profile of the config
+    UnknownProfile { profile: String },
+    /// An entry of a profile is neither the label of a command nor the name of a profile,
+    /// or a profile has the label of a command
+    InvalidProfile { profile: String, reason: String },
+    /// A command added to a config has the label of another command,
+    /// see [`RunConfigInput::extend_commands`](crate::RunConfigInput::extend_commands)
+    DuplicateLabel { label: String },
+    /// `max_concurrent` is 0, so no command could run
+    ZeroMaxConcurrent,
+    /// `repeat` of a command is 0
+    ZeroRepeat { label: String },
+    /// `schedule` of a command is no valid cron expression, `every` is 0, or a command has
+    /// more than one of `schedule`, `every` and `repeat`
+    InvalidSchedule { label: String, reason: String },
+    /// `nice` in the `limits` of a command isn't within -20 and 19
+    InvalidNice { label: String, nice: i32 },
+    /// A command is both `critical` and `transient`
+    CriticalAndTransient { label: String },
+    /// The `healthcheck` of a command has both or neither of `url` and `command`,
+    /// no `http://` url or 0 `retries`
+    InvalidHealthCheck { label: String, reason: String },
+    /// The `cwd` of a command isn't a directory.
+    ///
+    /// Not checked for commands which wait for others, as those may create it.
+    MissingCwd { label: String, cwd: String },
+    /// The command at this position of `commands` is blank, e.g. an empty string in its list
+    EmptyCommand { index: usize },
+    /// The step at this position of `before` or `after` has no program or script
+    EmptyStep { stage: &'static str, index: usize },
+    /// The port of `wait_for` is the env name of no `assign_port`
+    UnassignedPort { label: String, name: String },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::UnknownCommand {
+                label,
+                referenced_by,
+            } => write!(
+                f,
+                "command[{}] refers to command[{}] which doesn't exist",
+                referenced_by, label
+            ),
+            ConfigError::DependencyCycle(labels) => {
+                write!(f, "commands depend on each other: {}", labels.join(" -> "))
+            }
+            ConfigError::InvalidReadyWhen { label, error } => {
+                write!(f, "command[{}] has invalid ready_when: {}", label, error)
+            }
+            ConfigError::InvalidLabelFrom { label, error } => {
+                write!(f, "command[{}] has invalid label_from: {}", label, error)
+            }
+            ConfigError::InvalidLineFilter { label, error } => {
+                write!(f, "command[{}] has invalid ignore or show_only: {}", label, error)
+            }
+            ConfigError::InvalidProgressFrom { label, reason } => {
+                write!(f, "command[{}] has invalid progress_from: {}", label, reason)
+            }
+            ConfigError::MissingReadyWhen { label } => {
+                write!(
+                    f,
+                    "command[{}] restarts until ready but has no ready_when or startup_probe",
+                    label
+                )
+            }
+            ConfigError::NeverReady {
+                label,
+                referenced_by,
+            } => write!(
+                f,
+                "command[{}] waits until command[{}] is ready but it has no ready_when or startup_probe",
+                referenced_by, label
+            ),
+            ConfigError::InvalidWatchGlob { label, error } => {
+                write!(f, "command[{}] has invalid watch glob: {}", label, error)
+            }
+            ConfigError::UnknownEncoding { label, encoding } => write!(
+                f,
+                "command[{}] has unknown encoding {}, supported are utf-8, latin1 and windows-1252",
+                label, encoding
+            ),
+            ConfigError::InvalidFaketime { label, faketime } if cfg!(unix) => write!(
+                f,
+                "command[{}] has invalid faketime {:?}, expected e.g. \"2024-01-01 12:00:00\" or \"+2d\"",
+                label, faketime
+            ),
+            ConfigError::InvalidFaketime { label, .. } => write!(
+                f,
+                "command[{}] has faketime which is only supported on Unix",
+                label
+            ),
+            ConfigError::InvalidForeground { label } if cfg!(unix) => write!(
+                f,
+                "foreground refers to command[{}] which doesn't exist",
+                label
+            ),
+            ConfigError::InvalidForeground { .. } => {
+                write!(f, "foreground is only supported on Unix")
+            }
+            ConfigError::InvalidSystemReadyWhen { label } => write!(
+                f,
+                "system_ready_when refers to command[{}] which doesn't exist or has no ready_when or startup_probe",
+                label
+            ),
+            ConfigError::ProgramOrScript { label } => write!(
+                f,
+                "command[{}] needs either a program or a script",
+                label
+            ),
+            ConfigError::EmptyShell { label } => write!(f, "command[{}] has an empty shell", label),
+            ConfigError::MultipleInteractive(labels) => write!(
+                f,
+                "only one command may read stdin, but command[{}] are interactive or foreground",
+                labels.join("], command[")
+            ),
+            ConfigError::UnknownInteractive { label } => write!(
+                f,
+                "interactive refers to command[{}] which doesn't exist",
+                label
+            ),
+            ConfigError::DuplicateLabel { label } => {
+                write!(f, "command[{}] has the label of another command", label)
+            }
+            ConfigError::MissingCwd { label, cwd } => write!(
+                f,
+                "cwd {:?} of command[{}] doesn't exist or isn't a directory",
+                cwd, label
+            ),
+            ConfigError::EmptyCommand { index } => write!(
+                f,
+                "command at index {} is empty, it has no program or script",
+                index
+            ),
+            ConfigError::EmptyStep { stage, index } => write!(
+                f,
+                "{} step at index {} is empty, it has no program or script",
+                stage, index
+            ),
+            ConfigError::UnassignedPort { label, name } => write!(
+                f,
+                "command[{}] waits for port {} but no command has assign_port {}",
+                label, name, name
+            ),
+            ConfigError::ZeroMaxConcurrent => write!(f, "max_concurrent must be at least 1"),
+            ConfigError::ZeroRepeat { label } => {
+                write!(f, "command[{}] must repeat at least once", label)
+            }
+            ConfigError::InvalidSchedule { label, reason } => {
+                write!(f, "command[{}] has invalid schedule: {}", label, reason)
+            }
+            ConfigError::InvalidNice { label, nice } => write!(
+                f,
+                "command[{}] has nice {}, which isn't within -20 and 19",
+                label, nice
+            ),
+            ConfigError::CriticalAndTransient { label } => {
+                write!(f, "command[{}] can't be both critical and transient", label)
+            }
+            ConfigError::InvalidHealthCheck { label, reason } => {
+                write!(f, "command[{}] has invalid healthcheck: {}", label, reason)
+            }
+            ConfigError::UnknownProfile { profile } => write!(f, "no profile {}", profile),
+            ConfigError::InvalidProfile { profile, reason } => {
+                write!(f, "profile {} is invalid: {}", profile, reason)
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ConfigError::InvalidReadyWhen { error, .. } => Some(error),
+            ConfigError::InvalidLabelFrom { error, .. } => Some(error),
+            ConfigError::InvalidLineFilter { error, .. } => Some(error),
+            ConfigError::InvalidWatchGlob { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl RunConfig {
+    /// Check that this config can run without surprises.
+    ///
+    /// [`spawn_from_run_config_with_plugin`](crate::run::spawn_from_run_config_with_plugin)
+    /// doesn't validate configs, so commands depending on each other in a cycle would wait forever.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let labels: Vec<String> = self.commands.iter().map(|c| c.resolved_label()).collect();
+        let mut indexes = HashMap::with_capacity(labels.len());
+        for (i, label) in labels.iter().enumerate() {
+            indexes.entry(label.as_str()).or_insert(i);
+        }
+
+        let mut dependencies = Vec::with_capacity(labels.len());
+        for (index, (cmd, label)) in self.commands.iter().zip(&labels).enumerate() {
+            let blank = match &cmd.script {
+                Some(script) => cmd.program.is_empty() && script.trim().is_empty(),
+                // a labeled command without program is reported as `ProgramOrScript`
+                None => {
+                    cmd.program.trim().is_empty()
+                        && !(cmd.program.is_empty() && cmd.label.is_some())
+                }
+            };
+            if blank {
+                return Err(ConfigError::EmptyCommand { index });
+            }
+
+            if cmd.program.is_empty() == cmd.script.is_none() {
+                return Err(ConfigError::ProgramOrScript {
+                    label: label.clone(),
+                });
+            }
+
+            if cmd
+                .shell
+                .as_ref()
+                .is_some_and(|shell| shell.trim().is_empty())
+            {
+                return Err(ConfigError::EmptyShell {
+                    label: label.clone(),
+                });
+            }
+
+            let waits = cmd.after.as_ref().is_some_and(|after| !after.is_empty())
+                || cmd
+                    .after_ready
+                    .as_ref()
+                    .is_some_and(|after| !after.is_empty());
+            if let Some(cwd) = cmd.cwd.as_ref().filter(|_| !waits) {
+                if !std::path::Path::new(cwd).is_dir() {
+                    return Err(ConfigError::MissingCwd {
+                        label: label.clone(),
+                        cwd: cwd.clone(),
+                    });
+                }
+            }
+
+            if let Some(ready_when) = &cmd.ready_when {
+                if let Err(error) = regex::Regex::new(ready_when) {
+                    return Err(ConfigError::InvalidReadyWhen {
+                        label: label.clone(),
+                        error,
+                    });
+                }
+            } else if cmd.startup_probe.is_none()
+                && matches!(cmd.restart, Some(RestartPolicy::UntilReady { .. }))
+            {
+                return Err(ConfigError::MissingReadyWhen {
+                    label: label.clone(),
+                });
+            }
+
+            if let Some(Err(error)) = cmd.label_from.as_ref().map(LineExtractor::compile) {
+                return Err(ConfigError::InvalidLabelFrom {
+                    label: label.clone(),
+                    error,
+                });
+            }
+
+            if let Err(error) = LineFilter::of(cmd) {
+                return Err(ConfigError::InvalidLineFilter {
+                    label: label.clone(),
+                    error,
+                });
+            }
+
+            if let Some(progress_from) = &cmd.progress_from {
+                let reason = match regex::Regex::new(progress_from) {
+                    Ok(regex) if matches!(regex.captures_len(), 2 | 3) => None,
+                    Ok(_) => Some("it must capture a percentage, or done and total".to_string()),
+                    Err(error) => Some(error.to_string()),
+                };
+                if let Some(reason) = reason {
+                    return Err(ConfigError::InvalidProgressFrom {
+                        label: label.clone(),
+                        reason,
+                    });
+                }
+            }
+
+            let globs = cmd.watch.iter().chain(&cmd.watch_include);
+            let globs = globs.chain(&cmd.watch_exclude).flatten();
+            for glob in globs {
+                if let Err(error) = glob::Pattern::new(glob) {
+                    return Err(ConfigError::InvalidWatchGlob {
+                        label: label.clone(),
+                        error,
+                    });
+                }
+            }
+
+            if cmd.repeat == Some(0) {
+                return Err(ConfigError::ZeroRepeat {
+                    label: label.clone(),
+                });
+            }
+
+            let reason = match (&cmd.schedule, cmd.every) {
+                (Some(_), Some(_)) => Some("it has both schedule and every".to_string()),
+                (Some(_), _) | (_, Some(_)) if cmd.repeat.is_some() => {
+                    Some("it can't repeat as well".to_string())
+                }
+                (Some(schedule), None) => schedule.parse::<super::CronSchedule>().err(),
+                (None, Some(every)) if every.is_zero() => Some("every must not be 0".to_string()),
+                _ => None,
+            };
+            if let Some(reason) = reason {
+                return Err(ConfigError::InvalidSchedule {
+                    label: label.clone(),
+                    reason,
+                });
+            }
+
+            if let Some(nice) = cmd.limits.as_ref().and_then(|limits| limits.nice) {
+                if !(-20..=19).contains(&nice) {
+                    return Err(ConfigError::InvalidNice {
+                        label: label.clone(),
+                        nice,
+                    });
+                }
+            }
+
+            if cmd.critical && cmd.transient {
+                return Err(ConfigError::CriticalAndTransient {
+                    label: label.clone(),
+                });
+            }
+
+            if let Some(check) = &cmd.healthcheck {
+                let reason = match (&check.url, &check.command) {
+                    (Some(_), Some(_)) | (None, None) => Some("needs either a url or a command"),
+                    (Some(url), None) if !url.starts_with("http://") => {
+                        Some("only http:// urls are supported")
+                    }
+                    _ if check.retries == 0 => Some("retries must be at least 1"),
+                    _ => None,
+                };
+                if let Some(reason) = reason {
+                    return Err(ConfigError::InvalidHealthCheck {
+                        label: label.clone(),
+                        reason: reason.to_string(),
+                    });
+                }
+            }
+
+            if let Some(encoding) = &cmd.encoding {
+                if OutputEncoding::from_name(encoding).is_none() {
+                    return Err(ConfigError::UnknownEncoding {
+                        label: label.clone(),
+                        encoding: encoding.clone(),
+                    });
+                }
+            }
+
+            if let Some(faketime) = &cmd.faketime {
+                if !cfg!(unix) || !is_valid_faketime(faketime) {
+                    return Err(ConfigError::InvalidFaketime {
+                        label: label.clone(),
+                        faketime: faketime.clone(),
+                    });
+                }
+            }
+
+            let mut deps = vec![];
+            let after = cmd.after.iter().flatten().map(|dep| (dep, false));
+            let after_ready = cmd.after_ready.iter().flatten().map(|dep| (dep, true));
+            for (dep, ready) in after.chain(after_ready) {
+                let i = match indexes.get(dep.as_str()) {
+                    Some(&i) => i,
+                    None => {
+                        return Err(ConfigError::UnknownCommand {
+                            label: dep.clone(),
+                            referenced_by: label.clone(),
+                        })
+                    }
+                };
+                let other = &self.commands[i];
+                if ready && other.ready_when.is_none() && other.startup_probe.is_none() {
+                    return Err(ConfigError::NeverReady {
+                        label: dep.clone(),
+                        referenced_by: label.clone(),
+                    });
+                }
+                deps.push(i);
+            }
+            dependencies.push(deps);
+
+            // not a dependency, the command runs before those it waits for
+            for other in cmd.stop_when_ready.iter().flatten() {
+                if !indexes.contains_key(other.as_str()) {
+                    return Err(ConfigError::UnknownCommand {
+                        label: other.clone(),
+                        referenced_by: label.clone(),
+                    });
+                }
+            }
+        }
+
+        if self.max_concurrent == Some(0) {
+            return Err(ConfigError::ZeroMaxConcurrent);
+        }
+
+        for cmd in &self.commands {
+            if let Some(PortNumber::Assigned(name)) = cmd.wait_for.as_ref().map(|wait| &wait.port) {
+                let assigned = self
+                    .commands
+                    .iter()
+                    .any(|cmd| cmd.assign_port.as_ref() == Some(name));
+                if !assigned {
+                    return Err(ConfigError::UnassignedPort {
+                        label: cmd.resolved_label(),
+                        name: name.clone(),
+                    });
+                }
+            }
+        }
+
+        for (stage, steps) in [("before", &self.before), ("after", &self.after)] {
+            let blank = steps.iter().position(|step| match &step.script {
+                Some(script) => script.trim().is_empty(),
+                None => step.program.trim().is_empty(),
+            });
+            if let Some(index) = blank {
+                return Err(ConfigError::EmptyStep { stage, index });
+            }
+        }
+
+        for (profile, entries) in &self.profiles {
+            let reason = if indexes.contains_key(profile.as_str()) {
+                Some("it has the label of a command".to_string())
+            } else {
+                let unknown = entries.iter().find(|entry| {
+                    !indexes.contains_key(entry.as_str()) && !self.profiles.contains_key(*entry)
+                });
+                unknown.map(|entry| format!("{} is neither a command nor a profile", entry))
+            };
+            if let Some(reason) = reason {
+                return Err(ConfigError::InvalidProfile {
+                    profile: profile.clone(),
+                    reason,
+                });
+            }
+        }
+
+        if let Some(foreground) = &self.foreground {
+            if !cfg!(unix) || !indexes.contains_key(foreground.as_str()) {
+                return Err(ConfigError::InvalidForeground {
+                    label: foreground.clone(),
+                });
+            }
+        }
+
+        if let Some(label) = &self.system_ready_when {
+            let gate = indexes.get(label.as_str()).map(|&i| &self.commands[i]);
+            if !gate.is_some_and(|cmd| cmd.ready_when.is_some() || cmd.startup_probe.is_some()) {
+                return Err(ConfigError::InvalidSystemReadyWhen {
+                    label: label.clone(),
+                });
+            }
+        }
+
+        let mut interactive: Vec<String> = self
+            .commands
+            .iter()
+            .zip(&labels)
+            .filter(|(cmd, _)| cmd.interactive)
+            .map(|(_, label)| label.clone())
+            .collect();
+        if let Some(foreground) = &self.foreground {
+            if !interactive.contains(foreground) {
+                interactive.push(foreground.clone());
+            }
+        }
+        if interactive.len() > 1 {
+            return Err(ConfigError::MultipleInteractive(interactive));
+        }
+
+        if let Some(cycle) = find_cycle(&dependencies) {
+            return Err(ConfigError::DependencyCycle(
+                cycle.into_iter().map(|i| labels[i].clone()).collect(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Find a cycle in a graph given as adjacency lists.
+///
+/// The returned path starts and ends with the same node.
+fn find_cycle(graph: &[Vec<usize>]) -> Option<Vec<usize>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        New,
+        Visiting,
+        Done,
+    }
+
+    fn visit(node: usize, graph: &[Vec<usize>], marks: &mut [Mark], path: &mut Vec<usize>) -> bool {
+        marks[node] = Mark::Visiting;
+        path.push(node);
+        for &next in &graph[node] {
+            match marks[next] {
+                Mark::Visiting => {
+                    path.push(next);
+                    return true;
+                }
+                Mark::New => {
+                    if visit(next, graph, marks, path) {
+                        return true;
+                    }
+                }
+                Mark::Done => {}
+            }
+        }
+        path.pop();
+        marks[node] = Mark::Done;
+        false
+    }
+
+    let mut marks = vec![Mark::New; graph.len()];
+    let mut path = vec![];
+    for node in 0..graph.len() {
+        if marks[node] == Mark::New && visit(node, graph, &mut marks, &mut path) {
+            let last = *path.last().unwrap();
+            let start = path.iter().position(|&n| n == last).unwrap();
+            return Some(path.split_off(start));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConfigError;
+    use crate::RestartPolicy;
+    use crate::{CommandConfig, RunConfig};
+
+    fn command(label: &str, after: &[&str]) -> CommandConfig {
+        let mut cmd = CommandConfig::from_program_args("true".to_string(), None);
+        cmd.label = Some(label.to_string());
+        cmd.after = Some(after.iter().map(|s| s.to_string()).collect());
+        cmd
+    }
+
+    fn run_config(commands: Vec<CommandConfig>) -> RunConfig {
+        RunConfig {
+            commands,
+            before: vec![],
+            after: vec![],
+            max_label_length: 10,
+            label_width: None,
+            envs: None,
+            env_file: None,
+            computed_env: Default::default(),
+            secrets: None,
+            kill: Default::default(),
+            exit_when_only_optional_remain: false,
+            fail_on_skip: false,
+            profiles: Default::default(),
+            ctrl_c_signal: None,
+            ctrl_c_to_foreground: false,
+            shutdown_timeout: None,
+            kill_grace: None,
+            timeout: None,
+            kill_signal: None,
+            kill_process_groups: false,
+            pty: false,
+            pipe_stdin: false,
+            collapse_repeats: false,
+            interleave_guard: false,
+            group_output: false,
+            partial_line_flush: None,
+            timestamps: None,
+            defer_output_until_started: false,
+            output_backpressure: Default::default(),
+            summary_order: Default::default(),
+            exit_code: Default::default(),
+            max_buffer_memory: None,
+            log_file_max_size: None,
+            foreground: None,
+            history_limit: None,
+            tail_lines: None,
+            max_concurrent: None,
+            early_exit_backoff: None,
+            system_ready_when: None,
+            color_seed: None,
+            restart_separator: Default::default(),
+            show_silent_commands: true,
+            colorize_log_levels: false,
+            log_level_colors: Default::default(),
+        }
+    }
+
+    #[test]
+    fn validate_after() {
+        let config = run_config(vec![command("build", &[]), command("serve", &["build"])]);
+        assert!(config.validate().is_ok());
+
+        let config = run_config(vec![command("serve", &["build"])]);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::UnknownCommand { label, referenced_by }) if label == "build" && referenced_by == "serve"
+        ));
+
+        let config = run_config(vec![
+            command("a", &["b"]),
+            command("b", &["c"]),
+            command("c", &["a"]),
+        ]);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::DependencyCycle(labels)) if labels == ["a", "b", "c", "a"]
+        ));
+    }
+
+    #[test]
+    fn validate_stop_when_ready() {
+        let mut mock = command("mock", &[]);
+        mock.stop_when_ready = Some(vec!["client".to_string()]);
+        let config = run_config(vec![mock, command("client", &[])]);
+        assert!(config.validate().is_ok());
+
+        let mut mock = command("mock", &[]);
+        mock.stop_when_ready = Some(vec!["clinet".to_string()]);
+        let config = run_config(vec![mock, command("client", &[])]);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::UnknownCommand { label, referenced_by }) if label == "clinet" && referenced_by == "mock"
+        ));
+    }
+
+    #[test]
+    fn validate_after_ready() {
+        let after_ready = |label: &str, after: &[&str]| {
+            let mut cmd = command(label, &[]);
+            cmd.ready_when = Some("listening".to_string());
+            cmd.after_ready = Some(after.iter().map(|s| s.to_string()).collect());
+            cmd
+        };
+
+        let config = run_config(vec![after_ready("api", &[]), after_ready("web", &["api"])]);
+        assert!(config.validate().is_ok());
+
+        let config = run_config(vec![command("api", &[]), after_ready("web", &["api"])]);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::NeverReady { label, referenced_by }) if label == "api" && referenced_by == "web"
+        ));
+
+        // cycles may mix after and after_ready
+        let mut web = after_ready("web", &[]);
+        web.after = Some(vec!["api".to_string()]);
+        let config = run_config(vec![after_ready("api", &["web"]), web]);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::DependencyCycle(labels)) if labels == ["api", "web", "api"]
+        ));
+    }
+
+    #[test]
+    fn validate_ready_when() {
+        let mut cmd = command("serve", &[]);
+        cmd.ready_when = Some("listening (on".to_string());
+        assert!(matches!(
+            run_config(vec![cmd]).validate(),
+            Err(ConfigError::InvalidReadyWhen { label, .. }) if label == "serve"
+        ));
+
+        let mut cmd = command("serve", &[]);
+        cmd.restart = Some(RestartPolicy::UntilReady {
+            max: 3,
+            ready_timeout: std::time::Duration::from_secs(1),
+            backoff_schedule: vec![],
+        });
+        assert!(matches!(
+            run_config(vec![cmd]).validate(),
+            Err(ConfigError::MissingReadyWhen { label }) if label == "serve"
+        ));
+    }
+
+    #[test]
+    fn validate_progress_from() {
+        let progress_from = |pattern: &str| {
+            let mut cmd = command("build", &[]);
+            cmd.progress_from = Some(pattern.to_string());
+            run_config(vec![cmd]).validate()
+        };
+        assert!(progress_from(r"(\d+)%").is_ok());
+        assert!(progress_from(r"\[(\d+)/(\d+)\]").is_ok());
+        for invalid in [r"\d+%", r"(\d+", r"(\d)(\d)(\d)"] {
+            assert!(matches!(
+                progress_from(invalid),
+                Err(ConfigError::InvalidProgressFrom { label, .. }) if label == "build"
+            ));
+        }
+    }
+
+    #[test]
+    fn validate_schedule() {
+        let schedule = |schedule: Option<&str>, every: Option<u64>, repeat: Option<u32>| {
+            let mut cmd = command("backup", &[]);
+            cmd.schedule = schedule.map(str::to_string);
+            cmd.every = every.map(std::time::Duration::from_secs);
+            cmd.repeat = repeat;
+            run_config(vec![cmd]).validate()
+        };
+        assert!(schedule(Some("*/5 * * * *"), None, None).is_ok());
+        assert!(schedule(None, Some(30), None).is_ok());
+        for (cron, every, repeat) in [
+            (Some("*/5 * * *"), None, None),
+            (None, Some(0), None),
+            (Some("@hourly"), Some(30), None),
+            (None, Some(30), Some(2)),
+        ] {
+            assert!(matches!(
+                schedule(cron, every, repeat),
+                Err(ConfigError::InvalidSchedule { label, .. }) if label == "backup"
+            ));
+        }
+    }
+
+    #[test]
+    fn validate_nice() {
+        let nice = |nice| {
+            let mut cmd = command("watcher", &[]);
+            cmd.limits = Some(crate::ResourceLimits {
+                nice: Some(nice),
+                ..Default::default()
+            });
+            run_config(vec![cmd]).validate()
+        };
+        assert!(nice(10).is_ok());
+        assert!(nice(-20).is_ok());
+        assert!(matches!(
+            nice(20),
+            Err(ConfigError::InvalidNice { label, nice: 20 }) if label == "watcher"
+        ));
+    }
+
+    #[test]
+    fn validate_encoding() {
+        let mut cmd = command("legacy", &[]);
+        cmd.encoding = Some("Windows-1252".to_string());
+        assert!(run_config(vec![cmd.clone()]).validate().is_ok());
+
+        cmd.encoding = Some("shift_jis".to_string());
+        let err = run_config(vec![cmd]).validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "command[legacy] has unknown encoding shift_jis, supported are utf-8, latin1 and windows-1252"
+        );
+    }
+
+    #[test]
+    fn validate_line_filter() {
+        let mut cmd = command("web", &[]);
+        cmd.ignore = Some(vec!["^webpack compiled".to_string()]);
+        cmd.show_only = Some(vec!["error (".to_string()]);
+        assert!(matches!(
+            run_config(vec![cmd]).validate(),
+            Err(ConfigError::InvalidLineFilter { label, .. }) if label == "web"
+        ));
+    }
+
+    #[test]
+    fn validate_max_concurrent() {
+        let mut config = run_config(vec![command("web", &[])]);
+        config.max_concurrent = Some(1);
+        assert!(config.validate().is_ok());
+        config.max_concurrent = Some(0);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::ZeroMaxConcurrent)
+        ));
+    }
+
+    #[test]
+    fn validate_system_ready_when() {
+        let mut health = command("health", &[]);
+        health.ready_when = Some("healthy".to_string());
+        let mut config = run_config(vec![health, command("api", &[])]);
+        config.system_ready_when = Some("health".to_string());
+        assert!(config.validate().is_ok());
+
+        for label in ["api", "db"] {
+            config.system_ready_when = Some(label.to_string());
+            assert!(matches!(
+                config.validate(),
+                Err(ConfigError::InvalidSystemReadyWhen { label: l }) if l == label
+            ));
+        }
+    }
+
+    #[test]
+    fn validate_repeat() {
+        let mut bench = command("bench", &[]);
+        bench.repeat = Some(0);
+        assert!(matches!(
+            run_config(vec![bench]).validate(),
+            Err(ConfigError::ZeroRepeat { label }) if label == "bench"
+        ));
+    }
+
+    #[test]
+    fn validate_critical_and_transient() {
+        let mut server = command("server", &[]);
+        server.critical = true;
+        server.transient = true;
+        assert!(matches!(
+            run_config(vec![server]).validate(),
+            Err(ConfigError::CriticalAndTransient { label }) if label == "server"
+        ));
+    }
+
+    #[test]
+    fn validate_healthcheck() {
+        let with_check = |yaml: &str| {
+            let mut api = command("api", &[]);
+            api.healthcheck = Some(serde_yaml::from_str(yaml).unwrap());
+            run_config(vec![api]).validate()
+        };
+        assert!(with_check("{ url: http://localhost:3000/health }").is_ok());
+        assert!(with_check("{ command: curl -f localhost:3000, retries: 1 }").is_ok());
+        for (yaml, expected) in [
+            ("{ interval: 5s }", "needs either a url or a command"),
+            (
+                "{ url: https://localhost }",
+                "only http:// urls are supported",
+            ),
+            (
+                "{ command: 'true', retries: 0 }",
+                "retries must be at least 1",
+            ),
+        ] {
+            assert!(matches!(
+                with_check(yaml),
+                Err(ConfigError::InvalidHealthCheck { reason, .. }) if reason == expected
+            ));
+        }
+    }
+
+    #[test]
+    fn validate_shell() {
+        let mut web = command("web", &[]);
+        web.shell = Some(" ".to_string());
+        assert!(matches!(
+            run_config(vec![web]).validate(),
+            Err(ConfigError::EmptyShell { label }) if label == "web"
+        ));
+    }
+
+    #[test]
+    fn validate_profiles() {
+        let with_profiles = |profiles: &[(&str, &[&str])]| {
+            let mut config = run_config(vec![command("web", &[]), command("api", &[])]);
+            for (profile, entries) in profiles {
+                let entries = entries.iter().map(|s| s.to_string()).collect();
+                config.profiles.insert(profile.to_string(), entries);
+            }
+            config.validate()
+        };
+
+        assert!(with_profiles(&[("dev", &["web", "api"]), ("all", &["dev"])]).is_ok());
+        assert!(matches!(
+            with_profiles(&[("dev", &["web", "worker"])]),
+            Err(ConfigError::InvalidProfile { profile, reason })
+                if profile == "dev" && reason == "worker is neither a command nor a profile"
+        ));
+        assert!(matches!(
+            with_profiles(&[("web", &["web"])]),
+            Err(ConfigError::InvalidProfile { reason, .. }) if reason == "it has the label of a command"
+        ));
+    }
+
+    #[test]
+    fn validate_interactive() {
+        let interactive = |label: &str| {
+            let mut cmd = command(label, &[]);
+            cmd.interactive = true;
+            cmd
+        };
+
+        let config = run_config(vec![interactive("repl"), command("web", &[])]);
+        assert!(config.validate().is_ok());
+
+        let config = run_config(vec![interactive("repl"), interactive("migrate")]);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::MultipleInteractive(labels)) if labels == ["repl", "migrate"]
+        ));
+
+        #[cfg(unix)]
+        {
+            let mut config = run_config(vec![interactive("repl"), command("web", &[])]);
+            config.foreground = Some("repl".to_string());
+            assert!(config.validate().is_ok());
+            config.foreground = Some("web".to_string());
+            assert!(matches!(
+                config.validate(),
+                Err(ConfigError::MultipleInteractive(labels)) if labels == ["repl", "web"]
+            ));
+        }
+    }
+
+    #[test]
+    fn validate_script() {
+        let mut cmd = command("setup", &[]);
+        cmd.program = String::new();
+        cmd.script = Some("echo one\necho two\n".to_string());
+        assert!(run_config(vec![cmd]).validate().is_ok());
+
+        let mut cmd = command("setup", &[]);
+        cmd.script = Some("echo one\necho two\n".to_string());
+        assert!(matches!(
+            run_config(vec![cmd]).validate(),
+            Err(ConfigError::ProgramOrScript { label }) if label == "setup"
+        ));
+
+        let mut cmd = command("setup", &[]);
+        cmd.program = String::new();
+        assert!(matches!(
+            run_config(vec![cmd]).validate(),
+            Err(ConfigError::ProgramOrScript { label }) if label == "setup"
+        ));
+    }
+
+    #[test]
+    fn validate_cwd() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("dist").display().to_string();
+
+        let mut cmd = command("serve", &[]);
+        cmd.cwd = Some(dir.path().display().to_string());
+        assert!(run_config(vec![cmd]).validate().is_ok());
+
+        let mut cmd = command("serve", &[]);
+        cmd.cwd = Some(missing.clone());
+        assert!(matches!(
+            run_config(vec![cmd]).validate(),
+            Err(ConfigError::MissingCwd { label, cwd }) if label == "serve" && cwd == missing
+        ));
+
+        // the build may create it
+        let mut cmd = command("serve", &["build"]);
+        cmd.cwd = Some(missing);
+        assert!(run_config(vec![command("build", &[]), cmd])
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn validate_empty_command() {
+        let blank = crate::CommandConfigInput::Command("  \n".to_string());
+        let config = run_config(vec![
+            command("build", &[]),
+            blank.into_config(&Default::default()),
+        ]);
+        let err = config.validate().unwrap_err();
+        assert!(matches!(err, ConfigError::EmptyCommand { index: 1 }));
+        assert_eq!(
+            err.to_string(),
+            "command at index 1 is empty, it has no program or script"
+        );
+
+        let mut cmd = command("setup", &[]);
+        cmd.program = String::new();
+        cmd.script = Some(" ".to_string());
+        assert!(matches!(
+            run_config(vec![cmd]).validate(),
+            Err(ConfigError::EmptyCommand { index: 0 })
+        ));
+    }
+
+    #[test]
+    fn validate_empty_step() {
+        let blank = crate::CommandConfigInput::Command(" ".to_string());
+        let mut config = run_config(vec![command("build", &[])]);
+        config.before = vec![command("up", &[])];
+        config.after = vec![command("down", &[]), blank.into_config(&Default::default())];
+        let err = config.validate().unwrap_err();
+        assert!(matches!(
+            err,
+            ConfigError::EmptyStep {
+                stage: "after",
+                index: 1
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "after step at index 1 is empty, it has no program or script"
+        );
+
+        config.after.pop();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_unassigned_port() {
+        let mut client = command("client", &[]);
+        client.wait_for = Some(crate::PortWait {
+            port: crate::PortNumber::Assigned("API_PORT".to_string()),
+            host: "localhost".to_string(),
+            timeout: std::time::Duration::from_secs(1),
+        });
+        let err = run_config(vec![command("api", &[]), client.clone()])
+            .validate()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "command[client] waits for port API_PORT but no command has assign_port API_PORT"
+        );
+
+        let mut api = command("api", &[]);
+        api.assign_port = Some("API_PORT".to_string());
+        assert!(run_config(vec![api, client]).validate().is_ok());
+    }
+}
@@ -1,12 +1,50 @@
+use std::sync::{Arc, Mutex};
+
+use crate::LogColor;
+
 #[derive(Debug, Clone)]
 pub struct Label {
     label: String,
     display: Option<String>,
+    color: Option<LogColor>,
+    /// Shared by all clones, see [`Label::relabel`]
+    relabeled: Arc<Mutex<Option<String>>>,
 }
 
 impl Label {
     pub fn new(label: String, display: Option<String>) -> Self {
-        Self { label, display }
+        Self {
+            label,
+            display,
+            color: None,
+            relabeled: Default::default(),
+        }
+    }
+
+    /// Shows the label in `color` instead of the one picked for the command
+    pub fn with_color(mut self, color: Option<LogColor>) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn color(&self) -> Option<LogColor> {
+        self.color
+    }
+
+    /// Shows `label` in output instead from now on, truncated and padded to the width of
+    /// [`display`](Self::display) so that the output stays aligned.
+    ///
+    /// Applies to all clones, i.e. to all runs of the command. [`label`](Self::label)
+    /// doesn't change, so the command is still referred to by it, e.g. in `after`.
+    pub fn relabel(&self, label: &str) {
+        let width = self.display().len().max(3);
+        let relabeled = Self::from_label_with_width(label.to_string(), width, Some(width));
+        *self.relabeled.lock().unwrap() = Some(relabeled.display().to_string());
+    }
+
+    /// The label set by [`relabel`](Self::relabel), if any
+    pub fn relabeled(&self) -> Option<String> {
+        self.relabeled.lock().unwrap().clone()
     }
 
     pub fn label(&self) -> &str {
@@ -18,20 +56,76 @@ impl Label {
     }
 
     pub fn from_label(label: String, max_label_length: usize) -> Self {
+        Self::from_label_with_width(label, max_label_length, None)
+    }
+
+    /// Truncates the label to `max_label_length` and pads it to `label_width`,
+    /// which defaults to `max_label_length`.
+    pub fn from_label_with_width(
+        label: String,
+        max_label_length: usize,
+        label_width: Option<usize>,
+    ) -> Self {
         let len = label.len();
-        let display = if len > max_label_length {
+        let truncated = if len > max_label_length {
             let len_trim = std::cmp::min(len - max_label_length, 3);
 
             let label = &label[0..(max_label_length - len_trim)];
             let padding = ".".repeat(len_trim);
             Some(format!("{}{}", label, padding))
-        } else if len < max_label_length {
-            let padding = " ".repeat(max_label_length - len);
-            Some(format!("{}{}", label, padding))
         } else {
             None
         };
 
+        let width = label_width.unwrap_or(max_label_length);
+        let display_len = truncated.as_ref().map_or(len, |s| s.len());
+        let display = if display_len < width {
+            let padding = " ".repeat(width - display_len);
+            Some(format!(
+                "{}{}",
+                truncated.as_deref().unwrap_or(&label),
+                padding
+            ))
+        } else {
+            truncated
+        };
+
         Self::new(label, display)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Label;
+
+    #[test]
+    fn truncate_and_pad() {
+        let display = |label: &str, max_label_length, label_width| {
+            Label::from_label_with_width(label.to_string(), max_label_length, label_width)
+                .display()
+                .to_string()
+        };
+
+        assert_eq!(display("web", 6, None), "web   ");
+        assert_eq!(display("backend", 6, None), "backe.");
+        assert_eq!(display("web", 6, Some(4)), "web ");
+        assert_eq!(display("web", 2, Some(4)), "w.  ");
+        assert_eq!(display("backend", 6, Some(8)), "backe.  ");
+        assert_eq!(display("backend", 6, Some(3)), "backe.");
+        assert_eq!(display("frontend", 4, Some(6)), "f...  ");
+        assert_eq!(display("web", 3, Some(3)), "web");
+    }
+
+    #[test]
+    fn relabel_clones() {
+        let label = Label::from_label("npm start".to_string(), 10);
+        let run = label.clone();
+
+        assert_eq!(run.relabeled(), None);
+        label.relabel("api");
+        assert_eq!(run.relabeled().as_deref(), Some("api       "));
+        assert_eq!(run.label(), "npm start");
+        label.relabel("api gateway");
+        assert_eq!(run.relabeled().as_deref(), Some("api gatew."));
+    }
+}
@@ -0,0 +1,189 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, OnceLock},
+};
+use tokio::sync::broadcast;
+
+/// How many lines of a command the broadcast keeps for subscribers which attach later
+pub const DEFAULT_TAIL_LINES: usize = 100;
+
+/// How many lines a subscriber may fall behind before it misses lines
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// An output line of a command, without the line ending
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputEvent {
+    pub stream: OutputStream,
+    pub line: String,
+}
+
+struct CommandOutput {
+    /// the most recent lines, oldest first
+    tail: VecDeque<OutputEvent>,
+    /// `None` ends the output of a run
+    tx: broadcast::Sender<Option<OutputEvent>>,
+    /// Whether the output of the last run ended and no run published since
+    ended: bool,
+}
+
+impl CommandOutput {
+    fn new() -> Self {
+        Self {
+            tail: VecDeque::new(),
+            tx: broadcast::channel(CHANNEL_CAPACITY).0,
+            ended: false,
+        }
+    }
+}
+
+/// Gets each published line with the label of its command
+type ForwardLine = Box<dyn Fn(&str, OutputStream, &str) + Send + Sync>;
+
+/// Sends output lines of commands, by label, to subscribers which attach while they run,
+/// see [`CommandSystem::subscribe`](super::CommandSystem::subscribe).
+///
+/// Plugins reading the output of commands should [`publish`](Self::publish) every line,
+/// [`end`](Self::end) each run once its output was read to the end and return the broadcast
+/// from
+/// [`output_broadcast`](super::CommandSystemPlugin::output_broadcast).
+pub struct OutputBroadcast {
+    commands: Mutex<HashMap<String, CommandOutput>>,
+    tail_lines: usize,
+    /// Also gets each line with its label, see [`CommandSystem::events`](super::CommandSystem::events)
+    forward: OnceLock<ForwardLine>,
+}
+
+impl Default for OutputBroadcast {
+    fn default() -> Self {
+        Self::new(DEFAULT_TAIL_LINES)
+    }
+}
+
+impl OutputBroadcast {
+    /// Keeps the last `tail_lines` lines of each command
+    pub fn new(tail_lines: usize) -> Self {
+        Self {
+            commands: Default::default(),
+            tail_lines,
+            forward: OnceLock::new(),
+        }
+    }
+
+    pub fn publish(&self, label: &str, stream: OutputStream, line: &str) {
+        if let Some(forward) = self.forward.get() {
+            forward(label, stream, line);
+        }
+        let event = OutputEvent {
+            stream,
+            line: line.to_string(),
+        };
+        let mut commands = self.commands.lock().unwrap();
+        let output = commands
+            .entry(label.to_string())
+            .or_insert_with(CommandOutput::new);
+
+        if self.tail_lines > 0 {
+            if output.tail.len() == self.tail_lines {
+                output.tail.pop_front();
+            }
+            output.tail.push_back(event.clone());
+        }
+        output.ended = false;
+        // fails only without subscribers
+        let _ = output.tx.send(Some(event));
+    }
+
+    /// The output of the current run of the command with `label` was read to the end,
+    /// e.g. because it exited. Its subscribers receive `None` after its lines.
+    pub fn end(&self, label: &str) {
+        let mut commands = self.commands.lock().unwrap();
+        let output = commands
+            .entry(label.to_string())
+            .or_insert_with(CommandOutput::new);
+        output.ended = true;
+        let _ = output.tx.send(None);
+    }
+
+    /// Calls `forward` with each line published from now on, unless another function was set
+    pub(super) fn forward(
+        &self,
+        forward: impl Fn(&str, OutputStream, &str) + Send + Sync + 'static,
+    ) {
+        let _ = self.forward.set(Box::new(forward));
+    }
+
+    /// The kept lines of the command with `label`, oldest first, across its runs
+    pub fn tail(&self, label: &str) -> Vec<OutputEvent> {
+        let commands = self.commands.lock().unwrap();
+        commands
+            .get(label)
+            .map(|output| output.tail.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Receives the lines of the command published from now on, and before them
+    /// the kept tail if `replay_tail` is set. If its output already ended, this receives
+    /// the tail and then `None`.
+    pub fn subscribe(&self, label: &str, replay_tail: bool) -> OutputSubscription {
+        let mut commands = self.commands.lock().unwrap();
+        let output = commands
+            .entry(label.to_string())
+            .or_insert_with(CommandOutput::new);
+
+        OutputSubscription {
+            tail: match replay_tail {
+                true => output.tail.clone(),
+                false => VecDeque::new(),
+            },
+            ended: output.ended,
+            rx: output.tx.subscribe(),
+            closed: false,
+        }
+    }
+}
+
+/// Output lines of a command, see [`OutputBroadcast::subscribe`]
+pub struct OutputSubscription {
+    tail: VecDeque<OutputEvent>,
+    /// Whether `None` follows the tail, since the output had ended when subscribing
+    ended: bool,
+    rx: broadcast::Receiver<Option<OutputEvent>>,
+    closed: bool,
+}
+
+impl OutputSubscription {
+    /// The next line, `None` once the output of the current run of the command ended,
+    /// see [`OutputBroadcast::end`], or the broadcast is dropped.
+    ///
+    /// After the end of a run, this receives the lines of the next one, if it is
+    /// restarted. Lines which were published while this subscriber fell behind are skipped.
+    pub async fn recv(&mut self) -> Option<OutputEvent> {
+        if let Some(event) = self.tail.pop_front() {
+            return Some(event);
+        }
+        if std::mem::take(&mut self.ended) {
+            return None;
+        }
+        loop {
+            match self.rx.recv().await {
+                Ok(event) => return event,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => {
+                    self.closed = true;
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Whether the broadcast was dropped, so that no run publishes lines anymore
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+}
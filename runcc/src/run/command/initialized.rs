@@ -1,20 +1,112 @@
-use std::{io, process::Stdio};
+use std::{
+    io,
+    process::{ExitStatus, Stdio},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use tokio::process::{Child, ChildStderr, ChildStdout, Command};
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, Notify};
 
 use super::super::kill;
+use crate::{ResourceLimits, Signal};
 
 pub struct CommandInitialized<T> {
     command: Command,
     data: T,
+    /// What the command reads as stdin
+    stdin: StdinSource,
+    /// see [`kill_signal`](Self::kill_signal)
+    kill_signal: Option<(Signal, Duration)>,
+    /// see [`kill_process_group`](Self::kill_process_group)
+    group: bool,
+    /// see [`pty`](Self::pty)
+    pty: bool,
+    /// see [`limits`](Self::limits)
+    limits: Option<ResourceLimits>,
+}
+
+/// What a command reads as stdin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StdinSource {
+    /// nothing, it is closed right away
+    Null,
+    /// the stdin of runcc, see [`inherit_stdin`](CommandInitialized::inherit_stdin)
+    Inherit,
+    /// a pipe, see [`pipe_stdin`](CommandInitialized::pipe_stdin)
+    Pipe,
+}
+
+/// The processes which kills of a command reach
+struct KillScope {
+    /// The process group of the command instead of only the command, see
+    /// [`kill_process_group`](CommandInitialized::kill_process_group). On Windows,
+    /// whether the command has its own process group to get Ctrl-Break.
+    group: bool,
+    /// The command and its descendants, `None` if it couldn't be assigned to a job
+    #[cfg(windows)]
+    job: Option<kill::windows::Job>,
+}
+
+/// Sends `signal` to the command, or to its process group if `group`
+#[cfg(unix)]
+fn send_signal(child: &mut Child, signal: Signal, scope: &KillScope) -> io::Result<()> {
+    let pid = match child.id() {
+        Some(pid) => pid as libc::pid_t,
+        // child process has been waited
+        None => return Err(io::ErrorKind::InvalidInput.into()),
+    };
+
+    let pid = if scope.group { -pid } else { pid };
+    if unsafe { libc::kill(pid, signal.as_raw()) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Sends Ctrl-Break to the process group of the command for any signal but
+/// [`Signal::Kill`], which kills it forcefully like commands without their own group
+#[cfg(windows)]
+fn send_signal(child: &mut Child, signal: Signal, scope: &KillScope) -> io::Result<()> {
+    match child.id() {
+        Some(pid) if scope.group && signal != Signal::Kill => kill::windows::send_ctrl_break(pid),
+        Some(_) => start_kill(child, scope),
+        // child process has been waited
+        None => Err(io::ErrorKind::InvalidInput.into()),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn send_signal(child: &mut Child, _signal: Signal, _scope: &KillScope) -> io::Result<()> {
+    child.start_kill()
+}
+
+/// Kills the command forcefully, with its process group if `group` or with its job on Windows
+fn start_kill(child: &mut Child, scope: &KillScope) -> io::Result<()> {
+    #[cfg(windows)]
+    if let Some(job) = &scope.job {
+        return job.terminate();
+    }
+    #[cfg(unix)]
+    if scope.group {
+        return send_signal(child, Signal::Kill, scope);
+    }
+    child.start_kill()
 }
 
 fn start_kill_child_process<T>(
     child: &mut Child,
     kill_reason: kill::KillCommandReason<T>,
+    signal: Option<Signal>,
+    scope: &KillScope,
 ) -> kill::KillJoinHandleFinalStatus<T> {
-    if let Err(kill_err) = child.start_kill() {
+    let res = match signal {
+        Some(signal) => send_signal(child, signal, scope),
+        None => start_kill(child, scope),
+    };
+
+    if let Err(kill_err) = res {
         match kill_err.kind() {
             io::ErrorKind::InvalidInput | io::ErrorKind::PermissionDenied => {
                 // child process has exited
@@ -32,51 +124,319 @@ fn start_kill_child_process<T>(
     }
 }
 
+/// How long to wait for a command to exit after it was killed forcefully by
+/// [`CommandKiller::abandon`](kill::CommandKiller::abandon), before it is abandoned
+const ABANDON_GRACE: Duration = Duration::from_secs(1);
+
+/// Kills the command forcefully if it was only sent a signal so far
+fn force_kill_child<T>(
+    child: &mut Child,
+    kill_status: kill::KillJoinHandleFinalStatus<T>,
+    scope: &KillScope,
+) -> kill::KillJoinHandleFinalStatus<T> {
+    match kill_status {
+        kill::KillJoinHandleFinalStatus::Killed(reason) => match start_kill(child, scope) {
+            Ok(()) => kill::KillJoinHandleFinalStatus::ForceKilled(reason),
+            Err(error) => kill::KillJoinHandleFinalStatus::FailedToKill { reason, error },
+        },
+        kill_status => kill_status,
+    }
+}
+
+/// Kills the command forcefully, unless it was already, and waits shortly for it to exit.
+/// The status is an error if it doesn't.
+async fn abandon_child<T>(
+    child: &mut Child,
+    kill_status: &mut kill::KillJoinHandleFinalStatus<T>,
+    signaled: bool,
+    scope: &KillScope,
+) -> io::Result<ExitStatus> {
+    use kill::{CommandAlreadyExitedKind as EK, KillJoinHandleFinalStatus as KS};
+
+    if signaled {
+        let signaled = std::mem::replace(kill_status, KS::SenderDisconnected);
+        *kill_status = force_kill_child(child, signaled, scope);
+    }
+
+    match tokio::time::timeout(ABANDON_GRACE, child.wait()).await {
+        Ok(status) => status,
+        Err(_) => {
+            *kill_status = match std::mem::replace(kill_status, KS::SenderDisconnected) {
+                KS::Killed(reason)
+                | KS::ForceKilled(reason)
+                | KS::FailedToKill { reason, .. }
+                | KS::AlreadyExited(EK::ProcessExited(reason)) => KS::Abandoned(reason),
+                kill_status => kill_status,
+            };
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the command didn't exit after it was killed",
+            ))
+        }
+    }
+}
+
+type SpawnResult<T, R> = io::Result<(super::CommandSpawned<T, R>, ChildStdout, ChildStderr)>;
+
+fn spawn_command<T, R: 'static + std::marker::Sync + std::marker::Send>(
+    command: &mut Command,
+    data: T,
+    stdin: StdinSource,
+    kill_signal: Option<(Signal, Duration)>,
+    group: bool,
+    pty: bool,
+    limits: Option<&ResourceLimits>,
+) -> SpawnResult<T, R> {
+    let (kill_sender, kill_receiver) = oneshot::channel();
+    let force_kill = Arc::new(Notify::new());
+    let force_kill_receiver = force_kill.clone();
+    let abandon = Arc::new(Notify::new());
+    let abandon_receiver = abandon.clone();
+
+    command
+        .env("CARGO_TERM_COLOR", "always")
+        // yarn force color https://classic.yarnpkg.com/en/docs/cli/#toc-verbose
+        .env("FORCE_COLOR", "true")
+        .stdin(match stdin {
+            StdinSource::Null => Stdio::null(),
+            StdinSource::Inherit => Stdio::inherit(),
+            StdinSource::Pipe => Stdio::piped(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    let pty = match pty {
+        true => {
+            let pty = super::super::pty::Pty::open()?;
+            command.stdout(pty.stdio()?).stderr(pty.stdio()?);
+            Some(pty)
+        }
+        false => None,
+    };
+    #[cfg(not(unix))]
+    let _ = pty;
+    let spawned = command.spawn();
+    #[cfg(unix)]
+    if pty.is_some() {
+        // the output only ends once the command closed its side of the terminal
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    let mut child = spawned?;
+
+    #[cfg(unix)]
+    let (output, window) = match pty {
+        Some(pty) => {
+            let (stdout, stderr, window) = pty.output(child.id())?;
+            (Some((stdout, stderr)), window)
+        }
+        None => (None, None),
+    };
+    #[cfg(not(unix))]
+    let output = None;
+    let (stdout, stderr) = match output {
+        Some(output) => output,
+        None => {
+            let stdout = child
+                .stdout
+                .take()
+                .ok_or_else(|| io::Error::other("Could not capture standard output."))?;
+            let stderr = child
+                .stderr
+                .take()
+                .ok_or_else(|| io::Error::other("Could not capture standard error."))?;
+            (stdout, stderr)
+        }
+    };
+
+    let scope = KillScope {
+        group,
+        // the processes the command spawned before are not in the job
+        #[cfg(windows)]
+        job: kill::windows::Job::with_process(&child, limits).ok(),
+    };
+    // enforced before the command is executed on Unix,
+    // see [`CommandConfig::limits`](crate::CommandConfig::limits)
+    #[cfg(not(windows))]
+    let _ = limits;
+
+    let stdin = child.stdin.take().map(super::CommandStdin::new);
+    let pid = child.id();
+    let spawned_at = Instant::now();
+    let join_handle = tokio::spawn(async move {
+        // the terminal isn't resized anymore once the command exited
+        #[cfg(unix)]
+        let _window = window;
+        let (status, killed) = tokio::select! {
+            status = child.wait() => (status, None),
+            kill_reason = kill_receiver => {
+                let (mut kill_status, signal) = if let Ok((kill_reason, signal)) = kill_reason {
+                    let signal = kill_signal.map(|(signal, _)| signal).or(signal);
+                    let kill_status = start_kill_child_process(&mut child, kill_reason, signal, &scope);
+                    (kill_status, signal)
+                } else {
+                    (kill::KillJoinHandleFinalStatus::SenderDisconnected, None)
+                };
+
+                // the command may ignore signals other than SIGKILL
+                let forceable = signal.is_some_and(|signal| signal != Signal::Kill)
+                    && matches!(kill_status, kill::KillJoinHandleFinalStatus::Killed(_));
+
+                let force_kill = async {
+                    match kill_signal {
+                        Some((_, timeout)) => {
+                            let force_kill = force_kill_receiver.notified();
+                            let _ = tokio::time::timeout(timeout, force_kill).await;
+                        }
+                        None => force_kill_receiver.notified().await,
+                    }
+                };
+
+                let status = tokio::select! {
+                    status = child.wait() => status,
+                    _ = force_kill, if forceable => {
+                        kill_status = force_kill_child(&mut child, kill_status, &scope);
+                        tokio::select! {
+                            status = child.wait() => status,
+                            _ = abandon_receiver.notified() => {
+                                abandon_child(&mut child, &mut kill_status, false, &scope).await
+                            }
+                        }
+                    }
+                    _ = abandon_receiver.notified() => {
+                        abandon_child(&mut child, &mut kill_status, forceable, &scope).await
+                    }
+                };
+                (status, Some(kill_status))
+            }
+        };
+        (status, killed, spawned_at.elapsed())
+    });
+
+    Ok((
+        super::CommandSpawned::new(
+            data,
+            pid,
+            stdin,
+            kill_sender,
+            force_kill,
+            abandon,
+            join_handle,
+        ),
+        stdout,
+        stderr,
+    ))
+}
+
 impl<T> CommandInitialized<T> {
     pub fn new(command: Command, data: T) -> Self {
-        Self { command, data }
-    }
-
-    pub fn spawn<R: 'static + std::marker::Sync + std::marker::Send>(
-        self,
-    ) -> io::Result<(super::CommandSpawned<T, R>, ChildStdout, ChildStderr)> {
-        let Self { mut command, data } = self;
-        let (kill_sender, kill_receiver) = oneshot::channel::<kill::KillCommandReason<R>>();
-
-        let mut child = command
-            .env("CARGO_TERM_COLOR", "always")
-            // yarn force color https://classic.yarnpkg.com/en/docs/cli/#toc-verbose
-            .env("FORCE_COLOR", "true")
-            .stdin(Stdio::null())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?;
-        let stdout = child.stdout.take().ok_or_else(|| {
-            io::Error::new(io::ErrorKind::Other, "Could not capture standard output.")
-        })?;
-        let stderr = child.stderr.take().ok_or_else(|| {
-            io::Error::new(io::ErrorKind::Other, "Could not capture standard error.")
-        })?;
-
-        let join_handle = tokio::spawn(async move {
-            tokio::select! {
-                status = child.wait() => (status, None),
-                kill_reason = kill_receiver => {
-                    let kill_status = if let Ok(kill_reason) = kill_reason {
-                        start_kill_child_process(&mut child, kill_reason)
-                    } else {
-                        kill::KillJoinHandleFinalStatus::SenderDisconnected
-                    };
-                    let status = child.wait().await;
-                    (status, Some(kill_status))
-                }
-            }
-        });
+        Self {
+            command,
+            data,
+            stdin: StdinSource::Null,
+            kill_signal: None,
+            group: false,
+            pty: false,
+            limits: None,
+        }
+    }
+
+    /// Let the command read the stdin of runcc
+    pub fn inherit_stdin(mut self) -> Self {
+        self.stdin = StdinSource::Inherit;
+        self
+    }
+
+    /// Give the command a pipe as stdin which stays open while it runs,
+    /// so that input can be written to it, see [`CommandSpawned::stdin`](super::CommandSpawned::stdin)
+    pub fn pipe_stdin(mut self) -> Self {
+        self.stdin = StdinSource::Pipe;
+        self
+    }
+
+    /// Send `signal` whenever the command is killed, instead of the signal it is killed with
+    /// or killing it forcefully, and kill it forcefully if it still runs `timeout` later
+    pub fn kill_signal(mut self, signal: Signal, timeout: Duration) -> Self {
+        self.kill_signal = Some((signal, timeout));
+        self
+    }
+
+    /// Send signals to the process group of the command instead of only to the command,
+    /// so that its descendants are killed with it.
+    ///
+    /// The command must be the leader of its process group, e.g. by a
+    /// [`pre_exec`](std::os::unix::process::CommandExt::pre_exec) hook calling `setpgid(0, 0)`.
+    #[cfg(unix)]
+    pub fn kill_process_group(mut self) -> Self {
+        self.group = true;
+        self
+    }
+
+    /// Spawn the command in a new process group, which gets Ctrl-Break when the command is
+    /// killed with a signal. It doesn't get Ctrl-C of the console then.
+    #[cfg(windows)]
+    pub fn new_process_group(mut self) -> Self {
+        self.command
+            .creation_flags(kill::windows::CREATE_NEW_PROCESS_GROUP);
+        self.group = true;
+        self
+    }
+
+    /// Write stdout and stderr of the command to a pseudo-terminal instead of pipes, so that
+    /// it prints like on a terminal.
+    ///
+    /// Both of them are read from stdout then, stderr is empty.
+    #[cfg(unix)]
+    pub fn pty(mut self) -> Self {
+        self.pty = true;
+        self
+    }
+
+    /// Limit the resources of the command and its descendants by its job object
+    #[cfg(windows)]
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    pub fn spawn<R: 'static + std::marker::Sync + std::marker::Send>(self) -> SpawnResult<T, R> {
+        let Self {
+            mut command,
+            data,
+            stdin,
+            kill_signal,
+            group,
+            pty,
+            limits,
+        } = self;
+        spawn_command(
+            &mut command,
+            data,
+            stdin,
+            kill_signal,
+            group,
+            pty,
+            limits.as_ref(),
+        )
+    }
+
+    pub fn command_mut(&mut self) -> &mut Command {
+        &mut self.command
+    }
+}
 
-        Ok((
-            super::CommandSpawned::new(data, kill_sender, join_handle),
-            stdout,
-            stderr,
-        ))
+impl<T: Clone> CommandInitialized<T> {
+    /// Like [`spawn`](Self::spawn), but keeps the command so that it can be spawned again
+    pub fn respawn<R: 'static + std::marker::Sync + std::marker::Send>(
+        &mut self,
+    ) -> SpawnResult<T, R> {
+        spawn_command(
+            &mut self.command,
+            self.data.clone(),
+            self.stdin,
+            self.kill_signal,
+            self.group,
+            self.pty,
+            self.limits.as_ref(),
+        )
     }
 }
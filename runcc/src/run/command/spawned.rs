@@ -1,5 +1,10 @@
 use std::io;
 use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::ChildStdin;
+use tokio::sync::{mpsc, Notify};
 use tokio::task::JoinHandle;
 
 use super::super::kill;
@@ -10,27 +15,76 @@ pub struct SharedCommandSpawned<T, R> {
     killer: kill::CommandKiller<R>,
 }
 
+impl<T, R> SharedCommandSpawned<T, R> {
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    pub fn kill(&self, reason: kill::KillCommandReason<R>) -> kill::KillResult {
+        self.killer.kill(reason)
+    }
+}
+
 pub(super) type CommandTokioJoinHandle<T> = JoinHandle<(
     io::Result<ExitStatus>,
     Option<kill::KillJoinHandleFinalStatus<T>>,
+    Duration,
 )>;
 
 pub struct CommandJoinHandle<R>(CommandTokioJoinHandle<R>);
 
 impl<R> CommandJoinHandle<R> {
     pub async fn join(self) -> super::CommandStopped<(), R> {
-        let (exit_status, killed) = self.0.await.expect("command task handle should not panic");
+        let (exit_status, killed, ran_for) =
+            self.0.await.expect("command task handle should not panic");
 
         super::CommandStopped {
             data: (),
             exit_status,
             killed,
+            ran_for: Some(ran_for),
         }
     }
 }
 
+/// Writes input to the piped stdin of a running command, see
+/// [`CommandInitialized::pipe_stdin`](super::CommandInitialized::pipe_stdin).
+///
+/// The stdin of the command is closed once all clones are dropped.
+#[derive(Clone)]
+pub struct CommandStdin(mpsc::UnboundedSender<Vec<u8>>);
+
+impl CommandStdin {
+    pub(super) fn new(mut stdin: ChildStdin) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Vec<u8>>();
+        // writes in order without blocking the writers, until the command stops reading
+        tokio::spawn(async move {
+            while let Some(input) = receiver.recv().await {
+                if stdin.write_all(&input).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Self(sender)
+    }
+
+    /// Writes `line` and a newline to the stdin of the command. Returns `false` if the
+    /// command doesn't read its stdin anymore, e.g. because it exited.
+    pub fn write_line(&self, line: &str) -> bool {
+        let mut input = Vec::with_capacity(line.len() + 1);
+        input.extend_from_slice(line.as_bytes());
+        input.push(b'\n');
+        self.0.send(input).is_ok()
+    }
+}
+
 pub struct CommandSpawned<T, R> {
     pub data: T,
+    /// Process id, `None` if the process exited and was waited for right away
+    pub pid: Option<u32>,
+    /// The piped stdin of the command, `None` unless it was spawned with
+    /// [`pipe_stdin`](super::CommandInitialized::pipe_stdin)
+    pub stdin: Option<CommandStdin>,
     pub killer: kill::CommandKiller<R>,
     pub join_handle: CommandJoinHandle<R>,
 }
@@ -38,12 +92,18 @@ pub struct CommandSpawned<T, R> {
 impl<T, R> CommandSpawned<T, R> {
     pub(super) fn new(
         data: T,
+        pid: Option<u32>,
+        stdin: Option<CommandStdin>,
         kill_sender: kill::KillSender<R>,
+        force_kill: Arc<Notify>,
+        abandon: Arc<Notify>,
         join_handle: CommandTokioJoinHandle<R>,
     ) -> Self {
         Self {
             data,
-            killer: kill::CommandKiller::new(kill_sender),
+            pid,
+            stdin,
+            killer: kill::CommandKiller::new(kill_sender, force_kill, abandon),
             join_handle: CommandJoinHandle(join_handle),
         }
     }
@@ -63,6 +123,8 @@ impl<T, R> CommandSpawned<T, R> {
     pub fn with_data<S>(self, new_data: S) -> (T, CommandSpawned<S, R>) {
         let Self {
             data,
+            pid,
+            stdin,
             join_handle,
             killer,
         } = self;
@@ -71,6 +133,8 @@ impl<T, R> CommandSpawned<T, R> {
             data,
             CommandSpawned {
                 data: new_data,
+                pid,
+                stdin,
                 join_handle,
                 killer,
             },
@@ -1,4 +1,4 @@
-use std::{io, process::ExitStatus};
+use std::{error, fmt::Display, io, process::ExitStatus, time::Duration};
 
 use super::super::kill;
 
@@ -7,14 +7,42 @@ pub struct CommandStopped<T, R> {
     pub data: T,
     pub exit_status: io::Result<ExitStatus>,
     pub killed: Option<kill::KillJoinHandleFinalStatus<R>>,
+    /// How long the command ran, `None` if it wasn't spawned
+    pub ran_for: Option<Duration>,
 }
 
+/// How a command stopped, as counted in reports and exit codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandOutcome {
+    Succeeded,
+    /// The command exited without success on its own, wasn't spawned
+    /// or was killed because its startup probe failed or it timed out
+    Failed,
+    /// runcc killed the command, e.g. because another command exited,
+    /// so its exit status reflects the kill rather than a failure
+    Killed,
+}
+
+/// The error of [`CommandStopped::exit_status`] when the command was never spawned
+/// on purpose, e.g. because a command it depends on failed.
+#[derive(Debug)]
+pub struct CommandSkipped(pub String);
+
+impl Display for CommandSkipped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "skipped: {}", self.0)
+    }
+}
+
+impl error::Error for CommandSkipped {}
+
 impl<T, R> CommandStopped<T, R> {
     pub fn with_data<S>(self, new_data: S) -> (T, CommandStopped<S, R>) {
         let Self {
             data,
             exit_status,
             killed,
+            ran_for,
         } = self;
         (
             data,
@@ -22,7 +50,84 @@ impl<T, R> CommandStopped<T, R> {
                 data: new_data,
                 exit_status,
                 killed,
+                ran_for,
             },
         )
     }
+
+    pub(crate) fn new_skipped(data: T, reason: String) -> Self {
+        Self {
+            data,
+            exit_status: Err(io::Error::other(CommandSkipped(reason))),
+            killed: None,
+            ran_for: None,
+        }
+    }
+
+    /// Whether the command exited with a success status
+    pub fn success(&self) -> bool {
+        self.exit_status.as_ref().is_ok_and(|s| s.success())
+    }
+
+    pub fn outcome(&self) -> CommandOutcome {
+        use kill::KillJoinHandleFinalStatus as KS;
+
+        if self.success() {
+            CommandOutcome::Succeeded
+        } else if self.probe_failed() || self.timed_out().is_some() {
+            CommandOutcome::Failed
+        } else if matches!(
+            self.killed,
+            Some(KS::Killed(_)) | Some(KS::ForceKilled(_)) | Some(KS::Abandoned(_))
+        ) {
+            CommandOutcome::Killed
+        } else {
+            CommandOutcome::Failed
+        }
+    }
+
+    /// Whether the command had to be killed forcefully because it didn't exit in time,
+    /// after a signal or by the deadline of
+    /// [`CommandSystemKiller::kill_all_with_timeout`](crate::run::CommandSystemKiller::kill_all_with_timeout)
+    pub fn force_killed(&self) -> bool {
+        use kill::KillJoinHandleFinalStatus as KS;
+
+        matches!(
+            self.killed,
+            Some(KS::ForceKilled(_)) | Some(KS::Abandoned(_))
+        )
+    }
+
+    /// Whether the command was killed because all attempts of its startup probe failed
+    pub fn probe_failed(&self) -> bool {
+        use kill::KillJoinHandleFinalStatus as KS;
+
+        matches!(
+            &self.killed,
+            Some(KS::Killed(reason)) | Some(KS::ForceKilled(reason)) | Some(KS::Abandoned(reason))
+                if matches!(reason, kill::KillCommandReason::ProbeFailed { .. })
+        )
+    }
+
+    /// The timeout after which the command was killed, see [`KillCommandReason::TimedOut`](kill::KillCommandReason::TimedOut)
+    pub fn timed_out(&self) -> Option<Duration> {
+        use kill::KillJoinHandleFinalStatus as KS;
+
+        use kill::KillCommandReason::TimedOut;
+
+        match &self.killed {
+            Some(KS::Killed(TimedOut(timeout)))
+            | Some(KS::ForceKilled(TimedOut(timeout)))
+            | Some(KS::Abandoned(TimedOut(timeout))) => Some(*timeout),
+            _ => None,
+        }
+    }
+
+    pub fn skipped(&self) -> Option<&CommandSkipped> {
+        self.exit_status
+            .as_ref()
+            .err()
+            .and_then(|err| err.get_ref())
+            .and_then(|err| err.downcast_ref())
+    }
 }
@@ -0,0 +1,109 @@
+use std::process::ExitStatus;
+
+use tokio::sync::broadcast;
+
+use super::{kill::KillCommandReason, OutputStream};
+
+/// How many events a receiver may fall behind before it misses events
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// What happened to a command of a [`CommandSystem`](super::CommandSystem),
+/// see [`CommandSystem::events`](super::CommandSystem::events)
+pub enum CommandEvent<T> {
+    /// A run of the command was spawned, also when it was restarted.
+    /// `pid` is `None` if its process already exited and was waited for.
+    /// `pane` is the [`CommandConfig::pane`](crate::CommandConfig::pane) of the command.
+    Spawned {
+        label: String,
+        pane: Option<String>,
+        pid: Option<u32>,
+    },
+    /// An output line of the command, without the line ending.
+    ///
+    /// Sent from the [`output_broadcast`](super::CommandSystemPlugin::output_broadcast) of
+    /// the plugin if it has one. Otherwise, the system splits the output into lines
+    /// on its way to the plugin while anyone receives events.
+    /// The lines are read on their own, so the last lines of a run may follow its exit.
+    OutputLine {
+        label: String,
+        stream: OutputStream,
+        line: String,
+    },
+    /// A run of the command was killed by runcc, sent right before it is [`Exited`](Self::Exited)
+    KilledBecause {
+        label: String,
+        reason: KillCommandReason<T>,
+    },
+    /// A run of the command stopped.
+    ///
+    /// `status` is `None` if it failed to spawn, wasn't spawned or couldn't be waited for.
+    Exited {
+        label: String,
+        status: Option<ExitStatus>,
+    },
+}
+
+impl<T> CommandEvent<T> {
+    /// The label of the command
+    pub fn label(&self) -> &str {
+        match self {
+            Self::Spawned { label, .. }
+            | Self::OutputLine { label, .. }
+            | Self::KilledBecause { label, .. }
+            | Self::Exited { label, .. } => label,
+        }
+    }
+}
+
+impl<T> Clone for CommandEvent<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Spawned { label, pane, pid } => Self::Spawned {
+                label: label.clone(),
+                pane: pane.clone(),
+                pid: *pid,
+            },
+            Self::OutputLine {
+                label,
+                stream,
+                line,
+            } => Self::OutputLine {
+                label: label.clone(),
+                stream: *stream,
+                line: line.clone(),
+            },
+            Self::KilledBecause { label, reason } => Self::KilledBecause {
+                label: label.clone(),
+                reason: reason.clone(),
+            },
+            Self::Exited { label, status } => Self::Exited {
+                label: label.clone(),
+                status: *status,
+            },
+        }
+    }
+}
+
+/// Sends the [`CommandEvent`]s of a system to its receivers
+pub(super) struct EventSender<T>(broadcast::Sender<CommandEvent<T>>);
+
+impl<T> Default for EventSender<T> {
+    fn default() -> Self {
+        Self(broadcast::channel(CHANNEL_CAPACITY).0)
+    }
+}
+
+impl<T> EventSender<T> {
+    pub fn send(&self, event: CommandEvent<T>) {
+        // fails only without receivers
+        let _ = self.0.send(event);
+    }
+
+    pub fn has_receivers(&self) -> bool {
+        self.0.receiver_count() > 0
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<CommandEvent<T>> {
+        self.0.subscribe()
+    }
+}
@@ -0,0 +1,34 @@
+use std::{collections::HashMap, process::Stdio};
+
+use crate::{CommandConfig, FailureHook};
+
+/// Runs the [`FailureHook`] of a command
+#[derive(Debug, Clone)]
+pub(crate) struct HookRunner {
+    hook: FailureHook,
+    cwd: Option<String>,
+    envs: Option<HashMap<String, String>>,
+}
+
+impl HookRunner {
+    pub fn new(
+        hook: FailureHook,
+        cwd: Option<String>,
+        envs: Option<HashMap<String, String>>,
+    ) -> Self {
+        Self { hook, cwd, envs }
+    }
+
+    /// Runs the hook until it exits or its timeout passed
+    pub async fn run(&self) {
+        let mut config = CommandConfig::from_script(&self.hook.command, &Default::default());
+        config.cwd = self.cwd.clone();
+
+        let (mut command, _) = config.into_tokio_command_and_label(self.envs.as_ref());
+        command.stdin(Stdio::null()).kill_on_drop(true);
+
+        if let Ok(mut child) = command.spawn() {
+            let _ = tokio::time::timeout(self.hook.timeout, child.wait()).await;
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    io,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+/// pid of the command run by the init, signals of the init are forwarded to it
+static COMMAND_PID: AtomicI32 = AtomicI32::new(0);
+
+const FORWARDED_SIGNALS: [libc::c_int; 6] = [
+    libc::SIGINT,
+    libc::SIGTERM,
+    libc::SIGHUP,
+    libc::SIGQUIT,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let pid = COMMAND_PID.load(Ordering::Relaxed);
+    if pid > 0 {
+        unsafe { libc::kill(pid, signal) };
+    }
+}
+
+fn interrupted() -> bool {
+    io::Error::last_os_error().raw_os_error() == Some(libc::EINTR)
+}
+
+/// std keeps a pipe open until exec to report exec errors,
+/// spawning the command would block as long as the init holds it
+unsafe fn close_fds() {
+    let max = libc::sysconf(libc::_SC_OPEN_MAX).clamp(1024, 65536) as libc::c_int;
+    for fd in 3..max {
+        libc::close(fd);
+    }
+}
+
+/// Waits for `pid` while reaping all other children
+unsafe fn wait_for(pid: libc::pid_t) -> libc::c_int {
+    let mut status = 0;
+    loop {
+        let exited = libc::waitpid(-1, &mut status, 0);
+        if exited == pid || (exited < 0 && !interrupted()) {
+            return status;
+        }
+    }
+}
+
+unsafe fn exit_with(status: libc::c_int) -> ! {
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal)
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+/// A `pre_exec` hook which turns the spawned process into a minimal init
+/// for commands with [`init`](crate::CommandConfig::init).
+///
+/// The process forks the command and stays its parent as a child subreaper,
+/// so orphaned descendants are reparented to it and reaped.
+/// Signals are forwarded to the command. Once the command exited,
+/// the descendants left in its process group are killed and reaped
+/// and the init exits with the status of the command.
+///
+/// A `SIGKILL` of the init kills the command, but not its descendants.
+pub(crate) fn reap_as_init() -> io::Result<()> {
+    unsafe {
+        if libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let pid = libc::fork();
+        if pid < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if pid == 0 {
+            // the command and its descendants are killed together by the process group
+            libc::setpgid(0, 0);
+            libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0);
+            // the command is exec'd by std
+            return Ok(());
+        }
+
+        libc::setpgid(pid, pid);
+        COMMAND_PID.store(pid, Ordering::Relaxed);
+        for signal in FORWARDED_SIGNALS {
+            libc::signal(signal, forward_signal as *const () as libc::sighandler_t);
+        }
+        close_fds();
+
+        let status = wait_for(pid);
+
+        libc::kill(-pid, libc::SIGKILL);
+        while libc::waitpid(-pid, std::ptr::null_mut(), 0) > 0 || interrupted() {}
+        while libc::waitpid(-1, std::ptr::null_mut(), libc::WNOHANG) > 0 {}
+
+        exit_with(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    use crate::CommandConfig;
+
+    fn reaped(pid: &str) -> bool {
+        !Path::new("/proc").join(pid).exists()
+    }
+
+    #[tokio::test]
+    async fn reap_descendants() {
+        let config = CommandConfig {
+            program: "sh".to_string(),
+            args: Some(vec![
+                "-c".to_string(),
+                "(sleep 0.1 & echo $!); sleep 30 & echo $!; sleep 0.5".to_string(),
+            ]),
+            init: true,
+            ..Default::default()
+        };
+        let (mut command, _) = config.into_tokio_command_and_label(None::<[(&str, &str); 0]>);
+        let mut child = command
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let orphan = lines.next_line().await.unwrap().unwrap();
+        let background = lines.next_line().await.unwrap().unwrap();
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(reaped(&orphan), "orphan is a zombie");
+        assert!(!reaped(&background));
+
+        assert!(child.wait().await.unwrap().success());
+        assert!(reaped(&background), "background command was not killed");
+    }
+}
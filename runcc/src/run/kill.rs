@@ -1,15 +1,53 @@
 use std::{
     io,
+    path::PathBuf,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, Notify};
 
 use super::command::CommandStopped;
+use crate::Signal;
 
+#[cfg(windows)]
+pub(crate) mod windows;
+
+#[non_exhaustive]
 pub enum KillCommandReason<T> {
     OtherCommandExited(Arc<CommandStopped<T, T>>),
     MainProcessGotSignal,
+    /// The command didn't become ready within the timeout of its restart policy
+    NotReadyInTime,
+    /// All `attempts` of the startup probe of the command failed,
+    /// see [`StartupProbe::failure_threshold`](crate::StartupProbe::failure_threshold)
+    ProbeFailed {
+        attempts: u32,
+    },
+    /// The command ran longer than its [`timeout`](crate::CommandConfig::timeout),
+    /// or the run longer than [`RunConfig::timeout`](crate::RunConfig::timeout)
+    TimedOut(Duration),
+    /// Files watched by the command changed, it will be spawned again.
+    /// Holds one of them, relative to the watched directory.
+    FilesChanged(PathBuf),
+    /// A task of the command system panicked
+    SystemPanicked,
+    /// Only this command was killed, see [`CommandHandle::kill`](super::CommandHandle::kill)
+    Requested,
+    /// Only this command was killed to spawn it again,
+    /// see [`CommandHandle::restart`](super::CommandHandle::restart)
+    RestartRequested,
+    /// `failures` health checks of the command failed in a row, it will be spawned again,
+    /// see [`CommandConfig::healthcheck`](crate::CommandConfig::healthcheck)
+    Unhealthy {
+        failures: u32,
+    },
+    /// Only this command was stopped because the command with this label is ready, or
+    /// exited if `exited`, see [`CommandConfig::stop_when_ready`](crate::CommandConfig::stop_when_ready)
+    StopWhenReady {
+        label: String,
+        exited: bool,
+    },
 }
 
 impl<T> Clone for KillCommandReason<T> {
@@ -17,27 +55,79 @@ impl<T> Clone for KillCommandReason<T> {
         match self {
             Self::OtherCommandExited(arc) => Self::OtherCommandExited(arc.clone()),
             Self::MainProcessGotSignal => Self::MainProcessGotSignal,
+            Self::NotReadyInTime => Self::NotReadyInTime,
+            Self::ProbeFailed { attempts } => Self::ProbeFailed {
+                attempts: *attempts,
+            },
+            Self::TimedOut(timeout) => Self::TimedOut(*timeout),
+            Self::FilesChanged(path) => Self::FilesChanged(path.clone()),
+            Self::SystemPanicked => Self::SystemPanicked,
+            Self::Requested => Self::Requested,
+            Self::RestartRequested => Self::RestartRequested,
+            Self::Unhealthy { failures } => Self::Unhealthy {
+                failures: *failures,
+            },
+            Self::StopWhenReady { label, exited } => Self::StopWhenReady {
+                label: label.clone(),
+                exited: *exited,
+            },
         }
     }
 }
 
-pub(super) type KillSender<T> = oneshot::Sender<KillCommandReason<T>>;
+/// The reason and the signal to send, `None` means killing forcefully
+pub(super) type KillSender<T> = oneshot::Sender<(KillCommandReason<T>, Option<Signal>)>;
 // pub(super) type KillReceiver<T> = oneshot::Receiver<KillCommandReason<T>>;
 
 #[derive(Clone)]
-pub struct CommandKiller<T>(Arc<Mutex<Option<KillSender<T>>>>);
+pub struct CommandKiller<T> {
+    sender: Arc<Mutex<Option<KillSender<T>>>>,
+    force_kill: Arc<Notify>,
+    abandon: Arc<Notify>,
+}
 
 impl<T> CommandKiller<T> {
-    pub(super) fn new(kill_sender: KillSender<T>) -> Self {
-        Self(Arc::new(Mutex::new(Some(kill_sender))))
+    pub(super) fn new(
+        kill_sender: KillSender<T>,
+        force_kill: Arc<Notify>,
+        abandon: Arc<Notify>,
+    ) -> Self {
+        Self {
+            sender: Arc::new(Mutex::new(Some(kill_sender))),
+            force_kill,
+            abandon,
+        }
+    }
+
+    /// Kills the command forcefully if it is still running
+    /// after [`kill_with_signal`](Self::kill_with_signal)
+    pub fn force_kill(&self) {
+        self.force_kill.notify_one();
+    }
+
+    /// Kills the command forcefully and stops waiting for it if it still doesn't exit
+    /// shortly after, see [`KillJoinHandleFinalStatus::Abandoned`].
+    ///
+    /// Only applies once the command was sent a kill.
+    pub fn abandon(&self) {
+        self.abandon.notify_one();
     }
 
     pub fn kill(&self, reason: KillCommandReason<T>) -> KillResult {
-        let mut kill_sender = self.0.lock().unwrap();
+        self.send(reason, None)
+    }
+
+    /// Like [`kill`](Self::kill), but sends `signal` instead of killing forcefully
+    pub fn kill_with_signal(&self, reason: KillCommandReason<T>, signal: Signal) -> KillResult {
+        self.send(reason, Some(signal))
+    }
+
+    fn send(&self, reason: KillCommandReason<T>, signal: Option<Signal>) -> KillResult {
+        let mut kill_sender = self.sender.lock().unwrap();
 
         let kill_sender = kill_sender.take();
         if let Some(kill_sender) = kill_sender {
-            match kill_sender.send(reason) {
+            match kill_sender.send((reason, signal)) {
                 Ok(_) => KillResult::SentSuccess,
                 Err(_) => KillResult::AlreadyExited,
             }
@@ -56,10 +146,15 @@ impl<T> CommandKiller<T> {
 pub enum KillJoinHandleFinalStatus<T> {
     SenderDisconnected,
     Killed(KillCommandReason<T>),
+    /// The command didn't exit after a signal and was killed forcefully
+    ForceKilled(KillCommandReason<T>),
     FailedToKill {
         reason: KillCommandReason<T>,
         error: io::Error,
     },
+    /// The command didn't exit even after it was killed forcefully, e.g. it hangs
+    /// in the kernel, runcc stopped waiting for it
+    Abandoned(KillCommandReason<T>),
     AlreadyExited(CommandAlreadyExitedKind<T>),
     UnexpectedAlreadyKilled,
 }
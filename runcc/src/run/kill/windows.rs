@@ -0,0 +1,133 @@
+//! Killing commands on Windows, which has no signals.
+//!
+//! Commands in their own process group get Ctrl-Break instead of a signal to stop gracefully.
+//! Each command is spawned into a job object, so that killing it forcefully kills its
+//! descendants too, and those left once it exited are killed when the job is closed.
+//! The job also enforces the [`ResourceLimits`] of the command.
+
+use std::{io, mem, ptr};
+
+use tokio::process::Child;
+use winapi::um::{
+    handleapi::CloseHandle,
+    jobapi2::{
+        AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject, TerminateJobObject,
+    },
+    winbase::{
+        ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, IDLE_PRIORITY_CLASS,
+        NORMAL_PRIORITY_CLASS,
+    },
+    wincon::{GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT},
+    winnt::{
+        JobObjectCpuRateControlInformation, JobObjectExtendedLimitInformation, HANDLE,
+        JOBOBJECT_CPU_RATE_CONTROL_INFORMATION, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_CPU_RATE_CONTROL_ENABLE, JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP,
+        JOB_OBJECT_LIMIT_JOB_MEMORY, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        JOB_OBJECT_LIMIT_PRIORITY_CLASS,
+    },
+};
+
+use crate::{CpuShare, MemorySize, ResourceLimits};
+
+pub(crate) use winapi::um::winbase::CREATE_NEW_PROCESS_GROUP;
+
+/// Sends Ctrl-Break to the process group of `pid`, which must have been spawned with
+/// [`CREATE_NEW_PROCESS_GROUP`]. Otherwise all processes of the console would get it.
+pub(crate) fn send_ctrl_break(pid: u32) -> io::Result<()> {
+    if unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A job object which kills its processes once it is dropped
+#[derive(Debug)]
+pub(crate) struct Job(HANDLE);
+
+// the handle may be used and closed from any thread
+unsafe impl Send for Job {}
+unsafe impl Sync for Job {}
+
+impl Job {
+    /// A job holding `child`, and all processes it spawns after this returned,
+    /// limited by `resources` if given
+    pub fn with_process(child: &Child, resources: Option<&ResourceLimits>) -> io::Result<Self> {
+        let process = child
+            .raw_handle()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+
+        let job = unsafe { CreateJobObjectW(ptr::null_mut(), ptr::null()) };
+        if job.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let job = Self(job);
+
+        unsafe {
+            let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = mem::zeroed();
+            limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            if let Some(MemorySize(memory)) = resources.and_then(|r| r.memory) {
+                limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
+                limits.JobMemoryLimit = memory as usize;
+            }
+            if let Some(nice) = resources.and_then(|r| r.nice) {
+                limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PRIORITY_CLASS;
+                limits.BasicLimitInformation.PriorityClass = priority_class(nice);
+            }
+            let set = SetInformationJobObject(
+                job.0,
+                JobObjectExtendedLimitInformation,
+                &mut limits as *mut _ as *mut _,
+                mem::size_of_val(&limits) as u32,
+            );
+            if set == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if let Some(CpuShare(percent)) = resources.and_then(|r| r.cpu) {
+                let mut rate: JOBOBJECT_CPU_RATE_CONTROL_INFORMATION = mem::zeroed();
+                rate.ControlFlags =
+                    JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP;
+                // in hundredths of a percent
+                *rate.u.CpuRate_mut() = percent * 100;
+                let set = SetInformationJobObject(
+                    job.0,
+                    JobObjectCpuRateControlInformation,
+                    &mut rate as *mut _ as *mut _,
+                    mem::size_of_val(&rate) as u32,
+                );
+                if set == 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            if AssignProcessToJobObject(job.0, process as HANDLE) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(job)
+    }
+
+    /// Kills all processes of the job forcefully
+    pub fn terminate(&self) -> io::Result<()> {
+        if unsafe { TerminateJobObject(self.0, 1) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// The priority class closest to the nice level `nice` of Unix
+fn priority_class(nice: i32) -> u32 {
+    match nice {
+        i32::MIN..=-1 => ABOVE_NORMAL_PRIORITY_CLASS,
+        0 => NORMAL_PRIORITY_CLASS,
+        1..=14 => BELOW_NORMAL_PRIORITY_CLASS,
+        _ => IDLE_PRIORITY_CLASS,
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
@@ -1,8 +1,34 @@
+mod broadcast;
 mod command;
+mod events;
+mod hook;
+#[cfg(target_os = "linux")]
+pub(crate) mod init;
+mod probe;
+mod progress;
+#[cfg(unix)]
+mod pty;
+mod ready;
+mod reload;
 mod report;
+mod schedule;
+mod script;
+mod stack;
+mod steps;
 mod system;
+mod tee;
+#[cfg(unix)]
+pub(crate) mod terminal;
+pub(crate) mod watch;
 
+pub use broadcast::*;
 pub use command::*;
+pub use events::CommandEvent;
+pub use progress::*;
+pub use ready::*;
+pub use reload::*;
 pub use report::*;
+pub use stack::*;
+pub use steps::*;
 pub use system::*;
 pub mod kill;
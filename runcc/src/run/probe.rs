@@ -0,0 +1,266 @@
+use std::{collections::HashMap, process::Stdio, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    process::Command,
+};
+
+use super::ReadySignal;
+use crate::{CommandConfig, HealthCheck, PortNumber, PortWait, StartupProbe};
+
+/// How often [`wait_for_port`] tries to connect
+const PORT_WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `script` run like commands given as a string, without input or output
+fn check_command(
+    script: &str,
+    cwd: &Option<String>,
+    envs: Option<&HashMap<String, String>>,
+) -> Command {
+    let mut config = CommandConfig::from_script(script, &Default::default());
+    config.cwd = cwd.clone();
+
+    let (mut command, _) = config.into_tokio_command_and_label(envs);
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+    command
+}
+
+/// Whether `command` exits successfully within `timeout`
+async fn command_succeeds(mut command: Command, timeout: Duration) -> bool {
+    let status = match command.spawn() {
+        Ok(mut child) => tokio::time::timeout(timeout, child.wait()).await,
+        Err(_) => return false,
+    };
+
+    matches!(status, Ok(Ok(status)) if status.success())
+}
+
+/// Runs a [`StartupProbe`] for a command
+#[derive(Debug, Clone)]
+pub(crate) struct ProbeRunner {
+    probe: StartupProbe,
+    cwd: Option<String>,
+    envs: Option<HashMap<String, String>>,
+}
+
+impl ProbeRunner {
+    pub fn new(
+        probe: StartupProbe,
+        cwd: Option<String>,
+        envs: Option<HashMap<String, String>>,
+    ) -> Self {
+        Self { probe, cwd, envs }
+    }
+
+    async fn attempt(&self) -> bool {
+        let command = check_command(&self.probe.command, &self.cwd, self.envs.as_ref());
+        command_succeeds(command, self.probe.timeout).await
+    }
+
+    pub fn failure_threshold(&self) -> u32 {
+        self.probe.failure_threshold
+    }
+
+    /// Marks `ready` once an attempt succeeds and calls `on_failed` with the number of each
+    /// failed attempt.
+    ///
+    /// Returns whether the command is ready, `false` once all attempts failed.
+    pub async fn run(&self, ready: &ReadySignal, on_failed: impl Fn(u32)) -> bool {
+        for attempt in 1..=self.probe.failure_threshold {
+            tokio::time::sleep(self.probe.interval).await;
+
+            // e.g. by a line matching `ready_when`
+            if ready.is_ready() {
+                return true;
+            }
+            if self.attempt().await {
+                ready.set_ready();
+                return true;
+            }
+            on_failed(attempt);
+        }
+        ready.is_ready()
+    }
+}
+
+/// Waits until the port of `wait` accepts connections.
+///
+/// Returns why the command is skipped if it doesn't within the timeout of `wait`.
+pub(crate) async fn wait_for_port(wait: &PortWait) -> Result<(), String> {
+    let port = match &wait.port {
+        PortNumber::Number(port) => *port,
+        PortNumber::Assigned(name) => return Err(format!("port {} was never assigned", name)),
+    };
+    let connect = async {
+        while TcpStream::connect((wait.host.as_str(), port))
+            .await
+            .is_err()
+        {
+            tokio::time::sleep(PORT_WAIT_INTERVAL).await;
+        }
+    };
+    tokio::time::timeout(wait.timeout, connect)
+        .await
+        .map_err(|_| {
+            format!(
+                "{}:{} didn't accept connections within {}",
+                wait.host,
+                port,
+                humantime::format_duration(wait.timeout)
+            )
+        })
+}
+
+/// The host, port and path of an `http://` url
+fn parse_http_url(url: &str) -> Option<(&str, u16, &str)> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host, port, path))
+}
+
+/// Whether a GET of `url` answers with a 2xx or 3xx status
+async fn http_get_succeeds(url: &str) -> bool {
+    let (host, port, path) = match parse_http_url(url) {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let get = async {
+        let mut stream = TcpStream::connect((host, port)).await?;
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            path, host
+        );
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line).await?;
+        Ok::<_, std::io::Error>(status_line)
+    };
+    // e.g. `HTTP/1.1 200 OK`
+    let status_line = get.await.unwrap_or_default();
+    let status = status_line.split_whitespace().nth(1);
+    matches!(status.and_then(|s| s.parse::<u16>().ok()), Some(200..=399))
+}
+
+/// Runs a [`HealthCheck`] for a command
+#[derive(Debug, Clone)]
+pub(crate) struct HealthChecker {
+    check: HealthCheck,
+    cwd: Option<String>,
+    envs: Option<HashMap<String, String>>,
+}
+
+impl HealthChecker {
+    pub fn new(
+        check: HealthCheck,
+        cwd: Option<String>,
+        envs: Option<HashMap<String, String>>,
+    ) -> Self {
+        Self { check, cwd, envs }
+    }
+
+    async fn check(&self) -> bool {
+        match (&self.check.url, &self.check.command) {
+            (Some(url), _) => tokio::time::timeout(self.check.timeout, http_get_succeeds(url))
+                .await
+                .unwrap_or(false),
+            (None, Some(script)) => {
+                let command = check_command(script, &self.cwd, self.envs.as_ref());
+                command_succeeds(command, self.check.timeout).await
+            }
+            (None, None) => true,
+        }
+    }
+
+    /// Checks the command each interval, once `ready` if given, until `retries` checks
+    /// in a row failed. Then `on_unhealthy` is called with the number of failed checks.
+    ///
+    /// Returns then if the command should be restarted, otherwise keeps checking and calls
+    /// `on_unhealthy` again once the command was healthy in between.
+    pub async fn wait_unhealthy(
+        &self,
+        ready: Option<&ReadySignal>,
+        on_unhealthy: impl Fn(u32),
+    ) -> u32 {
+        if let Some(ready) = ready {
+            ready.wait().await;
+        }
+
+        let mut failures = 0;
+        loop {
+            tokio::time::sleep(self.check.interval).await;
+            if self.check().await {
+                failures = 0;
+                continue;
+            }
+
+            failures += 1;
+            if failures == self.check.retries {
+                on_unhealthy(failures);
+                if self.check.restart {
+                    return failures;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{http_get_succeeds, parse_http_url};
+
+    #[test]
+    fn parse_http_urls() {
+        assert_eq!(
+            parse_http_url("http://localhost:3000/health?full=1"),
+            Some(("localhost", 3000, "/health?full=1"))
+        );
+        assert_eq!(
+            parse_http_url("http://example.com"),
+            Some(("example.com", 80, "/"))
+        );
+        assert_eq!(parse_http_url("http://localhost:web/"), None);
+        assert_eq!(parse_http_url("https://example.com/"), None);
+    }
+
+    #[tokio::test]
+    async fn http_checks() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/health", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for status in ["200 OK", "503 Service Unavailable"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0; 1024];
+                let n = stream.read(&mut request).await.unwrap();
+                assert!(request[..n].starts_with(b"GET /health HTTP/1.1\r\n"));
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        assert!(http_get_succeeds(&url).await);
+        assert!(!http_get_succeeds(&url).await);
+        // nothing listens anymore
+        assert!(!http_get_succeeds(&url).await);
+    }
+}
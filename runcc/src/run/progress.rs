@@ -0,0 +1,73 @@
+use regex::Regex;
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+};
+
+/// stored while no line reported progress yet
+const NO_PROGRESS: u8 = u8::MAX;
+
+/// Tracks the progress a command reports in its output lines,
+/// see [`CommandConfig::progress_from`](crate::CommandConfig::progress_from)
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pattern: Regex,
+    percent: Arc<AtomicU8>,
+}
+
+impl Progress {
+    pub fn new(pattern: Regex) -> Self {
+        Self {
+            pattern,
+            percent: Arc::new(AtomicU8::new(NO_PROGRESS)),
+        }
+    }
+
+    /// Updates the progress if `line` reports it, as a percentage with one group of the
+    /// pattern or as done and total with two. Returns the new percentage then.
+    pub fn check_line(&self, line: &str) -> Option<u8> {
+        let captures = self.pattern.captures(line)?;
+        let number = |i| captures.get(i)?.as_str().parse::<f64>().ok();
+        let percent = match (number(1)?, number(2)) {
+            (done, Some(total)) if total > 0.0 => done / total * 100.0,
+            (_, Some(_)) => return None,
+            (percent, None) => percent,
+        };
+        let percent = percent.clamp(0.0, 100.0).round() as u8;
+        self.percent.store(percent, Ordering::Relaxed);
+        Some(percent)
+    }
+
+    /// The last reported percentage, `None` until a line reported progress
+    pub fn percent(&self) -> Option<u8> {
+        match self.percent.load(Ordering::Relaxed) {
+            NO_PROGRESS => None,
+            percent => Some(percent),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::Progress;
+
+    #[test]
+    fn track_progress() {
+        let percent = Progress::new(Regex::new(r"(\d+(?:\.\d+)?)%").unwrap());
+        assert_eq!(percent.percent(), None);
+        assert_eq!(percent.check_line("compiling"), None);
+        assert_eq!(percent.check_line("downloading 12.6%"), Some(13));
+        assert_eq!(percent.percent(), Some(13));
+        assert_eq!(percent.check_line("done 140%"), Some(100));
+
+        let steps = Progress::new(Regex::new(r"\[(\d+)/(\d+)\]").unwrap());
+        assert_eq!(steps.check_line("[3/12] CC main.o"), Some(25));
+        assert_eq!(steps.check_line("[0/0] nothing to do"), None);
+        assert_eq!(steps.percent(), Some(25));
+        // clones track the same command
+        assert_eq!(steps.clone().check_line("[12/12] LINK app"), Some(100));
+        assert_eq!(steps.percent(), Some(100));
+    }
+}
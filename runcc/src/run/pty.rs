@@ -0,0 +1,230 @@
+//! Pseudo-terminals for the output of [`pty`](crate::CommandConfig::pty) commands,
+//! so that they print colors and progress like on a terminal.
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    os::unix::{
+        io::{AsRawFd, FromRawFd, OwnedFd},
+        net::UnixStream,
+    },
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+use tokio::process::{ChildStderr, ChildStdout};
+
+/// The terminals of running commands with the pids of the commands,
+/// resized with the terminal of runcc, see [`Window`]
+static WINDOWS: Mutex<Vec<(Weak<File>, u32)>> = Mutex::new(Vec::new());
+
+/// Whether a task resizes the [`WINDOWS`] when runcc gets `SIGWINCH`
+static RESIZING: AtomicBool = AtomicBool::new(false);
+
+/// The window size of the terminal of runcc, 80x24 if it has none
+pub(crate) fn window_size() -> libc::winsize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let has_size = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    if !has_size || size.ws_col == 0 {
+        size.ws_col = 80;
+        size.ws_row = 24;
+    }
+    size
+}
+
+/// Sets the window size of the terminals of all running commands to `size`
+/// and sends them `SIGWINCH`, like a terminal does when it is resized.
+///
+/// The commands don't have their terminal as controlling terminal, so it doesn't signal them.
+pub(crate) fn resize_windows(size: libc::winsize) {
+    let mut windows = WINDOWS.lock().unwrap();
+    windows.retain(|(main, pid)| match main.upgrade() {
+        Some(main) => {
+            unsafe {
+                libc::ioctl(main.as_raw_fd(), libc::TIOCSWINSZ, &size);
+                libc::kill(*pid as libc::pid_t, libc::SIGWINCH);
+            }
+            true
+        }
+        None => false,
+    });
+}
+
+/// Resizes the terminals of commands to the terminal of runcc each time runcc gets
+/// `SIGWINCH`, unless a task of a running tokio runtime already does
+fn resize_on_window_change() {
+    if RESIZING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    /// Lets the next terminal start resizing again once the runtime dropped the task
+    struct Resizing;
+
+    impl Drop for Resizing {
+        fn drop(&mut self) {
+            RESIZING.store(false, Ordering::SeqCst);
+        }
+    }
+
+    let resizing = Resizing;
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let _resizing = resizing;
+        let mut sigwinch = match signal(SignalKind::window_change()) {
+            Ok(sigwinch) => sigwinch,
+            Err(err) => {
+                eprintln!(
+                    "[runcc][warning] failed to setup SIGWINCH signal handler: {}",
+                    err
+                );
+                return;
+            }
+        };
+        while sigwinch.recv().await.is_some() {
+            resize_windows(window_size());
+        }
+    });
+}
+
+/// The terminal of a command in [`WINDOWS`] until it is dropped, which must happen once the
+/// command exited, since its pid may belong to another process then
+pub(crate) struct Window(Weak<File>);
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        let mut windows = WINDOWS.lock().unwrap();
+        windows.retain(|(main, _)| !main.ptr_eq(&self.0));
+    }
+}
+
+fn close_on_exec(file: &File) -> io::Result<()> {
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A pseudo-terminal, the secondary side is the stdout and stderr of a command
+pub(crate) struct Pty {
+    main: Arc<File>,
+    secondary: File,
+}
+
+impl Pty {
+    pub fn open() -> io::Result<Self> {
+        let (mut main, mut secondary) = (0, 0);
+        let mut size = window_size();
+        let opened = unsafe {
+            libc::openpty(
+                &mut main,
+                &mut secondary,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                // `*mut` on macOS
+                &mut size as *mut _,
+            )
+        };
+        if opened != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (main, secondary) = unsafe { (File::from_raw_fd(main), File::from_raw_fd(secondary)) };
+        // other commands must not keep it open, its output only ends once it is closed
+        close_on_exec(&main)?;
+        close_on_exec(&secondary)?;
+
+        // lines end with `\n` like in pipes, instead of `\r\n`
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(secondary.as_raw_fd(), &mut termios) == 0 {
+                termios.c_oflag &= !libc::ONLCR;
+                libc::tcsetattr(secondary.as_raw_fd(), libc::TCSANOW, &termios);
+            }
+        }
+
+        Ok(Self {
+            main: Arc::new(main),
+            secondary,
+        })
+    }
+
+    /// The secondary side, for the stdout or stderr of the command
+    pub fn stdio(&self) -> io::Result<Stdio> {
+        Ok(self.secondary.try_clone()?.into())
+    }
+
+    /// The output of the command on stdout, its stderr is empty because both go to the
+    /// terminal. The command must have been spawned as `pid`.
+    ///
+    /// A thread copies the output to a socket, because reading the terminal fails once the
+    /// command closed it, instead of ending like pipes. The terminal is resized with the
+    /// terminal of runcc until the returned [`Window`] is dropped.
+    pub fn output(
+        self,
+        pid: Option<u32>,
+    ) -> io::Result<(ChildStdout, ChildStderr, Option<Window>)> {
+        let Self { main, secondary } = self;
+        drop(secondary);
+        let window = pid.map(|pid| {
+            let mut windows = WINDOWS.lock().unwrap();
+            windows.retain(|(main, _)| main.strong_count() > 0);
+            windows.push((Arc::downgrade(&main), pid));
+            Window(Arc::downgrade(&main))
+        });
+        if window.is_some() {
+            resize_on_window_change();
+        }
+
+        let (stdout, mut writer) = UnixStream::pair()?;
+        let (stderr, _) = UnixStream::pair()?;
+        std::thread::spawn(move || {
+            let mut buf = [0; 8192];
+            loop {
+                match (&*main).read(&mut buf) {
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if writer.write_all(&buf[..n]).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        let stdout = std::process::ChildStdout::from(OwnedFd::from(stdout));
+        let stderr = std::process::ChildStderr::from(OwnedFd::from(stderr));
+        Ok((
+            ChildStdout::from_std(stdout)?,
+            ChildStderr::from_std(stderr)?,
+            window,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Pty, WINDOWS};
+
+    #[tokio::test]
+    async fn forget_window_once_dropped() {
+        let registered = |pid| WINDOWS.lock().unwrap().iter().any(|(_, p)| *p == pid);
+
+        let pty = Pty::open().unwrap();
+        let mut child = tokio::process::Command::new("sleep")
+            .arg("10")
+            .stdout(pty.stdio().unwrap())
+            .spawn()
+            .unwrap();
+        let pid = child.id().unwrap();
+        let (_stdout, _stderr, window) = pty.output(Some(pid)).unwrap();
+        assert!(registered(pid));
+
+        child.kill().await.unwrap();
+        drop(window);
+        assert!(!registered(pid));
+    }
+}
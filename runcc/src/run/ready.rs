@@ -0,0 +1,61 @@
+use regex::Regex;
+use std::{sync::Arc, time::Instant};
+use tokio::sync::watch;
+
+/// Marks a command as ready when one of its output lines matches a pattern.
+///
+/// Plugins reading the output of commands should pass every line to
+/// [`check_line`](Self::check_line).
+#[derive(Debug, Clone)]
+pub struct ReadySignal {
+    /// `None` if only a startup probe makes the command ready
+    pattern: Option<Regex>,
+    /// when the command became ready
+    ready: Arc<watch::Sender<Option<Instant>>>,
+}
+
+impl ReadySignal {
+    pub fn new(pattern: Regex) -> Self {
+        Self::with_pattern(Some(pattern))
+    }
+
+    pub(crate) fn with_pattern(pattern: Option<Regex>) -> Self {
+        Self {
+            pattern,
+            ready: Arc::new(watch::channel(None).0),
+        }
+    }
+
+    pub fn check_line(&self, line: &str) {
+        if !self.is_ready() && self.pattern.as_ref().is_some_and(|p| p.is_match(line)) {
+            self.set_ready();
+        }
+    }
+
+    pub(crate) fn set_ready(&self) {
+        self.ready.send_replace(Some(Instant::now()));
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready_at().is_some()
+    }
+
+    /// When the current run of the command became ready
+    pub fn ready_at(&self) -> Option<Instant> {
+        *self.ready.borrow()
+    }
+
+    /// Called before the command is spawned again
+    pub(crate) fn reset(&self) {
+        self.ready.send_replace(None);
+    }
+
+    pub(crate) async fn wait(&self) {
+        let mut rx = self.ready.subscribe();
+        while rx.borrow().is_none() {
+            if rx.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! Applies a changed config to a running [`CommandSystem`](super::CommandSystem),
+//! see [`CommandSystem::reloader`](super::CommandSystem::reloader).
+
+use std::{collections::HashMap, fmt, mem};
+
+use crate::{CommandConfig, RunConfig};
+
+use super::{
+    kill::KillResult, script::ScriptFile, CommandHandle, CommandSpawner, CommandSystemPlugin,
+    CommandSystemStopped, LabeledCommandData,
+};
+
+/// How the commands of a reloaded config differ from the previous ones, by label
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ConfigChanges {
+    /// New commands, which are spawned
+    pub started: Vec<String>,
+    /// Removed commands, which are killed
+    pub stopped: Vec<String>,
+    /// Changed commands, which are killed and spawned again with their new options
+    pub restarted: Vec<String>,
+}
+
+impl ConfigChanges {
+    /// Compares the commands of `old` and `new` by label.
+    ///
+    /// A command changed if any of its options changed,
+    /// or the [`envs`](RunConfig::envs) of all commands.
+    pub fn between(old: &RunConfig, new: &RunConfig) -> Self {
+        fn find<'a>(config: &'a RunConfig, label: &str) -> Option<&'a CommandConfig> {
+            let mut commands = config.commands.iter();
+            commands.find(|cmd| cmd.resolved_label() == label)
+        }
+        let envs_changed = old.envs != new.envs;
+
+        let mut changes = Self::default();
+        for cmd in &new.commands {
+            let label = cmd.resolved_label();
+            match find(old, &label) {
+                None => changes.started.push(label),
+                Some(old_cmd) if envs_changed || !same_options(old_cmd, cmd) => {
+                    changes.restarted.push(label)
+                }
+                Some(_) => {}
+            }
+        }
+        changes.stopped = old
+            .commands
+            .iter()
+            .map(CommandConfig::resolved_label)
+            .filter(|label| find(new, label).is_none())
+            .collect();
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.started.is_empty() && self.stopped.is_empty() && self.restarted.is_empty()
+    }
+}
+
+impl fmt::Display for ConfigChanges {
+    /// e.g. `started web, api; restarted db`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no commands changed");
+        }
+        let changes = [
+            ("started", &self.started),
+            ("stopped", &self.stopped),
+            ("restarted", &self.restarted),
+        ];
+        let changes: Vec<_> = changes
+            .iter()
+            .filter(|(_, labels)| !labels.is_empty())
+            .map(|(change, labels)| format!("{} {}", change, labels.join(", ")))
+            .collect();
+        write!(f, "{}", changes.join("; "))
+    }
+}
+
+/// Whether `a` and `b` have the same options
+fn same_options(a: &CommandConfig, b: &CommandConfig) -> bool {
+    match (serde_json::to_value(a), serde_json::to_value(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Applies a changed config to the commands of a running system, e.g. once its
+/// config file changed
+pub struct Reloader<T, P> {
+    spawner: CommandSpawner<T, P>,
+    /// The config the commands run with
+    config: RunConfig,
+    /// The commands of `config`, in its order
+    commands: Vec<CommandHandle<T>>,
+    /// Files of scripts of the spawned commands, see
+    /// [`CommandConfig::script`](crate::CommandConfig::script)
+    scripts: Vec<ScriptFile>,
+}
+
+impl<T, P> Reloader<T, P>
+where
+    T: Send + Sync + 'static,
+    P: CommandSystemPlugin<T, CommandInitialData = LabeledCommandData>,
+{
+    pub(super) fn new(
+        spawner: CommandSpawner<T, P>,
+        config: RunConfig,
+        commands: Vec<CommandHandle<T>>,
+    ) -> Self {
+        Self {
+            spawner,
+            config,
+            commands,
+            scripts: Vec::new(),
+        }
+    }
+
+    /// Spawns the commands which are new in `config` and kills those which were removed.
+    /// The commands which changed are killed and spawned with their new options once they
+    /// stopped, also those which had stopped already.
+    ///
+    /// Only the commands change, the options for the whole run, like
+    /// [`kill`](RunConfig::kill), stay as they were. Spawned commands don't wait for their
+    /// prerequisites, like [`after`](CommandConfig::after), since these run already.
+    ///
+    /// Fails once the system stopped, the commands changed so far keep their new options then.
+    pub fn reload(&mut self, config: RunConfig) -> Result<ConfigChanges, CommandSystemStopped> {
+        let changes = ConfigChanges::between(&self.config, &config);
+        let old = mem::replace(&mut self.config, config);
+
+        let mut running: HashMap<_, _> = old
+            .commands
+            .iter()
+            .map(CommandConfig::resolved_label)
+            .zip(mem::take(&mut self.commands))
+            .collect();
+
+        for (index, cmd) in self.config.commands.iter().enumerate() {
+            let label = cmd.resolved_label();
+            let handle = match running.remove(&label) {
+                Some(handle) if !changes.restarted.contains(&label) => handle,
+                replaced => {
+                    // added before the old command is killed,
+                    // so that the system doesn't stop once it exited
+                    let (handle, script) = self.spawner.spawn_config(
+                        cmd.clone(),
+                        index,
+                        &self.config,
+                        replaced.clone(),
+                    )?;
+                    self.scripts.extend(script);
+                    if let Some(replaced) = replaced {
+                        replaced.kill();
+                    }
+                    handle
+                }
+            };
+            self.commands.push(handle);
+        }
+
+        for handle in running.values() {
+            handle.kill();
+        }
+
+        Ok(changes)
+    }
+
+    /// The label and the handle of each command of the current config, in its order
+    pub fn commands(&self) -> Vec<(String, CommandHandle<T>)> {
+        let labels = self
+            .config
+            .commands
+            .iter()
+            .map(CommandConfig::resolved_label);
+        labels.zip(self.commands.iter().cloned()).collect()
+    }
+
+    /// Restarts the command with `label` like [`CommandHandle::restart`], but also spawns it
+    /// again if it stopped for good, e.g. because it failed and isn't restarted on failure.
+    ///
+    /// `None` if no command of the config has `label`.
+    pub fn restart(&mut self, label: &str) -> Option<Result<KillResult, CommandSystemStopped>> {
+        let mut commands = self.config.commands.iter();
+        let index = commands.position(|cmd| cmd.resolved_label() == label)?;
+        let handle = &self.commands[index];
+        let restarted = match handle.restart() {
+            KillResult::AlreadyExited if handle.has_stopped() => {
+                let cmd = self.config.commands[index].clone();
+                let spawned =
+                    self.spawner
+                        .spawn_config(cmd, index, &self.config, Some(handle.clone()));
+                spawned.map(|(handle, script)| {
+                    self.scripts.extend(script);
+                    self.commands[index] = handle;
+                    KillResult::SentSuccess
+                })
+            }
+            result => Ok(result),
+        };
+        Some(restarted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConfigChanges;
+    use crate::{RunConfig, RunConfigInput};
+
+    fn config(yaml: &str) -> RunConfig {
+        let input: RunConfigInput = serde_yaml::from_str(yaml).unwrap();
+        input.into()
+    }
+
+    #[test]
+    fn config_changes_between_configs() {
+        let old = config(
+            r#"
+commands:
+  web: npm start
+  api: cargo run
+  db: postgres
+"#,
+        );
+        let new = config(
+            r#"
+commands:
+  web: npm start
+  api: cargo run --release
+  worker: cargo run --bin worker
+"#,
+        );
+
+        let changes = ConfigChanges::between(&old, &new);
+        assert_eq!(changes.started, ["worker"]);
+        assert_eq!(changes.stopped, ["db"]);
+        assert_eq!(changes.restarted, ["api"]);
+        assert_eq!(
+            changes.to_string(),
+            "started worker; stopped db; restarted api"
+        );
+
+        assert!(ConfigChanges::between(&new, &new).is_empty());
+
+        let mut with_envs = new.clone();
+        with_envs.envs = Some(std::iter::once(("PORT".to_string(), "80".to_string())).collect());
+        let mut restarted = ConfigChanges::between(&new, &with_envs).restarted;
+        restarted.sort();
+        assert_eq!(restarted, ["api", "web", "worker"]);
+    }
+}
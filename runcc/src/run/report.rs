@@ -1,12 +1,144 @@
+use std::fmt::Display;
+
+use crate::ExitCodePolicy;
+
 #[derive(Debug, Clone, Copy)]
 #[non_exhaustive]
 pub struct CommandSystemSimpleReport {
     pub command_count_total: usize,
     pub command_count_success: usize,
+    /// commands which runcc killed, they don't count as failed
+    pub command_count_killed: usize,
+    /// exit code of the command which failed first,
+    /// `None` if none failed or it had no code, e.g. because a signal terminated it
+    pub first_failed_code: Option<i32>,
+    /// exit code of the command which stopped on its own last, i.e. wasn't killed,
+    /// `None` if none did or it had no code
+    pub last_exit_code: Option<i32>,
+    /// whether the command which stopped on its own last failed
+    pub last_failed: bool,
+    /// Which code [`exit_code`](Self::exit_code) returns, the default unless set,
+    /// e.g. by [`cli::run`](crate::cli::run) from [`RunConfig::exit_code`](crate::RunConfig::exit_code)
+    pub exit_code_policy: ExitCodePolicy,
 }
 
 impl CommandSystemSimpleReport {
     pub fn command_count_failed(&self) -> usize {
-        self.command_count_total - self.command_count_success
+        self.command_count_total - self.command_count_success - self.command_count_killed
+    }
+
+    /// The code for runcc to exit with, 0 if no command failed.
+    ///
+    /// A failed command without a code, e.g. because a signal terminated it,
+    /// or with a code of 0, which would hide the failure, results in 2.
+    pub fn exit_code(&self) -> i32 {
+        if self.command_count_failed() == 0 {
+            return 0;
+        }
+        let failed_code = |code: Option<i32>| code.filter(|code| *code != 0).unwrap_or(2);
+        match self.exit_code_policy {
+            ExitCodePolicy::FirstFailure => failed_code(self.first_failed_code),
+            ExitCodePolicy::Last if self.last_failed => failed_code(self.last_exit_code),
+            ExitCodePolicy::Last => 0,
+            ExitCodePolicy::SuccessOnly => 1,
+        }
+    }
+}
+
+/// Counters of a running [`CommandSystem`](super::CommandSystem),
+/// see [`CommandSystem::stats`](super::CommandSystem::stats)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RunStats {
+    pub command_count_total: usize,
+    /// processes which are running right now
+    pub command_count_running: usize,
+    /// commands which stopped for good successfully
+    pub command_count_success: usize,
+    /// commands which stopped for good without success on their own, including skipped ones
+    pub command_count_failed: usize,
+    /// commands which runcc killed for good
+    pub command_count_killed: usize,
+    /// how often commands were spawned again, e.g. by retries or restarts
+    pub restart_count: usize,
+}
+
+impl Display for CommandSystemSimpleReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let failed = self.command_count_failed();
+        let killed = self.command_count_killed;
+        if failed == 0 && killed == 0 {
+            return write!(f, "all {} commands succeeded", self.command_count_total);
+        }
+
+        if failed == 0 {
+            write!(
+                f,
+                "{} of {} commands succeeded",
+                self.command_count_success, self.command_count_total
+            )?;
+        } else {
+            write!(
+                f,
+                "{} of {} commands failed",
+                failed, self.command_count_total
+            )?;
+        }
+        if killed > 0 {
+            write!(f, ", {} killed", killed)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CommandSystemSimpleReport;
+    use crate::ExitCodePolicy;
+
+    #[test]
+    fn display_summary() {
+        let report = |total, success, killed| {
+            CommandSystemSimpleReport {
+                command_count_total: total,
+                command_count_success: success,
+                command_count_killed: killed,
+                first_failed_code: None,
+                last_exit_code: None,
+                last_failed: false,
+                exit_code_policy: Default::default(),
+            }
+            .to_string()
+        };
+
+        assert_eq!(report(3, 3, 0), "all 3 commands succeeded");
+        assert_eq!(report(3, 1, 0), "2 of 3 commands failed");
+        assert_eq!(report(3, 2, 1), "2 of 3 commands succeeded, 1 killed");
+        assert_eq!(report(3, 1, 1), "1 of 3 commands failed, 1 killed");
+    }
+
+    #[test]
+    fn exit_code_by_policy() {
+        // the first command failed with 3, the last one succeeded
+        let mut report = CommandSystemSimpleReport {
+            command_count_total: 3,
+            command_count_success: 1,
+            command_count_killed: 1,
+            first_failed_code: Some(3),
+            last_exit_code: Some(0),
+            last_failed: false,
+            exit_code_policy: ExitCodePolicy::FirstFailure,
+        };
+        assert_eq!(report.exit_code(), 3);
+        report.exit_code_policy = ExitCodePolicy::Last;
+        assert_eq!(report.exit_code(), 0);
+        report.exit_code_policy = ExitCodePolicy::SuccessOnly;
+        assert_eq!(report.exit_code(), 1);
+
+        // the last one failed by a signal
+        report.last_exit_code = None;
+        report.last_failed = true;
+        report.exit_code_policy = ExitCodePolicy::Last;
+        assert_eq!(report.exit_code(), 2);
     }
 }
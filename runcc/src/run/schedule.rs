@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{CommandConfig, CronSchedule};
+
+/// When the runs of a command are due, see [`CommandConfig::schedule`] and
+/// [`CommandConfig::every`]
+#[derive(Debug, Clone)]
+pub(crate) enum Schedule {
+    Every(Duration),
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    /// The schedule of `cmd`, `None` if it runs only once
+    pub fn of(cmd: &CommandConfig) -> Option<Self> {
+        match (&cmd.schedule, cmd.every) {
+            (Some(schedule), _) => Some(Self::Cron(
+                schedule
+                    .parse()
+                    .expect("schedule should be a valid cron expression"),
+            )),
+            (None, Some(every)) => Some(Self::Every(every)),
+            (None, None) => None,
+        }
+    }
+
+    /// Whether the first run waits for the schedule instead of being spawned right away
+    pub fn waits_for_first_run(&self) -> bool {
+        matches!(self, Self::Cron(_))
+    }
+
+    /// How long until the next run is due, given when the first run was spawned
+    pub fn until_next_run(&self, first_run: Option<Instant>) -> Duration {
+        match (self, first_run) {
+            (Self::Every(_), None) => Duration::ZERO,
+            (Self::Every(every), Some(first_run)) => {
+                let elapsed = first_run.elapsed();
+                let runs = (elapsed.as_nanos() / every.as_nanos()) as u32 + 1;
+                (first_run + *every * runs).saturating_duration_since(Instant::now())
+            }
+            (Self::Cron(cron), _) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                let now_millis = now.as_millis() as i64 + local_utc_offset(now.as_secs()) * 1000;
+                match cron.next_after(now_millis.div_euclid(1000)) {
+                    Some(next) => Duration::from_millis((next * 1000 - now_millis).max(0) as u64),
+                    // validated to match within 28 years, checked again in a year otherwise
+                    None => Duration::from_secs(365 * 24 * 60 * 60),
+                }
+            }
+        }
+    }
+}
+
+/// Seconds the local time zone is ahead of UTC at `secs` since 1970
+#[cfg(unix)]
+fn local_utc_offset(secs: u64) -> i64 {
+    let time = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+/// Seconds the local time zone is ahead of UTC, schedules are in UTC on this platform
+#[cfg(not(unix))]
+fn local_utc_offset(_secs: u64) -> i64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Schedule;
+
+    #[test]
+    fn runs_every_interval_from_the_first_run() {
+        let every = Schedule::Every(Duration::from_secs(10));
+        assert_eq!(every.until_next_run(None), Duration::ZERO);
+
+        let first_run = Instant::now() - Duration::from_secs(25);
+        let until_next = every.until_next_run(Some(first_run));
+        assert!(until_next <= Duration::from_secs(5), "{:?}", until_next);
+        assert!(until_next > Duration::from_secs(4), "{:?}", until_next);
+
+        let cron = Schedule::Cron("* * * * *".parse().unwrap());
+        assert!(cron.until_next_run(None) <= Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,95 @@
+//! Runs the [`script`](crate::CommandConfig::script) of a command from a temporary file.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Numbers the script files of this process
+static NEXT_SCRIPT: AtomicUsize = AtomicUsize::new(0);
+
+/// A script written to a temporary file, which is removed on drop
+pub(crate) struct ScriptFile {
+    path: PathBuf,
+    shebang: bool,
+}
+
+impl ScriptFile {
+    pub(crate) fn create(script: &str) -> io::Result<Self> {
+        let extension = if cfg!(windows) { "cmd" } else { "sh" };
+        loop {
+            let name = format!(
+                "runcc-{}-{}.{}",
+                std::process::id(),
+                NEXT_SCRIPT.fetch_add(1, Ordering::Relaxed),
+                extension
+            );
+            let path = std::env::temp_dir().join(name);
+
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o700);
+            }
+            // a file of a previous run with the same process id may be left over
+            let mut file = match options.open(&path) {
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                file => file?,
+            };
+
+            let script_file = Self {
+                path,
+                shebang: cfg!(unix) && script.starts_with("#!"),
+            };
+            file.write_all(script.as_bytes())?;
+            return Ok(script_file);
+        }
+    }
+
+    /// Program and args running the script,
+    /// the script itself if it starts with a shebang and the shell otherwise
+    pub(crate) fn program_and_args(&self) -> (String, Vec<String>) {
+        let path = self.path.display().to_string();
+        if self.shebang {
+            (path, vec![])
+        } else if cfg!(windows) {
+            ("cmd".to_string(), vec!["/C".to_string(), path])
+        } else {
+            ("sh".to_string(), vec![path])
+        }
+    }
+}
+
+impl Drop for ScriptFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScriptFile;
+
+    #[cfg(unix)]
+    #[test]
+    fn run_script_file() {
+        let run = |script| {
+            let file = ScriptFile::create(script).unwrap();
+            let (program, args) = file.program_and_args();
+            let output = std::process::Command::new(program)
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(file.path.exists());
+            let path = file.path.clone();
+            drop(file);
+            assert!(!path.exists());
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        assert_eq!(run("echo one\necho two\n"), "one\ntwo\n");
+        assert_eq!(run("#!/bin/sh -e\necho one\nfalse\necho two\n"), "one\n");
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    process::{ChildStderr, ChildStdout, Command},
+    task::JoinHandle,
+};
+
+use super::{
+    CommandStopped, CommandSystemPlugin, LabeledCommandData, OutputBroadcast, OutputStream,
+    RunStats,
+};
+
+/// A plugin which watches a system next to the plugin reading the output of its commands,
+/// e.g. to collect metrics or forward lines, see [`PluginStack`]
+pub trait CommandSystemObserver<T>: Send + Sync + 'static {
+    fn on_system_started(&self) {}
+
+    /// See [`CommandSystemPlugin::on_command_started`]
+    fn on_command_started(&self, _data: &T, _pid: Option<u32>) {}
+
+    /// Called with each line a command prints, as it printed it, before the plugin
+    /// masks or relabels it. The last lines of a run may come after
+    /// [`on_command_exited`](Self::on_command_exited).
+    fn on_output_line(&self, _label: &str, _stream: OutputStream, _line: &str) {}
+
+    fn on_command_exited(&self, _cmd: &Arc<CommandStopped<T, T>>) {}
+
+    /// See [`CommandSystemPlugin::on_restarts_paused`]
+    fn on_restarts_paused(&self, _data: &LabeledCommandData, _cooldown: Duration) {}
+
+    /// See [`CommandSystemPlugin::on_early_exit`]
+    fn on_early_exit(
+        &self,
+        _data: &LabeledCommandData,
+        _attempt: u32,
+        _attempts: u32,
+        _delay: Duration,
+    ) {
+    }
+
+    /// See [`CommandSystemPlugin::on_probe_failed`]
+    fn on_probe_failed(&self, _data: &LabeledCommandData, _attempt: u32, _attempts: u32) {}
+
+    /// See [`CommandSystemPlugin::on_unhealthy`]
+    fn on_unhealthy(&self, _data: &LabeledCommandData, _failures: u32) {}
+}
+
+/// Runs several plugins at once: `plugin` reads the stdout and stderr of the commands,
+/// and each observer gets their lines and the commands starting and exiting.
+///
+/// The stack splits the output of each command into lines once, on its way to `plugin`,
+/// and gives each line to all observers.
+///
+/// Hooks which decide something, [`should_kill_all`](CommandSystemPlugin::should_kill_all)
+/// and [`rewrite_command`](CommandSystemPlugin::rewrite_command), and the spawner of the
+/// system only go to `plugin`.
+pub struct PluginStack<T, P> {
+    plugin: P,
+    observers: Vec<Arc<dyn CommandSystemObserver<T>>>,
+    /// Copy the output of commands to `plugin`
+    joins: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl<T, P> PluginStack<T, P> {
+    pub fn new(plugin: P) -> Self {
+        Self {
+            plugin,
+            observers: vec![],
+            joins: Default::default(),
+        }
+    }
+
+    /// Adds `observer`, called after `plugin` and the observers added before it
+    pub fn with(mut self, observer: impl CommandSystemObserver<T>) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    pub fn plugin(&self) -> &P {
+        &self.plugin
+    }
+}
+
+impl<T, P> CommandSystemPlugin<T> for PluginStack<T, P>
+where
+    T: 'static,
+    P: CommandSystemPlugin<T, CommandInitialData = LabeledCommandData>,
+{
+    type CommandInitialData = LabeledCommandData;
+
+    fn rewrite_command(&self, data: &Self::CommandInitialData, command: &mut Command) {
+        self.plugin.rewrite_command(data, command)
+    }
+
+    fn on_system_started(&self) {
+        self.plugin.on_system_started();
+        for observer in &self.observers {
+            observer.on_system_started();
+        }
+    }
+
+    fn initialize_spawn_failed_command_data(&self, data: Self::CommandInitialData) -> T {
+        self.plugin.initialize_spawn_failed_command_data(data)
+    }
+
+    fn initialize_command_data(
+        &self,
+        data: Self::CommandInitialData,
+        stdout: ChildStdout,
+        stderr: ChildStderr,
+    ) -> T {
+        let (stdout, stderr) = match self.observers.is_empty() {
+            true => (stdout, stderr),
+            false => {
+                let label = data.label.label().to_string();
+                let observers = self.observers.clone();
+                let (stdout, stderr, joins) =
+                    super::tee::tee(stdout, stderr, move |stream, line| {
+                        for observer in &observers {
+                            observer.on_output_line(&label, stream, line);
+                        }
+                    });
+                self.joins.lock().unwrap().extend(joins);
+                (stdout, stderr)
+            }
+        };
+        self.plugin.initialize_command_data(data, stdout, stderr)
+    }
+
+    fn on_command_started(&self, data: &T, pid: Option<u32>) {
+        self.plugin.on_command_started(data, pid);
+        for observer in &self.observers {
+            observer.on_command_started(data, pid);
+        }
+    }
+
+    fn on_output_line(&self, label: &str, stream: OutputStream, line: &str) {
+        // the observers got the line from the stack already
+        self.plugin.on_output_line(label, stream, line);
+    }
+
+    fn on_command_exited(&self, cmd: Arc<CommandStopped<T, T>>) {
+        self.plugin.on_command_exited(cmd.clone());
+        for observer in &self.observers {
+            observer.on_command_exited(&cmd);
+        }
+    }
+
+    fn on_restarts_paused(&self, data: &Self::CommandInitialData, cooldown: Duration) {
+        self.plugin.on_restarts_paused(data, cooldown);
+        for observer in &self.observers {
+            observer.on_restarts_paused(data, cooldown);
+        }
+    }
+
+    fn on_early_exit(
+        &self,
+        data: &Self::CommandInitialData,
+        attempt: u32,
+        attempts: u32,
+        delay: Duration,
+    ) {
+        self.plugin.on_early_exit(data, attempt, attempts, delay);
+        for observer in &self.observers {
+            observer.on_early_exit(data, attempt, attempts, delay);
+        }
+    }
+
+    fn on_probe_failed(&self, data: &Self::CommandInitialData, attempt: u32, attempts: u32) {
+        self.plugin.on_probe_failed(data, attempt, attempts);
+        for observer in &self.observers {
+            observer.on_probe_failed(data, attempt, attempts);
+        }
+    }
+
+    fn on_unhealthy(&self, data: &Self::CommandInitialData, failures: u32) {
+        self.plugin.on_unhealthy(data, failures);
+        for observer in &self.observers {
+            observer.on_unhealthy(data, failures);
+        }
+    }
+
+    fn should_kill_all(
+        &self,
+        exited: &CommandStopped<T, T>,
+        stats: &RunStats,
+        by_kill_behavior: bool,
+    ) -> bool {
+        self.plugin.should_kill_all(exited, stats, by_kill_behavior)
+    }
+
+    fn join(&self) -> Option<JoinHandle<()>> {
+        let plugin = self.plugin.join();
+        let joins: Vec<_> = self.joins.lock().unwrap().drain(..).collect();
+        Some(tokio::spawn(async move {
+            for join in joins {
+                let _ = join.await;
+            }
+            if let Some(plugin) = plugin {
+                let _ = plugin.await;
+            }
+        }))
+    }
+
+    fn output_broadcast(&self) -> Option<&OutputBroadcast> {
+        self.plugin.output_broadcast()
+    }
+}
@@ -0,0 +1,113 @@
+use std::{collections::HashMap, fmt::Display};
+
+use super::system::run_script_from_file;
+use crate::CommandConfig;
+
+/// A step of [`RunConfig::before`](crate::RunConfig::before) or
+/// [`RunConfig::after`](crate::RunConfig::after) which didn't succeed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepFailed {
+    pub label: String,
+    pub reason: String,
+}
+
+impl Display for StepFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "step `{}` {}", self.label, self.reason)
+    }
+}
+
+impl std::error::Error for StepFailed {}
+
+/// Runs `steps` one after another until one of them fails, with the output and input of
+/// runcc. Each step is killed once it runs longer than its
+/// [`timeout`](CommandConfig::timeout).
+///
+/// `envs` are passed to each step like the [`RunConfig::envs`](crate::RunConfig::envs) to
+/// commands.
+pub async fn run_steps(
+    steps: &[CommandConfig],
+    envs: Option<&HashMap<String, String>>,
+) -> Result<(), StepFailed> {
+    for step in steps {
+        let mut step = step.clone();
+        step.resolve_shell();
+        // removed once the step exited
+        let _script = run_script_from_file(&mut step);
+        let timeout = step.timeout;
+        let (mut command, label) = step.into_tokio_command_and_label(envs);
+        command.kill_on_drop(true);
+
+        eprintln!("[runcc][info] running step `{}`", label);
+        let failed = |reason: String| StepFailed {
+            label: label.clone(),
+            reason,
+        };
+        let mut child = command
+            .spawn()
+            .map_err(|err| failed(format!("failed to spawn: {}", err)))?;
+        let status = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, child.wait()).await {
+                Ok(status) => status,
+                Err(_) => return Err(failed(format!("timed out after {:?}", timeout))),
+            },
+            None => child.wait().await,
+        };
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => match status.code() {
+                Some(code) => return Err(failed(format!("exited with code {}", code))),
+                None => return Err(failed("was killed by a signal".to_string())),
+            },
+            Err(err) => return Err(failed(format!("failed to wait for it: {}", err))),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{run_steps, StepFailed};
+    use crate::CommandConfig;
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn steps_run_in_order_until_one_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let step = |script: &str| CommandConfig {
+            script: Some(script.to_string()),
+            cwd: Some(dir.path().to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        let envs: HashMap<_, _> = [("STEP".to_string(), "env".to_string())].into();
+
+        let steps = [
+            step("echo first >> log"),
+            step("echo $STEP >> log"),
+            CommandConfig {
+                label: Some("migrate".to_string()),
+                ..step("exit 3")
+            },
+            step("echo never >> log"),
+        ];
+        assert_eq!(
+            run_steps(&steps, Some(&envs)).await,
+            Err(StepFailed {
+                label: "migrate".to_string(),
+                reason: "exited with code 3".to_string(),
+            })
+        );
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "first\nenv\n");
+
+        let slow = CommandConfig {
+            timeout: Some(std::time::Duration::from_millis(100)),
+            ..step("sleep 10")
+        };
+        let failed = run_steps(&[slow], None).await.unwrap_err();
+        assert_eq!(failed.reason, "timed out after 100ms");
+        assert_eq!(run_steps(&[], None).await, Ok(()));
+    }
+}
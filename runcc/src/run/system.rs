@@ -1,37 +1,304 @@
 use std::{
-    cmp, mem,
-    sync::{Arc, Mutex},
+    cmp,
+    collections::{HashMap, VecDeque},
+    future::{self, Future},
+    io, mem,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError, Weak,
+    },
+    task::Poll,
+    time::{Duration, Instant},
 };
 
+use regex::Regex;
 use tokio::{
     process::{ChildStderr, ChildStdout, Command},
-    sync::{mpsc, Mutex as AsyncMutex},
+    sync::{mpsc, oneshot, watch, Mutex as AsyncMutex, OwnedSemaphorePermit, Semaphore},
     task::JoinHandle,
 };
 
-use crate::{label::Label, KillBehavior, RunConfig};
+use crate::{
+    label::Label, CommandConfig, EarlyExitBackoff, KillBehavior, LineFilter, LineLabel,
+    OutputEncoding, PortWait, RestartPolicy, RestartRateLimit, RunConfig, Signal, SpawnRetries,
+    StartupRetries, StreamRoute, DEFAULT_SHUTDOWN_TIMEOUT,
+};
 
 use super::kill;
 use super::{
-    command::{CommandInitialized, CommandSpawned, CommandStopped},
-    CommandSystemSimpleReport,
+    broadcast::{OutputBroadcast, OutputEvent, OutputStream, OutputSubscription},
+    command::{
+        CommandInitialized, CommandJoinHandle, CommandOutcome, CommandSpawned, CommandStdin,
+        CommandStopped,
+    },
+    events::{CommandEvent, EventSender},
+    hook::HookRunner,
+    probe::{wait_for_port, HealthChecker, ProbeRunner},
+    progress::Progress,
+    ready::ReadySignal,
+    reload::Reloader,
+    schedule::Schedule,
+    script::ScriptFile,
+    watch::DirWatcher,
+    CommandSystemSimpleReport, RunStats,
 };
 
+/// See [`CommandConfig::after_ready_timeout`](crate::CommandConfig::after_ready_timeout)
+const DEFAULT_AFTER_READY_TIMEOUT: Duration = Duration::from_secs(60);
+
 enum CommandState<T> {
     Processing,
     Spawned {
         data: T,
+        pid: Option<u32>,
         killer: kill::CommandKiller<T>,
+        stdin: Option<CommandStdin>,
     },
     Stopped(Arc<CommandStopped<T, T>>),
 }
 
+/// Whether the exit of a command kills the others, see
+/// [`CommandConfig::critical`](crate::CommandConfig::critical) and
+/// [`CommandConfig::transient`](crate::CommandConfig::transient)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitKills {
+    /// if [`KillBehavior`] says so
+    ByKillBehavior,
+    Always,
+    Never,
+}
+
+enum SystemEvent<T> {
+    /// with the slot of the command, see [`RunConfig::max_concurrent`]
+    Exited(
+        Arc<CommandStopped<T, T>>,
+        Option<OwnedSemaphorePermit>,
+        ExitKills,
+    ),
+    /// the supervisor of a command panicked, so the command never stops
+    Panicked,
+    /// kill all commands, with a signal or forcefully,
+    /// and abandon those still running after the timeout
+    KillAll(Option<Signal>, Option<Duration>),
+    /// the run took longer than [`RunConfig::timeout`]
+    TimedOut(Duration),
+}
+
 #[derive(Clone)]
-pub struct CommandSystemKiller<T>(mpsc::Sender<Option<Arc<CommandStopped<T, T>>>>);
+pub struct CommandSystemKiller<T> {
+    tx: mpsc::Sender<SystemEvent<T>>,
+    commands: Arc<Mutex<CommandRegistry<T>>>,
+}
 
 impl<T> CommandSystemKiller<T> {
     pub async fn kill_all(&self) {
-        let _ = self.0.send(None).await;
+        let _ = self.tx.send(SystemEvent::KillAll(None, None)).await;
+    }
+
+    /// Like [`kill_all`](Self::kill_all), but sends `signal` instead of killing forcefully
+    pub async fn kill_all_with_signal(&self, signal: Signal) {
+        let _ = self.tx.send(SystemEvent::KillAll(Some(signal), None)).await;
+    }
+
+    /// Like [`kill_all`](Self::kill_all), but commands still running after `timeout`
+    /// are killed forcefully and abandoned if they don't exit shortly after,
+    /// see [`CommandKiller::abandon`](kill::CommandKiller::abandon).
+    ///
+    /// So waiting for the system ends even if a command hangs.
+    pub async fn kill_all_with_timeout(&self, timeout: Duration) {
+        let _ = self
+            .tx
+            .send(SystemEvent::KillAll(None, Some(timeout)))
+            .await;
+    }
+
+    /// Like [`kill_all_with_timeout`](Self::kill_all_with_timeout),
+    /// but sends `signal` first. The command is killed forcefully after the
+    /// shutdown timeout or `timeout`, whichever is shorter.
+    pub async fn kill_all_with_signal_and_timeout(&self, signal: Signal, timeout: Duration) {
+        let _ = self
+            .tx
+            .send(SystemEvent::KillAll(Some(signal), Some(timeout)))
+            .await;
+    }
+
+    /// Kills all commands forcefully right away and abandons those which don't exit
+    /// shortly after, also if they were killed before, e.g. on a second Ctrl-C
+    pub async fn force_stop_all(&self) {
+        self.kill_all_with_timeout(Duration::ZERO).await;
+
+        // the system may have stopped receiving events after an earlier kill
+        for state in self.commands.lock().unwrap().states() {
+            if let CommandState::Spawned { killer, .. } =
+                &*state.lock().unwrap_or_else(PoisonError::into_inner)
+            {
+                killer.kill(kill::KillCommandReason::MainProcessGotSignal);
+                killer.abandon();
+            }
+        }
+    }
+}
+
+/// A single command of a [`CommandSystem`], see [`CommandSystem::command_handles`].
+///
+/// Refers to the current run of the command, also after it was restarted.
+/// Once the command stopped for good, killing it does nothing.
+pub struct CommandHandle<T> {
+    state: Arc<Mutex<CommandState<T>>>,
+    stopped: StoppedReceiver<T>,
+    control: Arc<SupervisorControl>,
+}
+
+impl<T> Clone for CommandHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            stopped: self.stopped.clone(),
+            control: self.control.clone(),
+        }
+    }
+}
+
+impl<T> CommandHandle<T> {
+    /// Waits until the command stopped for good, e.g. after it was killed.
+    ///
+    /// Returns `None` if its supervisor panicked.
+    pub async fn wait(&self) -> Option<Arc<CommandStopped<T, T>>> {
+        wait_stopped(&mut self.stopped.clone()).await
+    }
+
+    /// Whether the command stopped for good, see [`wait`](Self::wait)
+    pub fn has_stopped(&self) -> bool {
+        self.stopped.borrow().is_some()
+    }
+
+    /// Process id of the current run, `None` if the command isn't running
+    pub fn pid(&self) -> Option<u32> {
+        match &*self.state.lock().unwrap_or_else(PoisonError::into_inner) {
+            CommandState::Spawned { pid, .. } => *pid,
+            _ => None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(
+            &*self.state.lock().unwrap_or_else(PoisonError::into_inner),
+            CommandState::Spawned { .. }
+        )
+    }
+
+    /// Sends `signal` to the current run without killing it, e.g. to forward Ctrl-C.
+    /// Returns whether the command was running and got the signal.
+    #[cfg(unix)]
+    pub fn send_signal(&self, signal: Signal) -> bool {
+        match self.pid() {
+            Some(pid) => unsafe { libc::kill(pid as libc::pid_t, signal.as_raw()) == 0 },
+            None => false,
+        }
+    }
+
+    /// Writes `line` and a newline to the stdin of the current run. Returns whether the
+    /// command is running with a pipe as stdin which it still reads,
+    /// see [`RunConfig::pipe_stdin`].
+    pub fn write_line(&self, line: &str) -> bool {
+        match &*self.state.lock().unwrap_or_else(PoisonError::into_inner) {
+            CommandState::Spawned {
+                stdin: Some(stdin), ..
+            } => stdin.write_line(line),
+            _ => false,
+        }
+    }
+
+    /// Kills the current run of the command forcefully, the other commands keep running.
+    ///
+    /// The command isn't restarted and its exit doesn't make [`KillBehavior`] kill the others.
+    /// A command which isn't running, e.g. waiting for prerequisites or to be restarted after
+    /// a backoff, stops waiting and isn't spawned anymore.
+    /// Returns [`KillResult::AlreadyExited`](kill::KillResult::AlreadyExited)
+    /// if the command stopped for good already.
+    ///
+    /// A scheduled command doesn't run again, also if it was waiting for its next run,
+    /// see [`CommandConfig::schedule`](crate::CommandConfig::schedule).
+    pub fn kill(&self) -> kill::KillResult {
+        self.stop(None)
+    }
+
+    /// Like [`kill`](Self::kill), but sends `signal` instead of killing forcefully
+    pub fn kill_with_signal(&self, signal: Signal) -> kill::KillResult {
+        self.stop(Some(signal))
+    }
+
+    fn stop(&self, signal: Option<Signal>) -> kill::KillResult {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        // set while holding the state lock, which the supervisor holds when it spawns the command
+        let stopping = self.control.stop.started();
+        self.control.stop.start();
+        match &*state {
+            CommandState::Spawned { killer, .. } => {
+                let reason = kill::KillCommandReason::Requested;
+                match signal {
+                    Some(signal) => killer.kill_with_signal(reason, signal),
+                    None => killer.kill(reason),
+                }
+            }
+            _ if stopping => kill::KillResult::AlreadyExited,
+            _ => kill::KillResult::SentSuccess,
+        }
+    }
+
+    /// Kills the current run of the command forcefully and spawns it again,
+    /// like when its watched files changed. The restart doesn't count as a retry.
+    ///
+    /// A command which waits to be restarted, e.g. after a backoff, is spawned right away.
+    /// Returns [`KillResult::AlreadyExited`](kill::KillResult::AlreadyExited)
+    /// if the command wasn't spawned yet or stopped for good, it isn't spawned again then.
+    pub fn restart(&self) -> kill::KillResult {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match &*state {
+            CommandState::Spawned { killer, .. } => {
+                killer.kill(kill::KillCommandReason::RestartRequested)
+            }
+            // the supervisor stops the command for good while holding the state lock
+            CommandState::Stopped(_) if !self.control.stop.started() => {
+                self.control.restart.start();
+                kill::KillResult::SentSuccess
+            }
+            _ => kill::KillResult::AlreadyExited,
+        }
+    }
+}
+
+/// Requests for the supervisor of a command, see [`CommandHandle`]
+#[derive(Default)]
+struct SupervisorControl {
+    /// Started once the command was killed or stopped for good. It isn't spawned again,
+    /// also if it was waiting for its next run, see [`Schedule`], or a backoff.
+    stop: Shutdown,
+    /// Started when the command should be spawned again while it doesn't run,
+    /// which ends a backoff early. Reset once it is spawned.
+    restart: Shutdown,
+}
+
+impl SupervisorControl {
+    /// Waits until the command should stop or be spawned again right away
+    async fn interrupted(&self) {
+        tokio::select! {
+            _ = self.stop.wait() => {}
+            _ = self.restart.wait() => {}
+        }
+    }
+}
+
+impl<T: Clone> CommandHandle<T> {
+    /// The data of the current or the last run, `None` if the command wasn't spawned yet
+    pub fn data(&self) -> Option<T> {
+        match &*self.state.lock().unwrap_or_else(PoisonError::into_inner) {
+            CommandState::Spawned { data, .. } => Some(data.clone()),
+            CommandState::Stopped(cmd) => Some(cmd.data.clone()),
+            CommandState::Processing => None,
+        }
     }
 }
 
@@ -39,330 +306,5105 @@ pub struct CommandSystem<T, P>
 where
     P: CommandSystemPlugin<T>,
 {
-    commands: Arc<Vec<Arc<Mutex<CommandState<T>>>>>,
+    commands: Arc<Mutex<CommandRegistry<T>>>,
     killer: CommandSystemKiller<T>,
-    handles: AsyncMutex<Option<CommandSystemHandles>>,
+    killer_handle: AsyncMutex<Option<JoinHandle<()>>>,
     plugin: Arc<P>,
+    shutdown: Arc<Shutdown>,
+    shutdown_timeout: Duration,
+    live: Arc<LiveCounts>,
+    /// The commands [`wait_all_ready`](Self::wait_all_ready) waits for, by index, with their
+    /// signal and whether they stopped
+    ready: Vec<(usize, ReadySignal, StoppedReceiver<T>)>,
+    /// The data and handle of each command the system was spawned with,
+    /// not of those added by a [`CommandSpawner`]
+    initial_commands: Vec<(P::CommandInitialData, CommandHandle<T>)>,
+    spawner: CommandSpawner<T, P>,
+    events: Arc<EventSender<T>>,
+    /// Receives the events since the system was spawned, for the first call of
+    /// [`events`](Self::events)
+    first_events: Mutex<Option<tokio::sync::broadcast::Receiver<CommandEvent<T>>>>,
+    /// Files of scripts of commands, see [`CommandConfig::script`](crate::CommandConfig::script)
+    scripts: Vec<ScriptFile>,
+    /// How many lines of each command [`tail`](Self::tail) returns at most
+    tail_lines: usize,
 }
 
-struct CommandSystemHandles {
-    commands_handles: Vec<JoinHandle<()>>,
-    killer_handle: JoinHandle<()>,
+/// Counters of [`RunStats`] which change while commands run,
+/// updated without locking the [`CommandRegistry`]
+#[derive(Default)]
+struct LiveCounts {
+    running: AtomicUsize,
+    restarts: AtomicUsize,
 }
 
-impl<T, P> CommandSystem<T, P>
-where
-    T: std::marker::Send + std::marker::Sync + 'static,
-    P: CommandSystemPlugin<T>,
-{
-    fn spawn_with_plugin<I>(commands: I, kill_behavior: KillBehavior, plugin: P) -> Self
-    where
-        I: IntoIterator<Item = (Command, P::CommandInitialData)>,
-        P: ,
-    {
-        let commands: Vec<_> = commands.into_iter().collect();
-        let (tx, mut rx) = mpsc::channel(cmp::min(commands.len(), 1));
-
-        let plugin = Arc::new(plugin);
+/// Set once the system kills all commands, e.g. on Ctrl-C
+struct Shutdown(watch::Sender<bool>);
 
-        let (commands, handles): (Vec<_>, Vec<_>) = commands
-            .into_iter()
-            .map(|(command, data)| {
-                let tx = tx.clone();
-                let plugin = plugin.clone();
-
-                let spawned = CommandInitialized::new(command, ()).spawn::<T>();
-
-                let (cmd, stdout, stderr) = match spawned {
-                    Ok((cmd, stdout, stderr)) => (cmd.with_data(data).1, stdout, stderr),
-                    Err(err) => {
-                        let data = plugin.initialize_spawn_failed_command_data(data);
-                        let cmd = Arc::new(CommandStopped {
-                            data,
-                            exit_status: Err(err),
-                            killed: None,
-                        });
-                        let mutex = Arc::new(Mutex::new(CommandState::Stopped(cmd.clone())));
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self(watch::channel(false).0)
+    }
+}
 
-                        let handle = tokio::spawn(async move {
-                            &plugin.on_command_exited(cmd.clone());
-                            let _ = tx.send(Some(cmd)).await;
-                        });
+impl Shutdown {
+    fn start(&self) {
+        self.0.send_replace(true);
+    }
 
-                        return (mutex, handle);
-                    }
-                };
+    /// Unsets the flag, for flags which are set more than once
+    fn reset(&self) {
+        self.0.send_replace(false);
+    }
 
-                let CommandSpawned {
-                    data,
-                    join_handle,
-                    killer,
-                } = cmd;
+    fn started(&self) -> bool {
+        *self.0.borrow()
+    }
 
-                let data = plugin.initialize_command_data(data, stdout, stderr);
+    /// Waits until the shutdown started
+    async fn wait(&self) {
+        let mut rx = self.0.subscribe();
+        while !*rx.borrow() {
+            if rx.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
 
-                let mutex_ret = Arc::new(Mutex::new(CommandState::Spawned { data, killer }));
+/// All commands of a [`CommandSystem`], including those added by a [`CommandSpawner`]
+struct CommandRegistry<T> {
+    commands: Vec<CommandHandle<T>>,
+    /// handles of the supervisors which haven't been joined yet
+    handles: Vec<JoinHandle<()>>,
+    /// the first supervisor which panicked
+    panicked: Option<CommandSystemPanicked>,
+    /// set once all commands stopped, no commands can be added afterwards
+    closed: bool,
+    /// how many commands were added, including pruned ones
+    command_count_total: usize,
+    /// how many commands stopped successfully, including pruned ones
+    command_count_success: usize,
+    /// how many commands failed for good, including pruned ones
+    command_count_failed: usize,
+    /// how many commands were killed for good, including pruned ones
+    command_count_killed: usize,
+    /// exit code of the command which failed first
+    first_failed_code: Option<i32>,
+    /// exit code of the command which stopped on its own last
+    last_exit_code: Option<i32>,
+    /// whether the command which stopped on its own last failed
+    last_failed: bool,
+    /// how many commands are not [optional](crate::CommandConfig::optional)
+    required_count_total: usize,
+    required_count_running: usize,
+    /// commands which stopped for good, in the order they stopped
+    history: VecDeque<Arc<CommandStopped<T, T>>>,
+    history_limit: Option<usize>,
+}
 
-                let mutex = mutex_ret.clone();
+impl<T> CommandRegistry<T> {
+    fn new(capacity: usize, history_limit: Option<usize>) -> Self {
+        Self {
+            commands: Vec::with_capacity(capacity),
+            handles: Vec::with_capacity(capacity),
+            panicked: None,
+            closed: false,
+            command_count_total: 0,
+            command_count_success: 0,
+            command_count_failed: 0,
+            command_count_killed: 0,
+            first_failed_code: None,
+            last_exit_code: None,
+            last_failed: false,
+            required_count_total: 0,
+            required_count_running: 0,
+            history: VecDeque::new(),
+            history_limit,
+        }
+    }
 
-                let handle = tokio::spawn(async move {
-                    let cmd = join_handle.join().await;
+    fn push(&mut self, command: CommandHandle<T>, handle: JoinHandle<()>, optional: bool) {
+        self.commands.push(command);
+        self.handles.push(handle);
+        self.added(optional);
+    }
 
-                    let cmd = {
-                        let mut state = mutex.lock().unwrap();
+    fn states(&self) -> impl Iterator<Item = &Arc<Mutex<CommandState<T>>>> {
+        self.commands.iter().map(|cmd| &cmd.state)
+    }
 
-                        let old_state = mem::replace(&mut *state, CommandState::Processing);
+    /// Counts a command which will stop, also each run of a repeated command
+    fn added(&mut self, optional: bool) {
+        self.command_count_total += 1;
+        if !optional {
+            self.required_count_total += 1;
+            self.required_count_running += 1;
+        }
+    }
 
-                        let cmd = match old_state {
-                            CommandState::Spawned { data, killer: _ } => {
-                                let cmd = Arc::new(cmd.with_data(data).1);
+    fn stats(&self, live: &LiveCounts) -> RunStats {
+        RunStats {
+            command_count_total: self.command_count_total,
+            command_count_running: live.running.load(Ordering::SeqCst),
+            command_count_success: self.command_count_success,
+            command_count_failed: self.command_count_failed,
+            command_count_killed: self.command_count_killed,
+            restart_count: live.restarts.load(Ordering::SeqCst),
+        }
+    }
 
-                                *state = CommandState::Stopped(cmd.clone());
+    /// Whether commands were required and all of them stopped
+    fn only_optional_remain(&self) -> bool {
+        self.required_count_total > 0 && self.required_count_running == 0
+    }
 
-                                if tx.is_closed() {
-                                    &plugin.on_command_exited(cmd);
-                                    None
-                                } else {
-                                    &plugin.on_command_exited(cmd.clone());
-                                    Some(cmd)
-                                }
-                            }
-                            _ => panic!("unreachable"),
-                        };
+    /// Records a command which stopped for good.
+    ///
+    /// The oldest commands beyond `history_limit` are pruned.
+    fn stopped(&mut self, cmd: Arc<CommandStopped<T, T>>, optional: bool) {
+        if !optional {
+            self.required_count_running -= 1;
+        }
+        let code = cmd.exit_status.as_ref().ok().and_then(|s| s.code());
+        match cmd.outcome() {
+            CommandOutcome::Succeeded => {
+                self.command_count_success += 1;
+                self.last_exit_code = code;
+                self.last_failed = false;
+            }
+            CommandOutcome::Failed => {
+                if self.command_count_failed == 0 {
+                    self.first_failed_code = code;
+                }
+                self.command_count_failed += 1;
+                self.last_exit_code = code;
+                self.last_failed = true;
+            }
+            CommandOutcome::Killed => self.command_count_killed += 1,
+        }
+        self.history.push_back(cmd);
 
-                        drop(state);
-                        cmd
-                    };
+        let limit = match self.history_limit {
+            Some(limit) => limit,
+            None => return,
+        };
 
-                    if let Some(cmd) = cmd {
-                        let _ = tx.send(Some(cmd)).await;
+        while self.history.len() > limit {
+            if let Some(pruned) = self.history.pop_front() {
+                self.commands.retain(|cmd| {
+                    match &*cmd.state.lock().unwrap_or_else(PoisonError::into_inner) {
+                        CommandState::Stopped(cmd) => !Arc::ptr_eq(cmd, &pruned),
+                        _ => true,
                     }
                 });
+            }
+        }
+    }
+}
 
-                (mutex_ret, handle)
-            })
-            .collect::<Vec<_>>()
-            .into_iter()
-            .unzip();
+/// A command waiting to be spawned by [`CommandSystem`]
+struct CommandToRun<D> {
+    command: CommandInitialized<()>,
+    data: D,
+    /// The label of its events, see [`CommandSystem::events`]
+    label: String,
+    /// The pane of its events, see [`CommandConfig::pane`](crate::CommandConfig::pane)
+    pane: Option<String>,
+    startup_retries: Option<StartupRetries>,
+    spawn_retries: Option<SpawnRetries>,
+    after: Vec<Prerequisite>,
+    /// Labels and indexes of the commands in
+    /// [`CommandConfig::stop_when_ready`](crate::CommandConfig::stop_when_ready)
+    stop_when_ready: Vec<(String, usize)>,
+    /// How long to wait for prerequisites which must be ready
+    after_ready_timeout: Duration,
+    /// See [`CommandConfig::wait_for`](crate::CommandConfig::wait_for)
+    wait_for: Option<PortWait>,
+    /// How long a run may take, see [`CommandConfig::timeout`](crate::CommandConfig::timeout)
+    timeout: Option<Duration>,
+    restart: Option<RestartPolicy>,
+    restart_rate_limit: Option<RestartRateLimit>,
+    early_exit_backoff: Option<EarlyExitBackoff>,
+    /// How often the command runs, see [`CommandConfig::repeat`](crate::CommandConfig::repeat)
+    repeat: u32,
+    /// When the command runs again, as often as it is due
+    schedule: Option<Schedule>,
+    ready: Option<ReadySignal>,
+    probe: Option<ProbeRunner>,
+    health: Option<HealthChecker>,
+    on_failure: Option<HookRunner>,
+    watcher: Option<DirWatcher>,
+    optional: bool,
+    exit_kills: ExitKills,
+    /// Whether the command takes the terminal of runcc, see [`RunConfig::foreground`]
+    foreground: bool,
+}
 
-        let command_count = commands.len();
-        let commands_ret = Arc::new(commands);
+/// Options of [`RunConfig`] for the whole [`CommandSystem`]
+struct SystemOptions {
+    kill_behavior: KillBehavior,
+    exit_when_only_optional_remain: bool,
+    shutdown_timeout: Duration,
+    kill_grace: Option<Duration>,
+    kill_signal: Option<Signal>,
+    /// Kills all commands once the run took this long, see [`RunConfig::timeout`]
+    timeout: Option<Duration>,
+    history_limit: Option<usize>,
+    max_concurrent: Option<usize>,
+    /// The command whose readiness is the readiness of the system, see
+    /// [`RunConfig::system_ready_when`]
+    system_ready: Option<usize>,
+    /// How many output lines of each command are kept, see [`RunConfig::tail_lines`]
+    tail_lines: usize,
+}
 
-        let commands = commands_ret.clone();
-        let killer_handle = tokio::spawn(async move {
-            let mut exited_command_count = 0;
-            while let Some(exited_cmd) = rx.recv().await {
-                let reason = if let Some(ref exited_cmd) = exited_cmd {
-                    exited_command_count += 1;
+/// A command which must exit successfully, or be ready, before another one is spawned
+struct Prerequisite {
+    label: String,
+    /// `None` if no command has this label
+    index: Option<usize>,
+    /// whether the command only needs to be ready
+    ready: bool,
+}
 
-                    if exited_command_count >= command_count {
-                        break;
-                    }
+/// Where a command without prerequisites waits for a slot of [`RunConfig::max_concurrent`],
+/// so that the queued commands take slots in order
+struct QueuePosition {
+    /// closed once the command queued before this one took a slot
+    previous: Option<oneshot::Receiver<()>>,
+    /// dropped once this command took a slot
+    _taken: oneshot::Sender<()>,
+}
 
-                    let should_kill_all: bool = match &kill_behavior {
-                        KillBehavior::None => false,
-                        KillBehavior::WhenAnyExited => true,
-                        KillBehavior::WhenAnyExitedWithStatus(status) => match status {
-                            crate::ExitStatusPattern::Success => exited_cmd
-                                .exit_status
-                                .as_ref()
-                                .ok()
-                                .map_or(false, |s| s.success()),
-                            crate::ExitStatusPattern::Failed => exited_cmd
-                                .exit_status
-                                .as_ref()
-                                .ok()
-                                .map_or(true, |s| !s.success()),
-                            crate::ExitStatusPattern::StatusCode(code) => exited_cmd
-                                .exit_status
-                                .as_ref()
-                                .ok()
-                                .map_or(false, |s| s.code() == Some(*code)),
-                        },
-                    };
+type SpawnedOrStopped<T> = Result<CommandJoinHandle<T>, Arc<CommandStopped<T, T>>>;
 
-                    if should_kill_all {
-                        Some(kill::KillCommandReason::OtherCommandExited(
-                            exited_cmd.clone(),
-                        ))
-                    } else {
-                        None
-                    }
-                } else {
-                    // got kill all
-                    Some(kill::KillCommandReason::MainProcessGotSignal)
-                };
+type StoppedReceiver<T> = watch::Receiver<Option<Arc<CommandStopped<T, T>>>>;
 
-                if let Some(reason) = reason {
-                    drop(rx);
+/// Waits until the command behind `rx` stops for good.
+///
+/// Returns `None` if its supervisor is gone without publishing the result.
+async fn wait_stopped<T>(rx: &mut StoppedReceiver<T>) -> Option<Arc<CommandStopped<T, T>>> {
+    loop {
+        if let Some(cmd) = rx.borrow().clone() {
+            return Some(cmd);
+        }
+        if rx.changed().await.is_err() {
+            return rx.borrow().clone();
+        }
+    }
+}
 
-                    for state in commands.iter() {
-                        let mut state = state.lock().unwrap();
+/// Waits until any of `commands` is ready or exited,
+/// see [`CommandConfig::stop_when_ready`](crate::CommandConfig::stop_when_ready)
+async fn wait_any_ready<T: Send + Sync + 'static>(
+    commands: Vec<(String, Option<ReadySignal>, StoppedReceiver<T>)>,
+) -> kill::KillCommandReason<T> {
+    type Wait<T> = Pin<Box<dyn Future<Output = kill::KillCommandReason<T>> + Send>>;
 
-                        match &mut *state {
-                            CommandState::Spawned { killer, .. } => {
-                                killer.kill(reason.clone());
-                            }
-                            _ => {}
-                        }
+    let mut waits: Vec<Wait<T>> = commands
+        .into_iter()
+        .map(|(label, ready, mut rx)| -> Wait<T> {
+            Box::pin(async move {
+                let ready = async {
+                    match &ready {
+                        Some(ready) => ready.wait().await,
+                        None => future::pending().await,
                     }
+                };
+                let exited = tokio::select! {
+                    _ = ready => false,
+                    _ = wait_stopped(&mut rx) => true,
+                };
+                kill::KillCommandReason::StopWhenReady { label, exited }
+            })
+        })
+        .collect();
+    future::poll_fn(|cx| {
+        let ready = waits
+            .iter_mut()
+            .find_map(|wait| match wait.as_mut().poll(cx) {
+                Poll::Ready(reason) => Some(reason),
+                Poll::Pending => None,
+            });
+        ready.map_or(Poll::Pending, Poll::Ready)
+    })
+    .await
+}
 
-                    break;
+/// Spawns the command and lets the plugin take its output.
+///
+/// The new state is written to `state` and the join handle is returned.
+fn spawn_command<T, P>(
+    command: &mut CommandToRun<P::CommandInitialData>,
+    plugin: &P,
+    state: &mut CommandState<T>,
+    live: &LiveCounts,
+    events: &Arc<EventSender<T>>,
+) -> SpawnedOrStopped<T>
+where
+    T: Send + Sync + 'static,
+    P: CommandSystemPlugin<T>,
+    P::CommandInitialData: Clone + Send + Sync,
+{
+    let spawned = command.command.respawn::<T>();
+    spawned_or_stopped(spawned, command, plugin, state, live, events)
+}
+
+/// Lets the plugin take the output of a spawned command,
+/// or records that the command failed to spawn.
+fn spawned_or_stopped<T, P>(
+    spawned: io::Result<(CommandSpawned<(), T>, ChildStdout, ChildStderr)>,
+    command: &CommandToRun<P::CommandInitialData>,
+    plugin: &P,
+    state: &mut CommandState<T>,
+    live: &LiveCounts,
+    events: &Arc<EventSender<T>>,
+) -> SpawnedOrStopped<T>
+where
+    T: Send + Sync + 'static,
+    P: CommandSystemPlugin<T>,
+    P::CommandInitialData: Clone + Send + Sync,
+{
+    let data = command.data.clone();
+    match spawned {
+        Ok((cmd, stdout, stderr)) => {
+            let CommandSpawned {
+                pid,
+                stdin,
+                join_handle,
+                killer,
+                ..
+            } = cmd;
+
+            // lines the plugin publishes are sent from its broadcast
+            let (stdout, stderr) = match plugin.output_broadcast() {
+                None if events.has_receivers() => {
+                    let events = events.clone();
+                    let label = command.label.clone();
+                    let (stdout, stderr, _) =
+                        super::tee::tee(stdout, stderr, move |stream, line| {
+                            events.send(CommandEvent::OutputLine {
+                                label: label.clone(),
+                                stream,
+                                line: line.to_string(),
+                            })
+                        });
+                    (stdout, stderr)
                 }
-            }
-        });
+                _ => (stdout, stderr),
+            };
+            let data = plugin.initialize_command_data(data, stdout, stderr);
+            plugin.on_command_started(&data, pid);
+            events.send(CommandEvent::Spawned {
+                label: command.label.clone(),
+                pane: command.pane.clone(),
+                pid,
+            });
+            *state = CommandState::Spawned {
+                data,
+                pid,
+                killer,
+                stdin,
+            };
+            live.running.fetch_add(1, Ordering::SeqCst);
 
-        Self {
-            commands: commands_ret,
-            killer: CommandSystemKiller(tx),
-            handles: AsyncMutex::new(Some(CommandSystemHandles {
-                commands_handles: handles,
-                killer_handle,
-            })),
-            plugin,
+            Ok(join_handle)
+        }
+        Err(err) => {
+            let data = plugin.initialize_spawn_failed_command_data(data);
+            let cmd = Arc::new(CommandStopped {
+                data,
+                exit_status: Err(err),
+                killed: None,
+                ran_for: None,
+            });
+            *state = CommandState::Stopped(cmd.clone());
+
+            Err(cmd)
         }
     }
 }
 
-impl<T: Clone, P: CommandSystemPlugin<T>> CommandSystem<T, P> {
-    pub fn share_killer(&self) -> CommandSystemKiller<T> {
-        self.killer.clone()
+/// The message a task panicked with
+fn panic_message(err: tokio::task::JoinError) -> String {
+    match err.try_into_panic() {
+        Ok(panic) => match panic.downcast::<String>() {
+            Ok(message) => *message,
+            Err(panic) => match panic.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "Box<dyn Any>".to_string(),
+            },
+        },
+        Err(err) => err.to_string(),
     }
 }
 
-impl<T, P: CommandSystemPlugin<T>> CommandSystem<T, P> {
-    pub async fn kill_all(&self) {
-        self.killer.kill_all().await;
-    }
+/// Why the command was killed, if kill was sent to it
+pub(crate) fn kill_reason<T>(cmd: &CommandStopped<T, T>) -> Option<&kill::KillCommandReason<T>> {
+    use kill::{CommandAlreadyExitedKind as EK, KillJoinHandleFinalStatus as KS};
 
-    async fn wait_iter_stopped_commands<'a, R, F>(
-        &'a mut self,
-        cmd_processor: F,
-    ) -> impl Iterator<Item = R> + 'a
-    where
-        F: 'a + Fn(&Arc<CommandStopped<T, T>>) -> R,
-    {
-        let Self {
-            commands,
-            handles,
-            plugin,
-            ..
-        } = self;
+    match &cmd.killed {
+        Some(KS::Killed(reason))
+        | Some(KS::ForceKilled(reason))
+        | Some(KS::Abandoned(reason))
+        | Some(KS::AlreadyExited(EK::ProcessExited(reason))) => Some(reason),
+        _ => None,
+    }
+}
 
-        let mut handles = handles.lock().await;
+/// Spawns a command when it is allowed to start, watches it
+/// and spawns it again when its options ask for it.
+struct CommandSupervisor<T, P: CommandSystemPlugin<T>> {
+    command: CommandToRun<P::CommandInitialData>,
+    state: Arc<Mutex<CommandState<T>>>,
+    plugin: Arc<P>,
+    started_at: Instant,
+    shutdown: Arc<Shutdown>,
+    /// Shared with the [`CommandHandle`] of the command
+    control: Arc<SupervisorControl>,
+    live: Arc<LiveCounts>,
+    events: Arc<EventSender<T>>,
+    /// receivers of the commands in `command.after`
+    after: Vec<Option<StoppedReceiver<T>>>,
+    /// ready signals of the commands in `command.after`
+    after_ready: Vec<Option<ReadySignal>>,
+    /// Labels, ready signals and receivers of the commands in `command.stop_when_ready`
+    stop_when_ready: Vec<(String, Option<ReadySignal>, StoppedReceiver<T>)>,
+    /// The command this one replaces, which must stop before this one is spawned,
+    /// see [`CommandSpawner::spawn_config`]
+    replaces: Option<CommandHandle<T>>,
+    /// Limits how many commands run at the same time, see [`RunConfig::max_concurrent`]
+    slots: Option<Arc<Semaphore>>,
+    /// Taken from `slots` before the command is spawned first, released once it stopped for good
+    slot: Option<OwnedSemaphorePermit>,
+    queue: Option<QueuePosition>,
+    tx: mpsc::Sender<SystemEvent<T>>,
+    commands: Arc<Mutex<CommandRegistry<T>>>,
+}
 
-        if let Some(handles) = handles.take() {
-            let CommandSystemHandles {
-                commands_handles,
-                killer_handle,
-            } = handles;
+impl<T, P> CommandSupervisor<T, P>
+where
+    T: Send + Sync + 'static,
+    P: CommandSystemPlugin<T>,
+    P::CommandInitialData: Clone + Send + Sync,
+{
+    fn should_retry(&self, cmd: &CommandStopped<T, T>, retries: u32) -> bool {
+        match &self.command.restart {
+            Some(RestartPolicy::UntilReady { max, .. }) => {
+                let ready = self.command.ready.as_ref().is_some_and(|r| r.is_ready());
+                let killed_by_others = cmd.killed.is_some()
+                    && !matches!(
+                        kill_reason(cmd),
+                        Some(kill::KillCommandReason::NotReadyInTime)
+                            | Some(kill::KillCommandReason::ProbeFailed { .. })
+                    );
 
-            for handle in commands_handles {
-                handle.await.expect("CommandSystem subtask panicked");
+                if !ready && !killed_by_others && cmd.skipped().is_none() && retries < *max {
+                    return true;
+                }
             }
+            Some(RestartPolicy::Always { max, .. })
+            | Some(RestartPolicy::OnFailure { max, .. }) => {
+                let on_failure =
+                    matches!(self.command.restart, Some(RestartPolicy::OnFailure { .. }));
+                // commands which couldn't be spawned would fail again right away
+                let exited = cmd.exit_status.is_ok() && cmd.killed.is_none();
 
-            killer_handle
-                .await
-                .expect("CommandSystem's subtask for killing commands panicked");
+                if exited && !(on_failure && cmd.success()) && max.is_none_or(|max| retries < max) {
+                    return true;
+                }
+            }
+            Some(RestartPolicy::Never) | None => {}
         }
 
-        drop(handles);
+        let startup_retries = match &self.command.startup_retries {
+            Some(startup_retries) => startup_retries,
+            None => return false,
+        };
 
-        if let Some(plugin_join) = plugin.join() {
-            let _ = plugin_join.await;
-        }
+        !cmd.success()
+            && cmd.killed.is_none()
+            && cmd.skipped().is_none()
+            && retries < startup_retries.max_retries
+            && self.started_at.elapsed() < startup_retries.within
+    }
 
-        commands.iter().map(move |cmd| {
-            let cmd = cmd.lock().unwrap();
+    /// How long to wait before the command is spawned again because it failed early,
+    /// see [`RunConfig::early_exit_backoff`]. `attempts` start over once a run lasted longer.
+    fn early_exit_delay(&self, cmd: &CommandStopped<T, T>, attempts: &mut u32) -> Option<Duration> {
+        let backoff = self.command.early_exit_backoff.as_ref()?;
+        // commands which couldn't be spawned failed at once
+        let ran_for = cmd.ran_for.unwrap_or_default();
+        if ran_for >= backoff.within {
+            *attempts = 0;
+            return None;
+        }
 
-            match &*cmd {
-                CommandState::Stopped(cmd) => cmd_processor(cmd),
-                _ => panic!("CommandState should be stopped after handles joined"),
-            }
-        })
+        let ready = self.command.ready.as_ref().is_some_and(|r| r.is_ready());
+        if ready
+            || cmd.success()
+            || cmd.killed.is_some()
+            || cmd.skipped().is_some()
+            || *attempts >= backoff.max_attempts
+        {
+            return None;
+        }
+        let delay = backoff.delay(*attempts);
+        *attempts += 1;
+        self.plugin.on_early_exit(
+            &self.command.data,
+            *attempts + 1,
+            backoff.max_attempts + 1,
+            delay,
+        );
+        Some(delay)
     }
 
-    pub async fn wait(&mut self) -> CommandSystemSimpleReport {
-        let command_count_total = self.commands.len();
-        let mut command_count_success = 0usize;
+    /// How long to wait before the command is restarted because it restarted too often
+    /// recently, see [`CommandConfig::restart_rate_limit`](crate::CommandConfig::restart_rate_limit).
+    /// Records the restart in `restarted_at`.
+    fn restart_rate_delay(&self, restarted_at: &mut VecDeque<Instant>) -> Option<Duration> {
+        let limit = self.command.restart_rate_limit.as_ref()?;
 
-        for success in self
-            .wait_iter_stopped_commands(|cmd| {
-                if let Ok(status) = cmd.exit_status {
-                    status.success()
-                } else {
-                    false
-                }
-            })
-            .await
+        let now = Instant::now();
+        while restarted_at
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= limit.within)
         {
-            if success {
-                command_count_success += 1;
-            }
+            restarted_at.pop_front();
         }
-
-        CommandSystemSimpleReport {
-            command_count_total,
-            command_count_success,
+        if restarted_at.len() < limit.max as usize {
+            restarted_at.push_back(now);
+            return None;
         }
+
+        let cooldown = limit.cooldown();
+        self.plugin.on_restarts_paused(&self.command.data, cooldown);
+        // the window starts over after the cooldown
+        restarted_at.clear();
+        restarted_at.push_back(now + cooldown);
+        Some(cooldown)
     }
 
-    pub async fn wait_into_stopped_commands(&mut self) -> Vec<Arc<CommandStopped<T, T>>> {
-        let commands = self.wait_iter_stopped_commands(Arc::clone).await.collect();
+    fn skip(&self, state: &mut CommandState<T>, reason: String) -> Arc<CommandStopped<T, T>> {
+        let data = self
+            .plugin
+            .initialize_spawn_failed_command_data(self.command.data.clone());
+        let cmd = Arc::new(CommandStopped::new_skipped(data, reason));
+        *state = CommandState::Stopped(cmd.clone());
+        cmd
+    }
 
-        commands
+    /// Records a run of a repeated command which stopped like a command of its own,
+    /// before the next run stops
+    async fn repetition_stopped(&mut self, cmd: Arc<CommandStopped<T, T>>) {
+        {
+            let mut commands = self.commands.lock().unwrap();
+            commands.stopped(cmd.clone(), self.command.optional);
+            commands.added(self.command.optional);
+        }
+        if !self.tx.is_closed() {
+            let exit_kills = self.command.exit_kills;
+            let _ = self
+                .tx
+                .send(SystemEvent::Exited(cmd, None, exit_kills))
+                .await;
+        }
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct LabeledCommandData {
-    pub label: Label,
-}
+    /// Waits until the command exits.
+    ///
+    /// With [`RestartPolicy::UntilReady`], the command is killed if it isn't ready in time.
+    /// With a watcher, the command is killed when watched files change.
+    /// With `stop_when_ready`, it is killed once any of those commands is ready or exited.
+    /// The startup probe runs while waiting, the command is killed once all its attempts failed.
+    /// With a health check, the command is killed once it is unhealthy.
+    /// With a timeout, it is killed once the run took that long.
+    fn join(
+        &self,
+        join_handle: CommandJoinHandle<T>,
+    ) -> impl Future<Output = CommandStopped<(), T>> + Send + 'static {
+        let ready = match (&self.command.ready, &self.command.restart) {
+            (Some(ready), Some(RestartPolicy::UntilReady { ready_timeout, .. })) => {
+                Some((ready.clone(), *ready_timeout))
+            }
+            _ => None,
+        };
+        let probe = match (&self.command.probe, &self.command.ready) {
+            (Some(probe), Some(ready)) => Some((probe.clone(), ready.clone())),
+            _ => None,
+        };
+        let health = self.command.health.clone();
+        let health_ready = self.command.ready.clone();
+        let watcher = self.command.watcher.clone();
+        let timeout = self.command.timeout;
+        let stop_when_ready = self.stop_when_ready.clone();
+        let state = self.state.clone();
+        let (plugin, data) = (self.plugin.clone(), self.command.data.clone());
 
-pub fn spawn_from_run_config_with_plugin<T, P>(
-    run_config: RunConfig,
-    plugin: P,
-) -> CommandSystem<T, P>
-where
-    T: Send + Sync + 'static,
-    P: CommandSystemPlugin<T, CommandInitialData = LabeledCommandData>,
-{
-    let RunConfig {
-        commands,
-        max_label_length,
-        envs,
-        kill,
-    } = run_config;
+        async move {
+            if ready.is_none()
+                && probe.is_none()
+                && health.is_none()
+                && watcher.is_none()
+                && stop_when_ready.is_empty()
+                && timeout.is_none()
+            {
+                return join_handle.join().await;
+            }
 
-    let commands = commands.into_iter().map(|cmd| {
-        let (cmd, label) = cmd.into_tokio_command_and_label(envs.as_ref());
+            let not_ready_in_time = async {
+                if let Some((ready, ready_timeout)) = &ready {
+                    if tokio::time::timeout(*ready_timeout, ready.wait())
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+                future::pending().await
+            };
+            let (probe_plugin, probe_data) = (plugin.clone(), data.clone());
+            let probing = async move {
+                if let Some((probe, ready)) = &probe {
+                    let attempts = probe.failure_threshold();
+                    let on_failed =
+                        move |attempt| probe_plugin.on_probe_failed(&probe_data, attempt, attempts);
+                    if !probe.run(ready, on_failed).await {
+                        return kill::KillCommandReason::ProbeFailed { attempts };
+                    }
+                }
+                future::pending().await
+            };
+            let unhealthy = async move {
+                match &health {
+                    Some(health) => {
+                        let on_unhealthy = move |failures| plugin.on_unhealthy(&data, failures);
+                        let failures = health
+                            .wait_unhealthy(health_ready.as_ref(), on_unhealthy)
+                            .await;
+                        kill::KillCommandReason::Unhealthy { failures }
+                    }
+                    None => future::pending().await,
+                }
+            };
+            let timed_out = async {
+                match timeout {
+                    Some(timeout) => {
+                        tokio::time::sleep(timeout).await;
+                        timeout
+                    }
+                    None => future::pending().await,
+                }
+            };
+            let files_changed = async {
+                match &watcher {
+                    Some(watcher) => watcher.changed().await,
+                    None => future::pending().await,
+                }
+            };
 
-        (
-            cmd,
-            LabeledCommandData {
-                label: Label::from_label(label, max_label_length),
-            },
-        )
-    });
+            let join = join_handle.join();
+            tokio::pin!(join);
 
-    CommandSystem::spawn_with_plugin(commands, kill, plugin)
-}
+            let reason = tokio::select! {
+                cmd = &mut join => return cmd,
+                _ = not_ready_in_time => kill::KillCommandReason::NotReadyInTime,
+                timeout = timed_out => kill::KillCommandReason::TimedOut(timeout),
+                path = files_changed => kill::KillCommandReason::FilesChanged(path),
+                reason = wait_any_ready(stop_when_ready) => reason,
+                reason = probing => reason,
+                reason = unhealthy => reason,
+            };
 
-pub trait CommandSystemPlugin<T>: Send + Sync + 'static + Sized {
-    type CommandInitialData;
+            if let CommandState::Spawned { killer, .. } = &*state.lock().unwrap() {
+                killer.kill(reason);
+            }
+            join.await
+        }
+    }
 
-    fn initialize_spawn_failed_command_data(&self, data: Self::CommandInitialData) -> T;
+    /// Waits until the next run of a scheduled command is due, right away for other commands.
+    ///
+    /// Returns `false` if the command was killed or the shutdown started meanwhile.
+    /// A restart of the command ends the wait early.
+    async fn wait_for_schedule(&self, first_run: Option<Instant>) -> bool {
+        let schedule = match &self.command.schedule {
+            Some(schedule) if first_run.is_some() || schedule.waits_for_first_run() => schedule,
+            _ => return true,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(schedule.until_next_run(first_run)) => {}
+            _ = self.shutdown.wait() => {}
+            _ = self.control.interrupted() => {}
+        }
+        !self.shutdown.started() && !self.control.stop.started()
+    }
 
-    fn initialize_command_data(
-        &self,
-        data: Self::CommandInitialData,
+    /// Waits for `delay` before the command is spawned again, shorter if the shutdown started
+    /// or the command was killed or restarted meanwhile
+    async fn wait_to_restart(&self, delay: Duration) {
+        if delay.is_zero() {
+            return;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = self.shutdown.wait() => {}
+            _ = self.control.interrupted() => {}
+        }
+    }
+
+    /// Whether the command stops for good after a run which stopped on its own. It doesn't if
+    /// it was restarted meanwhile, see [`CommandHandle::restart`].
+    fn stops_for_good(&self) -> bool {
+        // decided while holding the state lock, so that a restart either sees that
+        // the command stopped for good or this task sees the restart
+        let _state = self.state.lock().unwrap();
+        if self.control.restart.started() && !self.control.stop.started() {
+            return false;
+        }
+        self.control.stop.start();
+        true
+    }
+
+    /// Waits until the command it replaces stopped and for prerequisites, and spawns the
+    /// command if all of them succeeded or are ready, once it has a slot.
+    async fn wait_and_spawn(&mut self) -> SpawnedOrStopped<T> {
+        if let Some(replaced) = self.replaces.take() {
+            replaced.wait().await;
+        }
+
+        for (i, rx) in self.after.iter_mut().enumerate() {
+            let label = &self.command.after[i].label;
+
+            let rx = match rx {
+                Some(rx) => rx,
+                None => {
+                    let mut state = self.state.lock().unwrap();
+                    let reason = format!("command[{}] doesn't exist", label);
+                    return Err(self.skip(&mut state, reason));
+                }
+            };
+
+            if self.command.after[i].ready {
+                let ready = match &self.after_ready[i] {
+                    Some(ready) => ready.clone(),
+                    None => {
+                        let mut state = self.state.lock().unwrap();
+                        let reason = format!("command[{}] never becomes ready", label);
+                        return Err(self.skip(&mut state, reason));
+                    }
+                };
+                let timeout = self.command.after_ready_timeout;
+
+                let reason = tokio::select! {
+                    biased;
+                    _ = ready.wait() => continue,
+                    _ = wait_stopped(rx) => format!("command[{}] exited before it was ready", label),
+                    _ = tokio::time::sleep(timeout) => format!(
+                        "command[{}] wasn't ready within {}",
+                        label,
+                        humantime::format_duration(timeout)
+                    ),
+                };
+                let mut state = self.state.lock().unwrap();
+                return Err(self.skip(&mut state, reason));
+            }
+
+            let success = wait_stopped(rx).await.is_some_and(|cmd| cmd.success());
+
+            if !success {
+                let mut state = self.state.lock().unwrap();
+                let reason = format!("command[{}] didn't exit successfully", label);
+                return Err(self.skip(&mut state, reason));
+            }
+        }
+
+        if let Some(wait) = &self.command.wait_for {
+            let reason = tokio::select! {
+                waited = wait_for_port(wait) => waited.err(),
+                _ = self.shutdown.wait() => Some("killed before it started".to_string()),
+            };
+            if let Some(reason) = reason {
+                let mut state = self.state.lock().unwrap();
+                return Err(self.skip(&mut state, reason));
+            }
+        }
+
+        if let (Some(slots), None) = (&self.slots, &self.slot) {
+            let mut queue = self.queue.take();
+            let take_slot = async {
+                if let Some(previous) = queue.as_mut().and_then(|q| q.previous.as_mut()) {
+                    let _ = previous.await;
+                }
+                slots.clone().acquire_owned().await
+            };
+            // commands queued when the shutdown starts are never spawned
+            tokio::select! {
+                slot = take_slot => self.slot = slot.ok(),
+                _ = self.shutdown.wait() => {}
+            }
+        }
+
+        match self.spawn().await {
+            Some(spawned) => spawned,
+            None => {
+                let mut state = self.state.lock().unwrap();
+                Err(self.skip(&mut state, "killed before it started".to_string()))
+            }
+        }
+    }
+
+    /// Spawns the command, and again after a delay while it fails to spawn
+    /// as [`CommandConfig::spawn_retries`](crate::CommandConfig::spawn_retries) allows.
+    ///
+    /// Returns `None` if the shutdown started or the command was killed before it was spawned.
+    async fn spawn(&mut self) -> Option<SpawnedOrStopped<T>> {
+        let mut retries = 0;
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                // checked while holding the state lock, so that kill_all and the command handle
+                // either see the spawned command or this task sees the shutdown or the kill
+                if self.shutdown.started() || self.control.stop.started() {
+                    return None;
+                }
+                self.control.restart.reset();
+
+                let spawned = self.command.command.respawn::<T>();
+                let retry = self
+                    .command
+                    .spawn_retries
+                    .filter(|r| retries < r.max_retries);
+                match (spawned, retry) {
+                    (Err(_), Some(_)) => {}
+                    (spawned, _) => {
+                        return Some(spawned_or_stopped(
+                            spawned,
+                            &self.command,
+                            &*self.plugin,
+                            &mut state,
+                            &self.live,
+                            &self.events,
+                        ))
+                    }
+                }
+            }
+
+            retries += 1;
+            if let Some(spawn_retries) = self.command.spawn_retries {
+                tokio::select! {
+                    _ = tokio::time::sleep(spawn_retries.delay) => {}
+                    _ = self.shutdown.wait() => {}
+                    _ = self.control.stop.wait() => {}
+                }
+            }
+        }
+    }
+
+    /// Waits until the command stops for good and returns the final stopped command.
+    ///
+    /// If `spawned` is `None`, the command is spawned once its prerequisites succeeded.
+    async fn supervise(
+        &mut self,
+        spawned: Option<SpawnedOrStopped<T>>,
+    ) -> Arc<CommandStopped<T, T>> {
+        let mut spawned = match spawned {
+            Some(spawned) => spawned,
+            None if !self.wait_for_schedule(None).await => {
+                let mut state = self.state.lock().unwrap();
+                Err(self.skip(&mut state, "killed before it started".to_string()))
+            }
+            None => {
+                let control = self.control.clone();
+                let spawned = tokio::select! {
+                    spawned = self.wait_and_spawn() => Some(spawned),
+                    _ = control.stop.wait() => None,
+                };
+                match spawned {
+                    Some(spawned) => spawned,
+                    None => {
+                        let mut state = self.state.lock().unwrap();
+                        Err(self.skip(&mut state, "killed before it started".to_string()))
+                    }
+                }
+            }
+        };
+        let first_run = Instant::now();
+        let scheduled = self.command.schedule.is_some();
+        let mut retries = 0;
+        let mut early_exits = 0;
+        let mut restarted_at = VecDeque::new();
+        let mut repetitions_left = self.command.repeat.saturating_sub(1);
+
+        loop {
+            let cmd = match spawned {
+                Ok(join_handle) => {
+                    let cmd = self.join(join_handle).await;
+                    self.live.running.fetch_sub(1, Ordering::SeqCst);
+                    #[cfg(unix)]
+                    if self.command.foreground {
+                        super::terminal::reclaim_terminal();
+                    }
+
+                    let mut state = self.state.lock().unwrap();
+                    let old_state = mem::replace(&mut *state, CommandState::Processing);
+
+                    let cmd = match old_state {
+                        CommandState::Spawned { data, .. } => Arc::new(cmd.with_data(data).1),
+                        _ => panic!("unreachable"),
+                    };
+
+                    *state = CommandState::Stopped(cmd.clone());
+                    cmd
+                }
+                Err(cmd) => cmd,
+            };
+
+            self.plugin.on_command_exited(cmd.clone());
+            let label = &self.command.label;
+            if let Some(reason) = kill_reason(&cmd) {
+                self.events.send(CommandEvent::KilledBecause {
+                    label: label.to_string(),
+                    reason: reason.clone(),
+                });
+            }
+            self.events.send(CommandEvent::Exited {
+                label: label.to_string(),
+                status: cmd.exit_status.as_ref().ok().copied(),
+            });
+
+            let mut repeated = false;
+            let mut restarting = matches!(
+                kill_reason(&cmd),
+                Some(kill::KillCommandReason::FilesChanged(_))
+                    | Some(kill::KillCommandReason::RestartRequested)
+                    | Some(kill::KillCommandReason::Unhealthy { .. })
+            );
+            // restarts due to changed files, requests or health checks don't count as retries
+            if !restarting {
+                if let Some(delay) = self.early_exit_delay(&cmd, &mut early_exits) {
+                    self.wait_to_restart(delay).await;
+                } else if self.should_retry(&cmd, retries) {
+                    retries += 1;
+
+                    if let Some(restart) = &self.command.restart {
+                        self.wait_to_restart(restart.backoff(retries)).await;
+                    }
+                } else {
+                    if let Some(on_failure) = &self.command.on_failure {
+                        if cmd.exit_status.is_ok() && cmd.outcome() == CommandOutcome::Failed {
+                            on_failure.run().await;
+                        }
+                    }
+                    // commands killed by runcc or skipped don't run again,
+                    // scheduled ones run as often as they are due
+                    let stops = (repetitions_left == 0 && !scheduled)
+                        || cmd.killed.is_some()
+                        || cmd.skipped().is_some();
+                    if stops && self.stops_for_good() {
+                        return cmd;
+                    }
+                    if stops {
+                        restarting = true;
+                    } else {
+                        repetitions_left = repetitions_left.saturating_sub(1);
+                        retries = 0;
+                        repeated = true;
+                    }
+                }
+            }
+
+            if self.shutdown.started() || self.control.stop.started() {
+                return cmd;
+            }
+
+            if !repeated && !restarting {
+                if let Some(cooldown) = self.restart_rate_delay(&mut restarted_at) {
+                    self.wait_to_restart(cooldown).await;
+                    if self.shutdown.started() || self.control.stop.started() {
+                        return cmd;
+                    }
+                }
+            }
+
+            if repeated && !self.wait_for_schedule(Some(first_run)).await {
+                return cmd;
+            }
+
+            if let Some(ready) = &self.command.ready {
+                ready.reset();
+            }
+
+            if !repeated {
+                self.live.restarts.fetch_add(1, Ordering::SeqCst);
+            }
+            spawned = match self.spawn().await {
+                Some(spawned) => spawned,
+                None => return cmd,
+            };
+            // only recorded once the next run exists, otherwise it is the final run
+            if repeated {
+                self.repetition_stopped(cmd).await;
+            }
+        }
+    }
+}
+
+impl<T, P> CommandSupervisor<T, P>
+where
+    T: Send + Sync + 'static,
+    P: CommandSystemPlugin<T>,
+    P::CommandInitialData: Clone + Send + Sync,
+{
+    /// Like [`supervise`](Self::supervise), also returns the slot of the command,
+    /// which is released once its exit was handled
+    async fn run(
+        mut self,
+        spawned: Option<SpawnedOrStopped<T>>,
+    ) -> (Arc<CommandStopped<T, T>>, Option<OwnedSemaphorePermit>) {
+        let cmd = self.supervise(spawned).await;
+        // its handle can't restart it anymore
+        self.control.stop.start();
+        (cmd, self.slot.take())
+    }
+
+    /// Runs the supervisor in a task which publishes the final stopped command
+    /// to `stopped_tx` and reports it to the killer.
+    ///
+    /// If the supervisor panics, the panic is recorded in the registry for the command at `index`
+    /// and the killer kills all other commands.
+    fn start(
+        self,
+        index: usize,
+        spawned: Option<SpawnedOrStopped<T>>,
+        stopped_tx: watch::Sender<Option<Arc<CommandStopped<T, T>>>>,
+    ) -> JoinHandle<()> {
+        let optional = self.command.optional;
+        let exit_kills = self.command.exit_kills;
+        let tx = self.tx.clone();
+        let commands = self.commands.clone();
+
+        tokio::spawn(async move {
+            let (cmd, slot) = match tokio::spawn(self.run(spawned)).await {
+                Ok(stopped) => stopped,
+                Err(err) => {
+                    commands
+                        .lock()
+                        .unwrap()
+                        .panicked
+                        .get_or_insert(CommandSystemPanicked {
+                            command_index: Some(index),
+                            message: panic_message(err),
+                        });
+                    if !tx.is_closed() {
+                        let _ = tx.send(SystemEvent::Panicked).await;
+                    }
+                    return;
+                }
+            };
+
+            stopped_tx.send_replace(Some(cmd.clone()));
+            commands.lock().unwrap().stopped(cmd.clone(), optional);
+
+            if !tx.is_closed() {
+                let _ = tx.send(SystemEvent::Exited(cmd, slot, exit_kills)).await;
+            }
+        })
+    }
+}
+
+/// The command system has stopped, so no commands can be added
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandSystemStopped;
+
+impl std::fmt::Display for CommandSystemStopped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the command system has stopped")
+    }
+}
+
+impl std::error::Error for CommandSystemStopped {}
+
+/// A task of the command system panicked, see [`CommandSystem::wait`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandSystemPanicked {
+    /// The index of the command whose supervisor panicked, in the order commands were added.
+    /// `None` if the task killing commands panicked.
+    pub command_index: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for CommandSystemPanicked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.command_index {
+            Some(index) => write!(f, "the supervisor of command #{} panicked", index)?,
+            None => write!(f, "the task killing commands panicked")?,
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for CommandSystemPanicked {}
+
+/// A command the system waits for to be ready stopped before it was,
+/// see [`CommandSystem::wait_all_ready`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandNeverReady {
+    /// The index of the command, in the order of the config
+    pub command_index: usize,
+}
+
+impl std::fmt::Display for CommandNeverReady {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "command #{} stopped before it was ready",
+            self.command_index
+        )
+    }
+}
+
+impl std::error::Error for CommandNeverReady {}
+
+/// Adds commands to a running [`CommandSystem`].
+///
+/// A plugin receives it in [`CommandSystemPlugin::initialize_spawner`].
+pub struct CommandSpawner<T, P> {
+    commands: Arc<Mutex<CommandRegistry<T>>>,
+    plugin: Weak<P>,
+    tx: mpsc::Sender<SystemEvent<T>>,
+    shutdown: Arc<Shutdown>,
+    live: Arc<LiveCounts>,
+    events: Arc<EventSender<T>>,
+    started_at: Instant,
+}
+
+impl<T, P> Clone for CommandSpawner<T, P> {
+    fn clone(&self) -> Self {
+        Self {
+            commands: self.commands.clone(),
+            plugin: self.plugin.clone(),
+            tx: self.tx.clone(),
+            shutdown: self.shutdown.clone(),
+            live: self.live.clone(),
+            events: self.events.clone(),
+            started_at: self.started_at,
+        }
+    }
+}
+
+impl<T, P> CommandSpawner<T, P>
+where
+    T: Send + Sync + 'static,
+    P: CommandSystemPlugin<T>,
+    P::CommandInitialData: Clone + Send + Sync,
+{
+    /// Spawns `command` and handles it like the commands the system started with.
+    ///
+    /// Its exit counts for [`KillBehavior`] and it is killed with all other commands.
+    /// It isn't limited by [`RunConfig::max_concurrent`].
+    /// Its [events](CommandSystem::events) are labeled with its program.
+    /// Fails after all commands stopped or were killed.
+    pub async fn spawn(
+        &self,
+        mut command: Command,
+        data: P::CommandInitialData,
+    ) -> Result<(), CommandSystemStopped> {
+        let plugin = self.plugin.upgrade().ok_or(CommandSystemStopped)?;
+        plugin.rewrite_command(&data, &mut command);
+
+        let label = command
+            .as_std()
+            .get_program()
+            .to_string_lossy()
+            .into_owned();
+        let command = CommandToRun {
+            command: CommandInitialized::new(command, ()),
+            data,
+            label,
+            pane: None,
+            startup_retries: None,
+            spawn_retries: None,
+            after: vec![],
+            stop_when_ready: vec![],
+            after_ready_timeout: DEFAULT_AFTER_READY_TIMEOUT,
+            wait_for: None,
+            timeout: None,
+            restart: None,
+            restart_rate_limit: None,
+            early_exit_backoff: None,
+            repeat: 1,
+            schedule: None,
+            ready: None,
+            probe: None,
+            health: None,
+            on_failure: None,
+            watcher: None,
+            optional: false,
+            exit_kills: ExitKills::ByKillBehavior,
+            foreground: false,
+        };
+        self.add(plugin, command, None)?;
+
+        Ok(())
+    }
+
+    /// Adds `command` to the system and spawns it, once `replaces` stopped if given
+    fn add(
+        &self,
+        plugin: Arc<P>,
+        mut command: CommandToRun<P::CommandInitialData>,
+        replaces: Option<CommandHandle<T>>,
+    ) -> Result<CommandHandle<T>, CommandSystemStopped> {
+        let mut commands = self.commands.lock().unwrap();
+        // checked while holding the lock, so that kill_all either sees
+        // the new command or this call sees the shutdown
+        if commands.closed || self.shutdown.started() {
+            return Err(CommandSystemStopped);
+        }
+
+        let optional = command.optional;
+        let mut state = CommandState::Processing;
+        // commands which may retry spawning or wait for a port are spawned by their supervisor
+        let waits = command.wait_for.is_some()
+            || command
+                .schedule
+                .as_ref()
+                .is_some_and(Schedule::waits_for_first_run);
+        let spawned = match (&replaces, command.spawn_retries) {
+            (None, None) if !waits => Some(spawn_command(
+                &mut command,
+                &*plugin,
+                &mut state,
+                &self.live,
+                &self.events,
+            )),
+            _ => None,
+        };
+        let state = Arc::new(Mutex::new(state));
+        let (stopped_tx, stopped_rx) = watch::channel(None);
+        let control = Arc::new(SupervisorControl::default());
+        let command_handle = CommandHandle {
+            state: state.clone(),
+            stopped: stopped_rx,
+            control: control.clone(),
+        };
+
+        let supervisor = CommandSupervisor {
+            command,
+            state,
+            plugin,
+            started_at: self.started_at,
+            shutdown: self.shutdown.clone(),
+            control,
+            live: self.live.clone(),
+            events: self.events.clone(),
+            after: vec![],
+            after_ready: vec![],
+            stop_when_ready: vec![],
+            replaces,
+            slots: None,
+            slot: None,
+            queue: None,
+            tx: self.tx.clone(),
+            commands: self.commands.clone(),
+        };
+
+        let index = commands.command_count_total;
+        let handle = supervisor.start(index, spawned, stopped_tx);
+        commands.push(command_handle.clone(), handle, optional);
+
+        Ok(command_handle)
+    }
+}
+
+impl<T, P> CommandSpawner<T, P>
+where
+    T: Send + Sync + 'static,
+    P: CommandSystemPlugin<T, CommandInitialData = LabeledCommandData>,
+{
+    /// Spawns `cmd` like [`spawn_from_run_config_with_plugin`] spawns the command at `index`
+    /// of `run_config`, e.g. with its envs, and returns its handle and the file of its script.
+    ///
+    /// If `replaces` is given, `cmd` is spawned once that command stopped.
+    /// Prerequisites of `cmd` like [`after`](CommandConfig::after) are ignored,
+    /// and it isn't limited by [`RunConfig::max_concurrent`].
+    pub(super) fn spawn_config(
+        &self,
+        mut cmd: CommandConfig,
+        index: usize,
+        run_config: &RunConfig,
+        replaces: Option<CommandHandle<T>>,
+    ) -> Result<(CommandHandle<T>, Option<ScriptFile>), CommandSystemStopped> {
+        let plugin = self.plugin.upgrade().ok_or(CommandSystemStopped)?;
+
+        cmd.resolve_shell();
+        let script = run_script_from_file(&mut cmd);
+        let mut command = CommandDefaults::new(run_config).command_to_run(index, cmd);
+        plugin.rewrite_command(&command.data, command.command.command_mut());
+
+        let handle = self.add(plugin, command, replaces)?;
+        Ok((handle, script))
+    }
+}
+
+impl<T, P> CommandSystem<T, P>
+where
+    T: std::marker::Send + std::marker::Sync + 'static,
+    P: CommandSystemPlugin<T>,
+    P::CommandInitialData: Clone + Send + Sync,
+{
+    fn spawn_with_plugin<I>(commands: I, options: SystemOptions, plugin: P) -> Self
+    where
+        I: IntoIterator<Item = CommandToRun<P::CommandInitialData>>,
+    {
+        let SystemOptions {
+            kill_behavior,
+            exit_when_only_optional_remain,
+            shutdown_timeout,
+            kill_grace,
+            timeout,
+            kill_signal,
+            history_limit,
+            max_concurrent,
+            system_ready,
+            tail_lines,
+        } = options;
+        let commands: Vec<_> = commands.into_iter().collect();
+        let (tx, mut rx) = mpsc::channel(cmp::max(commands.len(), 1));
+
+        let plugin = Arc::new(plugin);
+        let started_at = Instant::now();
+        let shutdown = Arc::new(Shutdown::default());
+        let live = Arc::new(LiveCounts::default());
+        let events = Arc::new(EventSender::default());
+        let first_events = Mutex::new(Some(events.subscribe()));
+        if let Some(broadcast) = plugin.output_broadcast() {
+            let events = events.clone();
+            // the plugin owns the broadcast
+            let weak = Arc::downgrade(&plugin);
+            broadcast.forward(move |label, stream, line| {
+                events.send(CommandEvent::OutputLine {
+                    label: label.to_string(),
+                    stream,
+                    line: line.to_string(),
+                });
+                if let Some(plugin) = weak.upgrade() {
+                    plugin.on_output_line(label, stream, line);
+                }
+            });
+        }
+
+        let registry = Arc::new(Mutex::new(CommandRegistry::new(
+            commands.len(),
+            history_limit,
+        )));
+
+        let spawner = CommandSpawner {
+            commands: registry.clone(),
+            plugin: Arc::downgrade(&plugin),
+            tx: tx.clone(),
+            shutdown: shutdown.clone(),
+            live: live.clone(),
+            events: events.clone(),
+            started_at,
+        };
+        plugin.initialize_spawner(spawner.clone());
+
+        let (stopped_txs, stopped_rxs): (Vec<_>, Vec<_>) =
+            commands.iter().map(|_| watch::channel(None)).unzip();
+        let readies: Vec<_> = commands.iter().map(|cmd| cmd.ready.clone()).collect();
+        let mut initial_commands = Vec::with_capacity(commands.len());
+        let gating = match system_ready {
+            Some(i) => i..i + 1,
+            None => 0..readies.len(),
+        };
+        let ready = gating
+            .filter_map(|i| Some((i, readies[i].clone()?, stopped_rxs[i].clone())))
+            .collect();
+        let slots = max_concurrent.map(|max| Arc::new(Semaphore::new(max)));
+        let mut last_queued = None;
+
+        for ((mut command, stopped_tx), stopped_rx) in
+            commands.into_iter().zip(stopped_txs).zip(&stopped_rxs)
+        {
+            plugin.rewrite_command(&command.data, command.command.command_mut());
+
+            let optional = command.optional;
+            let mut state = CommandState::Processing;
+            let waits_for_others = !command.after.is_empty() || command.wait_for.is_some();
+            // the first commands take the free slots in order, the others wait for one
+            let slot = match (&slots, !waits_for_others) {
+                (Some(slots), true) => slots.clone().try_acquire_owned().ok(),
+                _ => None,
+            };
+            let has_slot = slots.is_none() || slot.is_some();
+            let queue = match (&slots, &slot, !waits_for_others) {
+                (Some(_), None, true) => {
+                    let (taken, next) = oneshot::channel();
+                    Some(QueuePosition {
+                        previous: last_queued.replace(next),
+                        _taken: taken,
+                    })
+                }
+                _ => None,
+            };
+            // commands which may retry spawning or wait for their schedule are spawned by
+            // their supervisor
+            let waits = command
+                .schedule
+                .as_ref()
+                .is_some_and(Schedule::waits_for_first_run);
+            let spawned =
+                if !waits_for_others && command.spawn_retries.is_none() && has_slot && !waits {
+                    Some(spawn_command(
+                        &mut command,
+                        &*plugin,
+                        &mut state,
+                        &live,
+                        &events,
+                    ))
+                } else {
+                    None
+                };
+            let state = Arc::new(Mutex::new(state));
+
+            let after = command
+                .after
+                .iter()
+                .map(|p| p.index.map(|i| stopped_rxs[i].clone()))
+                .collect();
+            let after_ready = command
+                .after
+                .iter()
+                .map(|p| p.index.and_then(|i| readies[i].clone()))
+                .collect();
+            let stop_when_ready = command
+                .stop_when_ready
+                .iter()
+                .map(|(label, i)| (label.clone(), readies[*i].clone(), stopped_rxs[*i].clone()))
+                .collect();
+            let control = Arc::new(SupervisorControl::default());
+            let command_handle = CommandHandle {
+                state: state.clone(),
+                stopped: stopped_rx.clone(),
+                control: control.clone(),
+            };
+            initial_commands.push((command.data.clone(), command_handle.clone()));
+
+            let supervisor = CommandSupervisor {
+                command,
+                state: state.clone(),
+                plugin: plugin.clone(),
+                started_at,
+                shutdown: shutdown.clone(),
+                control,
+                live: live.clone(),
+                events: events.clone(),
+                after,
+                after_ready,
+                stop_when_ready,
+                replaces: None,
+                slots: slots.clone(),
+                slot,
+                queue,
+                tx: tx.clone(),
+                commands: registry.clone(),
+            };
+
+            // locked before starting, so that the command is pushed before it stops
+            let mut commands = registry.lock().unwrap();
+            let index = commands.command_count_total;
+            let handle = supervisor.start(index, spawned, stopped_tx);
+            commands.push(command_handle, handle, optional);
+        }
+
+        plugin.on_system_started();
+
+        if let Some(timeout) = timeout {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = tokio::time::sleep(timeout) => {
+                        let _ = tx.send(SystemEvent::TimedOut(timeout)).await;
+                    }
+                    // the system stopped
+                    _ = tx.closed() => {}
+                }
+            });
+        }
+
+        let commands = registry.clone();
+        let shutdown_ret = shutdown.clone();
+        let live_ret = live.clone();
+        let plugin_ret = plugin.clone();
+        let killer_handle = tokio::spawn(async move {
+            let mut exited_command_count = 0;
+            while let Some(event) = rx.recv().await {
+                // released once the shutdown started, so that queued commands don't start then
+                let mut _slot = None;
+                // when commands still running are abandoned, see `kill_all_with_timeout`
+                let mut deadline = None;
+                let (reason, signal) = match event {
+                    SystemEvent::Panicked => {
+                        exited_command_count += 1;
+
+                        let mut commands = commands.lock().unwrap();
+                        if exited_command_count >= commands.command_count_total {
+                            commands.closed = true;
+                            break;
+                        }
+                        (Some(kill::KillCommandReason::SystemPanicked), None)
+                    }
+                    SystemEvent::Exited(exited_cmd, slot, exit_kills) => {
+                        _slot = slot;
+                        exited_command_count += 1;
+
+                        let mut commands = commands.lock().unwrap();
+                        if exited_command_count >= commands.command_count_total {
+                            commands.closed = true;
+                            break;
+                        }
+                        let only_optional_remain =
+                            exit_when_only_optional_remain && commands.only_optional_remain();
+                        let stats = commands.stats(&live);
+                        drop(commands);
+
+                        // the command was killed on its own, see `CommandHandle::kill`
+                        // and `CommandConfig::stop_when_ready`
+                        let requested = matches!(
+                            kill_reason(&exited_cmd),
+                            Some(kill::KillCommandReason::Requested)
+                                | Some(kill::KillCommandReason::StopWhenReady { .. })
+                        );
+                        let by_kill_behavior = only_optional_remain
+                            || !requested
+                                && match (exit_kills, &kill_behavior) {
+                                    (ExitKills::Always, _) => true,
+                                    // the loop ends once all commands exited
+                                    (ExitKills::Never, _)
+                                    | (_, KillBehavior::None | KillBehavior::WhenAllExited) => {
+                                        false
+                                    }
+                                    (_, KillBehavior::WhenAnyExited) => true,
+                                    (_, KillBehavior::WhenAnyExitedWithStatus(status)) => status
+                                        .matches(
+                                            exited_cmd.exit_status.as_ref().ok(),
+                                            exited_cmd.ran_for,
+                                        ),
+                                };
+
+                        if plugin.should_kill_all(&exited_cmd, &stats, by_kill_behavior) {
+                            (
+                                Some(kill::KillCommandReason::OtherCommandExited(exited_cmd)),
+                                None,
+                            )
+                        } else {
+                            (None, None)
+                        }
+                    }
+                    SystemEvent::KillAll(signal, abandon_after) => {
+                        deadline = abandon_after;
+                        (Some(kill::KillCommandReason::MainProcessGotSignal), signal)
+                    }
+                    SystemEvent::TimedOut(timeout) => {
+                        (Some(kill::KillCommandReason::TimedOut(timeout)), None)
+                    }
+                };
+
+                if let Some(reason) = reason {
+                    drop(rx);
+
+                    shutdown.start();
+
+                    // how long commands may take to exit after the signal
+                    let (signal, timeout) = match (signal, kill_grace) {
+                        (Some(signal), _) => (Some(signal), shutdown_timeout),
+                        (None, Some(kill_grace)) => {
+                            (Some(kill_signal.unwrap_or(Signal::Terminate)), kill_grace)
+                        }
+                        (None, None) => (kill_signal, shutdown_timeout),
+                    };
+
+                    for state in commands.lock().unwrap().states() {
+                        // a supervisor may have panicked while holding the lock
+                        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+
+                        if let CommandState::Spawned { killer, .. } = &mut *state {
+                            match signal {
+                                Some(signal) => killer.kill_with_signal(reason.clone(), signal),
+                                None => killer.kill(reason.clone()),
+                            };
+                        }
+                    }
+
+                    if let Some(deadline) = deadline {
+                        let commands = commands.clone();
+                        tokio::spawn(async move {
+                            tokio::time::sleep(deadline).await;
+
+                            for state in commands.lock().unwrap().states() {
+                                if let CommandState::Spawned { killer, .. } =
+                                    &*state.lock().unwrap_or_else(PoisonError::into_inner)
+                                {
+                                    killer.abandon();
+                                }
+                            }
+                        });
+                    }
+
+                    if signal.is_some_and(|signal| signal != Signal::Kill) {
+                        let commands = commands.clone();
+                        tokio::spawn(async move {
+                            tokio::time::sleep(timeout).await;
+
+                            for state in commands.lock().unwrap().states() {
+                                if let CommandState::Spawned { killer, .. } =
+                                    &*state.lock().unwrap_or_else(PoisonError::into_inner)
+                                {
+                                    killer.force_kill();
+                                }
+                            }
+                        });
+                    }
+
+                    break;
+                }
+            }
+        });
+
+        Self {
+            commands: registry.clone(),
+            killer: CommandSystemKiller {
+                tx,
+                commands: registry,
+            },
+            killer_handle: AsyncMutex::new(Some(killer_handle)),
+            plugin: plugin_ret,
+            shutdown: shutdown_ret,
+            shutdown_timeout,
+            live: live_ret,
+            ready,
+            initial_commands,
+            spawner,
+            events,
+            first_events,
+            scripts: Vec::new(),
+            tail_lines,
+        }
+    }
+}
+
+impl<T: Clone, P: CommandSystemPlugin<T>> CommandSystem<T, P> {
+    pub fn share_killer(&self) -> CommandSystemKiller<T> {
+        self.killer.clone()
+    }
+
+    /// Handles of all commands in the order they were added, including those added by a
+    /// [`CommandSpawner`], e.g. to kill a single command
+    pub fn command_handles(&self) -> Vec<CommandHandle<T>> {
+        self.commands.lock().unwrap().commands.clone()
+    }
+}
+
+impl<T, P: CommandSystemPlugin<T>> CommandSystem<T, P> {
+    pub async fn kill_all(&self) {
+        self.killer.kill_all().await;
+    }
+
+    pub async fn kill_all_with_signal(&self, signal: Signal) {
+        self.killer.kill_all_with_signal(signal).await;
+    }
+
+    /// Counters of commands of the running system, pruned commands are counted too
+    pub fn stats(&self) -> RunStats {
+        self.commands.lock().unwrap().stats(&self.live)
+    }
+
+    /// Receives the output lines of the command with `label` from now on,
+    /// after the recent lines the plugin kept if `replay_tail` is set.
+    ///
+    /// `None` if the plugin doesn't publish output, see
+    /// [`CommandSystemPlugin::output_broadcast`].
+    pub fn subscribe(&self, label: &Label, replay_tail: bool) -> Option<OutputSubscription> {
+        let broadcast = self.plugin.output_broadcast()?;
+        Some(broadcast.subscribe(label.label(), replay_tail))
+    }
+
+    /// Receives what happens to the commands, e.g. to show them without a plugin.
+    ///
+    /// The first receiver gets the events since the system was spawned, later ones the events
+    /// from now on. A receiver which falls behind by more than 1024 events misses the oldest
+    /// ones, see [`RecvError::Lagged`](tokio::sync::broadcast::error::RecvError::Lagged).
+    pub fn events(&self) -> tokio::sync::broadcast::Receiver<CommandEvent<T>> {
+        let first = self.first_events.lock().unwrap().take();
+        first.unwrap_or_else(|| self.events.subscribe())
+    }
+
+    /// The last output lines of the command with `label`, oldest first and across its runs,
+    /// e.g. to show why it crashed. At most [`RunConfig::tail_lines`] of those the plugin's
+    /// broadcast keeps.
+    ///
+    /// Empty unless the plugin publishes the lines it reads, see
+    /// [`CommandSystemPlugin::output_broadcast`].
+    pub fn tail(&self, label: &Label) -> Vec<OutputEvent> {
+        let mut lines = match self.plugin.output_broadcast() {
+            Some(broadcast) => broadcast.tail(label.label()),
+            None => return vec![],
+        };
+        lines.drain(..lines.len().saturating_sub(self.tail_lines));
+        lines
+    }
+
+    /// Commands which stopped for good, oldest first.
+    ///
+    /// With [`RunConfig::history_limit`], only the most recent ones are kept.
+    pub fn history(&self) -> Vec<Arc<CommandStopped<T, T>>> {
+        self.commands
+            .lock()
+            .unwrap()
+            .history
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Waits until all commands stopped and the plugin finished
+    async fn join(&mut self) -> Result<(), CommandSystemPanicked> {
+        let Self {
+            commands,
+            killer_handle,
+            plugin,
+            shutdown,
+            shutdown_timeout,
+            ..
+        } = self;
+
+        let mut killer_handle = killer_handle.lock().await;
+
+        if let Some(killer_handle) = killer_handle.take() {
+            if let Err(err) = killer_handle.await {
+                // nothing else kills the commands anymore
+                shutdown.start();
+                let mut commands = commands.lock().unwrap();
+                commands.closed = true;
+                for state in commands.states() {
+                    let state = state.lock().unwrap_or_else(PoisonError::into_inner);
+                    if let CommandState::Spawned { killer, .. } = &*state {
+                        killer.kill(kill::KillCommandReason::SystemPanicked);
+                    }
+                }
+                commands.panicked.get_or_insert(CommandSystemPanicked {
+                    command_index: None,
+                    message: panic_message(err),
+                });
+            }
+
+            // no commands can be added once the killer stopped
+            let handles = mem::take(&mut commands.lock().unwrap().handles);
+            for handle in handles {
+                // supervisors catch panics of the commands they run
+                let _ = handle.await;
+            }
+        }
+
+        drop(killer_handle);
+
+        if let Some(plugin_join) = plugin.join() {
+            // processes spawned by killed commands may keep their output open
+            if shutdown.started() {
+                let _ = tokio::time::timeout(*shutdown_timeout, plugin_join).await;
+            } else {
+                let _ = plugin_join.await;
+            }
+        }
+
+        match &commands.lock().unwrap().panicked {
+            Some(panicked) => Err(panicked.clone()),
+            None => Ok(()),
+        }
+    }
+
+    async fn wait_iter_stopped_commands<'a, R, F>(
+        &'a mut self,
+        cmd_processor: F,
+    ) -> Result<impl Iterator<Item = R> + 'a, CommandSystemPanicked>
+    where
+        F: 'a + Fn(&Arc<CommandStopped<T, T>>) -> R,
+    {
+        self.join().await?;
+
+        let commands = self.commands.lock().unwrap().commands.clone();
+        Ok(commands.into_iter().map(move |cmd| {
+            let cmd = cmd.state.lock().unwrap();
+
+            match &*cmd {
+                CommandState::Stopped(cmd) => cmd_processor(cmd),
+                _ => panic!("CommandState should be stopped after handles joined"),
+            }
+        }))
+    }
+
+    /// Resolves once the system is ready: when the command of
+    /// [`RunConfig::system_ready_when`] is, otherwise once every command with
+    /// [`ready_when`](crate::CommandConfig::ready_when) or a startup probe was ready.
+    ///
+    /// Doesn't borrow the system, so it may be awaited alongside [`wait`](Self::wait).
+    /// Fails once such a command stops for good without becoming ready.
+    pub fn wait_all_ready(
+        &self,
+    ) -> impl Future<Output = Result<(), CommandNeverReady>> + Send + 'static
+    where
+        T: Send + Sync + 'static,
+    {
+        let ready = self.ready.clone();
+        async move {
+            for (command_index, signal, mut stopped) in ready {
+                tokio::select! {
+                    biased;
+                    _ = signal.wait() => {}
+                    _ = wait_stopped(&mut stopped) => {
+                        if !signal.is_ready() {
+                            return Err(CommandNeverReady { command_index });
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Waits until all commands stopped, pruned commands are counted too.
+    ///
+    /// Fails if a task of the system panicked, e.g. in a callback of the plugin.
+    /// All commands are killed then.
+    pub async fn wait(&mut self) -> Result<CommandSystemSimpleReport, CommandSystemPanicked> {
+        self.join().await?;
+
+        let commands = self.commands.lock().unwrap();
+        Ok(CommandSystemSimpleReport {
+            command_count_total: commands.command_count_total,
+            command_count_success: commands.command_count_success,
+            command_count_killed: commands.command_count_killed,
+            first_failed_code: commands.first_failed_code,
+            last_exit_code: commands.last_exit_code,
+            last_failed: commands.last_failed,
+            exit_code_policy: Default::default(),
+        })
+    }
+
+    /// Waits until all commands stopped and returns the ones which weren't pruned.
+    ///
+    /// Fails like [`wait`](Self::wait).
+    pub async fn wait_into_stopped_commands(
+        &mut self,
+    ) -> Result<Vec<Arc<CommandStopped<T, T>>>, CommandSystemPanicked> {
+        let commands = self.wait_iter_stopped_commands(Arc::clone).await?.collect();
+
+        Ok(commands)
+    }
+}
+
+impl<P: CommandSystemPlugin<LabeledCommandData>> CommandSystem<LabeledCommandData, P> {
+    /// Shows `new_label` instead of `label` in the output of the command from now on,
+    /// e.g. a role the command reported, see [`Label::relabel`].
+    ///
+    /// Returns whether a command with `label` was spawned.
+    pub fn set_label(&self, label: &str, new_label: &str) -> bool {
+        for state in self.commands.lock().unwrap().states() {
+            let state = state.lock().unwrap_or_else(PoisonError::into_inner);
+            let data = match &*state {
+                CommandState::Spawned { data, .. } => data,
+                CommandState::Stopped(cmd) => &cmd.data,
+                CommandState::Processing => continue,
+            };
+            if data.label.label() == label {
+                data.label.relabel(new_label);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Labels of the commands, in the order they were added, with the process id of their
+    /// current run, `None` if one isn't running. Commands not spawned yet are left out.
+    pub fn pids(&self) -> Vec<(Label, Option<u32>)> {
+        let handles = self.command_handles();
+        let pids = handles.iter().filter_map(|handle| {
+            let data = handle.data()?;
+            Some((data.label, handle.pid()))
+        });
+        pids.collect()
+    }
+
+    /// Kills the command with `label`, see [`CommandHandle::kill`].
+    ///
+    /// `None` if no spawned command has `label`.
+    pub fn kill_one(&self, label: &Label) -> Option<kill::KillResult> {
+        Some(self.command_handle(label)?.kill())
+    }
+
+    /// Restarts the command with `label`, see [`CommandHandle::restart`].
+    ///
+    /// `None` if no spawned command has `label`.
+    pub fn restart_one(&self, label: &Label) -> Option<kill::KillResult> {
+        Some(self.command_handle(label)?.restart())
+    }
+
+    /// The most recently added command with `label`,
+    /// e.g. the one replacing a reloaded command
+    fn command_handle(&self, label: &Label) -> Option<CommandHandle<LabeledCommandData>> {
+        let handles = self.command_handles();
+        handles.into_iter().rev().find(|handle| {
+            let data = handle.data();
+            data.is_some_and(|data| data.label.label() == label.label())
+        })
+    }
+}
+
+impl<P> CommandSystem<LabeledCommandData, P>
+where
+    P: CommandSystemPlugin<LabeledCommandData, CommandInitialData = LabeledCommandData>,
+{
+    /// Waits until the command with `label` stopped for good, without waiting for the others.
+    ///
+    /// `None` if no command the system was spawned with has `label`,
+    /// commands added by a [`CommandSpawner`] aren't found.
+    pub async fn wait_for(
+        &self,
+        label: &Label,
+    ) -> Option<Arc<CommandStopped<LabeledCommandData, LabeledCommandData>>> {
+        let (_, handle) = self
+            .initial_commands
+            .iter()
+            .find(|(data, _)| data.label.label() == label.label())?;
+        handle.wait().await
+    }
+
+    /// Applies changes of `config` to the running commands, see [`Reloader::reload`].
+    ///
+    /// `config` must be the config the system was spawned from.
+    pub fn reloader(&self, config: RunConfig) -> Reloader<LabeledCommandData, P> {
+        let commands = self.initial_commands.iter();
+        let commands = commands.map(|(_, handle)| handle.clone()).collect();
+        Reloader::new(self.spawner.clone(), config, commands)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LabeledCommandData {
+    pub label: Label,
+    /// Position of the command in [`RunConfig::commands`], which picks the color of its label
+    pub index: usize,
+    /// See [`CommandConfig::pane`](crate::CommandConfig::pane)
+    pub pane: Option<String>,
+    /// See [`CommandConfig::cwd`](crate::CommandConfig::cwd)
+    pub cwd: Option<String>,
+    /// See [`CommandConfig::log_file`](crate::CommandConfig::log_file)
+    pub log_file: Option<String>,
+    /// See [`CommandConfig::success_message`](crate::CommandConfig::success_message)
+    pub success_message: Option<String>,
+    /// See [`CommandConfig::failure_message`](crate::CommandConfig::failure_message)
+    pub failure_message: Option<String>,
+    /// See [`CommandConfig::ready_when`](crate::CommandConfig::ready_when)
+    pub ready: Option<ReadySignal>,
+    /// See [`CommandConfig::binary_safe`](crate::CommandConfig::binary_safe)
+    pub binary_safe: bool,
+    /// See [`CommandConfig::stdout`](crate::CommandConfig::stdout)
+    pub stdout: StreamRoute,
+    /// See [`CommandConfig::stderr`](crate::CommandConfig::stderr)
+    pub stderr: StreamRoute,
+    /// See [`CommandConfig::quiet_exit`](crate::CommandConfig::quiet_exit)
+    pub quiet_exit: bool,
+    /// See [`CommandConfig::encoding`](crate::CommandConfig::encoding)
+    pub encoding: OutputEncoding,
+    /// See [`CommandConfig::label_from`](crate::CommandConfig::label_from)
+    pub label_from: Option<LineLabel>,
+    /// See [`CommandConfig::progress_from`](crate::CommandConfig::progress_from)
+    pub progress: Option<Progress>,
+    /// See [`CommandConfig::ignore`](crate::CommandConfig::ignore)
+    pub line_filter: Option<LineFilter>,
+}
+
+#[cfg(test)]
+impl LabeledCommandData {
+    /// The first command, labeled `label` and without any options
+    pub(crate) fn labeled(label: &str) -> Self {
+        Self {
+            label: Label::from_label(label.to_string(), 10),
+            index: 0,
+            pane: None,
+            cwd: None,
+            log_file: None,
+            success_message: None,
+            failure_message: None,
+            ready: None,
+            binary_safe: false,
+            stdout: Default::default(),
+            stderr: Default::default(),
+            quiet_exit: false,
+            encoding: OutputEncoding::Utf8,
+            label_from: None,
+            progress: None,
+            line_filter: None,
+        }
+    }
+}
+
+/// Runs the [`script`](crate::CommandConfig::script) of `cmd` from a file, which is removed
+/// once the returned file is dropped. `None` if `cmd` has no script.
+pub(crate) fn run_script_from_file(cmd: &mut CommandConfig) -> Option<ScriptFile> {
+    cmd.script.as_ref()?;
+    cmd.label = Some(cmd.resolved_label());
+    let script = cmd.script.take().unwrap_or_default();
+    let (program, args, file) = match ScriptFile::create(&script) {
+        Ok(file) => {
+            let (program, args) = file.program_and_args();
+            (program, args, Some(file))
+        }
+        // the shell still runs the script, without its shebang
+        Err(_) if cfg!(windows) => ("cmd".to_string(), vec!["/C".to_string(), script], None),
+        Err(_) => ("sh".to_string(), vec!["-c".to_string(), script], None),
+    };
+    cmd.program = program;
+    cmd.args = (!args.is_empty()).then_some(args);
+    file
+}
+
+/// Options of a [`RunConfig`] which apply to each of its commands
+struct CommandDefaults {
+    envs: Option<HashMap<String, String>>,
+    max_label_length: usize,
+    /// The width all labels pad to, see [`RunConfig::label_width`]
+    label_width: Option<usize>,
+    shutdown_timeout: Option<Duration>,
+    kill_process_groups: bool,
+    pty: bool,
+    pipe_stdin: bool,
+    early_exit_backoff: Option<EarlyExitBackoff>,
+    foreground: Option<String>,
+    /// Ctrl-C on the terminal only reaches runcc and the interactive command then
+    interactive_gets_ctrl_c: bool,
+    /// The foreground command only gets the terminal if runcc has it
+    takes_terminal: bool,
+    /// Indexes of the commands by label
+    indexes: HashMap<String, usize>,
+}
+
+impl CommandDefaults {
+    fn new(run_config: &RunConfig) -> Self {
+        let commands = &run_config.commands;
+
+        #[cfg(unix)]
+        let interactive_gets_ctrl_c =
+            run_config.ctrl_c_to_foreground && commands.iter().any(|cmd| cmd.interactive);
+        #[cfg(not(unix))]
+        let interactive_gets_ctrl_c = false;
+
+        #[cfg(unix)]
+        let takes_terminal = run_config.foreground.is_some() && super::terminal::owns_terminal();
+        #[cfg(not(unix))]
+        let takes_terminal = false;
+
+        let mut indexes = HashMap::with_capacity(commands.len());
+        for (i, cmd) in commands.iter().enumerate() {
+            indexes.entry(cmd.resolved_label()).or_insert(i);
+        }
+        // computed before any command is spawned, so that all labels pad to the same width
+        let label_width = run_config.label_width.as_ref().map(|width| {
+            let lengths = commands.iter().map(|cmd| cmd.resolved_label().len());
+            width.resolve(lengths, run_config.max_label_length)
+        });
+
+        Self {
+            envs: run_config.envs.clone(),
+            max_label_length: run_config.max_label_length,
+            label_width,
+            shutdown_timeout: run_config.shutdown_timeout,
+            kill_process_groups: run_config.kill_process_groups,
+            pty: run_config.pty,
+            pipe_stdin: run_config.pipe_stdin,
+            early_exit_backoff: run_config.early_exit_backoff,
+            foreground: run_config.foreground.clone(),
+            interactive_gets_ctrl_c,
+            takes_terminal,
+            indexes,
+        }
+    }
+
+    /// `cmd` at `index` of the commands, ready to be spawned
+    fn command_to_run(
+        &self,
+        index: usize,
+        mut cmd: CommandConfig,
+    ) -> CommandToRun<LabeledCommandData> {
+        #[cfg(not(unix))]
+        let _ = (
+            self.interactive_gets_ctrl_c,
+            self.kill_process_groups,
+            self.pty,
+        );
+
+        let pane = cmd.pane.take();
+        let log_file = cmd.log_file.take();
+        let success_message = cmd.success_message.take();
+        let failure_message = cmd.failure_message.take();
+        let restart = cmd.restart.take();
+        let restart_rate_limit = cmd.restart_rate_limit.take();
+        let watch = cmd.watch.take();
+        let watcher = if cmd.watch_dir || watch.is_some() {
+            let globs = |globs: Option<Vec<String>>| -> Vec<_> {
+                globs
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|glob| glob::Pattern::new(glob).expect("watch globs should be valid"))
+                    .collect()
+            };
+            let dir = PathBuf::from(cmd.cwd.as_deref().unwrap_or("."));
+            Some(DirWatcher::new(
+                dir,
+                globs(match (watch, cmd.watch_include.take()) {
+                    (Some(watch), Some(include)) => Some([watch, include].concat()),
+                    (watch, include) => watch.or(include),
+                }),
+                globs(cmd.watch_exclude.take()),
+            ))
+        } else {
+            None
+        };
+        let pattern = cmd
+            .ready_when
+            .take()
+            .map(|pattern| Regex::new(&pattern).expect("ready_when should be a valid regex"));
+        let probe = cmd
+            .startup_probe
+            .take()
+            .map(|probe| ProbeRunner::new(probe, cmd.cwd.clone(), self.envs.clone()));
+        let health = cmd
+            .healthcheck
+            .take()
+            .map(|check| HealthChecker::new(check, cmd.cwd.clone(), self.envs.clone()));
+        let on_failure = cmd
+            .on_failure
+            .take()
+            .map(|hook| HookRunner::new(hook, cmd.cwd.clone(), self.envs.clone()));
+        let ready = if pattern.is_some() || probe.is_some() {
+            Some(ReadySignal::with_pattern(pattern))
+        } else {
+            None
+        };
+        let startup_retries = cmd.startup_retries.take();
+        let spawn_retries = cmd.spawn_retries.take();
+        let early_exit_backoff = cmd.early_exit_backoff.take().or(self.early_exit_backoff);
+        let repeat = cmd.repeat.unwrap_or(1);
+        let schedule = Schedule::of(&cmd);
+        let optional = cmd.optional;
+        let exit_kills = if cmd.critical {
+            ExitKills::Always
+        } else if cmd.transient || schedule.is_some() {
+            ExitKills::Never
+        } else {
+            ExitKills::ByKillBehavior
+        };
+        let binary_safe = cmd.binary_safe;
+        let (stdout, stderr) = (cmd.stdout_route(), cmd.stderr_route());
+        let quiet_exit = cmd.quiet_exit;
+        let color = cmd.color;
+        let cwd = cmd.cwd.clone();
+        let progress = cmd.progress_from.take().map(|pattern| {
+            Progress::new(Regex::new(&pattern).expect("progress_from should be a valid regex"))
+        });
+        let label_from = cmd.label_from.take().map(|extractor| {
+            extractor
+                .compile()
+                .expect("label_from should be a valid regex")
+        });
+        let line_filter =
+            LineFilter::of(&cmd).expect("ignore and show_only should be valid regexes");
+        let encoding = match cmd.encoding.take() {
+            Some(name) => OutputEncoding::from_name(&name).expect("encoding should be known"),
+            None => OutputEncoding::Utf8,
+        };
+        let after_ready = cmd.after_ready.take().unwrap_or_default();
+        let after = cmd
+            .after
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|label| (label, false))
+            .chain(after_ready.into_iter().map(|label| (label, true)))
+            .map(|(label, ready)| Prerequisite {
+                index: self.indexes.get(&label).copied(),
+                label,
+                ready,
+            })
+            .collect();
+        // labels of no command are reported by `RunConfig::validate`
+        let stop_when_ready = cmd
+            .stop_when_ready
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|label| Some((label.clone(), *self.indexes.get(&label)?)))
+            .collect();
+        let after_ready_timeout = cmd
+            .after_ready_timeout
+            .unwrap_or(DEFAULT_AFTER_READY_TIMEOUT);
+        let wait_for = cmd.wait_for.take();
+        let command_timeout = cmd.timeout;
+        let is_foreground = self.foreground.as_deref() == Some(cmd.resolved_label().as_str());
+        let interactive = cmd.interactive;
+        let kill_signal = cmd.kill_signal;
+        #[cfg(unix)]
+        let pty = self.pty || cmd.pty;
+        #[cfg(windows)]
+        let limits = cmd.limits.clone();
+        #[allow(unused_mut)]
+        let (mut cmd, label) = cmd.into_tokio_command_and_label(self.envs.as_ref());
+
+        // the other commands don't get signals of the terminal, e.g. of Ctrl-C
+        #[cfg(unix)]
+        if is_foreground {
+            unsafe { cmd.pre_exec(super::terminal::take_terminal) };
+        } else if self.foreground.is_some()
+            || (self.interactive_gets_ctrl_c || self.kill_process_groups) && !interactive
+        {
+            unsafe { cmd.pre_exec(super::terminal::own_process_group) };
+        }
+        let mut command = CommandInitialized::new(cmd, ());
+        #[cfg(unix)]
+        if self.kill_process_groups && (is_foreground || !interactive) {
+            command = command.kill_process_group();
+        }
+        // the interactive command gets Ctrl-C of the console like runcc
+        #[cfg(windows)]
+        if !interactive {
+            command = command.new_process_group();
+        }
+        #[cfg(windows)]
+        if let Some(limits) = limits {
+            command = command.limits(limits);
+        }
+        #[cfg(unix)]
+        if pty {
+            command = command.pty();
+        }
+        if is_foreground || interactive {
+            command = command.inherit_stdin();
+        } else if self.pipe_stdin {
+            command = command.pipe_stdin();
+        }
+        if let Some(signal) = kill_signal {
+            let timeout = self.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT);
+            command = command.kill_signal(signal, timeout);
+        }
+
+        CommandToRun {
+            command,
+            label: label.clone(),
+            pane: pane.clone(),
+            data: LabeledCommandData {
+                label: Label::from_label_with_width(label, self.max_label_length, self.label_width)
+                    .with_color(color),
+                index,
+                pane,
+                cwd,
+                log_file,
+                success_message,
+                failure_message,
+                ready: ready.clone(),
+                binary_safe,
+                stdout,
+                stderr,
+                quiet_exit,
+                encoding,
+                label_from,
+                progress,
+                line_filter,
+            },
+            startup_retries,
+            spawn_retries,
+            after,
+            stop_when_ready,
+            after_ready_timeout,
+            wait_for,
+            timeout: command_timeout,
+            restart,
+            restart_rate_limit,
+            early_exit_backoff,
+            repeat,
+            schedule,
+            ready,
+            probe,
+            health,
+            on_failure,
+            watcher,
+            optional,
+            exit_kills,
+            foreground: is_foreground && self.takes_terminal,
+        }
+    }
+}
+
+/// Spawns all commands of `run_config`.
+///
+/// The [`before`](RunConfig::before) and [`after`](RunConfig::after) steps are not run,
+/// run them with [`run_steps`](crate::run::run_steps) before spawning and once the system
+/// stopped, like the CLI does.
+///
+/// # Panics
+///
+/// Panics if `ready_when`, `label_from`, `progress_from`, `ignore`, `show_only`, watch globs
+/// or the encoding of a command are invalid, which [`RunConfig::validate`] reports.
+pub fn spawn_from_run_config_with_plugin<T, P>(
+    run_config: RunConfig,
+    plugin: P,
+) -> CommandSystem<T, P>
+where
+    T: Send + Sync + 'static,
+    P: CommandSystemPlugin<T, CommandInitialData = LabeledCommandData>,
+{
+    let defaults = CommandDefaults::new(&run_config);
+    let RunConfig {
+        mut commands,
+        kill,
+        exit_when_only_optional_remain,
+        shutdown_timeout,
+        kill_grace,
+        timeout,
+        kill_signal,
+        history_limit,
+        max_concurrent,
+        system_ready_when,
+        tail_lines,
+        ..
+    } = run_config;
+
+    for cmd in commands.iter_mut() {
+        cmd.resolve_shell();
+    }
+    // scripts are run from files which are removed once the system is dropped
+    let scripts = commands
+        .iter_mut()
+        .filter_map(run_script_from_file)
+        .collect();
+
+    let commands = commands
+        .into_iter()
+        .enumerate()
+        .map(|(index, cmd)| defaults.command_to_run(index, cmd));
+
+    let options = SystemOptions {
+        kill_behavior: kill,
+        exit_when_only_optional_remain,
+        shutdown_timeout: shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT),
+        kill_grace,
+        timeout,
+        kill_signal,
+        history_limit,
+        max_concurrent,
+        system_ready: system_ready_when.and_then(|label| defaults.indexes.get(&label).copied()),
+        tail_lines: tail_lines.unwrap_or(0),
+    };
+    let mut system = CommandSystem::spawn_with_plugin(commands, options, plugin);
+    system.scripts = scripts;
+    system
+}
+
+pub trait CommandSystemPlugin<T>: Send + Sync + 'static + Sized {
+    type CommandInitialData;
+
+    /// Called once for each command before it is spawned for the first time.
+    ///
+    /// The plugin may change args and envs or replace the command entirely,
+    /// e.g. to run it through a wrapper program.
+    fn rewrite_command(&self, _data: &Self::CommandInitialData, _command: &mut Command) {}
+
+    /// Called once all commands without prerequisites were spawned.
+    fn on_system_started(&self) {}
+
+    /// Called once before any command is spawned.
+    ///
+    /// The plugin may keep `spawner` to add commands while the system is running.
+    fn initialize_spawner(&self, _spawner: CommandSpawner<T, Self>) {}
+
+    fn initialize_spawn_failed_command_data(&self, data: Self::CommandInitialData) -> T;
+
+    /// Called when a command is spawned with its captured output.
+    ///
+    /// Reaching the end of `stdout` or `stderr` doesn't mean the command exited.
+    /// A command is stopped only when its process exits.
+    fn initialize_command_data(
+        &self,
+        data: Self::CommandInitialData,
         stdout: ChildStdout,
         stderr: ChildStderr,
     ) -> T;
 
-    fn on_command_exited(&self, _cmd: Arc<CommandStopped<T, T>>) {}
+    /// Called each time a command was spawned, including restarts,
+    /// right after [`initialize_command_data`](Self::initialize_command_data).
+    ///
+    /// It is called before [`on_command_exited`](Self::on_command_exited) of the same run.
+    /// `pid` is `None` if the process already exited and was waited for.
+    fn on_command_started(&self, _data: &T, _pid: Option<u32>) {}
+
+    /// Called with each output line the plugin publishes to its
+    /// [`output_broadcast`](Self::output_broadcast), after the system got it
+    fn on_output_line(&self, _label: &str, _stream: OutputStream, _line: &str) {}
+
+    fn on_command_exited(&self, _cmd: Arc<CommandStopped<T, T>>) {}
+
+    /// Called when a command restarted too often and waits for `cooldown` before it is
+    /// restarted again, see [`RestartRateLimit`]
+    fn on_restarts_paused(&self, _data: &Self::CommandInitialData, _cooldown: Duration) {}
+
+    /// Called when a command failed early and is spawned again after `delay`, as `attempt` of
+    /// at most `attempts`, see [`RunConfig::early_exit_backoff`]
+    fn on_early_exit(
+        &self,
+        _data: &Self::CommandInitialData,
+        _attempt: u32,
+        _attempts: u32,
+        _delay: Duration,
+    ) {
+    }
+
+    /// Called when `attempt` of the `attempts` of the startup probe of a command failed.
+    ///
+    /// Once all of them failed the command is killed, see [`KillCommandReason::ProbeFailed`](kill::KillCommandReason::ProbeFailed).
+    fn on_probe_failed(&self, _data: &Self::CommandInitialData, _attempt: u32, _attempts: u32) {}
+
+    /// Called when `failures` health checks of a command failed in a row.
+    ///
+    /// The command is restarted then, see [`KillCommandReason::Unhealthy`](kill::KillCommandReason::Unhealthy),
+    /// unless its [`HealthCheck::restart`](crate::HealthCheck::restart) is `false`.
+    fn on_unhealthy(&self, _data: &Self::CommandInitialData, _failures: u32) {}
+
+    /// Called when a command stopped for good, decides whether to kill all other commands.
+    ///
+    /// `by_kill_behavior` is what [`KillBehavior`] and
+    /// [`exit_when_only_optional_remain`](RunConfig::exit_when_only_optional_remain) decided,
+    /// which is returned by default. `stats` already count the exited command.
+    fn should_kill_all(
+        &self,
+        _exited: &CommandStopped<T, T>,
+        _stats: &RunStats,
+        by_kill_behavior: bool,
+    ) -> bool {
+        by_kill_behavior
+    }
+
+    fn join(&self) -> Option<JoinHandle<()>> {
+        None
+    }
+
+    /// Where the plugin publishes the output lines it reads,
+    /// for [`CommandSystem::subscribe`]
+    fn output_broadcast(&self) -> Option<&OutputBroadcast> {
+        None
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+        process::{ChildStderr, ChildStdout, Command},
+        sync::Notify,
+    };
+
+    use super::{
+        kill, kill_reason, spawn_from_run_config_with_plugin, CommandOutcome, CommandSpawner,
+        CommandState, CommandStopped, CommandSystemPlugin, LabeledCommandData, RunStats,
+    };
+    use crate::run::{CommandEvent, OutputBroadcast, OutputEvent, OutputStream};
+    use crate::run::{CommandSystemObserver, PluginStack};
+    use crate::{
+        label::Label, CommandConfig, EarlyExitBackoff, KillBehavior, LabelWidth, RestartPolicy,
+        RunConfig, Signal, StartupRetries,
+    };
+
+    #[derive(Default)]
+    struct RecordPlugin(Mutex<Vec<LabeledCommandData>>);
+
+    impl CommandSystemPlugin<LabeledCommandData> for Arc<RecordPlugin> {
+        type CommandInitialData = LabeledCommandData;
+
+        fn initialize_spawn_failed_command_data(
+            &self,
+            data: Self::CommandInitialData,
+        ) -> LabeledCommandData {
+            data
+        }
+
+        fn initialize_command_data(
+            &self,
+            data: Self::CommandInitialData,
+            _stdout: ChildStdout,
+            _stderr: ChildStderr,
+        ) -> LabeledCommandData {
+            self.0.lock().unwrap().push(data.clone());
+            data
+        }
+    }
+
+    /// Records when commands started and exited
+    #[derive(Default)]
+    struct LifecyclePlugin(Mutex<Vec<(String, Option<u32>)>>);
+
+    impl CommandSystemPlugin<()> for Arc<LifecyclePlugin> {
+        type CommandInitialData = LabeledCommandData;
+
+        fn initialize_spawn_failed_command_data(&self, _data: Self::CommandInitialData) {}
+
+        fn initialize_command_data(
+            &self,
+            _data: Self::CommandInitialData,
+            _stdout: ChildStdout,
+            _stderr: ChildStderr,
+        ) {
+        }
+
+        fn on_command_started(&self, _data: &(), pid: Option<u32>) {
+            self.0.lock().unwrap().push(("started".to_string(), pid));
+        }
+
+        fn on_command_exited(&self, _cmd: Arc<CommandStopped<(), ()>>) {
+            self.0.lock().unwrap().push(("exited".to_string(), None));
+        }
+    }
+
+    fn sh(script: &str) -> CommandConfig {
+        CommandConfig::from_program_args(
+            "sh".to_string(),
+            Some(vec!["-c".to_string(), script.to_string()]),
+        )
+    }
+
+    fn run_config(commands: Vec<CommandConfig>) -> RunConfig {
+        RunConfig {
+            commands,
+            before: vec![],
+            after: vec![],
+            max_label_length: 10,
+            label_width: None,
+            envs: None,
+            env_file: None,
+            computed_env: Default::default(),
+            secrets: None,
+            kill: Default::default(),
+            exit_when_only_optional_remain: false,
+            fail_on_skip: false,
+            profiles: Default::default(),
+            ctrl_c_signal: None,
+            ctrl_c_to_foreground: false,
+            shutdown_timeout: None,
+            kill_grace: None,
+            timeout: None,
+            kill_signal: None,
+            kill_process_groups: false,
+            pty: false,
+            pipe_stdin: false,
+            collapse_repeats: false,
+            interleave_guard: false,
+            group_output: false,
+            partial_line_flush: None,
+            timestamps: None,
+            defer_output_until_started: false,
+            output_backpressure: Default::default(),
+            summary_order: Default::default(),
+            exit_code: Default::default(),
+            max_buffer_memory: None,
+            log_file_max_size: None,
+            foreground: None,
+            history_limit: None,
+            tail_lines: None,
+            max_concurrent: None,
+            early_exit_backoff: None,
+            system_ready_when: None,
+            color_seed: None,
+            restart_separator: Default::default(),
+            show_silent_commands: true,
+            colorize_log_levels: false,
+            log_level_colors: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn pane_flows_to_plugin_and_events() {
+        let mut in_pane = CommandConfig::from_program_args("true".to_string(), None);
+        in_pane.pane = Some("backend".to_string());
+        let no_pane = CommandConfig::from_program_args("true".to_string(), None);
+
+        let plugin = Arc::new(RecordPlugin::default());
+        let mut system =
+            spawn_from_run_config_with_plugin(run_config(vec![in_pane, no_pane]), plugin.clone());
+        let mut events = system.events();
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert_eq!(stopped[0].data.pane.as_deref(), Some("backend"));
+        assert_eq!(stopped[1].data.pane, None);
+
+        let mut panes: Vec<_> = plugin
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|d| d.pane.clone())
+            .collect();
+        panes.sort();
+        assert_eq!(panes, [None, Some("backend".to_string())]);
+
+        let mut spawned = vec![];
+        while let Ok(event) = events.try_recv() {
+            if let CommandEvent::Spawned { label, pane, .. } = event {
+                spawned.push((label, pane));
+            }
+        }
+        spawned.sort();
+        assert_eq!(
+            spawned,
+            [
+                ("true".to_string(), None),
+                ("true".to_string(), Some("backend".to_string()))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn auto_label_width() {
+        let labeled = |label: &str| {
+            let mut cmd = CommandConfig::from_program_args("true".to_string(), None);
+            cmd.label = Some(label.to_string());
+            cmd
+        };
+        let mut config = run_config(vec![labeled("web"), labeled("worker")]);
+        config.max_label_length = 20;
+        config.label_width = Some(LabelWidth::Auto);
+
+        let mut system =
+            spawn_from_run_config_with_plugin(config, Arc::new(RecordPlugin::default()));
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert_eq!(stopped[0].data.label.display(), "web   ");
+        assert_eq!(stopped[1].data.label.display(), "worker");
+    }
+
+    #[tokio::test]
+    async fn startup_retries_within_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("count");
+        // fails on the first two runs, then succeeds
+        let mut cmd = sh(&format!(
+            "n=$(cat {0} 2>/dev/null || echo 0); n=$((n+1)); echo $n > {0}; [ $n -gt 2 ]",
+            counter.display()
+        ));
+        cmd.startup_retries = Some(StartupRetries {
+            max_retries: 5,
+            within: Duration::from_secs(10),
+        });
+
+        let plugin = Arc::new(RecordPlugin::default());
+        let mut system = spawn_from_run_config_with_plugin(run_config(vec![cmd]), plugin.clone());
+        let report = system.wait().await.unwrap();
+
+        assert_eq!(report.command_count_success, 1);
+        assert_eq!(plugin.0.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn startup_retries_after_window() {
+        let mut cmd = sh("sleep 0.3; exit 1");
+        cmd.startup_retries = Some(StartupRetries {
+            max_retries: 5,
+            within: Duration::from_millis(100),
+        });
+
+        let plugin = Arc::new(RecordPlugin::default());
+        let mut system = spawn_from_run_config_with_plugin(run_config(vec![cmd]), plugin.clone());
+        let report = system.wait().await.unwrap();
+
+        assert_eq!(report.command_count_failed(), 1);
+        assert_eq!(plugin.0.lock().unwrap().len(), 1);
+    }
+
+    fn early_exit_backoff(base: Duration, max_attempts: u32) -> EarlyExitBackoff {
+        EarlyExitBackoff {
+            within: Duration::from_secs(5),
+            base,
+            multiplier: 2,
+            max_delay: Duration::from_secs(10),
+            max_attempts,
+        }
+    }
+
+    #[tokio::test]
+    async fn early_exits_back_off() {
+        let mut config = run_config(vec![sh("exit 1")]);
+        config.early_exit_backoff = Some(early_exit_backoff(Duration::from_millis(50), 3));
+
+        let started = std::time::Instant::now();
+        let plugin = Arc::new(RecordPlugin::default());
+        let mut system = spawn_from_run_config_with_plugin(config, plugin.clone());
+        let report = system.wait().await.unwrap();
+
+        assert_eq!(report.command_count_failed(), 1);
+        assert_eq!(plugin.0.lock().unwrap().len(), 4);
+        // 50ms, 100ms and 200ms
+        assert!(started.elapsed() >= Duration::from_millis(350));
+    }
+
+    #[tokio::test]
+    async fn kill_all_ends_early_exit_backoff() {
+        let mut config = run_config(vec![sh("exit 1")]);
+        config.early_exit_backoff = Some(early_exit_backoff(Duration::from_secs(10), 3));
+
+        let started = std::time::Instant::now();
+        let plugin = Arc::new(RecordPlugin::default());
+        let mut system = spawn_from_run_config_with_plugin(config, plugin.clone());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        system.kill_all().await;
+        system.wait().await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert_eq!(plugin.0.lock().unwrap().len(), 1);
+    }
+
+    /// Records the attempts of [`CommandSystemPlugin::on_early_exit`] by label
+    #[derive(Clone, Default)]
+    struct EarlyExitPlugin(Arc<Mutex<Vec<(String, u32, u32)>>>);
+
+    impl CommandSystemPlugin<()> for EarlyExitPlugin {
+        type CommandInitialData = LabeledCommandData;
+
+        fn initialize_spawn_failed_command_data(&self, _data: Self::CommandInitialData) {}
+
+        fn initialize_command_data(
+            &self,
+            _data: Self::CommandInitialData,
+            _stdout: ChildStdout,
+            _stderr: ChildStderr,
+        ) {
+        }
+
+        fn on_early_exit(
+            &self,
+            data: &Self::CommandInitialData,
+            attempt: u32,
+            attempts: u32,
+            _delay: Duration,
+        ) {
+            let label = data.label.label().to_string();
+            self.0.lock().unwrap().push((label, attempt, attempts));
+        }
+    }
+
+    #[tokio::test]
+    async fn command_early_exit_backoff_retries_spawn_failures() {
+        let mut missing = CommandConfig::from_program_args("runcc-missing".to_string(), None);
+        missing.early_exit_backoff = Some(early_exit_backoff(Duration::from_millis(20), 2));
+        let mut config = run_config(vec![missing, sh("exit 1")]);
+        config.early_exit_backoff = Some(early_exit_backoff(Duration::from_millis(20), 1));
+
+        let plugin = EarlyExitPlugin::default();
+        let mut system = spawn_from_run_config_with_plugin(config, plugin.clone());
+        let report = system.wait().await.unwrap();
+
+        assert_eq!(report.command_count_failed(), 2);
+        let mut attempts = plugin.0.lock().unwrap().clone();
+        attempts.sort();
+        let attempt = |label: &str, attempt, attempts| (label.to_string(), attempt, attempts);
+        assert_eq!(
+            attempts,
+            [
+                attempt("runcc-missing", 2, 3),
+                attempt("runcc-missing", 3, 3),
+                attempt("sh -c exit 1", 2, 2)
+            ]
+        );
+    }
+
+    struct WrapPlugin(std::path::PathBuf);
+
+    impl CommandSystemPlugin<LabeledCommandData> for WrapPlugin {
+        type CommandInitialData = LabeledCommandData;
+
+        fn rewrite_command(&self, data: &LabeledCommandData, command: &mut Command) {
+            let std_command = command.as_std();
+            let mut wrapped = Command::new("sh");
+            wrapped
+                .arg("-c")
+                .arg(format!(
+                    "echo {} >> {}; exec \"$@\"",
+                    data.label.label(),
+                    self.0.display()
+                ))
+                .arg("wrapper")
+                .arg(std_command.get_program())
+                .args(std_command.get_args());
+            *command = wrapped;
+        }
+
+        fn initialize_spawn_failed_command_data(
+            &self,
+            data: Self::CommandInitialData,
+        ) -> LabeledCommandData {
+            data
+        }
+
+        fn initialize_command_data(
+            &self,
+            data: Self::CommandInitialData,
+            _stdout: ChildStdout,
+            _stderr: ChildStderr,
+        ) -> LabeledCommandData {
+            data
+        }
+    }
+
+    #[tokio::test]
+    async fn rewrite_command_with_wrapper() {
+        let dir = tempfile::tempdir().unwrap();
+        let wrapper_log = dir.path().join("wrapper.log");
+
+        let mut cmd = CommandConfig::from_program_args(
+            "sh".to_string(),
+            Some(vec!["-c".to_string(), "exit 3".to_string()]),
+        );
+        cmd.label = Some("wrapped".to_string());
+
+        let mut system = spawn_from_run_config_with_plugin(
+            run_config(vec![cmd]),
+            WrapPlugin(wrapper_log.clone()),
+        );
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert_eq!(stopped[0].exit_status.as_ref().unwrap().code(), Some(3));
+        assert_eq!(std::fs::read_to_string(wrapper_log).unwrap(), "wrapped\n");
+    }
+
+    #[tokio::test]
+    async fn after_waits_for_success() {
+        let dir = tempfile::tempdir().unwrap();
+        let artifact = dir.path().join("artifact");
+
+        let mut build = sh(&format!("sleep 0.2; echo built > {}", artifact.display()));
+        build.label = Some("build".to_string());
+        let mut serve = sh(&format!("test -f {}", artifact.display()));
+        serve.label = Some("serve".to_string());
+        serve.after = Some(vec!["build".to_string()]);
+
+        let plugin = Arc::new(RecordPlugin::default());
+        let mut system =
+            spawn_from_run_config_with_plugin(run_config(vec![serve, build]), plugin.clone());
+        let report = system.wait().await.unwrap();
+
+        assert_eq!(report.command_count_success, 2);
+    }
+
+    #[tokio::test]
+    async fn max_concurrent_queues_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let running = dir.path().join("running");
+        std::fs::create_dir(&running).unwrap();
+        let counts = dir.path().join("counts");
+
+        // each command records how many commands run, including itself
+        let commands = (0..5)
+            .map(|i| {
+                let mut cmd = sh(&format!(
+                    "touch {dir}/{i}; ls {dir} | wc -l >> {counts}; sleep 0.1; rm {dir}/{i}",
+                    dir = running.display(),
+                    i = i,
+                    counts = counts.display()
+                ));
+                cmd.label = Some(i.to_string());
+                cmd
+            })
+            .collect();
+        let mut config = run_config(commands);
+        config.max_concurrent = Some(2);
+
+        let plugin = Arc::new(RecordPlugin::default());
+        let mut system = spawn_from_run_config_with_plugin(config, plugin.clone());
+        let report = system.wait().await.unwrap();
+
+        assert_eq!(report.command_count_success, 5);
+        let counts = std::fs::read_to_string(&counts).unwrap();
+        assert_eq!(counts.lines().count(), 5);
+        assert!(counts
+            .lines()
+            .all(|count| count.trim().parse::<usize>().unwrap() <= 2));
+        let spawned: Vec<_> = plugin
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|data| data.label.label().to_string())
+            .collect();
+        assert_eq!(spawned, ["0", "1", "2", "3", "4"]);
+    }
+
+    #[tokio::test]
+    async fn max_concurrent_queued_commands_never_start_after_kill_all() {
+        let mut config = run_config(vec![sh("true"), sh("sleep 5"), sh("sleep 5")]);
+        config.max_concurrent = Some(1);
+        config.kill = KillBehavior::WhenAnyExited;
+
+        let plugin = Arc::new(RecordPlugin::default());
+        let mut system = spawn_from_run_config_with_plugin(config, plugin.clone());
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(stopped[0].success());
+        for queued in &stopped[1..] {
+            assert_eq!(queued.skipped().unwrap().0, "killed before it started");
+        }
+        assert_eq!(plugin.0.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn repeat_runs_exactly_n_times() {
+        let dir = tempfile::tempdir().unwrap();
+        let runs = dir.path().join("runs");
+
+        // the second run fails
+        let mut bench = sh(&format!(
+            "echo run >> {runs}; test $(wc -l < {runs}) -ne 2",
+            runs = runs.display()
+        ));
+        bench.repeat = Some(3);
+        let config = run_config(vec![bench, sh("true")]);
+
+        let plugin = Arc::new(RecordPlugin::default());
+        let mut system = spawn_from_run_config_with_plugin(config, plugin.clone());
+        let report = system.wait().await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(&runs).unwrap().lines().count(), 3);
+        assert_eq!(report.command_count_total, 4);
+        assert_eq!(report.command_count_success, 3);
+        assert_eq!(report.command_count_failed(), 1);
+        assert_eq!(system.history().len(), 4);
+        assert_eq!(system.stats().restart_count, 0);
+    }
+
+    #[tokio::test]
+    async fn every_runs_periodically_until_killed() {
+        let dir = tempfile::tempdir().unwrap();
+        let runs = dir.path().join("runs");
+
+        // a failing run neither ends the schedule nor kills the server
+        let mut backup = sh(&format!("echo run >> {}; exit 1", runs.display()));
+        backup.every = Some(Duration::from_millis(100));
+        let config = run_config(vec![backup, sh("sleep 10")]);
+
+        let plugin = Arc::new(RecordPlugin::default());
+        let mut system = spawn_from_run_config_with_plugin(config, plugin.clone());
+        tokio::time::sleep(Duration::from_millis(450)).await;
+        system.kill_all().await;
+        let report = system.wait().await.unwrap();
+
+        let run_count = std::fs::read_to_string(&runs).unwrap().lines().count();
+        assert!((4..=6).contains(&run_count), "{} runs", run_count);
+        // each run counts on its own, next to the killed server
+        assert_eq!(report.command_count_total, run_count + 1);
+        assert_eq!(report.command_count_failed(), run_count);
+    }
+
+    #[tokio::test]
+    async fn after_skips_when_prerequisite_fails() {
+        let mut build = sh("exit 1");
+        build.label = Some("build".to_string());
+        let mut serve = sh("true");
+        serve.label = Some("serve".to_string());
+        serve.after = Some(vec!["build".to_string()]);
+
+        let plugin = Arc::new(RecordPlugin::default());
+        let mut system =
+            spawn_from_run_config_with_plugin(run_config(vec![build, serve]), plugin.clone());
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(!stopped[0].success());
+        assert!(stopped[1].skipped().is_some());
+        // only build was spawned
+        assert_eq!(plugin.0.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn on_command_started_before_exited() {
+        let dir = tempfile::tempdir().unwrap();
+        let pids = dir.path().join("pids");
+        let mut cmd = sh(&format!("echo $$ >> {}", pids.display()));
+        cmd.restart = Some(RestartPolicy::Always {
+            max: Some(1),
+            backoff_schedule: vec![],
+        });
+
+        let plugin = Arc::new(LifecyclePlugin::default());
+        let mut system = spawn_from_run_config_with_plugin(run_config(vec![cmd]), plugin.clone());
+        system.wait().await.unwrap();
+
+        let pids: Vec<u32> = std::fs::read_to_string(&pids)
+            .unwrap()
+            .lines()
+            .map(|pid| pid.parse().unwrap())
+            .collect();
+        let events = plugin.0.lock().unwrap();
+        let event = |name: &str, pid| (name.to_string(), pid);
+        assert_eq!(
+            *events,
+            [
+                event("started", Some(pids[0])),
+                event("exited", None),
+                event("started", Some(pids[1])),
+                event("exited", None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn spawn_retries_until_binary_exists() {
+        let dir = tempfile::tempdir().unwrap();
+        let tool = dir.path().join("tool");
+
+        // the binary is written while the first spawn fails
+        let mut build = sh(&format!(
+            "sleep 0.1; printf '#!/bin/sh\\nexit 0\\n' > {0}.tmp; chmod +x {0}.tmp; mv {0}.tmp {0}",
+            tool.display()
+        ));
+        build.label = Some("build".to_string());
+        let mut run = CommandConfig::from_program_args(tool.display().to_string(), None);
+        run.label = Some("run".to_string());
+        run.spawn_retries = Some(crate::SpawnRetries {
+            max_retries: 20,
+            delay: Duration::from_millis(50),
+        });
+        let mut missing =
+            CommandConfig::from_program_args("runcc-missing-binary".to_string(), None);
+        missing.spawn_retries = Some(crate::SpawnRetries {
+            max_retries: 2,
+            delay: Duration::from_millis(10),
+        });
+
+        let plugin = Arc::new(RecordPlugin::default());
+        let config = run_config(vec![build, run, missing]);
+        let mut system = spawn_from_run_config_with_plugin(config, plugin.clone());
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+        let stats = system.stats();
+
+        assert!(stopped[0].success());
+        assert!(stopped[1].success());
+        // gave up, failed attempts don't count as restarts
+        assert!(stopped[2].exit_status.is_err());
+        assert!(stopped[2].skipped().is_none());
+        assert_eq!(stats.restart_count, 0);
+        // only build and run were spawned, each once
+        assert_eq!(plugin.0.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn after_ready_waits_for_ready_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("listening");
+
+        let mut api = sh(&format!(
+            "sleep 0.2; touch {}; echo 'Listening on 8080'; sleep 0.5",
+            marker.display()
+        ));
+        api.label = Some("api".to_string());
+        api.ready_when = Some("^Listening on".to_string());
+        let mut web = sh(&format!("test -f {}", marker.display()));
+        web.label = Some("web".to_string());
+        web.after_ready = Some(vec!["api".to_string()]);
+
+        let mut system = spawn_from_run_config_with_plugin(run_config(vec![web, api]), ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        // web ran while api was still running
+        assert!(stopped[0].success());
+        assert!(stopped[1].success());
+    }
+
+    #[tokio::test]
+    async fn stop_when_ready_stops_only_that_command() {
+        let labeled = |script, label: &str| {
+            let mut cmd = sh(script);
+            cmd.label = Some(label.to_string());
+            cmd
+        };
+        let mut mock = labeled("sleep 5", "mock");
+        mock.stop_when_ready = Some(vec!["client".to_string()]);
+        let mut client = labeled("sleep 0.2; echo connected; sleep 0.3", "client");
+        client.ready_when = Some("^connected$".to_string());
+        let mut config = run_config(vec![mock, client]);
+        config.kill = KillBehavior::WhenAnyExited;
+
+        let started = Instant::now();
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        // mock stopped once client was ready, which kept running though any exit kills all
+        assert!(matches!(
+            kill_reason(&stopped[0]),
+            Some(kill::KillCommandReason::StopWhenReady { label, exited: false }) if label == "client"
+        ));
+        assert!(stopped[1].success());
+    }
+
+    #[tokio::test]
+    async fn system_ready_when_gates_readiness() {
+        let ready_when = |script, label: &str| {
+            let mut cmd = sh(script);
+            cmd.label = Some(label.to_string());
+            cmd.ready_when = Some("^healthy$".to_string());
+            cmd
+        };
+        // db never becomes ready, the health check does
+        let commands = vec![
+            ready_when("sleep 5", "db"),
+            ready_when("echo healthy; sleep 5", "health"),
+        ];
+        let mut config = run_config(commands);
+        config.system_ready_when = Some("health".to_string());
+
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let ready = tokio::time::timeout(Duration::from_secs(2), system.wait_all_ready()).await;
+        assert_eq!(ready, Ok(Ok(())));
+
+        system.kill_all().await;
+        system.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn wait_all_ready_fails_when_never_ready() {
+        let ready_when = |script, label: &str| {
+            let mut cmd = sh(script);
+            cmd.label = Some(label.to_string());
+            cmd.ready_when = Some("^healthy$".to_string());
+            cmd
+        };
+        // db exits without becoming ready
+        let commands = vec![
+            ready_when("echo healthy; sleep 5", "web"),
+            ready_when("echo starting", "db"),
+        ];
+
+        let mut system = spawn_from_run_config_with_plugin(run_config(commands), ReadyPlugin);
+        let ready = tokio::time::timeout(Duration::from_secs(2), system.wait_all_ready()).await;
+        assert_eq!(
+            ready,
+            Ok(Err(super::CommandNeverReady { command_index: 1 }))
+        );
+
+        system.kill_all().await;
+        system.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn after_ready_skips_when_not_ready() {
+        let ready_when = |script, label: &str| {
+            let mut cmd = sh(script);
+            cmd.label = Some(label.to_string());
+            cmd.ready_when = Some("^Listening on".to_string());
+            cmd
+        };
+        let after_ready = |label: &str| {
+            let mut cmd = sh("true");
+            cmd.after_ready = Some(vec![label.to_string()]);
+            cmd.after_ready_timeout = Some(Duration::from_millis(200));
+            cmd
+        };
+        let commands = vec![
+            ready_when("exit 0", "crashing"),
+            ready_when("sleep 1", "hanging"),
+            after_ready("crashing"),
+            after_ready("hanging"),
+        ];
+
+        let started_at = std::time::Instant::now();
+        let mut system = spawn_from_run_config_with_plugin(run_config(commands), ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert_eq!(
+            stopped[2].skipped().unwrap().0,
+            "command[crashing] exited before it was ready"
+        );
+        assert_eq!(
+            stopped[3].skipped().unwrap().0,
+            "command[hanging] wasn't ready within 200ms"
+        );
+        assert!(started_at.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn wait_for_port_until_it_accepts_connections() {
+        let free_port = || {
+            let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let (open, closed) = (free_port(), free_port());
+        let wait_for = |port| {
+            let mut cmd = sh("true");
+            cmd.wait_for = Some(crate::PortWait {
+                port: crate::PortNumber::Number(port),
+                host: "127.0.0.1".to_string(),
+                timeout: Duration::from_millis(500),
+            });
+            cmd
+        };
+        let listener = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let listener = tokio::net::TcpListener::bind(("127.0.0.1", open)).await;
+            let _accepted = listener.unwrap().accept().await;
+        });
+
+        let commands = vec![wait_for(open), wait_for(closed)];
+        let mut system = spawn_from_run_config_with_plugin(run_config(commands), ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+        listener.abort();
+
+        assert!(stopped[0].success());
+        assert_eq!(
+            stopped[1].skipped().unwrap().0,
+            format!(
+                "127.0.0.1:{} didn't accept connections within 500ms",
+                closed
+            )
+        );
+    }
+
+    #[derive(Default)]
+    struct DrainPlugin(Arc<Notify>);
+
+    impl CommandSystemPlugin<()> for DrainPlugin {
+        type CommandInitialData = LabeledCommandData;
+
+        fn initialize_spawn_failed_command_data(&self, _data: Self::CommandInitialData) {}
+
+        fn initialize_command_data(
+            &self,
+            _data: Self::CommandInitialData,
+            mut stdout: ChildStdout,
+            _stderr: ChildStderr,
+        ) {
+            let eof = self.0.clone();
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let _ = stdout.read_to_end(&mut buf).await;
+                eof.notify_one();
+            });
+        }
+    }
+
+    /// Publishes each stdout line
+    #[derive(Default)]
+    struct BroadcastPlugin(Arc<OutputBroadcast>);
+
+    impl CommandSystemPlugin<()> for BroadcastPlugin {
+        type CommandInitialData = LabeledCommandData;
+
+        fn initialize_spawn_failed_command_data(&self, _data: Self::CommandInitialData) {}
+
+        fn initialize_command_data(
+            &self,
+            data: Self::CommandInitialData,
+            stdout: ChildStdout,
+            _stderr: ChildStderr,
+        ) {
+            let broadcast = self.0.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stdout).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    broadcast.publish(data.label.label(), OutputStream::Stdout, &line);
+                }
+                broadcast.end(data.label.label());
+            });
+        }
+
+        fn output_broadcast(&self) -> Option<&OutputBroadcast> {
+            Some(&self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn tail_keeps_last_lines() {
+        let mut printer = sh("for i in 1 2 3 4; do echo $i; done; exit 1");
+        printer.label = Some("printer".to_string());
+        let mut config = run_config(vec![printer]);
+        config.tail_lines = Some(2);
+        // the broadcast keeps more lines than the system returns
+        let plugin = BroadcastPlugin(Arc::new(OutputBroadcast::default()));
+        let mut system = spawn_from_run_config_with_plugin(config, plugin);
+        system.wait().await.unwrap();
+
+        let label = Label::from_label("printer".to_string(), 7);
+        // the plugin may still read the last lines after the exit
+        let deadline = Instant::now() + Duration::from_secs(3);
+        while system.tail(&label).last().map(|event| event.line.as_str()) != Some("4") {
+            assert!(Instant::now() < deadline, "{:?}", system.tail(&label));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let lines: Vec<_> = system.tail(&label).into_iter().map(|e| e.line).collect();
+        assert_eq!(lines, ["3", "4"]);
+    }
+
+    /// Records what it observes, prefixed with its name
+    struct RecordObserver(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl CommandSystemObserver<()> for RecordObserver {
+        fn on_command_started(&self, _data: &(), _pid: Option<u32>) {
+            self.1.lock().unwrap().push(format!("{} started", self.0));
+        }
+
+        fn on_output_line(&self, label: &str, _stream: OutputStream, line: &str) {
+            self.1
+                .lock()
+                .unwrap()
+                .push(format!("{} [{}] {}", self.0, label, line));
+        }
+
+        fn on_command_exited(&self, _cmd: &Arc<CommandStopped<(), ()>>) {
+            self.1.lock().unwrap().push(format!("{} exited", self.0));
+        }
+    }
+
+    /// The lines two observers next to `plugin` got, sorted
+    async fn observed_lines<P>(plugin: P) -> Vec<String>
+    where
+        P: CommandSystemPlugin<(), CommandInitialData = LabeledCommandData>,
+    {
+        let mut printer = sh("echo one; echo two; echo oops >&2");
+        printer.label = Some("printer".to_string());
+        let records = Arc::new(Mutex::new(vec![]));
+        let plugin = PluginStack::new(plugin)
+            .with(RecordObserver("metrics", records.clone()))
+            .with(RecordObserver("audit", records.clone()));
+        let mut system = spawn_from_run_config_with_plugin(run_config(vec![printer]), plugin);
+        system.wait().await.unwrap();
+
+        let records = records.lock().unwrap();
+        let of = |name: &str| -> Vec<_> {
+            let prefix = format!("{} ", name);
+            let mut lines: Vec<_> = records
+                .iter()
+                .filter_map(|record| record.strip_prefix(&prefix))
+                .filter(|record| record.starts_with('['))
+                .map(str::to_string)
+                .collect();
+            lines.sort();
+            lines
+        };
+        assert_eq!(of("audit"), of("metrics"));
+        let position = |record: &str| records.iter().position(|r| r == record).unwrap();
+        assert!(position("metrics started") < position("audit started"));
+        assert!(position("metrics exited") < position("audit exited"));
+        of("metrics")
+    }
+
+    #[tokio::test]
+    async fn plugin_stack_shares_output_lines() {
+        let lines = ["[printer] one", "[printer] oops", "[printer] two"];
+        // the plugin reads stdout without publishing it, and never reads stderr
+        assert_eq!(observed_lines(DrainPlugin::default()).await, lines);
+        // lines the plugin publishes aren't observed twice
+        assert_eq!(observed_lines(BroadcastPlugin::default()).await, lines);
+    }
+
+    #[tokio::test]
+    async fn events_of_commands() {
+        let events = [
+            "printer spawned",
+            "sleeper spawned",
+            "printer exited with Some(3)",
+            "sleeper killed",
+            "sleeper exited with None",
+        ];
+        assert_eq!(command_events(BroadcastPlugin::default()).await, events);
+        // the system reads the lines of a plugin which doesn't publish them
+        assert_eq!(command_events(DrainPlugin::default()).await, events);
+    }
+
+    /// The events of a command printing a line and one which is killed, without the line
+    async fn command_events<P>(plugin: P) -> Vec<String>
+    where
+        P: CommandSystemPlugin<(), CommandInitialData = LabeledCommandData>,
+    {
+        let mut printer = sh("echo hello; exit 3");
+        printer.label = Some("printer".to_string());
+        let mut sleeper = sh("exec sleep 5");
+        sleeper.label = Some("sleeper".to_string());
+        let mut system =
+            spawn_from_run_config_with_plugin(run_config(vec![printer, sleeper]), plugin);
+        let mut events = system.events();
+
+        let mut seen = vec![];
+        let mut exited = 0;
+        while exited < 2 {
+            let event = tokio::time::timeout(Duration::from_secs(3), events.recv());
+            let event = event.await.unwrap().unwrap();
+            let label = event.label().to_string();
+            seen.push(match event {
+                CommandEvent::Spawned { pid, .. } => {
+                    assert!(pid.is_some());
+                    format!("{} spawned", label)
+                }
+                CommandEvent::OutputLine { stream, line, .. } => {
+                    assert_eq!(stream, OutputStream::Stdout);
+                    format!("{}: {}", label, line)
+                }
+                CommandEvent::KilledBecause { reason, .. } => {
+                    assert!(matches!(reason, kill::KillCommandReason::Requested));
+                    format!("{} killed", label)
+                }
+                CommandEvent::Exited { status, .. } => {
+                    exited += 1;
+                    format!("{} exited with {:?}", label, status.and_then(|s| s.code()))
+                }
+            });
+            if seen.last().unwrap() == "printer exited with Some(3)" {
+                system.command_handles()[1].kill();
+            }
+        }
+
+        // the output is read on its own, so lines may follow the exit
+        let output = seen.iter().position(|event| event == "printer: hello");
+        seen.remove(output.unwrap());
+        system.wait().await.unwrap();
+        seen
+    }
+
+    #[tokio::test]
+    async fn write_lines_to_piped_stdin() {
+        let mut cmd = sh("read first; echo got $first; read second; echo got $second");
+        cmd.label = Some("reader".to_string());
+        let mut config = run_config(vec![cmd, sh("exit 0")]);
+        config.pipe_stdin = true;
+        let label = Label::new("reader".to_string(), None);
+
+        let mut system = spawn_from_run_config_with_plugin(config, BroadcastPlugin::default());
+        let mut lines = system.subscribe(&label, true).unwrap();
+        let handles = system.command_handles();
+        while handles[0].pid().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(handles[0].write_line("one"));
+        assert!(handles[0].write_line("two"));
+        let line = |line: &str| {
+            Some(OutputEvent {
+                stream: OutputStream::Stdout,
+                line: line.to_string(),
+            })
+        };
+        assert_eq!(lines.recv().await, line("got one"));
+        assert_eq!(lines.recv().await, line("got two"));
+
+        system.wait().await.unwrap();
+        assert!(!handles[0].write_line("three"));
+    }
+
+    #[tokio::test]
+    async fn pty_output() {
+        let mut cmd = sh("[ -t 1 ] && [ -t 2 ] && echo terminal; echo error >&2");
+        cmd.label = Some("cargo".to_string());
+        cmd.pty = true;
+        let label = Label::new("cargo".to_string(), None);
+
+        let mut system =
+            spawn_from_run_config_with_plugin(run_config(vec![cmd]), BroadcastPlugin::default());
+        let line = |line: &str| {
+            Some(OutputEvent {
+                stream: OutputStream::Stdout,
+                line: line.to_string(),
+            })
+        };
+
+        // stderr is read from stdout too
+        let mut lines = system.subscribe(&label, true).unwrap();
+        assert_eq!(lines.recv().await, line("terminal"));
+        assert_eq!(lines.recv().await, line("error"));
+        system.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pty_window_size() {
+        let mut cmd = sh("trap 'stty size <&1' WINCH; stty size <&1; while :; do sleep 0.01; done");
+        cmd.label = Some("top".to_string());
+        cmd.pty = true;
+        let label = Label::new("top".to_string(), None);
+
+        let mut system =
+            spawn_from_run_config_with_plugin(run_config(vec![cmd]), BroadcastPlugin::default());
+        let line = |line: String| {
+            Some(OutputEvent {
+                stream: OutputStream::Stdout,
+                line,
+            })
+        };
+
+        // the size of the terminal of runcc at spawn, then the size it is resized to
+        let mut lines = system.subscribe(&label, true).unwrap();
+        let size = super::super::pty::window_size();
+        let spawned_size = format!("{} {}", size.ws_row, size.ws_col);
+        assert_eq!(lines.recv().await, line(spawned_size));
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        size.ws_row = 30;
+        size.ws_col = 100;
+        super::super::pty::resize_windows(size);
+        assert_eq!(lines.recv().await, line("30 100".to_string()));
+
+        system.command_handles()[0].kill();
+        system.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn subscribe_to_running_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let go = dir.path().join("go");
+        let mut cmd = sh(&format!(
+            "echo early; while [ ! -f {go} ]; do sleep 0.01; done; echo late",
+            go = go.display()
+        ));
+        cmd.label = Some("web".to_string());
+        let label = Label::new("web".to_string(), None);
+
+        let mut system =
+            spawn_from_run_config_with_plugin(run_config(vec![cmd]), BroadcastPlugin::default());
+        let line = |line: &str| {
+            Some(OutputEvent {
+                stream: OutputStream::Stdout,
+                line: line.to_string(),
+            })
+        };
+
+        let mut from_start = system.subscribe(&label, true).unwrap();
+        assert_eq!(from_start.recv().await, line("early"));
+
+        let mut live = system.subscribe(&label, false).unwrap();
+        let mut with_tail = system.subscribe(&label, true).unwrap();
+        std::fs::write(&go, "").unwrap();
+        assert_eq!(live.recv().await, line("late"));
+        assert_eq!(with_tail.recv().await, line("early"));
+        assert_eq!(with_tail.recv().await, line("late"));
+
+        // the output ends once it was read to the end, also for subscribers after that
+        assert_eq!(live.recv().await, None);
+        assert_eq!(with_tail.recv().await, None);
+        system.wait().await.unwrap();
+        let mut ended = system.subscribe(&label, true).unwrap();
+        assert_eq!(ended.recv().await, line("early"));
+        assert_eq!(ended.recv().await, line("late"));
+        assert_eq!(ended.recv().await, None);
+        assert!(!ended.is_closed());
+    }
+
+    #[tokio::test]
+    async fn closed_stdout_is_still_running() {
+        let plugin = DrainPlugin::default();
+        let eof = plugin.0.clone();
+        let mut system = spawn_from_run_config_with_plugin(
+            run_config(vec![sh("echo ready; exec >&-; sleep 0.5")]),
+            plugin,
+        );
+
+        eof.notified().await;
+
+        assert!(matches!(
+            *system.commands.lock().unwrap().commands[0]
+                .state
+                .lock()
+                .unwrap(),
+            CommandState::Spawned { .. }
+        ));
+
+        let report = system.wait().await.unwrap();
+        assert_eq!(report.command_count_success, 1);
+    }
+
+    /// Spawns `worker` when a command prints `spawn worker`
+    struct SpawnPlugin {
+        spawner: Mutex<Option<CommandSpawner<(), Self>>>,
+        worker: String,
+    }
+
+    impl CommandSystemPlugin<()> for SpawnPlugin {
+        type CommandInitialData = LabeledCommandData;
+
+        fn initialize_spawner(&self, spawner: CommandSpawner<(), Self>) {
+            *self.spawner.lock().unwrap() = Some(spawner);
+        }
+
+        fn initialize_spawn_failed_command_data(&self, _data: Self::CommandInitialData) {}
+
+        fn initialize_command_data(
+            &self,
+            _data: Self::CommandInitialData,
+            stdout: ChildStdout,
+            _stderr: ChildStderr,
+        ) {
+            let spawner = self.spawner.lock().unwrap().clone().unwrap();
+            let worker = self.worker.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stdout).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line == "spawn worker" {
+                        let mut command = Command::new("sh");
+                        command.arg("-c").arg(&worker);
+                        let data = LabeledCommandData {
+                            index: 1,
+                            ..LabeledCommandData::labeled("worker")
+                        };
+                        spawner.spawn(command, data).await.unwrap();
+                    }
+                }
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn plugin_spawns_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+
+        let plugin = SpawnPlugin {
+            spawner: Mutex::new(None),
+            worker: format!("echo done > {}", out.display()),
+        };
+        let mut system = spawn_from_run_config_with_plugin(
+            run_config(vec![sh("echo 'spawn worker'; sleep 0.5")]),
+            plugin,
+        );
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert_eq!(stopped.len(), 2);
+        assert!(stopped.iter().all(|cmd| cmd.success()));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "done\n");
+    }
+
+    #[tokio::test]
+    async fn kill_all_with_signal() {
+        let dir = tempfile::tempdir().unwrap();
+        let ready = dir.path().join("ready");
+        let trapped = dir.path().join("trapped");
+
+        let cmd = sh(&format!(
+            "trap 'echo INT > {}; exit 3' INT; touch {}; sleep 2 & wait",
+            trapped.display(),
+            ready.display()
+        ));
+
+        let mut system = spawn_from_run_config_with_plugin(
+            run_config(vec![cmd]),
+            Arc::new(RecordPlugin::default()),
+        );
+
+        while !ready.exists() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        system.kill_all_with_signal(Signal::Interrupt).await;
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert_eq!(stopped[0].exit_status.as_ref().unwrap().code(), Some(3));
+        assert_eq!(std::fs::read_to_string(&trapped).unwrap(), "INT\n");
+    }
+
+    /// Passes output lines to the ready signal
+    struct ReadyPlugin;
+
+    impl CommandSystemPlugin<()> for ReadyPlugin {
+        type CommandInitialData = LabeledCommandData;
+
+        fn initialize_spawn_failed_command_data(&self, _data: Self::CommandInitialData) {}
+
+        fn initialize_command_data(
+            &self,
+            data: Self::CommandInitialData,
+            stdout: ChildStdout,
+            _stderr: ChildStderr,
+        ) {
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stdout).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if let Some(ready) = &data.ready {
+                        ready.check_line(&line);
+                    }
+                }
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn restart_until_ready() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("count");
+
+        // the first two attempts crash and hang before becoming ready
+        let mut cmd = sh(&format!(
+            "n=$(cat {0} 2>/dev/null || echo 0); echo $((n + 1)) > {0}; \
+             if [ $n -eq 0 ]; then exit 1; fi; \
+             if [ $n -eq 1 ]; then exec sleep 5; fi; \
+             echo listening; sleep 0.1",
+            counter.display()
+        ));
+        cmd.ready_when = Some("^listening$".to_string());
+        cmd.restart = Some(RestartPolicy::UntilReady {
+            max: 3,
+            ready_timeout: Duration::from_millis(300),
+            backoff_schedule: vec![],
+        });
+
+        let mut system = spawn_from_run_config_with_plugin(run_config(vec![cmd]), ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(stopped[0].success());
+        assert_eq!(std::fs::read_to_string(&counter).unwrap(), "3\n");
+    }
+
+    #[tokio::test]
+    async fn restart_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("count");
+
+        // fails twice, restarts must not kill the other command
+        let script = format!(
+            "n=$(cat {0} 2>/dev/null || echo 0); echo $((n + 1)) > {0}; [ $n -ge 2 ]",
+            counter.display()
+        );
+        let mut restarting = sh(&script);
+        restarting.restart = Some(RestartPolicy::OnFailure {
+            max: Some(5),
+            backoff_schedule: vec![],
+        });
+        let mut config = run_config(vec![restarting, sh("exec sleep 10")]);
+        config.kill = KillBehavior::WhenAnyExited;
+
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(stopped[0].success());
+        assert_eq!(std::fs::read_to_string(&counter).unwrap(), "3\n");
+        assert!(matches!(
+            stopped[1].killed,
+            Some(kill::KillJoinHandleFinalStatus::Killed(
+                kill::KillCommandReason::OtherCommandExited(_)
+            ))
+        ));
+        assert_eq!(system.stats().restart_count, 2);
+
+        // gives up after max restarts
+        std::fs::remove_file(&counter).unwrap();
+        let mut failing = sh(&script);
+        failing.restart = Some(RestartPolicy::Always {
+            max: Some(1),
+            backoff_schedule: vec![],
+        });
+
+        let mut system = spawn_from_run_config_with_plugin(run_config(vec![failing]), ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(!stopped[0].success());
+        assert_eq!(std::fs::read_to_string(&counter).unwrap(), "2\n");
+    }
+
+    #[tokio::test]
+    async fn restart_until_ready_gives_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("count");
+
+        let mut cmd = sh(&format!(
+            "n=$(cat {0} 2>/dev/null || echo 0); echo $((n + 1)) > {0}; exit 1",
+            counter.display()
+        ));
+        cmd.ready_when = Some("^listening$".to_string());
+        cmd.restart = Some(RestartPolicy::UntilReady {
+            max: 2,
+            ready_timeout: Duration::from_secs(5),
+            backoff_schedule: vec![],
+        });
+
+        let mut system = spawn_from_run_config_with_plugin(run_config(vec![cmd]), ReadyPlugin);
+        let report = system.wait().await.unwrap();
+
+        assert_eq!(report.command_count_success, 0);
+        assert_eq!(std::fs::read_to_string(&counter).unwrap(), "3\n");
+    }
+
+    /// Records when commands were spawned and when they exited, and pauses of restarts
+    #[derive(Default, Clone)]
+    struct SpawnTimesPlugin {
+        spawned: Arc<Mutex<Vec<std::time::Instant>>>,
+        exited: Arc<Mutex<Vec<std::time::Instant>>>,
+        paused: Arc<Mutex<Vec<Duration>>>,
+    }
+
+    impl CommandSystemPlugin<()> for SpawnTimesPlugin {
+        type CommandInitialData = LabeledCommandData;
+
+        fn initialize_spawn_failed_command_data(&self, _data: Self::CommandInitialData) {}
+
+        fn initialize_command_data(
+            &self,
+            _data: Self::CommandInitialData,
+            _stdout: ChildStdout,
+            _stderr: ChildStderr,
+        ) {
+            self.spawned.lock().unwrap().push(std::time::Instant::now());
+        }
+
+        fn on_command_exited(&self, _cmd: Arc<CommandStopped<(), ()>>) {
+            self.exited.lock().unwrap().push(std::time::Instant::now());
+        }
 
-    fn join(&self) -> Option<JoinHandle<()>> {
-        None
+        fn on_restarts_paused(&self, _data: &Self::CommandInitialData, cooldown: Duration) {
+            self.paused.lock().unwrap().push(cooldown);
+        }
+    }
+
+    #[tokio::test]
+    async fn restart_backoff_schedule() {
+        let mut cmd = sh("exit 1");
+        cmd.ready_when = Some("^listening$".to_string());
+        cmd.restart = Some(RestartPolicy::UntilReady {
+            max: 3,
+            ready_timeout: Duration::from_secs(5),
+            backoff_schedule: vec![Duration::from_millis(100), Duration::from_millis(400)],
+        });
+
+        let plugin = SpawnTimesPlugin::default();
+        let mut system = spawn_from_run_config_with_plugin(run_config(vec![cmd]), plugin.clone());
+        system.wait().await.unwrap();
+
+        let spawned = plugin.spawned.lock().unwrap();
+        let exited = plugin.exited.lock().unwrap();
+        let delays: Vec<_> = exited
+            .iter()
+            .zip(&spawned[1..])
+            .map(|(exited, spawned)| spawned.duration_since(*exited).as_millis())
+            .collect();
+
+        assert_eq!(delays.len(), 3);
+        for (delay, expected) in delays.iter().zip([100, 400, 400]) {
+            assert!(
+                (expected..expected + 200).contains(delay),
+                "delays {:?} don't match the schedule",
+                delays
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn restart_rate_limit_pauses_restarts() {
+        let mut cmd = sh("exit 1");
+        cmd.restart = Some(RestartPolicy::Always {
+            max: Some(5),
+            backoff_schedule: vec![],
+        });
+        cmd.restart_rate_limit = Some(crate::RestartRateLimit {
+            max: 2,
+            within: Duration::from_secs(10),
+            cooldown: Some(Duration::from_millis(300)),
+        });
+
+        let plugin = SpawnTimesPlugin::default();
+        let mut system = spawn_from_run_config_with_plugin(run_config(vec![cmd]), plugin.clone());
+        system.wait().await.unwrap();
+        assert_eq!(system.stats().restart_count, 5);
+
+        let spawned = plugin.spawned.lock().unwrap();
+        let exited = plugin.exited.lock().unwrap();
+        let delays: Vec<_> = exited
+            .iter()
+            .zip(&spawned[1..])
+            .map(|(exited, spawned)| spawned.duration_since(*exited).as_millis())
+            .collect();
+
+        // two restarts right away, then one after the cooldown and another one right
+        // away, since the window started over
+        assert_eq!(delays.len(), 5);
+        for (delay, paused) in delays.iter().zip([false, false, true, false, true]) {
+            let expected = if paused { 300..500 } else { 0..200 };
+            assert!(
+                expected.contains(delay),
+                "delays {:?} don't match the rate limit",
+                delays
+            );
+        }
+        assert_eq!(
+            *plugin.paused.lock().unwrap(),
+            [Duration::from_millis(300); 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn kill_during_restart_backoff() {
+        let mut cmd = sh("exit 1");
+        cmd.ready_when = Some("^listening$".to_string());
+        cmd.restart = Some(RestartPolicy::UntilReady {
+            max: 3,
+            ready_timeout: Duration::from_secs(5),
+            backoff_schedule: vec![Duration::from_secs(10)],
+        });
+
+        let started_at = std::time::Instant::now();
+        let mut system = spawn_from_run_config_with_plugin(run_config(vec![cmd]), ReadyPlugin);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        system.kill_all().await;
+        let report = system.wait().await.unwrap();
+
+        assert!(started_at.elapsed() < Duration::from_secs(3));
+        assert_eq!(report.command_count_success, 0);
+    }
+
+    #[tokio::test]
+    async fn kill_when_crashed_within() {
+        let crashed_within = |within| {
+            KillBehavior::WhenAnyExitedWithStatus(crate::ExitStatusPattern::CrashedWithin(within))
+        };
+
+        // exiting right away is a crash
+        let mut config = run_config(vec![sh("exit 0"), sh("exec sleep 5")]);
+        config.kill = crashed_within(Duration::from_secs(2));
+        let started_at = std::time::Instant::now();
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(started_at.elapsed() < Duration::from_secs(3));
+        assert!(stopped[0].ran_for.unwrap() < Duration::from_secs(2));
+        assert!(matches!(
+            stopped[1].killed,
+            Some(kill::KillJoinHandleFinalStatus::Killed(_))
+        ));
+
+        // exiting after running for a while isn't
+        let mut config = run_config(vec![sh("exec sleep 0.5"), sh("exec sleep 1")]);
+        config.kill = crashed_within(Duration::from_millis(200));
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(stopped[0].ran_for.unwrap() >= Duration::from_millis(500));
+        assert!(stopped[1].killed.is_none());
+        assert!(stopped[1].success());
+    }
+
+    /// Kills all commands once two of them failed
+    struct FailureBudgetPlugin;
+
+    impl CommandSystemPlugin<()> for FailureBudgetPlugin {
+        type CommandInitialData = LabeledCommandData;
+
+        fn initialize_spawn_failed_command_data(&self, _data: Self::CommandInitialData) {}
+
+        fn initialize_command_data(
+            &self,
+            _data: Self::CommandInitialData,
+            _stdout: ChildStdout,
+            _stderr: ChildStderr,
+        ) {
+        }
+
+        fn should_kill_all(
+            &self,
+            _exited: &CommandStopped<(), ()>,
+            stats: &RunStats,
+            by_kill_behavior: bool,
+        ) -> bool {
+            assert!(!by_kill_behavior);
+            stats.command_count_failed >= 2
+        }
+    }
+
+    #[tokio::test]
+    async fn plugin_decides_kill_all() {
+        let config = run_config(vec![
+            sh("exit 1"),
+            sh("sleep 0.2; exit 1"),
+            sh("exec sleep 10"),
+        ]);
+
+        let started_at = std::time::Instant::now();
+        let mut system = spawn_from_run_config_with_plugin(config, FailureBudgetPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(started_at.elapsed() < Duration::from_secs(3));
+        assert_eq!(stopped[0].outcome(), CommandOutcome::Failed);
+        assert_eq!(stopped[1].outcome(), CommandOutcome::Failed);
+        assert_eq!(stopped[2].outcome(), CommandOutcome::Killed);
+    }
+
+    /// Panics when the command labeled `panics` exits
+    struct PanicPlugin;
+
+    impl CommandSystemPlugin<LabeledCommandData> for PanicPlugin {
+        type CommandInitialData = LabeledCommandData;
+
+        fn initialize_spawn_failed_command_data(
+            &self,
+            data: Self::CommandInitialData,
+        ) -> LabeledCommandData {
+            data
+        }
+
+        fn initialize_command_data(
+            &self,
+            data: Self::CommandInitialData,
+            _stdout: ChildStdout,
+            _stderr: ChildStderr,
+        ) -> LabeledCommandData {
+            data
+        }
+
+        fn on_command_exited(
+            &self,
+            cmd: Arc<CommandStopped<LabeledCommandData, LabeledCommandData>>,
+        ) {
+            if cmd.data.label.label() == "panics" {
+                panic!("plugin failed");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn supervisor_panicked() {
+        let mut panics = sh("sleep 0.2");
+        panics.label = Some("panics".to_string());
+        let config = run_config(vec![sh("exec sleep 10"), panics]);
+
+        let started_at = std::time::Instant::now();
+        let mut system = spawn_from_run_config_with_plugin(config, PanicPlugin);
+        let panicked = system.wait().await.unwrap_err();
+
+        assert!(started_at.elapsed() < Duration::from_secs(3));
+        assert_eq!(panicked.command_index, Some(1));
+        assert_eq!(panicked.message, "plugin failed");
+        assert!(system.wait_into_stopped_commands().await.is_err());
+
+        let history = system.history();
+        assert_eq!(history.len(), 1);
+        assert!(matches!(
+            history[0].killed,
+            Some(kill::KillJoinHandleFinalStatus::Killed(
+                kill::KillCommandReason::SystemPanicked
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn exit_when_only_optional_remain() {
+        let mut background = sh("exec sleep 5");
+        background.optional = true;
+        let mut config = run_config(vec![sh("sleep 0.1"), background, sh("sleep 0.2")]);
+        config.exit_when_only_optional_remain = true;
+
+        let started_at = std::time::Instant::now();
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(started_at.elapsed() < Duration::from_secs(3));
+        assert!(stopped[0].success());
+        assert!(stopped[2].success());
+        assert!(matches!(
+            stopped[1].killed,
+            Some(kill::KillJoinHandleFinalStatus::Killed(
+                kill::KillCommandReason::OtherCommandExited(_)
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn critical_and_transient_commands() {
+        let mut server = sh("sleep 0.2");
+        server.critical = true;
+        let config = run_config(vec![sh("exec sleep 5"), server]);
+
+        let started_at = std::time::Instant::now();
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+        assert!(started_at.elapsed() < Duration::from_secs(3));
+        assert!(stopped[0].killed.is_some());
+        assert!(stopped[1].success());
+
+        let mut migrate = sh("exit 1");
+        migrate.transient = true;
+        let mut config = run_config(vec![migrate, sh("sleep 0.3")]);
+        config.kill = KillBehavior::WhenAnyExited;
+
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+        assert!(!stopped[0].success());
+        assert!(stopped[1].success());
+    }
+
+    #[tokio::test]
+    async fn on_failure_runs_only_for_failed_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let hook = |name: &str| {
+            Some(crate::FailureHook {
+                command: format!("touch {}", dir.path().join(name).display()),
+                timeout: Duration::from_secs(5),
+            })
+        };
+        let mut failing = sh("exit 1");
+        failing.on_failure = hook("failed");
+        let mut succeeding = sh("exit 0");
+        succeeding.on_failure = hook("succeeded");
+        let mut slow = sh("exit 2");
+        slow.on_failure = Some(crate::FailureHook {
+            command: "exec sleep 5".to_string(),
+            timeout: Duration::from_millis(200),
+        });
+
+        let started_at = std::time::Instant::now();
+        let config = run_config(vec![failing, succeeding, slow]);
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        system.wait().await.unwrap();
+
+        // hooks run before the command counts as stopped
+        assert!(dir.path().join("failed").exists());
+        assert!(!dir.path().join("succeeded").exists());
+        // the slow hook was killed after its timeout
+        assert!(started_at.elapsed() < Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn wait_when_all_exited() {
+        let mut config = run_config(vec![sh("exit 1"), sh("sleep 0.3")]);
+        config.kill = KillBehavior::WhenAllExited;
+
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(stopped.iter().all(|cmd| cmd.killed.is_none()));
+        assert!(!stopped[0].success());
+        assert!(stopped[1].success());
+    }
+
+    #[tokio::test]
+    async fn killed_commands_are_not_failed() {
+        let mut config = run_config(vec![sh("sleep 0.2; exit 3"), sh("sleep 10")]);
+        config.kill = KillBehavior::WhenAnyExited;
+
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let report = system.wait().await.unwrap();
+        let stats = system.stats();
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert_eq!(stopped[0].outcome(), CommandOutcome::Failed);
+        assert_eq!(stopped[0].exit_status.as_ref().unwrap().code(), Some(3));
+        assert_eq!(stopped[1].outcome(), CommandOutcome::Killed);
+        assert_eq!(report.command_count_failed(), 1);
+        assert_eq!(report.command_count_killed, 1);
+        assert_eq!(report.first_failed_code, Some(3));
+        assert_eq!(stats.command_count_failed, 1);
+        assert_eq!(stats.command_count_killed, 1);
+    }
+
+    #[tokio::test]
+    async fn report_code_of_first_failed_command() {
+        let config = run_config(vec![sh("sleep 0.3; exit 4"), sh("exit 0"), sh("exit 5")]);
+
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let report = system.wait().await.unwrap();
+
+        assert_eq!(report.command_count_failed(), 2);
+        assert_eq!(report.first_failed_code, Some(5));
+    }
+
+    #[tokio::test]
+    async fn stats_update_as_commands_stop() {
+        let mut failing = sh("exit 1");
+        failing.startup_retries = Some(StartupRetries {
+            max_retries: 2,
+            within: Duration::from_secs(10),
+        });
+        let config = run_config(vec![sh("sleep 1"), failing]);
+
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let stats = system.stats();
+        assert_eq!(stats.command_count_total, 2);
+        assert_eq!(stats.command_count_running, 2);
+        assert_eq!(stats.command_count_success + stats.command_count_failed, 0);
+
+        let stats = tokio::time::timeout(Duration::from_millis(800), async {
+            loop {
+                let stats = system.stats();
+                if stats.command_count_failed == 1 {
+                    return stats;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(stats.command_count_running, 1);
+        assert_eq!(stats.restart_count, 2);
+
+        system.wait().await.unwrap();
+        let stats = system.stats();
+        assert_eq!(stats.command_count_running, 0);
+        assert_eq!(stats.command_count_success, 1);
+        assert_eq!(stats.command_count_failed, 1);
+    }
+
+    #[tokio::test]
+    async fn history_limit_prunes_oldest_commands() {
+        let commands = (0..20).map(|i| sh(&format!("exit {}", i % 2))).collect();
+        let mut config = run_config(commands);
+        config.history_limit = Some(5);
+
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let report = system.wait().await.unwrap();
+
+        assert_eq!(report.command_count_total, 20);
+        assert_eq!(report.command_count_success, 10);
+        assert_eq!(system.history().len(), 5);
+        assert_eq!(system.wait_into_stopped_commands().await.unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn computed_env_is_passed_to_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+
+        let cmd = sh(&format!("echo \"$TOKEN\" > {}", out.display()));
+        let mut config = run_config(vec![cmd]);
+        config
+            .computed_env
+            .insert("TOKEN".to_string(), sh("echo '  secret '"));
+        config.resolve_computed_env().await.unwrap();
+
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let report = system.wait().await.unwrap();
+
+        assert_eq!(report.command_count_success, 1);
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "secret\n");
+
+        let mut config = run_config(vec![]);
+        config
+            .computed_env
+            .insert("TOKEN".to_string(), sh("exit 3"));
+        assert!(matches!(
+            config.resolve_computed_env().await,
+            Err(crate::ComputedEnvError::Failed { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn startup_probe_marks_ready() {
+        let dir = tempfile::tempdir().unwrap();
+        let probes = dir.path().join("probes");
+        let runs = dir.path().join("runs");
+
+        // the probe fails twice before succeeding
+        let mut cmd = sh(&format!("echo run >> {}; sleep 1", runs.display()));
+        cmd.startup_probe = Some(crate::StartupProbe {
+            command: format!(
+                "echo probe >> {0}; [ $(wc -l < {0}) -ge 3 ]",
+                probes.display()
+            ),
+            interval: Duration::from_millis(100),
+            timeout: Duration::from_secs(1),
+            failure_threshold: 5,
+        });
+        cmd.restart = Some(RestartPolicy::UntilReady {
+            max: 1,
+            ready_timeout: Duration::from_secs(2),
+            backoff_schedule: vec![],
+        });
+
+        let mut system = spawn_from_run_config_with_plugin(run_config(vec![cmd]), ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(stopped[0].success());
+        assert_eq!(std::fs::read_to_string(&probes).unwrap().lines().count(), 3);
+        assert_eq!(std::fs::read_to_string(&runs).unwrap(), "run\n");
+    }
+
+    #[tokio::test]
+    async fn unhealthy_command_is_restarted() {
+        let dir = tempfile::tempdir().unwrap();
+        let runs = dir.path().join("runs");
+        let healthy = dir.path().join("healthy");
+
+        // only the second run is healthy and exits on its own
+        let mut cmd = sh(&format!(
+            "echo run >> {0}; if [ $(wc -l < {0}) -ge 2 ]; then touch {1}; sleep 0.2; else exec sleep 10; fi",
+            runs.display(),
+            healthy.display()
+        ));
+        cmd.healthcheck = Some(crate::HealthCheck {
+            url: None,
+            command: Some(format!("[ -f {} ]", healthy.display())),
+            interval: Duration::from_millis(50),
+            timeout: Duration::from_secs(1),
+            retries: 2,
+            restart: true,
+        });
+        // a restart of an unhealthy command isn't a retry
+        cmd.restart = Some(RestartPolicy::Never);
+
+        let started_at = Instant::now();
+        let mut system = spawn_from_run_config_with_plugin(run_config(vec![cmd]), ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+        assert!(stopped[0].success());
+        assert_eq!(std::fs::read_to_string(&runs).unwrap(), "run\nrun\n");
+        assert_eq!(system.stats().restart_count, 1);
+    }
+
+    /// Records failed attempts of startup probes
+    #[derive(Default)]
+    struct ProbeFailuresPlugin(Mutex<Vec<(u32, u32)>>);
+
+    impl CommandSystemPlugin<()> for Arc<ProbeFailuresPlugin> {
+        type CommandInitialData = LabeledCommandData;
+
+        fn initialize_spawn_failed_command_data(&self, _data: Self::CommandInitialData) {}
+
+        fn initialize_command_data(
+            &self,
+            _data: Self::CommandInitialData,
+            _stdout: ChildStdout,
+            _stderr: ChildStderr,
+        ) {
+        }
+
+        fn on_probe_failed(&self, _data: &Self::CommandInitialData, attempt: u32, attempts: u32) {
+            self.0.lock().unwrap().push((attempt, attempts));
+        }
+    }
+
+    #[tokio::test]
+    async fn failing_startup_probe_fails_command() {
+        let mut api = sh("exec sleep 10");
+        api.label = Some("api".to_string());
+        api.startup_probe = Some(crate::StartupProbe {
+            command: "exit 1".to_string(),
+            interval: Duration::from_millis(20),
+            timeout: Duration::from_secs(1),
+            failure_threshold: 3,
+        });
+        let web = sh("exec sleep 10");
+        let mut config = run_config(vec![api, web]);
+        config.kill = KillBehavior::WhenAnyExited;
+
+        let plugin = Arc::new(ProbeFailuresPlugin::default());
+        let started_at = Instant::now();
+        let mut system = spawn_from_run_config_with_plugin(config, plugin.clone());
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+        assert_eq!(*plugin.0.lock().unwrap(), [(1, 3), (2, 3), (3, 3)]);
+        assert!(matches!(
+            kill_reason(&stopped[0]),
+            Some(kill::KillCommandReason::ProbeFailed { attempts: 3 })
+        ));
+        assert!(stopped[0].probe_failed());
+        assert_eq!(stopped[0].outcome(), CommandOutcome::Failed);
+        // which counts for the kill behavior
+        assert!(matches!(
+            kill_reason(&stopped[1]),
+            Some(kill::KillCommandReason::OtherCommandExited(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn watch_dir_restarts_only_watching_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let logs = tempfile::tempdir().unwrap();
+        let watched_runs = logs.path().join("watched");
+        let other_runs = logs.path().join("other");
+
+        let mut watched = sh(&format!(
+            "echo run >> {}; exec sleep 5",
+            watched_runs.display()
+        ));
+        watched.cwd = Some(dir.path().display().to_string());
+        watched.watch_dir = true;
+        watched.watch_exclude = Some(vec!["*.log".to_string()]);
+        let mut other = sh(&format!(
+            "echo run >> {}; exec sleep 5",
+            other_runs.display()
+        ));
+        other.cwd = Some(dir.path().display().to_string());
+
+        let mut system = spawn_from_run_config_with_plugin(
+            run_config(vec![watched, other]),
+            Arc::new(RecordPlugin::default()),
+        );
+
+        let runs = |path: &std::path::Path| {
+            std::fs::read_to_string(path)
+                .map(|s| s.lines().count())
+                .unwrap_or(0)
+        };
+
+        // let the watcher take its first snapshot
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        std::fs::write(dir.path().join("ignored.log"), "").unwrap();
+        tokio::time::sleep(Duration::from_millis(700)).await;
+        assert_eq!(runs(&watched_runs), 1);
+
+        std::fs::write(dir.path().join("main.rs"), "").unwrap();
+        for _ in 0..50 {
+            if runs(&watched_runs) == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        system.kill_all().await;
+        system.wait().await.unwrap();
+
+        assert_eq!(runs(&watched_runs), 2);
+        assert_eq!(runs(&other_runs), 1);
+    }
+
+    #[tokio::test]
+    async fn watch_restarts_on_matching_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let logs = tempfile::tempdir().unwrap();
+        let runs_path = logs.path().join("runs");
+
+        let mut cmd = sh(&format!(
+            "echo run >> {}; exec sleep 5",
+            runs_path.display()
+        ));
+        cmd.cwd = Some(dir.path().display().to_string());
+        cmd.watch = Some(vec!["src/*.rs".to_string()]);
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+
+        let mut system = spawn_from_run_config_with_plugin(
+            run_config(vec![cmd]),
+            Arc::new(RecordPlugin::default()),
+        );
+        let runs = || {
+            std::fs::read_to_string(&runs_path)
+                .map(|s| s.lines().count())
+                .unwrap_or(0)
+        };
+
+        // let the watcher take its first snapshot
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        std::fs::write(dir.path().join("README.md"), "").unwrap();
+        tokio::time::sleep(Duration::from_millis(700)).await;
+        assert_eq!(runs(), 1);
+
+        std::fs::write(dir.path().join("src").join("main.rs"), "").unwrap();
+        for _ in 0..50 {
+            if runs() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        system.kill_all().await;
+        system.wait().await.unwrap();
+        assert_eq!(runs(), 2);
+    }
+
+    #[tokio::test]
+    async fn force_kill_after_shutdown_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let ready = dir.path().join("ready");
+
+        let cmd = sh(&format!(
+            "trap '' INT; touch {}; exec sleep 5",
+            ready.display()
+        ));
+        let mut config = run_config(vec![cmd]);
+        config.shutdown_timeout = Some(Duration::from_millis(300));
+
+        let mut system =
+            spawn_from_run_config_with_plugin(config, Arc::new(RecordPlugin::default()));
+
+        while !ready.exists() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let started = std::time::Instant::now();
+        system.kill_all_with_signal(Signal::Interrupt).await;
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(matches!(
+            stopped[0].killed,
+            Some(kill::KillJoinHandleFinalStatus::ForceKilled(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn kill_all_with_timeout_kills_forcefully_at_deadline() {
+        let dir = tempfile::tempdir().unwrap();
+        let ready = dir.path().join("ready");
+
+        let cmd = sh(&format!(
+            "trap '' INT; touch {}; exec sleep 5",
+            ready.display()
+        ));
+        // the deadline is shorter than the default shutdown timeout
+        let mut system = spawn_from_run_config_with_plugin(
+            run_config(vec![cmd]),
+            Arc::new(RecordPlugin::default()),
+        );
+
+        while !ready.exists() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let started = std::time::Instant::now();
+        system
+            .share_killer()
+            .kill_all_with_signal_and_timeout(Signal::Interrupt, Duration::from_millis(300))
+            .await;
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert!(stopped[0].force_killed());
+    }
+
+    #[tokio::test]
+    async fn force_stop_all_after_kill_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let ready = dir.path().join("ready");
+
+        let cmd = sh(&format!(
+            "trap '' INT; touch {}; exec sleep 5",
+            ready.display()
+        ));
+        let mut system = spawn_from_run_config_with_plugin(
+            run_config(vec![cmd]),
+            Arc::new(RecordPlugin::default()),
+        );
+
+        while !ready.exists() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let started = std::time::Instant::now();
+        let killer = system.share_killer();
+        killer.kill_all_with_signal(Signal::Interrupt).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // e.g. on a second Ctrl-C, after the system stopped receiving events
+        killer.force_stop_all().await;
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert!(stopped[0].force_killed());
+        assert_eq!(stopped[0].outcome(), CommandOutcome::Killed);
+    }
+
+    #[tokio::test]
+    async fn kill_single_command() {
+        let mut killed = sh("exec sleep 5");
+        killed.restart = Some(RestartPolicy::Always {
+            max: None,
+            backoff_schedule: vec![],
+        });
+        let mut config = run_config(vec![killed, sh("exec sleep 5")]);
+        config.kill = KillBehavior::WhenAnyExited;
+        let mut system =
+            spawn_from_run_config_with_plugin(config, Arc::new(RecordPlugin::default()));
+
+        let handles = system.command_handles();
+        while !handles.iter().all(|handle| handle.pid().is_some()) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(matches!(handles[0].kill(), kill::KillResult::SentSuccess));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // neither restarted nor killing the other command
+        assert!(!handles[0].is_running());
+        assert!(handles[1].is_running());
+        assert!(matches!(
+            handles[0].clone().kill(),
+            kill::KillResult::AlreadyExited
+        ));
+
+        system.kill_all().await;
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+        assert!(matches!(
+            super::kill_reason(&stopped[0]),
+            Some(kill::KillCommandReason::Requested)
+        ));
+        assert!(matches!(
+            super::kill_reason(&stopped[1]),
+            Some(kill::KillCommandReason::MainProcessGotSignal)
+        ));
+    }
+
+    #[tokio::test]
+    async fn restart_single_command() {
+        let mut restarted = sh("exec sleep 5");
+        // a restart isn't a retry
+        restarted.restart = Some(RestartPolicy::Never);
+        let mut config = run_config(vec![restarted, sh("exec sleep 5")]);
+        config.kill = KillBehavior::WhenAnyExited;
+        let mut system =
+            spawn_from_run_config_with_plugin(config, Arc::new(RecordPlugin::default()));
+
+        let handles = system.command_handles();
+        while !handles.iter().all(|handle| handle.pid().is_some()) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let pid = handles[0].pid();
+        assert!(matches!(
+            handles[0].restart(),
+            kill::KillResult::SentSuccess
+        ));
+        let started = std::time::Instant::now();
+        while handles[0].pid().is_none() || handles[0].pid() == pid {
+            assert!(started.elapsed() < Duration::from_secs(3), "not restarted");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(handles[1].is_running());
+        assert_eq!(system.stats().restart_count, 1);
+
+        system.kill_all().await;
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+        assert!(matches!(
+            super::kill_reason(&stopped[0]),
+            Some(kill::KillCommandReason::MainProcessGotSignal)
+        ));
+    }
+
+    #[tokio::test]
+    async fn reload_commands() {
+        let labeled = |label: &str, script: &str| {
+            let mut cmd = sh(script);
+            cmd.label = Some(label.to_string());
+            cmd
+        };
+        let config = run_config(vec![
+            labeled("kept", "exec sleep 5"),
+            labeled("changed", "exec sleep 5"),
+            labeled("removed", "exec sleep 5"),
+        ]);
+        let mut system =
+            spawn_from_run_config_with_plugin(config.clone(), Arc::new(RecordPlugin::default()));
+        let mut reloader = system.reloader(config);
+
+        let handles = system.command_handles();
+        while !handles.iter().all(|handle| handle.pid().is_some()) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let changes = reloader
+            .reload(run_config(vec![
+                labeled("kept", "exec sleep 5"),
+                labeled("changed", "exec sleep 4"),
+                labeled("added", "exec sleep 5"),
+            ]))
+            .unwrap();
+        assert_eq!(changes.started, ["added"]);
+        assert_eq!(changes.stopped, ["removed"]);
+        assert_eq!(changes.restarted, ["changed"]);
+
+        for handle in &handles[1..] {
+            let stopped = tokio::time::timeout(Duration::from_secs(2), handle.wait());
+            let stopped = stopped.await.unwrap().unwrap();
+            assert!(matches!(
+                super::kill_reason(&stopped),
+                Some(kill::KillCommandReason::Requested)
+            ));
+        }
+        let handles = system.command_handles();
+        let started = std::time::Instant::now();
+        while !handles[3..].iter().all(|handle| handle.pid().is_some()) {
+            assert!(started.elapsed() < Duration::from_secs(3), "not spawned");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(handles[0].is_running());
+        let labels: Vec<_> = handles[3..]
+            .iter()
+            .map(|handle| handle.data().unwrap().label.label().to_string())
+            .collect();
+        assert_eq!(labels, ["changed", "added"]);
+
+        system.kill_all().await;
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+        assert_eq!(stopped.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn reload_command_in_backoff() {
+        let labeled = |label: &str, script: &str| {
+            let mut cmd = sh(script);
+            cmd.label = Some(label.to_string());
+            cmd
+        };
+        let mut crashing = labeled("crashing", "exit 1");
+        crashing.restart = Some(RestartPolicy::Always {
+            max: None,
+            backoff_schedule: vec![Duration::from_secs(30)],
+        });
+        let config = run_config(vec![crashing, labeled("kept", "exec sleep 5")]);
+        let mut system =
+            spawn_from_run_config_with_plugin(config.clone(), Arc::new(RecordPlugin::default()));
+        let mut reloader = system.reloader(config);
+
+        let handles = system.command_handles();
+        // exited once, and waits for the backoff
+        while handles[0].data().is_none() || handles[0].is_running() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let changes = reloader
+            .reload(run_config(vec![
+                labeled("crashing", "exec sleep 5"),
+                labeled("kept", "exec sleep 5"),
+            ]))
+            .unwrap();
+        assert_eq!(changes.restarted, ["crashing"]);
+
+        let stopped = tokio::time::timeout(Duration::from_secs(2), handles[0].wait());
+        let stopped = stopped.await.unwrap().unwrap();
+        assert_eq!(stopped.exit_status.as_ref().unwrap().code(), Some(1));
+        let handles = system.command_handles();
+        let started = std::time::Instant::now();
+        while !handles[2].is_running() {
+            assert!(started.elapsed() < Duration::from_secs(3), "not spawned");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        system.kill_all().await;
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+        assert_eq!(stopped.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn restart_stopped_command_with_reloader() {
+        let mut crashed = sh("exit 1");
+        crashed.label = Some("crashed".to_string());
+        let config = run_config(vec![crashed, sh("exec sleep 5")]);
+        let mut system =
+            spawn_from_run_config_with_plugin(config.clone(), Arc::new(RecordPlugin::default()));
+        let mut reloader = system.reloader(config);
+
+        let handles = system.command_handles();
+        let stopped = tokio::time::timeout(Duration::from_secs(2), handles[0].wait());
+        stopped.await.unwrap().unwrap();
+        assert!(handles[0].has_stopped());
+        assert!(matches!(
+            handles[0].restart(),
+            kill::KillResult::AlreadyExited
+        ));
+
+        let restarted = reloader.restart("crashed").unwrap().unwrap();
+        assert!(matches!(restarted, kill::KillResult::SentSuccess));
+        assert!(reloader.restart("missing").is_none());
+        let (label, handle) = &reloader.commands()[0];
+        assert_eq!(label, "crashed");
+        let stopped = tokio::time::timeout(Duration::from_secs(2), handle.wait());
+        let stopped = stopped.await.unwrap().unwrap();
+        assert_eq!(stopped.data.label.label(), "crashed");
+        assert_eq!(system.command_handles().len(), 3);
+
+        system.kill_all().await;
+        system.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn kill_command_in_backoff() {
+        let mut crashing = sh("exit 1");
+        crashing.restart = Some(RestartPolicy::Always {
+            max: None,
+            backoff_schedule: vec![Duration::from_secs(30)],
+        });
+        let mut system = spawn_from_run_config_with_plugin(
+            run_config(vec![crashing, sh("exec sleep 5")]),
+            Arc::new(RecordPlugin::default()),
+        );
+
+        let handles = system.command_handles();
+        while handles[0].data().is_none() || handles[0].is_running() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(matches!(handles[0].kill(), kill::KillResult::SentSuccess));
+        let stopped = tokio::time::timeout(Duration::from_secs(2), handles[0].wait());
+        stopped.await.unwrap().unwrap();
+        assert!(handles[1].is_running());
+        assert!(matches!(handles[0].kill(), kill::KillResult::AlreadyExited));
+        assert!(matches!(
+            handles[0].restart(),
+            kill::KillResult::AlreadyExited
+        ));
+
+        system.kill_all().await;
+        system.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn wait_for_single_command() {
+        let mut fast = sh("exit 3");
+        fast.label = Some("fast".to_string());
+        let mut slow = sh("exec sleep 5");
+        slow.label = Some("slow".to_string());
+        let mut system = spawn_from_run_config_with_plugin(
+            run_config(vec![slow, fast]),
+            Arc::new(RecordPlugin::default()),
+        );
+
+        let label = |label: &str| Label::from_label(label.to_string(), 10);
+        let (fast, missing) = (label("fast"), label("missing"));
+        let waited = tokio::time::timeout(Duration::from_secs(2), system.wait_for(&fast));
+        let fast = waited.await.unwrap().unwrap();
+        assert_eq!(fast.data.label.label(), "fast");
+        assert_eq!(fast.exit_status.as_ref().unwrap().code(), Some(3));
+        assert!(system.command_handles()[0].is_running());
+        assert!(system.wait_for(&missing).await.is_none());
+
+        system.kill_all().await;
+        system.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn restart_crashed_command_by_label() {
+        let mut web = sh("[ -e restarted ] && exec sleep 5; touch restarted; exit 1");
+        web.label = Some("web".to_string());
+        web.restart = Some(RestartPolicy::OnFailure {
+            max: None,
+            backoff_schedule: vec![Duration::from_secs(30)],
+        });
+        let dir = tempfile::tempdir().unwrap();
+        web.cwd = Some(dir.path().to_string_lossy().into());
+        let mut system = spawn_from_run_config_with_plugin(
+            run_config(vec![web, sh("exec sleep 5")]),
+            Arc::new(RecordPlugin::default()),
+        );
+
+        let web = Label::from_label("web".to_string(), 10);
+        let handles = system.command_handles();
+        // crashed, and waits for the backoff
+        while handles[0].data().is_none() || handles[0].is_running() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(matches!(
+            system.restart_one(&web),
+            Some(kill::KillResult::SentSuccess)
+        ));
+        let started = Instant::now();
+        while !handles[0].is_running() {
+            assert!(started.elapsed() < Duration::from_secs(3), "not restarted");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(system.stats().restart_count, 1);
+
+        system.kill_all().await;
+        system.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn kill_and_restart_command_by_label() {
+        let mut web = sh("exec sleep 5");
+        web.label = Some("web".to_string());
+        let mut api = sh("exec sleep 5");
+        api.label = Some("api".to_string());
+        let mut system = spawn_from_run_config_with_plugin(
+            run_config(vec![web, api]),
+            Arc::new(RecordPlugin::default()),
+        );
+
+        let label = |label: &str| Label::from_label(label.to_string(), 10);
+        let (web, api, missing) = (label("web"), label("api"), label("missing"));
+        let pids = system.pids();
+        let labels: Vec<_> = pids.iter().map(|(label, _)| label.label()).collect();
+        assert_eq!(labels, ["web", "api"]);
+        assert!(pids.iter().all(|(_, pid)| pid.is_some()));
+        let api_pid = pids[1].1;
+
+        assert!(matches!(
+            system.restart_one(&api),
+            Some(kill::KillResult::SentSuccess)
+        ));
+        let started = Instant::now();
+        while !matches!(system.pids()[1].1, Some(pid) if Some(pid) != api_pid) {
+            assert!(started.elapsed() < Duration::from_secs(3), "not restarted");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(matches!(
+            system.kill_one(&web),
+            Some(kill::KillResult::SentSuccess)
+        ));
+        let waited = tokio::time::timeout(Duration::from_secs(2), system.wait_for(&web));
+        waited.await.unwrap().unwrap();
+        assert_eq!(system.pids()[0].1, None);
+        assert!(system.command_handles()[1].is_running());
+        assert!(system.kill_one(&missing).is_none());
+
+        system.kill_all().await;
+        system.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn kill_signal_of_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name| dir.path().join(name).display().to_string();
+        let (checkpoint, ready, ignoring) = (path("checkpoint"), path("ready"), path("ignoring"));
+
+        let mut checkpoints: CommandConfig = serde_yaml::from_str(&format!(
+            r#"
+program: sh
+args: [-c, "trap 'echo done > {}; exit 0' INT; touch {}; while true; do sleep 0.05; done"]
+kill_signal: SIGINT
+"#,
+            checkpoint, ready
+        ))
+        .unwrap();
+        checkpoints.label = Some("worker".to_string());
+        let mut ignores = sh(&format!("trap '' INT; touch {}; exec sleep 5", ignoring));
+        ignores.kill_signal = Some(Signal::Interrupt);
+        let mut config = run_config(vec![checkpoints, ignores]);
+        config.shutdown_timeout = Some(Duration::from_millis(300));
+
+        let started = std::time::Instant::now();
+        let mut system =
+            spawn_from_run_config_with_plugin(config, Arc::new(RecordPlugin::default()));
+        while !std::path::Path::new(&ready).exists() || !std::path::Path::new(&ignoring).exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // forcefully for commands without kill_signal
+        system.kill_all().await;
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(&checkpoint).unwrap(), "done\n");
+        assert!(!stopped[0].force_killed());
+        assert!(stopped[1].force_killed());
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn kill_grace_terminates_before_killing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name| dir.path().join(name).display().to_string();
+        let (cleaned, ready, ignoring) = (path("cleaned"), path("ready"), path("ignoring"));
+
+        let cleans_up = sh(&format!(
+            "trap 'echo cleaned > {}; exit 0' TERM; touch {}; while true; do sleep 0.05; done",
+            cleaned, ready
+        ));
+        let ignores = sh(&format!("trap '' TERM; touch {}; exec sleep 5", ignoring));
+        let exits = sh(&format!(
+            "while [ ! -e {} ] || [ ! -e {} ]; do sleep 0.02; done",
+            ready, ignoring
+        ));
+        let mut config = run_config(vec![cleans_up, ignores, exits]);
+        config.kill = KillBehavior::WhenAnyExited;
+        config.kill_grace = Some(Duration::from_millis(300));
+
+        let started = std::time::Instant::now();
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert_eq!(std::fs::read_to_string(&cleaned).unwrap(), "cleaned\n");
+        assert!(matches!(
+            stopped[0].killed,
+            Some(kill::KillJoinHandleFinalStatus::Killed(
+                kill::KillCommandReason::OtherCommandExited(_)
+            ))
+        ));
+        assert!(matches!(
+            stopped[1].killed,
+            Some(kill::KillJoinHandleFinalStatus::ForceKilled(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn kill_signal_replaces_sigterm() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name| dir.path().join(name).display().to_string();
+        let (signal, ready, ignoring) = (path("signal"), path("ready"), path("ignoring"));
+
+        let cleans_up = sh(&format!(
+            "trap 'echo INT > {0}; exit 0' INT; trap 'echo TERM > {0}; exit 0' TERM; touch {1}; while true; do sleep 0.05; done",
+            signal, ready
+        ));
+        let ignores = sh(&format!("trap '' INT; touch {}; exec sleep 5", ignoring));
+        let exits = sh(&format!(
+            "while [ ! -e {} ] || [ ! -e {} ]; do sleep 0.02; done",
+            ready, ignoring
+        ));
+        let mut config = run_config(vec![cleans_up, ignores, exits]);
+        config.kill = KillBehavior::WhenAnyExited;
+        config.kill_signal = Some(Signal::Interrupt);
+        // without kill_grace, until the shutdown timeout
+        config.shutdown_timeout = Some(Duration::from_millis(300));
+
+        let started = std::time::Instant::now();
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert_eq!(std::fs::read_to_string(&signal).unwrap(), "INT\n");
+        assert!(stopped[0].success());
+        assert!(matches!(
+            stopped[1].killed,
+            Some(kill::KillJoinHandleFinalStatus::ForceKilled(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn timeouts_kill_commands() {
+        let mut hung = sh("exec sleep 5");
+        hung.timeout = Some(Duration::from_millis(200));
+        hung.restart = Some(RestartPolicy::OnFailure {
+            max: None,
+            backoff_schedule: vec![],
+        });
+        let fast = sh("exit 0");
+        let watcher = sh("exec sleep 5");
+        let mut config = run_config(vec![hung, fast, watcher]);
+        config.kill = KillBehavior::None;
+        config.timeout = Some(Duration::from_millis(600));
+
+        let started = std::time::Instant::now();
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(3));
+        // not restarted
+        assert_eq!(stopped[0].timed_out(), Some(Duration::from_millis(200)));
+        assert_eq!(stopped[0].outcome(), CommandOutcome::Failed);
+        assert!(stopped[1].success());
+        assert_eq!(stopped[2].timed_out(), Some(Duration::from_millis(600)));
+        assert_eq!(stopped[2].outcome(), CommandOutcome::Failed);
+    }
+
+    #[tokio::test]
+    async fn kill_process_groups_kills_descendants() {
+        let dir = tempfile::tempdir().unwrap();
+        let ticks = dir.path().join("ticks");
+
+        // the background loop would survive its shell
+        let wrapper = sh(&format!(
+            "(while true; do echo tick >> {}; sleep 0.02; done) & wait",
+            ticks.display()
+        ));
+        let exits = sh(&format!(
+            "while [ ! -s {} ]; do sleep 0.02; done",
+            ticks.display()
+        ));
+        let mut config = run_config(vec![wrapper, exits]);
+        config.kill = KillBehavior::WhenAnyExited;
+        config.kill_process_groups = true;
+
+        let mut system = spawn_from_run_config_with_plugin(config, ReadyPlugin);
+        let stopped = system.wait_into_stopped_commands().await.unwrap();
+        assert!(matches!(
+            kill_reason(&stopped[0]),
+            Some(kill::KillCommandReason::OtherCommandExited(_))
+        ));
+
+        let count = || std::fs::read_to_string(&ticks).unwrap().lines().count();
+        let killed_at = count();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(count(), killed_at, "the background loop still runs");
     }
 }
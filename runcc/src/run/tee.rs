@@ -0,0 +1,112 @@
+//! Splits the output of a command into lines on its way to the plugin reading it,
+//! for those who want the lines but don't read the pipes, see [`PluginStack`](super::PluginStack).
+
+use std::{io, sync::Arc};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
+    process::{ChildStderr, ChildStdout},
+    task::JoinHandle,
+};
+
+use super::OutputStream;
+
+/// Gets each line of the output with the stream it was printed to
+type OnLine = Arc<dyn Fn(OutputStream, &str) + Send + Sync>;
+
+/// The end of a [`pipe`] the tee writes to
+#[cfg(unix)]
+type Writer = tokio::net::UnixStream;
+#[cfg(windows)]
+type Writer = tokio::net::windows::named_pipe::NamedPipeServer;
+
+/// A socket the plugin reads the output of a command from like from its pipe,
+/// with the end the tee writes to
+#[cfg(unix)]
+fn pipe() -> io::Result<(std::os::unix::io::OwnedFd, Writer)> {
+    let (plugin_end, writer) = std::os::unix::net::UnixStream::pair()?;
+    writer.set_nonblocking(true)?;
+    Ok((plugin_end.into(), Writer::from_std(writer)?))
+}
+
+/// A named pipe the plugin reads the output of a command from like from its pipe,
+/// with the end the tee writes to
+#[cfg(windows)]
+fn pipe() -> io::Result<(std::os::windows::io::OwnedHandle, Writer)> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let name = format!(
+        r"\\.\pipe\runcc-output-{}-{}",
+        std::process::id(),
+        rand::random::<u64>()
+    );
+    let writer = ServerOptions::new()
+        .first_pipe_instance(true)
+        .access_inbound(false)
+        .create(&name)?;
+    let plugin_end = std::fs::OpenOptions::new().read(true).open(&name)?;
+    Ok((plugin_end.into(), writer))
+}
+
+/// Pipes the plugin reads the output of a command from, with the ends the tee writes to
+fn pipes() -> io::Result<((ChildStdout, Writer), (ChildStderr, Writer))> {
+    let (stdout, stdout_writer) = pipe()?;
+    let (stderr, stderr_writer) = pipe()?;
+    let stdout = ChildStdout::from_std(std::process::ChildStdout::from(stdout))?;
+    let stderr = ChildStderr::from_std(std::process::ChildStderr::from(stderr))?;
+    Ok(((stdout, stdout_writer), (stderr, stderr_writer)))
+}
+
+/// Copies `reader` to `writer` for the plugin, and gives each line to `on_line` on the way
+fn copy_lines(
+    reader: impl AsyncRead + Unpin + Send + 'static,
+    mut writer: Writer,
+    stream: OutputStream,
+    on_line: OnLine,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        // `on_line` still gets the lines if the plugin doesn't read them
+        #[cfg(unix)]
+        let mut forward = true;
+        // the end of the plugin is open already, so this doesn't wait
+        #[cfg(windows)]
+        let mut forward = writer.connect().await.is_ok();
+
+        let mut reader = BufReader::new(reader);
+        let mut line = vec![];
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            forward = forward && writer.write_all(&line).await.is_ok();
+            let text = String::from_utf8_lossy(&line);
+            let text = text.strip_suffix('\n').unwrap_or(&text);
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            on_line(stream, text);
+        }
+    })
+}
+
+/// The output of a command for the plugin, which `on_line` sees line by line first,
+/// and the tasks copying it, which end after the command closed its output.
+///
+/// Without pipes, which only fails if runcc ran out of file descriptors or handles,
+/// the plugin gets `stdout` and `stderr` and `on_line` misses the output.
+pub(super) fn tee(
+    stdout: ChildStdout,
+    stderr: ChildStderr,
+    on_line: impl Fn(OutputStream, &str) + Send + Sync + 'static,
+) -> (ChildStdout, ChildStderr, Vec<JoinHandle<()>>) {
+    let ((plugin_stdout, stdout_writer), (plugin_stderr, stderr_writer)) = match pipes() {
+        Ok(pipes) => pipes,
+        Err(_) => return (stdout, stderr, vec![]),
+    };
+    let on_line: OnLine = Arc::new(on_line);
+    let joins = vec![
+        copy_lines(stdout, stdout_writer, OutputStream::Stdout, on_line.clone()),
+        copy_lines(stderr, stderr_writer, OutputStream::Stderr, on_line),
+    ];
+    (plugin_stdout, plugin_stderr, joins)
+}
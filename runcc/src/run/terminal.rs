@@ -0,0 +1,56 @@
+//! Hands the controlling terminal of runcc to the
+//! [`foreground`](crate::RunConfig::foreground) command and takes it back.
+
+use std::io;
+
+/// Runs `f` with `SIGTTOU` blocked in the calling thread,
+/// which a process outside the foreground process group gets from `tcsetpgrp`
+unsafe fn without_sigttou<R>(f: impl FnOnce() -> R) -> R {
+    let mut set: libc::sigset_t = std::mem::zeroed();
+    let mut old: libc::sigset_t = std::mem::zeroed();
+    libc::sigemptyset(&mut set);
+    libc::sigaddset(&mut set, libc::SIGTTOU);
+    libc::pthread_sigmask(libc::SIG_BLOCK, &set, &mut old);
+    let result = f();
+    libc::pthread_sigmask(libc::SIG_SETMASK, &old, std::ptr::null_mut());
+    result
+}
+
+/// A `pre_exec` hook which moves the spawned process into a process group of its own,
+/// so that signals of the terminal, e.g. of Ctrl-C, don't reach it
+pub(crate) fn own_process_group() -> io::Result<()> {
+    if unsafe { libc::setpgid(0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A `pre_exec` hook which moves the spawned process into a process group of its own
+/// and makes it the foreground process group of the terminal on stdin.
+///
+/// The terminal is only taken if runcc is in its foreground process group,
+/// so that runcc running in the background of a shell doesn't steal it.
+pub(crate) fn take_terminal() -> io::Result<()> {
+    unsafe {
+        let owns_terminal = owns_terminal();
+        own_process_group()?;
+        if owns_terminal {
+            // fails if stdin is no terminal, the command runs in the background then
+            without_sigttou(|| libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp()));
+        }
+    }
+    Ok(())
+}
+
+/// Whether runcc is in the foreground process group of the terminal on stdin
+pub(crate) fn owns_terminal() -> bool {
+    unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp() }
+}
+
+/// Makes the process group of runcc the foreground process group of the terminal again,
+/// after the foreground command which took it with [`take_terminal`] exited
+pub(crate) fn reclaim_terminal() {
+    unsafe {
+        without_sigttou(|| libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp()));
+    }
+}
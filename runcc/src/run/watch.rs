@@ -0,0 +1,148 @@
+use glob::Pattern;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// How often watched directories are scanned
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+type Snapshot = HashMap<PathBuf, Option<SystemTime>>;
+
+/// Polls a directory for changed, added or removed files.
+#[derive(Debug, Clone)]
+pub(crate) struct DirWatcher {
+    dir: PathBuf,
+    /// empty includes all files
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl DirWatcher {
+    pub fn new(dir: PathBuf, include: Vec<Pattern>, exclude: Vec<Pattern>) -> Self {
+        Self {
+            dir,
+            include,
+            exclude,
+        }
+    }
+
+    fn is_watched(&self, relative: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches_path(relative)))
+            && !self.exclude.iter().any(|p| p.matches_path(relative))
+    }
+
+    fn scan(&self) -> Snapshot {
+        walkdir::WalkDir::new(&self.dir)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file())
+            .filter(|entry| {
+                entry
+                    .path()
+                    .strip_prefix(&self.dir)
+                    .is_ok_and(|relative| self.is_watched(relative))
+            })
+            .map(|entry| {
+                let modified = entry.metadata().ok().and_then(|m| m.modified().ok());
+                (entry.into_path(), modified)
+            })
+            .collect()
+    }
+
+    async fn scan_blocking(&self) -> Snapshot {
+        let watcher = self.clone();
+        tokio::task::spawn_blocking(move || watcher.scan())
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Resolves when files differ from the state when this function was called,
+    /// with a changed, added or removed file relative to the watched directory.
+    pub async fn changed(&self) -> PathBuf {
+        let snapshot = self.scan_blocking().await;
+
+        loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+
+            let current = self.scan_blocking().await;
+            let changed = current
+                .iter()
+                .find(|&(path, modified)| snapshot.get(path) != Some(modified))
+                .map(|(path, _)| path)
+                .or_else(|| snapshot.keys().find(|path| !current.contains_key(*path)));
+            if let Some(path) = changed {
+                return path.strip_prefix(&self.dir).unwrap_or(path).to_path_buf();
+            }
+        }
+    }
+}
+
+/// Polls a single file for changes, e.g. the config file for `--watch-config`
+#[derive(Debug, Clone)]
+pub(crate) struct FileWatcher {
+    path: PathBuf,
+    /// The state of the file when the watcher was created or the last change was reported
+    seen: FileState,
+}
+
+/// When a file was modified and its length, `None` while it is missing
+type FileState = Option<(Option<SystemTime>, u64)>;
+
+impl FileWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        let seen = Self::state(&path);
+        Self { path, seen }
+    }
+
+    fn state(path: &Path) -> FileState {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some((metadata.modified().ok(), metadata.len()))
+    }
+
+    /// Resolves when the file differs from the state it was last seen in and then stayed the
+    /// same for a scan, so that an editor finished writing it. Changes made while the last
+    /// one was handled are reported by the next call.
+    pub async fn changed(&mut self) {
+        let mut changed = None;
+        loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+
+            let current = Self::state(&self.path);
+            match changed {
+                Some(changed) if changed == current => {
+                    self.seen = current;
+                    return;
+                }
+                _ if current != self.seen => changed = Some(current),
+                _ => changed = None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileWatcher, WATCH_INTERVAL};
+
+    #[tokio::test]
+    async fn report_changes_made_between_calls() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("runcc.yml");
+        std::fs::write(&path, "commands: [web]").unwrap();
+        let mut watcher = FileWatcher::new(path.clone());
+        async fn changed(watcher: &mut FileWatcher) -> bool {
+            let changed = tokio::time::timeout(WATCH_INTERVAL * 4, watcher.changed());
+            changed.await.is_ok()
+        }
+
+        std::fs::write(&path, "commands: [web, api]").unwrap();
+        assert!(changed(&mut watcher).await);
+        assert!(!changed(&mut watcher).await);
+
+        // e.g. while the config is reloaded
+        std::fs::write(&path, "commands: [web, api, db]").unwrap();
+        assert!(changed(&mut watcher).await);
+    }
+}
@@ -0,0 +1,130 @@
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+fn ctl(dir: &std::path::Path, args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-runcc"))
+        .current_dir(dir)
+        .arg("ctl")
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    )
+}
+
+#[test]
+fn control_socket() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join(".runcc.sock");
+    let mut runcc = Command::new(env!("CARGO_BIN_EXE_cargo-runcc"))
+        .current_dir(dir.path())
+        .args([
+            "--control-socket",
+            ".runcc.sock",
+            "--control-stdin",
+            "exec sleep 10",
+        ])
+        .args(["--quiet", "exec sleep 10 #other"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let started_at = Instant::now();
+    let mut stream = loop {
+        match UnixStream::connect(&socket) {
+            Ok(stream) => break stream,
+            Err(_) if started_at.elapsed() < Duration::from_secs(5) => {
+                std::thread::sleep(Duration::from_millis(20))
+            }
+            Err(err) => panic!("{}", err),
+        }
+    };
+
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request = |json: &str| {
+        stream.write_all(format!("{}\n", json).as_bytes()).unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        line
+    };
+    let listed = request(r#"{"command":"list"}"#);
+    assert!(listed
+        .starts_with(r#"{"ok":true,"commands":[{"label":"exec sleep 10","running":true,"pid":"#));
+    assert_eq!(
+        request(r#"{"command":"kill","label":"missing"}"#),
+        "{\"ok\":false,\"error\":\"no command[missing]\"}\n"
+    );
+    assert_eq!(
+        request(r#"{"command":"send","label":"exec sleep 10","text":"y"}"#),
+        "{\"ok\":true}\n"
+    );
+    assert_eq!(
+        request(r#"{"command":"kill","label":"exec sleep 10 #other"}"#),
+        "{\"ok\":true}\n"
+    );
+
+    // waits until `ctl list` prints lines like `expected`, which end with the pid if running
+    let listed = |expected: &[&str]| {
+        let started_at = Instant::now();
+        loop {
+            let (ok, listed) = ctl(dir.path(), &["list"]);
+            assert!(ok);
+            let lines: Vec<&str> = listed.lines().collect();
+            if lines.len() == expected.len()
+                && lines.iter().zip(expected).all(|(l, e)| l.starts_with(e))
+            {
+                return listed;
+            }
+            assert!(
+                started_at.elapsed() < Duration::from_secs(5),
+                "listed {:?}",
+                lines
+            );
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    };
+    let expected = [
+        "exec sleep 10         running  pid ",
+        "exec sleep 10 #other  not running",
+    ];
+    let running = listed(&expected);
+
+    let (ok, _) = ctl(dir.path(), &["restart", "exec sleep 10"]);
+    assert!(ok);
+    // restarted with a new pid
+    let started_at = Instant::now();
+    while listed(&expected) == running {
+        assert!(
+            started_at.elapsed() < Duration::from_secs(5),
+            "not restarted"
+        );
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert!(!ctl(dir.path(), &["kill", "exec sleep 10 #other"]).0);
+
+    // a command which stopped is spawned again
+    assert!(ctl(dir.path(), &["restart", "exec sleep 10 #other"]).0);
+    listed(&[
+        "exec sleep 10         running  pid ",
+        "exec sleep 10 #other  running  pid ",
+    ]);
+
+    assert!(ctl(dir.path(), &["stop"]).0);
+    let started_at = Instant::now();
+    while runcc.try_wait().unwrap().is_none() {
+        if started_at.elapsed() > Duration::from_secs(5) {
+            let _ = runcc.kill();
+            panic!("runcc didn't stop");
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert!(!socket.exists());
+}
@@ -0,0 +1,100 @@
+#![cfg(unix)]
+
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn wait_for(output: &Mutex<String>, text: &str, timeout: Duration) -> bool {
+    let started_at = Instant::now();
+    while started_at.elapsed() < timeout {
+        if output.lock().unwrap().contains(text) {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+fn interrupt(runcc: &Child) {
+    assert_eq!(
+        unsafe { libc::kill(runcc.id() as libc::pid_t, libc::SIGINT) },
+        0
+    );
+}
+
+#[test]
+fn ctrl_c_to_interactive_command() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("runcc.yml"),
+        r#"
+commands:
+  repl:
+    program: sh
+    args: ["-c", "trap 'echo got INT' INT; echo waiting; while true; do sleep 0.05; done"]
+    interactive: true
+  helper: exec sleep 10
+kill: WhenAnyExited
+ctrl_c_to_foreground: true
+"#,
+    )
+    .unwrap();
+
+    let mut runcc = Command::new(env!("CARGO_BIN_EXE_cargo-runcc"))
+        .current_dir(dir.path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let output = Arc::new(Mutex::new(String::new()));
+    let streams: [Box<dyn Read + Send>; 2] = [
+        Box::new(runcc.stdout.take().unwrap()),
+        Box::new(runcc.stderr.take().unwrap()),
+    ];
+    for mut stream in streams {
+        let written = output.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0; 1024];
+            while let Ok(n @ 1..) = stream.read(&mut buf) {
+                let text = String::from_utf8_lossy(&buf[..n]);
+                written.lock().unwrap().push_str(&text);
+            }
+        });
+    }
+
+    assert!(
+        wait_for(&output, "waiting", Duration::from_secs(5)),
+        "{}",
+        output.lock().unwrap()
+    );
+    // until runcc handles Ctrl-C
+    std::thread::sleep(Duration::from_millis(200));
+    interrupt(&runcc);
+
+    assert!(
+        wait_for(&output, "[repl  ] got INT", Duration::from_secs(5)),
+        "{}",
+        output.lock().unwrap()
+    );
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(runcc.try_wait().unwrap().is_none(), "runcc stopped");
+    assert!(!output.lock().unwrap().contains("shutting down"));
+
+    // Ctrl-C twice shuts down
+    std::thread::sleep(Duration::from_millis(1100));
+    interrupt(&runcc);
+    std::thread::sleep(Duration::from_millis(100));
+    interrupt(&runcc);
+
+    let started_at = Instant::now();
+    while runcc.try_wait().unwrap().is_none() {
+        if started_at.elapsed() > Duration::from_secs(5) {
+            let _ = runcc.kill();
+            panic!("runcc didn't exit: {}", output.lock().unwrap());
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,130 @@
+#![cfg(target_os = "linux")]
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Opens a pseudo terminal and returns its master and the path of its slave
+fn open_pty() -> (File, String) {
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        assert!(master >= 0, "posix_openpt failed");
+        assert_eq!(libc::grantpt(master), 0);
+        assert_eq!(libc::unlockpt(master), 0);
+        let slave = CStr::from_ptr(libc::ptsname(master))
+            .to_str()
+            .unwrap()
+            .to_string();
+        (File::from_raw_fd(master), slave)
+    }
+}
+
+fn wait_for(output: &Mutex<String>, text: &str, timeout: Duration) -> bool {
+    let started_at = Instant::now();
+    while started_at.elapsed() < timeout {
+        if output.lock().unwrap().contains(text) {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+#[test]
+fn foreground_command_gets_ctrl_c() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("runcc.yml"),
+        r#"
+commands:
+  repl: "trap 'echo got INT; exit 0' INT; echo waiting; while true; do sleep 0.05; done"
+  helper: exec sleep 10
+kill: WhenAnyExited
+foreground: repl
+"#,
+    )
+    .unwrap();
+
+    let (mut master, slave) = open_pty();
+    let mut runcc = Command::new(env!("CARGO_BIN_EXE_cargo-runcc"));
+    runcc
+        .current_dir(dir.path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // runcc runs in a session of its own with the pseudo terminal as controlling terminal
+    unsafe {
+        runcc.pre_exec(move || {
+            libc::setsid();
+            let path = std::ffi::CString::new(slave.clone()).unwrap();
+            let tty = libc::open(path.as_ptr(), libc::O_RDWR);
+            if tty < 0 || libc::ioctl(tty, libc::TIOCSCTTY, 0) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            for fd in 0..3 {
+                libc::dup2(tty, fd);
+            }
+            Ok(())
+        });
+    }
+    let mut runcc = runcc.spawn().unwrap();
+
+    let output = Arc::new(Mutex::new(String::new()));
+    let mut reader = master.try_clone().unwrap();
+    let written = output.clone();
+    std::thread::spawn(move || {
+        let mut buf = [0; 1024];
+        // fails once runcc exited and closed the terminal
+        while let Ok(n @ 1..) = reader.read(&mut buf) {
+            written
+                .lock()
+                .unwrap()
+                .push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+    });
+
+    // runcc asks which commands to run on a terminal, all of them
+    assert!(
+        wait_for(&output, "empty for all", Duration::from_secs(5)),
+        "{}",
+        output.lock().unwrap()
+    );
+    master.write_all(b"\n").unwrap();
+
+    assert!(
+        wait_for(&output, "waiting", Duration::from_secs(5)),
+        "{}",
+        output.lock().unwrap()
+    );
+    // Ctrl-C, which the terminal sends as SIGINT to its foreground process group
+    master.write_all(b"\x03").unwrap();
+
+    let started_at = Instant::now();
+    let status = loop {
+        if let Some(status) = runcc.try_wait().unwrap() {
+            break status;
+        }
+        if started_at.elapsed() > Duration::from_secs(5) {
+            let _ = runcc.kill();
+            panic!("runcc didn't exit: {}", output.lock().unwrap());
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+
+    let output = output.lock().unwrap().clone();
+    // labels are colored on a terminal, by the index of the command
+    assert!(
+        output.contains("\x1b[36m[repl  ]\x1b[0m got INT"),
+        "{}",
+        output
+    );
+    // runcc itself didn't get the Ctrl-C, it only killed the helper because repl exited
+    assert!(output.contains("command[repl] exited"), "{}", output);
+    assert!(!output.contains("Ctrl-C"), "{}", output);
+    assert!(status.success(), "{}", output);
+}
@@ -0,0 +1,85 @@
+#![cfg(unix)]
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn wait_for(output: &Mutex<String>, text: &str, timeout: Duration) -> bool {
+    let started_at = Instant::now();
+    while started_at.elapsed() < timeout {
+        if output.lock().unwrap().contains(text) {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+#[test]
+fn reload_changed_config_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("runcc.yml");
+    std::fs::write(
+        &config,
+        "commands:\n  web: echo web started; exec sleep 10\n",
+    )
+    .unwrap();
+
+    let mut runcc = Command::new(env!("CARGO_BIN_EXE_cargo-runcc"))
+        .current_dir(dir.path())
+        .arg("--watch-config")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let output = Arc::new(Mutex::new(String::new()));
+    let streams: [Box<dyn Read + Send>; 2] = [
+        Box::new(runcc.stdout.take().unwrap()),
+        Box::new(runcc.stderr.take().unwrap()),
+    ];
+    for mut stream in streams {
+        let written = output.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0; 1024];
+            while let Ok(n @ 1..) = stream.read(&mut buf) {
+                let text = String::from_utf8_lossy(&buf[..n]);
+                written.lock().unwrap().push_str(&text);
+            }
+        });
+    }
+    let expect = |text: &str| {
+        assert!(
+            wait_for(&output, text, Duration::from_secs(5)),
+            "{}",
+            output.lock().unwrap()
+        );
+    };
+
+    expect("web started");
+    std::fs::write(
+        &config,
+        "commands:\n  web: echo web started; exec sleep 10\n  api: echo api started; exec sleep 10\n",
+    )
+    .unwrap();
+    expect("reloaded config: started api");
+    expect("api started");
+
+    // an invalid config keeps the commands running
+    std::fs::write(&config, "commands: [\n").unwrap();
+    expect("failed to reload config");
+    assert!(runcc.try_wait().unwrap().is_none(), "runcc stopped");
+    assert_eq!(output.lock().unwrap().matches("started").count(), 3);
+
+    unsafe { libc::kill(runcc.id() as libc::pid_t, libc::SIGTERM) };
+    let started_at = Instant::now();
+    while runcc.try_wait().unwrap().is_none() {
+        if started_at.elapsed() > Duration::from_secs(5) {
+            let _ = runcc.kill();
+            panic!("runcc didn't exit: {}", output.lock().unwrap());
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}